@@ -5,6 +5,7 @@ use extraction::{
     extract_pbos, 
     extract_pbo,
     extract_pbo_with_options,
+    init_logging,
 };
 use pbo_tools::extract::ExtractOptions;
 use log::{info, LevelFilter};
@@ -12,9 +13,11 @@ use log::{info, LevelFilter};
 #[tokio::main]
 async fn main() -> Result<()> {
     // Set up more detailed logging
-    env_logger::Builder::new()
-        .filter_level(LevelFilter::Debug)
-        .init();
+    init_logging(
+        env_logger::Builder::new()
+            .filter_level(LevelFilter::Debug)
+            .build(),
+    )?;
 
     // Example 1: Extract specific files from a single PBO
     info!("Example 1: Single PBO extraction");
@@ -38,12 +41,24 @@ async fn main() -> Result<()> {
 
     // Example 3: Batch processing with configuration
     info!("Example 3: Batch processing");
+    let input_dir = PathBuf::from("./input");
+    let batch_output = PathBuf::from("./output/batch");
     let config = ExtractionConfig {
-        input_dir: &PathBuf::from("./input"),
-        output_dir: &PathBuf::from("./output/batch"),
         extensions: "sqf,hpp,cpp",
         threads: num_cpus::get(),
         timeout: 30,
+        ..ExtractionConfig::new(&input_dir, &batch_output)
+    };
+
+    let report = extract_pbos(config).await?;
+    println!("{}", report);
+
+    // Example 4: Only extract scripts and UI files regardless of extension
+    info!("Example 4: Path-filtered batch processing");
+    let functions_output = PathBuf::from("./output/functions");
+    let config = ExtractionConfig {
+        include_paths: vec!["functions/".to_string(), "ui/".to_string()],
+        ..ExtractionConfig::new(&input_dir, &functions_output)
     };
 
     extract_pbos(config).await?;
@@ -1,11 +1,11 @@
 use std::path::PathBuf;
 use anyhow::Result;
-use extraction::{ExtractionConfig, extract_pbos};
+use extraction::{ExtractionConfig, extract_pbos, init_logging};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging
-    env_logger::init();
+    // Initialize logging above the progress bars
+    init_logging(env_logger::Builder::from_default_env().build())?;
 
     // Set up input and output directories
     let input_dir = PathBuf::from("./input");  // Directory containing PBO files
@@ -13,16 +13,17 @@ async fn main() -> Result<()> {
 
     // Create the extraction configuration
     let config = ExtractionConfig {
-        input_dir: &input_dir,
-        output_dir: &output_dir,
         extensions: "sqf,hpp,cpp", // Extract files with these extensions
         threads: num_cpus::get(),  // Use all available CPU cores
-        timeout: 30,               // 30 second timeout per PBO operation
+        list_timeout: 10,          // 10 second timeout for listing each PBO
+        extract_timeout: 120,      // 120 second timeout for extracting each PBO
+        ..ExtractionConfig::new(&input_dir, &output_dir)
     };
 
     // Run the extraction
-    extract_pbos(config).await?;
+    let report = extract_pbos(config).await?;
 
     println!("Extraction complete!");
+    println!("{}", report);
     Ok(())
 }
\ No newline at end of file
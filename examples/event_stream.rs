@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use anyhow::Result;
+use extraction::{ExtractionConfig, ExtractionEvent, extract_pbos};
+use tokio::sync::mpsc::unbounded_channel;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+
+    let input_dir = PathBuf::from("./input");
+    let output_dir = PathBuf::from("./output");
+
+    // Events arrive on this channel while the extraction runs
+    let (tx, mut rx) = unbounded_channel();
+
+    let config = ExtractionConfig {
+        extensions: "sqf,hpp,cpp",
+        events: Some(tx),
+        ..ExtractionConfig::new(&input_dir, &output_dir)
+    };
+
+    let printer = tokio::spawn(async move {
+        let (mut discovered, mut extracted, mut skipped, mut failed) = (0, 0, 0, 0);
+
+        while let Some(event) = rx.recv().await {
+            match event {
+                ExtractionEvent::PboDiscovered { .. } => discovered += 1,
+                ExtractionEvent::PboScanned { path, matching } => {
+                    println!("scanned   {} ({} matching files)", path.display(), matching);
+                },
+                ExtractionEvent::PboScanFailed { path, error } => {
+                    failed += 1;
+                    println!("failed    {}: {}", path.display(), error);
+                },
+                ExtractionEvent::PboSkipped { path, reason } => {
+                    skipped += 1;
+                    println!("skipped   {} ({:?})", path.display(), reason);
+                },
+                ExtractionEvent::ExtractionStarted { .. } => {},
+                ExtractionEvent::ExtractionFinished { path, files, duration, strategy, .. } => {
+                    extracted += 1;
+                    println!("extracted {} ({} files in {:.2?}, {})", path.display(), files, duration, strategy);
+                },
+                ExtractionEvent::ExtractionFailed { path, error } => {
+                    failed += 1;
+                    println!("failed    {}: {}", path.display(), error);
+                },
+                ExtractionEvent::RunFinished { duration, .. } => println!("finished in {:.2?}", duration),
+            }
+        }
+
+        println!(
+            "{} discovered, {} extracted, {} skipped, {} failed",
+            discovered, extracted, skipped, failed
+        );
+    });
+
+    // The sender is dropped with the config once extraction returns, ending the printer loop
+    let result = extract_pbos(config).await;
+    printer.await?;
+    result?;
+
+    Ok(())
+}
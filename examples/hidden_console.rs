@@ -0,0 +1,38 @@
+// Manual check that extracting from a GUI application opens no console windows
+//
+// Built for the Windows GUI subsystem, this runs without a console like an
+// application embedding the library. On Windows, run it with a directory of PBOs:
+//
+//     cargo run --example hidden_console -- <input dir> <output dir>
+//
+// No console window should flash up while it runs. Having no console to print to,
+// it writes the report to hidden_console.txt in the output directory. Run it with
+// HIDE_CONSOLE=0 to see the windows that are hidden otherwise.
+#![windows_subsystem = "windows"]
+
+use std::path::PathBuf;
+use anyhow::{Context, Result};
+use extraction::{ExtractionConfig, extract_pbos};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args_os().skip(1).map(PathBuf::from);
+    let (Some(input_dir), Some(output_dir)) = (args.next(), args.next()) else {
+        anyhow::bail!("Usage: hidden_console <input dir> <output dir>");
+    };
+
+    // Hidden by default without a console; set explicitly so both can be compared
+    let hide_console = std::env::var("HIDE_CONSOLE").as_deref() != Ok("0");
+    let config = ExtractionConfig {
+        hide_console: Some(hide_console),
+        ..ExtractionConfig::new(&input_dir, &output_dir)
+    };
+
+    let summary = match extract_pbos(config).await {
+        Ok(report) => report.to_string(),
+        Err(e) => format!("Extraction failed: {:#}", e),
+    };
+    std::fs::write(output_dir.join("hidden_console.txt"), summary)
+        .context("Failed to write hidden_console.txt")?;
+    Ok(())
+}
@@ -0,0 +1,784 @@
+//! Command line front end of the extraction library
+//!
+//! Exit codes: 0 when everything succeeded, 1 on fatal errors, 2 when some PBOs
+//! failed or the cache failed verification, and 130 when the run was cancelled.
+//! `watch` only stops on Ctrl-C, exiting with 0.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use extraction::{
+    adopt_existing,
+    compare_dirs,
+    diff_version,
+    extract_pbos,
+    find_pbos,
+    find_retained_versions,
+    health_check,
+    init_logging,
+    list_pbo_contents,
+    prune_versions,
+    set_tool_dir,
+    touch_hashes,
+    verify_cache,
+    watch_pbos,
+    write_default_toml,
+    CacheLock,
+    CacheLockPolicy,
+    CancelToken,
+    ConfigFile,
+    ExtractionConfig,
+    ExtractionError,
+    ExtractionIndex,
+    ExtractionManifest,
+    MergeStrategy,
+    NdjsonWriter,
+    RunReport,
+    WalkOptions,
+    WatchOptions,
+    INDEX_FILE_NAME,
+    MANIFEST_FILE_NAME,
+    RUN_REPORT_FILE_NAME,
+};
+use extraction::utils::{verify_checksums, CHECKSUM_FILE_NAME};
+
+const EXIT_FATAL: u8 = 1;
+const EXIT_PARTIAL: u8 = 2;
+const EXIT_CANCELLED: u8 = 130;
+
+#[derive(Parser)]
+#[command(name = "extraction", version, about = "Extract files from Arma PBO archives")]
+struct Cli {
+    /// Log more; repeat for trace output
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract matching files from every PBO below the input directory
+    Extract(ExtractArgs),
+    /// Extract PBOs below the input directory as they are created or modified, until Ctrl-C
+    Watch(WatchArgs),
+    /// Record complete outputs already in the output directory, like those of another
+    /// tool, as extracted without extracting anything
+    Adopt(ExtractArgs),
+    /// Record the current hash of PBOs whose output is complete, like mods downloaded
+    /// again unchanged, so runs don't extract them again
+    Touch(ExtractArgs),
+    /// List the PBOs below the input directory and their matching entries, without extracting
+    Scan(InputArgs),
+    /// Show the extraction index and the last run of a cache directory
+    Stats(StatsArgs),
+    /// Re-check extracted files against the manifests and checksums of a cache directory;
+    /// with an input directory, also against fresh PBO listings, recording broken
+    /// outputs in the index so the next run extracts them again
+    Verify(VerifyArgs),
+    /// Write a commented configuration file with every option, for `--config`
+    InitConfig(InitConfigArgs),
+    /// Compare two extraction indexes, listing added, removed and changed PBOs
+    DbDiff(DbDiffArgs),
+    /// Merge two extraction indexes of the same cache, like ones written on two machines
+    DbMerge(DbMergeArgs),
+    /// Compare two versions of a mod folder, listing the files changed inside each PBO
+    Diff(DiffArgs),
+    /// List, prune or compare the earlier outputs kept with `--retain-versions`
+    #[command(subcommand)]
+    Versions(VersionsCommand),
+    /// Check that the PBO tools can be run, showing where they are and their version
+    Backend(BackendArgs),
+}
+
+#[derive(Subcommand)]
+enum VersionsCommand {
+    /// List the versions kept of every output in a cache directory
+    List(CacheArgs),
+    /// Remove kept versions beyond the newest ones; the index lists the versions
+    /// of each PBO as of its last extraction
+    Prune(PruneVersionsArgs),
+    /// Compare a kept version of an output with the current one
+    Diff(VersionDiffArgs),
+}
+
+/// Which PBOs and entries a command looks at
+///
+/// Options given here override those of the configuration file.
+#[derive(Args)]
+struct InputArgs {
+    /// TOML configuration file, as written by `init-config`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Directory containing the PBOs; required unless set in the configuration file
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+    /// Extensions to extract, comma separated, like `sqf,hpp`; all files when empty or `*`
+    #[arg(short, long)]
+    ext: Option<String>,
+    /// Directory inside each PBO to restrict extraction to, like `functions/`; repeatable
+    #[arg(long = "include-path")]
+    include_paths: Vec<String>,
+    /// Glob a PBO must match to be processed; repeatable
+    #[arg(long)]
+    pbo_include: Vec<String>,
+    /// Glob excluding PBOs from processing; repeatable
+    #[arg(long)]
+    pbo_exclude: Vec<String>,
+    /// Maximum directory depth to look for PBOs at, 1 being the input directory itself
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Timeout in seconds for listing a PBO
+    #[arg(long)]
+    list_timeout: Option<u32>,
+}
+
+impl InputArgs {
+    /// Load the configuration file, if any, and apply the arguments given on top
+    fn config_file(&self) -> Result<ConfigFile> {
+        let mut file = match &self.config {
+            Some(path) => ConfigFile::load(path)?,
+            None => ConfigFile::default(),
+        };
+        set_if_some(&mut file.input_dir, &self.input);
+        set_if_some(&mut file.extensions, &self.ext);
+        set_if_some(&mut file.max_depth, &self.max_depth);
+        set_if_some(&mut file.list_timeout, &self.list_timeout);
+        for (list, args) in [
+            (&mut file.include_paths, &self.include_paths),
+            (&mut file.pbo_include, &self.pbo_include),
+            (&mut file.pbo_exclude, &self.pbo_exclude),
+        ] {
+            if !args.is_empty() {
+                *list = Some(args.clone());
+            }
+        }
+        if file.input_dir.is_none() {
+            anyhow::bail!("--input is required unless the configuration file sets input_dir");
+        }
+        Ok(file)
+    }
+}
+
+/// Replace the file's `value` with the one given on the command line, if any
+fn set_if_some<T: Clone>(value: &mut Option<T>, arg: &Option<T>) {
+    if arg.is_some() {
+        value.clone_from(arg);
+    }
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Directory to extract into; required unless set in the configuration file
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Worker threads; all cores by default
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+    /// Most PBOs listed or extracted at once, below the thread count; for spinning disks
+    #[arg(long)]
+    io_concurrency: Option<usize>,
+    /// Leave the machine usable: fewer threads, the process at low priority, pauses between PBOs
+    #[arg(long)]
+    background: bool,
+    /// Timeout in seconds for extracting a PBO
+    #[arg(long)]
+    extract_timeout: Option<u32>,
+    /// Skip PBOs whose output is already complete
+    #[arg(long)]
+    resume: bool,
+    /// Record complete outputs of an earlier extraction instead of extracting those PBOs again
+    #[arg(long)]
+    adopt_existing: bool,
+    /// Earlier outputs of changed PBOs to keep, as `<dir>.prev1` and up
+    #[arg(long)]
+    retain_versions: Option<usize>,
+    /// Wait for another extraction running on the output directory instead of failing
+    #[arg(long)]
+    wait_for_lock: bool,
+    /// Update the extraction index in the output directory
+    #[arg(long)]
+    index: bool,
+    /// List every PBO with the tool, without reusing or keeping cached listings
+    #[arg(long)]
+    no_listing_cache: bool,
+    /// Write the run report to this path, relative to the output directory
+    #[arg(long)]
+    run_report: Option<PathBuf>,
+    /// Also log into this file
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// `json` prints every event as a line of JSON on stdout, ending with a `run_finished` summary
+    #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl ExtractArgs {
+    /// Load the configuration file, if any, and apply the arguments given on top
+    fn config_file(&self) -> Result<ConfigFile> {
+        let mut file = self.input.config_file()?;
+        set_if_some(&mut file.output_dir, &self.output);
+        if file.output_dir.is_none() {
+            anyhow::bail!("--output is required unless the configuration file sets output_dir");
+        }
+        set_if_some(&mut file.threads, &self.threads);
+        set_if_some(&mut file.io_concurrency, &self.io_concurrency);
+        set_if_some(&mut file.extract_timeout, &self.extract_timeout);
+        set_if_some(&mut file.run_report, &self.run_report);
+        set_if_some(&mut file.log_file, &self.log_file);
+        set_if_some(&mut file.retain_versions, &self.retain_versions);
+        if self.resume {
+            file.resume = Some(true);
+        }
+        if self.background {
+            file.background = Some(true);
+        }
+        if self.adopt_existing {
+            file.adopt_existing = Some(true);
+        }
+        if self.index {
+            file.build_index = Some(true);
+        }
+        if self.no_listing_cache {
+            file.cache_listings = Some(false);
+        }
+        if self.wait_for_lock {
+            file.cache_lock = Some(CacheLockPolicy::Wait);
+        }
+        Ok(file)
+    }
+
+    /// Extraction configuration of `file`, with the options only given on the command line
+    fn configure<'a>(&self, file: &'a ConfigFile) -> Result<ExtractionConfig<'a>> {
+        let mut config = ExtractionConfig::from_toml(file)?;
+        config.handle_signals = file.handle_signals.unwrap_or(true);
+        if config.handle_signals {
+            exit_on_second_interrupt();
+        }
+        if self.message_format == MessageFormat::Json {
+            config.json_events = Some(Arc::new(NdjsonWriter::new(std::io::stdout())));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Args)]
+struct WatchArgs {
+    #[command(flatten)]
+    extract: ExtractArgs,
+    /// Seconds without further changes before changed PBOs are extracted
+    #[arg(long, default_value_t = 10)]
+    quiet_period: u64,
+    /// Remove the output of deleted PBOs from the cache
+    #[arg(long)]
+    prune_deleted: bool,
+    /// Only extract PBOs changing from now on, without a run over all PBOs first
+    #[arg(long)]
+    no_initial_run: bool,
+}
+
+#[derive(Args)]
+struct CacheArgs {
+    /// Directory extracted into
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    extract: ExtractArgs,
+    /// Extract PBOs with broken output again right away
+    #[arg(long)]
+    fix: bool,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    cache: CacheArgs,
+    /// Extraction index to read instead of the one in the output directory
+    #[arg(long, alias = "db")]
+    index: Option<PathBuf>,
+    /// Wait for an extraction running on the output directory instead of failing
+    #[arg(long)]
+    wait_for_lock: bool,
+}
+
+#[derive(Args)]
+struct InitConfigArgs {
+    /// File to create
+    #[arg(default_value = "extraction.toml")]
+    path: PathBuf,
+}
+
+#[derive(Args)]
+struct DbDiffArgs {
+    /// Older extraction index
+    old: PathBuf,
+    /// Newer extraction index
+    new: PathBuf,
+    /// Print the differences as a JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct DbMergeArgs {
+    /// Extraction index to merge into
+    a: PathBuf,
+    /// Extraction index to merge
+    b: PathBuf,
+    /// Where to write the merged index; may be one of the inputs
+    #[arg(short, long)]
+    output: PathBuf,
+    /// How PBOs recorded with another hash or status are settled
+    #[arg(long, value_enum, default_value_t = MergeArg::PreferNewer)]
+    strategy: MergeArg,
+    /// Print the summary as a JSON object
+    #[arg(long)]
+    json: bool,
+    /// Wait for an extraction running on the output's cache instead of failing
+    #[arg(long)]
+    wait_for_lock: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MergeArg {
+    PreferNewer,
+    PreferSuccess,
+    ErrorOnConflict,
+}
+
+impl From<MergeArg> for MergeStrategy {
+    fn from(arg: MergeArg) -> Self {
+        match arg {
+            MergeArg::PreferNewer => MergeStrategy::PreferNewer,
+            MergeArg::PreferSuccess => MergeStrategy::PreferSuccess,
+            MergeArg::ErrorOnConflict => MergeStrategy::ErrorOnConflict,
+        }
+    }
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Older version of the folder
+    old: PathBuf,
+    /// Newer version of the folder
+    new: PathBuf,
+    /// Maximum directory depth to look for PBOs at, 1 being the folder itself
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Timeout in seconds for listing a PBO
+    #[arg(long, default_value_t = 10)]
+    list_timeout: u32,
+    /// Print the differences as a JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct BackendArgs {
+    /// Look for the tool in this directory before PATH
+    #[arg(long)]
+    tool_dir: Option<PathBuf>,
+    /// Print the tool found as a JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct PruneVersionsArgs {
+    #[command(flatten)]
+    cache: CacheArgs,
+    /// Versions to keep of each output
+    #[arg(long, default_value_t = 0)]
+    keep: usize,
+    /// Wait for an extraction running on the output directory instead of failing
+    #[arg(long)]
+    wait_for_lock: bool,
+}
+
+#[derive(Args)]
+struct VersionDiffArgs {
+    #[command(flatten)]
+    cache: CacheArgs,
+    /// Output directory of the PBO, relative to the cache directory, like `@ace/addons/ace_medical`
+    dir: PathBuf,
+    /// Version to compare, 1 being the output replaced last
+    #[arg(long, default_value_t = 1)]
+    version: usize,
+    /// Print the differences as a JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    let logger = env_logger::Builder::new().filter_level(level).parse_default_env().build();
+    if let Err(e) = init_logging(logger) {
+        eprintln!("Failed to set up logging: {:#}", e);
+    }
+
+    let result = match &cli.command {
+        Command::Extract(args) => extract(args).await,
+        Command::Watch(args) => watch(args).await,
+        Command::Adopt(args) => adopt(args),
+        Command::Touch(args) => touch(args),
+        Command::Scan(args) => scan(args),
+        Command::Stats(args) => stats(args),
+        Command::Verify(args) => verify(args).await,
+        Command::InitConfig(args) => init_config(&args.path),
+        Command::DbDiff(args) => db_diff(args),
+        Command::DbMerge(args) => db_merge(args),
+        Command::Diff(args) => diff(args),
+        Command::Versions(command) => versions(command),
+        Command::Backend(args) => backend(args),
+    };
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::from(EXIT_FATAL)
+        },
+    }
+}
+
+/// Exit with `EXIT_CANCELLED` on a second Ctrl-C
+///
+/// The first one is handled by the library, which finishes the PBOs in progress
+/// and returns a cancelled run.
+fn exit_on_second_interrupt() {
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        log::warn!("Press Ctrl-C again to stop immediately");
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_CANCELLED.into());
+        }
+    });
+}
+
+async fn extract(args: &ExtractArgs) -> Result<u8> {
+    let file = args.config_file()?;
+    let config = args.configure(&file)?;
+    lower_priority(&config);
+    match extract_pbos(config).await {
+        Ok(report) if report.failed > 0 || report.scan_failed > 0 => Ok(EXIT_PARTIAL),
+        Ok(_) => Ok(0),
+        Err(ExtractionError::PartialFailure(_)) => Ok(EXIT_PARTIAL),
+        Err(ExtractionError::Cancelled(_)) => Ok(EXIT_CANCELLED),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// In background mode, lower the whole process, so the tool runs at low priority
+/// on every platform rather than only the workers
+///
+/// The library leaves the process to its host, which this binary is.
+fn lower_priority(config: &ExtractionConfig) {
+    if config.background {
+        if let Err(e) = extraction::utils::lower_process_priority() {
+            log::warn!("Running at normal priority: {:#}", e);
+        }
+    }
+}
+
+async fn watch(args: &WatchArgs) -> Result<u8> {
+    let file = args.extract.config_file()?;
+    let config = args.extract.configure(&file)?;
+    lower_priority(&config);
+    let options = WatchOptions {
+        quiet_period: Duration::from_secs(args.quiet_period),
+        prune_deleted: args.prune_deleted,
+        initial_run: !args.no_initial_run,
+    };
+    watch_pbos(config, options).await?;
+    Ok(0)
+}
+
+fn adopt(args: &ExtractArgs) -> Result<u8> {
+    let file = args.config_file()?;
+    let config = args.configure(&file)?;
+    let report = adopt_existing(config)?;
+    println!("{}", report);
+    Ok(if report.scan_failures.is_empty() { 0 } else { EXIT_PARTIAL })
+}
+
+fn touch(args: &ExtractArgs) -> Result<u8> {
+    let file = args.config_file()?;
+    let config = args.configure(&file)?;
+    let report = touch_hashes(config)?;
+    println!("{}", report);
+    Ok(if report.scan_failures.is_empty() { 0 } else { EXIT_PARTIAL })
+}
+
+fn scan(args: &InputArgs) -> Result<u8> {
+    let mut file = args.config_file()?;
+    // Nothing is written by a scan
+    file.output_dir.get_or_insert_with(PathBuf::new);
+    let config = ExtractionConfig::from_toml(&file)?;
+    let filter = config.file_filter();
+    // Walked as a run would, ignored directories, depth limit and grace period included
+    let pbos = find_pbos(&config)?;
+
+    let mut failed = 0;
+    for rel_path in &pbos {
+        match list_pbo_contents(&config.input_dir.join(rel_path), config.list_timeout_secs()) {
+            Ok(contents) => {
+                let matching = contents.entries.iter()
+                    .filter(|entry| filter.matches(entry))
+                    .filter(|entry| filter.matches_size(contents.entry_sizes.get(*entry).copied()))
+                    .count();
+                println!(
+                    "{}: {} of {} entries match{}",
+                    rel_path.display(),
+                    matching,
+                    contents.entries.len(),
+                    contents.prefix.map(|prefix| format!(" (prefix {})", prefix)).unwrap_or_default()
+                );
+            },
+            Err(e) => {
+                failed += 1;
+                println!("{}: listing failed: {:#}", rel_path.display(), e);
+            },
+        }
+    }
+    println!("Listed {} of {} PBOs", pbos.len() - failed, pbos.len());
+    Ok(if failed > 0 { EXIT_PARTIAL } else { 0 })
+}
+
+fn stats(args: &StatsArgs) -> Result<u8> {
+    let _lock = lock_shared(&args.cache.output, args.wait_for_lock)?;
+    let index_path = args.index.clone().unwrap_or_else(|| args.cache.output.join(INDEX_FILE_NAME));
+    match ExtractionIndex::load(&index_path) {
+        Ok(index) => {
+            let stats = index.stats();
+            println!("Index: {}", index_path.display());
+            println!("  PBOs: {} ({} failed, {} quarantined)", stats.sources, stats.failed, stats.quarantined);
+            println!("  Files: {}", stats.files);
+        },
+        Err(e) if args.index.is_some() => return Err(e),
+        Err(_) => println!("No index in {}", args.cache.output.display()),
+    }
+
+    let run_report_path = args.cache.output.join(RUN_REPORT_FILE_NAME);
+    if run_report_path.is_file() {
+        let run = RunReport::load(&run_report_path)?;
+        println!("Last run, finished at {} (Unix time):", run.finished_at);
+        println!("{}", run.report);
+    }
+    Ok(0)
+}
+
+async fn verify(args: &VerifyArgs) -> Result<u8> {
+    let input = &args.extract.input;
+    if input.input.is_none() && input.config.is_none() {
+        if args.fix {
+            anyhow::bail!("--fix needs the input directory, from --input or the configuration file");
+        }
+        let output_dir = args.extract.output.as_deref().context("--output is required")?;
+        let _lock = lock_shared(output_dir, args.extract.wait_for_lock)?;
+        return verify_manifests(output_dir);
+    }
+
+    let file = args.extract.config_file()?;
+    let config = args.extract.configure(&file)?;
+    let verification = match verify_cache(config, args.fix).await {
+        Ok(verification) => verification,
+        Err(ExtractionError::Cancelled(_)) => return Ok(EXIT_CANCELLED),
+        Err(e) => return Err(e.into()),
+    };
+    println!("{}", verification);
+    let repaired = match &verification.repair {
+        Some(report) => report.failed == 0 && report.scan_failed == 0,
+        None => verification.broken.is_empty(),
+    };
+    Ok(if repaired && verification.scan_failures.is_empty() { 0 } else { EXIT_PARTIAL })
+}
+
+/// Lock `output_dir` for reading, if it exists, so no extraction changes it meanwhile
+fn lock_shared(output_dir: &Path, wait: bool) -> Result<Option<CacheLock>> {
+    if !output_dir.is_dir() {
+        return Ok(None);
+    }
+    let policy = if wait { CacheLockPolicy::Wait } else { CacheLockPolicy::Fail };
+    // Ctrl-C keeps its default handling, ending the wait along with the process
+    Ok(Some(CacheLock::shared(output_dir, policy, &CancelToken::new())?))
+}
+
+/// Lock `output_dir` for changing it, if it exists, so no other run uses it meanwhile
+fn lock_exclusive(output_dir: &Path, wait: bool) -> Result<Option<CacheLock>> {
+    if !output_dir.is_dir() {
+        return Ok(None);
+    }
+    let policy = if wait { CacheLockPolicy::Wait } else { CacheLockPolicy::Fail };
+    Ok(Some(CacheLock::exclusive(output_dir, policy, &CancelToken::new())?))
+}
+
+/// Cache directory `index_path` is the extraction index of, going by its file name
+fn index_cache_dir(index_path: &Path) -> Option<&Path> {
+    if index_path.file_name()? != INDEX_FILE_NAME {
+        return None;
+    }
+    match index_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => Some(parent),
+        _ => Some(Path::new(".")),
+    }
+}
+
+/// Check every output below `output_dir` against its manifest and checksums, without the PBOs
+fn verify_manifests(output_dir: &Path) -> Result<u8> {
+    if !output_dir.is_dir() {
+        anyhow::bail!("Output directory does not exist: {}", output_dir.display());
+    }
+    let mut checked = 0;
+    let mut failed = 0;
+    for entry in walkdir::WalkDir::new(output_dir).sort_by_file_name().into_iter().filter_map(|entry| entry.ok()) {
+        if entry.file_name() != MANIFEST_FILE_NAME {
+            continue;
+        }
+        let base_dir = entry.path().parent().context("Manifest without a parent directory")?;
+        let rel_dir = base_dir.strip_prefix(output_dir).unwrap_or(base_dir);
+        checked += 1;
+
+        let mut problems = Vec::new();
+        match ExtractionManifest::read(base_dir) {
+            Ok(manifest) => {
+                let check = manifest.check(base_dir);
+                problems.extend(check.missing.iter().map(|path| format!("missing {}", path)));
+                problems.extend(check.changed.iter().map(|path| format!("size changed {}", path)));
+            },
+            Err(e) => problems.push(format!("{:#}", e)),
+        }
+        if base_dir.join(CHECKSUM_FILE_NAME).is_file() {
+            match verify_checksums(base_dir) {
+                Ok(mismatches) => {
+                    problems.extend(mismatches.iter().map(|mismatch| format!("checksum mismatch {}", mismatch.path)));
+                },
+                Err(e) => problems.push(format!("{:#}", e)),
+            }
+        }
+        if !problems.is_empty() {
+            failed += 1;
+            println!("{}:", rel_dir.display());
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+    println!("Verified {} extractions, {} with problems", checked, failed);
+    Ok(if failed > 0 { EXIT_PARTIAL } else { 0 })
+}
+
+fn init_config(path: &Path) -> Result<u8> {
+    write_default_toml(path)?;
+    println!("Wrote {}; set input_dir and output_dir to get started", path.display());
+    Ok(0)
+}
+
+fn db_diff(args: &DbDiffArgs) -> Result<u8> {
+    let old = ExtractionIndex::load(&args.old)?;
+    let new = ExtractionIndex::load(&args.new)?;
+    let diff = old.diff(&new);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        println!("{}", diff);
+    }
+    Ok(0)
+}
+
+fn db_merge(args: &DbMergeArgs) -> Result<u8> {
+    // Writing the index of a cache mustn't race a run on it; other files need no lock
+    let _lock = match index_cache_dir(&args.output) {
+        Some(cache_dir) => lock_exclusive(cache_dir, args.wait_for_lock)?,
+        None => None,
+    };
+    let mut index = ExtractionIndex::load(&args.a)?;
+    let summary = index.merge(ExtractionIndex::load(&args.b)?, args.strategy.into())?;
+    index.write(&args.output)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!("{}", summary);
+        println!("Wrote {}", args.output.display());
+    }
+    Ok(0)
+}
+
+fn diff(args: &DiffArgs) -> Result<u8> {
+    let walk_options = WalkOptions { max_depth: args.max_depth, ..Default::default() };
+    let diff = compare_dirs(&args.old, &args.new, &walk_options, args.list_timeout)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        println!("{}", diff);
+    }
+    Ok(if diff.failed.is_empty() { 0 } else { EXIT_PARTIAL })
+}
+
+fn backend(args: &BackendArgs) -> Result<u8> {
+    set_tool_dir(args.tool_dir.clone());
+    let info = health_check()?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        println!("{}", info);
+    }
+    Ok(0)
+}
+
+fn versions(command: &VersionsCommand) -> Result<u8> {
+    match command {
+        VersionsCommand::List(cache) => {
+            let outputs = find_retained_versions(&cache.output)?;
+            for (base_dir, versions) in &outputs {
+                println!("{}:", base_dir.strip_prefix(&cache.output).unwrap_or(base_dir).display());
+                for version in versions {
+                    println!("  prev{}: {}, extracted at {} (Unix time)", version.number, version.hash, version.extracted_at);
+                }
+            }
+            println!("{} outputs with kept versions", outputs.len());
+        },
+        VersionsCommand::Prune(args) => {
+            let _lock = lock_exclusive(&args.cache.output, args.wait_for_lock)?;
+            let mut removed = 0;
+            for base_dir in find_retained_versions(&args.cache.output)?.keys() {
+                removed += prune_versions(base_dir, args.keep)?.len();
+            }
+            println!("Removed {} versions", removed);
+        },
+        VersionsCommand::Diff(args) => {
+            let diff = diff_version(&args.cache.output.join(&args.dir), args.version)?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                println!("{}", diff);
+            }
+        },
+    }
+    Ok(0)
+}
@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use crate::scanner::types::ExtractionReport;
+
+/// How to make the PBO tools available, shown with `ExtractionError::BackendUnavailable`
+pub const BACKEND_INSTALL_HINT: &str = "Install Mikero's ExtractPbo and add its directory to PATH, \
+    or set skip_backend_check if the check misjudges your setup";
+
+/// Error returned by the public extraction functions
+///
+/// Converts into `anyhow::Error` like any other error, so `?` keeps working in
+/// functions returning `anyhow::Result`. Internal `anyhow` errors carrying one of
+/// these variants are unwrapped again when converted back.
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractionError {
+    #[error("Input directory does not exist: {}", .0.display())]
+    InputDirNotFound(PathBuf),
+    #[error("Input directory is not readable: {}", .path.display())]
+    InputDirNotReadable {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Output directory is not writable: {}", .path.display())]
+    OutputNotWritable {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The PBO tools could not be run at all, like when they are not installed
+    #[error("PBO tools are not available: {0}. {hint}", hint = BACKEND_INSTALL_HINT)]
+    BackendUnavailable(String),
+    /// The tool is not in the directory set by `set_tool_dir` nor any of PATH; see
+    /// `health_check`
+    #[error("{tool} not found in PATH{}", match (.installed_in, .searched.len()) {
+        (Some(dir), _) => format!(", but installed in {}: add that directory to PATH", dir.display()),
+        (None, searched) => format!(" ({} directories searched). {}", searched, BACKEND_INSTALL_HINT),
+    })]
+    BackendNotFound {
+        /// File name of the tool looked for
+        tool: String,
+        /// Directories searched, in order: the one set by `set_tool_dir`, then PATH
+        searched: Vec<PathBuf>,
+        /// Directory the tool's installer put it in, off PATH
+        installed_in: Option<PathBuf>,
+    },
+    /// The tool ran but named no version; `output` is what it printed, shortened
+    #[error("Could not tell the version of {} from its output: {output:?}", .path.display())]
+    BackendVersionUnknown {
+        path: PathBuf,
+        output: String,
+    },
+    #[error("No PBO files found in input directory: {}{}", .input_dir.display(), match .depth_limited_dirs {
+        0 => String::new(),
+        dirs => format!(" ({} directories skipped by the depth limit)", dirs),
+    })]
+    NoPbosFound {
+        input_dir: PathBuf,
+        /// Directories not descended into because of the depth limit
+        depth_limited_dirs: usize,
+    },
+    /// Another process holds the lock of the output directory; see `CacheLock`
+    #[error("Another extraction is already running on this cache: {}", .0.display())]
+    CacheLocked(PathBuf),
+    /// The run was cancelled; the report covers the PBOs finished before that
+    #[error("Extraction was cancelled after {} PBOs", .0.extracted + .0.skipped + .0.failed)]
+    Cancelled(Box<ExtractionReport>),
+    /// The failure policy stopped the run; the report lists the failures
+    #[error("Extraction stopped after {} PBOs failed", .0.failures.len())]
+    PartialFailure(Box<ExtractionReport>),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ExtractionError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<ExtractionError>() {
+            Ok(error) => error,
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+impl ExtractionError {
+    /// Report of the run, for runs that were stopped early
+    pub fn report(&self) -> Option<&ExtractionReport> {
+        match self {
+            Self::Cancelled(report) | Self::PartialFailure(report) => Some(report),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a PBO tool error message means the filter matched no file to extract
+///
+/// The tool exits with return code 11 then, which is no failure for extraction.
+pub fn is_nothing_to_extract(message: &str) -> bool {
+    message.contains("return code 11") || message.contains("no file(s) to extract")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anyhow_round_trip() {
+        let error = anyhow::Error::new(ExtractionError::InputDirNotFound(PathBuf::from("mods")));
+        assert!(matches!(ExtractionError::from(error), ExtractionError::InputDirNotFound(_)));
+
+        let error = ExtractionError::from(anyhow::anyhow!("Invalid PBO header"));
+        assert!(matches!(error, ExtractionError::Other(_)));
+        assert_eq!(error.to_string(), "Invalid PBO header");
+
+        let error = anyhow::Error::from(ExtractionError::NoPbosFound {
+            input_dir: PathBuf::from("mods"),
+            depth_limited_dirs: 2,
+        });
+        assert_eq!(
+            error.to_string(),
+            "No PBO files found in input directory: mods (2 directories skipped by the depth limit)"
+        );
+    }
+
+    #[test]
+    fn test_nothing_to_extract() {
+        assert!(is_nothing_to_extract("extractpbo failed with return code 11: no file(s) to extract"));
+        assert!(!is_nothing_to_extract("extractpbo failed with return code 1: Invalid PBO header"));
+    }
+
+    #[test]
+    fn test_backend_error_messages() {
+        let error = ExtractionError::BackendUnavailable("Failed to spawn extractpbo".to_string());
+        assert!(error.to_string().starts_with("PBO tools are not available: Failed to spawn extractpbo. Install"));
+
+        let error = ExtractionError::BackendNotFound {
+            tool: "ExtractPbo.exe".to_string(),
+            searched: vec![PathBuf::from("C:\\Windows"), PathBuf::from("C:\\Windows\\System32")],
+            installed_in: None,
+        };
+        assert!(error.to_string().starts_with("ExtractPbo.exe not found in PATH (2 directories searched). Install"));
+        let error = ExtractionError::BackendNotFound {
+            tool: "extractpbo".to_string(),
+            searched: Vec::new(),
+            installed_in: Some(PathBuf::from("/opt/mikero/bin")),
+        };
+        assert_eq!(
+            error.to_string(),
+            "extractpbo not found in PATH, but installed in /opt/mikero/bin: add that directory to PATH"
+        );
+    }
+}
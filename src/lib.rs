@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+pub mod error;
 pub mod scanner;
 pub mod utils;
 pub mod types;
@@ -8,11 +9,156 @@ pub mod types;
 mod extraction;
 
 pub use extraction::{
+    adopt_existing,
     extract_pbo,
     extract_pbo_with_options,
+    extract_file,
     extract_pbos,
+    extract_streaming,
+    extract_to_memory,
+    extract_to_memory_async,
+    extract_to_memory_with_limit,
+    find_pbos,
+    touch_hashes,
+    verify_cache,
     ExtractionConfig,
+    DEFAULT_MEMORY_LIMIT,
 };
 
 // Re-export commonly used types
-pub use types::PboScanResult;
+pub use error::{ExtractionError, BACKEND_INSTALL_HINT};
+pub use types::{PboEntry, PboScanResult};
+pub use scanner::archive::{
+    list_tar,
+    verify_tar,
+    write_tar,
+    write_tar_checked,
+    ArchiveStats,
+    TarCompression,
+    TarTarget,
+};
+pub use scanner::backend::{
+    backend_version,
+    health_check,
+    set_tool_dir,
+    BackendFeatures,
+    BackendInfo,
+    EXTRACTPBO_FILE_NAME,
+};
+pub use scanner::cache_lock::{CacheLock, CacheLockPolicy, CACHE_LOCK_FILE_NAME};
+pub use scanner::cancel::{CancelToken, SignalHandler};
+pub use scanner::config_file::{
+    write_default_toml,
+    ConfigFile,
+    FailurePolicyFile,
+    TarOutputFile,
+    DEFAULT_CONFIG_TOML,
+};
+pub use scanner::coordinator::{DEFAULT_MAX_NESTING_DEPTH, DEFAULT_SLOWEST_PBOS};
+pub use scanner::dedupe::{dedupe_dir, DedupeStats, DEFAULT_DEDUPE_MIN_SIZE};
+pub use scanner::derap::{derapify_tree, is_rapified, ConfigConverter, DerapifyResult, RapConverter};
+pub use scanner::events::ExtractionEvent;
+pub use scanner::failure::{failure_log_path, FailureLog, FailurePolicy, FailureTracker, FAILURE_LOG_DIR_NAME};
+pub use scanner::log_file::{LogFile, LogRotation, RotatingFile, TeeLogger, DEFAULT_LOG_FILE_MAX_SIZE};
+pub use scanner::filter::{FileFilter, PboFilter};
+pub use scanner::index::{
+    ExtractionIndex,
+    FailedSource,
+    FailureStage,
+    IndexEntry,
+    IndexedSource,
+    QuarantinedSource,
+    SourceOutput,
+    SourceUpdate,
+    INDEX_FILE_NAME,
+};
+pub use scanner::index_diff::{IndexDiff, SourceChange, SourceState, SourceStatus};
+pub use scanner::index_merge::{MergeStrategy, MergeSummary};
+pub use scanner::listing_cache::{ListingCache, DEFAULT_LISTING_CACHE_MAX_BYTES, LISTING_CACHE_FILE_NAME};
+pub use scanner::manifest::{write_checksums, ExtractionManifest, ManifestCheck, ManifestFile, MANIFEST_FILE_NAME};
+pub use scanner::ndjson::{NdjsonWriter, DEFAULT_NDJSON_CAPACITY};
+pub use scanner::pbo_diff::{compare_dirs, compare_pbos, ChangedPbo, DirDiff, EntryChange, PboDiff, PboDiffFailure};
+#[cfg(feature = "paa-convert")]
+pub use scanner::paa::{convert_paa_tree, PaaConversion, PaaFormat, PaaImage};
+#[cfg(feature = "signatures")]
+pub use scanner::signature::{bisign_files, BiPublicKey, BiSignature, SignatureVerifier};
+pub use scanner::quarantine::{QuarantinePolicy, DEFAULT_MAX_QUARANTINE_COPY_SIZE, QUARANTINE_DIR_NAME};
+pub use scanner::report_csv::{ModCsvRow, PboCsvRow};
+pub use scanner::retry::{is_lock_error, LockRetry, RetryPolicy, ToolTimeout};
+pub use scanner::run_report::{RunConfig, RunReport, RUN_REPORT_FILE_NAME, RUN_REPORT_VERSION};
+pub use scanner::store::{ObjectStore, StoreStats, OBJECTS_DIR_NAME};
+pub use scanner::stream::{stream_files, FileSink};
+pub use scanner::stringtable::{
+    convert_stringtables,
+    Stringtable,
+    StringtableConversion,
+    StringtableKey,
+    STRINGTABLE_FILE_NAME,
+};
+pub use scanner::transform::FileTransformer;
+pub use scanner::versions::{
+    diff_version,
+    find_retained_versions,
+    prune_versions,
+    replace_retaining,
+    retained_versions,
+    version_dir,
+    RetainedVersion,
+};
+#[cfg(feature = "watch")]
+pub use scanner::watch::{watch_pbos, WatchOptions};
+pub use scanner::progress::{
+    init_logging,
+    ExtractionProgress,
+    NoopProgress,
+    OutputMode,
+    ProgressReporter,
+    StatusInterval,
+};
+pub use scanner::types::{
+    AdoptionReport,
+    CacheDiscrepancy,
+    CacheVerification,
+    ExtractedFile,
+    ExtractionReport,
+    ExtractionStrategy,
+    HeaderCheck,
+    IndexStats,
+    ModDetection,
+    ModMetadata,
+    ModSummary,
+    NameSanitization,
+    OutputCollisionHandling,
+    OverwritePolicy,
+    PathConflict,
+    PboContents,
+    PboFailure,
+    PboHeader,
+    PboOutcome,
+    PboStatus,
+    ProcessingOrder,
+    ReservedNameHandling,
+    RunStats,
+    SignatureStatus,
+    SkipReason,
+    StrategyAttempt,
+    Throughput,
+    TimeoutPolicy,
+    TouchReport,
+    VerificationMode,
+    VerificationResult,
+    WalkOptions,
+};
+pub use scanner::utils::{
+    check_backend,
+    check_pbo_header,
+    default_tool_concurrency,
+    list_pbo_contents,
+    parse_mod_cpp,
+    prefix_map,
+    read_mod_metadata,
+    read_pbo_header,
+    set_hide_console,
+    set_tool_concurrency,
+    PREFIX_MAP_FILE_NAME,
+};
@@ -1,12 +1,58 @@
-use std::path::Path;
-use anyhow::Result;
-use log::debug;
-use pbo_tools::{
-    core::api::{PboApi, PboApiOps},
-    extract::ExtractOptions,
-};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use pbo_tools::extract::ExtractOptions;
 
-use crate::scanner::coordinator::ScanCoordinator;
+use crate::error::{is_nothing_to_extract, ExtractionError};
+use crate::scanner::archive::TarTarget;
+use crate::scanner::cache_lock::{CacheLock, CacheLockPolicy};
+use crate::scanner::cancel::{CancelToken, SignalHandler};
+use crate::scanner::config_file::ConfigFile;
+use crate::scanner::coordinator::{ScanCoordinator, DEFAULT_MAX_NESTING_DEPTH, DEFAULT_SLOWEST_PBOS};
+use crate::scanner::dedupe::DEFAULT_DEDUPE_MIN_SIZE;
+use crate::scanner::derap::{ConfigConverter, RapConverter};
+use crate::scanner::events::{EventSink, ExtractionEvent};
+use crate::scanner::failure::FailurePolicy;
+use crate::scanner::listing_cache::DEFAULT_LISTING_CACHE_MAX_BYTES;
+use crate::scanner::log_file::{LogFile, LogRotation};
+use crate::scanner::ndjson::NdjsonWriter;
+use crate::scanner::quarantine::QuarantinePolicy;
+use crate::scanner::filter::{FileFilter, PboFilter};
+use crate::scanner::processor::pending_extract_options;
+use crate::scanner::progress::{ExtractionProgress, OutputMode, StatusInterval};
+use crate::scanner::retry::{LockRetry, RetryPolicy};
+use crate::scanner::transform::FileTransformer;
+use crate::scanner::utils::{
+    check_backend,
+    estimated_size,
+    find_entry,
+    list_pbo_contents,
+    scan_pbo_contents,
+    tool_error,
+    with_tool,
+};
+use crate::scanner::types::{
+    AdoptionReport,
+    CacheVerification,
+    ExtractedFile,
+    ExtractionReport,
+    ExtractionStrategy,
+    ModDetection,
+    NameSanitization,
+    OutputCollisionHandling,
+    OverwritePolicy,
+    PboEntry,
+    PboScanResult,
+    ProcessingOrder,
+    ReservedNameHandling,
+    TimeoutPolicy,
+    TouchReport,
+    VerificationMode,
+    WalkOptions,
+};
 
 pub mod types;
 pub mod scanner;
@@ -19,12 +65,514 @@ pub struct ExtractionConfig<'a> {
     pub input_dir: &'a Path,
     /// Directory for extracted PBO contents
     pub output_dir: &'a Path,
-    /// File extensions to extract (comma-separated)
+    /// File extensions to extract (comma-separated, empty or `*` for every file)
     pub extensions: &'a str,
     /// Number of parallel threads to use
     pub threads: usize,
-    /// Timeout in seconds for PBO operations
+    /// Order PBOs are extracted in; largest first keeps a big PBO from starting last
+    /// and leaving one thread busy after the others are done
+    pub scheduling: ProcessingOrder,
+    /// Most PBOs the tool lists or extracts at once, for disks that slow down under
+    /// many readers and writers; hashing, verification and writing manifests still
+    /// use all `threads`. Above `threads` it has no effect, and `None` sets no limit
+    pub io_concurrency: Option<usize>,
+    /// Most tool processes running at once, listing and extracting, for the whole
+    /// process; runs over it queue and their wait is in the report. `None` uses
+    /// `default_tool_concurrency`: one per thread, up to two per core
+    pub tool_concurrency: Option<usize>,
+    /// Leave the machine usable for other programs: at most half the cores as
+    /// `threads`, the workers at lowered priority and a short pause after each PBO.
+    /// On Linux the tool runs at lowered priority too; elsewhere lowering it would
+    /// take the priority of the whole process, which is left to the host application,
+    /// see `lower_process_priority`. The report says the run was in background mode
+    /// and whether the tool ran at lowered priority
+    pub background: bool,
+    /// Run the tool without a console window rather than letting each run open one,
+    /// as it does on Windows for applications without a console. `None` hides them
+    /// exactly then, so embedding GUI applications don't flash a window per PBO
+    pub hide_console: Option<bool>,
+    /// Timeout in seconds for both listing and extraction; overrides `list_timeout`
+    /// and `extract_timeout` unless 0
     pub timeout: u32,
+    /// Timeout in seconds for listing a PBO's contents during the scan
+    pub list_timeout: u32,
+    /// Timeout in seconds for extracting a PBO, used as the base of the size-scaled timeout
+    pub extract_timeout: u32,
+    /// Extra extraction timeout seconds per 100 MB of PBO size (0 keeps it fixed)
+    pub timeout_per_100mb: u32,
+    /// Upper bound in seconds for the size-scaled timeout
+    pub max_timeout: u32,
+    /// Directory prefixes inside each PBO to restrict extraction to (e.g. `functions/`).
+    /// Combined with the extension filter; empty means the whole PBO.
+    pub include_paths: Vec<String>,
+    /// Skip entries smaller than this many bytes
+    pub min_file_size: Option<u64>,
+    /// Skip entries larger than this many bytes
+    pub max_file_size: Option<u64>,
+    /// Skip compiled `.sqfc` scripts whose `.sqf` source is in the same PBO
+    pub prefer_source: bool,
+    /// Glob patterns a PBO must match to be processed (empty means all PBOs).
+    /// Matched against the file name and the path relative to `input_dir`.
+    pub pbo_include: Vec<String>,
+    /// Glob patterns excluding PBOs from processing, applied after `pbo_include`
+    pub pbo_exclude: Vec<String>,
+    /// Follow symbolic links while walking `input_dir`
+    pub follow_symlinks: bool,
+    /// Maximum directory depth to scan, where 1 means only PBOs directly inside
+    /// `input_dir`. A typical `@mod/addons/x.pbo` layout needs a depth of 3.
+    pub max_depth: Option<usize>,
+    /// Directory names or relative globs skipped entirely during the scan,
+    /// e.g. `WalkOptions::default_ignore_dirs()` for `keys`, `optionals` and `.git`
+    pub ignore_dirs: Vec<String>,
+    /// Archive extensions treated as PBOs, matched case-insensitively (defaults to `pbo`).
+    /// Add `ebo` or `ifa` when the pbo_tools backend supports them.
+    pub archive_extensions: Vec<String>,
+    /// Channel receiving structured progress events; events are dropped if the receiver is gone
+    pub events: Option<tokio::sync::mpsc::UnboundedSender<ExtractionEvent>>,
+    /// Also write every event as a line of JSON, ending with a `RunFinished` summary,
+    /// like `NdjsonWriter::new(std::io::stdout())`. Events a slow reader can't keep up
+    /// with are dropped and counted rather than stalling the run
+    pub json_events: Option<Arc<NdjsonWriter>>,
+    /// How progress is shown; `Auto` uses bars on a terminal and plain status lines otherwise.
+    /// Use `init_logging` to keep log output from tearing the bars. Unless `Silent`, a
+    /// summary of the run is logged at info level when it ends.
+    pub output_mode: OutputMode,
+    /// How often `OutputMode::Plain` logs a status line
+    pub status_interval: StatusInterval,
+    /// Extraction strategies tried in order until one succeeds. Leave out
+    /// `Permissive` to never extract files the tool filter would have skipped.
+    pub fallback_chain: Vec<ExtractionStrategy>,
+    /// Write `.extraction-manifest.json` into each extracted PBO's directory
+    pub write_manifest: bool,
+    /// Write a log for each PBO that fails to extract to `<cache>/failures`, with the
+    /// error of every attempted strategy; removed again once the PBO extracts. On by
+    /// default
+    pub write_failure_logs: bool,
+    /// Write a `SHA256SUMS` file verifiable with `sha256sum -c` into each extracted PBO's directory
+    pub emit_checksums: bool,
+    /// Whether expected files missing after extraction only warn or fail the PBO
+    pub verification: VerificationMode,
+    /// Resume interrupted runs: only extract expected files missing from the output
+    /// (compared by path, and by size where the listing provides it), skipping
+    /// PBOs whose files are all present
+    pub resume: bool,
+    /// Record the output of PBOs whose expected files are all present, like that of an
+    /// earlier extraction by another tool, as their extraction instead of extracting
+    /// them again; see `adopt_existing`
+    pub adopt_existing: bool,
+    /// How expected files already in a PBO's output directory are treated: replaced,
+    /// kept (only missing files are extracted), or reported as an error for that PBO
+    pub overwrite: OverwritePolicy,
+    /// Earlier outputs kept when a changed PBO is extracted again, in `<base>.prev1`
+    /// for the last one up to `<base>.prev<N>`; see `scanner::versions`. Directory
+    /// outputs only; 0 replaces outputs in place
+    pub retain_versions: usize,
+    /// After extracting a PBO, delete files under its output directory that its current
+    /// listing (with the active filter) no longer contains
+    pub sync_outputs: bool,
+    /// Only report the files `sync_outputs` would delete
+    pub sync_dry_run: bool,
+    /// Lowercase every extracted file and directory name, along with the expected
+    /// paths and prefix from the listing; entries that only differ by case are
+    /// reported as collisions and extracted once
+    pub normalize_case: bool,
+    /// How entry names that can't be written on Windows (reserved characters, trailing
+    /// dots or spaces) are handled; renamed entries are recorded in the manifest
+    pub name_sanitization: NameSanitization,
+    /// Whether entries with Windows device names like `aux.sqf` are renamed or skipped;
+    /// either way they are listed in the report
+    pub reserved_names: ReservedNameHandling,
+    /// What happens when PBOs like `foo.pbo` and `foo.ebo`, or `foo.pbo` and a directory
+    /// `foo/` with PBOs inside, would extract into the same output directory. PBOs
+    /// named like retained versions, like `foo.prev1.pbo`, are handled the same way
+    pub output_collisions: OutputCollisionHandling,
+    /// Index the listed files of all PBOs and report virtual paths (prefix included)
+    /// provided by more than one PBO; the index can get large for full game data
+    pub detect_conflicts: bool,
+    /// Keep extracted files in a content-addressed store below the cache directory
+    /// (`objects/`), with the output directories made of hard links (copies where
+    /// linking isn't supported), so identical files are stored once across PBOs and runs
+    pub content_store: bool,
+    /// After the run, replace identical extracted files across the cache directory
+    /// by hard links to a single copy
+    pub dedupe: bool,
+    /// Smallest file size in bytes considered by dedupe
+    pub dedupe_min_size: u64,
+    /// After the run, update `index.json` in the cache directory, mapping the virtual
+    /// path of every extracted file to its location and source PBO. Covers output
+    /// directories of top-level PBOs; see `ExtractionIndex::load` for lookups
+    pub build_index: bool,
+    /// Before extracting, write `prefixes.json` into the cache directory, mapping every
+    /// normalized prefix to the PBOs declaring it; the report always includes the map.
+    /// Runs narrowed by `pbo_include` or `pbo_exclude` keep the prefixes of the PBOs
+    /// they leave out
+    pub write_prefix_map: bool,
+    /// Keep PBO listings in `listings.json.zst` in the cache directory and reuse them
+    /// for PBOs unchanged since, so they aren't listed by the tool again
+    pub cache_listings: bool,
+    /// Largest compressed size in bytes of the listing cache; larger ones are removed
+    /// rather than written
+    pub listing_cache_max_bytes: u64,
+    /// How PBOs are grouped by mod for `ExtractionReport::mods`
+    pub mod_detection: ModDetection,
+    /// Extract even when the estimated output size exceeds the free space of the
+    /// cache directory's file system
+    pub ignore_disk_space: bool,
+    /// Start without checking that the PBO tools can be run, for setups the check
+    /// misjudges; missing tools then fail every PBO on its own
+    pub skip_backend_check: bool,
+    /// Stop starting extractions once they would write more than this many bytes,
+    /// going by the listed entry sizes; remaining PBOs are reported as not processed
+    pub max_output_bytes: Option<u64>,
+    /// Leave PBOs modified less than this long ago for a later run, as an update may
+    /// still be writing them; PBOs changing size after listing are deferred as well
+    pub min_age: Option<Duration>,
+    /// Extract PBOs shipped inside extracted PBOs into a directory next to them;
+    /// `.pbo` entries are added to the extension filter for this
+    pub recursive: bool,
+    /// Nesting levels below the top-level PBOs extracted when `recursive` is set
+    pub max_nesting_depth: usize,
+    /// Write a config.cpp next to every extracted rapified `.bin` file; asking for
+    /// `cpp` files then also extracts `.bin` files
+    pub derapify: bool,
+    /// Parse every extracted stringtable.xml, write it as stringtable.json next to it
+    /// and return the parsed stringtables in the report
+    pub convert_stringtables: bool,
+    /// Write a PNG next to every extracted DXT1/DXT5 `.paa` texture; other formats
+    /// are skipped with a warning. Requires the `paa-convert` feature
+    pub convert_paa: bool,
+    /// Delete textures after converting them to PNG. Resumed runs then extract
+    /// their PBOs again, as the listed textures are missing from the output
+    pub replace_paa_originals: bool,
+    /// While scanning, check each PBO's `.bisign` files against the `.bikey` files in
+    /// its mod's `keys` directory (or `key_dir`) and report the result per PBO.
+    /// Requires the `signatures` feature
+    pub verify_signatures: bool,
+    /// Fail PBOs without a valid signature instead of extracting them; implies
+    /// `verify_signatures`
+    pub require_valid_signature: bool,
+    /// Directory holding the `.bikey` files used for every PBO, instead of each mod's
+    /// `keys` directory
+    pub key_dir: Option<PathBuf>,
+    /// Write each PBO's output into a deterministic tar archive, like
+    /// `addons/ace_medical.tar.zst`, instead of a directory. Resuming, skipping
+    /// existing files and the content store only apply to output directories
+    pub tar_output: Option<TarTarget>,
+    /// Hook run on every extracted file after verification, before it is moved into
+    /// the cache; failures are reported per file without failing the PBO
+    pub transformer: Option<Arc<dyn FileTransformer>>,
+    /// Before extracting, remove output directories of discovered PBOs that have no
+    /// extraction manifest (left partial by older runs); requires `write_manifest`
+    pub clean_partial: bool,
+    /// Extra attempts for listings and extractions that time out
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub retry_backoff: Duration,
+    /// Extra attempts for PBOs another process holds open without sharing; ones
+    /// still locked are reported as locked and extracted by the next run
+    pub lock_retries: u32,
+    /// Delay before each retry of a locked PBO
+    pub lock_retry_delay: Duration,
+    /// Cancelling this token from another task stops the run: no further PBOs are
+    /// started, the ones in progress finish and `extract_pbos` returns the report in
+    /// `ExtractionError::Cancelled`
+    pub cancel: CancelToken,
+    /// Whether to keep extracting when PBOs fail, stop on the first failure, or stop
+    /// once failures exceed a threshold. Stopped runs fail with
+    /// `ExtractionError::PartialFailure`, whose report lists the failures
+    pub failure_policy: FailurePolicy,
+    /// Skip PBOs that failed this many runs in a row until their hash changes,
+    /// optionally keeping a copy in `<cache>/quarantine`. Quarantines are kept in the
+    /// extraction index, which is then written even without `build_index`
+    pub quarantine: Option<QuarantinePolicy>,
+    /// Stop retrying PBOs that failed this many runs in a row until their hash
+    /// changes. Failures are counted in the extraction index, which is then written
+    /// even without `build_index`
+    pub max_attempts: Option<u32>,
+    /// Retry PBOs given up on because of `max_attempts` anyway
+    pub force_retry: bool,
+    /// Write a `RunReport` with the options, timestamps and report of each run to
+    /// this path, relative to the output directory, like `RUN_REPORT_FILE_NAME`.
+    /// Also written when the run is cancelled or stopped by the failure policy
+    pub run_report: Option<PathBuf>,
+    /// Write one CSV row per PBO to this path, relative to the output directory
+    pub csv_report: Option<PathBuf>,
+    /// Write one CSV row per mod to this path, relative to the output directory
+    pub mods_csv_report: Option<PathBuf>,
+    /// PBOs listed in the report's slowest table, 0 to leave it out
+    pub slowest_pbos: usize,
+    /// Also log to this file, for runs whose stderr goes nowhere. Unless the global
+    /// logger was installed with `init_logging`, the run's events are written instead.
+    /// Failing to open it only logs a warning
+    pub log_file: Option<PathBuf>,
+    /// Most verbose level written to `log_file`, independent of the console
+    pub log_file_level: log::LevelFilter,
+    pub log_rotation: LogRotation,
+    /// Cancel the run on Ctrl-C, finishing the PBOs in progress. Off by default,
+    /// as the handler replaces the process's default Ctrl-C handling for good
+    pub handle_signals: bool,
+    /// What to do when another process is running on the output directory. Runs
+    /// lock it exclusively, so two can't update its index at the same time
+    pub cache_lock: CacheLockPolicy,
+}
+
+impl<'a> ExtractionConfig<'a> {
+    /// Create a configuration with default options for the given directories
+    pub fn new(input_dir: &'a Path, output_dir: &'a Path) -> Self {
+        Self {
+            input_dir,
+            output_dir,
+            extensions: "",
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            scheduling: ProcessingOrder::default(),
+            io_concurrency: None,
+            tool_concurrency: None,
+            background: false,
+            hide_console: None,
+            timeout: 0,
+            list_timeout: 10,
+            extract_timeout: 120,
+            timeout_per_100mb: 0,
+            max_timeout: 600,
+            include_paths: Vec::new(),
+            min_file_size: None,
+            max_file_size: None,
+            prefer_source: false,
+            pbo_include: Vec::new(),
+            pbo_exclude: Vec::new(),
+            follow_symlinks: false,
+            max_depth: None,
+            ignore_dirs: Vec::new(),
+            archive_extensions: vec!["pbo".to_string()],
+            events: None,
+            json_events: None,
+            output_mode: OutputMode::Auto,
+            status_interval: StatusInterval::default(),
+            fallback_chain: ExtractionStrategy::default_chain(),
+            write_manifest: true,
+            write_failure_logs: true,
+            emit_checksums: false,
+            verification: VerificationMode::default(),
+            resume: false,
+            adopt_existing: false,
+            overwrite: OverwritePolicy::default(),
+            retain_versions: 0,
+            sync_outputs: false,
+            sync_dry_run: false,
+            clean_partial: false,
+            normalize_case: false,
+            name_sanitization: NameSanitization::default(),
+            reserved_names: ReservedNameHandling::default(),
+            output_collisions: OutputCollisionHandling::default(),
+            detect_conflicts: false,
+            content_store: false,
+            dedupe: false,
+            dedupe_min_size: DEFAULT_DEDUPE_MIN_SIZE,
+            build_index: false,
+            write_prefix_map: true,
+            cache_listings: true,
+            listing_cache_max_bytes: DEFAULT_LISTING_CACHE_MAX_BYTES,
+            mod_detection: ModDetection::default(),
+            ignore_disk_space: false,
+            skip_backend_check: false,
+            max_output_bytes: None,
+            min_age: None,
+            recursive: false,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            derapify: false,
+            convert_stringtables: false,
+            convert_paa: false,
+            replace_paa_originals: false,
+            verify_signatures: false,
+            require_valid_signature: false,
+            key_dir: None,
+            tar_output: None,
+            transformer: None,
+            max_retries: RetryPolicy::default().max_retries,
+            retry_backoff: RetryPolicy::default().backoff_base,
+            lock_retries: LockRetry::default().max_retries,
+            lock_retry_delay: LockRetry::default().delay,
+            cancel: CancelToken::new(),
+            failure_policy: FailurePolicy::default(),
+            quarantine: None,
+            max_attempts: None,
+            force_retry: false,
+            run_report: None,
+            csv_report: None,
+            mods_csv_report: None,
+            slowest_pbos: DEFAULT_SLOWEST_PBOS,
+            log_file: None,
+            log_file_level: log::LevelFilter::Info,
+            log_rotation: LogRotation::default(),
+            handle_signals: false,
+            cache_lock: CacheLockPolicy::default(),
+        }
+    }
+
+    /// Create a configuration from a file loaded with `ConfigFile::load`
+    ///
+    /// The configuration borrows its directories and extensions from `file`, so the
+    /// file is loaded first and can be adjusted, for example by command line flags.
+    /// Fails when `input_dir` or `output_dir` is missing, or a value is out of range.
+    pub fn from_toml(file: &'a ConfigFile) -> Result<Self> {
+        let input_dir = file.input_dir.as_deref().context("input_dir is not set")?;
+        let output_dir = file.output_dir.as_deref().context("output_dir is not set")?;
+        let defaults = Self::new(input_dir, output_dir);
+        Ok(Self {
+            extensions: file.extensions.as_deref().unwrap_or(defaults.extensions),
+            threads: file.threads.unwrap_or(defaults.threads),
+            scheduling: file.scheduling.unwrap_or(defaults.scheduling),
+            io_concurrency: file.io_concurrency.or(defaults.io_concurrency),
+            tool_concurrency: file.tool_concurrency.or(defaults.tool_concurrency),
+            background: file.background.unwrap_or(defaults.background),
+            hide_console: file.hide_console.or(defaults.hide_console),
+            timeout: file.timeout.unwrap_or(defaults.timeout),
+            list_timeout: file.list_timeout.unwrap_or(defaults.list_timeout),
+            extract_timeout: file.extract_timeout.unwrap_or(defaults.extract_timeout),
+            timeout_per_100mb: file.timeout_per_100mb.unwrap_or(defaults.timeout_per_100mb),
+            max_timeout: file.max_timeout.unwrap_or(defaults.max_timeout),
+            include_paths: file.include_paths.clone().unwrap_or(defaults.include_paths),
+            min_file_size: file.min_file_size.or(defaults.min_file_size),
+            max_file_size: file.max_file_size.or(defaults.max_file_size),
+            prefer_source: file.prefer_source.unwrap_or(defaults.prefer_source),
+            pbo_include: file.pbo_include.clone().unwrap_or(defaults.pbo_include),
+            pbo_exclude: file.pbo_exclude.clone().unwrap_or(defaults.pbo_exclude),
+            follow_symlinks: file.follow_symlinks.unwrap_or(defaults.follow_symlinks),
+            max_depth: file.max_depth.or(defaults.max_depth),
+            ignore_dirs: file.ignore_dirs.clone().unwrap_or(defaults.ignore_dirs),
+            archive_extensions: file.archive_extensions.clone().unwrap_or(defaults.archive_extensions),
+            output_mode: file.output_mode.unwrap_or(defaults.output_mode),
+            status_interval: StatusInterval {
+                every: file.status_interval_secs.map_or(defaults.status_interval.every, Duration::from_secs),
+                every_pbos: file.status_every_pbos.or(defaults.status_interval.every_pbos),
+            },
+            fallback_chain: file.fallback_chain.clone().unwrap_or(defaults.fallback_chain),
+            write_manifest: file.write_manifest.unwrap_or(defaults.write_manifest),
+            write_failure_logs: file.write_failure_logs.unwrap_or(defaults.write_failure_logs),
+            emit_checksums: file.emit_checksums.unwrap_or(defaults.emit_checksums),
+            verification: file.verification.unwrap_or(defaults.verification),
+            resume: file.resume.unwrap_or(defaults.resume),
+            adopt_existing: file.adopt_existing.unwrap_or(defaults.adopt_existing),
+            overwrite: file.overwrite.unwrap_or(defaults.overwrite),
+            retain_versions: file.retain_versions.unwrap_or(defaults.retain_versions),
+            sync_outputs: file.sync_outputs.unwrap_or(defaults.sync_outputs),
+            sync_dry_run: file.sync_dry_run.unwrap_or(defaults.sync_dry_run),
+            normalize_case: file.normalize_case.unwrap_or(defaults.normalize_case),
+            name_sanitization: file.name_sanitization.unwrap_or(defaults.name_sanitization),
+            reserved_names: file.reserved_names.unwrap_or(defaults.reserved_names),
+            output_collisions: file.output_collisions.unwrap_or(defaults.output_collisions),
+            detect_conflicts: file.detect_conflicts.unwrap_or(defaults.detect_conflicts),
+            content_store: file.content_store.unwrap_or(defaults.content_store),
+            dedupe: file.dedupe.unwrap_or(defaults.dedupe),
+            dedupe_min_size: file.dedupe_min_size.unwrap_or(defaults.dedupe_min_size),
+            build_index: file.build_index.unwrap_or(defaults.build_index),
+            write_prefix_map: file.write_prefix_map.unwrap_or(defaults.write_prefix_map),
+            cache_listings: file.cache_listings.unwrap_or(defaults.cache_listings),
+            listing_cache_max_bytes: file.listing_cache_max_bytes.unwrap_or(defaults.listing_cache_max_bytes),
+            mod_detection: file.mod_detection.unwrap_or(defaults.mod_detection),
+            ignore_disk_space: file.ignore_disk_space.unwrap_or(defaults.ignore_disk_space),
+            skip_backend_check: file.skip_backend_check.unwrap_or(defaults.skip_backend_check),
+            max_output_bytes: file.max_output_bytes.or(defaults.max_output_bytes),
+            min_age: file.min_age_secs.map(Duration::from_secs).or(defaults.min_age),
+            recursive: file.recursive.unwrap_or(defaults.recursive),
+            max_nesting_depth: file.max_nesting_depth.unwrap_or(defaults.max_nesting_depth),
+            derapify: file.derapify.unwrap_or(defaults.derapify),
+            convert_stringtables: file.convert_stringtables.unwrap_or(defaults.convert_stringtables),
+            convert_paa: file.convert_paa.unwrap_or(defaults.convert_paa),
+            replace_paa_originals: file.replace_paa_originals.unwrap_or(defaults.replace_paa_originals),
+            verify_signatures: file.verify_signatures.unwrap_or(defaults.verify_signatures),
+            require_valid_signature: file.require_valid_signature.unwrap_or(defaults.require_valid_signature),
+            key_dir: file.key_dir.clone().or(defaults.key_dir),
+            tar_output: file.tar_output.as_ref().map(|tar| tar.target()).transpose()?.or(defaults.tar_output),
+            clean_partial: file.clean_partial.unwrap_or(defaults.clean_partial),
+            max_retries: file.max_retries.unwrap_or(defaults.max_retries),
+            retry_backoff: file.retry_backoff_ms.map_or(defaults.retry_backoff, Duration::from_millis),
+            lock_retries: file.lock_retries.unwrap_or(defaults.lock_retries),
+            lock_retry_delay: file.lock_retry_delay_ms.map_or(defaults.lock_retry_delay, Duration::from_millis),
+            failure_policy: match &file.failure_policy {
+                Some(policy) => policy.policy()?,
+                None => defaults.failure_policy,
+            },
+            quarantine: file.quarantine.or(defaults.quarantine),
+            max_attempts: file.max_attempts.or(defaults.max_attempts),
+            force_retry: file.force_retry.unwrap_or(defaults.force_retry),
+            run_report: file.run_report.clone().or(defaults.run_report),
+            csv_report: file.csv_report.clone().or(defaults.csv_report),
+            mods_csv_report: file.mods_csv_report.clone().or(defaults.mods_csv_report),
+            slowest_pbos: file.slowest_pbos.unwrap_or(defaults.slowest_pbos),
+            log_file: file.log_file.clone().or(defaults.log_file),
+            log_file_level: file.log_file_level()?.unwrap_or(defaults.log_file_level),
+            log_rotation: file.log_rotation.unwrap_or(defaults.log_rotation),
+            handle_signals: file.handle_signals.unwrap_or(defaults.handle_signals),
+            cache_lock: file.cache_lock.unwrap_or(defaults.cache_lock),
+            ..defaults
+        })
+    }
+
+    /// Build the per-entry filter described by this configuration
+    pub fn file_filter(&self) -> FileFilter {
+        FileFilter {
+            min_file_size: self.min_file_size,
+            max_file_size: self.max_file_size,
+            prefer_source: self.prefer_source,
+            ..FileFilter::new(self.extensions).with_include_paths(self.include_paths.clone())
+        }
+    }
+
+    /// Timeout in seconds for listing a PBO's contents
+    pub fn list_timeout_secs(&self) -> u32 {
+        match self.timeout {
+            0 => self.list_timeout,
+            timeout => timeout,
+        }
+    }
+
+    /// Build the per-PBO extraction timeout policy described by this configuration
+    pub fn timeout_policy(&self) -> TimeoutPolicy {
+        TimeoutPolicy {
+            base_secs: match self.timeout {
+                0 => self.extract_timeout,
+                timeout => timeout,
+            },
+            secs_per_100mb: self.timeout_per_100mb,
+            max_secs: self.max_timeout,
+        }
+    }
+
+    /// Build the timeout retry policy described by this configuration
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            backoff_base: self.retry_backoff,
+        }
+    }
+
+    /// Build the locked file retry settings described by this configuration
+    pub fn lock_retry(&self) -> LockRetry {
+        LockRetry {
+            max_retries: self.lock_retries,
+            delay: self.lock_retry_delay,
+        }
+    }
+
+    /// Threads the run uses, capped in background mode
+    pub fn worker_threads(&self) -> usize {
+        if self.background {
+            crate::utils::background_threads(self.threads)
+        } else {
+            self.threads
+        }
+    }
+
+    /// Build the directory walk options described by this configuration
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            follow_symlinks: self.follow_symlinks,
+            max_depth: self.max_depth,
+            ignore_dirs: self.ignore_dirs.clone(),
+            archive_extensions: self.archive_extensions.clone(),
+        }
+    }
 }
 
 /// Extract files from multiple PBO archives in parallel
@@ -33,50 +581,324 @@ pub struct ExtractionConfig<'a> {
 /// * `config` - Configuration specifying input/output directories and extraction options
 ///
 /// # Returns
-/// * `Result<()>` - Success or error during extraction
-pub async fn extract_pbos(config: ExtractionConfig<'_>) -> Result<()> {
+/// * `Result<ExtractionReport, ExtractionError>` - Summary of the run, or why it failed.
+///   Runs that were cancelled or stopped by the failure policy return their report in
+///   `ExtractionError::Cancelled` and `ExtractionError::PartialFailure`
+pub async fn extract_pbos(config: ExtractionConfig<'_>) -> Result<ExtractionReport, ExtractionError> {
+    // First, so the file gets the whole run
+    let log_file = config.log_file.as_deref().and_then(|path| {
+        LogFile::open(path, config.log_file_level, config.log_rotation)
+            .inspect_err(|e| warn!("Not logging to {}: {:#}", path.display(), e))
+            .ok()
+    });
     debug!("Starting PBO extraction with configuration:");
     debug!("  Input directory: {}", config.input_dir.display());
     debug!("  Output directory: {}", config.output_dir.display());
     debug!("  Extensions filter: {}", config.extensions);
-    debug!("  Threads: {}", config.threads);
-    debug!("  Timeout: {} seconds", config.timeout);
+    debug!("  Include paths: {:?}", config.include_paths);
+    debug!("  File size range: {:?}..={:?}", config.min_file_size, config.max_file_size);
+    debug!("  Prefer source: {}", config.prefer_source);
+    debug!("  PBO include patterns: {:?}", config.pbo_include);
+    debug!("  PBO exclude patterns: {:?}", config.pbo_exclude);
+    debug!("  Threads: {}", config.worker_threads());
+    debug!("  Scheduling: {:?}", config.scheduling);
+    debug!("  I/O concurrency: {:?}", config.io_concurrency);
+    debug!("  Tool concurrency: {:?}", config.tool_concurrency);
+    debug!("  Background: {}", config.background);
+    debug!("  Hide console: {:?}", config.hide_console);
+    debug!("  List timeout: {} seconds", config.list_timeout_secs());
+    debug!("  Extract timeout: {:?}", config.timeout_policy());
+    debug!("  Output mode: {:?}", config.output_mode);
+    debug!("  JSON events: {}", config.json_events.is_some());
+    debug!("  Fallback chain: {:?}", config.fallback_chain);
+    debug!("  Write manifests: {}", config.write_manifest);
+    debug!("  Write failure logs: {}", config.write_failure_logs);
+    debug!("  Emit checksums: {}", config.emit_checksums);
+    debug!("  Verification: {:?}", config.verification);
+    debug!("  Resume: {}", config.resume);
+    debug!("  Adopt existing outputs: {}", config.adopt_existing);
+    debug!("  Overwrite: {:?}", config.overwrite);
+    debug!("  Retained versions: {}", config.retain_versions);
+    debug!("  Sync outputs: {} (dry run: {})", config.sync_outputs, config.sync_dry_run);
+    debug!("  Clean partial outputs: {}", config.clean_partial);
+    debug!("  Normalize case: {}", config.normalize_case);
+    debug!("  Name sanitization: {:?}", config.name_sanitization);
+    debug!("  Reserved names: {:?}", config.reserved_names);
+    debug!("  Output collisions: {:?}", config.output_collisions);
+    debug!("  Detect conflicts: {}", config.detect_conflicts);
+    debug!("  Content store: {}", config.content_store);
+    debug!("  Dedupe: {} (min size {} bytes)", config.dedupe, config.dedupe_min_size);
+    debug!("  Build index: {}", config.build_index);
+    debug!("  Write prefix map: {}", config.write_prefix_map);
+    debug!("  Cache listings: {} (max {} bytes)", config.cache_listings, config.listing_cache_max_bytes);
+    debug!("  Mod detection: {:?}", config.mod_detection);
+    debug!("  Ignore disk space: {}", config.ignore_disk_space);
+    debug!("  Skip backend check: {}", config.skip_backend_check);
+    debug!("  Max output bytes: {:?}", config.max_output_bytes);
+    debug!("  Min age: {:?}", config.min_age);
+    debug!("  Recursive: {} (max depth {})", config.recursive, config.max_nesting_depth);
+    debug!("  Derapify: {}", config.derapify);
+    debug!("  Convert stringtables: {}", config.convert_stringtables);
+    debug!("  Tar output: {:?}", config.tar_output);
+    debug!("  Convert PAA textures: {} (replace originals {})", config.convert_paa, config.replace_paa_originals);
+    debug!(
+        "  Verify signatures: {} (required {}, key dir {:?})",
+        config.verify_signatures,
+        config.require_valid_signature,
+        config.key_dir
+    );
+    debug!("  Transformer: {:?}", config.transformer);
+    debug!("  Retries: {} (backoff {:?})", config.max_retries, config.retry_backoff);
+    debug!("  Lock retries: {} (delay {:?})", config.lock_retries, config.lock_retry_delay);
+    debug!("  Failure policy: {:?}", config.failure_policy);
+    debug!("  Quarantine: {:?}", config.quarantine);
+    debug!("  Max attempts: {:?} (force retry: {})", config.max_attempts, config.force_retry);
+    debug!("  Run report: {:?}", config.run_report);
+    debug!("  CSV reports: {:?}, mods: {:?}", config.csv_report, config.mods_csv_report);
+    debug!("  Slowest PBOs: {}", config.slowest_pbos);
+    debug!("  Log file: {:?} ({}, {:?})", config.log_file, config.log_file_level, config.log_rotation);
+    debug!("  Handle signals: {}", config.handle_signals);
+    debug!("  Cache lock: {:?}", config.cache_lock);
     
+    check_dirs(&config)?;
+
+    // Create and run the coordinator
+    let filter = config.file_filter();
+    let coordinator = scan_coordinator(&config, &filter, log_file.as_ref())?;
+
+    let _signals = match config.handle_signals {
+        true => Some(SignalHandler::install(config.cancel.clone())?),
+        false => None,
+    };
+    let _lock = lock_cache_async(&config).await?;
+    let report = run_blocking(coordinator.run()).await;
+    if let Some(json_events) = &config.json_events {
+        json_events.flush();
+    }
+    let report = report?;
+    if config.output_mode != OutputMode::Silent {
+        info!("{}", report);
+    }
+    if report.aborted {
+        return Err(ExtractionError::PartialFailure(Box::new(report)));
+    }
+    if report.interrupted {
+        return Err(ExtractionError::Cancelled(Box::new(report)));
+    }
+    Ok(report)
+}
+
+/// Record the outputs already in the output directory, like those of an earlier
+/// extraction by another tool, without extracting anything
+///
+/// PBOs whose expected files are all in their output directory with the listed
+/// size get the manifest and index entry an extraction would have given them;
+/// see `ScanCoordinator::adopt_existing`. The others are left for `extract_pbos`.
+pub fn adopt_existing(config: ExtractionConfig<'_>) -> Result<AdoptionReport, ExtractionError> {
+    check_dirs(&config)?;
+    let filter = config.file_filter();
+    let coordinator = scan_coordinator(&config, &filter, None)?;
+    let _signals = match config.handle_signals {
+        true => Some(SignalHandler::install(config.cancel.clone())?),
+        false => None,
+    };
+    let _lock = lock_cache(&config)?;
+    Ok(coordinator.adopt_existing()?)
+}
+
+/// PBOs a run with `config` would pick up, relative to the input directory; see
+/// `ScanCoordinator::find_pbos`
+pub fn find_pbos(config: &ExtractionConfig<'_>) -> Result<Vec<PathBuf>, ExtractionError> {
+    let filter = config.file_filter();
+    let coordinator = scan_coordinator(config, &filter, None)?;
+    Ok(coordinator.find_pbos()?.into_iter().map(|(_, rel_path)| rel_path).collect())
+}
+
+/// Refresh the hash recorded for PBOs whose output is complete, without extracting;
+/// see `ScanCoordinator::touch_hashes`
+pub fn touch_hashes(config: ExtractionConfig<'_>) -> Result<TouchReport, ExtractionError> {
+    check_dirs(&config)?;
+    let filter = config.file_filter();
+    let coordinator = scan_coordinator(&config, &filter, None)?;
+    let _signals = match config.handle_signals {
+        true => Some(SignalHandler::install(config.cancel.clone())?),
+        false => None,
+    };
+    let _lock = lock_cache(&config)?;
+    Ok(coordinator.touch_hashes()?)
+}
+
+/// Re-check the outputs the extraction index records against fresh PBO listings
+///
+/// Broken outputs are recorded as failed in the index, so the next run extracts
+/// them again; see `ScanCoordinator::verify_cache`. With `fix`, they are extracted
+/// in full right away, with the other options of `config`, and the run's report is
+/// returned in `CacheVerification::repair`. That run only covers the broken PBOs, so
+/// like any run narrowed by `pbo_include` it keeps the others in `prefixes.json`.
+pub async fn verify_cache(config: ExtractionConfig<'_>, fix: bool) -> Result<CacheVerification, ExtractionError> {
+    check_dirs(&config)?;
+    let filter = config.file_filter();
+    let mut verification = {
+        let coordinator = scan_coordinator(&config, &filter, None)?;
+        let _signals = match config.handle_signals {
+            true => Some(SignalHandler::install(config.cancel.clone())?),
+            false => None,
+        };
+        // Released before repairing, which takes it again
+        let _lock = lock_cache_async(&config).await?;
+        run_blocking(async { coordinator.verify_cache() }).await?
+    };
+    if !fix || verification.broken.is_empty() {
+        return Ok(verification);
+    }
+
+    info!("Re-extracting {} PBOs with broken output", verification.broken.len());
+    let pbo_include = verification.broken.iter()
+        .map(|discrepancy| glob::Pattern::escape(&discrepancy.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+    let repair = ExtractionConfig { pbo_include, build_index: true, ..config };
+    let report = match extract_pbos(repair).await {
+        Ok(report) => report,
+        Err(ExtractionError::PartialFailure(report)) => *report,
+        Err(e) => return Err(e),
+    };
+    verification.repair = Some(Box::new(report));
+    Ok(verification)
+}
+
+/// Lock the output directory for a run writing to it, as `config.cache_lock` says
+fn lock_cache(config: &ExtractionConfig) -> Result<CacheLock, ExtractionError> {
+    CacheLock::exclusive(config.output_dir, config.cache_lock, &config.cancel)
+}
+
+/// `lock_cache` for async functions, waiting off the runtime's threads
+async fn lock_cache_async(config: &ExtractionConfig<'_>) -> Result<CacheLock, ExtractionError> {
+    let output_dir = config.output_dir.to_owned();
+    let (policy, cancel) = (config.cache_lock, config.cancel.clone());
+    tokio::task::spawn_blocking(move || CacheLock::exclusive(&output_dir, policy, &cancel))
+        .await
+        .context("Cache lock task failed")?
+}
+
+/// Drive `future`, which hashes, lists and extracts without yielding, without
+/// stalling the other tasks of a multi-threaded runtime
+///
+/// Its worker hands those tasks to the other workers first. A current-thread
+/// runtime has no other worker to hand them to, so there it runs in place.
+async fn run_blocking<F: std::future::Future>(future: F) -> F::Output {
+    let handle = tokio::runtime::Handle::current();
+    match handle.runtime_flavor() {
+        tokio::runtime::RuntimeFlavor::MultiThread => tokio::task::block_in_place(|| handle.block_on(future)),
+        _ => future.await,
+    }
+}
+
+/// Check the input directory is readable and the output directory writable, creating it
+fn check_dirs(config: &ExtractionConfig) -> Result<(), ExtractionError> {
+    if config.fallback_chain.is_empty() {
+        return Err(anyhow::anyhow!("Fallback chain must contain at least one extraction strategy").into());
+    }
+
     // Verify input directory exists and is readable
     if !config.input_dir.exists() {
-        return Err(anyhow::anyhow!("Input directory does not exist: {}", config.input_dir.display()));
+        return Err(ExtractionError::InputDirNotFound(config.input_dir.to_owned()));
     }
     
     // Verify input directory is readable by trying to list its contents
-    if let Err(e) = std::fs::read_dir(config.input_dir) {
-        return Err(anyhow::anyhow!("Input directory is not readable: {} - {}", config.input_dir.display(), e));
+    if let Err(source) = std::fs::read_dir(config.input_dir) {
+        return Err(ExtractionError::InputDirNotReadable { path: config.input_dir.to_owned(), source });
     }
     
     // Create output directory if it doesn't exist
     if !config.output_dir.exists() {
         debug!("Creating output directory: {}", config.output_dir.display());
-        if let Err(e) = std::fs::create_dir_all(config.output_dir) {
-            return Err(anyhow::anyhow!("Failed to create output directory: {} - {}", config.output_dir.display(), e));
+        if let Err(source) = std::fs::create_dir_all(config.output_dir) {
+            return Err(ExtractionError::OutputNotWritable { path: config.output_dir.to_owned(), source });
         }
     }
     
     // Verify output directory is writable
     let test_file = config.output_dir.join(".test_write");
-    if let Err(e) = std::fs::write(&test_file, "test") {
-        return Err(anyhow::anyhow!("Output directory is not writable: {} - {}", config.output_dir.display(), e));
+    if let Err(source) = std::fs::write(&test_file, "test") {
+        return Err(ExtractionError::OutputNotWritable { path: config.output_dir.to_owned(), source });
     }
     let _ = std::fs::remove_file(test_file);
+    Ok(())
+}
 
-    // Create and run the coordinator
+/// Coordinator running with the options of `config`
+pub(crate) fn scan_coordinator<'c>(
+    config: &'c ExtractionConfig<'_>,
+    filter: &'c FileFilter,
+    log_file: Option<&LogFile>,
+) -> Result<ScanCoordinator<'c>> {
+    let pbo_filter = PboFilter::new(&config.pbo_include, &config.pbo_exclude)?;
     let coordinator = ScanCoordinator::new(
         config.input_dir,
         config.output_dir,
-        config.extensions,
-        config.threads,
-        config.timeout,
-    )?;
-
-    coordinator.run().await
+        filter,
+        config.worker_threads(),
+        config.list_timeout_secs(),
+    )?
+    .with_pbo_filter(pbo_filter)
+    .with_processing_order(config.scheduling)
+    .with_io_concurrency(config.io_concurrency)
+    .with_tool_concurrency(config.tool_concurrency)
+    .with_background(config.background)
+    .with_hide_console(config.hide_console)
+    .with_walk_options(config.walk_options())
+    .with_events(
+        EventSink::new(config.events.clone())
+            .with_log_file(log_file.and_then(LogFile::event_file))
+            .with_json(config.json_events.clone())
+    )
+    .with_progress(ExtractionProgress::for_mode(config.output_mode, config.status_interval))
+    .with_retry(config.retry_policy())
+    .with_lock_retry(config.lock_retry())
+    .with_cancellation(config.cancel.clone())
+    .with_failure_policy(config.failure_policy)
+    .with_quarantine(config.quarantine)
+    .with_max_attempts(config.max_attempts)
+    .with_force_retry(config.force_retry)
+    .with_run_report(config.run_report.clone())
+    .with_csv_reports(config.csv_report.clone(), config.mods_csv_report.clone())
+    .with_slowest(config.slowest_pbos)
+    .with_fallback_chain(config.fallback_chain.clone())
+    .with_manifest(config.write_manifest)
+    .with_failure_logs(config.write_failure_logs)
+    .with_checksums(config.emit_checksums)
+    .with_verification(config.verification)
+    .with_resume(config.resume)
+    .with_adopt_existing(config.adopt_existing)
+    .with_overwrite(config.overwrite)
+    .with_retain_versions(config.retain_versions)
+    .with_sync_outputs(config.sync_outputs, config.sync_dry_run)
+    .with_clean_partial(config.clean_partial)
+    .with_normalize_case(config.normalize_case)
+    .with_name_sanitization(config.name_sanitization)
+    .with_reserved_names(config.reserved_names)
+    .with_output_collisions(config.output_collisions)
+    .with_conflict_detection(config.detect_conflicts)
+    .with_content_store(config.content_store)
+    .with_dedupe(config.dedupe, config.dedupe_min_size)
+    .with_index(config.build_index)
+    .with_prefix_map(config.write_prefix_map)
+    .with_listing_cache(config.cache_listings, config.listing_cache_max_bytes)
+    .with_mod_detection(config.mod_detection)
+    .with_ignore_disk_space(config.ignore_disk_space)
+    .with_skip_backend_check(config.skip_backend_check)
+    .with_max_output_bytes(config.max_output_bytes)
+    .with_min_age(config.min_age)
+    .with_recursion(config.recursive, config.max_nesting_depth)
+    .with_derapify(config.derapify.then(|| Arc::new(RapConverter) as Arc<dyn ConfigConverter>))
+    .with_stringtable_conversion(config.convert_stringtables)
+    .with_paa_conversion(config.convert_paa, config.replace_paa_originals)
+    .with_signature_verification(config.verify_signatures, config.require_valid_signature, config.key_dir.clone())
+    .with_tar_output(config.tar_output)
+    .with_transformer(config.transformer.clone())
+    .with_list_timeout(config.list_timeout_secs())
+    .with_timeout_policy(config.timeout_policy());
+    Ok(coordinator)
 }
 
 /// Extract a single PBO archive with default options
@@ -86,13 +908,179 @@ pub async fn extract_pbos(config: ExtractionConfig<'_>) -> Result<()> {
 /// * `output_dir` - Directory where contents will be extracted
 ///
 /// # Returns
-/// * `Result<()>` - Success or error during extraction
-pub fn extract_pbo(pbo_path: &Path, output_dir: &Path) -> Result<()> {
-    let api = PboApi::builder()
-        .with_timeout(30)
-        .build();
-    api.extract_files(pbo_path, output_dir, None)?;
-    Ok(())
+/// * `Result<(), ExtractionError>` - Success or error during extraction
+pub fn extract_pbo(pbo_path: &Path, output_dir: &Path) -> Result<(), ExtractionError> {
+    // Clearer than whatever the tool would say about it
+    if !pbo_path.is_file() {
+        return Err(anyhow::anyhow!("PBO file does not exist: {}", pbo_path.display()).into());
+    }
+    check_backend()?;
+    match with_tool(30, |api| api.extract_files(pbo_path, output_dir, None)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(tool_error(e, 30).into()),
+    }
+}
+
+/// Default cap on the total size of the files returned by `extract_to_memory`
+pub const DEFAULT_MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Extract the files of a PBO matching `filter` into memory
+///
+/// Returns the file contents by entry path, using forward slashes. Fails when the
+/// matching files take more than `DEFAULT_MEMORY_LIMIT` bytes.
+pub fn extract_to_memory(pbo_path: &Path, filter: &FileFilter, timeout: u32) -> Result<HashMap<String, Vec<u8>>> {
+    extract_to_memory_with_limit(pbo_path, filter, timeout, DEFAULT_MEMORY_LIMIT)
+}
+
+/// Extract the files of a PBO matching `filter` into memory, up to `max_bytes`
+///
+/// Listed sizes are checked before extracting. The files are extracted into a
+/// temporary directory, which is removed before returning.
+pub fn extract_to_memory_with_limit(
+    pbo_path: &Path,
+    filter: &FileFilter,
+    timeout: u32,
+    max_bytes: u64,
+) -> Result<HashMap<String, Vec<u8>>> {
+    let scan_result = scan_pbo_contents(pbo_path, filter, timeout)?;
+    if scan_result.expected_files.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let listed_size = estimated_size(&scan_result.expected_files, 0);
+    if listed_size > max_bytes {
+        return Err(anyhow::anyhow!(
+            "{} matching files in {} take {} bytes, over the limit of {} bytes",
+            scan_result.expected_files.len(),
+            pbo_path.display(),
+            listed_size,
+            max_bytes
+        ));
+    }
+
+    let temp_dir = extract_to_temp_dir(&scan_result, timeout)?;
+
+    // Sizes are checked again, as the listing may not provide them
+    let mut files = HashMap::new();
+    let mut total_size = 0;
+    for entry in &scan_result.expected_files {
+        let path = temp_dir.path().join(crate::utils::native_entry_path(&entry.path)?);
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("Listed file was not extracted from {}: {}", pbo_path.display(), entry.path);
+                continue;
+            },
+            Err(e) => return Err(e.into()),
+        };
+        total_size += size;
+        if total_size > max_bytes {
+            return Err(anyhow::anyhow!(
+                "Matching files in {} exceed the limit of {} bytes",
+                pbo_path.display(),
+                max_bytes
+            ));
+        }
+        files.insert(entry.path.clone(), std::fs::read(&path)?);
+    }
+    Ok(files)
+}
+
+/// Extract the files of a PBO matching `filter` into memory, up to `max_bytes`,
+/// on the blocking thread pool
+pub async fn extract_to_memory_async(
+    pbo_path: &Path,
+    filter: &FileFilter,
+    timeout: u32,
+    max_bytes: u64,
+) -> Result<HashMap<String, Vec<u8>>> {
+    let pbo_path = pbo_path.to_owned();
+    let filter = filter.clone();
+    tokio::task::spawn_blocking(move || extract_to_memory_with_limit(&pbo_path, &filter, timeout, max_bytes)).await?
+}
+
+/// Extract the files of a PBO matching `filter`, handing each file's path and
+/// contents to `sink` in listing order
+///
+/// The files are extracted into a temporary directory, which is removed before
+/// returning. An error from `sink` stops the extraction and is returned. Returns
+/// the number of files handed to `sink`.
+pub fn extract_streaming<F>(pbo_path: &Path, filter: &FileFilter, timeout: u32, mut sink: F) -> Result<usize>
+where
+    F: FnMut(&str, &[u8]) -> Result<()>,
+{
+    let scan_result = scan_pbo_contents(pbo_path, filter, timeout)?;
+    if scan_result.expected_files.is_empty() {
+        return Ok(0);
+    }
+    let temp_dir = extract_to_temp_dir(&scan_result, timeout)?;
+
+    let mut streamed = 0;
+    for entry in &scan_result.expected_files {
+        let path = temp_dir.path().join(crate::utils::native_entry_path(&entry.path)?);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("Listed file was not extracted from {}: {}", pbo_path.display(), entry.path);
+                continue;
+            },
+            Err(e) => return Err(e.into()),
+        };
+        sink(&entry.path, &data)?;
+        streamed += 1;
+    }
+    Ok(streamed)
+}
+
+/// Extract a single file from a PBO
+///
+/// `entry` is matched case-insensitively against the listing, with either separator;
+/// a bare file name is also looked up in every directory and fails when several
+/// entries have it. With a destination, the file is written to exactly that path,
+/// otherwise its contents are returned.
+pub fn extract_file(pbo_path: &Path, entry: &str, dest: Option<&Path>, timeout: u32) -> Result<ExtractedFile> {
+    let contents = list_pbo_contents(pbo_path, timeout)?;
+    let entry = find_entry(&contents.entries, entry)
+        .map_err(|e| anyhow::anyhow!("{}: {}", pbo_path.display(), e))?;
+
+    let scan_result = PboScanResult {
+        path: pbo_path.to_owned(),
+        expected_files: vec![PboEntry { path: entry.clone(), size: contents.entry_sizes.get(&entry).copied() }],
+        ..Default::default()
+    };
+    let temp_dir = extract_to_temp_dir(&scan_result, timeout)?;
+    let extracted = temp_dir.path().join(crate::utils::native_entry_path(&entry)?);
+    if !extracted.is_file() {
+        return Err(anyhow::anyhow!("{} was not extracted from {}", entry, pbo_path.display()));
+    }
+
+    match dest {
+        Some(dest) => {
+            if let Some(parent) = dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+            // The temporary directory may be on another file system
+            let size = std::fs::copy(&extracted, dest)?;
+            Ok(ExtractedFile { entry, size, data: None, path: Some(PathBuf::from(dest)) })
+        },
+        None => {
+            let data = std::fs::read(&extracted)?;
+            Ok(ExtractedFile { entry, size: data.len() as u64, data: Some(data), path: None })
+        },
+    }
+}
+
+/// Extract the expected files of a scanned PBO into a new temporary directory
+fn extract_to_temp_dir(scan_result: &PboScanResult, timeout: u32) -> Result<tempfile::TempDir> {
+    let temp_dir = tempfile::Builder::new().prefix("pbo-extract-").tempdir()?;
+    let options = pending_extract_options(scan_result, &scan_result.expected_files);
+    let output_dir = crate::utils::tool_path(temp_dir.path());
+    if let Err(e) = with_tool(timeout, |api| api.extract_with_options(&scan_result.path, &output_dir, options)) {
+        if !is_nothing_to_extract(&e.to_string()) {
+            return Err(tool_error(e, timeout));
+        }
+    }
+
+    Ok(temp_dir)
 }
 
 /// Extract a single PBO archive with custom options
@@ -100,7 +1088,8 @@ pub fn extract_pbo(pbo_path: &Path, output_dir: &Path) -> Result<()> {
 /// # Arguments
 /// * `pbo_path` - Path to the PBO file
 /// * `output_dir` - Directory where contents will be extracted
-/// * `options` - Custom extraction options
+/// * `options` - Custom extraction options; `no_pause` is always set, since a
+///   tool waiting for a key press would only stop at its timeout
 ///
 /// # Returns
 /// * `Result<()>` - Success or error during extraction
@@ -109,23 +1098,19 @@ pub fn extract_pbo_with_options(
     output_dir: &Path,
     options: ExtractOptions,
 ) -> Result<()> {
-    let api = PboApi::builder()
-        .with_timeout(30)
-        .build();
-    
-    match api.extract_with_options(pbo_path, output_dir, options) {
+    check_backend()?;
+    let options = ExtractOptions { no_pause: true, ..options };
+    match with_tool(30, |api| api.extract_with_options(pbo_path, output_dir, options)) {
         Ok(_) => Ok(()),
         Err(e) => {
             // Check if this is error code 11 (no files to extract)
-            let error_str = e.to_string();
-            if error_str.contains("return code 11") || 
-               error_str.contains("no file(s) to extract") {
+            if is_nothing_to_extract(&e.to_string()) {
                 debug!("No files to extract (error code 11), treating as success: {}", pbo_path.display());
                 return Ok(());
             }
             
             // Propagate other errors
-            Err(anyhow::anyhow!("{}", e))
+            Err(tool_error(e, 30))
         }
     }
 }
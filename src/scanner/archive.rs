@@ -0,0 +1,307 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{Context, Result};
+
+use super::manifest::MANIFEST_FILE_NAME;
+use super::types::{PboEntry, VerificationResult};
+use super::utils::verify_files;
+use crate::utils::{normalize_entry_path, CHECKSUM_FILE_NAME};
+
+/// Magic number at the start of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression applied to tar outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TarCompression {
+    None,
+    /// zstd at the given level, 1 (fastest) to 22
+    Zstd(i32),
+}
+
+/// Tar archive written per PBO instead of an output directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TarTarget {
+    pub compression: TarCompression,
+    /// Store every member with a modification time of zero, so extracting the same
+    /// PBO twice produces identical archives
+    pub zero_mtimes: bool,
+}
+
+impl TarTarget {
+    /// Archive replacing the output directory `base_dir`, like `addons/ace_medical.tar.zst`
+    pub fn archive_path(&self, base_dir: &Path) -> PathBuf {
+        let extension = match self.compression {
+            TarCompression::None => "tar",
+            TarCompression::Zstd(_) => "tar.zst",
+        };
+        let mut name = base_dir.file_name().unwrap_or_default().to_owned();
+        name.push(".");
+        name.push(extension);
+        base_dir.with_file_name(name)
+    }
+}
+
+/// Sizes of a written tar archive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    /// Total size of the archived files
+    pub uncompressed: u64,
+    /// Size of the archive on disk
+    pub compressed: u64,
+}
+
+/// Write the files below `dir` into the archive `dest`
+///
+/// Members are sorted by path and stored with fixed owners and permissions, so the
+/// archive only depends on the file contents (and modification times, unless
+/// zeroed). The archive is written next to `dest` and renamed into place.
+pub fn write_tar(dir: &Path, dest: &Path, target: &TarTarget) -> Result<ArchiveStats> {
+    write_tar_checked(dir, dest, target, |_| Ok(()))
+}
+
+/// `write_tar`, running `check` on the written archive before it replaces `dest`
+///
+/// A failing check removes the new archive, leaving an earlier one at `dest` as it was.
+pub fn write_tar_checked(
+    dir: &Path,
+    dest: &Path,
+    target: &TarTarget,
+    check: impl FnOnce(&Path) -> Result<()>,
+) -> Result<ArchiveStats> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let rel_path = normalize_entry_path(&entry.path().strip_prefix(dir)?.to_string_lossy());
+            files.push((rel_path, entry.into_path()));
+        }
+    }
+    files.sort();
+
+    let mut partial_name = dest.file_name().unwrap_or_default().to_owned();
+    partial_name.push(".partial");
+    let partial = dest.with_file_name(partial_name);
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = File::create(&partial).with_context(|| format!("Failed to create {}", partial.display()))?;
+
+    let result = match target.compression {
+        TarCompression::None => {
+            let mut builder = tar::Builder::new(BufWriter::new(file));
+            append_files(&mut builder, &files, target.zero_mtimes).and_then(|uncompressed| {
+                builder.into_inner()?.flush()?;
+                Ok(uncompressed)
+            })
+        },
+        TarCompression::Zstd(level) => {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(file, level)?);
+            append_files(&mut builder, &files, target.zero_mtimes).and_then(|uncompressed| {
+                builder.into_inner()?.finish()?;
+                Ok(uncompressed)
+            })
+        },
+    };
+    let uncompressed = match result {
+        Ok(uncompressed) => uncompressed,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e.context(format!("Failed to write {}", dest.display())));
+        },
+    };
+    if let Err(e) = check(&partial) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    let compressed = std::fs::metadata(&partial)?.len();
+    std::fs::rename(&partial, dest)
+        .with_context(|| format!("Failed to move {} to {}", partial.display(), dest.display()))?;
+    Ok(ArchiveStats { uncompressed, compressed })
+}
+
+fn append_files<W: Write>(builder: &mut tar::Builder<W>, files: &[(String, PathBuf)], zero_mtimes: bool) -> Result<u64> {
+    let mut total = 0;
+    for (rel_path, path) in files {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = if zero_mtimes {
+            0
+        } else {
+            metadata.modified()?
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        };
+
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(metadata.len());
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, rel_path, File::open(path)?)
+            .with_context(|| format!("Failed to archive {}", path.display()))?;
+        total += metadata.len();
+    }
+    Ok(total)
+}
+
+/// Paths of the files in a tar archive, compressed with zstd or not
+///
+/// Only the member headers are read; nothing is unpacked.
+pub fn list_tar(path: &Path) -> Result<Vec<String>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let reader: Box<dyn Read> = if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    };
+
+    let mut members = Vec::new();
+    for entry in tar::Archive::new(reader).entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            members.push(normalize_entry_path(&entry.path()?.to_string_lossy()));
+        }
+    }
+    Ok(members)
+}
+
+/// Compare the members of a tar archive below `root` against the expected entries
+///
+/// `root` is the directory inside the archive the entries are relative to, like the
+/// PBO prefix; empty for the archive root. Sidecar files at the archive root are
+/// ignored, as they are for output directories.
+pub fn verify_tar(path: &Path, root: &str, expected: &[PboEntry]) -> Result<VerificationResult> {
+    let root = normalize_entry_path(root);
+    let files = list_tar(path)?
+        .into_iter()
+        .filter(|member| member != MANIFEST_FILE_NAME && member != CHECKSUM_FILE_NAME)
+        .filter_map(|member| {
+            if root.is_empty() {
+                return Some(member);
+            }
+            member.strip_prefix(&root)?.strip_prefix('/').map(str::to_string)
+        })
+        .collect();
+    Ok(verify_files(files, expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn output(root: &Path) {
+        std::fs::create_dir_all(root.join("z/ace/medical/functions")).unwrap();
+        std::fs::write(root.join("z/ace/medical/config.cpp"), "class CfgPatches {};").unwrap();
+        std::fs::write(root.join("z/ace/medical/functions/fnc_heal.sqf"), "params [\"_unit\"];").unwrap();
+        std::fs::write(root.join(MANIFEST_FILE_NAME), "{}").unwrap();
+    }
+
+    fn entries(paths: &[&str]) -> Vec<PboEntry> {
+        paths.iter()
+            .map(|path| PboEntry { path: path.to_string(), size: None })
+            .collect()
+    }
+
+    #[test]
+    fn test_archive_path() {
+        let target = TarTarget { compression: TarCompression::Zstd(3), zero_mtimes: false };
+        assert_eq!(target.archive_path(Path::new("cache/addons/ace_medical")), Path::new("cache/addons/ace_medical.tar.zst"));
+        let target = TarTarget { compression: TarCompression::None, ..target };
+        assert_eq!(target.archive_path(Path::new("cache/addons/ace_medical")), Path::new("cache/addons/ace_medical.tar"));
+    }
+
+    #[test]
+    fn test_write_and_list_tar() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_dir = temp_dir.path().join("ace_medical");
+        output(&output_dir);
+
+        for compression in [TarCompression::None, TarCompression::Zstd(3)] {
+            let target = TarTarget { compression, zero_mtimes: true };
+            let archive = target.archive_path(&output_dir);
+            let stats = write_tar(&output_dir, &archive, &target).unwrap();
+
+            assert_eq!(stats.uncompressed, 20 + 17 + 2);
+            assert_eq!(stats.compressed, std::fs::metadata(&archive).unwrap().len());
+            assert!(!Path::new(&format!("{}.partial", archive.display())).exists());
+            assert_eq!(list_tar(&archive).unwrap(), vec![
+                MANIFEST_FILE_NAME.to_string(),
+                "z/ace/medical/config.cpp".to_string(),
+                "z/ace/medical/functions/fnc_heal.sqf".to_string(),
+            ]);
+        }
+
+        let zstd = std::fs::read(temp_dir.path().join("ace_medical.tar.zst")).unwrap();
+        assert!(zstd.starts_with(&ZSTD_MAGIC));
+    }
+
+    #[test]
+    fn test_write_tar_is_deterministic() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = TarTarget { compression: TarCompression::Zstd(3), zero_mtimes: true };
+        let first = temp_dir.path().join("first");
+        output(&first);
+        write_tar(&first, &temp_dir.path().join("first.tar.zst"), &target).unwrap();
+
+        let second = temp_dir.path().join("second");
+        output(&second);
+        let mtime = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options().write(true).open(second.join("z/ace/medical/config.cpp")).unwrap().set_modified(mtime).unwrap();
+        write_tar(&second, &temp_dir.path().join("second.tar.zst"), &target).unwrap();
+
+        assert_eq!(
+            std::fs::read(temp_dir.path().join("first.tar.zst")).unwrap(),
+            std::fs::read(temp_dir.path().join("second.tar.zst")).unwrap(),
+        );
+    }
+
+    #[test]
+    fn test_verify_tar() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_dir = temp_dir.path().join("ace_medical");
+        output(&output_dir);
+        let target = TarTarget { compression: TarCompression::Zstd(1), zero_mtimes: false };
+        let archive = target.archive_path(&output_dir);
+        write_tar(&output_dir, &archive, &target).unwrap();
+
+        let result = verify_tar(
+            &archive,
+            "z/ace/medical",
+            &entries(&["config.cpp", "functions/fnc_heal.sqf", "functions/fnc_treat.sqf"]),
+        ).unwrap();
+        assert_eq!(result.found, vec!["config.cpp", "functions/fnc_heal.sqf"]);
+        assert_eq!(result.missing, vec!["functions/fnc_treat.sqf"]);
+        assert!(result.unexpected.is_empty());
+
+        let result = verify_tar(&archive, "", &entries(&["z/ace/medical/config.cpp"])).unwrap();
+        assert!(result.is_complete());
+        assert_eq!(result.unexpected, vec!["z/ace/medical/functions/fnc_heal.sqf"]);
+    }
+
+    #[test]
+    fn test_failed_check_keeps_previous_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_dir = temp_dir.path().join("ace_medical");
+        output(&output_dir);
+        let target = TarTarget { compression: TarCompression::Zstd(1), zero_mtimes: true };
+        let archive = target.archive_path(&output_dir);
+        std::fs::write(&archive, "previous").unwrap();
+
+        let error = write_tar_checked(&output_dir, &archive, &target, |partial| {
+            assert_eq!(list_tar(partial)?.len(), 3);
+            anyhow::bail!("files missing")
+        }).unwrap_err();
+        assert_eq!(error.to_string(), "files missing");
+        assert_eq!(std::fs::read(&archive).unwrap(), b"previous");
+        assert!(!Path::new(&format!("{}.partial", archive.display())).exists());
+
+        write_tar_checked(&output_dir, &archive, &target, |_| Ok(())).unwrap();
+        assert_eq!(list_tar(&archive).unwrap().len(), 3);
+    }
+}
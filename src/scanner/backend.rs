@@ -0,0 +1,473 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use anyhow::Context;
+use log::{debug, warn};
+use pbo_tools::core::api::{ListResult, PboApiOps, PboError};
+use pbo_tools::extract::{ExtractOptions, ExtractResult};
+use serde::{Deserialize, Serialize};
+
+use super::utils::{check_backend, tool_slot};
+use crate::error::ExtractionError;
+
+/// File name of the tool the PBO tools run
+#[cfg(windows)]
+pub const EXTRACTPBO_FILE_NAME: &str = "ExtractPbo.exe";
+#[cfg(not(windows))]
+pub const EXTRACTPBO_FILE_NAME: &str = "extractpbo";
+
+/// Time the tool gets to print its usage in `health_check`
+const USAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Characters of the tool's output kept in `ExtractionError::BackendVersionUnknown`
+const MAX_REPORTED_OUTPUT: usize = 200;
+
+/// Set by `set_tool_dir`
+static TOOL_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// The PBO tools found by `health_check`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendInfo {
+    /// The tool, in the directory set by `set_tool_dir` or else the first directory
+    /// of PATH holding it
+    pub tool_path: PathBuf,
+    /// Version named in the tool's usage text, like `2.31`
+    pub version: Option<String>,
+    pub features: BackendFeatures,
+}
+
+impl fmt::Display for BackendInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tool: {}", self.tool_path.display())?;
+        writeln!(f, "Version: {}", self.version.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "Encrypted PBOs: {}", match self.features.encrypted_pbos {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        })?;
+        write!(f, "File filters: {}", if self.features.file_filter { "yes" } else { "no" })
+    }
+}
+
+/// What the tool supports, as far as its usage text tells
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendFeatures {
+    /// Opens encrypted PBOs (`.ebo`), which only some builds of the tool do; `None`
+    /// unless one of the options listed names them, as the syntax line names `.ebo`
+    /// in every build
+    pub encrypted_pbos: Option<bool>,
+    /// Extracts only the entries given with `-F`, as extracting with a file filter does
+    pub file_filter: bool,
+}
+
+impl BackendFeatures {
+    fn from_usage(usage: &str) -> Self {
+        Self {
+            encrypted_pbos: usage
+                .lines()
+                .any(|line| line.trim_start().starts_with('-') && line.to_ascii_lowercase().contains("ebo"))
+                .then_some(true),
+            file_filter: usage.lines().any(|line| line.trim_start().starts_with("-F")),
+        }
+    }
+}
+
+/// Find the PBO tools and tell which version they are, like before a first run
+///
+/// Looks for the tool in the directory set by `set_tool_dir`, then in PATH as the PBO
+/// tools do, checks that they can run it, and reads its version and features from
+/// the usage text it prints. Unlike `check_backend`, the tool is looked for again on
+/// every call.
+pub fn health_check() -> Result<BackendInfo, ExtractionError> {
+    probe().map(|(info, _)| info)
+}
+
+/// Version of the installed PBO tools, like `2.31`; see `health_check`
+pub fn backend_version() -> Result<String, ExtractionError> {
+    let (info, usage) = probe()?;
+    info.version.ok_or_else(|| ExtractionError::BackendVersionUnknown {
+        path: info.tool_path,
+        output: usage.trim().chars().take(MAX_REPORTED_OUTPUT).collect(),
+    })
+}
+
+/// Look for the tool in `dir` before PATH in `health_check` and `backend_version`
+///
+/// For tools kept off PATH, like next to the application. Listing and extracting
+/// still start whichever tool PATH holds, as the PBO tools do; `health_check` warns
+/// when that is not the tool in `dir`.
+pub fn set_tool_dir(dir: Option<PathBuf>) {
+    *TOOL_DIR.write().unwrap_or_else(|e| e.into_inner()) = dir;
+}
+
+/// `BackendInfo` of the tool, with the usage text it was read from
+fn probe() -> Result<(BackendInfo, String), ExtractionError> {
+    let tool_dir = TOOL_DIR.read().unwrap_or_else(|e| e.into_inner()).clone();
+    let path = std::env::var_os("PATH");
+    let tool_path = locate_tool(tool_dir.as_deref(), path.as_deref(), &install_dirs())?;
+    if tool_dir.is_some() {
+        match locate_tool(None, path.as_deref(), &[]) {
+            Ok(run) if run == tool_path => {},
+            Ok(run) => warn!("Runs start {} from PATH, not {}", run.display(), tool_path.display()),
+            Err(_) => warn!("Runs start the tool from PATH, which doesn't hold {}", tool_path.display()),
+        }
+    }
+    check_backend()?;
+    let usage = usage_text(&tool_path)?;
+    let info = BackendInfo {
+        version: parse_version(&usage),
+        features: BackendFeatures::from_usage(&usage),
+        tool_path,
+    };
+    debug!("Found PBO tools {} at {}", info.version.as_deref().unwrap_or("of unknown version"), info.tool_path.display());
+    Ok((info, usage))
+}
+
+/// The tool in `tool_dir`, or else in the first directory of `path`, a PATH value,
+/// holding it
+///
+/// `install_dirs` are only searched to tell where the tool is when it is off PATH.
+fn locate_tool(tool_dir: Option<&Path>, path: Option<&OsStr>, install_dirs: &[PathBuf]) -> Result<PathBuf, ExtractionError> {
+    let searched: Vec<PathBuf> = tool_dir
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(path.into_iter().flat_map(std::env::split_paths).filter(|dir| !dir.as_os_str().is_empty()))
+        .collect();
+    if let Some(tool) = searched.iter().map(|dir| dir.join(EXTRACTPBO_FILE_NAME)).find(|tool| tool.is_file()) {
+        return Ok(tool);
+    }
+    Err(ExtractionError::BackendNotFound {
+        tool: EXTRACTPBO_FILE_NAME.to_string(),
+        installed_in: install_dirs.iter().find(|dir| dir.join(EXTRACTPBO_FILE_NAME).is_file()).cloned(),
+        searched,
+    })
+}
+
+/// Directories the installer of Mikero's tools puts them in
+#[cfg(windows)]
+fn install_dirs() -> Vec<PathBuf> {
+    ["ProgramFiles(x86)", "ProgramFiles"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|dir| PathBuf::from(dir).join("Mikero").join("DePboTools").join("bin"))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn install_dirs() -> Vec<PathBuf> {
+    Vec::new()
+}
+
+/// What the tool prints when run without a PBO, its usage text
+///
+/// Runs in a slot under the tool limit like every other run of the tool, without a
+/// console window and without pausing for a key press.
+fn usage_text(tool: &Path) -> Result<String, ExtractionError> {
+    let _slot = tool_slot();
+    match run_hidden(tool, [OsString::from("-P")], USAGE_TIMEOUT)? {
+        Some(output) => Ok(output.stdout + &output.stderr),
+        None => Err(anyhow::anyhow!(
+            "{} did not exit within {} seconds of being run without a PBO",
+            tool.display(),
+            USAGE_TIMEOUT.as_secs()
+        ).into()),
+    }
+}
+
+/// Check that the tool can be started from PATH, as listings and extractions start it
+///
+/// Fails with `ExtractionError::BackendUnavailable` when the tool isn't on PATH, or
+/// starting it fails because it is missing or can't be executed. How the tool fares
+/// once started doesn't matter.
+pub(crate) fn check_tool_starts() -> Result<(), ExtractionError> {
+    let tool = locate_tool(None, std::env::var_os("PATH").as_deref(), &[])
+        .map_err(|_| ExtractionError::BackendUnavailable(format!("{} not found in PATH", EXTRACTPBO_FILE_NAME)))?;
+    match usage_text(&tool) {
+        Err(e @ ExtractionError::BackendUnavailable(_)) => Err(e),
+        Err(e) => {
+            debug!("Started {}, which then failed: {}", tool.display(), e);
+            Ok(())
+        },
+        Ok(_) => Ok(()),
+    }
+}
+
+/// What a run of the tool printed and how it exited
+struct ToolOutput {
+    /// Exit code, -1 when the tool was ended by a signal
+    code: i32,
+    stdout: String,
+    stderr: String,
+}
+
+/// Run `tool` with `args` without a console window, waiting up to `timeout`
+///
+/// `None` when the tool didn't exit in time, after it was killed. The tool's output
+/// goes to files rather than pipes, so a chatty tool can't block on a full pipe
+/// while it's waited for.
+fn run_hidden(
+    tool: &Path,
+    args: impl IntoIterator<Item = OsString>,
+    timeout: Duration,
+) -> Result<Option<ToolOutput>, ExtractionError> {
+    let output_file = || tempfile::tempfile().context("Failed to create a file for the tool's output");
+    let (mut stdout, mut stderr) = (output_file()?, output_file()?);
+    let mut command = Command::new(tool);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(stdout.try_clone().context("Failed to share the tool's output file")?)
+        .stderr(stderr.try_clone().context("Failed to share the tool's output file")?);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NO_WINDOW);
+    }
+
+    let mut child = command.spawn().map_err(|e| match e.kind() {
+        // The tool is missing or can't be executed, not merely failing
+        ErrorKind::NotFound | ErrorKind::PermissionDenied => {
+            ExtractionError::BackendUnavailable(format!("Failed to run {}: {}", tool.display(), e))
+        },
+        _ => anyhow::Error::new(e).context(format!("Failed to run {}", tool.display())).into(),
+    })?;
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().with_context(|| format!("Failed to wait for {}", tool.display()))? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+
+    let read = |file: &mut File| -> Result<String, ExtractionError> {
+        let mut output = Vec::new();
+        file.rewind().and_then(|_| file.read_to_end(&mut output)).context("Failed to read the tool's output")?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    };
+    Ok(Some(ToolOutput { code: status.code().unwrap_or(-1), stdout: read(&mut stdout)?, stderr: read(&mut stderr)? }))
+}
+
+/// The tool run for listings and extractions without a console window
+///
+/// Stands in for the backend API when tool consoles are hidden, see
+/// `set_hide_console`: the PBO tools start the tool like any console program, which
+/// opens a console window for each run in a process without a console. This starts
+/// it like `usage_text` does instead, from PATH as the PBO tools do, with the same
+/// options and the same errors for failed runs.
+pub(crate) struct HiddenTool {
+    /// The tool, or why it wasn't found
+    tool: Result<PathBuf, String>,
+    /// Timeout in seconds of each run
+    timeout: u32,
+}
+
+impl HiddenTool {
+    pub(crate) fn new(timeout: u32) -> Self {
+        let tool = locate_tool(None, std::env::var_os("PATH").as_deref(), &[]).map_err(|e| e.to_string());
+        Self { tool, timeout }
+    }
+
+    /// Run `tool` rather than the one on PATH
+    #[cfg(test)]
+    pub(crate) fn at(tool: PathBuf, timeout: u32) -> Self {
+        Self { tool: Ok(tool), timeout }
+    }
+
+    /// Run the tool with `args`, failing unless it exits with 0
+    fn run(&self, args: Vec<OsString>) -> Result<ToolOutput, PboError> {
+        let tool = self.tool.as_ref().map_err(|e| PboError(e.clone()))?;
+        let output = run_hidden(tool, args, Duration::from_secs(u64::from(self.timeout)))
+            .map_err(|e| PboError(e.to_string()))?
+            .ok_or_else(|| PboError(format!("Operation timed out after {} seconds", self.timeout)))?;
+        if output.code != 0 {
+            return Err(PboError(format!(
+                "{} failed with return code {}: {}",
+                tool.display(),
+                output.code,
+                output.stderr.trim()
+            )));
+        }
+        Ok(output)
+    }
+}
+
+/// Command line options of the tool for `options`, before the PBO
+fn option_args(options: &ExtractOptions) -> Vec<OsString> {
+    let mut args = Vec::new();
+    if options.no_pause {
+        args.push("-P".into());
+    }
+    if options.warnings_as_errors {
+        args.push("-W".into());
+    }
+    if options.verbose {
+        args.push("-N".into());
+    }
+    if let Some(filter) = &options.file_filter {
+        args.push(format!("-F={}", filter).into());
+    }
+    args
+}
+
+impl PboApiOps for HiddenTool {
+    fn list_contents(&self, pbo: &Path) -> Result<ListResult, PboError> {
+        self.list_with_options(pbo, ExtractOptions { no_pause: true, brief_listing: true, ..Default::default() })
+    }
+
+    fn list_with_options(&self, pbo: &Path, options: ExtractOptions) -> Result<ListResult, PboError> {
+        let mut args = vec![OsString::from(if options.brief_listing { "-LB" } else { "-L" })];
+        args.extend(option_args(&options));
+        args.push(pbo.into());
+        let output = self.run(args)?;
+        Ok(ListResult { return_code: output.code, stdout: output.stdout, stderr: output.stderr })
+    }
+
+    fn extract_files(&self, pbo: &Path, output_dir: &Path, filter: Option<&str>) -> Result<ExtractResult, PboError> {
+        let options = ExtractOptions { no_pause: true, file_filter: filter.map(str::to_string), ..Default::default() };
+        self.extract_with_options(pbo, output_dir, options)
+    }
+
+    fn extract_with_options(&self, pbo: &Path, output_dir: &Path, options: ExtractOptions) -> Result<ExtractResult, PboError> {
+        let mut args = option_args(&options);
+        args.extend([pbo.into(), output_dir.into()]);
+        let output = self.run(args)?;
+        Ok(ExtractResult { return_code: output.code, stdout: output.stdout, stderr: output.stderr })
+    }
+}
+
+/// Version in a usage text: the number after "version", or the first number of its
+/// first line
+fn parse_version(usage: &str) -> Option<String> {
+    let number = |text: &str| {
+        text.split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+            .find(|word| word.starts_with(|c: char| c.is_ascii_digit()) && word.contains('.'))
+            .map(|word| word.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()).to_string())
+    };
+    let after_version = usage.lines().find_map(|line| {
+        let start = line.to_ascii_lowercase().find("version")? + "version".len();
+        number(&line[start..])
+    });
+    after_version.or_else(|| number(usage.lines().find(|line| !line.trim().is_empty())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const USAGE: &str = "\
+extractpbo Version 2.31, Dll 9.62 \"no pbo given\"
+
+Syntax: extractpbo [-options...] PboName[.pbo|.ebo]|FolderName [destination]
+-F=FileList[,...] only extract these file(s)
+-P do not pause
+";
+
+    #[test]
+    fn test_usage_parsing() {
+        assert_eq!(parse_version(USAGE).as_deref(), Some("2.31"));
+        assert_eq!(parse_version("\nExtractPbo 1.98.\n").as_deref(), Some("1.98"));
+        assert_eq!(parse_version("Usage: extractpbo [options]"), None);
+        assert_eq!(BackendFeatures::from_usage(USAGE), BackendFeatures { encrypted_pbos: None, file_filter: true });
+        assert_eq!(
+            BackendFeatures::from_usage(&format!("{}-E extract encrypted .ebo files\n", USAGE)).encrypted_pbos,
+            Some(true)
+        );
+        assert_eq!(BackendFeatures::from_usage("Syntax: extractpbo PboName[.pbo]"), BackendFeatures::default());
+    }
+
+    #[test]
+    fn test_tool_located_in_path() {
+        let (empty, bin, installed) = (TempDir::new().unwrap(), TempDir::new().unwrap(), TempDir::new().unwrap());
+        let configured = TempDir::new().unwrap();
+        std::fs::write(installed.path().join(EXTRACTPBO_FILE_NAME), "").unwrap();
+        let install_dirs = [installed.path().to_path_buf()];
+
+        let path = std::env::join_paths([empty.path(), bin.path()]).unwrap();
+        match locate_tool(Some(configured.path()), Some(&path), &install_dirs).unwrap_err() {
+            ExtractionError::BackendNotFound { searched, installed_in, .. } => {
+                assert_eq!(
+                    searched,
+                    vec![configured.path().to_path_buf(), empty.path().to_path_buf(), bin.path().to_path_buf()]
+                );
+                assert_eq!(installed_in.as_deref(), Some(installed.path()));
+            },
+            error => panic!("unexpected error: {}", error),
+        }
+
+        std::fs::write(bin.path().join(EXTRACTPBO_FILE_NAME), "").unwrap();
+        assert_eq!(locate_tool(None, Some(&path), &install_dirs).unwrap(), bin.path().join(EXTRACTPBO_FILE_NAME));
+        std::fs::write(configured.path().join(EXTRACTPBO_FILE_NAME), "").unwrap();
+        assert_eq!(
+            locate_tool(Some(configured.path()), Some(&path), &install_dirs).unwrap(),
+            configured.path().join(EXTRACTPBO_FILE_NAME)
+        );
+        assert!(matches!(
+            locate_tool(None, None, &[]),
+            Err(ExtractionError::BackendNotFound { installed_in: None, .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_usage_text_of_tool() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = TempDir::new().unwrap();
+        let tool = bin.path().join(EXTRACTPBO_FILE_NAME);
+        std::fs::write(&tool, "#!/bin/sh\necho \"extractpbo Version 2.31 $1\"\necho 'Syntax: extractpbo' >&2\nexit 1\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let usage = usage_text(&tool).unwrap();
+        assert_eq!(usage, "extractpbo Version 2.31 -P\nSyntax: extractpbo\n");
+        assert!(matches!(
+            usage_text(&bin.path().join("missing")),
+            Err(ExtractionError::BackendUnavailable(message)) if message.starts_with("Failed to run")
+        ));
+        // A tool that can't be executed can't be started either
+        let unexecutable = bin.path().join("unexecutable");
+        std::fs::write(&unexecutable, "").unwrap();
+        assert!(matches!(usage_text(&unexecutable), Err(ExtractionError::BackendUnavailable(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hidden_tool_runs() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = TempDir::new().unwrap();
+        let tool = bin.path().join(EXTRACTPBO_FILE_NAME);
+        std::fs::write(&tool, "\
+#!/bin/sh
+echo \"$@\"
+case \"$*\" in
+  *missing*) echo 'no file(s) to extract' >&2; exit 11;;
+  *slow*) sleep 5;;
+esac
+").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let hidden = HiddenTool { tool: Ok(tool.clone()), timeout: 10 };
+
+        let listing = hidden.list_with_options(Path::new("a.pbo"), ExtractOptions { no_pause: true, ..Default::default() }).unwrap();
+        assert_eq!(listing.stdout, "-L -P a.pbo\n");
+        assert_eq!(hidden.list_contents(Path::new("a.pbo")).unwrap().stdout, "-LB -P a.pbo\n");
+        let options = ExtractOptions { no_pause: true, file_filter: Some("a.sqf,b.sqf".to_string()), ..Default::default() };
+        let extracted = hidden.extract_with_options(Path::new("a.pbo"), Path::new("out"), options).unwrap();
+        assert_eq!(extracted.stdout, "-P -F=a.sqf,b.sqf a.pbo out\n");
+
+        // Failed runs fail like they do through the PBO tools
+        let error = hidden.extract_files(Path::new("a.pbo"), Path::new("out"), Some("missing.sqf")).unwrap_err();
+        assert!(error.to_string().contains("return code 11: no file(s) to extract"), "{}", error);
+        let hasty = HiddenTool { tool: Ok(tool), timeout: 0 };
+        let error = hasty.list_contents(Path::new("slow.pbo")).unwrap_err();
+        assert!(error.to_string().contains("timed out after 0 seconds"), "{}", error);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::{File, TryLockError};
+use std::path::Path;
+use std::time::Duration;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use super::cancel::CancelToken;
+use crate::error::ExtractionError;
+
+/// File in the cache directory that runs lock, so only one writes to the cache at a time
+pub const CACHE_LOCK_FILE_NAME: &str = ".extraction.lock";
+
+/// Time between attempts to take a lock held by another process
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What to do when another process holds the lock of a cache directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheLockPolicy {
+    /// Fail right away with `ExtractionError::CacheLocked`
+    #[default]
+    Fail,
+    /// Wait until the lock is released, or the run is cancelled
+    Wait,
+}
+
+/// Advisory lock on a cache directory, released when dropped
+///
+/// Runs writing to the cache hold it exclusively from start to end, so their index
+/// updates can't clobber each other; read-only operations share it. The lock is
+/// taken on a file of its own, since the index is replaced rather than written in
+/// place. Being advisory, it only keeps out processes that take it too.
+#[derive(Debug)]
+pub struct CacheLock {
+    /// `None` for shared locks on caches the lock file can't be created in
+    _file: Option<File>,
+}
+
+impl CacheLock {
+    /// Lock `cache_dir` for a run writing to it
+    pub fn exclusive(cache_dir: &Path, policy: CacheLockPolicy, cancel: &CancelToken) -> Result<Self, ExtractionError> {
+        Self::acquire(cache_dir, true, policy, cancel)
+    }
+
+    /// Lock `cache_dir` for reading, alongside other readers
+    pub fn shared(cache_dir: &Path, policy: CacheLockPolicy, cancel: &CancelToken) -> Result<Self, ExtractionError> {
+        Self::acquire(cache_dir, false, policy, cancel)
+    }
+
+    fn acquire(cache_dir: &Path, exclusive: bool, policy: CacheLockPolicy, cancel: &CancelToken) -> Result<Self, ExtractionError> {
+        let path = cache_dir.join(CACHE_LOCK_FILE_NAME);
+        let file = match File::options().read(true).write(true).create(true).truncate(false).open(&path) {
+            Ok(file) => file,
+            // Read-only caches have no writers to wait for
+            Err(e) if !exclusive => {
+                debug!("Reading {} without a lock: {}", cache_dir.display(), e);
+                return Ok(Self { _file: None });
+            },
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to open lock file: {}", path.display())).into()),
+        };
+
+        let mut waiting = false;
+        loop {
+            let attempt = if exclusive { file.try_lock() } else { file.try_lock_shared() };
+            match attempt {
+                Ok(()) => return Ok(Self { _file: Some(file) }),
+                Err(TryLockError::WouldBlock) => {},
+                Err(TryLockError::Error(e)) => {
+                    return Err(anyhow::Error::new(e).context(format!("Failed to lock {}", path.display())).into());
+                },
+            }
+            if policy == CacheLockPolicy::Fail {
+                return Err(ExtractionError::CacheLocked(cache_dir.to_owned()));
+            }
+            if cancel.is_cancelled() {
+                return Err(ExtractionError::Cancelled(Box::default()));
+            }
+            if !waiting {
+                info!("Waiting for another extraction running on {} to finish", cache_dir.display());
+                waiting = true;
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+    use tempfile::TempDir;
+
+    /// Hold an exclusive lock on `cache_dir` in another thread for `held`
+    fn hold(cache_dir: &Path, held: Duration) -> std::thread::JoinHandle<()> {
+        let cache_dir = cache_dir.to_owned();
+        let (locked, is_locked) = mpsc::channel();
+        let holder = std::thread::spawn(move || {
+            let _lock = CacheLock::exclusive(&cache_dir, CacheLockPolicy::Fail, &CancelToken::new()).unwrap();
+            locked.send(()).unwrap();
+            std::thread::sleep(held);
+        });
+        is_locked.recv().unwrap();
+        holder
+    }
+
+    #[test]
+    fn test_second_run_fails_or_waits() {
+        let cache_dir = TempDir::new().unwrap();
+        let cancel = CancelToken::new();
+
+        let holder = hold(cache_dir.path(), Duration::from_millis(300));
+        let error = CacheLock::exclusive(cache_dir.path(), CacheLockPolicy::Fail, &cancel).unwrap_err();
+        assert!(matches!(error, ExtractionError::CacheLocked(_)), "{:?}", error);
+        assert!(error.to_string().contains("Another extraction is already running on this cache"));
+        assert!(CacheLock::shared(cache_dir.path(), CacheLockPolicy::Fail, &cancel).is_err());
+
+        let started = Instant::now();
+        let _lock = CacheLock::exclusive(cache_dir.path(), CacheLockPolicy::Wait, &cancel).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_readers_share_the_lock() {
+        let cache_dir = TempDir::new().unwrap();
+        let cancel = CancelToken::new();
+        let _first = CacheLock::shared(cache_dir.path(), CacheLockPolicy::Fail, &cancel).unwrap();
+        let _second = CacheLock::shared(cache_dir.path(), CacheLockPolicy::Fail, &cancel).unwrap();
+        assert!(CacheLock::exclusive(cache_dir.path(), CacheLockPolicy::Fail, &cancel).is_err());
+
+        cancel.cancel();
+        let error = CacheLock::exclusive(cache_dir.path(), CacheLockPolicy::Wait, &cancel).unwrap_err();
+        assert!(matches!(error, ExtractionError::Cancelled(_)));
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use tokio::sync::oneshot;
+
+/// Shared flag asking a running extraction to stop
+///
+/// Cancelling stops new PBOs from being listed or extracted; the ones in progress
+/// finish, and the run returns a report marked as interrupted. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Ctrl-C handler cancelling a token, removed when dropped
+///
+/// Runs on its own thread, so it works whatever the caller's runtime is busy with.
+/// Later Ctrl-Cs are ignored here; a caller wanting to stop harder, like exiting the
+/// process on a second one, listens for them itself.
+pub struct SignalHandler {
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SignalHandler {
+    /// Cancel `token` on Ctrl-C, through a console control handler on Windows
+    ///
+    /// Once installed, the platform's default handling of Ctrl-C stays replaced for
+    /// the rest of the process, also after the handler is dropped.
+    pub fn install(token: CancelToken) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to create the signal handling runtime")?;
+        let (stop, mut stopped) = oneshot::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("ctrl-c".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    tokio::select! {
+                        result = tokio::signal::ctrl_c() => {
+                            if let Err(e) = result {
+                                warn!("Failed to listen for Ctrl-C: {}", e);
+                                return;
+                            }
+                        },
+                        _ = &mut stopped => return,
+                    }
+                    warn!("Interrupted; finishing the PBOs in progress");
+                    token.cancel();
+
+                    loop {
+                        tokio::select! {
+                            _ = tokio::signal::ctrl_c() => debug!("Already interrupted"),
+                            _ = &mut stopped => return,
+                        }
+                    }
+                });
+            })
+            .context("Failed to start the signal handling thread")?;
+        debug!("Installed Ctrl-C handler");
+
+        Ok(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for SignalHandler {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
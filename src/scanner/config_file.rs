@@ -0,0 +1,340 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use super::archive::{TarCompression, TarTarget};
+use super::cache_lock::CacheLockPolicy;
+use super::failure::FailurePolicy;
+use super::log_file::LogRotation;
+use super::progress::OutputMode;
+use super::quarantine::QuarantinePolicy;
+use super::types::{
+    ExtractionStrategy,
+    ModDetection,
+    NameSanitization,
+    OutputCollisionHandling,
+    OverwritePolicy,
+    ProcessingOrder,
+    ReservedNameHandling,
+    VerificationMode,
+};
+
+/// Commented configuration file listing every key with its default or an example,
+/// as written by `write_default_toml`
+pub const DEFAULT_CONFIG_TOML: &str = include_str!("config_template.toml");
+
+/// Options of `ExtractionConfig` as read from a TOML file
+///
+/// Keys are named after the `ExtractionConfig` fields, and keys left out keep the
+/// defaults of `ExtractionConfig::new`. Durations are given in seconds or
+/// milliseconds, as their `_secs` and `_ms` suffixes say. The event channel, JSON
+/// event writer, file transformer and cancel token can't be configured from a file.
+/// Unknown keys are rejected so misspelled options don't go unnoticed.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Relative to the directory of the configuration file
+    pub input_dir: Option<PathBuf>,
+    /// Relative to the directory of the configuration file
+    pub output_dir: Option<PathBuf>,
+    pub extensions: Option<String>,
+    pub threads: Option<usize>,
+    pub scheduling: Option<ProcessingOrder>,
+    pub io_concurrency: Option<usize>,
+    pub tool_concurrency: Option<usize>,
+    pub background: Option<bool>,
+    pub hide_console: Option<bool>,
+    pub timeout: Option<u32>,
+    pub list_timeout: Option<u32>,
+    pub extract_timeout: Option<u32>,
+    pub timeout_per_100mb: Option<u32>,
+    pub max_timeout: Option<u32>,
+    pub include_paths: Option<Vec<String>>,
+    pub min_file_size: Option<u64>,
+    pub max_file_size: Option<u64>,
+    pub prefer_source: Option<bool>,
+    pub pbo_include: Option<Vec<String>>,
+    pub pbo_exclude: Option<Vec<String>>,
+    pub follow_symlinks: Option<bool>,
+    pub max_depth: Option<usize>,
+    pub ignore_dirs: Option<Vec<String>>,
+    pub archive_extensions: Option<Vec<String>>,
+    pub output_mode: Option<OutputMode>,
+    pub status_interval_secs: Option<u64>,
+    pub status_every_pbos: Option<u64>,
+    pub fallback_chain: Option<Vec<ExtractionStrategy>>,
+    pub write_manifest: Option<bool>,
+    pub write_failure_logs: Option<bool>,
+    pub emit_checksums: Option<bool>,
+    pub verification: Option<VerificationMode>,
+    pub resume: Option<bool>,
+    pub adopt_existing: Option<bool>,
+    pub overwrite: Option<OverwritePolicy>,
+    pub retain_versions: Option<usize>,
+    pub sync_outputs: Option<bool>,
+    pub sync_dry_run: Option<bool>,
+    pub normalize_case: Option<bool>,
+    pub name_sanitization: Option<NameSanitization>,
+    pub reserved_names: Option<ReservedNameHandling>,
+    pub output_collisions: Option<OutputCollisionHandling>,
+    pub detect_conflicts: Option<bool>,
+    pub content_store: Option<bool>,
+    pub dedupe: Option<bool>,
+    pub dedupe_min_size: Option<u64>,
+    pub build_index: Option<bool>,
+    pub write_prefix_map: Option<bool>,
+    pub cache_listings: Option<bool>,
+    pub listing_cache_max_bytes: Option<u64>,
+    pub mod_detection: Option<ModDetection>,
+    pub ignore_disk_space: Option<bool>,
+    pub skip_backend_check: Option<bool>,
+    pub max_output_bytes: Option<u64>,
+    pub min_age_secs: Option<u64>,
+    pub recursive: Option<bool>,
+    pub max_nesting_depth: Option<usize>,
+    pub derapify: Option<bool>,
+    pub convert_stringtables: Option<bool>,
+    pub convert_paa: Option<bool>,
+    pub replace_paa_originals: Option<bool>,
+    pub verify_signatures: Option<bool>,
+    pub require_valid_signature: Option<bool>,
+    /// Relative to the directory of the configuration file
+    pub key_dir: Option<PathBuf>,
+    pub tar_output: Option<TarOutputFile>,
+    pub clean_partial: Option<bool>,
+    pub max_retries: Option<u32>,
+    pub retry_backoff_ms: Option<u64>,
+    pub lock_retries: Option<u32>,
+    pub lock_retry_delay_ms: Option<u64>,
+    pub failure_policy: Option<FailurePolicyFile>,
+    pub quarantine: Option<QuarantinePolicy>,
+    pub max_attempts: Option<u32>,
+    pub force_retry: Option<bool>,
+    /// Relative to the output directory, like in `ExtractionConfig`
+    pub run_report: Option<PathBuf>,
+    /// Relative to the output directory, like in `ExtractionConfig`
+    pub csv_report: Option<PathBuf>,
+    /// Relative to the output directory, like in `ExtractionConfig`
+    pub mods_csv_report: Option<PathBuf>,
+    pub slowest_pbos: Option<usize>,
+    /// Relative to the directory of the configuration file
+    pub log_file: Option<PathBuf>,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub log_file_level: Option<String>,
+    pub log_rotation: Option<LogRotation>,
+    pub handle_signals: Option<bool>,
+    pub cache_lock: Option<CacheLockPolicy>,
+}
+
+/// `[tar_output]` table of a configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TarOutputFile {
+    /// zstd level from 1 (fastest) to 22; uncompressed tar archives when unset
+    pub zstd_level: Option<i32>,
+    pub zero_mtimes: bool,
+}
+
+impl TarOutputFile {
+    pub fn target(&self) -> Result<TarTarget> {
+        let compression = match self.zstd_level {
+            Some(level @ 1..=22) => TarCompression::Zstd(level),
+            Some(level) => anyhow::bail!("tar_output.zstd_level must be from 1 to 22, got {}", level),
+            None => TarCompression::None,
+        };
+        Ok(TarTarget { compression, zero_mtimes: self.zero_mtimes })
+    }
+}
+
+/// `[failure_policy]` table of a configuration file
+///
+/// Empty, it keeps extracting regardless of failures.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FailurePolicyFile {
+    pub fail_fast: bool,
+    pub max_failures: Option<usize>,
+    /// Fraction of the PBOs in the run, from 0 to 1
+    pub max_failure_ratio: Option<f64>,
+}
+
+impl FailurePolicyFile {
+    pub fn policy(&self) -> Result<FailurePolicy> {
+        if let Some(ratio) = self.max_failure_ratio.filter(|ratio| !(0.0..=1.0).contains(ratio)) {
+            anyhow::bail!("failure_policy.max_failure_ratio must be from 0 to 1, got {}", ratio);
+        }
+        Ok(match (self.fail_fast, self.max_failures, self.max_failure_ratio) {
+            (true, _, _) => FailurePolicy::FailFast,
+            (false, None, None) => FailurePolicy::ContinueAll,
+            (false, max_failures, max_failure_ratio) => FailurePolicy::Threshold { max_failures, max_failure_ratio },
+        })
+    }
+}
+
+impl ConfigFile {
+    /// Read the configuration file at `path`
+    ///
+    /// Errors name the file, and the line and key that failed to parse.
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read configuration file: {}", path.display()))?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&toml, base_dir).with_context(|| format!("Invalid configuration file: {}", path.display()))
+    }
+
+    /// Parse a configuration, resolving its relative paths against `base_dir`
+    pub fn parse(toml: &str, base_dir: &Path) -> Result<Self> {
+        let mut file: Self = toml::from_str(toml)?;
+        for path in [&mut file.input_dir, &mut file.output_dir, &mut file.key_dir, &mut file.log_file]
+            .into_iter()
+            .flatten()
+        {
+            if path.is_relative() {
+                *path = base_dir.join(&*path);
+            }
+        }
+        Ok(file)
+    }
+
+    /// `log_file_level` as a level filter, if set
+    pub fn log_file_level(&self) -> Result<Option<log::LevelFilter>> {
+        self.log_file_level.as_deref()
+            .map(|level| {
+                level.parse()
+                    .map_err(|_| anyhow::anyhow!("log_file_level must be off, error, warn, info, debug or trace, got `{}`", level))
+            })
+            .transpose()
+    }
+}
+
+/// Write `DEFAULT_CONFIG_TOML` to `path` as a starting point, refusing to replace an existing file
+pub fn write_default_toml(path: &Path) -> Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(DEFAULT_CONFIG_TOML.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    // Through `crate::scanner`, as `ExtractionConfig` uses its types
+    use std::path::{Path, PathBuf};
+    use crate::scanner::archive::TarCompression;
+    use crate::scanner::config_file::{write_default_toml, ConfigFile, DEFAULT_CONFIG_TOML};
+    use crate::scanner::failure::FailurePolicy;
+    use crate::scanner::types::{ExtractionStrategy, OverwritePolicy};
+    use crate::ExtractionConfig;
+
+    #[test]
+    fn test_relative_paths_resolve_against_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("extraction.toml");
+        std::fs::write(&path, r#"
+            input_dir = "arma"
+            output_dir = "/var/cache/extraction"
+            extensions = "sqf,hpp"
+            run_report = "last-run.json"
+            log_file = "logs/extraction.log"
+            log_file_level = "debug"
+            min_age_secs = 60
+            overwrite = "skip_existing"
+            fallback_chain = ["filtered", "direct"]
+
+            [tar_output]
+            zstd_level = 19
+
+            [failure_policy]
+            max_failures = 5
+        "#).unwrap();
+
+        let file = ConfigFile::load(&path).unwrap();
+        let config = ExtractionConfig::from_toml(&file).unwrap();
+        assert_eq!(config.input_dir, dir.path().join("arma"));
+        assert_eq!(config.output_dir, Path::new("/var/cache/extraction"));
+        assert_eq!(config.log_file, Some(dir.path().join("logs/extraction.log")));
+        assert_eq!(config.run_report, Some(PathBuf::from("last-run.json")));
+        assert_eq!(config.extensions, "sqf,hpp");
+        assert_eq!(config.log_file_level, log::LevelFilter::Debug);
+        assert_eq!(config.min_age, Some(std::time::Duration::from_secs(60)));
+        assert_eq!(config.overwrite, OverwritePolicy::SkipExisting);
+        assert_eq!(config.fallback_chain, vec![ExtractionStrategy::Filtered, ExtractionStrategy::Direct]);
+        assert_eq!(config.tar_output.map(|tar| tar.compression), Some(TarCompression::Zstd(19)));
+        assert_eq!(config.failure_policy, FailurePolicy::Threshold { max_failures: Some(5), max_failure_ratio: None });
+        // Untouched options keep their defaults
+        assert_eq!(config.list_timeout, 10);
+        assert!(config.write_manifest);
+    }
+
+    #[test]
+    fn test_timeout_overrides_list_and_extract_timeouts() {
+        let base_dir = Path::new("/srv");
+        let file = ConfigFile::parse("input_dir = \"arma\"\noutput_dir = \"cache\"\nlist_timeout = 5\nextract_timeout = 90\n", base_dir).unwrap();
+        let config = ExtractionConfig::from_toml(&file).unwrap();
+        assert_eq!(config.timeout, 0);
+        assert_eq!(config.list_timeout_secs(), 5);
+        assert_eq!(config.timeout_policy().base_secs, 90);
+
+        let file = ConfigFile { timeout: Some(30), ..file };
+        let config = ExtractionConfig::from_toml(&file).unwrap();
+        assert_eq!(config.list_timeout_secs(), 30);
+        assert_eq!(config.timeout_policy().base_secs, 30);
+
+        // Like before the split, a single timeout still sets both
+        let config = ExtractionConfig { timeout: 45, ..ExtractionConfig::new(Path::new("arma"), Path::new("cache")) };
+        assert_eq!((config.list_timeout_secs(), config.timeout_policy().base_secs), (45, 45));
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let base_dir = Path::new("/srv");
+        let error = ConfigFile::parse("input_dir = \"arma\"\nextension = \"sqf\"\n", base_dir).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `extension`"), "{:#}", error);
+
+        let error = ConfigFile::parse("[quarantine]\nafter_failure = 2\n", base_dir).unwrap_err();
+        assert!(format!("{:#}", error).contains("unknown field `after_failure`"), "{:#}", error);
+
+        let error = ConfigFile::parse("threads = \"four\"\n", base_dir).unwrap_err();
+        assert!(format!("{:#}", error).contains("threads"), "{:#}", error);
+
+        let file = ConfigFile::parse("output_dir = \"cache\"\nlog_file_level = \"verbose\"\n", base_dir).unwrap();
+        let error = ExtractionConfig::from_toml(&file).unwrap_err();
+        assert!(error.to_string().contains("input_dir"), "{}", error);
+        let file = ConfigFile { input_dir: Some(PathBuf::from("arma")), ..file };
+        let error = ExtractionConfig::from_toml(&file).unwrap_err();
+        assert!(error.to_string().contains("log_file_level"), "{}", error);
+    }
+
+    #[test]
+    fn test_template_documents_every_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("extraction.toml");
+        write_default_toml(&path).unwrap();
+        assert!(write_default_toml(&path).is_err());
+        let file = ConfigFile::load(&path).unwrap();
+        assert_eq!(file.input_dir, Some(dir.path().join("arma")));
+
+        // Uncommenting every documented key must give a valid file
+        let uncommented: String = DEFAULT_CONFIG_TOML.lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(key) if key.contains(" = ") || key.starts_with('[') => key,
+                _ => line,
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let file = ConfigFile::parse(&uncommented, dir.path()).unwrap();
+        ExtractionConfig::from_toml(&file).unwrap();
+
+        // The error for an unknown key lists all top-level keys
+        let error = ConfigFile::parse("not_a_key = 1", dir.path()).unwrap_err().to_string();
+        let keys = error.split("expected one of ").nth(1).unwrap().lines().next().unwrap();
+        for key in keys.split(", ").map(|key| key.trim_matches('`')) {
+            assert!(uncommented.lines().any(|line| line.starts_with(&format!("{} = ", key))
+                || line == format!("[{}]", key)), "{} is not in the template", key);
+        }
+    }
+}
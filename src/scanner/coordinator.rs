@@ -1,117 +1,3390 @@
 #[allow(dead_code)]
-use std::path::Path;
-use log::{debug, trace, warn};
-use walkdir::WalkDir;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use indicatif::HumanBytes;
+use log::{debug, info, trace, warn};
+use walkdir::{DirEntry, WalkDir};
 use anyhow::Result;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 
-use super::processor::PboProcessor;
+use super::archive::TarTarget;
+use super::cache_lock::CACHE_LOCK_FILE_NAME;
+use super::cancel::CancelToken;
+use super::dedupe::{dedupe_dir, DEFAULT_DEDUPE_MIN_SIZE};
+use super::index::{ExtractionIndex, FailureStage, SourceUpdate, INDEX_FILE_NAME};
+use super::derap::ConfigConverter;
+use super::events::{EventSink, ExtractionEvent};
+use super::failure::{FailurePolicy, FAILURE_LOG_DIR_NAME};
+use super::filter::{FileFilter, PathPatterns, PboFilter};
+use super::limit::ConcurrencyLimit;
+use super::listing_cache::{ListingCache, DEFAULT_LISTING_CACHE_MAX_BYTES, LISTING_CACHE_FILE_NAME};
+use super::manifest::{ExtractionManifest, MANIFEST_FILE_NAME};
+use super::processor::{PboProcessor, STAGING_DIR_NAME};
+use super::quarantine::{remove_copy, QuarantinePolicy, QUARANTINE_DIR_NAME};
+use super::progress::{ExtractionProgress, NoopProgress, ProgressReporter};
+use super::retry::{is_lock_error, LockRetry, RetryPolicy};
+use super::run_report::{RunConfig, RunReport, RUN_REPORT_FILE_NAME};
+#[cfg(feature = "signatures")]
+use super::signature::SignatureVerifier;
+use super::store::{ObjectStore, OBJECTS_DIR_NAME};
+use super::stream::FileSink;
+use super::transform::FileTransformer;
+use super::types::{
+    AdoptionReport,
+    CacheDiscrepancy,
+    CacheVerification,
+    ExtractionReport,
+    ExtractionStrategy,
+    ModDetection,
+    ModMetadata,
+    NameSanitization,
+    OutputCollisionHandling,
+    OverwritePolicy,
+    PboContents,
+    PboEntry,
+    PboFailure,
+    PboScanResult,
+    ProcessingOrder,
+    ReservedNameHandling,
+    SignatureStatus,
+    SkipReason,
+    TimeoutPolicy,
+    TouchReport,
+    VerificationMode,
+    WalkOptions,
+};
+use super::backend;
 use super::utils;
+use super::versions;
+use crate::error::ExtractionError;
+
+/// Files and directories the cache keeps at its root, which no PBO output may take
+const RESERVED_OUTPUT_NAMES: &[&str] = &[
+    CACHE_LOCK_FILE_NAME,
+    FAILURE_LOG_DIR_NAME,
+    INDEX_FILE_NAME,
+    LISTING_CACHE_FILE_NAME,
+    OBJECTS_DIR_NAME,
+    QUARANTINE_DIR_NAME,
+    RUN_REPORT_FILE_NAME,
+    STAGING_DIR_NAME,
+    utils::PREFIX_MAP_FILE_NAME,
+];
+
+/// Name of the file or directory at the cache root that the default output
+/// directory of a PBO would take or lie in, given relative to the input directory
+///
+/// Names are compared ignoring case, as the output may be lowercased.
+fn reserved_output(rel_path: &Path) -> Option<&'static str> {
+    let first = rel_path.with_extension("").components().next()?.as_os_str().to_string_lossy().to_lowercase();
+    RESERVED_OUTPUT_NAMES.iter().copied().find(|name| *name == first)
+}
+
+/// PBOs whose default output directory collides with that of other PBOs
+///
+/// Takes PBO paths relative to the input directory. A PBO collides when another PBO
+/// maps to the same output directory, or to one below it, which the swap into place
+/// would replace. It also collides with the cache's own files and directories its
+/// output would take or lie in, see `reserved_output`, given by their names, and
+/// with the retained versions of another output when named like one of them, as
+/// `foo.prev1.pbo` is, given by that output directory. Returns each colliding PBO
+/// with what it collides with.
+fn output_collisions(rel_paths: &[PathBuf]) -> BTreeMap<PathBuf, Vec<PathBuf>> {
+    let output_key = |rel_path: &Path| {
+        let key = rel_path.with_extension("").to_string_lossy().replace('\\', "/");
+        if cfg!(windows) { key.to_lowercase() } else { key }
+    };
+    let mut by_output: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+    for rel_path in rel_paths {
+        by_output.entry(output_key(rel_path)).or_default().push(rel_path);
+    }
+
+    let mut collisions: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for rel_path in rel_paths {
+        if let Some(name) = reserved_output(rel_path) {
+            collisions.entry(rel_path.clone()).or_default().push(PathBuf::from(name));
+        }
+        // Lowercased, as `foo.PREV1` is the directory of version 1 on Windows
+        let output = PathBuf::from(rel_path.with_extension("").to_string_lossy().to_lowercase());
+        if let Some((versioned, _)) = versions::parse_version_dir(&output) {
+            collisions.entry(rel_path.clone()).or_default().push(versioned);
+        }
+    }
+    for (key, owners) in &by_output {
+        // Same output directory
+        if owners.len() > 1 {
+            for owner in owners {
+                let others = owners.iter().filter(|other| *other != owner).map(|other| (*other).clone());
+                collisions.entry((*owner).clone()).or_default().extend(others);
+            }
+        }
+
+        // Output directories nested inside this one
+        let nested_prefix = format!("{}/", key);
+        for (_, nested) in by_output.range(nested_prefix.clone()..).take_while(|(k, _)| k.starts_with(&nested_prefix)) {
+            for owner in owners {
+                collisions.entry((*owner).clone()).or_default().extend(nested.iter().map(|path| (*path).clone()));
+            }
+        }
+    }
+
+    for colliding in collisions.values_mut() {
+        colliding.sort();
+        colliding.dedup();
+    }
+    collisions
+}
+
+/// Nesting levels extracted below a top-level PBO by default
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 2;
+
+/// PBOs kept in the report's slowest table by default
+pub const DEFAULT_SLOWEST_PBOS: usize = 10;
+
+/// PBOs listed by `ScanCoordinator::list_pbos`
+struct Listing {
+    scan_results: Vec<PboScanResult>,
+    /// PBOs whose contents could not be listed
+    failed: Vec<PboFailure>,
+    /// PBOs another process kept locked through every listing attempt
+    locked: Vec<PathBuf>,
+    /// PBOs not listed because the run was cancelled
+    not_listed: Vec<PathBuf>,
+    case_collisions: Vec<(PathBuf, Vec<String>)>,
+    reserved_names: Vec<(PathBuf, Vec<String>)>,
+    /// Time spent listing, summed over the PBOs
+    duration: Duration,
+    /// Times the tool ran to list the PBOs
+    tool_runs: usize,
+    /// Time the listings waited for a free tool slot, summed over the PBOs
+    tool_wait: Duration,
+}
+
+/// One PBO listed by `ScanCoordinator::list_pbo`
+struct Listed {
+    result: Result<PboScanResult>,
+    /// Time the listing took, retries included
+    duration: Duration,
+    /// Times the tool ran for the listing, retries and fallback listings included
+    tool_runs: usize,
+    /// Time the listing waited for a free tool slot
+    tool_wait: Duration,
+}
+
+/// What `ScanCoordinator::touch` did with the output of a PBO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Touched {
+    Refreshed,
+    Current,
+    /// Not extracted completely with the current filter, so left alone
+    Pending,
+    Skipped,
+}
+
+/// Index updates applied at once by `apply_source_updates`
+const INDEX_UPDATE_BATCH: usize = 256;
+
+/// Current time in seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Bring the index entries of `outputs`, by PBO, base directory and mod name, up to
+/// date with their manifests, returning the number of PBOs whose entries were kept
+///
+/// Manifests are read on the worker threads and sent to this thread, the only one
+/// touching the index, so reading never waits on applying or the other way around.
+/// Updates are applied in batches of `INDEX_UPDATE_BATCH`, sorting the files once
+/// per batch.
+fn apply_source_updates(
+    index: &mut ExtractionIndex,
+    outputs: &[(PathBuf, PathBuf, Option<String>)],
+    pool: &ThreadPool,
+) -> Result<usize> {
+    let (sender, updates) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        // Off this thread, so the loop below runs whether or not it's a worker itself
+        scope.spawn(move || pool.install(|| {
+            outputs.par_iter().for_each_with(sender, |sender, (source, base_dir, mod_name)| {
+                // Only fails once applying gave up
+                let _ = sender.send(SourceUpdate::read(source, base_dir, mod_name.as_deref()));
+            });
+        }));
+        let mut reused = 0;
+        let mut batch = Vec::with_capacity(INDEX_UPDATE_BATCH);
+        for update in updates {
+            batch.push(update);
+            if batch.len() == INDEX_UPDATE_BATCH {
+                reused += index.apply_updates(batch.drain(..))?;
+            }
+        }
+        reused += index.apply_updates(batch)?;
+        Ok(reused)
+    })
+}
+
+/// PBOs split off by `ScanCoordinator::skip_failing`, relative to the input directory
+#[derive(Default)]
+struct KnownFailures {
+    quarantined: Vec<PathBuf>,
+    /// PBOs that failed `max_attempts` runs in a row and haven't changed since
+    gave_up: Vec<PathBuf>,
+    /// Quarantined PBOs that changed, whose quarantine is to be lifted
+    released: Vec<PathBuf>,
+}
+
+/// PBO found inside the output of another PBO
+struct NestedPbo {
+    path: PathBuf,
+    /// Directory next to the nested PBO it extracts into
+    base_dir: PathBuf,
+    /// Path of the outermost PBO relative to the input directory, followed by the
+    /// entry path of each nested PBO, separated by `!`
+    label: PathBuf,
+}
+
+/// PBOs found by `ScanCoordinator::discover_and_list`, with the listings of those to process
+struct StreamedScan {
+    /// PBO files found by the walk, before any filter
+    discovered: usize,
+    /// Directories not descended into because of the depth limit
+    depth_limited_dirs: usize,
+    /// PBOs left out by the include and exclude patterns
+    filtered_out: usize,
+    /// PBOs modified within the grace period, relative to the input directory
+    deferred: Vec<PathBuf>,
+    known_failures: KnownFailures,
+    /// PBOs to process, in the order of the walk
+    pbos: Vec<DirEntry>,
+    listing: Listing,
+}
+
+/// PBOs found by `ScanCoordinator::discover_and_list` and queued for listing, before
+/// the listing workers catch up
+const DISCOVERY_BUFFER: usize = 256;
+
+/// PBO files found by walking the input directory
+struct Discovery {
+    pbos: Vec<DirEntry>,
+    /// Directories not descended into because of the depth limit
+    depth_limited_dirs: usize,
+}
 
 pub struct ScanCoordinator<'a> {
     input_dir: &'a Path,
     cache_dir: &'a Path,
-    extensions: &'a str,
+    filter: &'a FileFilter,
     threads: usize,
-    timeout: u32
+    /// Pool every parallel step runs in; see `thread_pool`
+    thread_pool: OnceLock<Arc<ThreadPool>>,
+    processing_order: ProcessingOrder,
+    /// Limit on tool runs listing or extracting at once, shared with the processors
+    io_limit: Option<Arc<ConcurrencyLimit>>,
+    /// Limit on tool processes running at once, applied to the whole process by `run`
+    tool_concurrency: usize,
+    background: bool,
+    /// Applied to the whole process by `run` when set, see `set_hide_console`
+    hide_console: Option<bool>,
+    list_timeout: u32,
+    timeout_policy: TimeoutPolicy,
+    pbo_filter: PboFilter,
+    walk_options: WalkOptions,
+    events: EventSink,
+    progress: ExtractionProgress,
+    retry: RetryPolicy,
+    lock_retry: LockRetry,
+    fallback_chain: Vec<ExtractionStrategy>,
+    write_manifest: bool,
+    write_failure_logs: bool,
+    emit_checksums: bool,
+    verification: VerificationMode,
+    resume: bool,
+    adopt_existing: bool,
+    overwrite: OverwritePolicy,
+    retain_versions: usize,
+    sync_outputs: bool,
+    sync_dry_run: bool,
+    clean_partial: bool,
+    normalize_case: bool,
+    name_sanitization: NameSanitization,
+    reserved_names: ReservedNameHandling,
+    output_collisions: OutputCollisionHandling,
+    detect_conflicts: bool,
+    content_store: bool,
+    dedupe: bool,
+    dedupe_min_size: u64,
+    build_index: bool,
+    write_prefix_map: bool,
+    /// Listings of earlier runs, reused for PBOs that haven't changed since
+    listing_cache: Option<ListingCache>,
+    mod_detection: ModDetection,
+    ignore_disk_space: bool,
+    skip_backend_check: bool,
+    /// `utils::check_backend`, unless a test replaces it
+    backend_check: fn() -> Result<(), ExtractionError>,
+    max_output_bytes: Option<u64>,
+    cancel: CancelToken,
+    failure_policy: FailurePolicy,
+    quarantine: Option<QuarantinePolicy>,
+    max_attempts: Option<u32>,
+    force_retry: bool,
+    run_report: Option<PathBuf>,
+    csv_report: Option<PathBuf>,
+    mods_csv_report: Option<PathBuf>,
+    slowest: usize,
+    recursive: bool,
+    max_nesting_depth: usize,
+    derapify: Option<Arc<dyn ConfigConverter>>,
+    transformer: Option<Arc<dyn FileTransformer>>,
+    convert_stringtables: bool,
+    tar_target: Option<TarTarget>,
+    sink: Option<Arc<dyn FileSink>>,
+    convert_paa: bool,
+    #[cfg_attr(not(feature = "paa-convert"), allow(dead_code))]
+    replace_paa_originals: bool,
+    min_age: Option<Duration>,
+    verify_signatures: bool,
+    require_valid_signature: bool,
+    #[cfg_attr(not(feature = "signatures"), allow(dead_code))]
+    key_dir: Option<PathBuf>,
 }
 
 impl<'a> ScanCoordinator<'a> {
     pub fn new(
         input_dir: &'a Path,
         cache_dir: &'a Path,
-        extensions: &'a str,
+        filter: &'a FileFilter,
         threads: usize,
         timeout: u32,
     ) -> Result<Self> {
         Ok(Self {
             input_dir,
             cache_dir,
-            extensions,
+            filter,
             threads,
-            timeout,
+            thread_pool: OnceLock::new(),
+            processing_order: ProcessingOrder::default(),
+            io_limit: None,
+            tool_concurrency: utils::default_tool_concurrency(threads),
+            background: false,
+            hide_console: None,
+            list_timeout: timeout,
+            timeout_policy: TimeoutPolicy::fixed(timeout),
+            pbo_filter: PboFilter::default(),
+            walk_options: WalkOptions::default(),
+            events: EventSink::default(),
+            progress: ExtractionProgress::default(),
+            retry: RetryPolicy::none(),
+            lock_retry: LockRetry::none(),
+            fallback_chain: ExtractionStrategy::default_chain(),
+            write_manifest: true,
+            write_failure_logs: true,
+            emit_checksums: false,
+            verification: VerificationMode::default(),
+            resume: false,
+            adopt_existing: false,
+            overwrite: OverwritePolicy::default(),
+            retain_versions: 0,
+            sync_outputs: false,
+            sync_dry_run: false,
+            clean_partial: false,
+            normalize_case: false,
+            name_sanitization: NameSanitization::default(),
+            reserved_names: ReservedNameHandling::default(),
+            output_collisions: OutputCollisionHandling::default(),
+            detect_conflicts: false,
+            content_store: false,
+            dedupe: false,
+            dedupe_min_size: DEFAULT_DEDUPE_MIN_SIZE,
+            build_index: false,
+            write_prefix_map: true,
+            listing_cache: Some(ListingCache::new(input_dir, cache_dir, DEFAULT_LISTING_CACHE_MAX_BYTES)),
+            mod_detection: ModDetection::default(),
+            ignore_disk_space: false,
+            skip_backend_check: false,
+            backend_check: utils::check_backend,
+            max_output_bytes: None,
+            cancel: CancelToken::default(),
+            failure_policy: FailurePolicy::default(),
+            quarantine: None,
+            max_attempts: None,
+            force_retry: false,
+            run_report: None,
+            csv_report: None,
+            mods_csv_report: None,
+            slowest: DEFAULT_SLOWEST_PBOS,
+            recursive: false,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            derapify: None,
+            transformer: None,
+            convert_stringtables: false,
+            tar_target: None,
+            sink: None,
+            convert_paa: false,
+            replace_paa_originals: false,
+            min_age: None,
+            verify_signatures: false,
+            require_valid_signature: false,
+            key_dir: None,
         })
     }
 
-    pub async fn run(&self) -> Result<()> {
-        debug!("Starting extraction process with the following configuration:");
-        debug!("  Input directory: {}", self.input_dir.display());
-        debug!("  Cache directory: {}", self.cache_dir.display());
-        debug!("  Extensions filter: {}", self.extensions);
-        debug!("  Threads: {}", self.threads);
-        debug!("  Timeout: {} seconds", self.timeout);
+    /// Run listing, extraction and the other parallel steps in `pool`, such as one
+    /// shared by several coordinators, rather than a pool of `threads` workers
+    ///
+    /// The pool is used as it is, also in background mode.
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = OnceLock::from(pool);
+        self
+    }
 
-        // Verify directories exist
+    /// Order PBOs are handed to the extraction workers in
+    pub fn with_processing_order(mut self, processing_order: ProcessingOrder) -> Self {
+        self.processing_order = processing_order;
+        self
+    }
+
+    /// Run the tool on at most `io_concurrency` PBOs at once, listing and extracting
+    /// together, while the other steps use every thread
+    ///
+    /// Meant for disks that slow down under many readers and writers, such as
+    /// spinning disks; `None` leaves `threads` as the only limit.
+    pub fn with_io_concurrency(mut self, io_concurrency: Option<usize>) -> Self {
+        self.io_limit = io_concurrency.map(|limit| Arc::new(ConcurrencyLimit::new(limit)));
+        self
+    }
+
+    /// Run at most `tool_concurrency` tool processes at once, listing and extracting,
+    /// or `default_tool_concurrency` of `threads` when `None`
+    ///
+    /// Unlike the I/O limit, it holds for the whole process, so the coordinator run
+    /// last sets it for every run still going.
+    pub fn with_tool_concurrency(mut self, tool_concurrency: Option<usize>) -> Self {
+        self.tool_concurrency = tool_concurrency.unwrap_or_else(|| utils::default_tool_concurrency(self.threads));
+        self
+    }
+
+    /// Run in background mode, leaving the machine usable for other programs: the
+    /// workers run at lowered priority and pause after each PBO
+    ///
+    /// The coordinator's own pool is built with `build_background_pool`, so only its
+    /// workers and, on Linux, the tool they run are lowered. Threads aren't capped
+    /// here; build the coordinator with `background_threads` of the threads wanted.
+    pub fn with_background(mut self, background: bool) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Keep the tool's console windows hidden, or show them; left as it is for the
+    /// process when `None`, which hides them in processes without a console
+    pub fn with_hide_console(mut self, hide_console: Option<bool>) -> Self {
+        self.hide_console = hide_console;
+        self
+    }
+
+    /// Report listing and extraction progress
+    pub fn with_progress(mut self, progress: ExtractionProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Use a separate timeout for listing PBO contents during the scan
+    pub fn with_list_timeout(mut self, list_timeout: u32) -> Self {
+        self.list_timeout = list_timeout;
+        self
+    }
+
+    /// Scale extraction timeouts by PBO size instead of using the fixed timeout
+    pub fn with_timeout_policy(mut self, timeout_policy: TimeoutPolicy) -> Self {
+        self.timeout_policy = timeout_policy;
+        self
+    }
+
+    /// Extraction strategies tried in order until one succeeds
+    pub fn with_fallback_chain(mut self, fallback_chain: Vec<ExtractionStrategy>) -> Self {
+        self.fallback_chain = fallback_chain;
+        self
+    }
+
+    /// Write an extraction manifest into each extracted PBO's base directory
+    pub fn with_manifest(mut self, write_manifest: bool) -> Self {
+        self.write_manifest = write_manifest;
+        self
+    }
+
+    /// Write a log into the base directory of each PBO failing to extract
+    pub fn with_failure_logs(mut self, write_failure_logs: bool) -> Self {
+        self.write_failure_logs = write_failure_logs;
+        self
+    }
+
+    /// Write `SHA256SUMS` for the extracted files of each PBO
+    pub fn with_checksums(mut self, emit_checksums: bool) -> Self {
+        self.emit_checksums = emit_checksums;
+        self
+    }
+
+    /// Choose whether missing files after extraction fail the PBO
+    pub fn with_verification(mut self, verification: VerificationMode) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Only extract expected files not already present in the output directory
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Record complete existing outputs as the extraction of their PBO instead of
+    /// extracting it again, see `adopt_existing`
+    pub fn with_adopt_existing(mut self, adopt_existing: bool) -> Self {
+        self.adopt_existing = adopt_existing;
+        self
+    }
+
+    /// Keep up to `retain_versions` earlier outputs of changed PBOs, see `versions::replace_retaining`
+    pub fn with_retain_versions(mut self, retain_versions: usize) -> Self {
+        self.retain_versions = retain_versions;
+        self
+    }
+
+    /// Delete files left over from previous extractions that the new listing no longer
+    /// contains; with `dry_run` they are only reported
+    pub fn with_sync_outputs(mut self, sync_outputs: bool, dry_run: bool) -> Self {
+        self.sync_outputs = sync_outputs;
+        self.sync_dry_run = dry_run;
+        self
+    }
+
+    /// Choose how expected files already present in an output directory are treated
+    pub fn with_overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Lowercase the prefix and expected paths of every listing, and the files
+    /// extracted for them; entries differing only by case are extracted once and
+    /// reported as collisions. The tool is still given the names in the PBO
+    pub fn with_normalize_case(mut self, normalize_case: bool) -> Self {
+        self.normalize_case = normalize_case;
+        self
+    }
+
+    pub fn with_name_sanitization(mut self, name_sanitization: NameSanitization) -> Self {
+        self.name_sanitization = name_sanitization;
+        self
+    }
+
+    pub fn with_reserved_names(mut self, reserved_names: ReservedNameHandling) -> Self {
+        self.reserved_names = reserved_names;
+        self
+    }
+
+    pub fn with_output_collisions(mut self, output_collisions: OutputCollisionHandling) -> Self {
+        self.output_collisions = output_collisions;
+        self
+    }
+
+    pub fn with_conflict_detection(mut self, detect_conflicts: bool) -> Self {
+        self.detect_conflicts = detect_conflicts;
+        self
+    }
+
+    pub fn with_content_store(mut self, content_store: bool) -> Self {
+        self.content_store = content_store;
+        self
+    }
+
+    /// Hard link identical files of at least `min_size` bytes across the cache after the run
+    pub fn with_dedupe(mut self, dedupe: bool, min_size: u64) -> Self {
+        self.dedupe = dedupe;
+        self.dedupe_min_size = min_size;
+        self
+    }
+
+    /// Update the index of extracted files in the cache directory after the run
+    pub fn with_index(mut self, build_index: bool) -> Self {
+        self.build_index = build_index;
+        self
+    }
+
+    /// Group PBOs by the mod folder they were found in
+    pub fn with_mod_detection(mut self, mod_detection: ModDetection) -> Self {
+        self.mod_detection = mod_detection;
+        self
+    }
+
+    /// Write the prefixes of the listed PBOs into the cache directory before extracting
+    pub fn with_prefix_map(mut self, write_prefix_map: bool) -> Self {
+        self.write_prefix_map = write_prefix_map;
+        self
+    }
+
+    /// Keep the listings of PBOs in the cache directory and reuse them while the PBOs
+    /// are unchanged, unless the compressed listings exceed `max_bytes`
+    pub fn with_listing_cache(mut self, cache_listings: bool, max_bytes: u64) -> Self {
+        self.listing_cache = cache_listings.then(|| ListingCache::new(self.input_dir, self.cache_dir, max_bytes));
+        self
+    }
+
+    /// Extract even when the estimated output doesn't fit in the free disk space
+    pub fn with_ignore_disk_space(mut self, ignore_disk_space: bool) -> Self {
+        self.ignore_disk_space = ignore_disk_space;
+        self
+    }
+
+    /// Start without checking that the tool can be run, see `check_backend`; nor is
+    /// it checked when every listing fails
+    pub fn with_skip_backend_check(mut self, skip_backend_check: bool) -> Self {
+        self.skip_backend_check = skip_backend_check;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_backend_check(mut self, backend_check: fn() -> Result<(), ExtractionError>) -> Self {
+        self.backend_check = backend_check;
+        self
+    }
+
+    /// Stop starting extractions once they would write more than `max_output_bytes`
+    pub fn with_max_output_bytes(mut self, max_output_bytes: Option<u64>) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    /// Stop listing and extracting further PBOs once `cancel` is cancelled
+    pub fn with_cancellation(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Skip PBOs that failed too many runs in a row until they change
+    ///
+    /// Quarantines are kept in the extraction index, so it's updated even without `with_index`.
+    pub fn with_quarantine(mut self, quarantine: Option<QuarantinePolicy>) -> Self {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// Stop retrying PBOs that failed `max_attempts` runs in a row until they change
+    ///
+    /// Failures are counted in the extraction index, so it's updated even without `with_index`.
+    pub fn with_max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Retry PBOs given up on by `with_max_attempts` anyway
+    pub fn with_force_retry(mut self, force_retry: bool) -> Self {
+        self.force_retry = force_retry;
+        self
+    }
+
+    /// Write a `RunReport` to `path` at the end of each run, relative to the cache directory
+    pub fn with_run_report(mut self, path: Option<PathBuf>) -> Self {
+        self.run_report = path;
+        self
+    }
+
+    /// Write the per-PBO and per-mod CSVs of the report at the end of each run,
+    /// relative to the cache directory
+    pub fn with_csv_reports(mut self, pbos: Option<PathBuf>, mods: Option<PathBuf>) -> Self {
+        self.csv_report = pbos;
+        self.mods_csv_report = mods;
+        self
+    }
+
+    /// Keep the `count` slowest PBOs of each run in the report, 0 for none
+    pub fn with_slowest(mut self, count: usize) -> Self {
+        self.slowest = count;
+        self
+    }
+
+    /// Stop starting extractions once failures exceed `policy`
+    pub fn with_failure_policy(mut self, policy: FailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// Extract PBOs found inside extracted PBOs, up to `max_depth` levels deep
+    pub fn with_recursion(mut self, recursive: bool, max_depth: usize) -> Self {
+        self.recursive = recursive;
+        self.max_nesting_depth = max_depth;
+        self
+    }
+
+    /// Convert extracted rapified configs to config.cpp with `converter`
+    pub fn with_derapify(mut self, converter: Option<Arc<dyn ConfigConverter>>) -> Self {
+        self.derapify = converter;
+        self
+    }
+
+    /// Run `transformer` on every extracted file before it is moved into the cache
+    pub fn with_transformer(mut self, transformer: Option<Arc<dyn FileTransformer>>) -> Self {
+        self.transformer = transformer;
+        self
+    }
+
+    pub fn with_stringtable_conversion(mut self, convert_stringtables: bool) -> Self {
+        self.convert_stringtables = convert_stringtables;
+        self
+    }
+
+    /// Write each PBO's output into a tar archive instead of a directory
+    pub fn with_tar_output(mut self, tar_target: Option<TarTarget>) -> Self {
+        self.tar_target = tar_target;
+        self
+    }
+
+    /// Hand every extracted file to `sink` instead of keeping it in the cache, which
+    /// then only holds manifests to skip PBOs unchanged since they were streamed
+    pub fn with_sink(mut self, sink: Option<Arc<dyn FileSink>>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Write a PNG next to every extracted PAA texture, deleting the texture with
+    /// `replace_originals`; needs the `paa-convert` feature
+    pub fn with_paa_conversion(mut self, convert_paa: bool, replace_originals: bool) -> Self {
+        self.convert_paa = convert_paa;
+        self.replace_paa_originals = replace_originals;
+        self
+    }
+
+    /// Leave PBOs modified less than `min_age` ago for a later run, as they may still
+    /// be written by an update
+    pub fn with_min_age(mut self, min_age: Option<Duration>) -> Self {
+        self.min_age = min_age;
+        self
+    }
+
+    /// Check each PBO's `.bisign` files against the keys in `key_dir`, or in its mod's
+    /// `keys` directory, while scanning; with `require_valid`, PBOs without a valid
+    /// signature fail instead of being extracted. Needs the `signatures` feature
+    pub fn with_signature_verification(mut self, verify: bool, require_valid: bool, key_dir: Option<PathBuf>) -> Self {
+        self.verify_signatures = verify || require_valid;
+        self.require_valid_signature = require_valid;
+        self.key_dir = key_dir;
+        self
+    }
+
+    /// Remove output directories of discovered PBOs that lack an extraction manifest
+    pub fn with_clean_partial(mut self, clean_partial: bool) -> Self {
+        self.clean_partial = clean_partial;
+        self
+    }
+
+    /// Retry listings and extractions that time out
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Retry extractions of PBOs held open by another process
+    pub fn with_lock_retry(mut self, lock_retry: LockRetry) -> Self {
+        self.lock_retry = lock_retry;
+        self
+    }
+
+    /// Send progress events while scanning and extracting
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Path of a PBO relative to the input directory, as reported in events
+    fn relative_path(&self, pbo_path: &Path) -> std::path::PathBuf {
+        pbo_path.strip_prefix(self.input_dir).unwrap_or(pbo_path).to_path_buf()
+    }
+
+    /// Restrict the PBO files picked up by the directory walk
+    pub fn with_pbo_filter(mut self, pbo_filter: PboFilter) -> Self {
+        self.pbo_filter = pbo_filter;
+        self
+    }
+
+    /// Control how the input directory is walked
+    pub fn with_walk_options(mut self, walk_options: WalkOptions) -> Self {
+        self.walk_options = walk_options;
+        self
+    }
+
+    /// Walk the input directory and collect every PBO file
+    fn discover_pbos(&self) -> Result<Discovery> {
+        let mut pbos = Vec::new();
+        let depth_limited_dirs = self.walk_pbos(|entry| pbos.push(entry))?;
+        Ok(Discovery { pbos, depth_limited_dirs })
+    }
+
+    /// PBOs a run would pick up, as their paths and their paths relative to the input
+    /// directory, in path order
+    ///
+    /// The input directory is walked as for a run, and the PBOs found go through the
+    /// include and exclude patterns and the grace period. Known failures are kept.
+    pub fn find_pbos(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
         if !self.input_dir.exists() {
-            return Err(anyhow::anyhow!("Input directory does not exist: {}", self.input_dir.display()));
+            return Err(ExtractionError::InputDirNotFound(self.input_dir.to_owned()).into());
+        }
+        let discovery = self.discover_pbos()?;
+        let mut pbos = self.selected_pbos(&discovery.pbos);
+        if pbos.is_empty() {
+            return Err(ExtractionError::NoPbosFound {
+                input_dir: self.input_dir.to_owned(),
+                depth_limited_dirs: discovery.depth_limited_dirs,
+            }
+            .into());
         }
+        pbos.sort_by(|(_, a), (_, b)| a.cmp(b));
+        Ok(pbos)
+    }
 
-        // Create cache directory if it doesn't exist
-        if !self.cache_dir.exists() {
-            debug!("Creating cache directory: {}", self.cache_dir.display());
-            std::fs::create_dir_all(self.cache_dir)?;
+    /// Base output directories of the PBOs at `rel_paths`, relative to the input
+    /// directory, as a run over it plans them, whether or not the PBOs still exist
+    ///
+    /// PBOs deleted since their extraction are planned along with the PBOs still
+    /// there, so outputs moved aside for a collision or a name of the cache are found.
+    pub fn base_output_dirs(&self, rel_paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let filter = self.scan_filter();
+        let mut planned: BTreeSet<PathBuf> = self.discover_pbos()?.pbos
+            .iter()
+            .map(|entry| self.relative_path(entry.path()))
+            .filter(|rel_path| self.pbo_filter.matches(rel_path))
+            .collect();
+        planned.extend(rel_paths.iter().cloned());
+        let planned: Vec<_> = planned.into_iter().collect();
+        let (output_dirs, _) = self.plan_output_dirs(&output_collisions(&planned));
+        let processor = self.processor(&filter, self.progress.extraction.clone()).with_output_dirs(output_dirs);
+        rel_paths.iter().map(|rel_path| processor.base_output_dir(&self.input_dir.join(rel_path))).collect()
+    }
+
+    /// Discovered PBOs matching the include and exclude patterns and out of the grace
+    /// period, with their relative paths
+    fn selected_pbos(&self, pbos: &[DirEntry]) -> Vec<(PathBuf, PathBuf)> {
+        pbos.iter()
+            .filter(|entry| self.pbo_filter.matches(&self.relative_path(entry.path())) && !self.is_too_recent(entry))
+            .map(|entry| (entry.path().to_path_buf(), self.relative_path(entry.path())))
+            .collect()
+    }
+
+    /// Walk the input directory, passing every PBO file to `found` as soon as it's
+    /// seen, and return the number of directories not descended into because of the
+    /// depth limit
+    fn walk_pbos(&self, mut found: impl FnMut(DirEntry)) -> Result<usize> {
+        let ignore_dirs = PathPatterns::new(&self.walk_options.ignore_dirs, "ignore directory")?;
+
+        let mut walker = WalkDir::new(self.input_dir)
+            .follow_links(self.walk_options.follow_symlinks);
+        if let Some(max_depth) = self.walk_options.max_depth {
+            walker = walker.max_depth(max_depth);
         }
 
-        // Count total PBOs first for reference
-        debug!("Scanning input directory for PBO files...");
-        let total_pbo_files = WalkDir::new(self.input_dir)
+        let mut depth_limited_dirs = 0;
+        walker
             .into_iter()
-            .filter_map(|e| e.ok())
+            .filter_entry(|e| {
+                // Ignored subtrees are never descended into
+                if e.depth() == 0 || !e.file_type().is_dir() || ignore_dirs.is_empty() {
+                    return true;
+                }
+                let rel_path = e.path().strip_prefix(self.input_dir).unwrap_or(e.path());
+                let ignored = ignore_dirs.matches(rel_path);
+                if ignored {
+                    trace!("Ignoring directory: {}", e.path().display());
+                }
+                !ignored
+            })
+            .filter_map(|e| match e {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    // Symlink loops surface here when following links
+                    warn!("Skipping unreadable path during scan: {}", e);
+                    None
+                }
+            })
             .filter(|e| {
-                e.path().extension()
-                    .map(|ext| ext == "pbo")
-                    .unwrap_or(false)
+                if e.file_type().is_dir() && Some(e.depth()) == self.walk_options.max_depth {
+                    trace!("Not descending past depth limit: {}", e.path().display());
+                    depth_limited_dirs += 1;
+                    return false;
+                }
+                e.file_type().is_file() && self.walk_options.is_archive(e.path())
             })
-            .collect::<Vec<_>>();
+            .for_each(&mut found);
 
-        let total_pbo_count = total_pbo_files.len();
+        Ok(depth_limited_dirs)
+    }
 
-        if total_pbo_count == 0 {
-            return Err(anyhow::anyhow!("No PBO files found in input directory: {}", self.input_dir.display()));
+    /// Walk the input directory and list the PBOs to process while the walk goes on
+    ///
+    /// PBOs go through the include and exclude patterns, the grace period and the
+    /// known failures as they are found, then on to the listing workers, so listing
+    /// starts with the first PBO found rather than once the walk is done. The
+    /// listing progress total grows with the PBOs found. Listings are put back in
+    /// the order of the walk, as listing every PBO at once would give them.
+    fn discover_and_list(&self, filter: &FileFilter, progress: &dyn ProgressReporter) -> Result<StreamedScan> {
+        let failure_index = self.failure_index();
+        let mut discovered = 0;
+        let mut filtered_out = 0;
+        let mut deferred = Vec::new();
+        let mut known_failures = KnownFailures::default();
+        let mut pbos = Vec::new();
+        let after_extraction = self.lists_after_extraction(filter);
+        if after_extraction {
+            debug!("Extracting every entry, so PBOs are listed by their extraction instead of the tool");
         }
 
-        debug!("Found {} PBO files to process", total_pbo_count);
+        let (found_sender, found) = std::sync::mpsc::sync_channel(DISCOVERY_BUFFER);
+        let (list_sender, to_list) = std::sync::mpsc::sync_channel::<(usize, PathBuf, PathBuf)>(DISCOVERY_BUFFER);
+        let (depth_limited_dirs, mut listed) = std::thread::scope(|scope| {
+            let walk = scope.spawn(move || {
+                self.walk_pbos(|entry| {
+                    // Only fails once discovery gave up
+                    let _ = found_sender.send(entry);
+                })
+            });
+            // Off this thread, which keeps filtering what the walk finds meanwhile
+            let lister = scope.spawn(move || self.thread_pool().install(|| {
+                to_list
+                    .into_iter()
+                    .par_bridge()
+                    .map(|(order, path, label)| {
+                        (order, self.list_pbo(filter, &path, &label, after_extraction, progress), label)
+                    })
+                    .collect::<Vec<_>>()
+            }));
 
-        // Initialize processor with multithreading
-        debug!("Initializing PBO processor for extraction with {} threads", self.threads);
+            for entry in found {
+                discovered += 1;
+                // Apply PBO include/exclude patterns before anything touches the files
+                let rel_path = self.relative_path(entry.path());
+                if !self.pbo_filter.matches(&rel_path) {
+                    trace!("Filtered out PBO: {}", entry.path().display());
+                    filtered_out += 1;
+                    continue;
+                }
+                // Files still being written by an update are picked up by a later run
+                if self.is_too_recent(&entry) {
+                    debug!("Deferring recently modified PBO: {}", entry.path().display());
+                    deferred.push(rel_path);
+                    continue;
+                }
+                // Known broken PBOs never reach the tool again until they change
+                if failure_index.as_ref().is_some_and(|index| self.is_known_failure(index, &entry, &mut known_failures)) {
+                    continue;
+                }
+
+                self.events.send(ExtractionEvent::PboDiscovered { path: rel_path.clone() });
+                progress.set_total(pbos.len() as u64 + 1);
+                let _ = list_sender.send((pbos.len(), entry.path().to_path_buf(), rel_path));
+                pbos.push(entry);
+            }
+            drop(list_sender);
+            let walked = walk.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            let listed = lister.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            walked.map(|depth_limited_dirs| (depth_limited_dirs, listed))
+        })?;
+
+        listed.sort_by_key(|(order, _, _)| *order);
+        self.save_listing_cache();
+        let listing = self.collect_listing(listed.into_iter().map(|(_, result, label)| (result, label)));
+        Ok(StreamedScan { discovered, depth_limited_dirs, filtered_out, deferred, known_failures, pbos, listing })
+    }
+
+    /// Whether a discovered PBO was modified within the grace period
+    fn is_too_recent(&self, entry: &DirEntry) -> bool {
+        let Some(threshold) = self.min_age.and_then(|min_age| SystemTime::now().checked_sub(min_age)) else {
+            return false;
+        };
+        entry.metadata()
+            .ok()
+            .and_then(|metadata| metadata.modified().ok())
+            .is_some_and(|modified| modified > threshold)
+    }
+
+    /// Resolve PBOs whose default output directories collide
+    ///
+    /// Takes the result of `output_collisions`. Returns the base directories replacing
+    /// the default ones and the PBOs that must fail instead, depending on the collision
+    /// handling.
+    fn plan_output_dirs(
+        &self,
+        collisions: &BTreeMap<PathBuf, Vec<PathBuf>>,
+    ) -> (HashMap<PathBuf, PathBuf>, HashMap<PathBuf, String>) {
+        let mut output_dirs = HashMap::new();
+        let mut conflicts = HashMap::new();
+
+        for (rel_path, colliding) in collisions {
+            let pbo_path = self.input_dir.join(rel_path);
+            // Renaming the output directory doesn't move it out of a directory of the cache
+            let reserved_dir = reserved_output(rel_path).filter(|_| rel_path.components().count() > 1);
+            match (self.output_collisions, reserved_dir) {
+                (_, Some(name)) => {
+                    conflicts.insert(pbo_path, format!(
+                        "Output directory of {} would be inside the cache's {} directory",
+                        rel_path.display(),
+                        name
+                    ));
+                },
+                (OutputCollisionHandling::Disambiguate, None) => {
+                    let mut file_name = rel_path.file_name().unwrap_or_default().to_os_string();
+                    file_name.push(".d");
+                    let output_dir = self.cache_dir.join(rel_path.with_file_name(file_name));
+                    debug!(
+                        "Output directory of {} collides with {:?}, using {}",
+                        rel_path.display(),
+                        colliding,
+                        output_dir.display()
+                    );
+                    output_dirs.insert(pbo_path, output_dir);
+                },
+                (OutputCollisionHandling::Fail, None) => {
+                    let colliding: Vec<_> = colliding.iter().map(|path| path.display().to_string()).collect();
+                    conflicts.insert(pbo_path, format!(
+                        "Output directory of {} collides with: {}",
+                        rel_path.display(),
+                        colliding.join(", ")
+                    ));
+                },
+            }
+        }
+
+        (output_dirs, conflicts)
+    }
+
+    /// Filter used for listing and extraction
+    ///
+    /// Recursive runs also extract nested PBOs, and asking for config.cpp while
+    /// derapifying also extracts the `.bin` files it may be binarized to.
+    fn scan_filter(&self) -> FileFilter {
+        let mut filter = self.filter.clone();
+        if self.recursive {
+            filter = filter.with_extension("pbo");
+        }
+        if self.derapify.is_some() && crate::utils::matches_extension(Path::new("config.cpp"), &filter.extensions) {
+            filter = filter.with_extension("bin");
+        }
+        filter
+    }
+
+    /// Pool every parallel step runs in: the one given to `with_thread_pool`, or
+    /// `threads` workers of its own, built on first use
+    fn thread_pool(&self) -> &Arc<ThreadPool> {
+        self.thread_pool.get_or_init(|| {
+            let pool = match self.background {
+                true => crate::utils::build_background_pool(self.threads).or_else(|e| {
+                    warn!("Keeping the workers at normal priority: {:#}", e);
+                    crate::utils::build_thread_pool(self.threads)
+                }),
+                false => crate::utils::build_thread_pool(self.threads),
+            };
+            Arc::new(pool.expect("failed to start extraction threads"))
+        })
+    }
+
+    /// Processor extracting with this coordinator's options
+    fn processor<'b>(&'b self, filter: &'b FileFilter, progress: Arc<dyn ProgressReporter>) -> PboProcessor<'b> {
         let processor = PboProcessor::new(
             self.input_dir,
             self.cache_dir,
-            self.extensions,
+            filter,
             self.threads,
-            self.timeout,
-        );
+            self.timeout_policy.base_secs,
+        )
+        .with_processing_order(self.processing_order)
+        .with_io_limit(self.io_limit.clone())
+        .with_background(self.background)
+        .with_events(self.events.clone())
+        .with_progress(progress)
+        .with_retry(self.retry)
+        .with_lock_retry(self.lock_retry)
+        .with_fallback_chain(self.fallback_chain.clone())
+        .with_manifest(self.write_manifest)
+        .with_failure_logs(self.write_failure_logs)
+        .with_checksums(self.emit_checksums)
+        .with_verification(self.verification)
+        .with_resume(self.resume)
+        .with_overwrite(self.overwrite)
+        .with_retain_versions(self.retain_versions)
+        .with_sync_outputs(self.sync_outputs, self.sync_dry_run)
+        .with_normalize_case(self.normalize_case)
+        .with_object_store(self.content_store.then(|| ObjectStore::new(self.cache_dir)))
+        .with_max_output_bytes(self.max_output_bytes)
+        .with_cancellation(self.cancel.clone())
+        .with_derapify(self.derapify.clone())
+        .with_transformer(self.transformer.clone())
+        .with_stringtable_conversion(self.convert_stringtables)
+        .with_tar_output(self.tar_target)
+        .with_sink(self.sink.clone())
+        .with_timeout_policy(self.timeout_policy)
+        .with_thread_pool(self.thread_pool().clone());
+        #[cfg(feature = "paa-convert")]
+        let processor = processor.with_paa_conversion(self.convert_paa, self.replace_paa_originals);
+        processor
+    }
+
+    /// Verify the signatures of the listed PBOs, returning the status of each
+    #[cfg(feature = "signatures")]
+    fn verify_signatures(&self, scan_results: &mut [PboScanResult]) -> Vec<(PathBuf, SignatureStatus)> {
+        let verifier = SignatureVerifier::new(self.key_dir.clone());
+        self.thread_pool().install(|| scan_results.par_iter_mut().for_each(|result| {
+            let rel_path = self.relative_path(&result.path);
+            let mod_dir = self.mod_detection.mod_root(&rel_path).map(|root| self.input_dir.join(root));
+            result.signature = Some(verifier.verify(&result.path, mod_dir.as_deref()));
+        }));
 
-        // Process PBOs in parallel
-        let scan_results: Vec<_> = total_pbo_files
+        let mut statuses = Vec::new();
+        for result in scan_results.iter() {
+            let rel_path = self.relative_path(&result.path);
+            let Some(status) = result.signature.clone() else {
+                continue;
+            };
+            match &status {
+                SignatureStatus::Valid(authority) => debug!("{} is signed by {}", rel_path.display(), authority),
+                SignatureStatus::Missing => warn!("{} is not signed", rel_path.display()),
+                SignatureStatus::Invalid(reason) => warn!("Invalid signature on {}: {}", rel_path.display(), reason),
+            }
+            statuses.push((rel_path, status));
+        }
+        statuses
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    fn verify_signatures(&self, _scan_results: &mut [PboScanResult]) -> Vec<(PathBuf, SignatureStatus)> {
+        Vec::new()
+    }
+
+    /// List the given PBOs, paired with the path they are reported under, and
+    /// apply case normalization, name sanitization and reserved name handling
+    fn list_pbos(&self, filter: &FileFilter, pbos: &[(PathBuf, PathBuf)], progress: &dyn ProgressReporter) -> Listing {
+        let listed: Vec<_> = self.thread_pool().install(|| pbos
             .par_iter()
-            .map(|entry| {
-                utils::scan_pbo_contents(entry.path(), self.extensions, self.timeout)
-            })
-            .filter_map(|result| {
-                match result {
-                    Ok(result) => {
-                        debug!("Found {} matching files in {}", result.expected_files.len(), result.path.display());
-                        if !result.expected_files.is_empty() {
-                            trace!("Files to extract from {}: {:?}", result.path.display(), result.expected_files);
-                        }
-                        Some(result)
-                    },
-                    Err(e) => {
-                        warn!("Failed to process PBO: {}", e);
-                        None
-                    }
+            .map(|(path, label)| self.list_pbo(filter, path, label, false, progress))
+            .collect());
+        self.save_listing_cache();
+        self.collect_listing(listed.into_iter().zip(pbos.iter().map(|(_, label)| label.clone())))
+    }
+
+    /// List one PBO for `list_pbos`, or return `None` when the run was cancelled
+    ///
+    /// With `after_extraction`, PBOs whose headers allow it are only scanned, see
+    /// `lists_after_extraction`.
+    fn list_pbo(
+        &self,
+        filter: &FileFilter,
+        path: &Path,
+        label: &Path,
+        after_extraction: bool,
+        progress: &dyn ProgressReporter,
+    ) -> Option<Listed> {
+        if self.cancel.is_cancelled() {
+            return None;
+        }
+        progress.set_message(&label.to_string_lossy());
+        let started = Instant::now();
+        // Retries run on this thread too
+        let runs_before = utils::listing_runs();
+        let waited_before = utils::tool_wait();
+        let result = self.lock_retry.run("Listing", path, || self.retry.run("Listing", path, || {
+            if after_extraction {
+                if let Some(scan_result) = utils::scan_pbo_header(path, self.name_sanitization)? {
+                    return Ok(scan_result);
                 }
-            })
-            .collect();
+            }
+            utils::scan_pbo_with(path, filter, || self.list_contents(path))
+        }));
+        let listing_duration = started.elapsed();
+        let tool_runs = utils::listing_runs() - runs_before;
+        let tool_wait = utils::tool_wait() - waited_before;
+        let result = result.map(|scan_result| PboScanResult {
+            listing_duration,
+            ..scan_result
+        });
+        match &result {
+            Ok(scan_result) => self.events.send(ExtractionEvent::PboScanned {
+                path: label.to_owned(),
+                matching: scan_result.expected_files.len(),
+            }),
+            Err(e) if is_lock_error(e) => self.events.send(ExtractionEvent::PboSkipped {
+                path: label.to_owned(),
+                reason: SkipReason::Locked,
+            }),
+            Err(e) => {
+                self.events.send(ExtractionEvent::PboScanFailed {
+                    path: label.to_owned(),
+                    error: format!("{:#}", e),
+                });
+                progress.inc_failed(1);
+            },
+        }
+        progress.inc(1);
+        Some(Listed { result, duration: listing_duration, tool_runs, tool_wait })
+    }
 
-        debug!("PBO scan complete:");
-        debug!("  Total PBOs scanned: {}", scan_results.len());
+    /// Fail with `ExtractionError::BackendUnavailable` if the tool can't be started,
+    /// once every listing failed
+    ///
+    /// The tool is started again, since it may have gone missing after the check the
+    /// run started with, and every PBO failing then says nothing about the PBOs.
+    fn check_tool_after_failures(&self) -> Result<()> {
+        if !self.skip_backend_check {
+            backend::check_tool_starts()?;
+        }
+        Ok(())
+    }
 
-        // Process PBOs for extraction
-        debug!("Starting extraction from {} PBOs", scan_results.len());
-        processor.process_all(&scan_results)?;
+    /// Whether `run` can leave PBOs unlisted, taking their expected files from the
+    /// extraction instead
+    ///
+    /// Only while every entry is extracted and nothing needs the entries up front:
+    /// resuming and the other overwrite policies compare them with the output,
+    /// adopting and conflict detection look at them, nested PBOs are found among
+    /// them and archives are verified against them.
+    fn lists_after_extraction(&self, filter: &FileFilter) -> bool {
+        filter.matches_everything()
+            && !self.resume
+            && self.overwrite == OverwritePolicy::Overwrite
+            && !self.adopt_existing
+            && !self.detect_conflicts
+            && !self.recursive
+            && self.tar_target.is_none()
+    }
 
-        Ok(())
+    /// Listing of the PBO at `path`, from the listing cache while the PBO is unchanged
+    fn list_contents(&self, path: &Path) -> Result<PboContents> {
+        let cached = self.listing_cache.as_ref().zip(path.strip_prefix(self.input_dir).ok());
+        let Some((cache, rel_path)) = cached else {
+            return self.run_listing(path);
+        };
+        // Hashed first, so a PBO changing while it's listed is listed again next time.
+        // The fast hash misses changes past the first bytes that keep size and mtime
+        let hash = crate::utils::sha256_file(path)?;
+        if let Some(contents) = cache.get(rel_path, &hash) {
+            trace!("Using the cached listing of {}", path.display());
+            return Ok(contents);
+        }
+        let contents = self.run_listing(path)?;
+        cache.insert(rel_path, hash, contents.clone());
+        Ok(contents)
+    }
+
+    /// List the PBO at `path` with the tool, holding a permit of the I/O limit
+    fn run_listing(&self, path: &Path) -> Result<PboContents> {
+        let _permit = self.io_limit.as_deref().map(ConcurrencyLimit::acquire);
+        utils::list_pbo_contents(path, self.list_timeout)
+    }
+
+    /// Write the listings made so far to the listing cache, if it's enabled
+    fn save_listing_cache(&self) {
+        if let Some(cache) = &self.listing_cache {
+            if let Err(e) = cache.save() {
+                warn!("Failed to save the listing cache: {:#}", e);
+            }
+        }
+    }
+
+    /// Gather the results of `list_pbo` with the labels of their PBOs, in the order
+    /// the PBOs are processed in
+    fn collect_listing(&self, listed: impl IntoIterator<Item = (Option<Listed>, PathBuf)>) -> Listing {
+        let mut listing = Listing {
+            scan_results: Vec::new(),
+            failed: Vec::new(),
+            locked: Vec::new(),
+            not_listed: Vec::new(),
+            case_collisions: Vec::new(),
+            reserved_names: Vec::new(),
+            duration: Duration::ZERO,
+            tool_runs: 0,
+            tool_wait: Duration::ZERO,
+        };
+        for (listed, label) in listed {
+            let Some(listed) = listed else {
+                listing.not_listed.push(label.clone());
+                continue;
+            };
+            listing.duration += listed.duration;
+            listing.tool_runs += listed.tool_runs;
+            listing.tool_wait += listed.tool_wait;
+            let mut result = match listed.result {
+                Ok(result) => result,
+                // Left for a later run, like a PBO locked while extracting it
+                Err(e) if is_lock_error(&e) => {
+                    warn!("Locked by another process, leaving for a later run: {}: {}", label.display(), e);
+                    listing.locked.push(label.clone());
+                    continue;
+                },
+                Err(e) => {
+                    warn!("Failed to process PBO: {:#}", e);
+                    listing.failed.push(PboFailure::new(label.clone(), &e));
+                    continue;
+                },
+            };
+            result.mod_name = self.mod_detection.mod_name(&label);
+
+            if self.normalize_case {
+                let collisions = utils::lowercase_scan_result(&mut result);
+                if !collisions.is_empty() {
+                    warn!(
+                        "Dropping {} entries of {} that differ from others only by case: {:?}",
+                        collisions.len(),
+                        result.path.display(),
+                        collisions,
+                    );
+                    listing.case_collisions.push((label.clone(), collisions));
+                }
+            }
+            utils::sanitize_scan_result(&mut result, self.name_sanitization);
+            utils::handle_reserved_names(&mut result, self.reserved_names);
+            if !result.reserved_names.is_empty() {
+                warn!(
+                    "{} entries of {} use Windows device names ({}): {:?}",
+                    result.reserved_names.len(),
+                    result.path.display(),
+                    match self.reserved_names {
+                        ReservedNameHandling::Skip => "skipped",
+                        ReservedNameHandling::Rename => "renamed",
+                    },
+                    result.reserved_names,
+                );
+                listing.reserved_names.push((label.clone(), result.reserved_names.clone()));
+            }
+            if !result.renamed.is_empty() {
+                debug!("Renamed entries of {}: {:?}", result.path.display(), result.renamed);
+            }
+            if result.listed_after_extraction {
+                debug!("Expecting whatever extracting {} writes", result.path.display());
+            } else {
+                debug!("Found {} matching files in {}", result.expected_files.len(), result.path.display());
+            }
+            if !result.expected_files.is_empty() {
+                trace!("Files to extract from {}: {:?}", result.path.display(), result.expected_files);
+            }
+            listing.scan_results.push(result);
+        }
+        listing
+    }
+
+    /// Metadata of the mods of the given PBOs, relative to the input directory, by mod name
+    ///
+    /// Read once per mod folder; a name shared by two folders takes the first one's.
+    fn mod_metadata<'p>(&self, rel_paths: impl IntoIterator<Item = &'p PathBuf>) -> BTreeMap<String, ModMetadata> {
+        let mut metadata = BTreeMap::new();
+        for rel_path in rel_paths {
+            let Some(root) = self.mod_detection.mod_root(rel_path) else {
+                continue;
+            };
+            let name = root.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            metadata.entry(name).or_insert_with(|| utils::read_mod_metadata(&self.input_dir.join(&root)));
+        }
+        for (name, mod_metadata) in &metadata {
+            debug!("Mod {}: {:?}", name, mod_metadata);
+        }
+        metadata
     }
-}
\ No newline at end of file
+
+    /// Bring the index in the cache directory up to date with the outputs of
+    /// `scan_results`, returning the number of indexed files
+    ///
+    /// Entries of PBOs no longer in the input directory are dropped; those of PBOs
+    /// that weren't extracted again are kept as they were.
+    fn update_index(
+        &self,
+        processor: &PboProcessor,
+        scan_results: &[PboScanResult],
+        mod_metadata: &BTreeMap<String, ModMetadata>,
+        released: &[PathBuf],
+        report: &mut ExtractionReport,
+    ) -> Result<usize> {
+        let path = self.cache_dir.join(INDEX_FILE_NAME);
+        let mut index = if path.exists() {
+            ExtractionIndex::load(&path).unwrap_or_else(|e| {
+                warn!("Rebuilding index: {:#}", e);
+                ExtractionIndex::new(self.cache_dir)
+            })
+        } else {
+            ExtractionIndex::new(self.cache_dir)
+        };
+
+        // Changed since quarantined, so their failures start over
+        for source in released {
+            self.release(&mut index, source);
+        }
+
+        let failed: HashSet<PathBuf> = report.failures.iter()
+            .chain(&report.scan_failures)
+            .map(|failure| failure.path.clone())
+            .collect();
+        let processed: HashSet<&Path> = report.outcomes.iter().map(|outcome| outcome.path.as_path()).collect();
+        let mut outputs = Vec::new();
+        let mut previously_failed = 0;
+        for result in scan_results {
+            let Ok((base_dir, _)) = processor.output_dirs(result) else {
+                continue;
+            };
+            let mod_name = result.mod_name.as_ref()
+                .and_then(|mod_name| mod_metadata.get(mod_name))
+                .map(|metadata| metadata.name.clone());
+            let rel_path = self.relative_path(&result.path);
+            if index.failed.contains_key(&rel_path) && processed.contains(rel_path.as_path()) {
+                previously_failed += 1;
+            }
+            // Failures stay recorded, so their attempts add up across runs
+            if !report.not_processed.contains(&rel_path) && !failed.contains(&rel_path) {
+                index.clear_failure(&rel_path);
+                if index.quarantined.contains_key(&rel_path) {
+                    self.release(&mut index, &rel_path);
+                }
+            }
+            outputs.push((rel_path, base_dir, mod_name));
+        }
+        let reused = apply_source_updates(&mut index, &outputs, self.thread_pool())?;
+        let now = unix_now();
+        // Nested PBOs fail under labels outside the input directory, so these drop them again
+        let hash = |failure: &PboFailure| crate::utils::calculate_file_hash(&self.input_dir.join(&failure.path)).ok();
+        for failure in &report.scan_failures {
+            index.record_failure(failure, FailureStage::Listing, hash(failure), now);
+        }
+        for failure in &report.failures {
+            index.record_failure(failure, FailureStage::Extraction, hash(failure), now);
+        }
+        if let Some(policy) = &self.quarantine {
+            for source in index.due_for_quarantine(policy.after_failures) {
+                let pbo = self.input_dir.join(&source);
+                if !pbo.is_file() {
+                    continue;
+                }
+                let hash = match crate::utils::calculate_file_hash(&pbo) {
+                    Ok(hash) => hash,
+                    Err(e) => {
+                        warn!("Not quarantining {}: {}", source.display(), e);
+                        continue;
+                    }
+                };
+                let copy = processor.failure_log_path(&pbo)
+                    .and_then(|failure_log| policy.copy(&pbo, &source, &failure_log, self.cache_dir))
+                    .unwrap_or_else(|e| {
+                        warn!("Quarantining {} without a copy: {:#}", source.display(), e);
+                        None
+                    });
+                warn!(
+                    "Quarantined {} after {} failed runs; it's skipped until it changes",
+                    source.display(),
+                    index.failed[&source].attempts
+                );
+                index.quarantine(&source, hash, copy, now);
+                report.newly_quarantined.push(source);
+            }
+        }
+        report.run_stats.previously_failed = previously_failed;
+        index.retain_sources(|source| self.input_dir.join(source).is_file());
+        index.write(&path)?;
+        report.index_stats = Some(index.stats());
+        debug!(
+            "Indexed {} files from {} PBOs ({} unchanged)",
+            index.files.len(),
+            index.sources.len(),
+            reused
+        );
+        Ok(index.files.len())
+    }
+
+    fn run_config(&self) -> RunConfig {
+        RunConfig {
+            input_dir: self.input_dir.to_owned(),
+            cache_dir: self.cache_dir.to_owned(),
+            extensions: self.filter.extensions.clone(),
+            include_paths: self.filter.include_paths.clone(),
+            threads: self.threads,
+            list_timeout_secs: self.list_timeout,
+            extract_timeout: self.timeout_policy,
+            fallback_chain: self.fallback_chain.clone(),
+        }
+    }
+
+    /// Lift the quarantine of `source` in `index` and remove its copy
+    fn release(&self, index: &mut ExtractionIndex, source: &Path) {
+        let Some(quarantined) = index.release(source) else {
+            return;
+        };
+        info!("Lifted the quarantine of {}", source.display());
+        if let Some(copy) = &quarantined.copy {
+            if let Err(e) = remove_copy(self.cache_dir, copy) {
+                warn!("Failed to remove quarantine copy of {}: {:#}", source.display(), e);
+            }
+        }
+    }
+
+    /// Whether the extraction index is updated, because it's asked for, because it
+    /// keeps the failures other options decide on, or to record adopted outputs
+    fn tracks_index(&self) -> bool {
+        self.build_index || self.quarantine.is_some() || self.max_attempts.is_some() || self.adopt_existing
+    }
+
+    /// Fail `operation` on outputs that aren't directories, which tar and sink outputs replace
+    fn check_directory_outputs(&self, operation: &str) -> Result<()> {
+        if self.tar_target.is_some() || self.sink.is_some() {
+            anyhow::bail!("{} needs directory outputs, not tar archives or a sink", operation);
+        }
+        Ok(())
+    }
+
+    /// Record the existing output of a listed PBO as its extraction, when every
+    /// expected file is there with the listed size
+    ///
+    /// Writes the manifest an extraction would have, listing every file in the base
+    /// directory. Returns whether the output was adopted; one already recorded for
+    /// the PBO's current hash counts as adopted.
+    fn adopt(&self, filter: &FileFilter, processor: &PboProcessor, scan_result: &PboScanResult) -> Result<bool> {
+        if scan_result.expected_files.is_empty() || processor.has_output_conflict(&scan_result.path) {
+            return Ok(false);
+        }
+        let (base_dir, output_dir) = processor.output_dirs(scan_result)?;
+        let existing = utils::existing_entries(&output_dir, &scan_result.expected_files);
+        if !existing.missing.is_empty() || !existing.changed.is_empty() {
+            debug!(
+                "Not adopting {}: {} files missing, {} with another size",
+                scan_result.path.display(),
+                existing.missing.len(),
+                existing.changed.len()
+            );
+            return Ok(false);
+        }
+
+        let hash = crate::utils::calculate_file_hash(&scan_result.path)?;
+        let recorded = ExtractionManifest::read(&base_dir)
+            .is_ok_and(|manifest| !manifest.streamed && manifest.hash == hash && manifest.extensions == filter.extensions);
+        if !recorded {
+            let mut manifest = ExtractionManifest::collect(
+                &base_dir,
+                &scan_result.path,
+                hash,
+                scan_result.prefix.clone(),
+                &filter.extensions,
+            )?;
+            manifest.renamed = scan_result.renamed.clone();
+            manifest.write(&base_dir)?;
+        }
+        debug!("Adopted the existing output of {} in {}", scan_result.path.display(), base_dir.display());
+        Ok(true)
+    }
+
+    /// Adopt the existing outputs of the listed PBOs, returning the adopted PBOs
+    fn adopt_listed(&self, filter: &FileFilter, processor: &PboProcessor, scan_results: &[PboScanResult]) -> HashSet<PathBuf> {
+        self.thread_pool().install(|| scan_results
+            .par_iter()
+            .filter(|scan_result| scan_result.skip_reason.is_none() && !self.cancel.is_cancelled())
+            .filter_map(|scan_result| match self.adopt(filter, processor, scan_result) {
+                Ok(true) => Some(scan_result.path.clone()),
+                Ok(false) => None,
+                Err(e) => {
+                    warn!("Not adopting the output of {}: {:#}", scan_result.path.display(), e);
+                    None
+                },
+            })
+            .collect())
+    }
+
+    /// Record the PBO's current hash in the manifest of its output, if that output is
+    /// complete
+    ///
+    /// Only outputs whose manifest records an extraction with the current extension
+    /// filter count, with every expected file there with the listed size.
+    fn touch(&self, filter: &FileFilter, processor: &PboProcessor, scan_result: &PboScanResult) -> Result<Touched> {
+        if scan_result.skip_reason.is_some() || scan_result.expected_files.is_empty() {
+            return Ok(Touched::Skipped);
+        }
+        if self.cancel.is_cancelled() || processor.has_output_conflict(&scan_result.path) {
+            return Ok(Touched::Pending);
+        }
+        let (base_dir, output_dir) = processor.output_dirs(scan_result)?;
+        let mut manifest = match ExtractionManifest::read(&base_dir) {
+            Ok(manifest) if !manifest.streamed && manifest.extensions == filter.extensions => manifest,
+            _ => return Ok(Touched::Pending),
+        };
+        let existing = utils::existing_entries(&output_dir, &scan_result.expected_files);
+        if !existing.missing.is_empty() || !existing.changed.is_empty() {
+            debug!(
+                "Not refreshing the hash of {}: {} files missing, {} with another size",
+                scan_result.path.display(),
+                existing.missing.len(),
+                existing.changed.len()
+            );
+            return Ok(Touched::Pending);
+        }
+
+        let hash = crate::utils::calculate_file_hash(&scan_result.path)?;
+        if manifest.hash == hash {
+            return Ok(Touched::Current);
+        }
+        debug!("Refreshing the hash of {}: {} -> {}", scan_result.path.display(), manifest.hash, hash);
+        manifest.hash = hash;
+        manifest.write(&base_dir)?;
+        Ok(Touched::Refreshed)
+    }
+
+    /// Check the output of an indexed PBO against its listing, when it could be
+    /// listed, and against its manifest and checksums
+    fn check_output(&self, processor: &PboProcessor, pbo: &Path, scan_result: Option<&PboScanResult>) -> CacheDiscrepancy {
+        let mut discrepancy = CacheDiscrepancy { path: self.relative_path(pbo), ..Default::default() };
+        let base_dir = match processor.base_output_dir(pbo) {
+            Ok(base_dir) => base_dir,
+            Err(e) => {
+                discrepancy.error = Some(format!("{:#}", e));
+                return discrepancy;
+            },
+        };
+        let manifest = match ExtractionManifest::read(&base_dir) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                discrepancy.error = Some(format!("{:#}", e));
+                return discrepancy;
+            },
+        };
+        // Nothing was kept on disk
+        if manifest.streamed {
+            return discrepancy;
+        }
+
+        let check = manifest.check(&base_dir);
+        let mut missing: BTreeSet<String> = check.missing.into_iter().collect();
+        let mut changed: BTreeSet<String> = check.changed.into_iter().collect();
+        if let Some(scan_result) = scan_result {
+            match processor.output_dirs(scan_result) {
+                Ok((_, output_dir)) => {
+                    let prefix = crate::utils::normalize_entry_path(scan_result.prefix.as_deref().unwrap_or_default());
+                    let base_path = |entry: &PboEntry| match prefix.trim_end_matches('/') {
+                        "" => entry.path.clone(),
+                        prefix => format!("{}/{}", prefix, entry.path),
+                    };
+                    let existing = utils::existing_entries(&output_dir, &scan_result.expected_files);
+                    missing.extend(existing.missing.iter().map(base_path));
+                    changed.extend(existing.changed.iter().map(base_path));
+                },
+                Err(e) => discrepancy.error = Some(format!("{:#}", e)),
+            }
+        }
+        discrepancy.missing = missing.into_iter().collect();
+        discrepancy.changed = changed.into_iter().collect();
+
+        if base_dir.join(crate::utils::CHECKSUM_FILE_NAME).is_file() {
+            match crate::utils::verify_checksums(&base_dir) {
+                Ok(mismatches) => {
+                    discrepancy.checksum_mismatches = mismatches.into_iter().map(|mismatch| mismatch.path).collect();
+                },
+                Err(e) => discrepancy.error = Some(format!("{:#}", e)),
+            }
+        }
+        discrepancy
+    }
+
+    /// Re-check the output of every PBO the extraction index records as extracted
+    ///
+    /// Each PBO is listed again, and its output checked for the expected files with
+    /// their listed size, for the files its manifest recorded, and against the
+    /// checksums written with it, if any. PBOs that can't be listed are checked
+    /// against their manifest only. Broken outputs are recorded as failed in the
+    /// index with `FailureStage::Verification`, so the next run extracts them in
+    /// full, even when resuming.
+    pub fn verify_cache(&self) -> Result<CacheVerification> {
+        self.check_directory_outputs("Verifying the cache")?;
+        let index_path = self.cache_dir.join(INDEX_FILE_NAME);
+        let mut index = ExtractionIndex::load(&index_path)?;
+
+        let mut report = CacheVerification::default();
+        let mut pbos = Vec::new();
+        for source in index.sources.keys() {
+            let pbo = self.input_dir.join(source);
+            if pbo.is_file() {
+                pbos.push((pbo, source.clone()));
+            } else {
+                report.gone.push(source.clone());
+            }
+        }
+        report.checked = pbos.len();
+
+        // Output directories are planned like a run plans them
+        let filter = self.scan_filter();
+        let rel_paths: Vec<_> = self.discover_pbos()?.pbos
+            .iter()
+            .map(|entry| self.relative_path(entry.path()))
+            .filter(|rel_path| self.pbo_filter.matches(rel_path))
+            .collect();
+        let (output_dirs, _) = self.plan_output_dirs(&output_collisions(&rel_paths));
+        let processor = self.processor(&filter, self.progress.extraction.clone()).with_output_dirs(output_dirs);
+
+        self.progress.listing.set_total(pbos.len() as u64);
+        let listing = self.list_pbos(&filter, &pbos, self.progress.listing.as_ref());
+        self.progress.listing.finish();
+        if listing.scan_results.is_empty() && !listing.failed.is_empty() {
+            self.check_tool_after_failures()?;
+        }
+        let scan_results: HashMap<&Path, &PboScanResult> = listing.scan_results
+            .iter()
+            .map(|scan_result| (scan_result.path.as_path(), scan_result))
+            .collect();
+
+        self.progress.extraction.set_total(pbos.len() as u64);
+        let checked: Vec<_> = self.thread_pool().install(|| pbos
+            .par_iter()
+            .filter(|(_, source)| !listing.not_listed.contains(source))
+            .map(|(pbo, _)| {
+                let discrepancy = self.check_output(&processor, pbo, scan_results.get(pbo.as_path()).copied());
+                self.progress.extraction.inc(1);
+                discrepancy
+            })
+            .collect());
+        self.progress.extraction.finish();
+
+        let now = unix_now();
+        for discrepancy in checked {
+            if discrepancy.is_ok() {
+                continue;
+            }
+            warn!("Broken output of {}", discrepancy);
+            index.mark_broken(&discrepancy.path, format!("Broken output: {}", discrepancy.problems()), now);
+            report.broken.push(discrepancy);
+        }
+        report.broken.sort_by(|a, b| a.path.cmp(&b.path));
+        report.scan_failures = listing.failed;
+        index.write(&index_path)?;
+        info!("{}", report);
+        Ok(report)
+    }
+
+    /// Record the outputs already in the cache directory, like those of another
+    /// tool, as the extractions of their PBOs without extracting anything
+    ///
+    /// Every PBO is hashed and listed; when each of its expected files exists in its
+    /// output directory with the listed size, it gets the manifest and index entry an
+    /// extraction would have given it. Other PBOs are left for an extraction run.
+    pub fn adopt_existing(&self) -> Result<AdoptionReport> {
+        self.check_directory_outputs("Adopting existing outputs")?;
+        if !self.input_dir.exists() {
+            return Err(ExtractionError::InputDirNotFound(self.input_dir.to_owned()).into());
+        }
+        std::fs::create_dir_all(self.cache_dir)?;
+
+        let filter = self.scan_filter();
+        let discovery = self.discover_pbos()?;
+        let discovered = discovery.pbos.len();
+        let pbos = self.selected_pbos(&discovery.pbos);
+
+        let rel_paths: Vec<_> = pbos.iter().map(|(_, rel_path)| rel_path.clone()).collect();
+        let (output_dirs, output_conflicts) = self.plan_output_dirs(&output_collisions(&rel_paths));
+        let processor = self.processor(&filter, self.progress.extraction.clone())
+            .with_output_dirs(output_dirs)
+            .with_output_conflicts(output_conflicts);
+
+        self.progress.listing.set_total(pbos.len() as u64);
+        let listing = self.list_pbos(&filter, &pbos, self.progress.listing.as_ref());
+        self.progress.listing.finish();
+        let adopted = self.adopt_listed(&filter, &processor, &listing.scan_results);
+
+        let mut report = AdoptionReport {
+            discovered,
+            scan_failures: listing.failed,
+            pending: listing.not_listed,
+            ..Default::default()
+        };
+        report.pending.extend(listing.locked);
+        let mut adopted_results = Vec::new();
+        for scan_result in listing.scan_results {
+            let rel_path = self.relative_path(&scan_result.path);
+            if adopted.contains(&scan_result.path) {
+                report.adopted.push(rel_path);
+                adopted_results.push(scan_result);
+            } else if scan_result.skip_reason.is_some() || scan_result.expected_files.is_empty() {
+                report.skipped.push(rel_path);
+            } else {
+                report.pending.push(rel_path);
+            }
+        }
+        report.pending.sort();
+
+        let mod_metadata = self.mod_metadata(&report.adopted);
+        report.indexed_files =
+            self.update_index(&processor, &adopted_results, &mod_metadata, &[], &mut ExtractionReport::default())?;
+        info!("{}", report);
+        Ok(report)
+    }
+
+    /// Refresh the hash recorded for PBOs whose output is complete, without extracting
+    ///
+    /// Meant for PBOs downloaded again with the same contents, whose hash changed with
+    /// their modification time. The manifests and index entries of these PBOs get
+    /// their current hash, so runs no longer extract them again; PBOs with missing or
+    /// differently sized output files are left as they are, for the next run to extract.
+    pub fn touch_hashes(&self) -> Result<TouchReport> {
+        self.check_directory_outputs("Refreshing hashes")?;
+        if !self.input_dir.exists() {
+            return Err(ExtractionError::InputDirNotFound(self.input_dir.to_owned()).into());
+        }
+
+        let filter = self.scan_filter();
+        let discovery = self.discover_pbos()?;
+        let discovered = discovery.pbos.len();
+        let pbos = self.selected_pbos(&discovery.pbos);
+
+        let rel_paths: Vec<_> = pbos.iter().map(|(_, rel_path)| rel_path.clone()).collect();
+        let (output_dirs, output_conflicts) = self.plan_output_dirs(&output_collisions(&rel_paths));
+        let processor = self.processor(&filter, self.progress.extraction.clone())
+            .with_output_dirs(output_dirs)
+            .with_output_conflicts(output_conflicts);
+
+        self.progress.listing.set_total(pbos.len() as u64);
+        let listing = self.list_pbos(&filter, &pbos, self.progress.listing.as_ref());
+        self.progress.listing.finish();
+        let touched: Vec<Touched> = self.thread_pool().install(|| listing.scan_results
+            .par_iter()
+            .map(|scan_result| {
+                self.touch(&filter, &processor, scan_result).unwrap_or_else(|e| {
+                    warn!("Not refreshing the hash of {}: {:#}", scan_result.path.display(), e);
+                    Touched::Pending
+                })
+            })
+            .collect());
+
+        let mut report = TouchReport {
+            discovered,
+            scan_failures: listing.failed,
+            pending: listing.not_listed,
+            ..Default::default()
+        };
+        report.pending.extend(listing.locked);
+        let mut refreshed_results = Vec::new();
+        for (scan_result, touched) in listing.scan_results.into_iter().zip(touched) {
+            let rel_path = self.relative_path(&scan_result.path);
+            match touched {
+                Touched::Refreshed => {
+                    report.refreshed.push(rel_path);
+                    refreshed_results.push(scan_result);
+                },
+                Touched::Current => report.current.push(rel_path),
+                Touched::Pending => report.pending.push(rel_path),
+                Touched::Skipped => report.skipped.push(rel_path),
+            }
+        }
+        report.pending.sort();
+
+        if !refreshed_results.is_empty() {
+            let mod_metadata = self.mod_metadata(&report.refreshed);
+            report.indexed_files =
+                self.update_index(&processor, &refreshed_results, &mod_metadata, &[], &mut ExtractionReport::default())?;
+        }
+        info!("{}", report);
+        Ok(report)
+    }
+
+    /// PBOs whose output a cache verification found broken, to extract in full
+    ///
+    /// Only matters when existing files may be kept, by resuming or the overwrite
+    /// policy, since other runs extract everything anyway.
+    fn broken_outputs(&self) -> HashSet<PathBuf> {
+        let path = self.cache_dir.join(INDEX_FILE_NAME);
+        if (!self.resume && self.overwrite == OverwritePolicy::Overwrite) || !path.exists() {
+            return HashSet::new();
+        }
+        let Ok(index) = ExtractionIndex::load(&path) else {
+            return HashSet::new();
+        };
+        index.failed
+            .iter()
+            .filter(|(_, failed)| failed.stage == FailureStage::Verification)
+            .map(|(source, _)| self.input_dir.join(source))
+            .collect()
+    }
+
+    /// Index to look up PBOs that are quarantined or were given up on, when they
+    /// are skipped at all
+    fn failure_index(&self) -> Option<ExtractionIndex> {
+        let max_attempts = self.max_attempts.filter(|_| !self.force_retry);
+        let path = self.cache_dir.join(INDEX_FILE_NAME);
+        if (self.quarantine.is_none() && max_attempts.is_none()) || !path.exists() {
+            return None;
+        }
+        ExtractionIndex::load(&path).ok()
+    }
+
+    /// Whether to skip a PBO that is quarantined or was given up on, unless it changed
+    ///
+    /// PBOs skipped or released are recorded in `known`.
+    fn is_known_failure(&self, index: &ExtractionIndex, entry: &DirEntry, known: &mut KnownFailures) -> bool {
+        let max_attempts = self.max_attempts.filter(|_| !self.force_retry);
+        let rel_path = self.relative_path(entry.path());
+        let quarantined = self.quarantine.is_some() && index.quarantined.contains_key(&rel_path);
+        let failed = max_attempts.is_some() && index.failed.contains_key(&rel_path);
+        if !quarantined && !failed {
+            return false;
+        }
+        let hash = crate::utils::calculate_file_hash(entry.path()).ok();
+        let hash = hash.as_deref().unwrap_or_default();
+        if quarantined {
+            if index.is_quarantined(&rel_path, hash) {
+                debug!("Skipping quarantined PBO: {}", rel_path.display());
+                known.quarantined.push(rel_path);
+                return true;
+            }
+            debug!("Changed since it was quarantined: {}", rel_path.display());
+            known.released.push(rel_path);
+        } else if max_attempts.is_some_and(|max| index.is_given_up(&rel_path, hash, max)) {
+            debug!("Giving up on PBO failing every attempt: {}", rel_path.display());
+            known.gave_up.push(rel_path);
+            return true;
+        }
+        false
+    }
+
+    /// PBOs extracted into the outputs of `scan_results` by `processor`
+    fn nested_pbos(
+        &self,
+        processor: &PboProcessor,
+        scan_results: &[PboScanResult],
+        labels: &HashMap<PathBuf, PathBuf>,
+    ) -> Vec<NestedPbo> {
+        let mut nested = Vec::new();
+        for result in scan_results {
+            let Ok((_, output_dir)) = processor.output_dirs(result) else {
+                continue;
+            };
+            let parent = labels.get(&result.path).cloned().unwrap_or_else(|| self.relative_path(&result.path));
+            for entry in &result.expected_files {
+                let is_pbo = Path::new(&entry.path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pbo"));
+                let Ok(rel_path) = crate::utils::native_entry_path(&entry.path) else {
+                    continue;
+                };
+                let path = output_dir.join(rel_path);
+                if is_pbo && path.is_file() {
+                    nested.push(NestedPbo {
+                        base_dir: path.with_extension(""),
+                        label: PathBuf::from(format!("{}!{}", parent.display(), entry.path)),
+                        path,
+                    });
+                }
+            }
+        }
+        nested
+    }
+
+    /// Extract PBOs found in the outputs of `scan_results`, one nesting level at a time
+    ///
+    /// Nested PBOs go through the same listing, filters and skip logic as the others,
+    /// extracting into a directory next to them named after the PBO. Their outcomes
+    /// are counted separately in the report.
+    fn extract_nested(
+        &self,
+        filter: &FileFilter,
+        processor: &PboProcessor,
+        scan_results: &[PboScanResult],
+        report: &mut ExtractionReport,
+    ) -> Result<()> {
+        let mut quota_used = processor.output_bytes();
+        let mut nested = self.nested_pbos(processor, scan_results, &HashMap::new());
+
+        for depth in 1..=self.max_nesting_depth {
+            if nested.is_empty() || report.quota_exceeded || report.aborted {
+                break;
+            }
+            debug!("Extracting {} nested PBOs at depth {}", nested.len(), depth);
+            for pbo in &nested {
+                self.events.send(ExtractionEvent::PboDiscovered { path: pbo.label.clone() });
+            }
+
+            let pbos: Vec<_> = nested.iter().map(|pbo| (pbo.path.clone(), pbo.label.clone())).collect();
+            let listing = self.list_pbos(filter, &pbos, &NoopProgress);
+            let labels: HashMap<_, _> = pbos.into_iter().collect();
+            let level = self.processor(filter, Arc::new(NoopProgress))
+                .with_output_dirs(nested.into_iter().map(|pbo| (pbo.path, pbo.base_dir)).collect())
+                .with_path_labels(labels.clone())
+                .with_failure_tracker(processor.failure_tracker())
+                .with_max_output_bytes(self.max_output_bytes.map(|max| max.saturating_sub(quota_used)));
+            let level_report = level.process_all(&listing.scan_results)?;
+            quota_used += level.output_bytes();
+
+            report.nested_discovered += labels.len();
+            report.nested_extracted += level_report.extracted;
+            report.nested_skipped += level_report.skipped + listing.locked.len();
+            report.locked.extend(listing.locked);
+            report.nested_failed += level_report.failed + listing.failed.len();
+            report.scan_failures.extend(listing.failed);
+            report.quota_exceeded |= level_report.quota_exceeded;
+            report.interrupted |= level_report.interrupted || !listing.not_listed.is_empty();
+            report.aborted |= level_report.aborted;
+            report.failures.extend(level_report.failures);
+            report.not_processed.extend(level_report.not_processed);
+            report.not_processed.extend(listing.not_listed);
+            report.fallback_extractions.extend(level_report.fallback_extractions);
+            report.pruned_files += level_report.pruned_files;
+            report.missing_files.extend(level_report.missing_files);
+            report.stale_files.extend(level_report.stale_files);
+            report.converted_configs += level_report.converted_configs;
+            report.unconverted_configs.extend(level_report.unconverted_configs);
+            report.transform_failures.extend(level_report.transform_failures);
+            report.stringtables.extend(level_report.stringtables);
+            report.stringtable_errors.extend(level_report.stringtable_errors);
+            report.converted_textures += level_report.converted_textures;
+            report.unconverted_textures.extend(level_report.unconverted_textures);
+            report.archived_bytes += level_report.archived_bytes;
+            report.archive_bytes += level_report.archive_bytes;
+            report.streamed_files += level_report.streamed_files;
+            report.throughput.add(&level_report.throughput);
+            report.throughput.listing_ms += listing.duration.as_millis() as u64;
+            report.throughput.listing_runs += listing.tool_runs;
+            report.throughput.tool_wait_ms += listing.tool_wait.as_millis() as u64;
+            report.case_collisions.extend(listing.case_collisions);
+            report.reserved_names.extend(listing.reserved_names);
+
+            nested = self.nested_pbos(&level, &listing.scan_results, &labels);
+        }
+
+        if !nested.is_empty() && !report.quota_exceeded {
+            warn!(
+                "Not extracting {} PBOs nested deeper than {} levels: {:?}",
+                nested.len(),
+                self.max_nesting_depth,
+                nested.iter().map(|pbo| &pbo.label).collect::<Vec<_>>()
+            );
+            report.nesting_limited.extend(nested.into_iter().map(|pbo| pbo.label));
+        }
+        Ok(())
+    }
+
+    /// Fail when `required` bytes don't fit in the free space of the cache directory
+    fn check_disk_space(&self, required: u64) -> Result<()> {
+        if self.ignore_disk_space || required == 0 {
+            return Ok(());
+        }
+
+        let available = match crate::utils::available_space(self.cache_dir) {
+            Ok(available) => available,
+            Err(e) => {
+                warn!("Not checking free disk space: {:#}", e);
+                return Ok(());
+            },
+        };
+        debug!("Free space in {}: {}", self.cache_dir.display(), HumanBytes(available));
+
+        if required > available {
+            return Err(anyhow::anyhow!(
+                "Extraction needs an estimated {} in {}, but only {} are free; \
+                 free up space or set ignore_disk_space to run anyway",
+                HumanBytes(required),
+                self.cache_dir.display(),
+                HumanBytes(available)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove staging directories left behind by interrupted runs
+    ///
+    /// Runs must not share a cache directory, since this also removes the
+    /// staging directories of a concurrent run.
+    fn clean_staging_dirs(&self) -> Result<usize> {
+        let staging_root = crate::utils::long_path(&self.cache_dir.join(STAGING_DIR_NAME));
+        if !staging_root.is_dir() {
+            return Ok(0);
+        }
+
+        let mut cleaned = 0;
+        for entry in std::fs::read_dir(&staging_root)? {
+            let path = entry?.path();
+            info!("Removing leftover staging directory: {}", path.display());
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+            cleaned += 1;
+        }
+        std::fs::remove_dir(&staging_root)?;
+
+        Ok(cleaned)
+    }
+
+    /// Remove output directories of the given PBOs that have no extraction manifest
+    ///
+    /// Successful extractions always write a manifest, so a directory without one
+    /// was left partial by a run predating staged extraction or by manual edits.
+    fn clean_partial_outputs(&self, processor: &PboProcessor, pbos: &[DirEntry]) -> Result<usize> {
+        if !self.write_manifest {
+            warn!("Cleaning partial outputs needs extraction manifests, which are disabled; skipping");
+            return Ok(0);
+        }
+
+        let mut cleaned = 0;
+        for entry in pbos {
+            let base_dir = processor.base_output_dir(entry.path())?;
+            if base_dir.is_dir() && !base_dir.join(MANIFEST_FILE_NAME).is_file() {
+                info!("Removing partial output without manifest: {}", base_dir.display());
+                std::fs::remove_dir_all(&base_dir)?;
+                cleaned += 1;
+            }
+        }
+
+        Ok(cleaned)
+    }
+
+    /// Scan the input directory and extract what is new or changed
+    ///
+    /// Ends with a `RunFinished` event, also when the run fails.
+    pub async fn run(&self) -> Result<ExtractionReport> {
+        let started = Instant::now();
+        let result = self.run_extraction(started).await;
+        let event = match &result {
+            Ok(report) => ExtractionEvent::RunFinished {
+                discovered: report.discovered,
+                extracted: report.extracted,
+                skipped: report.skipped,
+                failed: report.failed,
+                scan_failed: report.scan_failed,
+                files: report.throughput.files_written,
+                bytes: report.throughput.bytes_written,
+                duration: Duration::from_millis(report.elapsed_ms),
+                interrupted: report.interrupted,
+                aborted: report.aborted,
+                dropped_events: self.events.dropped_events(),
+                error: None,
+            },
+            Err(e) => ExtractionEvent::RunFinished {
+                discovered: 0,
+                extracted: 0,
+                skipped: 0,
+                failed: 0,
+                scan_failed: 0,
+                files: 0,
+                bytes: 0,
+                duration: started.elapsed(),
+                interrupted: false,
+                aborted: false,
+                dropped_events: self.events.dropped_events(),
+                error: Some(format!("{:#}", e)),
+            },
+        };
+        self.events.send(event);
+        result
+    }
+
+    async fn run_extraction(&self, started: Instant) -> Result<ExtractionReport> {
+        let started_at = unix_now();
+        debug!("Starting extraction process with the following configuration:");
+        debug!("  Input directory: {}", self.input_dir.display());
+        debug!("  Cache directory: {}", self.cache_dir.display());
+        debug!("  Extensions filter: {}", self.filter.extensions);
+        debug!("  Include paths: {:?}", self.filter.include_paths);
+        debug!("  Threads: {}", self.threads);
+        debug!("  Tool processes: {}", self.tool_concurrency);
+        debug!("  Background: {}", self.background);
+        debug!("  List timeout: {} seconds", self.list_timeout);
+        debug!("  Extract timeout: {:?}", self.timeout_policy);
+        debug!("  Follow symlinks: {}", self.walk_options.follow_symlinks);
+        debug!("  Max depth: {:?}", self.walk_options.max_depth);
+        debug!("  Ignored directories: {:?}", self.walk_options.ignore_dirs);
+        debug!("  Archive extensions: {:?}", self.walk_options.archive_extensions);
+        debug!("  Fallback chain: {:?}", self.fallback_chain);
+        debug!("  Retries: {} (backoff {:?})", self.retry.max_retries, self.retry.backoff_base);
+        debug!("  Lock retries: {} (delay {:?})", self.lock_retry.max_retries, self.lock_retry.delay);
+
+        utils::set_tool_concurrency(self.tool_concurrency);
+        if let Some(hide_console) = self.hide_console {
+            utils::set_hide_console(hide_console);
+        }
+        if self.background {
+            info!("Running in background mode on {} threads", self.threads);
+        }
+
+        // Verify directories exist
+        if !self.input_dir.exists() {
+            return Err(ExtractionError::InputDirNotFound(self.input_dir.to_owned()).into());
+        }
+
+        // Create cache directory if it doesn't exist
+        if !self.cache_dir.exists() {
+            debug!("Creating cache directory: {}", self.cache_dir.display());
+            std::fs::create_dir_all(self.cache_dir)?;
+        }
+
+        // Without the tool, every PBO would fail on its own after waiting for it
+        if !self.skip_backend_check {
+            (self.backend_check)()?;
+        }
+
+        if self.convert_paa && !cfg!(feature = "paa-convert") {
+            warn!("Converting PAA textures needs the paa-convert feature; extracting them unconverted");
+        }
+        if self.require_valid_signature && !cfg!(feature = "signatures") {
+            return Err(anyhow::anyhow!("Requiring valid signatures needs the signatures feature"));
+        }
+        if self.verify_signatures && !cfg!(feature = "signatures") {
+            warn!("Verifying signatures needs the signatures feature; not checking them");
+        }
+        if self.adopt_existing {
+            self.check_directory_outputs("Adopting existing outputs")?;
+        }
+
+        let cleaned_staging = self.clean_staging_dirs()?;
+        let filter = self.scan_filter();
+
+        // Listing starts with the first PBO found rather than once the walk is done
+        debug!("Scanning input directory for PBO files...");
+        let progress = &self.progress;
+        let StreamedScan {
+            discovered: total_pbo_count,
+            depth_limited_dirs,
+            filtered_out,
+            deferred,
+            known_failures,
+            pbos: total_pbo_files,
+            listing,
+        } = self.discover_and_list(&filter, progress.listing.as_ref())?;
+
+        if depth_limited_dirs > 0 {
+            debug!("Skipped {} directories beyond the depth limit", depth_limited_dirs);
+        }
+
+        if total_pbo_count == 0 {
+            return Err(ExtractionError::NoPbosFound {
+                input_dir: self.input_dir.to_owned(),
+                depth_limited_dirs,
+            }
+            .into());
+        }
+
+        debug!(
+            "Found {} PBO files to process ({} filtered out, {} deferred, {} quarantined, {} given up)",
+            total_pbo_files.len(),
+            filtered_out,
+            deferred.len(),
+            known_failures.quarantined.len(),
+            known_failures.gave_up.len()
+        );
+        progress.extraction.set_total(total_pbo_files.len() as u64);
+
+        // Two PBOs must never swap into the same output directory
+        let rel_paths: Vec<_> = total_pbo_files.iter().map(|entry| self.relative_path(entry.path())).collect();
+        let collisions = output_collisions(&rel_paths);
+        for (path, colliding) in &collisions {
+            warn!("Output directory of {} collides with {:?}", path.display(), colliding);
+        }
+        let (output_dirs, output_conflicts) = self.plan_output_dirs(&collisions);
+        let reextract = self.broken_outputs();
+
+        // Initialize processor with multithreading
+        debug!("Initializing PBO processor for extraction with {} threads", self.threads);
+        let processor = self.processor(&filter, progress.extraction.clone())
+            .with_output_dirs(output_dirs)
+            .with_output_conflicts(output_conflicts)
+            .with_required_signatures(self.require_valid_signature)
+            .with_failure_policy(self.failure_policy)
+            .with_reextract(reextract.clone());
+
+        let cleaned_partial = if self.clean_partial {
+            self.clean_partial_outputs(&processor, &total_pbo_files)?
+        } else {
+            0
+        };
+
+        // Sizes are known from the walk, so extended timeouts are decided up front
+        let extended_timeouts: Vec<_> = total_pbo_files
+            .iter()
+            .map(|entry| (entry, entry.metadata().map(|meta| meta.len()).unwrap_or(0)))
+            .filter(|(_, size)| self.timeout_policy.is_extended(*size))
+            .map(|(entry, size)| (self.relative_path(entry.path()), self.timeout_policy.for_size(size)))
+            .collect();
+        for (path, timeout) in &extended_timeouts {
+            debug!("Using extended timeout of {} seconds for {}", timeout, path.display());
+        }
+
+        let Listing {
+            mut scan_results,
+            failed: scan_failed,
+            locked,
+            not_listed,
+            case_collisions,
+            reserved_names,
+            duration: listing_duration,
+            tool_runs: listing_runs,
+            tool_wait: listing_tool_wait,
+        } = listing;
+
+        progress.listing.finish();
+        if scan_results.is_empty() && !scan_failed.is_empty() {
+            self.check_tool_after_failures()?;
+        }
+        let signatures = if self.verify_signatures {
+            self.verify_signatures(&mut scan_results)
+        } else {
+            Vec::new()
+        };
+        let mod_metadata = self.mod_metadata(&rel_paths);
+
+        // Outputs are per PBO, so shared prefixes only get reported
+        let prefix_collisions: Vec<_> = utils::prefix_collisions(&scan_results)
+            .into_iter()
+            .map(|(prefix, paths)| (prefix, paths.iter().map(|path| self.relative_path(path)).collect::<Vec<_>>()))
+            .collect();
+        for (prefix, paths) in &prefix_collisions {
+            warn!("Prefix {} is declared by {} PBOs: {:?}", prefix, paths.len(), paths);
+        }
+
+        // Only needs the listings, so it's written even if extraction is aborted
+        let mut prefixes = utils::prefix_map(&scan_results);
+        for paths in prefixes.values_mut() {
+            *paths = paths.iter().map(|path| self.relative_path(path)).collect();
+        }
+        if self.write_prefix_map {
+            let path = self.cache_dir.join(utils::PREFIX_MAP_FILE_NAME);
+            // Runs over some of the PBOs, like those of a watch, keep the prefixes of the others
+            if self.pbo_filter.is_restricted() {
+                let kept = |rel_path: &Path| {
+                    !self.pbo_filter.matches(rel_path) && self.input_dir.join(rel_path).is_file()
+                };
+                match utils::read_prefix_map(&path) {
+                    Ok(previous) => utils::merge_prefix_map(&mut prefixes, previous, kept),
+                    Err(e) => warn!("Replacing the prefix map: {:#}", e),
+                }
+            }
+            utils::write_prefix_map(&path, &prefixes)?;
+            debug!("Wrote {} prefixes to {}", prefixes.len(), utils::PREFIX_MAP_FILE_NAME);
+        }
+
+        // Built from the listings, so no extra tool invocations
+        let conflicts = if self.detect_conflicts {
+            let mut conflicts = utils::path_conflicts(&scan_results);
+            for conflict in &mut conflicts {
+                conflict.pbos = conflict.pbos.iter().map(|path| self.relative_path(path)).collect();
+            }
+            debug!("Found {} files provided by more than one PBO", conflicts.len());
+            conflicts
+        } else {
+            Vec::new()
+        };
+
+        // Complete outputs of earlier extractions are recorded rather than replaced
+        let adopted = if self.adopt_existing {
+            self.adopt_listed(&filter, &processor, &scan_results)
+        } else {
+            HashSet::new()
+        };
+        for path in &adopted {
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(path),
+                reason: SkipReason::AlreadyExtracted,
+            });
+        }
+        let to_extract: Vec<_> = scan_results.iter()
+            .filter(|scan_result| !adopted.contains(&scan_result.path))
+            .cloned()
+            .collect();
+
+        let (estimated_size, required_space) = to_extract
+            .iter()
+            .map(|result| processor.estimate_size(result))
+            .fold((0, 0), |(total, required), (size, new)| (total + size, required + new));
+        debug!(
+            "Estimated extraction size: {} ({} not replacing existing files)",
+            HumanBytes(estimated_size),
+            HumanBytes(required_space)
+        );
+        self.check_disk_space(required_space)?;
+
+        // PBOs that could not be listed never reach the extraction phase
+        progress.extraction.set_total(to_extract.len() as u64);
+
+        debug!("PBO scan complete:");
+        debug!("  Total PBOs scanned: {}", scan_results.len());
+        debug!("  Adopted: {}", adopted.len());
+
+        // Process PBOs for extraction
+        debug!("Starting extraction from {} PBOs", to_extract.len());
+        let mut report = processor.process_all(&to_extract)?;
+        report.adopted = adopted.iter().map(|path| self.relative_path(path)).collect();
+        report.adopted.sort();
+        progress.extraction.finish();
+        report.throughput.listing_ms = listing_duration.as_millis() as u64;
+        report.throughput.listing_runs = listing_runs;
+        report.throughput.tool_wait_ms += listing_tool_wait.as_millis() as u64;
+        report.discovered = total_pbo_count;
+        report.filtered_out = filtered_out;
+        report.depth_limited_dirs = depth_limited_dirs;
+        report.scan_failed = scan_failed.len();
+        for failure in &scan_failed {
+            if let Some(mod_name) = self.mod_detection.mod_name(&failure.path) {
+                let summary = report.mods.entry(mod_name).or_default();
+                summary.pbos += 1;
+                summary.scan_failed += 1;
+            }
+        }
+        report.scan_failures = scan_failed;
+        report.skipped += locked.len();
+        for rel_path in &locked {
+            if let Some(mod_name) = self.mod_detection.mod_name(rel_path) {
+                let summary = report.mods.entry(mod_name).or_default();
+                summary.pbos += 1;
+                summary.skipped += 1;
+            }
+        }
+        report.locked.extend(locked);
+        report.locked.sort();
+        for (mod_name, summary) in &mut report.mods {
+            summary.metadata = mod_metadata.get(mod_name).cloned().unwrap_or_default();
+        }
+        report.extended_timeouts = extended_timeouts;
+        report.cleaned_dirs = cleaned_staging + cleaned_partial;
+        report.case_collisions = case_collisions;
+        report.reserved_names = reserved_names;
+        report.output_collisions = collisions.into_iter().collect();
+        report.prefix_collisions = prefix_collisions;
+        report.prefixes = prefixes;
+        report.signatures = signatures;
+        report.deferred.extend(deferred);
+        report.deferred.sort();
+        report.quarantined = known_failures.quarantined;
+        report.gave_up = known_failures.gave_up;
+        report.interrupted |= !not_listed.is_empty();
+        report.not_processed.extend(not_listed);
+        report.conflicts = conflicts;
+        report.estimated_size = estimated_size;
+        report.required_space = required_space;
+
+        if self.recursive {
+            if report.quota_exceeded {
+                debug!("Output quota reached, not looking for nested PBOs");
+            } else if self.cancel.is_cancelled() {
+                debug!("Interrupted, not looking for nested PBOs");
+            } else if report.aborted {
+                debug!("Failure policy exceeded, not looking for nested PBOs");
+            } else {
+                self.extract_nested(&filter, &processor, &scan_results, &mut report)?;
+            }
+        }
+        if report.quota_exceeded {
+            warn!(
+                "Output quota reached, {} PBOs were not processed; rerun with resume and a higher quota to continue",
+                report.not_processed.len()
+            );
+        }
+
+        // Replaced outputs may have been the last users of some objects
+        if self.content_store {
+            ObjectStore::new(self.cache_dir).gc(self.cache_dir)?;
+        }
+        // Interrupted runs only finish what keeps the cache consistent
+        if self.dedupe && !self.cancel.is_cancelled() && !report.aborted {
+            let stats = dedupe_dir(self.cache_dir, self.dedupe_min_size)?;
+            report.deduplicated_files = stats.linked;
+            report.dedupe_bytes_saved = stats.bytes_saved;
+        }
+        // Repaired outputs are no longer broken
+        if self.tracks_index() || !reextract.is_empty() {
+            report.indexed_files =
+                self.update_index(&processor, &scan_results, &mod_metadata, &known_failures.released, &mut report)?;
+        }
+        if report.aborted {
+            warn!(
+                "Stopped after {} failed PBOs, {} PBOs were not processed; first failure: {:?}",
+                report.failures.len(),
+                report.not_processed.len(),
+                report.failures.first()
+            );
+        }
+        if report.interrupted {
+            warn!(
+                "Interrupted, {} PBOs were not processed; rerun to continue",
+                report.not_processed.len()
+            );
+        }
+
+        report.elapsed_ms = started.elapsed().as_millis() as u64;
+        report.slowest = report.slowest(self.slowest).into_iter().cloned().collect();
+        // Stopped runs too, so their partial results are visible
+        if let Some(path) = &self.run_report {
+            let path = self.cache_dir.join(path);
+            RunReport::new(self.run_config(), started_at, unix_now(), report.clone()).write(&path)?;
+            debug!("Wrote run report to {}", path.display());
+        }
+        if let Some(path) = &self.csv_report {
+            report.write_csv(&self.cache_dir.join(path))?;
+        }
+        if let Some(path) = &self.mods_csv_report {
+            report.write_mods_csv(&self.cache_dir.join(path))?;
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use super::super::archive::TarCompression;
+    use super::super::derap::RapConverter;
+    use super::super::run_report::RUN_REPORT_FILE_NAME;
+    use super::super::test_utils::write_file;
+
+    fn discovered_names(coordinator: &ScanCoordinator) -> Vec<String> {
+        let mut names: Vec<_> = coordinator.discover_pbos()
+            .unwrap()
+            .pbos
+            .iter()
+            .map(|e| e.path().strip_prefix(coordinator.input_dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_discover_through_symlinked_directory() {
+        let mods_dir = TempDir::new().unwrap();
+        let workshop_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        std::fs::create_dir_all(workshop_dir.path().join("addons")).unwrap();
+        std::fs::write(workshop_dir.path().join("addons/ace_medical.pbo"), b"").unwrap();
+        std::os::unix::fs::symlink(workshop_dir.path(), mods_dir.path().join("@ace")).unwrap();
+        // A link back to the root must not hang the walk
+        std::os::unix::fs::symlink(mods_dir.path(), mods_dir.path().join("@ace_loop")).unwrap();
+
+        let coordinator = ScanCoordinator::new(mods_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        assert!(discovered_names(&coordinator).is_empty());
+
+        let coordinator = coordinator.with_walk_options(WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        });
+        assert_eq!(discovered_names(&coordinator), vec!["@ace/addons/ace_medical.pbo"]);
+    }
+
+    #[test]
+    fn test_discover_with_max_depth() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        std::fs::create_dir_all(input_dir.path().join("@ace/addons")).unwrap();
+        std::fs::create_dir_all(input_dir.path().join("backup")).unwrap();
+        std::fs::write(input_dir.path().join("root.pbo"), b"").unwrap();
+        std::fs::write(input_dir.path().join("@ace/addons/ace_medical.pbo"), b"").unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_walk_options(WalkOptions {
+                max_depth: Some(1),
+                ..Default::default()
+            });
+        let discovery = coordinator.discover_pbos().unwrap();
+        assert_eq!(discovery.pbos.len(), 1);
+        assert_eq!(discovery.depth_limited_dirs, 2);
+
+        // @mod/addons/x.pbo sits at depth 3
+        let coordinator = coordinator.with_walk_options(WalkOptions {
+            max_depth: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(discovered_names(&coordinator), vec!["@ace/addons/ace_medical.pbo", "root.pbo"]);
+        assert_eq!(coordinator.discover_pbos().unwrap().depth_limited_dirs, 0);
+    }
+
+    #[test]
+    fn test_find_pbos_as_a_run_would() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        for name in ["@ace/addons/ace_medical.pbo", "@ace/addons/ace_common.pbo", "@ace/optionals/ace_x.pbo", "backup/old.pbo"] {
+            write_file(&input_dir.path().join(name), b"");
+        }
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_walk_options(WalkOptions {
+                ignore_dirs: vec!["backup".to_string()],
+                ..Default::default()
+            })
+            .with_pbo_filter(PboFilter::new(&[], &["**/optionals/**".to_string()]).unwrap());
+        let rel_paths: Vec<_> = coordinator.find_pbos().unwrap().into_iter().map(|(_, rel_path)| rel_path).collect();
+        assert_eq!(rel_paths, [PathBuf::from("@ace/addons/ace_common.pbo"), PathBuf::from("@ace/addons/ace_medical.pbo")]);
+
+        // Everything was just written, so all of it is within the grace period
+        let coordinator = coordinator.with_min_age(Some(Duration::from_secs(3600)));
+        assert!(matches!(
+            coordinator.find_pbos().unwrap_err().downcast_ref::<ExtractionError>(),
+            Some(ExtractionError::NoPbosFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_discover_archive_extensions() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        for name in ["FOO.PBO", "bar.Pbo", "baz.ebo", "readme.txt"] {
+            std::fs::write(input_dir.path().join(name), b"").unwrap();
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        assert_eq!(discovered_names(&coordinator), vec!["FOO.PBO", "bar.Pbo"]);
+
+        let coordinator = coordinator.with_walk_options(WalkOptions {
+            archive_extensions: vec!["pbo".to_string(), "ebo".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(discovered_names(&coordinator), vec!["FOO.PBO", "bar.Pbo", "baz.ebo"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_finishes_with_error() {
+        let dir = TempDir::new().unwrap();
+        let (input_dir, cache_dir) = (dir.path().join("missing"), dir.path().join("cache"));
+        let filter = FileFilter::default();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let coordinator = ScanCoordinator::new(&input_dir, &cache_dir, &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_events(EventSink::new(Some(sender)));
+
+        assert!(coordinator.run().await.is_err());
+        match receiver.try_recv().unwrap() {
+            ExtractionEvent::RunFinished { discovered, error: Some(error), .. } => {
+                assert_eq!(discovered, 0);
+                assert!(error.contains("missing"), "{}", error);
+            },
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_starts_with_backend_check() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        fn missing_tool() -> Result<(), ExtractionError> {
+            Err(ExtractionError::BackendUnavailable("Failed to spawn extractpbo".to_string()))
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_backend_check(missing_tool);
+        let error = coordinator.run().await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ExtractionError::BackendUnavailable(_))), "{:#}", error);
+
+        // Skipping it, the run goes on to find no PBOs
+        let error = coordinator.with_skip_backend_check(true).run().await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ExtractionError::NoPbosFound { .. })), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run_keeps_index() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        std::fs::create_dir(input_dir.path().join("addons")).unwrap();
+        std::fs::write(input_dir.path().join("addons/ace_medical.pbo"), b"").unwrap();
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_index(true)
+            .with_run_report(Some(PathBuf::from(RUN_REPORT_FILE_NAME)))
+            .with_cancellation(cancel);
+
+        let report = coordinator.run().await.unwrap();
+        assert!(report.interrupted);
+        assert_eq!(report.not_processed, vec![PathBuf::from("addons/ace_medical.pbo")]);
+        assert_eq!(report.scan_failed, 0);
+        let index = ExtractionIndex::load(&cache_dir.path().join(INDEX_FILE_NAME)).unwrap();
+        assert!(index.files.is_empty());
+        let run = RunReport::load(&cache_dir.path().join(RUN_REPORT_FILE_NAME)).unwrap();
+        assert_eq!(run.report, report);
+        assert_eq!(run.config.extensions, "sqf");
+        assert!(run.finished_at >= run.started_at);
+    }
+
+    #[test]
+    fn test_extracting_everything_skips_listing() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("*");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo");
+        std::fs::copy(fixture, input_dir.path().join("ace_medical.pbo")).unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        let scan = coordinator.discover_and_list(&coordinator.scan_filter(), &NoopProgress).unwrap();
+        assert_eq!(scan.listing.tool_runs, 0);
+        let [result] = scan.listing.scan_results.as_slice() else {
+            panic!("{} PBOs scanned", scan.listing.scan_results.len());
+        };
+        assert!(result.listed_after_extraction);
+        assert!(result.expected_files.is_empty());
+        assert_eq!(result.prefix.as_deref(), Some("z/ace/addons/medical"));
+
+        // Entries are needed up front to compare them with the output, or with any filter
+        assert!(!coordinator.lists_after_extraction(&FileFilter::new("sqf")));
+        assert!(!coordinator.with_resume(true).lists_after_extraction(&filter));
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn test_locked_pbo_is_skipped_while_listing() {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let pbo = input_dir.path().join("@ace/addons/ace_medical.pbo");
+        write_file(&pbo, &std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo")).unwrap());
+        // No sharing at all, like an antivirus scanner holding the file
+        let _lock = std::fs::OpenOptions::new().read(true).share_mode(0).open(&pbo).unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true);
+        let report = coordinator.run().await.unwrap();
+        assert_eq!(report.locked, vec![PathBuf::from("@ace/addons/ace_medical.pbo")]);
+        assert_eq!((report.scan_failed, report.skipped), (0, 1));
+        assert_eq!(report.mods["@ace"].skipped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_pbos_listed_from_cache() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let pbo = input_dir.path().join("ace_medical.pbo");
+        std::fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo"), &pbo).unwrap();
+
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), DEFAULT_LISTING_CACHE_MAX_BYTES);
+        let contents = PboContents {
+            prefix: Some("z\\ace\\addons\\medical".to_string()),
+            entries: vec!["config.cpp".to_string()],
+            ..Default::default()
+        };
+        cache.insert(Path::new("ace_medical.pbo"), crate::utils::sha256_file(&pbo).unwrap(), contents);
+        cache.save().unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true);
+        let report = coordinator.run().await.unwrap();
+        assert_eq!((report.scan_failed, report.skipped), (0, 1));
+        assert_eq!((report.throughput.listing_runs, report.throughput.extraction_runs), (0, 0));
+        assert_eq!(report.prefixes.keys().collect::<Vec<_>>(), vec!["z/ace/addons/medical"]);
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_listing_cache(false, DEFAULT_LISTING_CACHE_MAX_BYTES);
+        let report = coordinator.run().await.unwrap();
+        assert!(report.throughput.listing_runs > 0);
+
+        // A change past the first bytes, keeping size and modification time
+        let modified = std::fs::metadata(&pbo).unwrap().modified().unwrap();
+        let mut bytes = std::fs::read(&pbo).unwrap();
+        bytes[100_000] ^= 0xff;
+        std::fs::write(&pbo, bytes).unwrap();
+        std::fs::File::options().write(true).open(&pbo).unwrap().set_modified(modified).unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true);
+        let report = coordinator.run().await.unwrap();
+        assert!(report.throughput.listing_runs > 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_extractions_add_up_attempts() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("*");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo");
+        std::fs::copy(fixture, input_dir.path().join("ace_medical.pbo")).unwrap();
+
+        // Listed from its header and failing before the tool runs
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_fallback_chain(Vec::new())
+            .with_index(true);
+        for attempts in 1..=2 {
+            let report = coordinator.run().await.unwrap();
+            assert_eq!(report.failed, 1);
+            let index = ExtractionIndex::load(&cache_dir.path().join(INDEX_FILE_NAME)).unwrap();
+            let failed = &index.failed[Path::new("ace_medical.pbo")];
+            assert_eq!((failed.stage, failed.attempts), (FailureStage::Extraction, attempts));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restricted_run_keeps_other_prefixes() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("*");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo");
+        for name in ["a.pbo", "b.pbo"] {
+            std::fs::copy(&fixture, input_dir.path().join(name)).unwrap();
+        }
+        let prefixes = || {
+            let map = utils::read_prefix_map(&cache_dir.path().join(utils::PREFIX_MAP_FILE_NAME)).unwrap();
+            let mut paths: Vec<_> = map.into_values().flatten().collect();
+            paths.sort();
+            paths
+        };
+
+        // Listed from their headers, without the tool
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_fallback_chain(Vec::new());
+        coordinator.run().await.unwrap();
+        assert_eq!(prefixes(), [PathBuf::from("a.pbo"), PathBuf::from("b.pbo")]);
+
+        // As a watch runs over the PBOs that changed
+        let coordinator = coordinator.with_pbo_filter(PboFilter::new(&["a.pbo".to_string()], &[]).unwrap());
+        coordinator.run().await.unwrap();
+        assert_eq!(prefixes(), [PathBuf::from("a.pbo"), PathBuf::from("b.pbo")]);
+
+        std::fs::remove_file(input_dir.path().join("b.pbo")).unwrap();
+        coordinator.run().await.unwrap();
+        assert_eq!(prefixes(), [PathBuf::from("a.pbo")]);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("*");
+        let pbo = input_dir.path().join("broken.pbo");
+        std::fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/ace_medical.pbo"), &pbo).unwrap();
+        let index_path = cache_dir.path().join(INDEX_FILE_NAME);
+
+        // Three runs failing to extract it, listed from its header and failing before the tool runs
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_fallback_chain(Vec::new())
+            .with_max_attempts(Some(3));
+        for _ in 0..3 {
+            let report = coordinator.run().await.unwrap();
+            assert_eq!(report.failed, 1);
+            assert!(report.gave_up.is_empty());
+        }
+        assert_eq!(ExtractionIndex::load(&index_path).unwrap().failed[Path::new("broken.pbo")].attempts, 3);
+
+        // The fourth run skips it
+        let report = coordinator.run().await.unwrap();
+        assert_eq!(report.gave_up, vec![PathBuf::from("broken.pbo")]);
+        assert_eq!((report.failed, report.scan_failed), (0, 0));
+        assert_eq!(ExtractionIndex::load(&index_path).unwrap().failed[Path::new("broken.pbo")].attempts, 3);
+
+        // Retried when forced or changed; not listing it keeps the test off the tool
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let coordinator = coordinator.with_cancellation(cancel);
+        let report = coordinator.with_force_retry(true).run().await.unwrap();
+        assert!(report.gave_up.is_empty());
+        assert_eq!(report.not_processed, vec![PathBuf::from("broken.pbo")]);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_max_attempts(Some(3))
+            .with_cancellation(cancel);
+        std::fs::write(&pbo, b"fixed version").unwrap();
+        let report = coordinator.run().await.unwrap();
+        assert!(report.gave_up.is_empty());
+        assert_eq!(report.not_processed, vec![PathBuf::from("broken.pbo")]);
+    }
+
+    #[tokio::test]
+    async fn test_quarantined_pbos_are_skipped_until_changed() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        std::fs::create_dir(input_dir.path().join("addons")).unwrap();
+        let pbo = input_dir.path().join("addons/broken.pbo");
+        std::fs::write(&pbo, b"broken").unwrap();
+
+        let rel_path = Path::new("addons/broken.pbo");
+        let copy = Path::new("quarantine/addons/broken.pbo");
+        std::fs::create_dir_all(cache_dir.path().join("quarantine/addons")).unwrap();
+        std::fs::copy(&pbo, cache_dir.path().join(copy)).unwrap();
+        let index_path = cache_dir.path().join(INDEX_FILE_NAME);
+        let mut index = ExtractionIndex::new(cache_dir.path());
+        let failure = PboFailure::new(rel_path.to_owned(), &anyhow::anyhow!("Invalid PBO header"));
+        let hash = crate::utils::calculate_file_hash(&pbo).unwrap();
+        index.record_failure(&failure, FailureStage::Listing, Some(hash.clone()), 1_700_000_000);
+        index.quarantine(rel_path, hash, Some(copy.to_owned()), 1_700_000_000);
+        index.write(&index_path).unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_skip_backend_check(true)
+            .with_quarantine(Some(QuarantinePolicy::after(2)));
+        let report = coordinator.run().await.unwrap();
+        assert_eq!(report.quarantined, vec![rel_path.to_owned()]);
+        assert_eq!(report.scan_failed, 0);
+        assert_eq!(ExtractionIndex::load(&index_path).unwrap().quarantined_entries().count(), 1);
+
+        // A new version gets another chance; not listing it keeps the test off the tool
+        std::fs::write(&pbo, b"fixed version").unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let report = coordinator.with_cancellation(cancel).run().await.unwrap();
+        assert!(report.quarantined.is_empty());
+        let index = ExtractionIndex::load(&index_path).unwrap();
+        assert_eq!(index.quarantined_entries().count(), 0);
+        assert!(index.failed.is_empty());
+        assert!(!cache_dir.path().join(copy).exists());
+    }
+
+    #[test]
+    fn test_recently_modified_pbos_are_deferred() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        for name in ["fresh.pbo", "settled.pbo"] {
+            std::fs::write(input_dir.path().join(name), b"").unwrap();
+        }
+        let settled = SystemTime::now() - Duration::from_secs(2 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(input_dir.path().join("settled.pbo"))
+            .unwrap()
+            .set_modified(settled)
+            .unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        let too_recent = |coordinator: &ScanCoordinator| -> Vec<String> {
+            coordinator.discover_pbos()
+                .unwrap()
+                .pbos
+                .iter()
+                .filter(|entry| coordinator.is_too_recent(entry))
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert!(too_recent(&coordinator).is_empty());
+
+        let coordinator = coordinator.with_min_age(Some(Duration::from_secs(3600)));
+        assert_eq!(too_recent(&coordinator), vec!["fresh.pbo"]);
+        let coordinator = coordinator.with_min_age(Some(Duration::from_secs(3 * 3600)));
+        assert_eq!(too_recent(&coordinator).len(), 2);
+    }
+
+    #[test]
+    fn test_streamed_discovery_keeps_walk_order() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        // More than fit in the discovery buffer at once
+        for mod_index in 0..4 {
+            let addons = input_dir.path().join(format!("@mod{}/addons", mod_index));
+            std::fs::create_dir_all(&addons).unwrap();
+            for pbo in 0..100 {
+                std::fs::write(addons.join(format!("pbo_{}.pbo", pbo)), b"not a pbo").unwrap();
+            }
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 4, 30)
+            .unwrap()
+            .with_pbo_filter(PboFilter::new(&[], &["@mod2/**".to_string()]).unwrap());
+        let expected: Vec<_> = coordinator.discover_pbos()
+            .unwrap()
+            .pbos
+            .iter()
+            .map(|entry| coordinator.relative_path(entry.path()))
+            .filter(|rel_path| !rel_path.starts_with("@mod2"))
+            .collect();
+
+        let scan = coordinator.discover_and_list(&filter, &NoopProgress).unwrap();
+        assert_eq!((scan.discovered, scan.filtered_out), (400, 100));
+        let found: Vec<_> = scan.pbos.iter().map(|entry| coordinator.relative_path(entry.path())).collect();
+        assert_eq!(found, expected);
+        // Skipped as invalid without running the tool, in the order of the walk
+        let listed: Vec<_> = scan.listing.scan_results.iter().map(|result| coordinator.relative_path(&result.path)).collect();
+        assert_eq!(listed, expected);
+    }
+
+    /// Reporter holding up the listing of the first PBO for `slow`, and of every
+    /// other one for `fast`, recording the order in which the delays end
+    struct DelayedProgress {
+        slow: Duration,
+        fast: Duration,
+        listed: std::sync::atomic::AtomicUsize,
+        finished: std::sync::Mutex<Vec<usize>>,
+    }
+
+    impl ProgressReporter for DelayedProgress {
+        fn set_total(&self, _total: u64) {}
+        fn inc(&self, _delta: u64) {}
+        fn set_message(&self, _message: &str) {
+            let started = self.listed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::thread::sleep(if started == 0 { self.slow } else { self.fast });
+            self.finished.lock().unwrap().push(started);
+        }
+        fn finish(&self) {}
+    }
+
+    #[test]
+    fn test_slow_pbo_holds_up_only_its_own_listing() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        for pbo in 0..13 {
+            std::fs::write(input_dir.path().join(format!("pbo_{}.pbo", pbo)), b"not a pbo").unwrap();
+        }
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 4, 30).unwrap();
+        let progress = DelayedProgress {
+            slow: Duration::from_millis(2000),
+            fast: Duration::from_millis(50),
+            listed: Default::default(),
+            finished: Default::default(),
+        };
+
+        let scan = coordinator.discover_and_list(&filter, &progress).unwrap();
+        assert_eq!(scan.listing.scan_results.len(), 13);
+        // The other three workers list the twelve fast PBOs meanwhile, taking about
+        // 200 ms; listing in chunks of four would wait for the slow one first
+        let finished = progress.finished.into_inner().unwrap();
+        assert_eq!(finished.len(), 13);
+        assert_eq!(finished.last(), Some(&0), "{:?}", finished);
+    }
+
+    #[test]
+    fn test_discover_skips_ignored_dirs() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+
+        for dir in ["@ace/addons", "@ace/keys", "@ace/optionals", "@ace/backup/old"] {
+            std::fs::create_dir_all(input_dir.path().join(dir)).unwrap();
+            std::fs::write(input_dir.path().join(dir).join("ace_medical.pbo"), b"").unwrap();
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_walk_options(WalkOptions {
+                ignore_dirs: WalkOptions::default_ignore_dirs(),
+                ..Default::default()
+            });
+        assert_eq!(discovered_names(&coordinator), vec![
+            "@ace/addons/ace_medical.pbo",
+            "@ace/backup/old/ace_medical.pbo",
+        ]);
+
+        let coordinator = coordinator.with_walk_options(WalkOptions {
+            ignore_dirs: vec!["@ace/backup".to_string(), "KEYS".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(discovered_names(&coordinator), vec![
+            "@ace/addons/ace_medical.pbo",
+            "@ace/optionals/ace_medical.pbo",
+        ]);
+    }
+
+    #[test]
+    fn test_clean_staging_dirs() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let staging_root = cache_dir.path().join(STAGING_DIR_NAME);
+        std::fs::create_dir_all(staging_root.join("ace_medical-123-0/functions")).unwrap();
+        std::fs::write(staging_root.join("ace_medical-123-0/functions/fnc_heal.sqf"), b"heal").unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        assert_eq!(coordinator.clean_staging_dirs().unwrap(), 1);
+        assert!(!staging_root.exists());
+        assert_eq!(coordinator.clean_staging_dirs().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_clean_partial_outputs_keeps_manifested_dirs() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        std::fs::File::create(input_dir.path().join("complete.pbo")).unwrap();
+        std::fs::File::create(input_dir.path().join("partial.pbo")).unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("complete")).unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("partial")).unwrap();
+        std::fs::create_dir_all(cache_dir.path().join("unrelated")).unwrap();
+        std::fs::write(cache_dir.path().join("complete").join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        let pbos = coordinator.discover_pbos().unwrap().pbos;
+
+        assert_eq!(coordinator.clean_partial_outputs(&processor, &pbos).unwrap(), 1);
+        assert!(cache_dir.path().join("complete").exists());
+        assert!(!cache_dir.path().join("partial").exists());
+        assert!(cache_dir.path().join("unrelated").exists());
+    }
+
+    #[test]
+    fn test_adopt_complete_outputs_only() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let scan_result = |name: &str| PboScanResult {
+            path: input_dir.path().join(format!("{}.pbo", name)),
+            prefix: Some("z\\ace\\addons\\medical".to_string()),
+            expected_files: vec![
+                PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+                PboEntry { path: "config.cpp".to_string(), size: None },
+            ],
+            ..Default::default()
+        };
+        for name in ["complete", "partial"] {
+            std::fs::write(input_dir.path().join(format!("{}.pbo", name)), name).unwrap();
+            let output_dir = cache_dir.path().join(name).join("z/ace/addons/medical");
+            std::fs::create_dir_all(output_dir.join("functions")).unwrap();
+            std::fs::write(output_dir.join("config.cpp"), "class CfgPatches {};").unwrap();
+            let heal = if name == "complete" { "heal" } else { "heal them all" };
+            std::fs::write(output_dir.join("functions/fnc_heal.sqf"), heal).unwrap();
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        assert!(coordinator.adopt(&filter, &processor, &scan_result("complete")).unwrap());
+        assert!(!coordinator.adopt(&filter, &processor, &scan_result("partial")).unwrap());
+
+        let manifest = ExtractionManifest::read(&cache_dir.path().join("complete")).unwrap();
+        assert_eq!(manifest.hash, crate::utils::calculate_file_hash(&input_dir.path().join("complete.pbo")).unwrap());
+        assert_eq!(manifest.files.len(), 2);
+        assert!(!cache_dir.path().join("partial").join(MANIFEST_FILE_NAME).exists());
+
+        let coordinator = coordinator.with_tar_output(Some(TarTarget { compression: TarCompression::None, zero_mtimes: false }));
+        assert!(coordinator.check_directory_outputs("Adopting").is_err());
+    }
+
+    #[test]
+    fn test_touch_refreshes_complete_outputs_only() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let scan_result = |name: &str| PboScanResult {
+            path: input_dir.path().join(format!("{}.pbo", name)),
+            expected_files: vec![PboEntry { path: "config.cpp".to_string(), size: Some(4) }],
+            ..Default::default()
+        };
+        for name in ["complete", "partial", "foreign"] {
+            let pbo = input_dir.path().join(format!("{}.pbo", name));
+            std::fs::write(&pbo, name).unwrap();
+            let base_dir = cache_dir.path().join(name);
+            std::fs::create_dir_all(&base_dir).unwrap();
+            let config = if name == "partial" { "class" } else { "cfg;" };
+            std::fs::write(base_dir.join("config.cpp"), config).unwrap();
+            if name != "foreign" {
+                ExtractionManifest::collect(&base_dir, &pbo, "stale".to_string(), None, &filter.extensions)
+                    .unwrap()
+                    .write(&base_dir)
+                    .unwrap();
+            }
+        }
+
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        assert_eq!(coordinator.touch(&filter, &processor, &scan_result("complete")).unwrap(), Touched::Refreshed);
+        assert_eq!(coordinator.touch(&filter, &processor, &scan_result("complete")).unwrap(), Touched::Current);
+        assert_eq!(coordinator.touch(&filter, &processor, &scan_result("partial")).unwrap(), Touched::Pending);
+        // Outputs of other tools are for adopting
+        assert_eq!(coordinator.touch(&filter, &processor, &scan_result("foreign")).unwrap(), Touched::Pending);
+
+        let hash = |name: &str| ExtractionManifest::read(&cache_dir.path().join(name)).unwrap().hash;
+        assert_eq!(hash("complete"), crate::utils::calculate_file_hash(&input_dir.path().join("complete.pbo")).unwrap());
+        assert_eq!(hash("partial"), "stale");
+    }
+
+    #[test]
+    fn test_output_collisions() {
+        let paths: Vec<PathBuf> = [
+            "@ace/addons/ace_medical.pbo",
+            "@ace/addons/ace_medical.ebo",
+            "src/foo.pbo",
+            "src/foo/bar.pbo",
+            "src/foo/baz/qux.pbo",
+            "src/foo_extra.pbo",
+            "src/other.pbo",
+        ].iter().map(PathBuf::from).collect();
+
+        let collisions = output_collisions(&paths);
+
+        assert_eq!(collisions.len(), 3);
+        assert_eq!(collisions[Path::new("@ace/addons/ace_medical.pbo")], vec![PathBuf::from("@ace/addons/ace_medical.ebo")]);
+        assert_eq!(collisions[Path::new("@ace/addons/ace_medical.ebo")], vec![PathBuf::from("@ace/addons/ace_medical.pbo")]);
+        // Only the PBO whose output would contain the others collides
+        assert_eq!(collisions[Path::new("src/foo.pbo")], vec![
+            PathBuf::from("src/foo/bar.pbo"),
+            PathBuf::from("src/foo/baz/qux.pbo"),
+        ]);
+
+        // Names the cache uses at its root
+        let paths: Vec<PathBuf> = ["Objects.pbo", "index.json.pbo", "quarantine/broken.pbo", "@ace/objects.pbo", "index.pbo"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let collisions = output_collisions(&paths);
+        assert_eq!(collisions.len(), 3);
+        assert_eq!(collisions[Path::new("Objects.pbo")], vec![PathBuf::from(OBJECTS_DIR_NAME)]);
+        assert_eq!(collisions[Path::new("index.json.pbo")], vec![PathBuf::from(INDEX_FILE_NAME)]);
+        assert_eq!(collisions[Path::new("quarantine/broken.pbo")], vec![PathBuf::from(QUARANTINE_DIR_NAME)]);
+
+        // Names of retained versions
+        let paths: Vec<PathBuf> = ["foo.pbo", "foo.prev1.pbo", "src/Foo.PREV12.pbo", "foo.prev.pbo", "foo.prev1a.pbo"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let collisions = output_collisions(&paths);
+        assert_eq!(collisions.len(), 2);
+        assert_eq!(collisions[Path::new("foo.prev1.pbo")], vec![PathBuf::from("foo")]);
+        assert_eq!(collisions[Path::new("src/Foo.PREV12.pbo")], vec![PathBuf::from("src/foo")]);
+    }
+
+    #[test]
+    fn test_plan_output_dirs() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        for path in ["foo.pbo", "foo.ebo", "foo/bar.pbo"] {
+            write_file(&input_dir.path().join(path), b"");
+        }
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_walk_options(WalkOptions {
+                archive_extensions: vec!["pbo".to_string(), "ebo".to_string()],
+                ..Default::default()
+            });
+        let pbos = coordinator.discover_pbos().unwrap().pbos;
+        let rel_paths: Vec<_> = pbos.iter().map(|entry| coordinator.relative_path(entry.path())).collect();
+        let collisions = output_collisions(&rel_paths);
+
+        let (output_dirs, conflicts) = coordinator.plan_output_dirs(&collisions);
+        assert!(conflicts.is_empty());
+        assert_eq!(output_dirs.len(), 2);
+        assert_eq!(output_dirs[&input_dir.path().join("foo.pbo")], cache_dir.path().join("foo.pbo.d"));
+        assert_eq!(output_dirs[&input_dir.path().join("foo.ebo")], cache_dir.path().join("foo.ebo.d"));
+
+        // Disambiguated PBOs no longer collide with anything
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_output_dirs(output_dirs);
+        assert_eq!(
+            processor.base_output_dir(&input_dir.path().join("foo/bar.pbo")).unwrap(),
+            cache_dir.path().join("foo/bar")
+        );
+
+        let coordinator = coordinator.with_output_collisions(OutputCollisionHandling::Fail);
+        let (output_dirs, conflicts) = coordinator.plan_output_dirs(&collisions);
+        assert!(output_dirs.is_empty());
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[&input_dir.path().join("foo.pbo")],
+            format!("Output directory of foo.pbo collides with: {}, foo.ebo", Path::new("foo/bar.pbo").display())
+        );
+
+        // Outputs taking a name of the cache move aside; those inside its directories can't
+        let coordinator = coordinator.with_output_collisions(OutputCollisionHandling::Disambiguate);
+        let rel_paths = [PathBuf::from("objects.pbo"), Path::new(STAGING_DIR_NAME).join("foo.pbo")];
+        let (output_dirs, conflicts) = coordinator.plan_output_dirs(&output_collisions(&rel_paths));
+        assert_eq!(output_dirs[&input_dir.path().join("objects.pbo")], cache_dir.path().join("objects.pbo.d"));
+        assert_eq!(
+            conflicts[&input_dir.path().join(&rel_paths[1])],
+            format!("Output directory of {} would be inside the cache's .tmp directory", rel_paths[1].display())
+        );
+    }
+
+    #[test]
+    fn test_nested_pbos_in_output() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .unwrap()
+            .with_recursion(true, DEFAULT_MAX_NESTING_DEPTH);
+        let scan_filter = coordinator.scan_filter();
+        assert_eq!(scan_filter.extensions, "sqf,pbo");
+        let processor = coordinator.processor(&scan_filter, Arc::new(NoopProgress));
+
+        let output_dir = cache_dir.path().join("addons/ace_missions/missions");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("template.pbo"), b"PboPrefix").unwrap();
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("addons/ace_missions.pbo"),
+            expected_files: vec![
+                PboEntry { path: "missions/template.pbo".to_string(), size: None },
+                PboEntry { path: "missions/init.sqf".to_string(), size: None },
+                // Listed but not extracted
+                PboEntry { path: "missions/missing.pbo".to_string(), size: None },
+            ],
+            ..Default::default()
+        };
+
+        let nested = coordinator.nested_pbos(&processor, &[scan_result], &HashMap::new());
+        assert_eq!(nested.len(), 1);
+        assert_eq!(nested[0].path, output_dir.join("template.pbo"));
+        assert_eq!(nested[0].base_dir, output_dir.join("template"));
+        assert_eq!(
+            nested[0].label,
+            PathBuf::from(format!("{}!missions/template.pbo", Path::new("addons/ace_missions.pbo").display()))
+        );
+    }
+
+    #[test]
+    fn test_scan_filter_adds_bin_for_derapify() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let scan_filter = |extensions: &str| {
+            let filter = FileFilter::new(extensions);
+            ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+                .unwrap()
+                .with_derapify(Some(Arc::new(RapConverter)))
+                .scan_filter()
+                .extensions
+        };
+
+        assert_eq!(scan_filter("cpp,hpp"), "cpp,hpp,bin");
+        assert_eq!(scan_filter("sqf"), "sqf");
+        assert_eq!(scan_filter(""), "");
+    }
+
+    #[test]
+    fn test_check_disk_space() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).unwrap();
+
+        assert!(coordinator.check_disk_space(0).is_ok());
+        assert!(coordinator.check_disk_space(1024).is_ok());
+        let error = coordinator.check_disk_space(u64::MAX).unwrap_err();
+        assert!(error.to_string().contains("set ignore_disk_space"), "{error}");
+        assert!(coordinator.with_ignore_disk_space(true).check_disk_space(u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_thread_pool_built_on_first_use() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 2, 30)
+            .unwrap()
+            .with_background(true);
+        assert!(coordinator.thread_pool.get().is_none());
+        assert_eq!(coordinator.thread_pool().current_num_threads(), 2);
+
+        let pool = Arc::new(crate::utils::build_thread_pool(1).unwrap());
+        let coordinator = ScanCoordinator::new(input_dir.path(), cache_dir.path(), &filter, 2, 30)
+            .unwrap()
+            .with_thread_pool(pool.clone());
+        assert!(Arc::ptr_eq(coordinator.thread_pool(), &pool));
+    }
+}
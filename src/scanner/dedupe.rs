@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+use super::manifest::MANIFEST_FILE_NAME;
+use super::processor::STAGING_DIR_NAME;
+use super::store::OBJECTS_DIR_NAME;
+use crate::utils::{self, CHECKSUM_FILE_NAME};
+
+/// Files below this size aren't worth a link, since they fit in a single block
+pub const DEFAULT_DEDUPE_MIN_SIZE: u64 = 4096;
+
+/// Result of a `dedupe_dir` pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupeStats {
+    /// Files replaced by a hard link to an identical file
+    pub linked: usize,
+    /// Disk space freed by the links, in bytes
+    pub bytes_saved: u64,
+}
+
+/// Replace identical files below `dir` by hard links to a single copy
+///
+/// Only files of at least `min_size` bytes are considered, and files are only
+/// linked to others on the same file system. Files that already share their
+/// data are recognised by their file ID and left alone, so re-running the pass
+/// is cheap and changes nothing. Sidecar files, staging directories and the
+/// object store are skipped.
+pub fn dedupe_dir(dir: &Path, min_size: u64) -> Result<DedupeStats> {
+    // Files of the same size on the same device are the only dedupe candidates
+    let mut candidates: HashMap<(Option<u64>, u64), Vec<PathBuf>> = HashMap::new();
+    let walker = walkdir::WalkDir::new(utils::long_path(dir))
+        .into_iter()
+        .filter_entry(|e| e.depth() != 1 || (e.file_name() != STAGING_DIR_NAME && e.file_name() != OBJECTS_DIR_NAME));
+    for entry in walker.filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() || entry.file_name() == MANIFEST_FILE_NAME || entry.file_name() == CHECKSUM_FILE_NAME {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.len() >= min_size {
+            candidates.entry((device_id(&metadata), metadata.len()))
+                .or_default()
+                .push(entry.into_path());
+        }
+    }
+
+    let mut stats = DedupeStats::default();
+    for ((_, size), mut paths) in candidates {
+        if paths.len() < 2 {
+            continue;
+        }
+        paths.sort();
+
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            by_hash.entry(utils::sha256_file(&path)?).or_default().push(path);
+        }
+
+        for paths in by_hash.into_values() {
+            let (canonical, duplicates) = paths.split_first().expect("hash groups are never empty");
+            for duplicate in duplicates {
+                if same_file::is_same_file(canonical, duplicate)? {
+                    continue;
+                }
+                if replace_with_link(canonical, duplicate)? {
+                    stats.linked += 1;
+                    stats.bytes_saved += size;
+                }
+            }
+        }
+    }
+
+    debug!(
+        "Deduplicated {} files below {}, saving {} bytes",
+        stats.linked,
+        dir.display(),
+        stats.bytes_saved
+    );
+    Ok(stats)
+}
+
+/// Atomically replace `duplicate` by a hard link to `canonical`
+///
+/// Returns false, leaving `duplicate` in place, if the file system can't link the two.
+fn replace_with_link(canonical: &Path, duplicate: &Path) -> Result<bool> {
+    let mut temp_name = duplicate.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".dedupe");
+    let temp_path = duplicate.with_file_name(temp_name);
+
+    if let Err(e) = std::fs::hard_link(canonical, &temp_path) {
+        trace!("Not linking {} to {}: {}", duplicate.display(), canonical.display(), e);
+        return Ok(false);
+    }
+    if let Err(e) = std::fs::rename(&temp_path, duplicate) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e).with_context(|| format!("Failed to replace {} by a link", duplicate.display()));
+    }
+
+    trace!("Linked {} to {}", duplicate.display(), canonical.display());
+    Ok(true)
+}
+
+#[cfg(unix)]
+fn device_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// Not available on stable Rust elsewhere; linking across volumes fails and is skipped instead
+#[cfg(not(unix))]
+fn device_id(_metadata: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_utils::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dedupe_links_identical_files() {
+        let cache_dir = TempDir::new().unwrap();
+        let macros = vec![b'#'; 5000];
+        let licence = vec![b'L'; 5000];
+        write_file(&cache_dir.path().join("ace_medical/script_macros.hpp"), &macros);
+        write_file(&cache_dir.path().join("ace_common/script_macros.hpp"), &macros);
+        write_file(&cache_dir.path().join("cba_main/script_macros.hpp"), &macros);
+        write_file(&cache_dir.path().join("ace_common/LICENSE"), &licence);
+        write_file(&cache_dir.path().join("ace_common/small.hpp"), b"small");
+        write_file(&cache_dir.path().join("ace_medical/small.hpp"), b"small");
+
+        let stats = dedupe_dir(cache_dir.path(), DEFAULT_DEDUPE_MIN_SIZE).unwrap();
+        assert_eq!(stats, DedupeStats { linked: 2, bytes_saved: 10_000 });
+        assert!(same_file::is_same_file(
+            cache_dir.path().join("ace_common/script_macros.hpp"),
+            cache_dir.path().join("cba_main/script_macros.hpp"),
+        ).unwrap());
+        assert_eq!(std::fs::read(cache_dir.path().join("cba_main/script_macros.hpp")).unwrap(), macros);
+        // Below the threshold
+        assert!(!same_file::is_same_file(
+            cache_dir.path().join("ace_common/small.hpp"),
+            cache_dir.path().join("ace_medical/small.hpp"),
+        ).unwrap());
+
+        // Already linked files are recognised on a second pass
+        assert_eq!(dedupe_dir(cache_dir.path(), DEFAULT_DEDUPE_MIN_SIZE).unwrap(), DedupeStats::default());
+    }
+
+    #[test]
+    fn test_dedupe_skips_sidecars_and_staging() {
+        let cache_dir = TempDir::new().unwrap();
+        let content = vec![b'x'; 5000];
+        write_file(&cache_dir.path().join("ace_medical").join(MANIFEST_FILE_NAME), &content);
+        write_file(&cache_dir.path().join("ace_common").join(MANIFEST_FILE_NAME), &content);
+        write_file(&cache_dir.path().join(STAGING_DIR_NAME).join("ace_medical/fnc_heal.sqf"), &content);
+        write_file(&cache_dir.path().join("ace_common/fnc_heal.sqf"), &content);
+
+        assert_eq!(dedupe_dir(cache_dir.path(), DEFAULT_DEDUPE_MIN_SIZE).unwrap(), DedupeStats::default());
+    }
+}
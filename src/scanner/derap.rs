@@ -0,0 +1,375 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+
+/// Magic bytes at the start of every rapified (binarized) config
+pub const RAP_MAGIC: &[u8; 4] = b"\0raP";
+
+/// Nesting depth beyond which a rapified config is treated as corrupt
+const MAX_CLASS_DEPTH: usize = 64;
+
+/// Turns a rapified config back into config.cpp source
+pub trait ConfigConverter: std::fmt::Debug + Send + Sync {
+    fn derapify(&self, data: &[u8]) -> Result<String>;
+}
+
+/// Built-in decoder for the raP format written by the Arma tools
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RapConverter;
+
+impl ConfigConverter for RapConverter {
+    fn derapify(&self, data: &[u8]) -> Result<String> {
+        if !is_rapified(data) {
+            bail!("Not a rapified config");
+        }
+        let mut reader = RapReader { data, pos: 4, bodies: &mut HashSet::new() };
+        reader.u32()?;
+        reader.u32()?;
+        // Offset of the enum list, which config.cpp has no syntax for
+        reader.u32()?;
+        reader.bodies.insert(reader.pos);
+
+        let mut out = String::new();
+        reader.class_body(&mut out, 0)?;
+        Ok(out)
+    }
+}
+
+/// Whether `data` starts with the raP magic
+pub fn is_rapified(data: &[u8]) -> bool {
+    data.starts_with(RAP_MAGIC)
+}
+
+/// Result of `derapify_tree`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerapifyResult {
+    /// Rapified files converted, relative to the directory
+    pub converted: Vec<String>,
+    /// Rapified files that could not be converted, relative to the directory
+    pub unconverted: Vec<String>,
+}
+
+/// Write a `.cpp` file next to every rapified `.bin` file below `dir`
+///
+/// `.bin` files without the raP magic, like texture headers, are ignored, and
+/// existing `.cpp` files are never overwritten: the processor only leaves those the
+/// PBO ships, and removes ones converted for an earlier output. Failed conversions
+/// are logged and recorded instead of failing the whole tree.
+pub fn derapify_tree(dir: &Path, converter: &dyn ConfigConverter) -> Result<DerapifyResult> {
+    let mut result = DerapifyResult::default();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let is_bin = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin"));
+        if !entry.file_type().is_file() || !is_bin {
+            continue;
+        }
+        let rel_path = path.strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+        let data = std::fs::read(path)?;
+        if !is_rapified(&data) {
+            continue;
+        }
+
+        let cpp_path: PathBuf = path.with_extension("cpp");
+        if cpp_path.exists() {
+            debug!("Not derapifying {}: {} already exists", rel_path, cpp_path.display());
+            continue;
+        }
+        match converter.derapify(&data) {
+            Ok(source) => {
+                crate::utils::write_replacing(&cpp_path, source)
+                    .with_context(|| format!("Failed to write {}", cpp_path.display()))?;
+                debug!("Derapified {} to {}", rel_path, cpp_path.display());
+                result.converted.push(rel_path);
+            },
+            Err(e) => {
+                warn!("Failed to derapify {}: {:#}", path.display(), e);
+                result.unconverted.push(rel_path);
+            },
+        }
+    }
+    Ok(result)
+}
+
+struct RapReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    /// Offsets of the class bodies read so far, shared by the readers of nested
+    /// bodies
+    bodies: &'a mut HashSet<usize>,
+}
+
+impl RapReader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.data.len())
+            .with_context(|| format!("Unexpected end of data at offset {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    /// Little-endian base 128 integer
+    fn compressed_int(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Compressed integer too long at offset {}", self.pos)
+    }
+
+    fn asciiz(&mut self) -> Result<String> {
+        let len = self.data[self.pos.min(self.data.len())..]
+            .iter()
+            .position(|&byte| byte == 0)
+            .with_context(|| format!("Unterminated string at offset {}", self.pos))?;
+        let string = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+        self.pos += 1;
+        Ok(string)
+    }
+
+    /// Class body at the current position, written with `depth` levels of indentation
+    fn class_body(&mut self, out: &mut String, depth: usize) -> Result<()> {
+        if depth > MAX_CLASS_DEPTH {
+            bail!("Classes nested deeper than {} levels", MAX_CLASS_DEPTH);
+        }
+        let indent = "\t".repeat(depth);
+
+        // Parent class names are written by the caller
+        self.asciiz()?;
+        let entries = self.compressed_int()?;
+        for _ in 0..entries {
+            match self.u8()? {
+                0 => {
+                    let name = self.asciiz()?;
+                    let offset = self.u32()? as usize;
+                    // Classes sharing a body would be written again for every class
+                    // pointing at it, doubling the output with each level
+                    if !self.bodies.insert(offset) {
+                        bail!("Class {} reuses the body at offset {}", name, offset);
+                    }
+                    let mut body = RapReader { data: self.data, pos: offset, bodies: &mut *self.bodies };
+                    let parent = body.asciiz()?;
+                    body.pos = offset;
+                    if parent.is_empty() {
+                        writeln!(out, "{indent}class {name}")?;
+                    } else {
+                        writeln!(out, "{indent}class {name}: {parent}")?;
+                    }
+                    writeln!(out, "{indent}{{")?;
+                    body.class_body(out, depth + 1)?;
+                    writeln!(out, "{indent}}};")?;
+                },
+                1 => {
+                    let kind = self.u8()?;
+                    let name = self.asciiz()?;
+                    let value = self.scalar(kind)?;
+                    writeln!(out, "{indent}{name} = {value};")?;
+                },
+                2 => {
+                    let name = self.asciiz()?;
+                    let array = self.array(0)?;
+                    writeln!(out, "{indent}{name}[] = {array};")?;
+                },
+                3 => writeln!(out, "{indent}class {};", self.asciiz()?)?,
+                4 => writeln!(out, "{indent}delete {};", self.asciiz()?)?,
+                5 => {
+                    let flags = self.u32()?;
+                    let name = self.asciiz()?;
+                    let array = self.array(0)?;
+                    let operator = if flags == 1 { "+=" } else { "-=" };
+                    writeln!(out, "{indent}{name}[] {operator} {array};")?;
+                },
+                other => bail!("Unknown entry type {} at offset {}", other, self.pos - 1),
+            }
+        }
+        Ok(())
+    }
+
+    fn scalar(&mut self, kind: u8) -> Result<String> {
+        Ok(match kind {
+            0 => format!("\"{}\"", self.asciiz()?.replace('"', "\"\"")),
+            1 => self.f32()?.to_string(),
+            2 => self.i32()?.to_string(),
+            4 => self.asciiz()?,
+            6 => self.i64()?.to_string(),
+            other => bail!("Unknown value type {} at offset {}", other, self.pos),
+        })
+    }
+
+    fn array(&mut self, depth: usize) -> Result<String> {
+        if depth > MAX_CLASS_DEPTH {
+            bail!("Arrays nested deeper than {} levels", MAX_CLASS_DEPTH);
+        }
+        let count = self.compressed_int()?;
+        let mut elements = Vec::new();
+        for _ in 0..count {
+            let kind = self.u8()?;
+            elements.push(if kind == 3 { self.array(depth + 1)? } else { self.scalar(kind)? });
+        }
+        Ok(format!("{{{}}}", elements.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn asciiz(out: &mut Vec<u8>, value: &str) {
+        out.extend_from_slice(value.as_bytes());
+        out.push(0);
+    }
+
+    /// `class CfgPatches { class ace_medical: ace_main { name = "ACE3 ""Medical"""; units[] = {}; ... }; };`
+    fn rapified_config() -> Vec<u8> {
+        let mut data = RAP_MAGIC.to_vec();
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        // Root body: a single class whose body follows right after
+        asciiz(&mut data, "");
+        data.push(2);
+        data.push(0);
+        asciiz(&mut data, "CfgPatches");
+        let cfg_patches_offset = data.len() + 4 + 1 + "CfgFunctions".len() + 1;
+        data.extend_from_slice(&(cfg_patches_offset as u32).to_le_bytes());
+        data.push(3);
+        asciiz(&mut data, "CfgFunctions");
+
+        // CfgPatches body
+        asciiz(&mut data, "");
+        data.push(1);
+        data.push(0);
+        asciiz(&mut data, "ace_medical");
+        let medical_offset = data.len() + 4;
+        data.extend_from_slice(&(medical_offset as u32).to_le_bytes());
+
+        // ace_medical body
+        asciiz(&mut data, "ace_main");
+        data.push(5);
+        data.push(1);
+        data.push(0);
+        asciiz(&mut data, "name");
+        asciiz(&mut data, "ACE3 \"Medical\"");
+        data.push(1);
+        data.push(1);
+        asciiz(&mut data, "requiredVersion");
+        data.extend_from_slice(&2.5f32.to_le_bytes());
+        data.push(2);
+        asciiz(&mut data, "units");
+        data.push(0);
+        data.push(5);
+        data.extend_from_slice(&1u32.to_le_bytes());
+        asciiz(&mut data, "requiredAddons");
+        data.push(2);
+        data.push(0);
+        asciiz(&mut data, "ace_common");
+        data.push(3);
+        data.push(1);
+        data.push(2);
+        data.extend_from_slice(&200i32.to_le_bytes());
+        data.push(4);
+        asciiz(&mut data, "ace_old");
+        data
+    }
+
+    #[test]
+    fn test_derapify_config() {
+        let source = RapConverter.derapify(&rapified_config()).unwrap();
+        assert_eq!(source, "\
+class CfgPatches
+{
+\tclass ace_medical: ace_main
+\t{
+\t\tname = \"ACE3 \"\"Medical\"\"\";
+\t\trequiredVersion = 2.5;
+\t\tunits[] = {};
+\t\trequiredAddons[] += {\"ace_common\", {200}};
+\t\tdelete ace_old;
+\t};
+};
+class CfgFunctions;
+");
+    }
+
+    #[test]
+    fn test_derapify_rejects_invalid_data() {
+        assert!(RapConverter.derapify(b"class CfgPatches {};").is_err());
+        let mut truncated = rapified_config();
+        truncated.truncate(40);
+        assert!(RapConverter.derapify(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_derapify_rejects_shared_bodies() {
+        let mut data = RAP_MAGIC.to_vec();
+        data.extend_from_slice(&[0; 12]);
+        asciiz(&mut data, "");
+        data.push(2);
+        let body_offset = data.len() + 2 * (1 + 2 + 4);
+        for name in ["A", "B"] {
+            data.push(0);
+            asciiz(&mut data, name);
+            data.extend_from_slice(&(body_offset as u32).to_le_bytes());
+        }
+        asciiz(&mut data, "");
+        data.push(0);
+        let error = RapConverter.derapify(&data).unwrap_err();
+        assert_eq!(error.to_string(), format!("Class B reuses the body at offset {}", body_offset));
+
+        // Nor may a class contain the root
+        data[4 + 12 + 2 + 3..][..4].copy_from_slice(&16u32.to_le_bytes());
+        assert!(RapConverter.derapify(&data).is_err());
+    }
+
+    #[test]
+    fn test_derapify_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("addons/medical")).unwrap();
+        std::fs::create_dir_all(root.join("addons/common")).unwrap();
+        std::fs::write(root.join("addons/medical/config.bin"), rapified_config()).unwrap();
+        std::fs::write(root.join("texHeaders.bin"), b"0DHT").unwrap();
+        let mut corrupt = rapified_config();
+        corrupt.truncate(40);
+        std::fs::write(root.join("addons/common/config.bin"), corrupt).unwrap();
+
+        let result = derapify_tree(root, &RapConverter).unwrap();
+        assert_eq!(result.converted, vec!["addons/medical/config.bin".to_string()]);
+        assert_eq!(result.unconverted, vec!["addons/common/config.bin".to_string()]);
+        assert!(std::fs::read_to_string(root.join("addons/medical/config.cpp")).unwrap().starts_with("class CfgPatches"));
+        assert!(!root.join("texHeaders.cpp").exists());
+
+        // Existing sources are left alone on a second pass
+        std::fs::write(root.join("addons/medical/config.cpp"), b"// edited").unwrap();
+        let result = derapify_tree(root, &RapConverter).unwrap();
+        assert!(result.converted.is_empty());
+        assert_eq!(std::fs::read(root.join("addons/medical/config.cpp")).unwrap(), b"// edited");
+    }
+}
@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use serde::{Serialize, Serializer};
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::log_file::{timestamp, RotatingFile};
+use super::ndjson::NdjsonWriter;
+use super::types::{ExtractionStrategy, SkipReason};
+
+/// Structured progress event emitted while scanning and extracting
+///
+/// Paths are relative to the input directory. Serializes as an object tagged with
+/// `event`, like `{"event":"pbo_scanned","path":"@ace/addons/medical.pbo","matching":42}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ExtractionEvent {
+    /// A PBO was found by the directory walk and will be scanned
+    PboDiscovered { path: PathBuf },
+    /// A PBO was listed; `matching` entries pass the filter
+    PboScanned { path: PathBuf, matching: usize },
+    /// A PBO could not be listed
+    PboScanFailed { path: PathBuf, error: String },
+    /// A PBO was not extracted
+    PboSkipped { path: PathBuf, reason: SkipReason },
+    /// Extraction of a PBO started
+    ExtractionStarted { path: PathBuf },
+    /// Extraction of a PBO finished successfully using `strategy`; `pruned`
+    /// extracted files outside the filter were deleted
+    ExtractionFinished {
+        path: PathBuf,
+        files: usize,
+        #[serde(rename = "ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        strategy: ExtractionStrategy,
+        pruned: usize,
+    },
+    /// Extraction of a PBO failed
+    ExtractionFailed { path: PathBuf, error: String },
+    /// The run ended, with the totals of its report; `dropped_events` were not
+    /// written by the JSON event writer. Runs failing outright end with this too,
+    /// with the totals left at zero and the reason in `error`
+    RunFinished {
+        discovered: usize,
+        extracted: usize,
+        skipped: usize,
+        failed: usize,
+        scan_failed: usize,
+        files: usize,
+        bytes: u64,
+        #[serde(rename = "ms", serialize_with = "serialize_millis")]
+        duration: Duration,
+        interrupted: bool,
+        aborted: bool,
+        dropped_events: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Event as written to a log file, with the time it was sent
+#[derive(Serialize)]
+struct LoggedEvent<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a ExtractionEvent,
+}
+
+/// Optional sender for extraction events
+///
+/// Events are silently dropped when no channel is configured or the receiver is gone.
+#[derive(Debug, Clone, Default)]
+pub struct EventSink {
+    sender: Option<UnboundedSender<ExtractionEvent>>,
+    log_file: Option<Arc<RotatingFile>>,
+    json: Option<Arc<NdjsonWriter>>,
+}
+
+impl EventSink {
+    pub fn new(sender: Option<UnboundedSender<ExtractionEvent>>) -> Self {
+        Self { sender, log_file: None, json: None }
+    }
+
+    /// Also write every event to `log_file` as a line of JSON
+    pub fn with_log_file(mut self, log_file: Option<Arc<RotatingFile>>) -> Self {
+        self.log_file = log_file;
+        self
+    }
+
+    /// Also queue every event on `json`, without waiting for it to be written
+    pub fn with_json(mut self, json: Option<Arc<NdjsonWriter>>) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Events the JSON writer dropped so far
+    pub fn dropped_events(&self) -> u64 {
+        self.json.as_ref().map_or(0, |json| json.dropped())
+    }
+
+    pub fn send(&self, event: ExtractionEvent) {
+        if let Some(log_file) = &self.log_file {
+            if let Ok(line) = serde_json::to_string(&LoggedEvent { time: timestamp(), event: &event }) {
+                log_file.write_line(&line);
+            }
+        }
+        if let Some(json) = &self.json {
+            json.send(&event);
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    #[test]
+    fn test_send_without_channel() {
+        EventSink::default().send(ExtractionEvent::PboDiscovered { path: PathBuf::from("a.pbo") });
+    }
+
+    #[test]
+    fn test_send_after_receiver_dropped() {
+        let (tx, rx) = unbounded_channel();
+        let sink = EventSink::new(Some(tx));
+        drop(rx);
+        sink.send(ExtractionEvent::PboDiscovered { path: PathBuf::from("a.pbo") });
+    }
+
+    #[test]
+    fn test_send_delivers_events() {
+        let (tx, mut rx) = unbounded_channel();
+        let sink = EventSink::new(Some(tx));
+        sink.send(ExtractionEvent::PboScanned { path: PathBuf::from("a.pbo"), matching: 3 });
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            ExtractionEvent::PboScanned { path: PathBuf::from("a.pbo"), matching: 3 }
+        );
+    }
+
+    #[test]
+    fn test_events_written_to_log_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("extraction.log");
+        let log_file = Arc::new(RotatingFile::open(&path, Default::default()).unwrap());
+        let sink = EventSink::default().with_log_file(Some(log_file));
+        sink.send(ExtractionEvent::ExtractionFinished {
+            path: PathBuf::from("@ace/addons/medical.pbo"),
+            files: 42,
+            duration: Duration::from_millis(310),
+            strategy: ExtractionStrategy::Filtered,
+            pruned: 0,
+        });
+        sink.send(ExtractionEvent::PboSkipped { path: PathBuf::from("a.pbo"), reason: SkipReason::AlreadyExtracted });
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]["time"].is_string());
+        assert_eq!(lines[0]["event"], "extraction_finished");
+        assert_eq!((lines[0]["files"].as_u64(), lines[0]["ms"].as_u64()), (Some(42), Some(310)));
+        assert_eq!(lines[0]["strategy"], "filtered");
+        assert_eq!((lines[1]["event"].as_str(), lines[1]["reason"].as_str()), (Some("pbo_skipped"), Some("already_extracted")));
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use anyhow::{Context, Result};
+
+use super::types::ExtractionStrategy;
+
+/// Directory in the cache directory the logs of failed extractions are written to
+pub const FAILURE_LOG_DIR_NAME: &str = "failures";
+
+/// Log of a failed extraction whose base directory is `rel_base_dir` in the cache
+///
+/// Logs mirror the base directories below `<cache>/failures`, with `.log` appended,
+/// so a PBO that never extracted doesn't leave an output directory behind.
+pub fn failure_log_path(cache_dir: &Path, rel_base_dir: &Path) -> PathBuf {
+    let mut path = cache_dir.join(FAILURE_LOG_DIR_NAME).join(rel_base_dir).into_os_string();
+    path.push(".log");
+    PathBuf::from(path)
+}
+
+/// What a run does when PBOs fail to extract
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FailurePolicy {
+    /// Extract every PBO regardless of failures
+    #[default]
+    ContinueAll,
+    /// Stop starting PBOs after the first failure
+    FailFast,
+    /// Stop starting PBOs once `max_failures` PBOs failed, or more than
+    /// `max_failure_ratio` of the PBOs in the run; unset limits don't apply
+    Threshold {
+        max_failures: Option<usize>,
+        max_failure_ratio: Option<f64>,
+    },
+}
+
+impl FailurePolicy {
+    /// Whether `failures` out of `total` PBOs stop the run
+    pub fn is_exceeded(&self, failures: usize, total: usize) -> bool {
+        match *self {
+            Self::ContinueAll => false,
+            Self::FailFast => failures > 0,
+            Self::Threshold { max_failures, max_failure_ratio } => {
+                max_failures.is_some_and(|max| failures >= max)
+                    || max_failure_ratio.is_some_and(|ratio| failures as f64 > ratio * total as f64)
+            },
+        }
+    }
+}
+
+/// Failures counted against a `FailurePolicy`, shared by the processors of a run
+#[derive(Debug, Default)]
+pub struct FailureTracker {
+    policy: FailurePolicy,
+    /// PBOs the failure ratio is taken of
+    total: AtomicUsize,
+    failures: AtomicUsize,
+    aborted: AtomicBool,
+}
+
+impl FailureTracker {
+    pub fn new(policy: FailurePolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Count `count` more PBOs towards the total of the run
+    pub fn add_pbos(&self, count: usize) {
+        self.total.fetch_add(count, Ordering::SeqCst);
+    }
+
+    /// Count a failed PBO, returning true only for the failure that aborts the run
+    pub fn record_failure(&self) -> bool {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        self.policy.is_exceeded(failures, self.total.load(Ordering::SeqCst))
+            && !self.aborted.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::SeqCst)
+    }
+
+    /// Whether the failure policy stopped the run
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    pub fn policy(&self) -> FailurePolicy {
+        self.policy
+    }
+}
+
+/// Every strategy of the fallback chain failed to extract a PBO
+#[derive(Debug)]
+pub struct StrategyFailures {
+    /// Each attempted strategy with its full error chain
+    pub attempts: Vec<(ExtractionStrategy, String)>,
+    /// Message of the last attempt's error
+    pub last_error: String,
+}
+
+impl std::fmt::Display for StrategyFailures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let strategies = self.attempts.iter()
+            .map(|(strategy, _)| strategy.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "All extraction attempts failed ({}): {}", strategies.join(", "), self.last_error)
+    }
+}
+
+impl std::error::Error for StrategyFailures {}
+
+/// What went wrong extracting a PBO, for someone looking into it later
+#[derive(Debug, Clone)]
+pub struct FailureLog {
+    pub pbo: String,
+    /// PBO hash as stored in manifests, or why it couldn't be taken
+    pub hash: String,
+    /// Seconds since the Unix epoch
+    pub failed_at: u64,
+    /// Options of the run as `name: value`
+    pub options: Vec<(String, String)>,
+    pub attempts: Vec<(ExtractionStrategy, String)>,
+    /// Full error chain of the failure
+    pub error: String,
+}
+
+impl FailureLog {
+    /// Build the log of `error`, taking the attempted strategies from it when present
+    pub fn new(pbo: &Path, hash: String, failed_at: u64, error: &anyhow::Error) -> Self {
+        let attempts = error.chain()
+            .find_map(|cause| cause.downcast_ref::<StrategyFailures>())
+            .map(|failures| failures.attempts.clone())
+            .unwrap_or_default();
+        Self {
+            pbo: pbo.display().to_string(),
+            hash,
+            failed_at,
+            options: Vec::new(),
+            attempts,
+            error: format!("{:#}", error),
+        }
+    }
+
+    pub fn with_option(mut self, name: &str, value: impl ToString) -> Self {
+        self.options.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut log = String::new();
+        let _ = writeln!(log, "PBO: {}", self.pbo);
+        let _ = writeln!(log, "Hash: {}", self.hash);
+        let _ = writeln!(log, "Failed at: {}", self.failed_at);
+        let _ = writeln!(log, "Error: {}", self.error);
+        if !self.options.is_empty() {
+            let _ = writeln!(log, "\nOptions:");
+            for (name, value) in &self.options {
+                let _ = writeln!(log, "  {}: {}", name, value);
+            }
+        }
+        if !self.attempts.is_empty() {
+            let _ = writeln!(log, "\nAttempts:");
+            for (strategy, error) in &self.attempts {
+                let _ = writeln!(log, "  {}: {}", strategy, error);
+            }
+        }
+        log
+    }
+
+    /// Write the log to `path`, replacing an earlier one
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        crate::utils::write_replacing(path, self.render())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Remove the log of an earlier failure at `path`, returning whether there was one
+    pub fn remove(path: &Path) -> Result<bool> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_limits() {
+        assert!(!FailurePolicy::ContinueAll.is_exceeded(100, 100));
+        assert!(FailurePolicy::FailFast.is_exceeded(1, 100));
+
+        let max_failures = FailurePolicy::Threshold { max_failures: Some(3), max_failure_ratio: None };
+        assert!(!max_failures.is_exceeded(2, 100));
+        assert!(max_failures.is_exceeded(3, 100));
+
+        let ratio = FailurePolicy::Threshold { max_failures: None, max_failure_ratio: Some(0.1) };
+        assert!(!ratio.is_exceeded(10, 100));
+        assert!(ratio.is_exceeded(11, 100));
+    }
+
+    #[test]
+    fn test_only_one_failure_aborts() {
+        let tracker = FailureTracker::new(FailurePolicy::Threshold { max_failures: Some(2), max_failure_ratio: None });
+        tracker.add_pbos(10);
+        assert!(!tracker.record_failure());
+        assert!(tracker.record_failure());
+        assert!(!tracker.record_failure());
+        assert!(tracker.is_aborted());
+        assert_eq!(tracker.failures(), 3);
+    }
+
+    #[test]
+    fn test_failure_log() {
+        let failures = StrategyFailures {
+            attempts: vec![
+                (ExtractionStrategy::Filtered, "Operation timed out after 30 seconds".to_string()),
+                (ExtractionStrategy::Direct, "Invalid PBO header: unexpected end of file".to_string()),
+            ],
+            last_error: "Invalid PBO header".to_string(),
+        };
+        assert_eq!(failures.to_string(), "All extraction attempts failed (filtered, direct): Invalid PBO header");
+
+        let error = anyhow::Error::new(failures).context("Extraction of ace_medical.pbo failed");
+        let log = FailureLog::new(Path::new("addons/ace_medical.pbo"), "abc123".to_string(), 1_700_000_000, &error)
+            .with_option("Timeout", "30 seconds");
+        assert_eq!(log.attempts.len(), 2);
+        let rendered = log.render();
+        assert!(rendered.starts_with("PBO: addons/ace_medical.pbo\nHash: abc123\nFailed at: 1700000000\n"));
+        assert!(rendered.contains("Error: Extraction of ace_medical.pbo failed: All extraction attempts failed"));
+        assert!(rendered.contains("\n  Timeout: 30 seconds\n"));
+        assert!(rendered.contains("\n  direct: Invalid PBO header: unexpected end of file\n"));
+
+        let cache_dir = tempfile::TempDir::new().unwrap();
+        let path = failure_log_path(cache_dir.path(), Path::new("addons/ace_medical"));
+        assert_eq!(path, cache_dir.path().join("failures/addons/ace_medical.log"));
+        log.write(&path).unwrap();
+        assert!(path.is_file());
+        assert!(!cache_dir.path().join("addons").exists());
+        assert!(FailureLog::remove(&path).unwrap());
+        assert!(!FailureLog::remove(&path).unwrap());
+    }
+}
@@ -0,0 +1,324 @@
+use std::path::Path;
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+
+use crate::utils::{matches_every_extension, matches_extension, normalize_entry_path};
+
+/// Filter deciding which entries inside a PBO are extracted
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// File extensions to extract (comma-separated, empty or `*` matches everything)
+    pub extensions: String,
+    /// Directory prefixes inside the PBO to restrict extraction to (empty matches everything)
+    pub include_paths: Vec<String>,
+    /// Minimum entry size in bytes (inclusive)
+    pub min_file_size: Option<u64>,
+    /// Maximum entry size in bytes (inclusive)
+    pub max_file_size: Option<u64>,
+    /// Drop compiled `.sqfc` entries whose `.sqf` source is in the same PBO
+    pub prefer_source: bool,
+}
+
+/// Path of the source script a compiled `.sqfc` entry was built from
+pub fn compiled_source(entry: &str) -> Option<String> {
+    let is_compiled = Path::new(entry).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("sqfc"));
+    is_compiled.then(|| entry[..entry.len() - 1].to_string())
+}
+
+impl FileFilter {
+    pub fn new(extensions: &str) -> Self {
+        Self {
+            extensions: extensions.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_include_paths(mut self, include_paths: Vec<String>) -> Self {
+        self.include_paths = include_paths;
+        self
+    }
+
+    /// Also match `extension`, unless the extension list already matches everything
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        let included = self.extensions.split(',').any(|ext| ext.trim().eq_ignore_ascii_case(extension));
+        if !matches_every_extension(&self.extensions) && !included {
+            self.extensions = format!("{},{}", self.extensions, extension);
+        }
+        self
+    }
+
+    /// Check whether a PBO-internal entry path passes all filters
+    pub fn matches(&self, entry: &str) -> bool {
+        let entry = normalize_entry_path(entry);
+        matches_extension(Path::new(&entry), &self.extensions)
+            && self.matches_include_paths(&entry)
+    }
+
+    /// Check an entry size against the size limits
+    ///
+    /// Entries with an unknown size always pass.
+    pub fn matches_size(&self, size: Option<u64>) -> bool {
+        let Some(size) = size else {
+            return true;
+        };
+        self.min_file_size.is_none_or(|min| size >= min)
+            && self.max_file_size.is_none_or(|max| size <= max)
+    }
+
+    /// Check a normalized entry path against the include path prefixes
+    fn matches_include_paths(&self, entry: &str) -> bool {
+        if self.include_paths.is_empty() {
+            return true;
+        }
+
+        let entry = entry.to_lowercase();
+        self.include_paths.iter().any(|prefix| {
+            let mut prefix = normalize_entry_path(prefix).to_lowercase();
+            if prefix.is_empty() {
+                return true;
+            }
+            if !prefix.ends_with('/') {
+                prefix.push('/');
+            }
+            entry.starts_with(&prefix)
+        })
+    }
+
+    /// Whether `entry` is a compiled script to drop because `has_entry` reports its source
+    pub fn is_superseded(&self, entry: &str, has_entry: impl Fn(&str) -> bool) -> bool {
+        self.prefer_source && compiled_source(entry).is_some_and(|source| has_entry(&source))
+    }
+
+    /// Whether any filter beyond the extension list is active
+    pub fn has_path_filters(&self) -> bool {
+        !self.include_paths.is_empty()
+    }
+
+    /// Whether a minimum or maximum entry size is set
+    pub fn has_size_limits(&self) -> bool {
+        self.min_file_size.is_some() || self.max_file_size.is_some()
+    }
+
+    /// Whether every entry passes, with no extension list, path filter, size limit
+    /// or preference for sources
+    pub fn matches_everything(&self) -> bool {
+        matches_every_extension(&self.extensions)
+            && !self.has_path_filters()
+            && !self.has_size_limits()
+            && !self.prefer_source
+    }
+
+    /// Filter string handed to the extraction tool, if any
+    pub fn tool_filter(&self) -> Option<String> {
+        if matches_every_extension(&self.extensions) {
+            None
+        } else {
+            Some(self.extensions.clone())
+        }
+    }
+}
+
+/// Glob patterns matched against paths relative to the input directory
+///
+/// Patterns are matched case-insensitively against both the final path
+/// component and the full relative path, so plain names work as well.
+#[derive(Debug, Clone, Default)]
+pub struct PathPatterns {
+    patterns: Vec<Pattern>,
+}
+
+impl PathPatterns {
+    /// Compile patterns, naming `kind` in the error for an invalid one
+    pub fn new(patterns: &[String], kind: &str) -> Result<Self> {
+        let patterns = patterns.iter()
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid {} pattern: {}", kind, p)))
+            .collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Check whether any pattern matches the relative path or its last component
+    pub fn matches(&self, rel_path: &Path) -> bool {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        let name = rel_path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let rel_path = normalize_entry_path(&rel_path.to_string_lossy());
+
+        self.patterns.iter().any(|pattern| {
+            pattern.matches_with(&name, options) || pattern.matches_with(&rel_path, options)
+        })
+    }
+}
+
+/// Include/exclude glob patterns applied to PBO files during the directory walk
+#[derive(Debug, Clone, Default)]
+pub struct PboFilter {
+    include: PathPatterns,
+    exclude: PathPatterns,
+}
+
+impl PboFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: PathPatterns::new(include, "PBO include")?,
+            exclude: PathPatterns::new(exclude, "PBO exclude")?,
+        })
+    }
+
+    /// Check a PBO path relative to the input directory against the patterns
+    pub fn matches(&self, rel_path: &Path) -> bool {
+        let included = self.include.is_empty() || self.include.matches(rel_path);
+        included && !self.exclude.matches(rel_path)
+    }
+
+    /// Whether any patterns are set, so some PBOs may be left out
+    pub fn is_restricted(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_extension() {
+        assert_eq!(FileFilter::new("sqf,hpp").with_extension("pbo").extensions, "sqf,hpp,pbo");
+        assert_eq!(FileFilter::new("sqf, PBO").with_extension("pbo").extensions, "sqf, PBO");
+        assert_eq!(FileFilter::new("").with_extension("pbo").extensions, "");
+        assert_eq!(FileFilter::new("*").with_extension("pbo").extensions, "*");
+        assert!(FileFilter::new("sqf").with_extension("pbo").matches("missions/template.pbo"));
+    }
+
+    #[test]
+    fn test_matches_everything() {
+        assert!(FileFilter::new("").matches_everything());
+        assert!(FileFilter::new("*").matches_everything());
+        assert_eq!(FileFilter::new("*").tool_filter(), None);
+        assert!(!FileFilter::new("sqf").matches_everything());
+        assert!(!FileFilter::new("").with_include_paths(vec!["functions".to_string()]).matches_everything());
+        assert!(!FileFilter { min_file_size: Some(1), ..FileFilter::new("*") }.matches_everything());
+    }
+
+    #[test]
+    fn test_compiled_source() {
+        assert_eq!(compiled_source("functions/fnc_heal.sqfc"), Some("functions/fnc_heal.sqf".to_string()));
+        assert_eq!(compiled_source("functions/FNC_HEAL.SQFC"), Some("functions/FNC_HEAL.SQF".to_string()));
+        assert_eq!(compiled_source("functions/fnc_heal.sqf"), None);
+        assert_eq!(compiled_source("sqfc"), None);
+    }
+
+    #[test]
+    fn test_matches_without_include_paths() {
+        let filter = FileFilter::new("sqf");
+        assert!(filter.matches("functions/fnc_test.sqf"));
+        assert!(filter.matches("config.sqf"));
+        assert!(!filter.matches("config.cpp"));
+    }
+
+    #[test]
+    fn test_include_paths_with_extension_filter() {
+        let filter = FileFilter::new("sqf")
+            .with_include_paths(vec!["functions".to_string(), "ui/".to_string()]);
+        assert!(filter.matches("functions/fnc_test.sqf"));
+        assert!(filter.matches("ui/display.sqf"));
+        assert!(!filter.matches("functions/fnc_test.hpp"));
+        assert!(!filter.matches("dev/debug.sqf"));
+        assert!(!filter.matches("functionsExtra/fnc_test.sqf"));
+    }
+
+    #[test]
+    fn test_include_paths_normalize_separators() {
+        let filter = FileFilter::new("")
+            .with_include_paths(vec!["\\Functions\\".to_string()]);
+        assert!(filter.matches("functions\\fnc_test.sqf"));
+        assert!(filter.matches("FUNCTIONS/fnc_test.paa"));
+        assert!(!filter.matches("dev\\functions\\fnc_test.sqf"));
+    }
+
+    #[test]
+    fn test_nested_include_paths() {
+        let filter = FileFilter::new("sqf")
+            .with_include_paths(vec!["functions/medical/".to_string()]);
+        assert!(filter.matches("functions/medical/fnc_heal.sqf"));
+        assert!(filter.matches("functions\\medical\\sub\\fnc_heal.sqf"));
+        assert!(!filter.matches("functions/fnc_heal.sqf"));
+        assert!(!filter.matches("functions/medical_old/fnc_heal.sqf"));
+    }
+
+    #[test]
+    fn test_size_limits_boundaries() {
+        let mut filter = FileFilter::new("");
+        filter.min_file_size = Some(10);
+        filter.max_file_size = Some(20);
+
+        assert!(!filter.matches_size(Some(9)));
+        assert!(filter.matches_size(Some(10)));
+        assert!(filter.matches_size(Some(20)));
+        assert!(!filter.matches_size(Some(21)));
+        assert!(filter.matches_size(None));
+    }
+
+    #[test]
+    fn test_size_limits_unset() {
+        let filter = FileFilter::new("");
+        assert!(!filter.has_size_limits());
+        assert!(filter.matches_size(Some(0)));
+        assert!(filter.matches_size(Some(u64::MAX)));
+    }
+
+    #[test]
+    fn test_pbo_filter_include() {
+        let filter = PboFilter::new(&["ace_*.pbo".to_string()], &[]).unwrap();
+        assert!(filter.matches(Path::new("@ace/addons/ace_medical.pbo")));
+        assert!(filter.matches(Path::new("ACE_Common.PBO")));
+        assert!(!filter.matches(Path::new("@cba/addons/cba_main.pbo")));
+    }
+
+    #[test]
+    fn test_pbo_filter_exclude() {
+        let filter = PboFilter::new(&[], &["*_c.pbo".to_string(), "@terrain/*".to_string()]).unwrap();
+        assert!(filter.matches(Path::new("@ace/addons/ace_medical.pbo")));
+        assert!(!filter.matches(Path::new("@ace/addons/ace_medical_c.pbo")));
+        assert!(!filter.matches(Path::new("@terrain/addons/map.pbo")));
+    }
+
+    #[test]
+    fn test_pbo_filter_exclude_wins() {
+        let filter = PboFilter::new(&["ace_*".to_string()], &["ace_*_c.pbo".to_string()]).unwrap();
+        assert!(filter.matches(Path::new("ace_medical.pbo")));
+        assert!(!filter.matches(Path::new("ace_medical_c.pbo")));
+    }
+
+    #[test]
+    fn test_pbo_filter_invalid_pattern() {
+        assert!(PboFilter::new(&["[".to_string()], &[]).is_err());
+        assert!(PboFilter::default().matches(Path::new("anything.pbo")));
+    }
+
+    #[test]
+    fn test_path_patterns_names_and_globs() {
+        let patterns = PathPatterns::new(
+            &["keys".to_string(), "@*/optionals".to_string()],
+            "ignore",
+        ).unwrap();
+        assert!(patterns.matches(Path::new("@ace/keys")));
+        assert!(patterns.matches(Path::new("Keys")));
+        assert!(patterns.matches(Path::new("@ace/optionals")));
+        assert!(!patterns.matches(Path::new("optionals")));
+        assert!(!patterns.matches(Path::new("@ace/addons")));
+    }
+
+    #[test]
+    fn test_tool_filter() {
+        assert_eq!(FileFilter::new("").tool_filter(), None);
+        assert_eq!(FileFilter::new("sqf,hpp").tool_filter(), Some("sqf,hpp".to_string()));
+    }
+}
@@ -0,0 +1,700 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+
+use super::manifest::{ExtractionManifest, ManifestFile};
+use super::types::{IndexStats, PboFailure};
+use super::versions::{retained_versions, RetainedVersion};
+use crate::utils::{matches_extension, native_entry_path, normalize_entry_path};
+
+/// File name of the index written into the cache directory
+pub const INDEX_FILE_NAME: &str = "index.json";
+
+/// Extracted file in the index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Path in the game's virtual file system: the PBO prefix joined with the
+    /// entry path, using forward slashes
+    pub virtual_path: String,
+    /// Extracted file, relative to the cache directory
+    pub path: PathBuf,
+    /// Source PBO, relative to the input directory
+    pub source: PathBuf,
+    pub size: u64,
+}
+
+/// Extraction of an indexed PBO, used to tell whether its entries are current
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedSource {
+    pub hash: String,
+    pub extracted_at: u64,
+    /// Display name of the mod the PBO belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mod_name: Option<String>,
+    /// Earlier outputs kept next to the current one, see `versions::replace_retaining`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<RetainedVersion>,
+    /// Whether the PBO's expected files were taken from its extraction rather than a
+    /// listing, see `ExtractionManifest::listed_after_extraction`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub listed_after_extraction: bool,
+}
+
+/// Output of one PBO as read by `SourceUpdate::read`, to bring its index entries up to date
+///
+/// Reading touches the disk, so it can run on any thread; the updates are then
+/// applied one at a time with `ExtractionIndex::apply_update`, in any order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceUpdate {
+    /// Source PBO, relative to the input directory
+    pub source: PathBuf,
+    pub base_dir: PathBuf,
+    pub output: SourceOutput,
+}
+
+/// What `SourceUpdate::read` found in the base directory of a PBO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceOutput {
+    /// Output with a manifest, to index
+    Extracted { source: IndexedSource, files: Vec<ManifestFile> },
+    /// Nothing on disk to index: the PBO was streamed or its base directory is gone
+    Gone,
+    /// Base directory without a readable manifest, like a failed extraction leaves
+    Unreadable,
+}
+
+impl SourceUpdate {
+    /// Read the manifest in the base directory of `source`
+    pub fn read(source: &Path, base_dir: &Path, mod_name: Option<&str>) -> Self {
+        let output = match ExtractionManifest::read(base_dir) {
+            Ok(manifest) if !manifest.streamed => SourceOutput::Extracted {
+                source: IndexedSource {
+                    hash: manifest.hash,
+                    extracted_at: manifest.extracted_at,
+                    mod_name: mod_name.map(str::to_string),
+                    versions: retained_versions(base_dir),
+                    listed_after_extraction: manifest.listed_after_extraction,
+                },
+                files: manifest.files,
+            },
+            Ok(_) => SourceOutput::Gone,
+            Err(_) if !base_dir.exists() => SourceOutput::Gone,
+            Err(_) => SourceOutput::Unreadable,
+        };
+        Self { source: source.to_owned(), base_dir: base_dir.to_owned(), output }
+    }
+}
+
+/// Step a PBO failed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureStage {
+    Listing,
+    Extraction,
+    /// Output found incomplete by a cache verification
+    Verification,
+}
+
+/// Last failure of a PBO that hasn't been extracted successfully since
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedSource {
+    pub stage: FailureStage,
+    /// Error message, followed by the messages of its causes
+    pub error: String,
+    /// Seconds since the Unix epoch
+    pub failed_at: u64,
+    /// Consecutive runs the PBO failed in, since it last changed
+    pub attempts: u32,
+    /// Hash of the PBO when it last failed, as stored in manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// PBO that failed too many runs in a row, skipped until its hash changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedSource {
+    /// Hash of the PBO when it was quarantined, as stored in manifests
+    pub hash: String,
+    /// Error of the last failure before the quarantine
+    pub error: String,
+    /// Seconds since the Unix epoch
+    pub quarantined_at: u64,
+    /// Copy of the PBO, relative to the cache directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy: Option<PathBuf>,
+}
+
+/// Index of the files extracted into a cache directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractionIndex {
+    /// Indexed PBOs by path relative to the input directory
+    pub sources: BTreeMap<PathBuf, IndexedSource>,
+    /// Files sorted by virtual path
+    pub files: Vec<IndexEntry>,
+    /// PBOs whose last listing or extraction failed, by path relative to the input
+    /// directory. They keep the entries of their last successful extraction
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failed: BTreeMap<PathBuf, FailedSource>,
+    /// Quarantined PBOs by path relative to the input directory. They stay in
+    /// `failed` too
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quarantined: BTreeMap<PathBuf, QuarantinedSource>,
+    /// Directory the file paths are relative to
+    #[serde(skip)]
+    root: PathBuf,
+}
+
+impl ExtractionIndex {
+    /// Empty index of the cache directory `root`
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Read an index written by an extraction run
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read index: {}", path.display()))?;
+        let mut index: Self = serde_json::from_str(&json)
+            .with_context(|| format!("Malformed index: {}", path.display()))?;
+        index.root = path.parent().unwrap_or(Path::new("")).to_owned();
+        Ok(index)
+    }
+
+    /// Counts of the indexed, failed and quarantined PBOs and indexed files
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            sources: self.sources.len(),
+            files: self.files.len(),
+            failed: self.failed.len(),
+            quarantined: self.quarantined.len(),
+        }
+    }
+
+    /// Write the index to `path`, replacing it atomically
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write index: {}", partial.display()))?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("Failed to move index into place: {}", path.display()))
+    }
+
+    /// Bring the entries of `source` up to date with the manifest in its base directory
+    ///
+    /// Entries are kept when the manifest describes the extraction they were built
+    /// from, and replaced otherwise. A base directory without a readable manifest
+    /// keeps the entries, as failed extractions leave the previous output in place,
+    /// unless the directory is gone. Streamed PBOs have nothing on disk to index.
+    /// Returns whether the existing entries were kept.
+    pub fn update_source(&mut self, source: &Path, base_dir: &Path, mod_name: Option<&str>) -> Result<bool> {
+        self.apply_update(SourceUpdate::read(source, base_dir, mod_name))
+    }
+
+    /// Apply an update read with `SourceUpdate::read`, like `update_source`
+    pub fn apply_update(&mut self, update: SourceUpdate) -> Result<bool> {
+        Ok(self.apply_updates([update])? == 1)
+    }
+
+    /// Apply updates read with `SourceUpdate::read` in order, returning how many
+    /// kept the existing entries
+    ///
+    /// The index ends up like after `apply_update` for each update, but files are
+    /// dropped and sorted once for the whole batch rather than once per PBO. An
+    /// error leaves the updates before the failing one applied.
+    pub fn apply_updates(&mut self, updates: impl IntoIterator<Item = SourceUpdate>) -> Result<usize> {
+        let mut kept = 0;
+        let mut replaced = HashSet::new();
+        let mut added: Vec<IndexEntry> = Vec::new();
+        let mut batched = HashSet::new();
+        let mut error = None;
+        for SourceUpdate { source, base_dir, output } in updates {
+            let (indexed, files) = match output {
+                SourceOutput::Extracted { source, files } => (source, files),
+                SourceOutput::Gone => {
+                    if self.sources.remove(&source).is_some() {
+                        if batched.contains(&source) {
+                            added.retain(|file| file.source != source);
+                        }
+                        replaced.insert(source);
+                    }
+                    continue;
+                },
+                SourceOutput::Unreadable => {
+                    if self.sources.contains_key(&source) {
+                        kept += 1;
+                    }
+                    continue;
+                },
+            };
+            if self.sources.get(&source) == Some(&indexed) {
+                kept += 1;
+                continue;
+            }
+
+            let base_path = base_dir.strip_prefix(&self.root).unwrap_or(&base_dir);
+            let entries = files.into_iter()
+                .map(|file| Ok(IndexEntry {
+                    path: base_path.join(native_entry_path(&file.path)?),
+                    virtual_path: file.path,
+                    source: source.clone(),
+                    size: file.size,
+                }))
+                .collect::<Result<Vec<_>>>();
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                },
+            };
+            if self.sources.insert(source.clone(), indexed).is_some() {
+                if batched.contains(&source) {
+                    added.retain(|file| file.source != source);
+                }
+                replaced.insert(source.clone());
+            }
+            added.extend(entries);
+            batched.insert(source);
+        }
+
+        if !replaced.is_empty() {
+            self.files.retain(|file| !replaced.contains(&file.source));
+        }
+        self.files.extend(added);
+        self.files.sort_by(|a, b| (&a.virtual_path, &a.source).cmp(&(&b.virtual_path, &b.source)));
+        match error {
+            Some(e) => Err(e),
+            None => Ok(kept),
+        }
+    }
+
+    /// Record a failed listing or extraction at `now`, in seconds since the Unix epoch
+    ///
+    /// Attempts start over when the PBO's hash differs from its last failure.
+    pub fn record_failure(&mut self, failure: &PboFailure, stage: FailureStage, hash: Option<String>, now: u64) {
+        let attempts = match self.failed.get(&failure.path) {
+            Some(failed) if failed.hash.is_some() && hash.is_some() && failed.hash != hash => 0,
+            Some(failed) => failed.attempts,
+            None => 0,
+        };
+        self.failed.insert(failure.path.clone(), FailedSource {
+            stage,
+            error: failure.message(),
+            failed_at: now,
+            attempts: attempts.saturating_add(1),
+            hash,
+        });
+    }
+
+    /// Whether `source`, with the hash `hash`, failed at least `max_attempts` runs in a
+    /// row without changing
+    pub fn is_given_up(&self, source: &Path, hash: &str, max_attempts: u32) -> bool {
+        self.failed.get(source)
+            .is_some_and(|failed| failed.attempts >= max_attempts && failed.hash.as_deref() == Some(hash))
+    }
+
+    /// Forget the failure of a PBO processed successfully
+    pub fn clear_failure(&mut self, source: &Path) {
+        self.failed.remove(source);
+    }
+
+    /// Failed PBOs with the step they failed in and the error, sorted by path
+    pub fn failed_entries(&self) -> impl Iterator<Item = (&Path, FailureStage, &str)> {
+        self.failed.iter().map(|(path, failed)| (path.as_path(), failed.stage, failed.error.as_str()))
+    }
+
+    /// Failed PBOs not quarantined yet that failed at least `after_failures` runs in a row
+    pub fn due_for_quarantine(&self, after_failures: u32) -> Vec<PathBuf> {
+        self.failed.iter()
+            .filter(|(source, failed)| failed.attempts >= after_failures && !self.quarantined.contains_key(*source))
+            .map(|(source, _)| source.clone())
+            .collect()
+    }
+
+    /// Quarantine a failed PBO at `now` while its hash stays `hash`
+    pub fn quarantine(&mut self, source: &Path, hash: String, copy: Option<PathBuf>, now: u64) {
+        let error = self.failed.get(source).map(|failed| failed.error.clone()).unwrap_or_default();
+        self.quarantined.insert(source.to_owned(), QuarantinedSource {
+            hash,
+            error,
+            quarantined_at: now,
+            copy,
+        });
+    }
+
+    /// Whether runs skip `source`, a quarantined PBO that still has the hash `hash`
+    pub fn is_quarantined(&self, source: &Path, hash: &str) -> bool {
+        self.quarantined.get(source).is_some_and(|quarantined| quarantined.hash == hash)
+    }
+
+    /// Lift the quarantine of `source`, also forgetting its earlier failures
+    pub fn release(&mut self, source: &Path) -> Option<QuarantinedSource> {
+        self.failed.remove(source);
+        self.quarantined.remove(source)
+    }
+
+    /// Quarantined PBOs sorted by path
+    pub fn quarantined_entries(&self) -> impl Iterator<Item = (&Path, &QuarantinedSource)> {
+        self.quarantined.iter().map(|(path, quarantined)| (path.as_path(), quarantined))
+    }
+
+    /// Drop the entries, failures and quarantines of every source `keep` returns false for
+    pub fn retain_sources<F>(&mut self, keep: F)
+    where
+        F: Fn(&Path) -> bool,
+    {
+        self.quarantined.retain(|source, _| keep(source));
+        self.failed.retain(|source, _| keep(source));
+        self.sources.retain(|source, _| keep(source));
+        let sources = &self.sources;
+        self.files.retain(|file| sources.contains_key(&file.source));
+    }
+
+    /// Record the output of `source` as broken at `now`, as found by a cache verification
+    ///
+    /// Its entries are dropped, so lookups no longer return files that are gone, and
+    /// it's recorded as failed until a run extracts it again. This doesn't count as a
+    /// failed attempt.
+    pub fn mark_broken(&mut self, source: &Path, error: String, now: u64) {
+        let hash = self.sources.get(source).map(|indexed| indexed.hash.clone());
+        let attempts = self.failed.get(source).map_or(0, |failed| failed.attempts);
+        self.remove_source(source);
+        self.failed.insert(source.to_owned(), FailedSource {
+            stage: FailureStage::Verification,
+            error,
+            failed_at: now,
+            attempts,
+            hash,
+        });
+    }
+
+    fn remove_source(&mut self, source: &Path) {
+        if self.sources.remove(source).is_some() {
+            self.files.retain(|file| file.source != source);
+        }
+    }
+
+    /// Location of an indexed file on disk
+    pub fn disk_path(&self, entry: &IndexEntry) -> PathBuf {
+        self.root.join(&entry.path)
+    }
+
+    /// Files with one of the comma-separated extensions, case-insensitively
+    pub fn by_extension(&self, extensions: &str) -> Vec<&IndexEntry> {
+        self.files.iter()
+            .filter(|file| matches_extension(Path::new(&file.virtual_path), extensions))
+            .collect()
+    }
+
+    /// Files whose name matches a glob pattern, case-insensitively
+    ///
+    /// Patterns containing a `/` are matched against the whole virtual path instead.
+    pub fn by_glob(&self, pattern: &str) -> Result<Vec<&IndexEntry>> {
+        let pattern = normalize_entry_path(pattern);
+        let whole_path = pattern.contains('/');
+        let pattern = Pattern::new(&pattern).with_context(|| format!("Invalid index pattern: {}", pattern))?;
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        Ok(self.files.iter()
+            .filter(|file| {
+                let name = match whole_path {
+                    true => file.virtual_path.as_str(),
+                    false => file.virtual_path.rsplit('/').next().unwrap_or_default(),
+                };
+                pattern.matches_with(name, options)
+            })
+            .collect())
+    }
+
+    /// Files extracted from PBOs of the mod with the given display name
+    pub fn by_mod(&self, mod_name: &str) -> Vec<&IndexEntry> {
+        self.files.iter()
+            .filter(|file| {
+                self.sources.get(&file.source).and_then(|source| source.mod_name.as_deref()) == Some(mod_name)
+            })
+            .collect()
+    }
+
+    /// Files below a virtual directory, like `z/ace/addons/medical`, case-insensitively
+    pub fn by_prefix(&self, prefix: &str) -> Vec<&IndexEntry> {
+        let prefix = normalize_entry_path(prefix).trim_matches('/').to_lowercase();
+        self.files.iter()
+            .filter(|file| {
+                let path = file.virtual_path.to_lowercase();
+                prefix.is_empty()
+                    || path.strip_prefix(&prefix).is_some_and(|rest| rest.starts_with('/'))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::manifest::MANIFEST_FILE_NAME;
+    use super::super::test_utils::write_file;
+    use tempfile::TempDir;
+
+    /// Extract a fake PBO into `cache/<name>` below the prefix and write its manifest
+    fn extract(cache: &Path, name: &str, hash: &str, files: &[&str]) -> PathBuf {
+        let base_dir = cache.join(name);
+        let _ = std::fs::remove_dir_all(&base_dir);
+        for file in files {
+            write_file(&base_dir.join(file), file);
+        }
+        let manifest = ExtractionManifest::collect(
+            &base_dir,
+            Path::new(name),
+            hash.to_string(),
+            None,
+            "sqf,cpp,paa",
+        ).unwrap();
+        manifest.write(&base_dir).unwrap();
+        base_dir
+    }
+
+    fn virtual_paths(files: &[&IndexEntry]) -> Vec<String> {
+        files.iter().map(|file| file.virtual_path.clone()).collect()
+    }
+
+    fn index() -> (TempDir, ExtractionIndex) {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = temp_dir.path();
+        let mut index = ExtractionIndex::new(cache);
+        let medical = extract(cache, "ace_medical", "a1", &[
+            "z/ace/addons/medical/config.cpp",
+            "z/ace/addons/medical/functions/fnc_heal.sqf",
+            "z/ace/addons/medical/ui/Tourniquet.PAA",
+        ]);
+        let common = extract(cache, "ace_common", "b1", &[
+            "z/ace/addons/common/config.cpp",
+            "z/ace/addons/common/functions/fnc_log.sqf",
+        ]);
+        assert!(!index.update_source(Path::new("ace_medical.pbo"), &medical, Some("ACE3")).unwrap());
+        assert!(!index.update_source(Path::new("ace_common.pbo"), &common, None).unwrap());
+        (temp_dir, index)
+    }
+
+    #[test]
+    fn test_lookups() {
+        let (temp_dir, index) = index();
+
+        assert_eq!(virtual_paths(&index.by_extension("paa")), vec!["z/ace/addons/medical/ui/Tourniquet.PAA"]);
+        assert_eq!(virtual_paths(&index.by_glob("fnc_*.sqf").unwrap()), vec![
+            "z/ace/addons/common/functions/fnc_log.sqf",
+            "z/ace/addons/medical/functions/fnc_heal.sqf",
+        ]);
+        assert_eq!(virtual_paths(&index.by_glob("z/ace/addons/*/CONFIG.cpp").unwrap()).len(), 2);
+        assert!(index.by_glob("[").is_err());
+        assert_eq!(index.by_prefix("Z\\ACE\\addons\\medical").len(), 3);
+        assert!(index.by_prefix("z/ace/addons/med").is_empty());
+        assert_eq!(index.by_prefix("").len(), 5);
+        assert_eq!(index.by_mod("ACE3").len(), 3);
+
+        let heal = index.by_glob("fnc_heal.sqf").unwrap()[0];
+        assert_eq!(heal.source, Path::new("ace_medical.pbo"));
+        assert_eq!(
+            index.disk_path(heal),
+            temp_dir.path().join("ace_medical/z/ace/addons/medical/functions/fnc_heal.sqf")
+        );
+        assert_eq!(std::fs::read_to_string(index.disk_path(heal)).unwrap(), "z/ace/addons/medical/functions/fnc_heal.sqf");
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let (temp_dir, index) = index();
+        let cache = temp_dir.path();
+        let path = cache.join(INDEX_FILE_NAME);
+        index.write(&path).unwrap();
+
+        let mut index = ExtractionIndex::load(&path).unwrap();
+        // Unchanged since indexed
+        assert!(index.update_source(Path::new("ace_common.pbo"), &cache.join("ace_common"), None).unwrap());
+        // Extracted again with a new version
+        let medical = extract(cache, "ace_medical", "a2", &["z/ace/addons/medical/config.cpp"]);
+        assert!(!index.update_source(Path::new("ace_medical.pbo"), &medical, Some("ACE3")).unwrap());
+        assert_eq!(index.by_prefix("z/ace/addons/medical").len(), 1);
+        assert_eq!(index.sources[Path::new("ace_medical.pbo")].hash, "a2");
+
+        // A failed extraction leaves the previous output and its entries in place
+        std::fs::remove_file(medical.join(MANIFEST_FILE_NAME)).unwrap();
+        assert!(index.update_source(Path::new("ace_medical.pbo"), &medical, Some("ACE3")).unwrap());
+        assert_eq!(index.by_prefix("z/ace/addons/medical").len(), 1);
+
+        index.retain_sources(|source| source != Path::new("ace_common.pbo"));
+        assert_eq!(virtual_paths(&index.by_prefix("")), vec!["z/ace/addons/medical/config.cpp"]);
+        assert_eq!(index.sources.len(), 1);
+    }
+
+    #[test]
+    fn test_failures() {
+        let (temp_dir, index) = index();
+        let path = temp_dir.path().join(INDEX_FILE_NAME);
+        // Written before failures were recorded
+        index.write(&path).unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("failed"));
+
+        let mut index = ExtractionIndex::load(&path).unwrap();
+        let error = anyhow::anyhow!("return code 1").context("All extraction attempts failed (filtered)");
+        let failure = PboFailure::new(PathBuf::from("ace_medical.pbo"), &error);
+        index.record_failure(&failure, FailureStage::Extraction, None, 1_700_000_000);
+        index.record_failure(&failure, FailureStage::Extraction, None, 1_700_086_400);
+        let broken = PboFailure::new(PathBuf::from("broken.pbo"), &anyhow::anyhow!("Listing timed out"));
+        index.record_failure(&broken, FailureStage::Listing, None, 1_700_086_400);
+        index.write(&path).unwrap();
+
+        let mut index = ExtractionIndex::load(&path).unwrap();
+        let medical = &index.failed[Path::new("ace_medical.pbo")];
+        assert_eq!((medical.attempts, medical.failed_at), (2, 1_700_086_400));
+        assert_eq!(index.failed_entries().collect::<Vec<_>>(), vec![
+            (Path::new("ace_medical.pbo"), FailureStage::Extraction, "All extraction attempts failed (filtered): return code 1"),
+            (Path::new("broken.pbo"), FailureStage::Listing, "Listing timed out"),
+        ]);
+        // The entries of the last successful extraction stay available
+        assert_eq!(index.by_prefix("z/ace/addons/medical").len(), 3);
+
+        index.clear_failure(Path::new("ace_medical.pbo"));
+        index.retain_sources(|source| source != Path::new("broken.pbo"));
+        assert_eq!(index.failed_entries().count(), 0);
+    }
+
+    #[test]
+    fn test_attempts_start_over_when_changed() {
+        let (_temp_dir, mut index) = index();
+        let failure = PboFailure::new(PathBuf::from("broken.pbo"), &anyhow::anyhow!("Invalid PBO header"));
+        for now in [1_700_000_000, 1_700_086_400, 1_700_172_800] {
+            index.record_failure(&failure, FailureStage::Extraction, Some("c1".to_string()), now);
+        }
+        assert!(index.is_given_up(Path::new("broken.pbo"), "c1", 3));
+        assert!(!index.is_given_up(Path::new("broken.pbo"), "c1", 4));
+        assert!(!index.is_given_up(Path::new("broken.pbo"), "c2", 3));
+
+        index.record_failure(&failure, FailureStage::Extraction, Some("c2".to_string()), 1_700_259_200);
+        assert_eq!(index.failed[Path::new("broken.pbo")].attempts, 1);
+        // Failures recorded without a hash keep counting
+        index.record_failure(&failure, FailureStage::Extraction, None, 1_700_345_600);
+        assert_eq!(index.failed[Path::new("broken.pbo")].attempts, 2);
+    }
+
+    #[test]
+    fn test_mark_broken() {
+        let (temp_dir, mut index) = index();
+        let path = temp_dir.path().join(INDEX_FILE_NAME);
+        index.mark_broken(Path::new("ace_medical.pbo"), "missing z/ace/addons/medical/config.cpp".to_string(), 1_700_000_000);
+        index.write(&path).unwrap();
+
+        let index = ExtractionIndex::load(&path).unwrap();
+        assert!(!index.sources.contains_key(Path::new("ace_medical.pbo")));
+        assert!(index.by_prefix("z/ace/addons/medical").is_empty());
+        let medical = &index.failed[Path::new("ace_medical.pbo")];
+        assert_eq!((medical.stage, medical.attempts), (FailureStage::Verification, 0));
+        assert_eq!(medical.hash.as_deref(), Some("a1"));
+        assert_eq!(index.by_prefix("z/ace/addons/common").len(), 2);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let (temp_dir, mut index) = index();
+        let path = temp_dir.path().join(INDEX_FILE_NAME);
+        let broken = PboFailure::new(PathBuf::from("broken.pbo"), &anyhow::anyhow!("Invalid PBO header"));
+        let medical = PboFailure::new(PathBuf::from("ace_medical.pbo"), &anyhow::anyhow!("return code 1"));
+        for now in [1_700_000_000, 1_700_086_400] {
+            index.record_failure(&broken, FailureStage::Extraction, None, now);
+        }
+        index.record_failure(&medical, FailureStage::Extraction, None, 1_700_086_400);
+        assert_eq!(index.due_for_quarantine(2), vec![PathBuf::from("broken.pbo")]);
+
+        index.quarantine(Path::new("broken.pbo"), "c1".to_string(), Some(PathBuf::from("quarantine/broken.pbo")), 1_700_086_400);
+        assert!(index.due_for_quarantine(2).is_empty());
+        index.write(&path).unwrap();
+
+        let mut index = ExtractionIndex::load(&path).unwrap();
+        assert!(index.is_quarantined(Path::new("broken.pbo"), "c1"));
+        assert!(!index.is_quarantined(Path::new("broken.pbo"), "c2"));
+        assert!(!index.is_quarantined(Path::new("ace_medical.pbo"), "a1"));
+        let quarantined: Vec<_> = index.quarantined_entries().collect();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].1.error, "Invalid PBO header");
+
+        let released = index.release(Path::new("broken.pbo")).unwrap();
+        assert_eq!(released.copy, Some(PathBuf::from("quarantine/broken.pbo")));
+        assert!(!index.failed.contains_key(Path::new("broken.pbo")));
+        assert_eq!(index.quarantined_entries().count(), 0);
+    }
+
+    #[test]
+    fn test_batch_matches_single_updates() {
+        let (temp_dir, indexed) = index();
+        let cache = temp_dir.path();
+        let medical = extract(cache, "ace_medical", "a2", &["z/ace/addons/medical/config.cpp"]);
+        let arsenal = extract(cache, "ace_arsenal", "c1", &["z/ace/addons/arsenal/config.cpp"]);
+        let updates = vec![
+            SourceUpdate::read(Path::new("ace_medical.pbo"), &medical, Some("ACE3")),
+            SourceUpdate::read(Path::new("ace_common.pbo"), &cache.join("ace_common"), None),
+            SourceUpdate::read(Path::new("ace_arsenal.pbo"), &arsenal, None),
+            SourceUpdate::read(Path::new("ace_arsenal.pbo"), &arsenal, Some("ACE3")),
+            SourceUpdate::read(Path::new("ace_missing.pbo"), &cache.join("ace_missing"), None),
+        ];
+        let kept = vec![false, true, false, false, false];
+
+        let mut single = indexed.clone();
+        let single_kept: Vec<_> = updates.iter().map(|update| single.apply_update(update.clone()).unwrap()).collect();
+        assert_eq!(single_kept, kept);
+        let mut batch = indexed;
+        assert_eq!(batch.apply_updates(updates).unwrap(), 1);
+        assert_eq!(batch, single);
+        assert_eq!(batch.by_mod("ACE3").len(), 2);
+
+        // Removed in the same batch it was extracted in
+        let gone = SourceUpdate { output: SourceOutput::Gone, ..SourceUpdate::read(Path::new("x.pbo"), &arsenal, None) };
+        let mut batch = single.clone();
+        batch.apply_updates([SourceUpdate::read(Path::new("x.pbo"), &arsenal, None), gone]).unwrap();
+        assert_eq!(batch, single);
+    }
+
+    #[test]
+    fn test_updates_from_many_threads() {
+        let mut index = ExtractionIndex::new(Path::new("cache"));
+        let (sender, updates) = std::sync::mpsc::channel();
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for pbo in 0..250 {
+                        let name = format!("mod_{}_{}", thread, pbo);
+                        let output = SourceOutput::Extracted {
+                            source: IndexedSource { hash: name.clone(), extracted_at: 1, mod_name: None, versions: Vec::new(), listed_after_extraction: false },
+                            files: ["config.cpp", "fnc_init.sqf"]
+                                .iter()
+                                .map(|file| ManifestFile { path: format!("z/{}/{}", name, file), size: 1 })
+                                .collect(),
+                        };
+                        let source = PathBuf::from(format!("{}.pbo", name));
+                        sender.send(SourceUpdate { source, base_dir: Path::new("cache").join(&name), output }).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        for update in updates {
+            assert!(!index.apply_update(update).unwrap());
+        }
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(index.sources.len(), 2000);
+        assert_eq!(index.files.len(), 4000);
+        assert!(index.files.windows(2).all(|pair| pair[0].virtual_path < pair[1].virtual_path));
+        assert_eq!(index.by_prefix("z/mod_7_249").len(), 2);
+        assert_eq!(index.by_prefix("z/mod_7_249")[0].path, Path::new("mod_7_249/z/mod_7_249/config.cpp"));
+    }
+}
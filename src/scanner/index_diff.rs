@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use super::index::ExtractionIndex;
+use crate::utils::normalize_entry_path;
+
+/// State of a PBO recorded in an extraction index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    Extracted,
+    /// Its last listing or extraction failed
+    Failed,
+    Quarantined,
+}
+
+impl fmt::Display for SourceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Extracted => "extracted",
+            Self::Failed => "failed",
+            Self::Quarantined => "quarantined",
+        };
+        f.write_str(status)
+    }
+}
+
+/// PBO as recorded in one index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceState {
+    /// Path relative to the input directory, using forward slashes
+    pub path: PathBuf,
+    /// Hash of the PBO when it was last extracted, failed or quarantined; failures
+    /// recorded without a hash keep the one of the last extraction, if any
+    pub hash: Option<String>,
+    pub status: SourceStatus,
+}
+
+/// PBO recorded in both indexes with a different hash or status
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceChange {
+    /// Path relative to the input directory, using forward slashes
+    pub path: PathBuf,
+    pub old_hash: Option<String>,
+    pub new_hash: Option<String>,
+    pub old_status: SourceStatus,
+    pub new_status: SourceStatus,
+}
+
+/// Differences between two extraction indexes, as returned by `ExtractionIndex::diff`
+///
+/// Every list is sorted by path. PBOs whose hash and status both changed are in
+/// both `changed_hash` and `changed_status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDiff {
+    /// PBOs only in the newer index
+    pub added: Vec<SourceState>,
+    /// PBOs only in the older index
+    pub removed: Vec<SourceState>,
+    pub changed_hash: Vec<SourceChange>,
+    pub changed_status: Vec<SourceChange>,
+}
+
+impl IndexDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed_hash.is_empty() && self.changed_status.is_empty()
+    }
+}
+
+impl ExtractionIndex {
+    /// State of every PBO in the index, by path with forward slashes
+    ///
+    /// Covers PBOs with indexed files as well as failed and quarantined ones that
+    /// never extracted. Indexes written on Windows use backslashes, so paths are
+    /// normalized to compare indexes across platforms.
+    pub fn source_states(&self) -> BTreeMap<String, SourceState> {
+        let mut states = BTreeMap::new();
+        let paths = self.sources.keys().chain(self.failed.keys()).chain(self.quarantined.keys());
+        for path in paths {
+            let key = source_key(path);
+            if states.contains_key(&key) {
+                continue;
+            }
+            let extracted_hash = self.sources.get(path).map(|source| source.hash.clone());
+            let (hash, status) = match (self.quarantined.get(path), self.failed.get(path)) {
+                (Some(quarantined), _) => (Some(quarantined.hash.clone()), SourceStatus::Quarantined),
+                (None, Some(failed)) => (failed.hash.clone().or(extracted_hash), SourceStatus::Failed),
+                (None, None) => (extracted_hash, SourceStatus::Extracted),
+            };
+            states.insert(key.clone(), SourceState { path: PathBuf::from(key), hash, status });
+        }
+        states
+    }
+
+    /// What changed from this index to `other`, a newer index of the same input directory
+    pub fn diff(&self, other: &ExtractionIndex) -> IndexDiff {
+        let old = self.source_states();
+        let mut new = other.source_states();
+        let mut diff = IndexDiff::default();
+        for (key, old_state) in old {
+            let Some(new_state) = new.remove(&key) else {
+                diff.removed.push(old_state);
+                continue;
+            };
+            let change = || SourceChange {
+                path: new_state.path.clone(),
+                old_hash: old_state.hash.clone(),
+                new_hash: new_state.hash.clone(),
+                old_status: old_state.status,
+                new_status: new_state.status,
+            };
+            if !same_hash(old_state.hash.as_deref(), new_state.hash.as_deref()) {
+                diff.changed_hash.push(change());
+            }
+            if old_state.status != new_state.status {
+                diff.changed_status.push(change());
+            }
+        }
+        diff.added = new.into_values().collect();
+        diff
+    }
+}
+
+/// Index key of `path`, written on any platform
+pub(crate) fn source_key(path: &Path) -> String {
+    let key = normalize_entry_path(&path.to_string_lossy());
+    match key.strip_prefix("./") {
+        Some(key) => key.to_string(),
+        None => key,
+    }
+}
+
+/// Hashes are hex digests; their case depends on the writer
+pub(crate) fn same_hash(old: Option<&str>, new: Option<&str>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old.eq_ignore_ascii_case(new),
+        (old, new) => old == new,
+    }
+}
+
+impl fmt::Display for IndexDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = |hash: &Option<String>| hash.clone().unwrap_or_else(|| "no hash".to_string());
+        let path = |path: &Path| path.display().to_string();
+        write!(
+            f,
+            "{} added, {} removed, {} changed, {} changed status",
+            self.added.len(),
+            self.removed.len(),
+            self.changed_hash.len(),
+            self.changed_status.len()
+        )?;
+        for state in &self.added {
+            write!(f, "\n  + {} ({}, {})", path(&state.path), hash(&state.hash), state.status)?;
+        }
+        for state in &self.removed {
+            write!(f, "\n  - {} ({}, {})", path(&state.path), hash(&state.hash), state.status)?;
+        }
+        for change in &self.changed_hash {
+            write!(f, "\n  ~ {}: {} -> {}", path(&change.path), hash(&change.old_hash), hash(&change.new_hash))?;
+        }
+        for change in &self.changed_status {
+            write!(f, "\n  ! {}: {} -> {}", path(&change.path), change.old_status, change.new_status)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::index::{FailedSource, FailureStage, IndexedSource, QuarantinedSource};
+
+    fn source(hash: &str) -> IndexedSource {
+        IndexedSource { hash: hash.to_string(), extracted_at: 1, mod_name: None, versions: Vec::new(), listed_after_extraction: false }
+    }
+
+    fn failed(hash: Option<&str>) -> FailedSource {
+        FailedSource {
+            stage: FailureStage::Extraction,
+            error: "Extraction timed out".to_string(),
+            failed_at: 2,
+            attempts: 1,
+            hash: hash.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_diff_across_platforms() {
+        let mut old = ExtractionIndex::default();
+        old.sources.insert(PathBuf::from("@ace\\addons\\ace_medical.pbo"), source("AB12"));
+        old.sources.insert(PathBuf::from(".\\@ace\\addons\\ace_common.pbo"), source("cd34"));
+        old.sources.insert(PathBuf::from("@ace\\addons\\ace_removed.pbo"), source("ef56"));
+        old.sources.insert(PathBuf::from("@ace\\addons\\ace_broken.pbo"), source("0a0a"));
+
+        let mut new = ExtractionIndex::default();
+        new.sources.insert(PathBuf::from("@ace/addons/ace_medical.pbo"), source("ab12"));
+        new.sources.insert(PathBuf::from("@ace/addons/ace_common.pbo"), source("cd35"));
+        new.sources.insert(PathBuf::from("@ace/addons/ace_broken.pbo"), source("0a0a"));
+        new.failed.insert(PathBuf::from("@ace/addons/ace_broken.pbo"), failed(Some("0b0b")));
+        new.failed.insert(PathBuf::from("@ace/addons/ace_new.pbo"), failed(None));
+        new.quarantined.insert(PathBuf::from("@ace/addons/ace_new.pbo"), QuarantinedSource {
+            hash: "1c1c".to_string(),
+            error: "Extraction timed out".to_string(),
+            quarantined_at: 3,
+            copy: None,
+        });
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec![SourceState {
+            path: PathBuf::from("@ace/addons/ace_new.pbo"),
+            hash: Some("1c1c".to_string()),
+            status: SourceStatus::Quarantined,
+        }]);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].path, Path::new("@ace/addons/ace_removed.pbo"));
+        let changed: Vec<_> = diff.changed_hash.iter().map(|change| change.path.to_str().unwrap()).collect();
+        assert_eq!(changed, ["@ace/addons/ace_broken.pbo", "@ace/addons/ace_common.pbo"]);
+        assert_eq!(diff.changed_status, vec![SourceChange {
+            path: PathBuf::from("@ace/addons/ace_broken.pbo"),
+            old_hash: Some("0a0a".to_string()),
+            new_hash: Some("0b0b".to_string()),
+            old_status: SourceStatus::Extracted,
+            new_status: SourceStatus::Failed,
+        }]);
+
+        assert!(new.diff(&new).is_empty());
+        assert_eq!(
+            diff.to_string().lines().next(),
+            Some("1 added, 1 removed, 2 changed, 1 changed status")
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::index::{ExtractionIndex, FailedSource, IndexEntry, IndexedSource, QuarantinedSource};
+use super::index_diff::{same_hash, source_key, SourceChange, SourceState, SourceStatus};
+
+/// How `ExtractionIndex::merge` settles PBOs both indexes record with another hash or status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Take the record updated last, by the time of its extraction, failure or
+    /// quarantine; ties keep the record of the index merged into
+    #[default]
+    PreferNewer,
+    /// Take the record of an extracted PBO over a failed or quarantined one, and
+    /// the newer one otherwise
+    PreferSuccess,
+    /// Fail the merge, leaving the index merged into unchanged
+    ErrorOnConflict,
+}
+
+/// Result of `ExtractionIndex::merge`, with PBOs by path with forward slashes
+///
+/// Every list is sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeSummary {
+    /// PBOs only the other index records, now merged in
+    pub added: Vec<PathBuf>,
+    /// PBOs both indexes record with the same hash and status
+    pub identical: usize,
+    /// PBOs both indexes record with another hash or status, the index merged into
+    /// being the old side
+    pub conflicts: Vec<SourceChange>,
+    /// Conflicting PBOs whose record was taken from the other index
+    pub replaced: Vec<PathBuf>,
+    /// Conflicting PBOs that kept the record of the index merged into
+    pub kept: Vec<PathBuf>,
+}
+
+impl fmt::Display for MergeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} identical, {} conflicting: {} replaced, {} kept",
+            self.added.len(),
+            self.identical,
+            self.conflicts.len(),
+            self.replaced.len(),
+            self.kept.len()
+        )?;
+        for path in &self.replaced {
+            write!(f, "\n  < {}", path.display())?;
+        }
+        for path in &self.kept {
+            write!(f, "\n  > {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Everything an index records about one PBO
+#[derive(Default)]
+struct SourceRecord {
+    source: Option<IndexedSource>,
+    failed: Option<FailedSource>,
+    quarantined: Option<QuarantinedSource>,
+    files: Vec<IndexEntry>,
+}
+
+impl SourceRecord {
+    /// Last time the PBO was extracted, failed or quarantined, in seconds since the Unix epoch
+    fn updated_at(&self) -> u64 {
+        let extracted = self.source.as_ref().map(|source| source.extracted_at);
+        let failed = self.failed.as_ref().map(|failed| failed.failed_at);
+        let quarantined = self.quarantined.as_ref().map(|quarantined| quarantined.quarantined_at);
+        [extracted, failed, quarantined].into_iter().flatten().max().unwrap_or_default()
+    }
+}
+
+impl MergeStrategy {
+    /// Whether `theirs` replaces `ours`, two records of a PBO in conflict
+    fn prefers_theirs(self, ours: (&SourceRecord, SourceStatus), theirs: (&SourceRecord, SourceStatus)) -> bool {
+        let newer = theirs.0.updated_at() > ours.0.updated_at();
+        match self {
+            Self::PreferNewer | Self::ErrorOnConflict => newer,
+            Self::PreferSuccess => match (ours.1 == SourceStatus::Extracted, theirs.1 == SourceStatus::Extracted) {
+                (true, false) => false,
+                (false, true) => true,
+                _ => newer,
+            },
+        }
+    }
+}
+
+fn same_state(a: &SourceState, b: &SourceState) -> bool {
+    a.status == b.status && same_hash(a.hash.as_deref(), b.hash.as_deref())
+}
+
+impl ExtractionIndex {
+    /// Merge `other`, an index of the same cache directory written elsewhere, into this one
+    ///
+    /// Each PBO keeps the whole record of one index: its extraction, files, failure
+    /// and quarantine. Paths are compared and stored with forward slashes, so indexes
+    /// written on Windows and Linux line up; file paths stay relative to the cache
+    /// directory, which both indexes are expected to describe.
+    pub fn merge(&mut self, mut other: ExtractionIndex, strategy: MergeStrategy) -> Result<MergeSummary> {
+        let our_states = self.source_states();
+        let their_states = other.source_states();
+        let mut summary = MergeSummary::default();
+        let mut conflicts = BTreeMap::new();
+        for (key, theirs) in &their_states {
+            match our_states.get(key) {
+                None => summary.added.push(theirs.path.clone()),
+                Some(ours) if same_state(ours, theirs) => summary.identical += 1,
+                Some(ours) => {
+                    conflicts.insert(key.clone(), SourceChange {
+                        path: theirs.path.clone(),
+                        old_hash: ours.hash.clone(),
+                        new_hash: theirs.hash.clone(),
+                        old_status: ours.status,
+                        new_status: theirs.status,
+                    });
+                },
+            }
+        }
+        if strategy == MergeStrategy::ErrorOnConflict && !conflicts.is_empty() {
+            let paths: Vec<_> = conflicts.keys().map(String::as_str).collect();
+            anyhow::bail!("{} PBOs differ between the indexes: {}", paths.len(), paths.join(", "));
+        }
+
+        let mut ours = self.take_records();
+        for (key, theirs) in other.take_records() {
+            let Some(change) = conflicts.get(&key) else {
+                ours.entry(key).or_insert(theirs);
+                continue;
+            };
+            let replace = ours.get(&key).is_some_and(|record| {
+                strategy.prefers_theirs((record, change.old_status), (&theirs, change.new_status))
+            });
+            if replace {
+                summary.replaced.push(change.path.clone());
+                ours.insert(key, theirs);
+            } else {
+                summary.kept.push(change.path.clone());
+            }
+        }
+        self.put_records(ours);
+        summary.conflicts = conflicts.into_values().collect();
+        Ok(summary)
+    }
+
+    /// Move every record out of the index, by path with forward slashes
+    ///
+    /// Of records whose paths only differ by their separators, the first is taken.
+    fn take_records(&mut self) -> BTreeMap<String, SourceRecord> {
+        let mut records: BTreeMap<String, SourceRecord> = BTreeMap::new();
+        let mut taken = HashMap::new();
+        for (path, source) in std::mem::take(&mut self.sources) {
+            let key = source_key(&path);
+            let record = records.entry(key.clone()).or_default();
+            if record.source.is_none() {
+                record.source = Some(source);
+                taken.insert(path, key);
+            }
+        }
+        for (path, failed) in std::mem::take(&mut self.failed) {
+            records.entry(source_key(&path)).or_default().failed.get_or_insert(failed);
+        }
+        for (path, mut quarantined) in std::mem::take(&mut self.quarantined) {
+            quarantined.copy = quarantined.copy.map(|copy| PathBuf::from(source_key(&copy)));
+            records.entry(source_key(&path)).or_default().quarantined.get_or_insert(quarantined);
+        }
+        for mut file in std::mem::take(&mut self.files) {
+            let Some(key) = taken.get(&file.source) else {
+                continue;
+            };
+            file.source = PathBuf::from(key);
+            file.path = PathBuf::from(source_key(&file.path));
+            if let Some(record) = records.get_mut(key) {
+                record.files.push(file);
+            }
+        }
+        records
+    }
+
+    fn put_records(&mut self, records: BTreeMap<String, SourceRecord>) {
+        for (key, record) in records {
+            let path = PathBuf::from(key);
+            if let Some(source) = record.source {
+                self.sources.insert(path.clone(), source);
+            }
+            if let Some(failed) = record.failed {
+                self.failed.insert(path.clone(), failed);
+            }
+            if let Some(quarantined) = record.quarantined {
+                self.quarantined.insert(path, quarantined);
+            }
+            self.files.extend(record.files);
+        }
+        self.files.sort_by(|a, b| (&a.virtual_path, &a.source).cmp(&(&b.virtual_path, &b.source)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use super::super::index::FailureStage;
+
+    fn extracted(index: &mut ExtractionIndex, source: &str, hash: &str, extracted_at: u64) {
+        index.sources.insert(PathBuf::from(source), IndexedSource {
+            hash: hash.to_string(),
+            extracted_at,
+            mod_name: None,
+            versions: Vec::new(),
+            listed_after_extraction: false,
+        });
+        index.files.push(IndexEntry {
+            virtual_path: format!("z/{}/config.cpp", hash),
+            path: PathBuf::from(source.replace(".pbo", "")).join("config.cpp"),
+            source: PathBuf::from(source),
+            size: 4,
+        });
+    }
+
+    fn failed(index: &mut ExtractionIndex, source: &str, failed_at: u64) {
+        index.failed.insert(PathBuf::from(source), FailedSource {
+            stage: FailureStage::Extraction,
+            error: "Extraction timed out".to_string(),
+            failed_at,
+            attempts: 1,
+            hash: None,
+        });
+    }
+
+    /// Indexes of the same cache written on Windows and on Linux
+    fn indexes() -> (ExtractionIndex, ExtractionIndex) {
+        let mut windows = ExtractionIndex::default();
+        extracted(&mut windows, "@ace\\addons\\ace_common.pbo", "aa", 10);
+        extracted(&mut windows, "@ace\\addons\\ace_medical.pbo", "b1", 10);
+        extracted(&mut windows, "@ace\\addons\\ace_arsenal.pbo", "c1", 30);
+        failed(&mut windows, "@ace\\addons\\ace_arsenal.pbo", 40);
+
+        let mut linux = ExtractionIndex::default();
+        extracted(&mut linux, "@ace/addons/ace_common.pbo", "AA", 20);
+        extracted(&mut linux, "@ace/addons/ace_medical.pbo", "b2", 20);
+        extracted(&mut linux, "@ace/addons/ace_arsenal.pbo", "c1", 20);
+        extracted(&mut linux, "@cba/addons/cba_main.pbo", "d1", 20);
+        (windows, linux)
+    }
+
+    #[test]
+    fn test_merge_strategies() {
+        let (mut index, linux) = indexes();
+        let summary = index.merge(linux.clone(), MergeStrategy::PreferNewer).unwrap();
+        assert_eq!(summary.added, vec![PathBuf::from("@cba/addons/cba_main.pbo")]);
+        assert_eq!(summary.identical, 1);
+        assert_eq!(summary.replaced, vec![PathBuf::from("@ace/addons/ace_medical.pbo")]);
+        assert_eq!(summary.kept, vec![PathBuf::from("@ace/addons/ace_arsenal.pbo")]);
+        assert_eq!(index.sources[Path::new("@ace/addons/ace_medical.pbo")].hash, "b2");
+        assert!(index.failed.contains_key(Path::new("@ace/addons/ace_arsenal.pbo")));
+        assert!(index.sources.keys().chain(index.files.iter().map(|file| &file.path)).all(|path| !path.to_string_lossy().contains('\\')));
+        assert_eq!(index.files.len(), 4);
+
+        let (mut index, linux) = indexes();
+        let summary = index.merge(linux.clone(), MergeStrategy::PreferSuccess).unwrap();
+        assert_eq!(summary.replaced.len(), 2);
+        assert!(!index.failed.contains_key(Path::new("@ace/addons/ace_arsenal.pbo")));
+
+        let (mut index, linux) = indexes();
+        let error = index.merge(linux, MergeStrategy::ErrorOnConflict).unwrap_err();
+        assert!(error.to_string().starts_with("2 PBOs differ between the indexes"));
+        assert!(index.sources.contains_key(Path::new("@ace\\addons\\ace_medical.pbo")));
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counting semaphore capping how many threads run a step at once
+///
+/// Threads over the limit block in `acquire` until a permit is dropped, taking
+/// permits as they're released. The I/O limit is shared through an `Arc` by the
+/// coordinator and its processors, so listing, extraction and nested PBOs all count
+/// against it; the tool limit is one for the whole process.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    /// Only changed with `running` locked, so waiting threads see every change
+    limit: AtomicUsize,
+    running: Mutex<usize>,
+    released: Condvar,
+}
+
+impl ConcurrencyLimit {
+    /// Let `limit` threads through at once, at least one
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit.max(1)),
+            running: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Let `limit` threads through at once from now on, at least one
+    ///
+    /// Permits already held stay valid; with a lower limit, waiting threads get
+    /// theirs once enough are released.
+    pub fn set_limit(&self, limit: usize) {
+        let _running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        self.limit.store(limit.max(1), Ordering::Relaxed);
+        self.released.notify_all();
+    }
+
+    /// Wait for a permit, held until the returned guard is dropped
+    pub fn acquire(&self) -> Permit<'_> {
+        self.acquire_timed().0
+    }
+
+    /// `acquire`, also returning the time spent waiting for the permit
+    pub fn acquire_timed(&self) -> (Permit<'_>, Duration) {
+        let started = Instant::now();
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        while *running >= self.limit() {
+            running = self.released.wait(running).unwrap_or_else(|e| e.into_inner());
+        }
+        *running += 1;
+        (Permit { limit: self }, started.elapsed())
+    }
+}
+
+/// Permit of a `ConcurrencyLimit`, released when dropped
+#[must_use]
+pub struct Permit<'a> {
+    limit: &'a ConcurrencyLimit,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut running = self.limit.running.lock().unwrap_or_else(|e| e.into_inner());
+        *running -= 1;
+        self.limit.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    /// Counts the threads inside a step at once
+    #[derive(Default)]
+    struct Active {
+        now: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    impl Active {
+        fn run(&self, millis: u64) {
+            let now = self.now.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(millis));
+            self.now.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_limited_steps_are_serialized_while_others_overlap() {
+        let limit = ConcurrencyLimit::new(1);
+        let (io, cpu) = (Active::default(), Active::default());
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        pool.install(|| (0..8).into_par_iter().for_each(|_| {
+            let permit = limit.acquire();
+            io.run(10);
+            drop(permit);
+            cpu.run(50);
+        }));
+        assert_eq!(io.max.load(Ordering::SeqCst), 1);
+        assert!(cpu.max.load(Ordering::SeqCst) > 1);
+
+        let limit = ConcurrencyLimit::new(2);
+        let io = Active::default();
+        pool.install(|| (0..8).into_par_iter().for_each(|_| {
+            let _permit = limit.acquire();
+            io.run(10);
+        }));
+        assert_eq!(io.max.load(Ordering::SeqCst), 2);
+        assert_eq!(ConcurrencyLimit::new(0).limit(), 1);
+    }
+
+    #[test]
+    fn test_waiting_threads_take_raised_limit() {
+        let limit = ConcurrencyLimit::new(1);
+        let held = limit.acquire();
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| limit.acquire_timed().1);
+            std::thread::sleep(Duration::from_millis(50));
+            limit.set_limit(2);
+            assert!(waiting.join().unwrap() >= Duration::from_millis(50));
+        });
+        drop(held);
+        assert!(limit.acquire_timed().1 < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::types::PboContents;
+use crate::utils::normalize_entry_path;
+
+/// File in the cache directory keeping the listings of PBOs between runs, as zstd
+/// compressed JSON
+pub const LISTING_CACHE_FILE_NAME: &str = "listings.json.zst";
+
+/// Default cap on the size of the listing cache file: 64 MiB
+pub const DEFAULT_LISTING_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Version of the listing cache layout; caches written with another one are dropped
+const LISTING_CACHE_VERSION: u32 = 2;
+
+/// zstd level the cache is written with
+const LISTING_CACHE_ZSTD_LEVEL: i32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct CachedListing {
+    /// SHA-256 of the PBO's content when it was listed
+    hash: String,
+    contents: PboContents,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ListingCacheFile<L> {
+    version: u32,
+    /// `CachedListing` by path relative to the input directory, with forward slashes
+    listings: L,
+}
+
+/// Listings read from the cache file or made by this run
+#[derive(Default)]
+struct Listings {
+    listings: BTreeMap<String, CachedListing>,
+    changed: bool,
+}
+
+/// Listings of the PBOs below an input directory, kept in the cache directory so
+/// PBOs unchanged since a previous run aren't listed by the tool again
+///
+/// A listing is only used while the PBO's hash is the one it was listed with. The
+/// file is read on first use and written by `save`.
+pub struct ListingCache {
+    input_dir: PathBuf,
+    path: PathBuf,
+    max_bytes: u64,
+    listings: Mutex<Option<Listings>>,
+}
+
+impl ListingCache {
+    /// Cache of the PBOs below `input_dir`, in `cache_dir`, not written once its
+    /// compressed size exceeds `max_bytes`
+    pub fn new(input_dir: &Path, cache_dir: &Path, max_bytes: u64) -> Self {
+        Self {
+            input_dir: input_dir.to_owned(),
+            path: cache_dir.join(LISTING_CACHE_FILE_NAME),
+            max_bytes,
+            listings: Mutex::new(None),
+        }
+    }
+
+    /// Listing of the PBO at `rel_path`, relative to the input directory, if it was
+    /// listed with the hash `hash`
+    pub fn get(&self, rel_path: &Path, hash: &str) -> Option<PboContents> {
+        let key = normalize_entry_path(&rel_path.to_string_lossy());
+        let mut listings = self.listings.lock().unwrap_or_else(|e| e.into_inner());
+        let listing = self.loaded(&mut listings).listings.get(&key)?;
+        (listing.hash == hash).then(|| listing.contents.clone())
+    }
+
+    /// Record the listing of the PBO at `rel_path`, made while its hash was `hash`
+    pub fn insert(&self, rel_path: &Path, hash: String, contents: PboContents) {
+        let key = normalize_entry_path(&rel_path.to_string_lossy());
+        let mut listings = self.listings.lock().unwrap_or_else(|e| e.into_inner());
+        let listings = self.loaded(&mut listings);
+        listings.listings.insert(key, CachedListing { hash, contents });
+        listings.changed = true;
+    }
+
+    /// Write the cache file, leaving out PBOs no longer in the input directory
+    ///
+    /// Caches outgrowing the size cap are removed instead, so every PBO is listed
+    /// again rather than some of them being listed from a stale file.
+    pub fn save(&self) -> Result<()> {
+        let mut listings = self.listings.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(listings) = listings.as_mut() else {
+            return Ok(());
+        };
+        let known = listings.listings.len();
+        listings.listings.retain(|key, _| self.input_dir.join(key).is_file());
+        if !listings.changed && listings.listings.len() == known {
+            return Ok(());
+        }
+
+        let json = serde_json::to_vec(&ListingCacheFile { version: LISTING_CACHE_VERSION, listings: &listings.listings })?;
+        let compressed = zstd::encode_all(json.as_slice(), LISTING_CACHE_ZSTD_LEVEL)
+            .context("Failed to compress listing cache")?;
+        if compressed.len() as u64 > self.max_bytes {
+            warn!(
+                "Not caching listings: {} bytes exceed the limit of {} bytes",
+                compressed.len(),
+                self.max_bytes
+            );
+            return match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(anyhow::Error::new(e).context(format!("Failed to remove {}", self.path.display())))
+                },
+                _ => Ok(()),
+            };
+        }
+
+        let partial = self.path.with_extension("zst.partial");
+        std::fs::write(&partial, compressed)
+            .with_context(|| format!("Failed to write listing cache: {}", partial.display()))?;
+        std::fs::rename(&partial, &self.path)
+            .with_context(|| format!("Failed to move listing cache into place: {}", self.path.display()))?;
+        listings.changed = false;
+        debug!("Cached the listings of {} PBOs in {}", listings.listings.len(), self.path.display());
+        Ok(())
+    }
+
+    /// The listings, reading the cache file the first time
+    fn loaded<'b>(&self, listings: &'b mut Option<Listings>) -> &'b mut Listings {
+        listings.get_or_insert_with(|| match self.read() {
+            Ok(listings) => Listings { listings, changed: false },
+            Err(e) => {
+                warn!("Listing every PBO again, the listing cache is unusable: {:#}", e);
+                Listings::default()
+            },
+        })
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, CachedListing>> {
+        let compressed = match std::fs::read(&self.path) {
+            Ok(compressed) => compressed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("Failed to read {}", self.path.display()))),
+        };
+        let json = zstd::decode_all(compressed.as_slice())
+            .with_context(|| format!("Failed to decompress {}", self.path.display()))?;
+        let file: ListingCacheFile<BTreeMap<String, CachedListing>> = serde_json::from_slice(&json)
+            .with_context(|| format!("Malformed listing cache: {}", self.path.display()))?;
+        if file.version != LISTING_CACHE_VERSION {
+            debug!("Dropping listing cache of version {}", file.version);
+            return Ok(BTreeMap::new());
+        }
+        Ok(file.listings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn contents(prefix: &str) -> PboContents {
+        PboContents {
+            prefix: Some(prefix.to_string()),
+            entries: vec!["config.cpp".to_string(), "functions/fnc_heal.sqf".to_string()],
+            entry_sizes: [("config.cpp".to_string(), 120)].into_iter().collect(),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_listings_reused_while_hash_matches() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(input_dir.path().join("@ace/addons")).unwrap();
+        for name in ["ace_medical.pbo", "ace_common.pbo"] {
+            std::fs::write(input_dir.path().join("@ace/addons").join(name), name).unwrap();
+        }
+
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), DEFAULT_LISTING_CACHE_MAX_BYTES);
+        cache.insert(Path::new("@ace/addons/ace_medical.pbo"), "aa".to_string(), contents("z\\ace\\medical"));
+        cache.insert(Path::new("@ace/addons/ace_common.pbo"), "bb".to_string(), contents("z\\ace\\common"));
+        cache.save().unwrap();
+        std::fs::remove_file(input_dir.path().join("@ace/addons/ace_common.pbo")).unwrap();
+
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), DEFAULT_LISTING_CACHE_MAX_BYTES);
+        let listed = cache.get(Path::new("@ace\\addons\\ace_medical.pbo"), "aa").unwrap();
+        assert_eq!(listed.prefix.as_deref(), Some("z\\ace\\medical"));
+        assert_eq!(listed.entry_sizes.get("config.cpp"), Some(&120));
+        assert!(cache.get(Path::new("@ace/addons/ace_medical.pbo"), "changed").is_none());
+
+        // Deleted PBOs are dropped on the next save
+        cache.save().unwrap();
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), DEFAULT_LISTING_CACHE_MAX_BYTES);
+        assert!(cache.get(Path::new("@ace/addons/ace_common.pbo"), "bb").is_none());
+        assert!(cache.get(Path::new("@ace/addons/ace_medical.pbo"), "aa").is_some());
+    }
+
+    #[test]
+    fn test_oversized_cache_is_removed() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        std::fs::write(input_dir.path().join("ace_medical.pbo"), "pbo").unwrap();
+        let path = cache_dir.path().join(LISTING_CACHE_FILE_NAME);
+
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), DEFAULT_LISTING_CACHE_MAX_BYTES);
+        cache.insert(Path::new("ace_medical.pbo"), "aa".to_string(), contents("z\\ace\\medical"));
+        cache.save().unwrap();
+        assert!(path.is_file());
+
+        let cache = ListingCache::new(input_dir.path(), cache_dir.path(), 16);
+        cache.insert(Path::new("ace_medical.pbo"), "bb".to_string(), contents("z\\ace\\medical"));
+        cache.save().unwrap();
+        assert!(!path.exists());
+    }
+}
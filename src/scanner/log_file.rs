@@ -0,0 +1,358 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+/// Default size a log file grows to before it is rotated: 10 MiB
+pub const DEFAULT_LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// When a log file is rotated and how many rotated files are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogRotation {
+    /// Rotate once the file would grow past this many bytes, 0 to never rotate
+    pub max_size: u64,
+    /// Rotated files kept next to the log as `<name>.1` to `<name>.<keep>`, newest first
+    pub keep: usize,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_LOG_FILE_MAX_SIZE,
+            keep: 5,
+        }
+    }
+}
+
+/// File appended to line by line, rotated by size
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    /// Open file and its current size
+    file: Mutex<(File, u64)>,
+    /// Set once rotating failed; the file then grows past `max_size`
+    rotation_failed: AtomicBool,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending, creating it and its parent directories as needed
+    pub fn open(path: &Path, rotation: LogRotation) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let file = open_append(path)?;
+        let size = file.metadata().map_or(0, |meta| meta.len());
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            file: Mutex::new((file, size)),
+            rotation_failed: AtomicBool::new(false),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `line` and a newline, rotating first if the file would grow too large
+    ///
+    /// Write errors are ignored; a log file must never fail the run. When rotating
+    /// fails, a warning is logged once and the file keeps growing.
+    pub fn write_line(&self, line: &str) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        let len = line.len() as u64 + 1;
+        let mut rotate_error = None;
+        if self.rotation.max_size > 0
+            && file.1 > 0
+            && file.1 + len > self.rotation.max_size
+            && !self.rotation_failed.load(Ordering::Relaxed)
+        {
+            match self.rotate() {
+                Ok(rotated) => *file = (rotated, 0),
+                Err(e) => {
+                    self.rotation_failed.store(true, Ordering::Relaxed);
+                    rotate_error = Some(e);
+                },
+            }
+        }
+        if writeln!(file.0, "{}", line).is_ok() {
+            file.1 += len;
+        }
+        // Unlocked first, as the warning may be written to this file too
+        drop(file);
+        if let Some(e) = rotate_error {
+            warn!("Failed to rotate {}: {:#}", self.path.display(), e);
+        }
+    }
+
+    /// Shift the rotated files up by one, dropping the oldest, and start a new file
+    fn rotate(&self) -> Result<File> {
+        if self.rotation.keep == 0 {
+            return File::create(&self.path).with_context(|| format!("Failed to truncate {}", self.path.display()));
+        }
+        let _ = std::fs::remove_file(rotated_path(&self.path, self.rotation.keep));
+        for index in (1..self.rotation.keep).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        open_append(&self.path)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// `<path>.<index>`, as the rotated file `index` of `path`
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Seconds since the Unix epoch with milliseconds, as logged
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+/// Files attached to the crate's logger, one per run logging to a file
+struct Attached {
+    files: Vec<(Arc<RotatingFile>, LevelFilter)>,
+    /// Max level before the first file was attached, restored after the last
+    base_level: LevelFilter,
+}
+
+impl Attached {
+    /// Max level letting through what the console and every attached file take
+    fn max_level(&self) -> LevelFilter {
+        self.files.iter().map(|(_, level)| *level).fold(self.base_level, Ord::max)
+    }
+}
+
+static ATTACHED: RwLock<Attached> = RwLock::new(Attached { files: Vec::new(), base_level: LevelFilter::Off });
+
+/// Whether the global logger is a `TeeLogger` that log files can be attached to
+static TEE_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Logger passing records on to `console` and to the attached log files
+///
+/// Log records carry nothing telling which run they belong to, so while runs overlap
+/// each of their files gets the records of all of them.
+pub struct TeeLogger<L> {
+    console: L,
+}
+
+impl<L: Log> TeeLogger<L> {
+    /// Wrap `console`; call `tee_installed` once the result is the global logger
+    pub fn new(console: L) -> Self {
+        Self { console }
+    }
+}
+
+/// Note that a `TeeLogger` was installed as the global logger
+pub(crate) fn tee_installed() {
+    TEE_INSTALLED.store(true, Ordering::SeqCst);
+}
+
+impl<L: Log> Log for TeeLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata) || file_enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.console.enabled(record.metadata()) {
+            self.console.log(record);
+        }
+        // Taken out of the lock, as writing may log a warning of its own
+        let files: Vec<Arc<RotatingFile>> = match ATTACHED.read() {
+            Ok(attached) => attached.files.iter()
+                .filter(|(_, level)| record.level() <= *level)
+                .map(|(file, _)| file.clone())
+                .collect(),
+            Err(_) => return,
+        };
+        if files.is_empty() {
+            return;
+        }
+        let line = format!("{} {:<5} {}: {}", timestamp(), record.level(), record.target(), record.args());
+        for file in files {
+            file.write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+fn file_enabled(metadata: &Metadata) -> bool {
+    ATTACHED.read().is_ok_and(|attached| {
+        attached.files.iter().any(|(_, level)| metadata.level() <= *level)
+    })
+}
+
+/// Log file of a run, detached again when dropped
+///
+/// Log records go to the file when the global logger is the crate's own, installed
+/// by `init_logging`. Otherwise the global logger, or the lack of one, is left to the
+/// host application, and the file gets the run's `ExtractionEvent`s as JSON lines
+/// instead, through `LogFile::event_file`.
+pub struct LogFile {
+    file: Arc<RotatingFile>,
+    /// Whether the file is attached to the crate's logger
+    attached: bool,
+}
+
+impl LogFile {
+    /// Open `path` and write records up to `level` to it, or events if the logger isn't ours
+    pub fn open(path: &Path, level: LevelFilter, rotation: LogRotation) -> Result<Self> {
+        Self::open_with(path, level, rotation, TEE_INSTALLED.load(Ordering::SeqCst))
+    }
+
+    fn open_with(path: &Path, level: LevelFilter, rotation: LogRotation, tee_installed: bool) -> Result<Self> {
+        let file = Arc::new(RotatingFile::open(path, rotation)?);
+        if !tee_installed {
+            return Ok(Self { file, attached: false });
+        }
+        let Ok(mut attached) = ATTACHED.write() else {
+            return Ok(Self { file, attached: false });
+        };
+        if attached.files.is_empty() {
+            attached.base_level = log::max_level();
+        }
+        attached.files.push((file.clone(), level));
+        log::set_max_level(attached.max_level());
+        Ok(Self { file, attached: true })
+    }
+
+    /// File the run's events are to be written to, when log records can't be
+    pub fn event_file(&self) -> Option<Arc<RotatingFile>> {
+        (!self.attached).then(|| self.file.clone())
+    }
+}
+
+impl Drop for LogFile {
+    fn drop(&mut self) {
+        if !self.attached {
+            return;
+        }
+        if let Ok(mut attached) = ATTACHED.write() {
+            // Other runs' files stay attached
+            attached.files.retain(|(file, _)| !Arc::ptr_eq(file, &self.file));
+            log::set_max_level(attached.max_level());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rotation_keeps_newest_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("logs/extraction.log");
+        let file = RotatingFile::open(&path, LogRotation { max_size: 20, keep: 2 }).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line);
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(std::fs::read_to_string(rotated_path(&path, 1)).unwrap(), "third line\n");
+        assert_eq!(std::fs::read_to_string(rotated_path(&path, 2)).unwrap(), "second line\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_reopen_appends() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("extraction.log");
+        RotatingFile::open(&path, LogRotation::default()).unwrap().write_line("first run");
+        RotatingFile::open(&path, LogRotation::default()).unwrap().write_line("second run");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first run\nsecond run\n");
+
+        let truncating = RotatingFile::open(&path, LogRotation { max_size: 1, keep: 0 }).unwrap();
+        truncating.write_line("third run");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third run\n");
+        assert!(!rotated_path(&path, 1).exists());
+    }
+
+    struct Quiet;
+
+    impl Log for Quiet {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            false
+        }
+
+        fn log(&self, _record: &Record) {}
+
+        fn flush(&self) {}
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path).unwrap()
+            .lines()
+            .map(|line| line.split_once(": ").unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_files_attach_and_detach() {
+        let dir = TempDir::new().unwrap();
+        let (first_path, second_path) = (dir.path().join("first.log"), dir.path().join("second.log"));
+        let base_level = log::max_level();
+        let logger = TeeLogger::new(Quiet);
+        let log = |level, message: &str| {
+            logger.log(&Record::builder().level(level).target("test").args(format_args!("{}", message)).build());
+        };
+
+        let first = LogFile::open_with(&first_path, LevelFilter::Info, LogRotation::default(), true).unwrap();
+        let second = LogFile::open_with(&second_path, LevelFilter::Trace, LogRotation::default(), true).unwrap();
+        assert!(first.event_file().is_none() && second.event_file().is_none());
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        log(log::Level::Info, "both runs");
+        log(log::Level::Debug, "second run only");
+
+        drop(first);
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        log(log::Level::Info, "first run done");
+        drop(second);
+        assert_eq!(log::max_level(), base_level);
+        log(log::Level::Info, "both runs done");
+
+        assert_eq!(lines(&first_path), ["both runs"]);
+        assert_eq!(lines(&second_path), ["both runs", "second run only", "first run done"]);
+    }
+
+    #[test]
+    fn test_host_logger_left_alone() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("extraction.log");
+        let log_file = LogFile::open(&path, LevelFilter::Debug, LogRotation::default()).unwrap();
+
+        // The tests never install the crate's logger, and opening a file mustn't either
+        assert!(!TEE_INSTALLED.load(Ordering::SeqCst));
+        assert_eq!(log_file.event_file().unwrap().path(), path);
+        assert!(!ATTACHED.read().unwrap().files.iter().any(|(file, _)| file.path() == path));
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{normalize_entry_path, sha256_file, write_replacing, CHECKSUM_FILE_NAME};
+
+/// File name of the manifest written into each extracted PBO's base directory
+pub const MANIFEST_FILE_NAME: &str = ".extraction-manifest.json";
+
+/// File written by an extraction, relative to the PBO's base directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Files of a manifest that no longer match its base directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestCheck {
+    /// Listed files that are gone
+    pub missing: Vec<String>,
+    /// Listed files whose size changed
+    pub changed: Vec<String>,
+}
+
+impl ManifestCheck {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Record of what was extracted from a single PBO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractionManifest {
+    /// Path of the source PBO
+    pub source: PathBuf,
+    /// Hash of the source PBO, as computed by `utils::calculate_file_hash`
+    pub hash: String,
+    /// Prefix declared in the PBO header, if any
+    pub prefix: Option<String>,
+    /// Extension filter used for the extraction
+    pub extensions: String,
+    /// Time of the extraction in seconds since the UNIX epoch
+    pub extracted_at: u64,
+    /// Files present in the base directory after extraction
+    pub files: Vec<ManifestFile>,
+    /// Entries written under a lowercased or sanitized name, path in the PBO to the
+    /// path written
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub renamed: BTreeMap<String, String>,
+    /// Whether the files were handed to a sink instead of being kept; `files` still
+    /// lists them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub streamed: bool,
+    /// Whether the PBO was extracted whole without being listed first, so `files`
+    /// also stands in for the files it was expected to hold
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub listed_after_extraction: bool,
+    /// Files the transformer handled, relative to the base directory, so later runs
+    /// carrying them over don't transform them again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transformed: Vec<String>,
+}
+
+impl ExtractionManifest {
+    /// Record the files currently below `base_dir`, excluding the manifest and checksum list
+    pub fn collect(
+        base_dir: &Path,
+        source: &Path,
+        hash: String,
+        prefix: Option<String>,
+        extensions: &str,
+    ) -> Result<Self> {
+        let mut files = Vec::new();
+        for entry in walkdir::WalkDir::new(base_dir).into_iter().filter_map(|e| e.ok()) {
+            let is_sidecar = entry.depth() == 1
+                && (entry.file_name() == MANIFEST_FILE_NAME || entry.file_name() == CHECKSUM_FILE_NAME);
+            if !entry.file_type().is_file() || is_sidecar {
+                continue;
+            }
+            let rel_path = entry.path().strip_prefix(base_dir)?;
+            files.push(ManifestFile {
+                path: normalize_entry_path(&rel_path.to_string_lossy()),
+                size: entry.metadata()?.len(),
+            });
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self {
+            source: source.to_owned(),
+            hash,
+            prefix,
+            extensions: extensions.to_string(),
+            extracted_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            files,
+            renamed: BTreeMap::new(),
+            streamed: false,
+            listed_after_extraction: false,
+            transformed: Vec::new(),
+        })
+    }
+
+    /// Write the manifest into `base_dir`, replacing rather than writing through the
+    /// previous one
+    pub fn write(&self, base_dir: &Path) -> Result<()> {
+        let path = base_dir.join(MANIFEST_FILE_NAME);
+        let json = serde_json::to_string_pretty(self)?;
+        crate::utils::write_replacing(&path, json)
+            .with_context(|| format!("Failed to write manifest: {}", path.display()))
+    }
+
+    /// Read the manifest from `base_dir`
+    pub fn read(base_dir: &Path) -> Result<Self> {
+        let path = base_dir.join(MANIFEST_FILE_NAME);
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Compare the listed files with those in `base_dir` by presence and size
+    ///
+    /// Streamed extractions always pass, as their files were never kept.
+    pub fn check(&self, base_dir: &Path) -> ManifestCheck {
+        let mut check = ManifestCheck::default();
+        if self.streamed {
+            return check;
+        }
+        for file in &self.files {
+            match std::fs::metadata(base_dir.join(&file.path)) {
+                Ok(meta) if meta.is_file() && meta.len() == file.size => {},
+                Ok(meta) if meta.is_file() => check.changed.push(file.path.clone()),
+                _ => check.missing.push(file.path.clone()),
+            }
+        }
+        check
+    }
+}
+
+/// Write `SHA256SUMS` for every file below `dir` in `sha256sum -c` format
+///
+/// Files are hashed in parallel. The checksum list and the extraction manifest
+/// themselves are not included, and a directory with no other files gets an
+/// empty list. Returns the number of files hashed.
+pub fn write_checksums(dir: &Path) -> Result<usize> {
+    let files: Vec<_> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            e.depth() != 1 || (e.file_name() != CHECKSUM_FILE_NAME && e.file_name() != MANIFEST_FILE_NAME)
+        })
+        .collect();
+
+    let mut lines = files
+        .par_iter()
+        .map(|entry| {
+            let rel_path = entry.path().strip_prefix(dir)?;
+            let hash = sha256_file(entry.path())?;
+            Ok(format!("{}  {}", hash, normalize_entry_path(&rel_path.to_string_lossy())))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    lines.sort_by(|a, b| a[64..].cmp(&b[64..]));
+
+    let path = dir.join(CHECKSUM_FILE_NAME);
+    let mut content = lines.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    write_replacing(&path, content)
+        .with_context(|| format!("Failed to write checksums: {}", path.display()))?;
+
+    Ok(lines.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::verify_checksums;
+    use tempfile::TempDir;
+
+    fn manifest() -> ExtractionManifest {
+        ExtractionManifest {
+            source: PathBuf::from("@ace/addons/ace_medical.pbo"),
+            hash: "abc123".to_string(),
+            prefix: Some("z\\ace\\addons\\medical".to_string()),
+            extensions: "sqf,hpp".to_string(),
+            extracted_at: 1_700_000_000,
+            files: vec![ManifestFile {
+                path: "z/ace/addons/medical/fnc_heal.sqf".to_string(),
+                size: 42,
+            }],
+            renamed: BTreeMap::new(),
+            streamed: false,
+            listed_after_extraction: false,
+            transformed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_manifest_json_round_trip() {
+        let manifest = manifest();
+        let json = serde_json::to_string(&manifest).unwrap();
+        let parsed: ExtractionManifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, manifest);
+    }
+
+    #[test]
+    fn test_manifest_renamed_entries() {
+        let mut manifest = manifest();
+        assert!(!serde_json::to_string(&manifest).unwrap().contains("renamed"));
+
+        manifest.renamed.insert("data/what?.paa".to_string(), "data/what_.paa".to_string());
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.contains(r#""renamed":{"data/what?.paa":"data/what_.paa"}"#), "{json}");
+        assert_eq!(serde_json::from_str::<ExtractionManifest>(&json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_manifest_file_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let manifest = manifest();
+        manifest.write(temp_dir.path()).unwrap();
+        assert_eq!(ExtractionManifest::read(temp_dir.path()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_check_against_disk() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path();
+        let mut manifest = manifest();
+        manifest.files.push(ManifestFile { path: "z/ace/addons/medical/config.cpp".to_string(), size: 3 });
+        std::fs::create_dir_all(base_dir.join("z/ace/addons/medical")).unwrap();
+        std::fs::write(base_dir.join("z/ace/addons/medical/config.cpp"), b"class").unwrap();
+
+        assert_eq!(manifest.check(base_dir), ManifestCheck {
+            missing: vec!["z/ace/addons/medical/fnc_heal.sqf".to_string()],
+            changed: vec!["z/ace/addons/medical/config.cpp".to_string()],
+        });
+        std::fs::write(base_dir.join("z/ace/addons/medical/fnc_heal.sqf"), vec![0u8; 42]).unwrap();
+        std::fs::write(base_dir.join("z/ace/addons/medical/config.cpp"), b"cls").unwrap();
+        assert!(manifest.check(base_dir).is_ok());
+
+        manifest.streamed = true;
+        assert!(manifest.check(temp_dir.path().join("gone").as_path()).is_ok());
+    }
+
+    #[test]
+    fn test_collect_skips_previous_manifest() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path();
+        std::fs::create_dir_all(base_dir.join("functions")).unwrap();
+        std::fs::write(base_dir.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(base_dir.join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        let manifest = ExtractionManifest::collect(
+            base_dir,
+            Path::new("ace_medical.pbo"),
+            "abc123".to_string(),
+            None,
+            "sqf",
+        ).unwrap();
+
+        assert_eq!(manifest.files, vec![ManifestFile {
+            path: "functions/fnc_heal.sqf".to_string(),
+            size: 4,
+        }]);
+    }
+
+    #[test]
+    fn test_write_and_verify_checksums() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        std::fs::write(root.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("config.cpp"), b"class").unwrap();
+        std::fs::write(root.join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        assert_eq!(write_checksums(root).unwrap(), 2);
+        let sums = std::fs::read_to_string(root.join(CHECKSUM_FILE_NAME)).unwrap();
+        let paths: Vec<_> = sums.lines().map(|line| &line[66..]).collect();
+        assert_eq!(paths, vec!["config.cpp", "functions/fnc_heal.sqf"]);
+        assert!(verify_checksums(root).unwrap().is_empty());
+
+        std::fs::write(root.join("config.cpp"), b"changed").unwrap();
+        std::fs::remove_file(root.join("functions/fnc_heal.sqf")).unwrap();
+
+        let mismatches = verify_checksums(root).unwrap();
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].path, "config.cpp");
+        assert!(mismatches[0].actual.is_some());
+        assert_eq!(mismatches[1].path, "functions/fnc_heal.sqf");
+        assert_eq!(mismatches[1].actual, None);
+    }
+
+    #[test]
+    fn test_checksums_of_empty_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        assert_eq!(write_checksums(temp_dir.path()).unwrap(), 0);
+        assert_eq!(std::fs::read(temp_dir.path().join(CHECKSUM_FILE_NAME)).unwrap(), b"");
+        assert!(verify_checksums(temp_dir.path()).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,42 @@
 pub mod types;
+pub mod archive;
+pub mod backend;
+pub mod cache_lock;
+pub mod cancel;
+pub mod config_file;
+pub mod events;
+pub mod failure;
+pub mod index;
+pub mod index_diff;
+pub mod index_merge;
+pub mod limit;
+pub mod listing_cache;
+pub mod log_file;
+pub mod manifest;
+pub mod ndjson;
+#[cfg(feature = "paa-convert")]
+pub mod paa;
+pub mod pbo_diff;
+pub mod progress;
+pub mod quarantine;
+pub mod report_csv;
+pub mod retry;
+pub mod run_report;
+#[cfg(feature = "signatures")]
+pub mod signature;
+pub mod store;
+pub mod stream;
+pub mod stringtable;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod throughput;
+pub mod transform;
+pub mod filter;
 pub mod processor;
 pub mod coordinator;
-pub mod utils;
\ No newline at end of file
+pub mod dedupe;
+pub mod derap;
+pub mod utils;
+pub mod versions;
+#[cfg(feature = "watch")]
+pub mod watch;
\ No newline at end of file
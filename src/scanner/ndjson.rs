@@ -0,0 +1,235 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use log::warn;
+
+use super::events::ExtractionEvent;
+
+/// Events queued for writing before further ones are dropped
+pub const DEFAULT_NDJSON_CAPACITY: usize = 4096;
+
+enum Message {
+    Event(ExtractionEvent),
+    /// Answered once everything queued before it is written
+    Flush(SyncSender<()>),
+}
+
+/// Writer of events as newline-delimited JSON, one `ExtractionEvent` object per line
+///
+/// Lines are written and flushed by a thread of its own, so a slow reader never
+/// stalls the workers sending events: once `capacity` events are queued, further
+/// ones are dropped and counted. Only `RunFinished`, sent once at the end of a run,
+/// waits for room instead, so the summary always comes last. After a write error,
+/// like a closed pipe, events are counted as dropped too.
+pub struct NdjsonWriter {
+    sender: Option<SyncSender<Message>>,
+    dropped: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NdjsonWriter {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::with_capacity(writer, DEFAULT_NDJSON_CAPACITY)
+    }
+
+    pub fn with_capacity<W: Write + Send + 'static>(writer: W, capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread_dropped = dropped.clone();
+        let thread = std::thread::Builder::new()
+            .name("ndjson-events".to_string())
+            .spawn(move || write_events(writer, receiver, &thread_dropped))
+            .expect("failed to spawn the event writer thread");
+        Self {
+            sender: Some(sender),
+            dropped,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue `event` for writing, or drop it if the queue is full
+    ///
+    /// `RunFinished` waits for room in the queue rather than being dropped.
+    pub fn send(&self, event: &ExtractionEvent) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if matches!(event, ExtractionEvent::RunFinished { .. }) {
+            if sender.send(Message::Event(event.clone())).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+        match sender.try_send(Message::Event(event.clone())) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+    }
+
+    /// Events dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wait until every event queued so far is written
+    pub fn flush(&self) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let (done, written) = sync_channel(1);
+        if sender.send(Message::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+impl std::fmt::Debug for NdjsonWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NdjsonWriter").field("dropped", &self.dropped()).finish_non_exhaustive()
+    }
+}
+
+impl Drop for NdjsonWriter {
+    fn drop(&mut self) {
+        // Ends the thread once it has written what is queued
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_events<W: Write>(mut writer: W, receiver: Receiver<Message>, dropped: &AtomicU64) {
+    let mut failed = false;
+    for message in receiver {
+        match message {
+            Message::Event(_) if failed => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            },
+            Message::Event(event) => {
+                let written = serde_json::to_writer(&mut writer, &event)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| writer.write_all(b"\n"))
+                    .and_then(|_| writer.flush());
+                if let Err(e) = written {
+                    warn!("Stopped writing JSON events: {}", e);
+                    failed = true;
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+            Message::Flush(done) => {
+                let _ = done.send(());
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+    use super::super::types::SkipReason;
+
+    /// Writer into a shared buffer that blocks until `release` is dropped
+    struct SlowWriter {
+        buffer: Arc<Mutex<Vec<u8>>>,
+        gate: Option<Receiver<()>>,
+    }
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if let Some(gate) = self.gate.take() {
+                let _ = gate.recv();
+            }
+            self.buffer.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn skipped(index: usize) -> ExtractionEvent {
+        ExtractionEvent::PboSkipped { path: PathBuf::from(format!("{}.pbo", index)), reason: SkipReason::AlreadyExtracted }
+    }
+
+    #[test]
+    fn test_slow_reader_drops_instead_of_blocking() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (release, gate) = sync_channel::<()>(0);
+        let writer = NdjsonWriter::with_capacity(SlowWriter { buffer: buffer.clone(), gate: Some(gate) }, 2);
+
+        let started = Instant::now();
+        for index in 0..10 {
+            writer.send(&skipped(index));
+        }
+        assert!(started.elapsed() < Duration::from_secs(1));
+        // One event is held by the blocked writer, two are queued
+        assert!(writer.dropped() >= 7, "{:?}", writer);
+
+        drop(release);
+        writer.flush();
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len() as u64 + writer.dropped(), 10);
+        assert_eq!(lines[0]["event"], "pbo_skipped");
+        assert_eq!(lines[0]["path"], "0.pbo");
+    }
+
+    #[test]
+    fn test_run_finished_waits_for_room() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (release, gate) = sync_channel::<()>(0);
+        let writer = NdjsonWriter::with_capacity(SlowWriter { buffer: buffer.clone(), gate: Some(gate) }, 1);
+        for index in 0..5 {
+            writer.send(&skipped(index));
+        }
+        let dropped = writer.dropped();
+        assert!(dropped > 0);
+
+        let releasing = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(release);
+        });
+        writer.send(&ExtractionEvent::RunFinished {
+            discovered: 5,
+            extracted: 0,
+            skipped: 5,
+            failed: 0,
+            scan_failed: 0,
+            files: 0,
+            bytes: 0,
+            duration: Duration::from_millis(10),
+            interrupted: false,
+            aborted: false,
+            dropped_events: dropped,
+            error: None,
+        });
+        releasing.join().unwrap();
+        writer.flush();
+
+        assert_eq!(writer.dropped(), dropped);
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(output.lines().last().unwrap()).unwrap();
+        assert_eq!(summary["event"], "run_finished");
+        assert!(summary.get("error").is_none());
+    }
+
+    #[test]
+    fn test_drop_writes_queued_events() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = NdjsonWriter::new(SlowWriter { buffer: buffer.clone(), gate: None });
+        for index in 0..100 {
+            writer.send(&skipped(index));
+        }
+        drop(writer);
+        assert_eq!(String::from_utf8(buffer.lock().unwrap().clone()).unwrap().lines().count(), 100);
+    }
+}
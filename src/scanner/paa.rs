@@ -0,0 +1,497 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use rayon::prelude::*;
+
+/// Texture extensions converted by `convert_paa_tree`
+const TEXTURE_EXTENSIONS: [&str; 2] = ["paa", "pac"];
+
+/// Mipmap widths with this bit set hold LZO compressed data
+const LZO_FLAG: u16 = 0x8000;
+
+/// Block compressed PAA formats the decoder supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaaFormat {
+    Dxt1,
+    Dxt5,
+}
+
+impl PaaFormat {
+    /// Format of the type tag at the start of a PAA file
+    pub fn from_tag(tag: u16) -> Option<Self> {
+        match tag {
+            0xff01 => Some(PaaFormat::Dxt1),
+            0xff05 => Some(PaaFormat::Dxt5),
+            _ => None,
+        }
+    }
+
+    /// Bytes per 4x4 pixel block
+    fn block_size(self) -> usize {
+        match self {
+            PaaFormat::Dxt1 => 8,
+            PaaFormat::Dxt5 => 16,
+        }
+    }
+}
+
+/// Largest mipmap of a PAA texture, decoded to RGBA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaaImage {
+    pub format: PaaFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Eight bits per channel, row by row
+    pub rgba: Vec<u8>,
+}
+
+impl PaaImage {
+    /// Decode the largest mipmap of a PAA file
+    ///
+    /// Unsupported formats fail with an error naming their type tag.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        let tag = reader.u16()?;
+        let format = PaaFormat::from_tag(tag)
+            .with_context(|| format!("unsupported PAA format 0x{:04X}", tag))?;
+
+        // Tags hold the average color, flags and mipmap offsets, none needed here
+        while data.get(reader.pos..reader.pos + 4) == Some(b"GGAT") {
+            reader.take(8)?;
+            let len = reader.u32()? as usize;
+            reader.take(len)?;
+        }
+        let palette_len = reader.u16()? as usize;
+        reader.take(palette_len * 3)?;
+
+        let width = reader.u16()?;
+        let height = reader.u16()? as usize;
+        let compressed = width & LZO_FLAG != 0;
+        let width = (width & !LZO_FLAG) as usize;
+        if width == 0 || height == 0 {
+            anyhow::bail!("PAA file without mipmaps");
+        }
+        let len = reader.u24()?;
+        let mipmap = reader.take(len)?;
+
+        let expected = width.div_ceil(4) * height.div_ceil(4) * format.block_size();
+        let blocks = if compressed {
+            lzo1x_decompress(mipmap, expected).context("corrupt LZO compressed mipmap")?
+        } else {
+            mipmap.to_vec()
+        };
+        if blocks.len() < expected {
+            anyhow::bail!("truncated mipmap: {} of {} bytes", blocks.len(), expected);
+        }
+
+        Ok(Self {
+            format,
+            width: width as u32,
+            height: height as u32,
+            rgba: decode_blocks(format, &blocks, width, height),
+        })
+    }
+
+    /// Write the image as an 8-bit RGBA PNG
+    ///
+    /// An existing file at `path` is replaced rather than written into, so hard links
+    /// to it keep their content.
+    pub fn write_png(&self, path: &Path) -> Result<()> {
+        let mut png_data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png_data, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgba)?;
+        writer.finish()?;
+        crate::utils::write_replacing(path, png_data).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + len)
+            .with_context(|| format!("unexpected end of file at offset {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Result<usize> {
+        let bytes = self.take(3)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]) as usize)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+fn decode_blocks(format: PaaFormat, blocks: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut rgba = vec![0; width * height * 4];
+    let blocks_wide = width.div_ceil(4);
+    let block_count = blocks_wide * height.div_ceil(4);
+
+    for (index, block) in blocks.chunks_exact(format.block_size()).take(block_count).enumerate() {
+        let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+        let (alpha, color) = match format {
+            PaaFormat::Dxt1 => (None, block),
+            PaaFormat::Dxt5 => {
+                let mut bits = [0; 8];
+                bits[..6].copy_from_slice(&block[2..8]);
+                (Some((alpha_palette(block[0], block[1]), u64::from_le_bytes(bits))), &block[8..])
+            },
+        };
+        let colors = color_palette(color, format == PaaFormat::Dxt1);
+        let indices = u32::from_le_bytes([color[4], color[5], color[6], color[7]]);
+
+        for pixel in 0..16 {
+            let (x, y) = (block_x + pixel % 4, block_y + pixel / 4);
+            if x >= width || y >= height {
+                continue;
+            }
+            let mut value = colors[(indices >> (2 * pixel) & 3) as usize];
+            if let Some((alphas, bits)) = alpha {
+                value[3] = alphas[(bits >> (3 * pixel) & 7) as usize];
+            }
+            let offset = (y * width + x) * 4;
+            rgba[offset..offset + 4].copy_from_slice(&value);
+        }
+    }
+    rgba
+}
+
+/// The four colors of a DXT color block
+///
+/// DXT1 blocks whose first color isn't greater than the second have a single
+/// interpolated color and transparent black instead of two interpolated colors.
+fn color_palette(block: &[u8], dxt1: bool) -> [[u8; 4]; 4] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (p0, p1) = (rgb565(c0), rgb565(c1));
+    let mix = |w0: u32, w1: u32| {
+        let channel = |i: usize| ((p0[i] as u32 * w0 + p1[i] as u32 * w1) / (w0 + w1)) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    if c0 > c1 || !dxt1 {
+        [p0, p1, mix(2, 1), mix(1, 2)]
+    } else {
+        [p0, p1, mix(1, 1), [0, 0, 0, 0]]
+    }
+}
+
+fn rgb565(color: u16) -> [u8; 4] {
+    let (r, g, b) = ((color >> 11) as u8, (color >> 5 & 0x3f) as u8, (color & 0x1f) as u8);
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+}
+
+/// The eight alpha values of a DXT5 alpha block
+fn alpha_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (w0, w1) = (a0 as u32, a1 as u32);
+    let mut alphas = [a0, a1, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            alphas[i + 1] = (((7 - i as u32) * w0 + i as u32 * w1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            alphas[i + 1] = (((5 - i as u32) * w0 + i as u32 * w1) / 5) as u8;
+        }
+    }
+    alphas
+}
+
+/// Decompress an LZO1X stream producing at most `max_len` bytes
+fn lzo1x_decompress(input: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    // Sized by the input too, since `max_len` comes from the file's header
+    let mut out: Vec<u8> = Vec::with_capacity(max_len.min(input.len().saturating_mul(4)));
+    let mut ip = 0;
+    let byte = |ip: &mut usize| -> Result<usize> {
+        let value = *input.get(*ip).context("input overrun")?;
+        *ip += 1;
+        Ok(value as usize)
+    };
+    // Lengths of zero continue in the following bytes, 255 per zero byte
+    let long_length = |ip: &mut usize, base: usize| -> Result<usize> {
+        let mut length = base;
+        loop {
+            match byte(ip)? {
+                0 => length += 255,
+                value => return Ok(length + value),
+            }
+        }
+    };
+    let copy_literals = |out: &mut Vec<u8>, ip: &mut usize, len: usize| -> Result<()> {
+        let literals = input.get(*ip..*ip + len).context("input overrun")?;
+        if out.len() + len > max_len {
+            anyhow::bail!("output overrun");
+        }
+        out.extend_from_slice(literals);
+        *ip += len;
+        Ok(())
+    };
+
+    // Literals copied after the previous instruction, which selects the meaning
+    // of short match instructions
+    let mut state = 0;
+    if input.first().is_some_and(|&first| first > 17) {
+        let len = byte(&mut ip)? - 17;
+        copy_literals(&mut out, &mut ip, len)?;
+        state = len.min(4);
+    }
+
+    loop {
+        let t = byte(&mut ip)?;
+        let (distance, len, next) = if t < 16 {
+            match state {
+                0 => {
+                    let len = if t == 0 { long_length(&mut ip, 15)? } else { t } + 3;
+                    copy_literals(&mut out, &mut ip, len)?;
+                    state = 4;
+                    continue;
+                },
+                4 => (1 + 0x800 + (t >> 2) + (byte(&mut ip)? << 2), 3, t & 3),
+                _ => (1 + (t >> 2) + (byte(&mut ip)? << 2), 2, t & 3),
+            }
+        } else if t >= 64 {
+            (1 + (t >> 2 & 7) + (byte(&mut ip)? << 3), (t >> 5) + 1, t & 3)
+        } else if t >= 32 {
+            let len = match t & 31 {
+                0 => long_length(&mut ip, 31)?,
+                len => len,
+            } + 2;
+            let trailer = byte(&mut ip)? | byte(&mut ip)? << 8;
+            (1 + (trailer >> 2), len, trailer & 3)
+        } else {
+            let len = match t & 7 {
+                0 => long_length(&mut ip, 7)?,
+                len => len,
+            } + 2;
+            let trailer = byte(&mut ip)? | byte(&mut ip)? << 8;
+            let distance = ((t & 8) << 11) + (trailer >> 2);
+            if distance == 0 {
+                break;
+            }
+            (distance + 0x4000, len, trailer & 3)
+        };
+
+        if distance > out.len() {
+            anyhow::bail!("match distance {} before the start of the output", distance);
+        }
+        if out.len() + len > max_len {
+            anyhow::bail!("output overrun");
+        }
+        // Matches may overlap the bytes they produce
+        let start = out.len() - distance;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+        copy_literals(&mut out, &mut ip, next)?;
+        state = next;
+    }
+    Ok(out)
+}
+
+/// Textures found by `convert_paa_tree`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaaConversion {
+    /// Converted textures, by path relative to the directory
+    pub converted: Vec<String>,
+    /// Textures that were not converted, as `path: reason`
+    pub skipped: Vec<String>,
+}
+
+/// Write a `.png` next to every `.paa` and `.pac` texture below `dir` in parallel
+///
+/// Unsupported or corrupt textures are logged and recorded, not treated as
+/// errors, and so are textures with an existing `.png`: the processor only leaves
+/// those the PBO ships, and removes ones converted for an earlier output. With
+/// `replace_originals` converted textures are deleted.
+pub fn convert_paa_tree(dir: &Path, replace_originals: bool) -> Result<PaaConversion> {
+    let textures: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| {
+            TEXTURE_EXTENSIONS.iter().any(|texture| ext.eq_ignore_ascii_case(texture))
+        }))
+        .collect();
+
+    let results: Vec<_> = textures
+        .par_iter()
+        .map(|path| {
+            let rel_path = path.strip_prefix(dir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+            (rel_path, convert_paa(path, replace_originals))
+        })
+        .collect();
+
+    let mut conversion = PaaConversion::default();
+    for (rel_path, result) in results {
+        match result {
+            Ok(()) => conversion.converted.push(rel_path),
+            Err(e) => {
+                warn!("Not converting texture {}: {:#}", rel_path, e);
+                conversion.skipped.push(format!("{}: {:#}", rel_path, e));
+            },
+        }
+    }
+    conversion.converted.sort();
+    conversion.skipped.sort();
+    Ok(conversion)
+}
+
+fn convert_paa(path: &Path, replace_original: bool) -> Result<()> {
+    let png_path = path.with_extension("png");
+    if png_path.exists() {
+        anyhow::bail!("{} already exists", png_path.file_name().unwrap_or_default().to_string_lossy());
+    }
+    let image = PaaImage::decode(&std::fs::read(path)?)?;
+    image.write_png(&png_path)?;
+    if replace_original {
+        std::fs::remove_file(path)?;
+    }
+    debug!("Converted {:?} texture {} to {}", image.format, path.display(), png_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    /// Red and blue, with the four palette indices in the first row
+    const DXT1_BLOCK: [u8; 8] = [0x00, 0xf8, 0x1f, 0x00, 0b11_10_01_00, 0, 0, 0];
+
+    /// Opaque to transparent in the first row, over the DXT1 colors
+    const DXT5_BLOCK: [u8; 16] = [
+        255, 0, 0b10_001_000, 0, 0, 0, 0, 0,
+        0x00, 0xf8, 0x1f, 0x00, 0b11_10_01_00, 0, 0, 0,
+    ];
+
+    fn paa(tag: u16, width: u16, height: u16, data: &[u8]) -> Vec<u8> {
+        let mut paa = tag.to_le_bytes().to_vec();
+        paa.extend_from_slice(b"GGATCGVA");
+        paa.extend_from_slice(&4u32.to_le_bytes());
+        paa.extend_from_slice(&[0x80, 0x80, 0x80, 0xff]);
+        paa.extend_from_slice(&0u16.to_le_bytes());
+        paa.extend_from_slice(&width.to_le_bytes());
+        paa.extend_from_slice(&height.to_le_bytes());
+        paa.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+        paa.extend_from_slice(data);
+        // Smaller mipmaps are ignored; an empty one ends the list
+        paa.extend_from_slice(&[0; 4]);
+        paa
+    }
+
+    fn pixel(image: &PaaImage, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * image.width as usize + x) * 4;
+        image.rgba[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_decode_dxt1() {
+        let image = PaaImage::decode(&paa(0xff01, 4, 4, &DXT1_BLOCK)).unwrap();
+        assert_eq!(image.format, PaaFormat::Dxt1);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(pixel(&image, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 2, 0), [170, 0, 85, 255]);
+        assert_eq!(pixel(&image, 3, 0), [85, 0, 170, 255]);
+        assert_eq!(pixel(&image, 3, 3), [255, 0, 0, 255]);
+
+        // The first color not being greater selects the transparent palette
+        let block = [0x1f, 0x00, 0x00, 0xf8, 0b11_10_01_00, 0, 0, 0];
+        let image = PaaImage::decode(&paa(0xff01, 4, 4, &block)).unwrap();
+        assert_eq!(pixel(&image, 2, 0), [127, 0, 127, 255]);
+        assert_eq!(pixel(&image, 3, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_dxt5() {
+        let image = PaaImage::decode(&paa(0xff05, 4, 4, &DXT5_BLOCK)).unwrap();
+        assert_eq!(image.format, PaaFormat::Dxt5);
+        assert_eq!(pixel(&image, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 1, 0), [0, 0, 255, 0]);
+        assert_eq!(pixel(&image, 2, 0), [170, 0, 85, 218]);
+        assert_eq!(pixel(&image, 3, 0), [85, 0, 170, 255]);
+    }
+
+    #[test]
+    fn test_decode_lzo_compressed() {
+        // Eight literal bytes, the same eight bytes again as a match, end of stream
+        let mut lzo = vec![17 + 8];
+        lzo.extend_from_slice(&DXT1_BLOCK);
+        lzo.extend_from_slice(&[32 + 6, 7 << 2, 0, 0x11, 0, 0]);
+
+        let image = PaaImage::decode(&paa(0xff01, 8 | LZO_FLAG, 4, &lzo)).unwrap();
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!(pixel(&image, 4, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 5, 0), [0, 0, 255, 255]);
+
+        let truncated = &lzo[..lzo.len() - 3];
+        assert!(PaaImage::decode(&paa(0xff01, 8 | LZO_FLAG, 4, truncated)).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_unsupported_formats() {
+        let error = PaaImage::decode(&paa(0x4444, 4, 4, &[0; 32])).unwrap_err();
+        assert_eq!(error.to_string(), "unsupported PAA format 0x4444");
+        let error = PaaImage::decode(&paa(0xff01, 4, 4, &DXT1_BLOCK[..4])).unwrap_err();
+        assert!(error.to_string().starts_with("truncated mipmap"), "{error}");
+
+        // Sizes in the header don't decide what is allocated for a short stream
+        assert_eq!(lzo1x_decompress(&[0x11, 0, 0], usize::MAX).unwrap(), Vec::<u8>::new());
+        let huge = PaaImage::decode(&paa(0xff01, 0x7fff | LZO_FLAG, 0xffff, &[0x11, 0, 0])).unwrap_err();
+        assert!(huge.to_string().starts_with("truncated mipmap"), "{huge}");
+    }
+
+    #[test]
+    fn test_convert_paa_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("data")).unwrap();
+        std::fs::write(root.join("data/medkit_co.paa"), paa(0xff01, 4, 4, &DXT1_BLOCK)).unwrap();
+        std::fs::write(root.join("data/medkit_ca.PAC"), paa(0xff05, 4, 4, &DXT5_BLOCK)).unwrap();
+        std::fs::write(root.join("data/medkit_nohq.paa"), paa(0x8888, 4, 4, &[0; 64])).unwrap();
+
+        let conversion = convert_paa_tree(root, false).unwrap();
+        assert_eq!(conversion.converted, vec!["data/medkit_ca.PAC", "data/medkit_co.paa"]);
+        assert_eq!(conversion.skipped, vec!["data/medkit_nohq.paa: unsupported PAA format 0x8888"]);
+        assert!(root.join("data/medkit_co.paa").exists());
+        assert!(!root.join("data/medkit_nohq.png").exists());
+
+        let decoder = png::Decoder::new(File::open(root.join("data/medkit_ca.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(&rgba[..8], &[255, 0, 0, 255, 0, 0, 255, 0]);
+
+        // PNGs the PBO ships are left alone, and so are their textures
+        std::fs::write(root.join("data/medkit_co.png"), b"shipped").unwrap();
+        let conversion = convert_paa_tree(root, true).unwrap();
+        assert!(conversion.converted.is_empty());
+        assert_eq!(conversion.skipped[1], "data/medkit_co.paa: medkit_co.png already exists");
+        assert_eq!(std::fs::read(root.join("data/medkit_co.png")).unwrap(), b"shipped");
+        assert!(root.join("data/medkit_co.paa").exists());
+
+        std::fs::remove_file(root.join("data/medkit_ca.png")).unwrap();
+        std::fs::remove_file(root.join("data/medkit_co.png")).unwrap();
+        let conversion = convert_paa_tree(root, true).unwrap();
+        assert_eq!(conversion.converted.len(), 2);
+        assert!(!root.join("data/medkit_co.paa").exists());
+        assert!(root.join("data/medkit_co.png").exists());
+        assert!(root.join("data/medkit_nohq.paa").exists());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::types::{PboContents, PboEntry, WalkOptions};
+use super::utils::list_pbo_contents;
+use crate::utils::{normalize_entry_path, sha256_file};
+
+/// Entry whose size differs between two versions of a PBO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryChange {
+    /// Path inside the newer PBO, using forward slashes
+    pub path: String,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+}
+
+/// Differences between the entries of two versions of a PBO, as returned by `compare_pbos`
+///
+/// Entries are paired case-insensitively, like the game resolves them. Listings
+/// carry sizes but no checksums, so an entry edited without changing its size is
+/// not reported, nor is any entry of a listing without sizes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboDiff {
+    pub old_prefix: Option<String>,
+    pub new_prefix: Option<String>,
+    /// Entries only in the newer PBO
+    pub added: Vec<PboEntry>,
+    /// Entries only in the older PBO
+    pub removed: Vec<PboEntry>,
+    pub changed: Vec<EntryChange>,
+}
+
+impl PboDiff {
+    /// Whether the entries and prefix are the same in both versions
+    pub fn is_empty(&self) -> bool {
+        self.old_prefix == self.new_prefix && self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Compare two listings of a PBO
+    pub fn from_contents(old: &PboContents, new: &PboContents) -> Self {
+        let entries = |contents: &PboContents| -> BTreeMap<String, PboEntry> {
+            contents.entries.iter()
+                .map(|path| {
+                    let path = normalize_entry_path(path);
+                    let size = contents.entry_sizes.get(&path).copied();
+                    (path.to_lowercase(), PboEntry { path, size })
+                })
+                .collect()
+        };
+        let old_entries = entries(old);
+        let mut new_entries = entries(new);
+
+        let mut diff = PboDiff {
+            old_prefix: old.prefix.clone(),
+            new_prefix: new.prefix.clone(),
+            ..Default::default()
+        };
+        for (key, old_entry) in old_entries {
+            match new_entries.remove(&key) {
+                Some(new_entry) if new_entry.size != old_entry.size => diff.changed.push(EntryChange {
+                    path: new_entry.path,
+                    old_size: old_entry.size,
+                    new_size: new_entry.size,
+                }),
+                Some(_) => {},
+                None => diff.removed.push(old_entry),
+            }
+        }
+        diff.added = new_entries.into_values().collect();
+        diff
+    }
+}
+
+impl fmt::Display for PboDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |size: Option<u64>| size.map(|size| size.to_string()).unwrap_or_else(|| "?".to_string());
+        write!(f, "{} added, {} removed, {} changed", self.added.len(), self.removed.len(), self.changed.len())?;
+        if self.old_prefix != self.new_prefix {
+            write!(
+                f,
+                "\n  prefix: {} -> {}",
+                self.old_prefix.as_deref().unwrap_or("none"),
+                self.new_prefix.as_deref().unwrap_or("none")
+            )?;
+        }
+        for entry in &self.added {
+            write!(f, "\n  + {} ({} bytes)", entry.path, size(entry.size))?;
+        }
+        for entry in &self.removed {
+            write!(f, "\n  - {} ({} bytes)", entry.path, size(entry.size))?;
+        }
+        for change in &self.changed {
+            write!(f, "\n  ~ {}: {} -> {} bytes", change.path, size(change.old_size), size(change.new_size))?;
+        }
+        Ok(())
+    }
+}
+
+/// List two versions of a PBO and compare their entries
+///
+/// `timeout` is the listing timeout in seconds, for each PBO.
+pub fn compare_pbos(old: &Path, new: &Path, timeout: u32) -> Result<PboDiff> {
+    let old_contents = list_pbo_contents(old, timeout)
+        .with_context(|| format!("Failed to list {}", old.display()))?;
+    let new_contents = list_pbo_contents(new, timeout)
+        .with_context(|| format!("Failed to list {}", new.display()))?;
+    Ok(PboDiff::from_contents(&old_contents, &new_contents))
+}
+
+/// PBO found below both roots whose contents differ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedPbo {
+    /// Path relative to the older root
+    pub old_path: PathBuf,
+    /// Path relative to the newer root, differing from `old_path` by case at most
+    pub new_path: PathBuf,
+    pub diff: PboDiff,
+}
+
+/// PBO found below both roots that could not be listed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboDiffFailure {
+    /// Path relative to the newer root
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Differences between two versions of a mod folder, as returned by `compare_dirs`
+///
+/// Lists are sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirDiff {
+    /// PBOs only below the newer root, relative to it
+    pub added: Vec<PathBuf>,
+    /// PBOs only below the older root, relative to it
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<ChangedPbo>,
+    pub failed: Vec<PboDiffFailure>,
+    /// PBOs found below both roots with the same contents
+    pub unchanged: usize,
+}
+
+impl DirDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.failed.is_empty()
+    }
+}
+
+impl fmt::Display for DirDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} PBOs added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )?;
+        if !self.failed.is_empty() {
+            write!(f, ", {} failed to list", self.failed.len())?;
+        }
+        for path in &self.added {
+            write!(f, "\n+ {}", path.display())?;
+        }
+        for path in &self.removed {
+            write!(f, "\n- {}", path.display())?;
+        }
+        for pbo in &self.changed {
+            if pbo.old_path == pbo.new_path {
+                write!(f, "\n~ {}: ", pbo.new_path.display())?;
+            } else {
+                write!(f, "\n~ {} (was {}): ", pbo.new_path.display(), pbo.old_path.display())?;
+            }
+            write!(f, "{}", pbo.diff.to_string().replace('\n', "\n  "))?;
+        }
+        for failure in &self.failed {
+            write!(f, "\n! {}: {}", failure.path.display(), failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the PBOs below two versions of a mod folder
+///
+/// PBOs are paired by their path relative to each root, case-insensitively when
+/// no PBO has the exact same path, so PBOs renamed by case only are compared
+/// rather than reported as removed and added. PBOs with the same bytes are not
+/// listed. PBOs are found like the input directory of an extraction run, except
+/// for `ignore_dirs`.
+pub fn compare_dirs(old_root: &Path, new_root: &Path, walk_options: &WalkOptions, timeout: u32) -> Result<DirDiff> {
+    let old_pbos = find_pbos(old_root, walk_options)?;
+    let new_pbos = find_pbos(new_root, walk_options)?;
+    let (pairs, removed, added) = pair_pbos(old_pbos, new_pbos);
+    debug!("Comparing {} PBOs found below both {} and {}", pairs.len(), old_root.display(), new_root.display());
+
+    let results: Vec<_> = pairs
+        .into_par_iter()
+        .map(|(old_path, new_path)| {
+            let diff = compare_pair(&old_root.join(&old_path), &new_root.join(&new_path), timeout);
+            (old_path, new_path, diff)
+        })
+        .collect();
+
+    let mut diff = DirDiff { added, removed, ..Default::default() };
+    for (old_path, new_path, result) in results {
+        match result {
+            Ok(None) => diff.unchanged += 1,
+            Ok(Some(pbo_diff)) if pbo_diff.is_empty() => diff.unchanged += 1,
+            Ok(Some(pbo_diff)) => diff.changed.push(ChangedPbo { old_path, new_path, diff: pbo_diff }),
+            Err(e) => diff.failed.push(PboDiffFailure { path: new_path, error: format!("{:#}", e) }),
+        }
+    }
+    Ok(diff)
+}
+
+/// Differences between two PBOs, or `None` when their bytes are the same
+///
+/// Compared by content hash, since copies of the same PBO rarely share an mtime.
+fn compare_pair(old: &Path, new: &Path, timeout: u32) -> Result<Option<PboDiff>> {
+    let same_size = std::fs::metadata(old)?.len() == std::fs::metadata(new)?.len();
+    if same_size && sha256_file(old)? == sha256_file(new)? {
+        return Ok(None);
+    }
+    compare_pbos(old, new, timeout).map(Some)
+}
+
+/// Every PBO below `root`, relative to it
+fn find_pbos(root: &Path, walk_options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        anyhow::bail!("Directory does not exist: {}", root.display());
+    }
+    let mut walker = walkdir::WalkDir::new(root).follow_links(walk_options.follow_symlinks);
+    if let Some(max_depth) = walk_options.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+    Ok(walker
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && walk_options.is_archive(entry.path()))
+        .filter_map(|entry| entry.path().strip_prefix(root).ok().map(Path::to_path_buf))
+        .collect())
+}
+
+/// Pairs of old and new paths, then the old and the new paths left unpaired, sorted
+fn pair_pbos(old: Vec<PathBuf>, new: Vec<PathBuf>) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>, Vec<PathBuf>) {
+    let key = |path: &Path| normalize_entry_path(&path.to_string_lossy());
+    let mut new: BTreeMap<String, PathBuf> = new.into_iter().map(|path| (key(&path), path)).collect();
+    let mut pairs = Vec::new();
+    let mut unpaired = Vec::new();
+    for old_path in old {
+        match new.remove(&key(&old_path)) {
+            Some(new_path) => pairs.push((old_path, new_path)),
+            None => unpaired.push(old_path),
+        }
+    }
+
+    let mut new_by_case: HashMap<String, Vec<String>> = HashMap::new();
+    for new_key in new.keys() {
+        new_by_case.entry(new_key.to_lowercase()).or_default().push(new_key.clone());
+    }
+    let mut removed = Vec::new();
+    for old_path in unpaired {
+        let new_key = new_by_case.get_mut(&key(&old_path).to_lowercase()).and_then(|keys| keys.pop());
+        match new_key.and_then(|new_key| new.remove(&new_key)) {
+            Some(new_path) => pairs.push((old_path, new_path)),
+            None => removed.push(old_path),
+        }
+    }
+
+    pairs.sort();
+    removed.sort();
+    (pairs, removed, new.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(entries: &[(&str, u64)]) -> PboContents {
+        PboContents {
+            prefix: Some("z\\ace\\addons\\medical".to_string()),
+            entries: entries.iter().map(|(path, _)| path.to_string()).collect(),
+            entry_sizes: entries.iter().map(|(path, size)| (normalize_entry_path(path), *size)).collect(),
+            properties: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_entries_compared_by_size_ignoring_case() {
+        let old = contents(&[
+            ("config.cpp", 100),
+            ("functions\\fnc_heal.sqf", 40),
+            ("functions\\fnc_removed.sqf", 10),
+            ("Data\\Icon.paa", 512),
+        ]);
+        let new = contents(&[
+            ("config.cpp", 100),
+            ("functions\\fnc_heal.sqf", 42),
+            ("functions\\fnc_added.sqf", 12),
+            ("data\\icon.paa", 512),
+        ]);
+
+        let diff = PboDiff::from_contents(&old, &new);
+        assert_eq!(diff.added, vec![PboEntry { path: "functions/fnc_added.sqf".to_string(), size: Some(12) }]);
+        assert_eq!(diff.removed, vec![PboEntry { path: "functions/fnc_removed.sqf".to_string(), size: Some(10) }]);
+        assert_eq!(diff.changed, vec![EntryChange {
+            path: "functions/fnc_heal.sqf".to_string(),
+            old_size: Some(40),
+            new_size: Some(42),
+        }]);
+        assert!(PboDiff::from_contents(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_dirs_pair_pbos_renamed_by_case() -> Result<()> {
+        let old_root = tempfile::tempdir()?;
+        let new_root = tempfile::tempdir()?;
+        for root in [old_root.path(), new_root.path()] {
+            std::fs::create_dir_all(root.join("@ACE/addons"))?;
+        }
+        std::fs::write(old_root.path().join("@ACE/addons/ace_medical.pbo"), "same bytes")?;
+        std::fs::write(new_root.path().join("@ACE/addons/ACE_Medical.pbo"), "same bytes")?;
+        std::fs::write(old_root.path().join("@ACE/addons/ace_common.pbo"), "same bytes")?;
+        std::fs::write(new_root.path().join("@ACE/addons/ace_common.pbo"), "same bytes")?;
+        std::fs::write(old_root.path().join("@ACE/addons/ace_old.pbo"), "removed")?;
+        std::fs::write(new_root.path().join("@ACE/addons/ace_new.pbo"), "added")?;
+        // Copies made at different times are still the same PBOs
+        let copied = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        for (path, mtime) in [
+            (old_root.path().join("@ACE/addons/ace_medical.pbo"), copied),
+            (old_root.path().join("@ACE/addons/ace_common.pbo"), copied),
+            (new_root.path().join("@ACE/addons/ACE_Medical.pbo"), copied + std::time::Duration::from_secs(3600)),
+            (new_root.path().join("@ACE/addons/ace_common.pbo"), copied + std::time::Duration::from_secs(7200)),
+        ] {
+            std::fs::File::options().write(true).open(path)?.set_modified(mtime)?;
+        }
+
+        let diff = compare_dirs(old_root.path(), new_root.path(), &WalkOptions::default(), 10)?;
+        assert_eq!(diff.added, vec![PathBuf::from("@ACE/addons/ace_new.pbo")]);
+        assert_eq!(diff.removed, vec![PathBuf::from("@ACE/addons/ace_old.pbo")]);
+        assert_eq!((diff.unchanged, diff.changed.len(), diff.failed.len()), (2, 0, 0));
+        assert!(diff.to_string().starts_with("1 PBOs added, 1 removed, 0 changed, 2 unchanged"));
+        Ok(())
+    }
+}
@@ -1,273 +1,1839 @@
 #[allow(dead_code)]
-use std::path::Path;
-use anyhow::Result;
-use log::{debug, trace, warn};
-use pbo_tools::{
-    core::api::{PboApi, PboApiOps},
-    extract::{ExtractOptions, ExtractResult},
-    core::config::PboConfig,
-};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result};
+use log::{debug, info, trace, warn};
+use pbo_tools::extract::ExtractOptions;
 use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use super::archive::{verify_tar, write_tar_checked, ArchiveStats, TarTarget};
+use super::cancel::CancelToken;
+use super::derap::{derapify_tree, is_rapified, ConfigConverter, DerapifyResult};
+use super::events::{EventSink, ExtractionEvent};
+use super::failure::{failure_log_path, FailureLog, FailurePolicy, FailureTracker, StrategyFailures};
+use super::filter::FileFilter;
+use super::limit::ConcurrencyLimit;
+use super::manifest::{write_checksums, ExtractionManifest, MANIFEST_FILE_NAME};
+#[cfg(feature = "paa-convert")]
+use super::paa::convert_paa_tree;
+use super::progress::{NoopProgress, ProgressReporter};
+use super::retry::{check_readable, is_lock_error, LockRetry, RetryPolicy};
+use super::store::ObjectStore;
+use super::stream::{stream_files, FileSink};
+use super::stringtable::{convert_stringtables, STRINGTABLE_FILE_NAME};
+use super::throughput::{written_bytes, ThroughputCounters};
+use super::transform::{transform_files, FileTransformer};
+use super::versions;
+use crate::error::is_nothing_to_extract;
+use super::types::{
+    ExtractionReport,
+    ExtractionStrategy,
+    ModSummary,
+    OverwritePolicy,
+    PboFailure,
+    PboOutcome,
+    PboScanResult,
+    PboStatus,
+    ProcessOutcome,
+    ProcessingOrder,
+    PboEntry,
+    RunStats,
+    SignatureStatus,
+    StrategyAttempt,
+    SkipReason,
+    Throughput,
+    TimeoutPolicy,
+    VerificationMode,
+};
+use super::utils::{
+    estimated_size,
+    existing_entries,
+    extracted_entries,
+    stale_files,
+    tool_error,
+    tool_wait,
+    verify_output,
+    with_tool,
+};
+use crate::utils;
 
-use super::types::PboScanResult;
+/// Directory below the cache where PBOs are extracted before being moved into place
+pub const STAGING_DIR_NAME: &str = ".tmp";
+
+/// Files at the top of a base directory describing a single extraction, which
+/// `carry_over` leaves behind
+const EXTRACTION_SIDECARS: [&str; 2] = [MANIFEST_FILE_NAME, utils::CHECKSUM_FILE_NAME];
+
+static STAGING_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Pause of a worker after each PBO in background mode, leaving the machine to other programs
+pub const BACKGROUND_PAUSE: Duration = Duration::from_millis(50);
 
 pub struct PboProcessor<'a> {
     input_dir: &'a Path,
     cache_dir: &'a Path,
-    extensions: &'a str,
+    filter: &'a FileFilter,
     threads: usize,
-    timeout: u32,
+    /// Pool the PBOs are processed in, instead of one of `threads` workers per run
+    thread_pool: Option<Arc<ThreadPool>>,
+    processing_order: ProcessingOrder,
+    /// Limit on tool runs extracting at once, below `threads`
+    io_limit: Option<Arc<ConcurrencyLimit>>,
+    background: bool,
+    timeout_policy: TimeoutPolicy,
+    events: EventSink,
+    progress: Arc<dyn ProgressReporter>,
+    retry: RetryPolicy,
+    lock_retry: LockRetry,
+    fallback_chain: Vec<ExtractionStrategy>,
+    write_manifest: bool,
+    emit_checksums: bool,
+    verification: VerificationMode,
+    resume: bool,
+    overwrite: OverwritePolicy,
+    /// Earlier outputs kept when a changed PBO replaces its output
+    retain_versions: usize,
+    /// PBOs extracted in full whatever `resume` and `overwrite` say
+    reextract: HashSet<PathBuf>,
+    sync_outputs: bool,
+    sync_dry_run: bool,
+    normalize_case: bool,
+    /// Base directories replacing the default one for specific PBOs
+    output_dirs: HashMap<PathBuf, PathBuf>,
+    /// PBOs that must not be extracted because of output collisions, with the reason
+    output_conflicts: HashMap<PathBuf, String>,
+    object_store: Option<ObjectStore>,
+    max_output_bytes: Option<u64>,
+    /// Bytes reserved against `max_output_bytes` by started extractions
+    output_bytes: AtomicU64,
+    quota_reached: AtomicBool,
+    throughput: ThroughputCounters,
+    cancel: CancelToken,
+    failures: Arc<FailureTracker>,
+    write_failure_logs: bool,
+    /// Paths reported for PBOs outside the input directory
+    path_labels: HashMap<PathBuf, PathBuf>,
+    derapify: Option<Arc<dyn ConfigConverter>>,
+    transformer: Option<Arc<dyn FileTransformer>>,
+    convert_stringtables: bool,
+    tar_target: Option<TarTarget>,
+    sink: Option<Arc<dyn FileSink>>,
+    require_valid_signature: bool,
+    #[cfg(feature = "paa-convert")]
+    convert_paa: bool,
+    #[cfg(feature = "paa-convert")]
+    replace_paa_originals: bool,
 }
 
 impl<'a> PboProcessor<'a> {
     pub fn new(
         input_dir: &'a Path,
         cache_dir: &'a Path,
-        extensions: &'a str,
+        filter: &'a FileFilter,
         threads: usize,
         timeout: u32,
     ) -> Self {
         Self {
             input_dir,
             cache_dir,
-            extensions,
+            filter,
             threads,
-            timeout,
+            thread_pool: None,
+            processing_order: ProcessingOrder::default(),
+            io_limit: None,
+            background: false,
+            timeout_policy: TimeoutPolicy::fixed(timeout),
+            events: EventSink::default(),
+            progress: Arc::new(NoopProgress),
+            retry: RetryPolicy::none(),
+            lock_retry: LockRetry::none(),
+            fallback_chain: ExtractionStrategy::default_chain(),
+            write_manifest: true,
+            emit_checksums: false,
+            verification: VerificationMode::default(),
+            resume: false,
+            overwrite: OverwritePolicy::default(),
+            retain_versions: 0,
+            reextract: HashSet::new(),
+            sync_outputs: false,
+            sync_dry_run: false,
+            normalize_case: false,
+            output_dirs: HashMap::new(),
+            output_conflicts: HashMap::new(),
+            object_store: None,
+            max_output_bytes: None,
+            output_bytes: AtomicU64::new(0),
+            quota_reached: AtomicBool::new(false),
+            throughput: ThroughputCounters::default(),
+            cancel: CancelToken::default(),
+            failures: Arc::default(),
+            write_failure_logs: true,
+            path_labels: HashMap::new(),
+            derapify: None,
+            transformer: None,
+            convert_stringtables: false,
+            tar_target: None,
+            sink: None,
+            require_valid_signature: false,
+            #[cfg(feature = "paa-convert")]
+            convert_paa: false,
+            #[cfg(feature = "paa-convert")]
+            replace_paa_originals: false,
+        }
+    }
+
+    /// Report each processed PBO to `progress`
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressReporter>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Send progress events for each processed PBO
+    pub fn with_events(mut self, events: EventSink) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Scale the extraction timeout by PBO size instead of using the fixed timeout
+    pub fn with_timeout_policy(mut self, timeout_policy: TimeoutPolicy) -> Self {
+        self.timeout_policy = timeout_policy;
+        self
+    }
+
+    /// Extraction strategies tried in order until one succeeds
+    pub fn with_fallback_chain(mut self, fallback_chain: Vec<ExtractionStrategy>) -> Self {
+        self.fallback_chain = fallback_chain;
+        self
+    }
+
+    /// Write an extraction manifest into each extracted PBO's base directory
+    pub fn with_manifest(mut self, write_manifest: bool) -> Self {
+        self.write_manifest = write_manifest;
+        self
+    }
+
+    /// Write a log for PBOs failing to extract to `<cache>/failures`
+    ///
+    /// A later successful extraction of the PBO removes the log again.
+    pub fn with_failure_logs(mut self, write_failure_logs: bool) -> Self {
+        self.write_failure_logs = write_failure_logs;
+        self
+    }
+
+    /// Write `SHA256SUMS` for the extracted files of each PBO
+    pub fn with_checksums(mut self, emit_checksums: bool) -> Self {
+        self.emit_checksums = emit_checksums;
+        self
+    }
+
+    /// Choose whether missing files after extraction fail the PBO
+    pub fn with_verification(mut self, verification: VerificationMode) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Only extract expected files not already present in the output directory
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Choose how expected files already present in the output directory are treated
+    pub fn with_overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Keep up to `retain_versions` earlier outputs of changed PBOs next to the new one
+    pub fn with_retain_versions(mut self, retain_versions: usize) -> Self {
+        self.retain_versions = retain_versions;
+        self
+    }
+
+    /// Lowercase every extracted file and directory name, for scan results whose
+    /// paths were lowercased by `lowercase_scan_result`; their `renamed` names in the
+    /// PBO are what the tool is given
+    pub fn with_normalize_case(mut self, normalize_case: bool) -> Self {
+        self.normalize_case = normalize_case;
+        self
+    }
+
+    /// Extract the given PBOs in full, like those whose output was found broken, even
+    /// when resuming or keeping existing files
+    pub fn with_reextract(mut self, reextract: HashSet<PathBuf>) -> Self {
+        self.reextract = reextract;
+        self
+    }
+
+    /// Extract the given PBOs into the mapped base directories instead of the default ones
+    pub fn with_output_dirs(mut self, output_dirs: HashMap<PathBuf, PathBuf>) -> Self {
+        self.output_dirs = output_dirs;
+        self
+    }
+
+    /// Fail the given PBOs with the mapped reason instead of extracting them
+    pub fn with_output_conflicts(mut self, output_conflicts: HashMap<PathBuf, String>) -> Self {
+        self.output_conflicts = output_conflicts;
+        self
+    }
+
+    /// Deduplicate extracted files through a content-addressed store
+    pub fn with_object_store(mut self, object_store: Option<ObjectStore>) -> Self {
+        self.object_store = object_store;
+        self
+    }
+
+    /// Stop extracting once the listed sizes of extracted entries would exceed `max_output_bytes`
+    pub fn with_max_output_bytes(mut self, max_output_bytes: Option<u64>) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    /// Report the given PBOs under the mapped paths instead of relative to the input directory
+    pub fn with_path_labels(mut self, path_labels: HashMap<PathBuf, PathBuf>) -> Self {
+        self.path_labels = path_labels;
+        self
+    }
+
+    /// Write a config.cpp next to every extracted rapified `.bin` file using `converter`
+    pub fn with_derapify(mut self, converter: Option<Arc<dyn ConfigConverter>>) -> Self {
+        self.derapify = converter;
+        self
+    }
+
+    /// Run `transformer` on every extracted file before it is moved into the cache
+    pub fn with_transformer(mut self, transformer: Option<Arc<dyn FileTransformer>>) -> Self {
+        self.transformer = transformer;
+        self
+    }
+
+    /// Write a stringtable.json next to every extracted stringtable.xml
+    pub fn with_stringtable_conversion(mut self, convert_stringtables: bool) -> Self {
+        self.convert_stringtables = convert_stringtables;
+        self
+    }
+
+    /// Write each PBO's output into a tar archive next to where its output directory
+    /// would be, instead of the directory
+    pub fn with_tar_output(mut self, tar_target: Option<TarTarget>) -> Self {
+        self.tar_target = tar_target;
+        self
+    }
+
+    /// Hand every extracted file to `sink` instead of keeping it in the cache
+    ///
+    /// Only the manifest of each streamed PBO is kept, replacing any earlier output.
+    /// PBOs unchanged since they were last streamed with the same extension filter
+    /// are skipped.
+    pub fn with_sink(mut self, sink: Option<Arc<dyn FileSink>>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Process PBOs in `pool` rather than a pool of `threads` workers started by
+    /// each `process_all`
+    pub fn with_thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Order workers take up the PBOs of `process_all` in
+    pub fn with_processing_order(mut self, processing_order: ProcessingOrder) -> Self {
+        self.processing_order = processing_order;
+        self
+    }
+
+    /// Hold a permit of `io_limit` while the tool extracts a PBO
+    ///
+    /// Hashing, verification and the other steps of each PBO still run on all
+    /// `threads` workers.
+    pub fn with_io_limit(mut self, io_limit: Option<Arc<ConcurrencyLimit>>) -> Self {
+        self.io_limit = io_limit;
+        self
+    }
+
+    /// Pause each worker for `BACKGROUND_PAUSE` after every PBO
+    pub fn with_background(mut self, background: bool) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Fail PBOs whose signature wasn't verified as valid during the scan
+    pub fn with_required_signatures(mut self, require_valid_signature: bool) -> Self {
+        self.require_valid_signature = require_valid_signature;
+        self
+    }
+
+    /// Write a PNG next to every extracted PAA texture, deleting the texture with
+    /// `replace_originals`
+    #[cfg(feature = "paa-convert")]
+    pub fn with_paa_conversion(mut self, convert_paa: bool, replace_originals: bool) -> Self {
+        self.convert_paa = convert_paa;
+        self.replace_paa_originals = replace_originals;
+        self
+    }
+
+    /// Bytes reserved against the output quota so far
+    /// Stop starting PBOs once `cancel` is cancelled; remaining ones are skipped as
+    /// interrupted
+    pub fn with_cancellation(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Stop starting PBOs once failures exceed `policy`; remaining ones are skipped as
+    /// aborted
+    pub fn with_failure_policy(self, policy: FailurePolicy) -> Self {
+        self.with_failure_tracker(Arc::new(FailureTracker::new(policy)))
+    }
+
+    /// Count failures in `failures`, shared with other processors of the same run
+    pub fn with_failure_tracker(mut self, failures: Arc<FailureTracker>) -> Self {
+        self.failures = failures;
+        self
+    }
+
+    pub fn failure_tracker(&self) -> Arc<FailureTracker> {
+        self.failures.clone()
+    }
+
+    pub fn output_bytes(&self) -> u64 {
+        self.output_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Delete files left over from previous extractions that the new listing no longer
+    /// contains; with `dry_run` they are only reported
+    pub fn with_sync_outputs(mut self, sync_outputs: bool, dry_run: bool) -> Self {
+        self.sync_outputs = sync_outputs;
+        self.sync_dry_run = dry_run;
+        self
+    }
+
+    /// Retry extraction attempts that time out
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Retry PBOs held open by another process; ones still locked afterwards are
+    /// skipped as locked and extracted by a later run
+    pub fn with_lock_retry(mut self, lock_retry: LockRetry) -> Self {
+        self.lock_retry = lock_retry;
+        self
+    }
+
+    /// Path of a PBO relative to the input directory, as reported in events
+    fn relative_path(&self, pbo_path: &Path) -> PathBuf {
+        if let Some(label) = self.path_labels.get(pbo_path) {
+            return label.clone();
         }
+        pbo_path.strip_prefix(self.input_dir).unwrap_or(pbo_path).to_path_buf()
     }
 
-    pub fn process_all(&self, scan_results: &[PboScanResult]) -> Result<()> {
+    pub fn process_all(&self, scan_results: &[PboScanResult]) -> Result<ExtractionReport> {
         debug!("Processing {} PBOs for extraction", scan_results.len());
+        self.failures.add_pbos(scan_results.len());
+        let pool = match &self.thread_pool {
+            Some(pool) => pool.clone(),
+            None if self.background => Arc::new(utils::build_background_pool(self.threads)?),
+            None => Arc::new(utils::build_thread_pool(self.threads)?),
+        };
         
+        // Workers take PBOs one at a time in this order, while results keep the given one
+        let mut order: Vec<usize> = (0..scan_results.len()).collect();
+        if self.processing_order == ProcessingOrder::LargestFirst {
+            order.sort_by_key(|&index| std::cmp::Reverse(scan_results[index].pbo_size));
+        }
+
         // Process each PBO
-        let results: Vec<_> = scan_results
-            .par_iter()
-            .with_max_len(self.threads)
-            .map(|result| {
+        let started = Instant::now();
+        let mut processed: Vec<_> = pool.install(|| order
+            .into_iter()
+            .par_bridge()
+            .map(|index| {
+                let result = &scan_results[index];
+                self.progress.set_message(&self.relative_path(&result.path).to_string_lossy());
+                let started = Instant::now();
                 let process_result = self.process_pbo(result);
-                (result, process_result)
+                let duration = started.elapsed();
+                if process_result.is_err() {
+                    self.progress.inc_failed(1);
+                    if self.failures.record_failure() {
+                        warn!(
+                            "{} PBOs failed, stopping as the failure policy {:?} was exceeded by {}",
+                            self.failures.failures(),
+                            self.failures.policy(),
+                            result.path.display()
+                        );
+                    }
+                }
+                self.progress.inc(1);
+                if self.background {
+                    std::thread::sleep(BACKGROUND_PAUSE);
+                }
+                (index, (result, process_result), duration)
             })
-            .collect();
+            .collect());
+        let extraction_wall_ms = started.elapsed().as_millis() as u64;
+        processed.sort_by_key(|(index, _, _)| *index);
+        let (results, durations): (Vec<_>, Vec<_>) = processed.into_iter()
+            .map(|(_, result, duration)| (result, duration))
+            .unzip();
             
-        // Count successes and failures
-        let success_count = results.iter().filter(|(_, r)| r.is_ok()).count();
-        let failure_count = results.len() - success_count;
+        // Count successes, skips and failures
+        let success_count = results.iter()
+            .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Extracted { .. })))
+            .count();
+        let skipped_count = results.iter()
+            .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Skipped(_))))
+            .count();
+        let failure_count = results.len() - success_count - skipped_count;
+        let pruned_files = results.iter()
+            .map(|(_, r)| match r {
+                Ok(ProcessOutcome::Extracted { pruned, .. }) => *pruned,
+                _ => 0,
+            })
+            .sum();
+        let stale_files: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { stale, .. }) if !stale.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), stale.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let missing_files: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { verification, .. }) if !verification.is_complete() => {
+                    Some((self.relative_path(&scan_result.path), verification.missing.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+
+        // PBOs that only extracted after falling back past the first strategy
+        let fallback_extractions: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { strategy, .. }) if self.fallback_chain.first() != Some(strategy) => {
+                    Some((self.relative_path(&scan_result.path), *strategy))
+                },
+                _ => None,
+            })
+            .collect();
+        let converted_configs = results.iter()
+            .map(|(_, r)| match r {
+                Ok(ProcessOutcome::Extracted { converted, .. }) => *converted,
+                _ => 0,
+            })
+            .sum();
+        let unconverted_configs: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { unconverted, .. }) if !unconverted.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), unconverted.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let transform_failures: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { transform_failed, .. }) if !transform_failed.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), transform_failed.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let stringtables: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { stringtables, .. }) if !stringtables.stringtables.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), stringtables.stringtables.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let stringtable_errors: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { stringtables, .. }) if !stringtables.errors.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), stringtables.errors.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let converted_textures = results.iter()
+            .map(|(_, r)| match r {
+                Ok(ProcessOutcome::Extracted { converted_textures, .. }) => *converted_textures,
+                _ => 0,
+            })
+            .sum();
+        let unconverted_textures: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Ok(ProcessOutcome::Extracted { unconverted_textures, .. }) if !unconverted_textures.is_empty() => {
+                    Some((self.relative_path(&scan_result.path), unconverted_textures.clone()))
+                },
+                _ => None,
+            })
+            .collect();
+        let archives: Vec<ArchiveStats> = results.iter()
+            .filter_map(|(_, r)| match r {
+                Ok(ProcessOutcome::Extracted { archive, .. }) => *archive,
+                _ => None,
+            })
+            .collect();
+        let streamed_files = match self.sink {
+            Some(_) => results.iter()
+                .map(|(_, r)| match r {
+                    Ok(ProcessOutcome::Extracted { verification, .. }) => verification.found.len(),
+                    _ => 0,
+                })
+                .sum(),
+            None => 0,
+        };
+        let mut mods: BTreeMap<String, ModSummary> = BTreeMap::new();
+        for (scan_result, r) in &results {
+            let Some(mod_name) = &scan_result.mod_name else {
+                continue;
+            };
+            let summary = mods.entry(mod_name.clone()).or_default();
+            summary.pbos += 1;
+            match r {
+                Ok(ProcessOutcome::Extracted { verification, .. }) => {
+                    summary.extracted += 1;
+                    summary.extracted_files += verification.found.len();
+                },
+                Ok(ProcessOutcome::Skipped(_)) => summary.skipped += 1,
+                Err(_) => summary.failed += 1,
+            }
+        }
+        let deferred: Vec<_> = results.iter()
+            .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Skipped(SkipReason::Deferred))))
+            .map(|(scan_result, _)| self.relative_path(&scan_result.path))
+            .collect();
+        let locked: Vec<_> = results.iter()
+            .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Skipped(SkipReason::Locked))))
+            .map(|(scan_result, _)| self.relative_path(&scan_result.path))
+            .collect();
+        let skip_reason = |r: &Result<ProcessOutcome>| match r {
+            Ok(ProcessOutcome::Skipped(reason)) => Some(*reason),
+            _ => None,
+        };
+        let quota_exceeded = results.iter().any(|(_, r)| skip_reason(r) == Some(SkipReason::QuotaExceeded));
+        let interrupted = results.iter().any(|(_, r)| skip_reason(r) == Some(SkipReason::Interrupted));
+        let not_processed: Vec<_> = results.iter()
+            .filter(|(_, r)| {
+                matches!(
+                    skip_reason(r),
+                    Some(SkipReason::QuotaExceeded | SkipReason::Interrupted | SkipReason::Aborted)
+                )
+            })
+            .map(|(scan_result, _)| self.relative_path(&scan_result.path))
+            .collect();
+        let failures: Vec<_> = results.iter()
+            .filter_map(|(scan_result, r)| match r {
+                Err(e) => Some(PboFailure::new(self.relative_path(&scan_result.path), e)),
+                Ok(_) => None,
+            })
+            .collect();
+        let run_stats = RunStats {
+            unchanged: results.iter()
+                .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Skipped(SkipReason::AlreadyExtracted))))
+                .count(),
+            filter_changed: results.iter()
+                .filter(|(_, r)| matches!(r, Ok(ProcessOutcome::Extracted { filter_changed: true, .. })))
+                .count(),
+            ..Default::default()
+        };
+        let outcomes: Vec<_> = results.iter()
+            .zip(&durations)
+            .map(|((scan_result, r), duration)| PboOutcome {
+                path: self.relative_path(&scan_result.path),
+                status: match r {
+                    Ok(ProcessOutcome::Extracted { strategy, .. }) => PboStatus::Extracted { strategy: *strategy },
+                    Ok(ProcessOutcome::Skipped(reason)) => PboStatus::Skipped { reason: *reason },
+                    Err(e) => PboStatus::Failed { error: format!("{:#}", e) },
+                },
+                attempts: match r {
+                    Ok(ProcessOutcome::Extracted { attempts, .. }) => attempts.clone(),
+                    Ok(ProcessOutcome::Skipped(_)) => Vec::new(),
+                    Err(e) => e.chain()
+                        .find_map(|cause| cause.downcast_ref::<StrategyFailures>())
+                        .map(|failures| failures.attempts.iter()
+                            .map(|(strategy, error)| StrategyAttempt { strategy: *strategy, error: Some(error.clone()) })
+                            .collect())
+                        .unwrap_or_default(),
+                },
+                mod_name: scan_result.mod_name.clone(),
+                hash: utils::calculate_file_hash(&scan_result.path).ok(),
+                expected_files: match r {
+                    Ok(ProcessOutcome::Extracted { verification, .. }) if scan_result.listed_after_extraction => {
+                        verification.found.len()
+                    },
+                    _ => scan_result.expected_files.len(),
+                },
+                extracted_files: match r {
+                    Ok(ProcessOutcome::Extracted { verification, .. }) => verification.found.len(),
+                    _ => 0,
+                },
+                size: scan_result.pbo_size,
+                listing_ms: scan_result.listing_duration.as_millis() as u64,
+                duration_ms: duration.as_millis() as u64,
+            })
+            .collect();
         
         debug!("PBO processing complete:");
         debug!("  Total PBOs processed: {}", results.len());
         debug!("  Successful: {}", success_count);
+        debug!("  Skipped: {}", skipped_count);
         debug!("  Failed: {}", failure_count);
+        debug!("  Files pruned after extraction: {}", pruned_files);
+        debug!("  PBOs with missing files: {}", missing_files.len());
+        for (path, strategy) in &fallback_extractions {
+            debug!("  Extracted with {} fallback: {}", strategy, path.display());
+        }
+        if !not_processed.is_empty() {
+            debug!("  Not processed because of the output quota or an interruption: {}", not_processed.len());
+        }
+        if !locked.is_empty() {
+            debug!("  Locked by another process: {}", locked.len());
+        }
         
-        Ok(())
+        Ok(ExtractionReport {
+            extracted: success_count,
+            skipped: skipped_count,
+            failed: failure_count,
+            failures,
+            outcomes,
+            run_stats,
+            aborted: self.failures.is_aborted(),
+            background: self.background,
+            tool_priority_lowered: self.background && pool.install(utils::tool_priority_lowered),
+            fallback_extractions,
+            pruned_files,
+            missing_files,
+            stale_files,
+            converted_configs,
+            unconverted_configs,
+            transform_failures,
+            stringtables,
+            stringtable_errors,
+            converted_textures,
+            unconverted_textures,
+            archived_bytes: archives.iter().map(|archive| archive.uncompressed).sum(),
+            streamed_files,
+            mods,
+            archive_bytes: archives.iter().map(|archive| archive.compressed).sum(),
+            quota_exceeded,
+            not_processed,
+            interrupted,
+            deferred,
+            locked,
+            throughput: Throughput {
+                extraction_wall_ms,
+                ..self.throughput.snapshot()
+            },
+            ..Default::default()
+        })
     }
 
-    fn process_pbo(&self, scan_result: &PboScanResult) -> Result<()> {
+    fn process_pbo(&self, scan_result: &PboScanResult) -> Result<ProcessOutcome> {
         debug!("Processing PBO: {}", scan_result.path.display());
+
+        if self.cancel.is_cancelled() {
+            debug!("Interrupted, not starting: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::Interrupted,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::Interrupted));
+        }
+        if self.failures.is_aborted() {
+            debug!("Failure policy exceeded, not starting: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::Aborted,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::Aborted));
+        }
+
+        if let Some(reason) = scan_result.skip_reason {
+            debug!("Rejected while scanning ({:?}), skipping: {}", reason, scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason,
+            });
+            return Ok(ProcessOutcome::Skipped(reason));
+        }
         
         // If no matching files, skip processing
-        if scan_result.expected_files.is_empty() {
+        if scan_result.expected_files.is_empty() && !scan_result.listed_after_extraction {
             debug!("No matching files found in PBO, skipping: {}", scan_result.path.display());
-            return Ok(());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::NoMatchingFiles,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::NoMatchingFiles));
         }
 
-        // Prepare output directory
-        let (_, output_dir) = self.prepare_output_dirs(scan_result)?;
+        if let Some(conflict) = self.output_conflicts.get(&scan_result.path) {
+            warn!("Not extracting {}: {}", scan_result.path.display(), conflict);
+            self.events.send(ExtractionEvent::ExtractionFailed {
+                path: self.relative_path(&scan_result.path),
+                error: conflict.clone(),
+            });
+            return Err(anyhow::anyhow!("{}", conflict));
+        }
 
-        // Extract files
-        match self.extract_pbo_files(scan_result, &output_dir) {
-            Ok(_) => {
-                debug!("Successfully extracted PBO to {}", output_dir.display());
-            },
+        if self.require_valid_signature {
+            if let Some(error) = self.signature_error(scan_result) {
+                warn!("Not extracting {}: {}", scan_result.path.display(), error);
+                self.events.send(ExtractionEvent::ExtractionFailed {
+                    path: self.relative_path(&scan_result.path),
+                    error: error.clone(),
+                });
+                return Err(anyhow::anyhow!("{}", error));
+            }
+        }
+
+        if let Some(reason) = self.unsafe_reason(scan_result) {
+            warn!("Skipping {}: {}", scan_result.path.display(), reason);
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::InvalidFormat,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::InvalidFormat));
+        }
+
+        if self.sink.is_some() && self.is_streamed(scan_result) {
+            debug!("Unchanged since it was last streamed, skipping: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::AlreadyExtracted,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::AlreadyExtracted));
+        }
+
+        let pending = match self.pending_entries(scan_result) {
+            Ok(pending) => pending,
             Err(e) => {
-                warn!("Failed to extract PBO {}: {}", scan_result.path.display(), e);
+                warn!("Not extracting {}: {}", scan_result.path.display(), e);
+                self.events.send(ExtractionEvent::ExtractionFailed {
+                    path: self.relative_path(&scan_result.path),
+                    error: e.to_string(),
+                });
+                return Err(e);
             }
+        };
+        if pending.as_ref().is_some_and(|pending| pending.is_empty()) {
+            debug!("All expected files already extracted, skipping: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::AlreadyExtracted,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::AlreadyExtracted));
         }
 
-        Ok(())
-    }
+        if self.pbo_changed(scan_result) {
+            debug!("Changed size since it was listed, deferring: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::Deferred,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::Deferred));
+        }
 
-    fn prepare_output_dirs(&self, scan_result: &PboScanResult) -> Result<(std::path::PathBuf, std::path::PathBuf)> {
-        // Create output directory for this PBO
-        let rel_path = scan_result.path.strip_prefix(self.input_dir)?;
-        let base_dir = self.cache_dir.join(rel_path).with_extension("");
-        debug!("Creating base directory: {}", base_dir.display());
-        std::fs::create_dir_all(&base_dir)?;
+        // Other read errors are left for the tool to report
+        let readable = self.lock_retry.run("Opening", &scan_result.path, || check_readable(&scan_result.path));
+        if let Err(e) = readable {
+            if is_lock_error(&e) {
+                warn!("Locked by another process, leaving for a later run: {}: {}", scan_result.path.display(), e);
+                self.events.send(ExtractionEvent::PboSkipped {
+                    path: self.relative_path(&scan_result.path),
+                    reason: SkipReason::Locked,
+                });
+                return Ok(ProcessOutcome::Skipped(SkipReason::Locked));
+            }
+        }
 
-        // Get prefix from PBO
-        let api = self.create_pbo_api();
-        
-        // List contents and get prefix
-        debug!("Listing contents of PBO: {}", scan_result.path.display());
-        let list_result = match api.list_contents(&scan_result.path) {
-            Ok(result) => result,
+        let Some(reserved) = self.reserve_output_bytes(scan_result, pending.as_deref()) else {
+            debug!("Output quota reached, not extracting: {}", scan_result.path.display());
+            self.events.send(ExtractionEvent::PboSkipped {
+                path: self.relative_path(&scan_result.path),
+                reason: SkipReason::QuotaExceeded,
+            });
+            return Ok(ProcessOutcome::Skipped(SkipReason::QuotaExceeded));
+        };
+
+        let started = Instant::now();
+        self.events.send(ExtractionEvent::ExtractionStarted {
+            path: self.relative_path(&scan_result.path),
+        });
+
+        let extraction = self.lock_retry.run("Extraction", &scan_result.path, || {
+            self.extract_to_output(scan_result, pending.as_deref())
+        });
+        match extraction {
+            Ok(outcome) => {
+                match &outcome {
+                    ProcessOutcome::Extracted { strategy, pruned, verification, .. } => {
+                        self.remove_failure_log(scan_result);
+                        self.events.send(ExtractionEvent::ExtractionFinished {
+                            path: self.relative_path(&scan_result.path),
+                            files: if scan_result.listed_after_extraction {
+                                verification.found.len()
+                            } else {
+                                scan_result.expected_files.len()
+                            },
+                            duration: started.elapsed(),
+                            strategy: *strategy,
+                            pruned: *pruned,
+                        });
+                    },
+                    ProcessOutcome::Skipped(reason) => {
+                        self.output_bytes.fetch_sub(reserved, Ordering::SeqCst);
+                        self.events.send(ExtractionEvent::PboSkipped {
+                            path: self.relative_path(&scan_result.path),
+                            reason: *reason,
+                        });
+                    },
+                }
+                Ok(outcome)
+            },
+            // Without a manifest the next run extracts it again, even when unchanged
+            Err(e) if is_lock_error(&e) => {
+                warn!("Locked by another process, leaving for a later run: {}: {}", scan_result.path.display(), e);
+                self.output_bytes.fetch_sub(reserved, Ordering::SeqCst);
+                self.events.send(ExtractionEvent::PboSkipped {
+                    path: self.relative_path(&scan_result.path),
+                    reason: SkipReason::Locked,
+                });
+                Ok(ProcessOutcome::Skipped(SkipReason::Locked))
+            },
+            Err(e) => {
+                // Failed extractions leave the previous output in place
+                self.output_bytes.fetch_sub(reserved, Ordering::SeqCst);
+                self.write_failure_log(scan_result, pending.as_deref(), &e);
+                self.events.send(ExtractionEvent::ExtractionFailed {
+                    path: self.relative_path(&scan_result.path),
+                    error: e.to_string(),
+                });
+                Err(e)
+            }
+        }
+    }
+
+    /// Record a failed extraction in the PBO's failure log, if enabled
+    fn write_failure_log(&self, scan_result: &PboScanResult, pending: Option<&[PboEntry]>, error: &anyhow::Error) {
+        if !self.write_failure_logs {
+            return;
+        }
+        let path = match self.failure_log_path(&scan_result.path) {
+            Ok(path) => path,
             Err(e) => {
-                warn!("Failed to list PBO contents {}: {}", scan_result.path.display(), e);
-                return Err(anyhow::anyhow!("Failed to list PBO contents: {}", e));
+                warn!("Not writing a failure log for {}: {}", scan_result.path.display(), e);
+                return;
             }
         };
-        
-        let prefix = list_result.get_prefix().unwrap_or_default();
-        debug!("PBO prefix: {}", prefix);
 
-        // Create output directory with prefix path
-        let output_dir = base_dir.join(prefix);
-        trace!("Creating output directory: {}", output_dir.display());
-        std::fs::create_dir_all(&output_dir)?;
+        let hash = utils::calculate_file_hash(&scan_result.path).unwrap_or_else(|e| format!("unavailable ({})", e));
+        let failed_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        let strategies = self.fallback_chain.iter().map(ToString::to_string).collect::<Vec<_>>();
+        let log = FailureLog::new(&scan_result.path, hash, failed_at, error)
+            .with_option("Extensions", &self.filter.extensions)
+            .with_option("Tool filter", self.create_extract_options(scan_result, pending).file_filter.unwrap_or_default())
+            .with_option("Timeout", format!("{} seconds", self.timeout_policy.for_size(scan_result.pbo_size)))
+            .with_option("Fallback chain", strategies.join(", "))
+            .with_option("Retries", self.retry.max_retries)
+            .with_option("Verification", format!("{:?}", self.verification));
+        match log.write(&path) {
+            Ok(()) => debug!("Wrote failure log to {}", path.display()),
+            Err(e) => warn!("Failed to write failure log for {}: {}", scan_result.path.display(), e),
+        }
+    }
 
-        Ok((base_dir, output_dir))
+    /// Remove the log of an earlier failed extraction of the PBO
+    fn remove_failure_log(&self, scan_result: &PboScanResult) {
+        let Ok(path) = self.failure_log_path(&scan_result.path) else {
+            return;
+        };
+        match FailureLog::remove(&path) {
+            Ok(true) => debug!("Removed failure log of an earlier run: {}", path.display()),
+            Ok(false) => {},
+            Err(e) => warn!("Failed to remove failure log {}: {}", path.display(), e),
+        }
     }
 
-    fn create_pbo_api(&self) -> PboApi {
-        let config = PboConfig::default();
-        PboApi::builder()
-            .with_config(config)
-            .with_timeout(self.timeout)
-            .build()
+    /// Reserve the estimated bytes of a PBO's extraction against the output quota
+    ///
+    /// Returns the reserved bytes, or `None` when they don't fit. Once that happens
+    /// no further PBOs are started, even ones that would still fit, so a later run
+    /// with a raised quota picks up every remaining PBO.
+    fn reserve_output_bytes(&self, scan_result: &PboScanResult, pending: Option<&[PboEntry]>) -> Option<u64> {
+        let max = match self.max_output_bytes {
+            Some(max) => max,
+            None => return Some(0),
+        };
+        if self.quota_reached.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let size = if scan_result.listed_after_extraction {
+            scan_result.pbo_size
+        } else {
+            estimated_size(pending.unwrap_or(&scan_result.expected_files), scan_result.pbo_size)
+        };
+        let reserved = self.output_bytes.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(size).filter(|total| *total <= max)
+        });
+        match reserved {
+            Ok(_) => Some(size),
+            Err(used) => {
+                if !self.quota_reached.swap(true, Ordering::SeqCst) {
+                    warn!(
+                        "Output quota of {} bytes reached ({} used, {} needed for {}); not starting further PBOs",
+                        max,
+                        used,
+                        size,
+                        scan_result.path.display()
+                    );
+                }
+                None
+            },
+        }
     }
 
-    fn create_extract_options(&self) -> ExtractOptions {
-        let mut options = ExtractOptions::default();
-        options.file_filter = Some(self.extensions.split(',').map(str::to_string).collect());
-        options.no_pause = true;
-        options.warnings_as_errors = false;
-        options.verbose = true;
-        options
+    /// Whether a PBO's size on disk differs from the size recorded when it was listed
+    fn pbo_changed(&self, scan_result: &PboScanResult) -> bool {
+        std::fs::metadata(&scan_result.path).is_ok_and(|metadata| metadata.len() != scan_result.pbo_size)
     }
 
-    fn extract_pbo_files(
-        &self, 
-        scan_result: &PboScanResult, 
-        output_dir: &std::path::Path
-    ) -> Result<ExtractResult> {
-        let api = self.create_pbo_api();
-        let options = self.create_extract_options();
-        
-        // First, check if there are any files to extract by listing contents
-        debug!("Checking PBO contents before extraction: {}", scan_result.path.display());
-        let list_result = match api.list_contents(&scan_result.path) {
-            Ok(result) => result,
-            Err(e) => {
-                warn!("Failed to list PBO contents {}: {}", scan_result.path.display(), e);
-                return Err(anyhow::anyhow!("Failed to list PBO contents: {}", e));
+    /// Why a PBO's signature isn't accepted, if it isn't
+    fn signature_error(&self, scan_result: &PboScanResult) -> Option<String> {
+        match &scan_result.signature {
+            Some(SignatureStatus::Valid(_)) => None,
+            Some(SignatureStatus::Missing) => Some("PBO is not signed".to_string()),
+            Some(SignatureStatus::Invalid(reason)) => Some(format!("Invalid signature: {}", reason)),
+            None => Some("Signature was not verified".to_string()),
+        }
+    }
+
+    /// Why a PBO's paths can't be extracted below its output directory, if they can't
+    fn unsafe_reason(&self, scan_result: &PboScanResult) -> Option<String> {
+        if !scan_result.unsafe_paths.is_empty() {
+            return Some(format!("unsafe paths in listing: {}", scan_result.unsafe_paths.join(", ")));
+        }
+        scan_result.prefix.iter()
+            .chain(scan_result.expected_files.iter().map(|entry| &entry.path))
+            .find_map(|path| utils::native_entry_path(path).err())
+            .map(|e| e.to_string())
+    }
+
+    /// Estimated bytes an extraction of the PBO writes, and the part of them that
+    /// doesn't replace files already in the output directory
+    ///
+    /// PBOs that won't be extracted, because they're skipped or fail before reaching
+    /// the tool, are estimated at zero. PBOs listed after extraction are estimated
+    /// at their own size, none of it replacing existing files.
+    pub fn estimate_size(&self, scan_result: &PboScanResult) -> (u64, u64) {
+        if (scan_result.expected_files.is_empty() && !scan_result.listed_after_extraction)
+            || self.output_conflicts.contains_key(&scan_result.path)
+            || self.unsafe_reason(scan_result).is_some()
+        {
+            return (0, 0);
+        }
+        if scan_result.listed_after_extraction {
+            return (scan_result.pbo_size, scan_result.pbo_size);
+        }
+
+        let pending = match self.pending_entries(scan_result) {
+            Ok(Some(pending)) => pending,
+            Ok(None) => scan_result.expected_files.clone(),
+            Err(_) => return (0, 0),
+        };
+        let Ok((_, output_dir)) = self.output_dirs(scan_result) else {
+            return (0, 0);
+        };
+
+        let missing = existing_entries(&output_dir, &pending).missing;
+        (
+            estimated_size(&pending, scan_result.pbo_size),
+            estimated_size(&missing, scan_result.pbo_size),
+        )
+    }
+
+    /// Entries to extract based on what's already in the output directory
+    ///
+    /// `None` extracts every expected file. When resuming, files already present with
+    /// the listed size are left out; with `SkipExisting`, every present file is. The
+    /// `Error` policy fails for files that would be replaced. PBOs to re-extract get
+    /// every expected file.
+    fn pending_entries(&self, scan_result: &PboScanResult) -> Result<Option<Vec<PboEntry>>> {
+        if (!self.resume && self.overwrite == OverwritePolicy::Overwrite) || self.reextract.contains(&scan_result.path) {
+            return Ok(None);
+        }
+
+        let (_, output_dir) = self.output_dirs(scan_result)?;
+        let existing = existing_entries(&output_dir, &scan_result.expected_files);
+
+        if self.overwrite == OverwritePolicy::Error {
+            // Resuming leaves complete files alone, so only changed ones would be replaced
+            let conflicts: Vec<&str> = if self.resume {
+                existing.changed.iter().map(|entry| entry.path.as_str()).collect()
+            } else {
+                existing.present().map(|entry| entry.path.as_str()).collect()
+            };
+            if !conflicts.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} files already exist in {}: {}",
+                    conflicts.len(),
+                    output_dir.display(),
+                    conflicts.join(", ")
+                ));
             }
+        }
+
+        let pending = match (self.overwrite, self.resume) {
+            (OverwritePolicy::SkipExisting, _) => existing.missing,
+            (_, true) => {
+                let mut pending = existing.changed;
+                pending.extend(existing.missing);
+                pending
+            },
+            (_, false) => return Ok(None),
         };
-        
-        // Get the list of files in the PBO
-        let file_list = list_result.get_file_list();
-        
-        // Check if there are any files matching our extension filter
-        let has_matching_files = file_list.iter().any(|file| {
-            let path = Path::new(file);
-            path.extension()
-                .map(|ext| self.extensions.contains(&ext.to_string_lossy().to_string()))
-                .unwrap_or(false)
-        });
-        
-        if !has_matching_files {
-            debug!("No files matching extension filter '{}' found in PBO, skipping extraction: {}", 
-                   self.extensions, scan_result.path.display());
-            // Return a successful empty result
-            return Ok(ExtractResult {
-                return_code: 0,
-                stdout: String::new(),
-                stderr: String::new(),
-            });
+        if !pending.is_empty() {
+            debug!(
+                "{} of {} files pending for {}",
+                pending.len(),
+                scan_result.expected_files.len(),
+                scan_result.path.display()
+            );
         }
-        
-        // Attempt 1: Standard extraction
-        debug!("Trying standard extraction for PBO: {}", scan_result.path.display());
-        match api.extract_with_options(&scan_result.path, output_dir, options.clone()) {
-            Ok(result) => {
-                debug!("Extraction successful with standard extraction");
-                Ok(result)
+        Ok(Some(pending))
+    }
+
+    /// Extract a PBO's matching files into its output directory
+    ///
+    /// Extraction happens in a staging directory that replaces the PBO's base directory
+    /// only once everything succeeded; on failure the previous output is left untouched.
+    /// `pending` restricts the tool to the given entries when resuming or skipping
+    /// existing files.
+    fn extract_to_output(&self, scan_result: &PboScanResult, pending: Option<&[PboEntry]>) -> Result<ProcessOutcome> {
+        let (staging_base, staging_output) = self.prepare_output_dirs(scan_result)?;
+
+        let result = self.extract_staged(scan_result, pending, &staging_base, &staging_output);
+        let extracted = matches!(result, Ok(ProcessOutcome::Extracted { .. }));
+        if !extracted && staging_base.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&staging_base) {
+                warn!("Failed to remove staging directory {}: {}", staging_base.display(), e);
+            }
+        }
+        result
+    }
+
+    fn extract_staged(
+        &self,
+        scan_result: &PboScanResult,
+        pending: Option<&[PboEntry]>,
+        staging_base: &Path,
+        staging_output: &Path,
+    ) -> Result<ProcessOutcome> {
+        let (base_dir, output_dir) = self.output_dirs(scan_result)?;
+        let previous_manifest = ExtractionManifest::read(&base_dir).ok();
+        let filter_changed = previous_manifest.as_ref()
+            .is_some_and(|manifest| manifest.extensions != self.filter.extensions);
+
+        // Extract files
+        let started = Instant::now();
+        let extraction = self.extract_pbo_files(scan_result, staging_output, pending);
+        self.throughput.add_extraction(started.elapsed());
+        // A PBO written to while extracting yields a partial or failed extraction
+        if self.pbo_changed(scan_result) {
+            debug!("Changed size while extracting, deferring: {}", scan_result.path.display());
+            return Ok(ProcessOutcome::Skipped(SkipReason::Deferred));
+        }
+        let (strategy, attempts) = match extraction {
+            Ok(extracted) => {
+                debug!("Successfully extracted PBO to {}", staging_output.display());
+                extracted
             },
             Err(e) => {
-                // Check if this is error code 11 (no files to extract)
-                if e.to_string().contains("return code 11") || 
-                   e.to_string().contains("no file(s) to extract") {
-                    debug!("No files to extract (error code 11), treating as success: {}", scan_result.path.display());
-                    return Ok(ExtractResult {
-                        return_code: 0,
-                        stdout: String::new(),
-                        stderr: String::new(),
-                    });
-                }
-                
-                warn!("Standard extraction failed: {}", e);
-                
-                // Attempt 2: Permissive extraction
-                debug!("Trying permissive extraction for PBO: {}", scan_result.path.display());
-                let mut permissive_options = options.clone();
-                permissive_options.file_filter = None; // Extract all files
-                match api.extract_with_options(&scan_result.path, output_dir, permissive_options) {
-                    Ok(result) => {
-                        debug!("Extraction successful with permissive extraction");
-                        Ok(result)
-                    },
-                    Err(e) => {
-                        // Check again for error code 11
-                        if e.to_string().contains("return code 11") || 
-                           e.to_string().contains("no file(s) to extract") {
-                            debug!("No files to extract (error code 11), treating as success: {}", scan_result.path.display());
-                            return Ok(ExtractResult {
-                                return_code: 0,
-                                stdout: String::new(),
-                                stderr: String::new(),
-                            });
-                        }
-                        
-                        warn!("Permissive extraction failed: {}", e);
-                        
-                        // Attempt 3: Direct extraction
-                        debug!("Trying direct extraction for PBO: {}", scan_result.path.display());
-                        match api.extract_files(&scan_result.path, output_dir, None) {
-                            Ok(result) => {
-                                debug!("Extraction successful with direct extraction");
-                                Ok(result)
-                            },
-                            Err(e) => {
-                                // Check one more time for error code 11
-                                if e.to_string().contains("return code 11") || 
-                                   e.to_string().contains("no file(s) to extract") {
-                                    debug!("No files to extract (error code 11), treating as success: {}", scan_result.path.display());
-                                    return Ok(ExtractResult {
-                                        return_code: 0,
-                                        stdout: String::new(),
-                                        stderr: String::new(),
-                                    });
-                                }
-                                
-                                warn!("Direct extraction failed: {}", e);
-                                Err(anyhow::anyhow!("All extraction attempts failed: {}", e))
-                            }
-                        }
-                    }
-                }
+                warn!("Failed to extract PBO {}: {}", scan_result.path.display(), e);
+                return Err(e);
+            }
+        };
+
+        // Expected paths were lowercased during the scan, so the files have to follow
+        if self.normalize_case {
+            let collisions = utils::lowercase_tree(staging_base)?;
+            if !collisions.is_empty() {
+                warn!(
+                    "{} files in {} differ from others only by case and keep their names: {:?}",
+                    collisions.len(),
+                    scan_result.path.display(),
+                    collisions,
+                );
             }
         }
-    }
-}
 
-#[cfg(test)]
+        // Sanitized names were decided during the scan; the tool wrote the originals
+        // wherever the file system accepted them, lowercased since when normalizing
+        let on_disk = |original: &str| match self.normalize_case {
+            true => original.to_lowercase(),
+            false => original.to_string(),
+        };
+        for (original, sanitized) in &scan_result.renamed {
+            let to = staging_output.join(utils::native_entry_path(sanitized)?);
+            // Names that aren't paths here, like with a drive separator, weren't written
+            let Ok(from) = utils::native_entry_path(&on_disk(original)).map(|original| staging_output.join(original)) else {
+                continue;
+            };
+            // Only lowercased, which is done already
+            if from == to || !from.is_file() {
+                continue;
+            }
+            if to.exists() {
+                warn!("Not renaming {} to {}: target already exists", original, sanitized);
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(&from, &to)?;
+        }
+        // Skipped device names the tool still managed to write, like on Linux
+        for original in &scan_result.reserved_names {
+            let Ok(path) = utils::native_entry_path(&on_disk(original)).map(|original| staging_output.join(original)) else {
+                continue;
+            };
+            if !scan_result.renamed.contains_key(original) && path.is_file() {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        if !scan_result.renamed.is_empty() || !scan_result.reserved_names.is_empty() {
+            utils::remove_empty_dirs(staging_output)?;
+        }
+
+        // Without a listing, whatever the tool wrote is what was expected
+        let listed_after;
+        let expected_files = if scan_result.listed_after_extraction {
+            listed_after = extracted_entries(staging_output)?;
+            trace!("Listed {} files after extracting {}", listed_after.len(), scan_result.path.display());
+            &listed_after
+        } else {
+            &scan_result.expected_files
+        };
+
+        // Files kept from the previous output, minus stale ones when syncing
+        let (stale, mut carried) = self.carry_over(expected_files, &base_dir, &output_dir, staging_base)?;
+        self.drop_conversions(expected_files, (staging_base, staging_output), &mut carried)?;
+
+        // Conversion failures are recorded, not fatal
+        let derapified = match &self.derapify {
+            Some(converter) => derapify_tree(staging_output, converter.as_ref())?,
+            None => DerapifyResult::default(),
+        };
+
+        let pruned = self.prune_output(staging_output, strategy)?;
+
+        // The tool reporting success doesn't guarantee every listed file was written
+        let started = Instant::now();
+        let verification = verify_output(staging_output, expected_files)?;
+        self.throughput.add_verification(started.elapsed());
+        // Counted once the PBO made it into the output, but sized while still staged
+        let bytes_written = written_bytes(staging_output, expected_files, &verification.found);
+        if !verification.is_complete() {
+            warn!(
+                "{} of {} expected files missing after extracting {}: {:?}",
+                verification.missing.len(),
+                expected_files.len(),
+                scan_result.path.display(),
+                verification.missing,
+            );
+            if self.verification == VerificationMode::Strict {
+                return Err(anyhow::anyhow!(
+                    "{} expected files missing after extraction: {}",
+                    verification.missing.len(),
+                    verification.missing.join(", ")
+                ));
+            }
+        }
+        if !verification.unexpected.is_empty() {
+            debug!("Unexpected files after extracting {}: {:?}", scan_result.path.display(), verification.unexpected);
+        }
+
+        // After pruning, which would remove the JSON files unless the filter asks for them
+        let stringtables = if self.convert_stringtables {
+            Box::new(convert_stringtables(staging_output)?)
+        } else {
+            Box::default()
+        };
+
+        // Before checksums and the manifest, so they describe the transformed files
+        let (transform_failed, transformed) = match &self.transformer {
+            Some(transformer) => self.transform_staged(
+                transformer.as_ref(),
+                scan_result,
+                (staging_base, staging_output),
+                &verification.found,
+                &carried,
+                previous_manifest.as_ref(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        // After the transformer, so it sees the textures as extracted
+        let (converted_textures, unconverted_textures) = self.convert_textures(staging_output)?;
+
+        // A failing sink fails the PBO before anything reaches the cache
+        if let Some(sink) = &self.sink {
+            stream_files(sink.as_ref(), &self.relative_path(&scan_result.path), staging_output, &verification.found)?;
+        }
+
+        // Hashing runs after the tool has exited, outside its timeout
+        if self.emit_checksums && self.sink.is_none() {
+            let hashed = write_checksums(staging_base)?;
+            trace!("Wrote checksums for {} files to {}", hashed, staging_base.display());
+        }
+
+        // Only successful extractions get a manifest
+        if self.write_manifest {
+            let mut manifest = ExtractionManifest::collect(
+                staging_base,
+                &scan_result.path,
+                utils::calculate_file_hash(&scan_result.path)?,
+                scan_result.prefix.clone(),
+                &self.filter.extensions,
+            )?;
+            manifest.renamed = scan_result.renamed.clone();
+            manifest.streamed = self.sink.is_some();
+            manifest.listed_after_extraction = scan_result.listed_after_extraction;
+            manifest.transformed = transformed;
+            manifest.write(staging_base)?;
+            trace!("Wrote manifest with {} files to {}", manifest.files.len(), staging_base.display());
+        }
+
+        let archive = if self.sink.is_some() {
+            // The manifest alone lets later runs skip the PBO while it's unchanged
+            utils::remove_unmatched_files(staging_base, |rel_path, _| rel_path == MANIFEST_FILE_NAME)?;
+            utils::replace_dir(staging_base, &base_dir)?;
+            None
+        } else if let Some(target) = &self.tar_target {
+            Some(self.archive_staged(scan_result, target, staging_base, &base_dir)?)
+        } else {
+            // Unchanged files become links to content stored by earlier extractions
+            if let Some(store) = &self.object_store {
+                store.store_tree(staging_base)?;
+            }
+
+            let keep = self.versions_to_keep(scan_result, &base_dir);
+            versions::replace_retaining(staging_base, &base_dir, keep)?;
+            debug!("Moved extracted files into {}", base_dir.display());
+            None
+        };
+
+        self.throughput.add_written(verification.found.len(), bytes_written);
+        Ok(ProcessOutcome::Extracted {
+            strategy,
+            attempts,
+            pruned,
+            verification: Box::new(verification),
+            stale,
+            converted: derapified.converted.len(),
+            unconverted: derapified.unconverted,
+            transform_failed,
+            stringtables,
+            converted_textures,
+            unconverted_textures,
+            archive,
+            filter_changed,
+        })
+    }
+
+    /// Earlier outputs to keep when replacing the output in `base_dir`
+    ///
+    /// Only the output of a changed PBO is kept, so extracting it again with another
+    /// filter or after a broken extraction doesn't rotate out older versions.
+    fn versions_to_keep(&self, scan_result: &PboScanResult, base_dir: &Path) -> usize {
+        if self.retain_versions == 0 {
+            return 0;
+        }
+        match ExtractionManifest::read(base_dir) {
+            Ok(previous) if !previous.streamed
+                && !utils::calculate_file_hash(&scan_result.path).is_ok_and(|hash| hash == previous.hash) => self.retain_versions,
+            _ => 0,
+        }
+    }
+
+    /// Whether the manifest in the PBO's base directory records streaming the PBO in
+    /// its current state with the current extension filter
+    fn is_streamed(&self, scan_result: &PboScanResult) -> bool {
+        let Ok(base_dir) = self.base_output_dir(&scan_result.path) else {
+            return false;
+        };
+        match ExtractionManifest::read(&base_dir) {
+            Ok(manifest) => {
+                manifest.streamed
+                    && manifest.extensions == self.filter.extensions
+                    && utils::calculate_file_hash(&scan_result.path).is_ok_and(|hash| hash == manifest.hash)
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// Write the staging directory into the PBO's tar archive and remove it
+    ///
+    /// In strict verification mode the archive members are checked against the
+    /// listing, which doesn't need unpacking.
+    fn archive_staged(
+        &self,
+        scan_result: &PboScanResult,
+        target: &TarTarget,
+        staging_base: &Path,
+        base_dir: &Path,
+    ) -> Result<ArchiveStats> {
+        let archive_path = target.archive_path(base_dir);
+        // Checked before it replaces the previous archive, which a broken one mustn't
+        let stats = write_tar_checked(staging_base, &archive_path, target, |partial| {
+            if self.verification != VerificationMode::Strict {
+                return Ok(());
+            }
+            let prefix = scan_result.prefix.as_deref().unwrap_or_default();
+            let verification = verify_tar(partial, prefix, &scan_result.expected_files)?;
+            if !verification.is_complete() {
+                return Err(anyhow::anyhow!(
+                    "{} expected files missing from {}: {}",
+                    verification.missing.len(),
+                    archive_path.display(),
+                    verification.missing.join(", ")
+                ));
+            }
+            Ok(())
+        })?;
+        std::fs::remove_dir_all(staging_base)?;
+        debug!(
+            "Archived {} bytes of extracted files into {} ({} bytes)",
+            stats.uncompressed,
+            archive_path.display(),
+            stats.compressed
+        );
+        Ok(stats)
+    }
+
+    /// Convert PAA textures below `dir` to PNG, if enabled
+    ///
+    /// Returns the number of converted textures and the skipped ones as `path: reason`.
+    #[cfg(feature = "paa-convert")]
+    fn convert_textures(&self, dir: &Path) -> Result<(usize, Vec<String>)> {
+        if !self.convert_paa {
+            return Ok((0, Vec::new()));
+        }
+        let conversion = convert_paa_tree(dir, self.replace_paa_originals)?;
+        Ok((conversion.converted.len(), conversion.skipped))
+    }
+
+    #[cfg(not(feature = "paa-convert"))]
+    fn convert_textures(&self, _dir: &Path) -> Result<(usize, Vec<String>)> {
+        Ok((0, Vec::new()))
+    }
+
+    /// Run the transformer on the staged files `found` below `staging_output`,
+    /// except files carried over that it already handled
+    ///
+    /// A carried-over file was handled when the previous manifest lists it as
+    /// transformed, or when there's no manifest to tell: its output is kept as it
+    /// was. Returns the entries that failed, and the files of the output handled by
+    /// the transformer relative to the base directory.
+    fn transform_staged(
+        &self,
+        transformer: &dyn FileTransformer,
+        scan_result: &PboScanResult,
+        (staging_base, staging_output): (&Path, &Path),
+        found: &[String],
+        carried: &HashSet<String>,
+        previous_manifest: Option<&ExtractionManifest>,
+    ) -> (Vec<String>, Vec<String>) {
+        let prefix = staged_prefix(staging_base, staging_output);
+        let base_path = |entry: &str| base_relative(&prefix, entry);
+        let already: HashSet<&str> = previous_manifest
+            .map(|manifest| manifest.transformed.iter().map(String::as_str).collect())
+            .unwrap_or_default();
+        let (pending, done): (Vec<String>, Vec<String>) = found.iter().cloned().partition(|entry| {
+            let path = base_path(entry);
+            !carried.contains(&path) || (previous_manifest.is_some() && !already.contains(path.as_str()))
+        });
+        if !done.is_empty() {
+            trace!("Not transforming {} files carried over into {}", done.len(), staging_output.display());
+        }
+
+        let failed = transform_files(transformer, &self.relative_path(&scan_result.path), staging_output, &pending);
+        let mut transformed: Vec<String> = pending.iter()
+            .chain(&done)
+            .filter(|entry| failed.binary_search(entry).is_err())
+            .map(|entry| base_path(entry))
+            .collect();
+        transformed.sort();
+        (failed, transformed)
+    }
+
+    /// Bring files of the previous output into the staging directory
+    ///
+    /// Freshly extracted files take precedence. Files no longer among `expected_files`
+    /// are left behind when syncing outputs, unless it's a dry run. The manifest
+    /// and checksums aren't carried over. Files are hard-linked where
+    /// possible, so nothing may write into them in place before the swap.
+    /// Returns the stale paths and the files carried over, relative to `base_dir`.
+    fn carry_over(
+        &self,
+        expected_files: &[PboEntry],
+        base_dir: &Path,
+        output_dir: &Path,
+        staging_base: &Path,
+    ) -> Result<(Vec<String>, HashSet<String>)> {
+        if !base_dir.is_dir() {
+            return Ok((Vec::new(), HashSet::new()));
+        }
+
+        let stale = if self.sync_outputs {
+            stale_files(base_dir, output_dir, expected_files)?
+        } else {
+            Vec::new()
+        };
+        let dropped: HashSet<&str> = if self.sync_dry_run {
+            HashSet::new()
+        } else {
+            stale.iter().map(String::as_str).collect()
+        };
+
+        let mut carried = HashSet::new();
+        for entry in walkdir::WalkDir::new(base_dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let rel_path = entry.path().strip_prefix(base_dir)?;
+            // Written anew for the new output, or only left by a failed extraction
+            if entry.depth() == 1 && EXTRACTION_SIDECARS.iter().any(|name| entry.file_name() == *name) {
+                continue;
+            }
+            let target = staging_base.join(rel_path);
+            let key = utils::normalize_entry_path(&rel_path.to_string_lossy());
+            if target.exists() || dropped.contains(key.as_str()) {
+                continue;
+            }
+            utils::link_or_copy(entry.path(), &target)?;
+            carried.insert(key);
+        }
+
+        if !stale.is_empty() {
+            if self.sync_dry_run {
+                info!("Would remove {} stale files from {}: {:?}", stale.len(), base_dir.display(), stale);
+            } else {
+                info!("Removing {} stale files from {}: {:?}", stale.len(), base_dir.display(), stale);
+            }
+        }
+
+        Ok((stale, carried))
+    }
+
+    /// Remove files carried over from the previous output that a conversion wrote,
+    /// so they're converted anew rather than kept stale when their source changed
+    ///
+    /// Conversions leave existing files alone, taking them for files of the PBO;
+    /// carried files among `expected_files` are, and stay. The removed files are
+    /// taken out of `carried`.
+    fn drop_conversions(
+        &self,
+        expected_files: &[PboEntry],
+        (staging_base, staging_output): (&Path, &Path),
+        carried: &mut HashSet<String>,
+    ) -> Result<()> {
+        let prefix = staged_prefix(staging_base, staging_output);
+        let shipped: HashSet<String> = expected_files.iter().map(|entry| base_relative(&prefix, &entry.path)).collect();
+        let mut converted = Vec::new();
+        for path in carried.iter().filter(|path| !shipped.contains(*path)) {
+            let staged = staging_base.join(utils::native_entry_path(path)?);
+            if self.is_conversion(&staged) {
+                std::fs::remove_file(&staged)?;
+                converted.push(path.clone());
+            }
+        }
+        if !converted.is_empty() {
+            trace!("Converting {} files carried over into {} anew", converted.len(), staging_base.display());
+        }
+        for path in &converted {
+            carried.remove(path);
+        }
+        Ok(())
+    }
+
+    /// Whether a conversion enabled for the run writes `path` from a staged file
+    /// next to it
+    fn is_conversion(&self, path: &Path) -> bool {
+        let has_extension = |extension: &str| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(extension));
+        let stringtable = path.with_extension("xml");
+        (self.derapify.is_some()
+            && has_extension("cpp")
+            && std::fs::read(path.with_extension("bin")).is_ok_and(|data| is_rapified(&data)))
+        || (self.convert_stringtables
+            && has_extension("json")
+            && stringtable.file_name().is_some_and(|name| name.eq_ignore_ascii_case(STRINGTABLE_FILE_NAME))
+            && stringtable.is_file())
+        || self.is_texture_conversion(path)
+    }
+
+    /// Whether `path` is a PNG converted from a texture next to it, if enabled
+    #[cfg(feature = "paa-convert")]
+    fn is_texture_conversion(&self, path: &Path) -> bool {
+        self.convert_paa
+            && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+            && ["paa", "pac"].iter().any(|texture| path.with_extension(texture).is_file())
+    }
+
+    #[cfg(not(feature = "paa-convert"))]
+    fn is_texture_conversion(&self, _path: &Path) -> bool {
+        false
+    }
+
+    /// Delete extracted files that don't pass the active filter
+    ///
+    /// The tool only filters by extension, and fallback strategies don't filter at all,
+    /// so the output is pruned to make it independent of the strategy that succeeded.
+    /// Returns the number of files deleted.
+    fn prune_output(&self, output_dir: &Path, strategy: ExtractionStrategy) -> Result<usize> {
+        let unfiltered = strategy != ExtractionStrategy::Filtered;
+        if !unfiltered && !self.filter.has_path_filters() && !self.filter.has_size_limits() && !self.filter.prefer_source {
+            return Ok(0);
+        }
+
+        let removed = utils::remove_unmatched_files(output_dir, |path, size| {
+            self.filter.matches(path)
+                && self.filter.matches_size(Some(size))
+                && !self.filter.is_superseded(path, |source| output_dir.join(source).is_file())
+        })?;
+        if removed > 0 {
+            debug!("Removed {} files outside the filter from {}", removed, output_dir.display());
+        }
+        Ok(removed)
+    }
+
+    /// Output directory for a PBO, mirroring its path relative to the input directory
+    /// without the archive extension
+    pub fn base_output_dir(&self, pbo_path: &Path) -> Result<std::path::PathBuf> {
+        if let Some(output_dir) = self.output_dirs.get(pbo_path) {
+            return Ok(utils::long_path(output_dir));
+        }
+        let rel_path = pbo_path.strip_prefix(self.input_dir)?;
+        Ok(utils::long_path(&self.cache_dir.join(rel_path).with_extension("")))
+    }
+
+    /// Failure log of a PBO, mirroring its base directory below `<cache>/failures`
+    pub fn failure_log_path(&self, pbo_path: &Path) -> Result<PathBuf> {
+        let cache_dir = utils::long_path(self.cache_dir);
+        let base_dir = self.base_output_dir(pbo_path)?;
+        let rel_base_dir = base_dir.strip_prefix(&cache_dir)
+            .with_context(|| format!("Output of {} is outside the cache directory", pbo_path.display()))?;
+        Ok(failure_log_path(&cache_dir, rel_base_dir))
+    }
+
+    /// Whether the PBO is failed instead of extracted because its output directory
+    /// collides with another PBO's
+    pub fn has_output_conflict(&self, pbo_path: &Path) -> bool {
+        self.output_conflicts.contains_key(pbo_path)
+    }
+
+    /// Base directory of a PBO and the directory its entries are extracted to
+    pub fn output_dirs(&self, scan_result: &PboScanResult) -> Result<(PathBuf, PathBuf)> {
+        let base_dir = self.base_output_dir(&scan_result.path)?;
+
+        // Prefix was already read from the listing during the scan
+        let prefix = scan_result.prefix.clone().unwrap_or_default();
+        let output_dir = base_dir.join(utils::native_entry_path(&prefix)?);
+
+        Ok((base_dir, output_dir))
+    }
+
+    /// Unique staging directory for a PBO below the cache's staging root
+    fn staging_dir(&self, pbo_path: &Path) -> PathBuf {
+        let stem = pbo_path.file_stem().unwrap_or_default().to_string_lossy();
+        let nonce = STAGING_COUNTER.fetch_add(1, Ordering::Relaxed);
+        utils::long_path(&self.cache_dir
+            .join(STAGING_DIR_NAME)
+            .join(format!("{}-{}-{}", stem, std::process::id(), nonce)))
+    }
+
+    /// Create the staging base and output directories a PBO is extracted into
+    fn prepare_output_dirs(&self, scan_result: &PboScanResult) -> Result<(std::path::PathBuf, std::path::PathBuf)> {
+        // Prefix was already read from the listing during the scan
+        let prefix = scan_result.prefix.clone().unwrap_or_default();
+        debug!("PBO prefix: {}", prefix);
+        let prefix_path = utils::native_entry_path(&prefix)?;
+
+        // Create staging directory for this PBO
+        let base_dir = self.staging_dir(&scan_result.path);
+        debug!("Creating staging directory: {}", base_dir.display());
+        std::fs::create_dir_all(&base_dir)?;
+
+        // Create output directory with prefix path
+        let output_dir = base_dir.join(prefix_path);
+        trace!("Creating output directory: {}", output_dir.display());
+        std::fs::create_dir_all(&output_dir)?;
+        utils::ensure_within(&base_dir, &output_dir)?;
+
+        Ok((base_dir, output_dir))
+    }
+
+    /// Tool options, with the filter narrowed to `pending` entries when given
+    fn create_extract_options(&self, scan_result: &PboScanResult, pending: Option<&[PboEntry]>) -> ExtractOptions {
+        match pending {
+            Some(entries) => pending_extract_options(scan_result, entries),
+            None => ExtractOptions {
+                file_filter: self.filter.tool_filter(),
+                ..pending_extract_options(scan_result, &[])
+            },
+        }
+    }
+
+    fn extract_pbo_files(
+        &self, 
+        scan_result: &PboScanResult, 
+        output_dir: &std::path::Path,
+        pending: Option<&[PboEntry]>,
+    ) -> Result<(ExtractionStrategy, Vec<StrategyAttempt>)> {
+        let timeout = self.timeout_policy.for_size(scan_result.pbo_size);
+        trace!("Using {} second timeout for {}", timeout, scan_result.path.display());
+        let options = self.create_extract_options(scan_result, pending);
+        // The tool doesn't take extended-length paths
+        let output_dir = utils::tool_path(output_dir);
+        let output_dir = output_dir.as_path();
+
+        let mut attempts = Vec::new();
+        let mut last_error = None;
+
+        for &strategy in &self.fallback_chain {
+            debug!("Trying {} extraction for PBO: {}", strategy, scan_result.path.display());
+
+            let result = self.retry.run(&format!("{} extraction", strategy), &scan_result.path, || {
+                // Taken for each run, so waits between retries don't hold it
+                let _permit = self.io_limit.as_deref().map(ConcurrencyLimit::acquire);
+                self.throughput.add_extraction_run();
+                let waited_before = tool_wait();
+                let result = with_tool(timeout, |api| match strategy {
+                    ExtractionStrategy::Filtered => {
+                        api.extract_with_options(&scan_result.path, output_dir, options.clone())
+                    },
+                    ExtractionStrategy::Permissive => {
+                        let mut permissive_options = options.clone();
+                        permissive_options.file_filter = None; // Extract all files
+                        api.extract_with_options(&scan_result.path, output_dir, permissive_options)
+                    },
+                    ExtractionStrategy::Direct => {
+                        api.extract_files(&scan_result.path, output_dir, None)
+                    },
+                });
+                self.throughput.add_tool_wait(tool_wait() - waited_before);
+                result.map_err(|e| tool_error(e, timeout))
+            });
+
+            match result {
+                Ok(_) => {
+                    debug!("Extraction successful with {} extraction", strategy);
+                    return Ok((strategy, succeeded(strategy, attempts)));
+                },
+                // Error code 11 means no files to extract
+                Err(e) if is_nothing_to_extract(&format!("{:#}", e)) => {
+                    debug!("No files to extract (error code 11), treating as success: {}", scan_result.path.display());
+                    return Ok((strategy, succeeded(strategy, attempts)));
+                },
+                // Other strategies open the same file
+                Err(e) if is_lock_error(&e) => return Err(e),
+                Err(e) => {
+                    warn!("{} extraction failed: {:#}", strategy, e);
+                    attempts.push((strategy, format!("{:#}", e)));
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(StrategyFailures {
+                attempts,
+                last_error: format!("{:#}", e),
+            }.into()),
+            None => Err(anyhow::anyhow!("No extraction strategies configured")),
+        }
+    }
+}
+
+/// Failed `attempts` followed by the `strategy` that succeeded after them
+fn succeeded(strategy: ExtractionStrategy, attempts: Vec<(ExtractionStrategy, String)>) -> Vec<StrategyAttempt> {
+    attempts.into_iter()
+        .map(|(strategy, error)| StrategyAttempt { strategy, error: Some(error) })
+        .chain(std::iter::once(StrategyAttempt { strategy, error: None }))
+        .collect()
+}
+
+/// Tool options extracting just the `pending` entries of a scanned PBO
+///
+/// Pending entries are named as in the output directory; the tool is given the
+/// names they have in the PBO, from before name sanitization.
+pub(crate) fn pending_extract_options(scan_result: &PboScanResult, pending: &[PboEntry]) -> ExtractOptions {
+    let originals: HashMap<&str, &str> = scan_result.renamed.iter()
+        .map(|(original, sanitized)| (sanitized.as_str(), original.as_str()))
+        .collect();
+    let mut options = ExtractOptions::default();
+    options.file_filter = Some(
+        pending.iter()
+            .map(|entry| originals.get(entry.path.as_str()).copied().unwrap_or(&entry.path).replace('/', "\\"))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    options.no_pause = true;
+    options.warnings_as_errors = false;
+    options.verbose = true;
+    options
+}
+
+/// Path of the staged output directory relative to the staging base, the prefix
+fn staged_prefix(staging_base: &Path, staging_output: &Path) -> String {
+    staging_output.strip_prefix(staging_base)
+        .map(|prefix| utils::normalize_entry_path(&prefix.to_string_lossy()))
+        .unwrap_or_default()
+}
+
+/// Path of an entry of the output directory relative to the base directory
+fn base_relative(prefix: &str, entry: &str) -> String {
+    match prefix {
+        "" => entry.to_string(),
+        prefix => format!("{}/{}", prefix, entry),
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::utils::{filter_entries, missing_entries};
+    use super::super::derap::{RapConverter, RAP_MAGIC};
+    use super::super::stringtable::Stringtable;
     use tempfile::TempDir;
     use std::path::PathBuf;
+    use std::sync::Mutex;
     
     #[test]
     fn test_skip_empty_result() {
         let input_dir = TempDir::new().unwrap();
         let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf,hpp");
         
         let scan_result = PboScanResult {
             path: PathBuf::from("test.pbo"),
-            expected_files: vec![],
+            ..Default::default()
         };
         
         let processor = PboProcessor::new(
             input_dir.path(),
             cache_dir.path(),
-            "sqf,hpp",
+            &filter,
             1,
             30,
         );
@@ -275,4 +1841,992 @@ mod tests {
         let result = processor.process_pbo(&scan_result);
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+
+    #[derive(Default)]
+    struct RecordingProgress {
+        done: AtomicU64,
+        messages: Mutex<Vec<String>>,
+    }
+
+    impl ProgressReporter for RecordingProgress {
+        fn set_total(&self, _total: u64) {}
+
+        fn inc(&self, delta: u64) {
+            self.done.fetch_add(delta, Ordering::SeqCst);
+        }
+
+        fn set_message(&self, message: &str) {
+            self.messages.lock().unwrap().push(message.to_string());
+        }
+
+        fn finish(&self) {}
+    }
+
+    #[test]
+    fn test_process_all_reports_progress() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let progress = Arc::new(RecordingProgress::default());
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_progress(progress.clone());
+
+        let scan_results: Vec<_> = ["a.pbo", "b.pbo"].iter()
+            .map(|name| PboScanResult {
+                path: input_dir.path().join(name),
+                ..Default::default()
+            })
+            .collect();
+
+        let report = processor.process_all(&scan_results).unwrap();
+        assert_eq!(report.skipped, 2);
+        assert_eq!(progress.done.load(Ordering::SeqCst), 2);
+
+        let mut messages = progress.messages.lock().unwrap().clone();
+        messages.sort();
+        assert_eq!(messages, vec!["a.pbo".to_string(), "b.pbo".to_string()]);
+    }
+
+    #[test]
+    fn test_process_all_starts_largest_pbos_first() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_results: Vec<_> = [("a.pbo", 10), ("b.pbo", 300), ("c.pbo", 10), ("d.pbo", 40)].iter()
+            .map(|(name, pbo_size)| PboScanResult {
+                path: input_dir.path().join(name),
+                pbo_size: *pbo_size,
+                ..Default::default()
+            })
+            .collect();
+        let started = |order: ProcessingOrder| {
+            let progress = Arc::new(RecordingProgress::default());
+            let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+                .with_progress(progress.clone())
+                .with_processing_order(order);
+            assert_eq!(processor.process_all(&scan_results).unwrap().skipped, 4);
+            let messages = progress.messages.lock().unwrap().clone();
+            messages
+        };
+
+        assert_eq!(started(ProcessingOrder::LargestFirst), vec!["b.pbo", "d.pbo", "a.pbo", "c.pbo"]);
+        assert_eq!(started(ProcessingOrder::PathOrder), vec!["a.pbo", "b.pbo", "c.pbo", "d.pbo"]);
+    }
+
+    #[test]
+    fn test_background_pauses_after_each_pbo() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_results: Vec<_> = (0..3)
+            .map(|i| PboScanResult {
+                path: input_dir.path().join(format!("{}.pbo", i)),
+                ..Default::default()
+            })
+            .collect();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_background(true);
+
+        let started = Instant::now();
+        let report = processor.process_all(&scan_results).unwrap();
+        assert!(started.elapsed() >= 3 * BACKGROUND_PAUSE);
+        assert!(report.background);
+        // Workers are reniced on Linux, and the tools they start with them
+        #[cfg(target_os = "linux")]
+        {
+            assert!(report.tool_priority_lowered);
+            assert!(report.to_string().lines().next().unwrap().ends_with(" in background mode"));
+        }
+        let normal = ExtractionReport { tool_priority_lowered: false, ..report };
+        assert!(normal.to_string().lines().next().unwrap().ends_with(" in background mode, the tool at normal priority"));
+    }
+
+    /// Counts the PBOs being processed at once, from their message to their progress
+    #[derive(Default)]
+    struct ConcurrencyProgress {
+        active: AtomicU64,
+        max_active: AtomicU64,
+    }
+
+    impl ProgressReporter for ConcurrencyProgress {
+        fn set_total(&self, _total: u64) {}
+
+        fn inc(&self, delta: u64) {
+            self.active.fetch_sub(delta, Ordering::SeqCst);
+        }
+
+        fn set_message(&self, _message: &str) {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        fn finish(&self) {}
+    }
+
+    #[test]
+    fn test_threads_limit_concurrent_pbos() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_results: Vec<_> = (0..8)
+            .map(|i| PboScanResult {
+                path: input_dir.path().join(format!("{}.pbo", i)),
+                ..Default::default()
+            })
+            .collect();
+        let max_active = |threads: usize| {
+            let progress = Arc::new(ConcurrencyProgress::default());
+            let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, threads, 30)
+                .with_progress(progress.clone());
+            assert_eq!(processor.process_all(&scan_results).unwrap().skipped, 8);
+            progress.max_active.load(Ordering::SeqCst)
+        };
+
+        assert_eq!(max_active(1), 1);
+        assert!(max_active(4) > 1);
+    }
+
+    #[test]
+    fn test_base_output_dir_strips_archive_extension() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        for (name, expected) in [
+            ("addons/FOO.PBO", "addons/FOO"),
+            ("addons/foo.Pbo", "addons/foo"),
+            ("addons/foo.ebo", "addons/foo"),
+            ("addons/foo.bar.pbo", "addons/foo.bar"),
+        ] {
+            let output = processor.base_output_dir(&input_dir.path().join(name)).unwrap();
+            assert_eq!(output, utils::long_path(&cache_dir.path().join(expected)));
+        }
+    }
+
+    #[test]
+    fn test_prepare_output_dirs_uses_scanned_prefix() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        // No PBO exists on disk, so this would fail if the tool were invoked
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("addons/ace_medical.pbo"),
+            prefix: Some("z\\ace\\addons\\medical".to_string()),
+            ..Default::default()
+        };
+
+        let (staging_base, staging_output) = processor.prepare_output_dirs(&scan_result).unwrap();
+        assert!(staging_base.starts_with(utils::long_path(&cache_dir.path().join(STAGING_DIR_NAME))));
+        assert_eq!(staging_output, staging_base.join("z/ace/addons/medical"));
+        assert!(staging_output.is_dir());
+
+        let (base_dir, output_dir) = processor.output_dirs(&scan_result).unwrap();
+        assert_eq!(base_dir, utils::long_path(&cache_dir.path().join("addons/ace_medical")));
+        assert_eq!(output_dir, base_dir.join("z/ace/addons/medical"));
+        assert!(!base_dir.exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_prepare_output_dirs_beyond_max_path() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::default();
+        let deep_cache = cache_dir.path().join("a".repeat(100)).join("b".repeat(100));
+        let processor = PboProcessor::new(input_dir.path(), &deep_cache, &filter, 1, 30);
+
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("addons/ace_medical_treatment.pbo"),
+            prefix: Some(format!("z\\ace\\addons\\medical_treatment\\{}", "c".repeat(100))),
+            ..Default::default()
+        };
+
+        let (staging_base, staging_output) = processor.prepare_output_dirs(&scan_result).unwrap();
+        assert!(staging_output.as_os_str().len() > 260);
+        assert!(staging_output.is_dir());
+        std::fs::write(staging_output.join(format!("fnc_{}.sqf", "d".repeat(100))), b"heal").unwrap();
+        assert_eq!(verify_output(&staging_output, &[]).unwrap().unexpected.len(), 1);
+        std::fs::remove_dir_all(staging_base).unwrap();
+    }
+
+    #[test]
+    fn test_failed_extraction_keeps_previous_output() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_fallback_chain(Vec::new());
+
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(base_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![PboEntry { path: "fnc_heal.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+
+        assert!(processor.process_pbo(&scan_result).is_err());
+        assert_eq!(std::fs::read(base_dir.join("fnc_heal.sqf")).unwrap(), b"heal");
+        assert_eq!(std::fs::read_dir(cache_dir.path().join(STAGING_DIR_NAME)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_skip_include_paths_without_matches() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf")
+            .with_include_paths(vec!["functions/medical/".to_string()]);
+        
+        let listing = vec![
+            "config.cpp".to_string(),
+            "functions\\fnc_setUnconscious.sqf".to_string(),
+        ];
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("test.pbo"),
+            expected_files: filter_entries(&listing, &HashMap::new(), &filter),
+            ..Default::default()
+        };
+        
+        let processor = PboProcessor::new(
+            input_dir.path(),
+            cache_dir.path(),
+            &filter,
+            1,
+            30,
+        );
+        
+        let result = processor.process_pbo(&scan_result).unwrap();
+        assert_eq!(result, ProcessOutcome::Skipped(SkipReason::NoMatchingFiles));
+        assert_eq!(std::fs::read_dir(cache_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_pbo_changed_since_listing_is_deferred() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let pbo_path = input_dir.path().join("ace_medical.pbo");
+        std::fs::write(&pbo_path, b"still downloading").unwrap();
+        let scan_result = PboScanResult {
+            path: pbo_path,
+            expected_files: vec![PboEntry { path: "fnc_heal.sqf".to_string(), size: Some(4) }],
+            pbo_size: 5,
+            ..Default::default()
+        };
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        let report = processor.process_all(&[scan_result]).unwrap();
+        assert_eq!(report.deferred, vec![PathBuf::from("ace_medical.pbo")]);
+        assert_eq!((report.skipped, report.failed), (1, 0));
+        assert!(!cache_dir.path().join("ace_medical").exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_locked_pbo_is_skipped_as_locked() {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let pbo_path = input_dir.path().join("ace_medical.pbo");
+        std::fs::write(&pbo_path, b"locked").unwrap();
+        // No sharing at all, like an antivirus scanner holding the file
+        let _lock = std::fs::OpenOptions::new().read(true).share_mode(0).open(&pbo_path).unwrap();
+        let scan_result = PboScanResult {
+            path: pbo_path,
+            expected_files: vec![PboEntry { path: "fnc_heal.sqf".to_string(), size: Some(4) }],
+            pbo_size: 6,
+            ..Default::default()
+        };
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_lock_retry(LockRetry { max_retries: 1, delay: std::time::Duration::from_millis(10) });
+
+        let report = processor.process_all(&[scan_result]).unwrap();
+        assert_eq!(report.locked, vec![PathBuf::from("ace_medical.pbo")]);
+        assert_eq!((report.skipped, report.failed), (1, 0));
+        assert!(!cache_dir.path().join("ace_medical").exists());
+    }
+
+    #[test]
+    fn test_failure_threshold_stops_scheduling() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_fallback_chain(Vec::new())
+            .with_failure_policy(FailurePolicy::Threshold { max_failures: Some(2), max_failure_ratio: None });
+        let scan_results: Vec<_> = ["a", "b", "c", "d", "e"].iter()
+            .map(|name| PboScanResult {
+                path: input_dir.path().join(format!("{}.pbo", name)),
+                expected_files: vec![PboEntry { path: "fnc_test.sqf".to_string(), size: None }],
+                ..Default::default()
+            })
+            .collect();
+
+        // One worker starts the PBOs in order
+        let report = processor.process_all(&scan_results).unwrap();
+        assert!(report.aborted);
+        assert_eq!((report.failed, report.skipped), (2, 3));
+        let failed: Vec<_> = report.failures.iter().map(|failure| failure.to_string()).collect();
+        assert_eq!(failed, vec![
+            "a.pbo: No extraction strategies configured",
+            "b.pbo: No extraction strategies configured",
+        ]);
+        assert_eq!(report.not_processed, vec![PathBuf::from("c.pbo"), PathBuf::from("d.pbo"), PathBuf::from("e.pbo")]);
+    }
+
+    #[test]
+    fn test_empty_fallback_chain_fails_without_invoking_tool() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_fallback_chain(Vec::new());
+
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("test.pbo"),
+            expected_files: vec![PboEntry { path: "fnc_test.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+
+        let error = processor.process_pbo(&scan_result).unwrap_err();
+        assert_eq!(error.to_string(), "No extraction strategies configured");
+    }
+
+    #[test]
+    fn test_attempts_end_with_successful_strategy() {
+        let attempts = succeeded(
+            ExtractionStrategy::Direct,
+            vec![
+                (ExtractionStrategy::Filtered, "Operation timed out after 30 seconds".to_string()),
+                (ExtractionStrategy::Permissive, "return code 3".to_string()),
+            ],
+        );
+        assert_eq!(attempts, vec![
+            StrategyAttempt {
+                strategy: ExtractionStrategy::Filtered,
+                error: Some("Operation timed out after 30 seconds".to_string()),
+            },
+            StrategyAttempt { strategy: ExtractionStrategy::Permissive, error: Some("return code 3".to_string()) },
+            StrategyAttempt { strategy: ExtractionStrategy::Direct, error: None },
+        ]);
+
+        let json = serde_json::to_string(&attempts[2]).unwrap();
+        assert_eq!(json, r#"{"strategy":"direct"}"#);
+    }
+
+    #[test]
+    fn test_failure_log() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("test.pbo"),
+            prefix: Some("x\\test".to_string()),
+            expected_files: vec![PboEntry { path: "fnc_test.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_fallback_chain(Vec::new())
+            .with_failure_logs(false);
+        let base_dir = processor.base_output_dir(&scan_result.path).unwrap();
+        let log_path = processor.failure_log_path(&scan_result.path).unwrap();
+        assert_eq!(log_path, utils::long_path(&cache_dir.path().join("failures/test.log")));
+        processor.process_pbo(&scan_result).unwrap_err();
+        assert!(!log_path.exists());
+
+        let processor = processor.with_failure_logs(true);
+        processor.process_pbo(&scan_result).unwrap_err();
+        let log = std::fs::read_to_string(&log_path).unwrap();
+        assert!(log.starts_with(&format!("PBO: {}\n", scan_result.path.display())));
+        assert!(log.contains("Error: No extraction strategies configured\n"));
+        assert!(log.contains("  Extensions: sqf\n"));
+        // A PBO that never extracted gets no output directory for its log
+        assert!(!base_dir.exists());
+    }
+
+    #[test]
+    fn test_sidecars_stay_with_their_output() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![PboEntry { path: "fnc_heal.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(base_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+        ExtractionManifest::collect(&base_dir, &scan_result.path, "aa".to_string(), None, "sqf").unwrap().write(&base_dir).unwrap();
+        write_checksums(&base_dir).unwrap();
+        let previous_manifest = std::fs::read(base_dir.join(MANIFEST_FILE_NAME)).unwrap();
+        let previous_checksums = std::fs::read(base_dir.join(utils::CHECKSUM_FILE_NAME)).unwrap();
+
+        let staging = TempDir::new().unwrap();
+        let (_, carried) = processor.carry_over(&scan_result.expected_files, &base_dir, &base_dir, staging.path()).unwrap();
+        assert_eq!(carried, HashSet::from(["fnc_heal.sqf".to_string()]));
+        assert!(!staging.path().join(MANIFEST_FILE_NAME).exists());
+        assert!(!staging.path().join(utils::CHECKSUM_FILE_NAME).exists());
+
+        // Writing the new output's sidecars leaves the previous output as it was
+        ExtractionManifest::collect(staging.path(), &scan_result.path, "bb".to_string(), None, "sqf").unwrap().write(staging.path()).unwrap();
+        write_checksums(staging.path()).unwrap();
+        assert_eq!(std::fs::read(base_dir.join(MANIFEST_FILE_NAME)).unwrap(), previous_manifest);
+        assert_eq!(std::fs::read(base_dir.join(utils::CHECKSUM_FILE_NAME)).unwrap(), previous_checksums);
+    }
+
+    #[test]
+    fn test_carried_conversions_are_converted_anew() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("*");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_derapify(Some(Arc::new(RapConverter)))
+            .with_stringtable_conversion(true);
+        let expected_files: Vec<PboEntry> = ["config.bin", "stringtable.xml", "shipped/config.bin", "shipped/config.cpp"].iter()
+            .map(|path| PboEntry { path: path.to_string(), size: None })
+            .collect();
+        // An empty root class
+        let rapified = [&RAP_MAGIC[..], &[0; 14]].concat();
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(base_dir.join("shipped")).unwrap();
+        for (path, content) in [
+            ("config.bin", &rapified[..]),
+            ("config.cpp", b"class Stale {};"),
+            ("stringtable.xml", b"<Project name=\"Old\"/>"),
+            ("stringtable.json", b"{}"),
+            ("shipped/config.bin", &rapified[..]),
+            ("shipped/config.cpp", b"class Shipped {};"),
+        ] {
+            std::fs::write(base_dir.join(path), content).unwrap();
+        }
+
+        // The changed files are extracted again, the rest carried over
+        let staging = TempDir::new().unwrap();
+        std::fs::write(staging.path().join("config.bin"), &rapified).unwrap();
+        std::fs::write(staging.path().join("stringtable.xml"), b"<Project name=\"ACE\"></Project>").unwrap();
+        let (_, mut carried) = processor.carry_over(&expected_files, &base_dir, &base_dir, staging.path()).unwrap();
+        processor.drop_conversions(&expected_files, (staging.path(), staging.path()), &mut carried).unwrap();
+        assert_eq!(carried, HashSet::from(["shipped/config.bin".to_string(), "shipped/config.cpp".to_string()]));
+
+        let result = derapify_tree(staging.path(), &RapConverter).unwrap();
+        assert_eq!(result.converted, vec!["config.bin".to_string()]);
+        assert_eq!(std::fs::read_to_string(staging.path().join("config.cpp")).unwrap(), "");
+        assert_eq!(std::fs::read(staging.path().join("shipped/config.cpp")).unwrap(), b"class Shipped {};");
+        assert_eq!(std::fs::read(base_dir.join("config.cpp")).unwrap(), b"class Stale {};");
+
+        convert_stringtables(staging.path()).unwrap();
+        let json = std::fs::read_to_string(staging.path().join("stringtable.json")).unwrap();
+        assert_eq!(serde_json::from_str::<Stringtable>(&json).unwrap().project.as_deref(), Some("ACE"));
+        assert_eq!(std::fs::read(base_dir.join("stringtable.json")).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_retained_version_keeps_its_manifest() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![
+                PboEntry { path: "fnc_heal.sqf".to_string(), size: None },
+                PboEntry { path: "fnc_splint.sqf".to_string(), size: None },
+            ],
+            ..Default::default()
+        };
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(base_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+        ExtractionManifest::collect(&base_dir, &scan_result.path, "aa".to_string(), None, "sqf").unwrap().write(&base_dir).unwrap();
+        write_checksums(&base_dir).unwrap();
+        let previous_checksums = std::fs::read(base_dir.join(utils::CHECKSUM_FILE_NAME)).unwrap();
+
+        // The re-run extracts the new file and carries over the unchanged one
+        let staging = processor.staging_dir(&scan_result.path);
+        std::fs::create_dir_all(&staging).unwrap();
+        std::fs::write(staging.join("fnc_splint.sqf"), b"splint").unwrap();
+        processor.carry_over(&scan_result.expected_files, &base_dir, &base_dir, &staging).unwrap();
+        write_checksums(&staging).unwrap();
+        ExtractionManifest::collect(&staging, &scan_result.path, "bb".to_string(), None, "sqf").unwrap().write(&staging).unwrap();
+        versions::replace_retaining(&staging, &base_dir, 1).unwrap();
+
+        let previous = versions::version_dir(&base_dir, 1);
+        let manifest = ExtractionManifest::read(&previous).unwrap();
+        assert_eq!(manifest.hash, "aa");
+        assert_eq!(manifest.files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), vec!["fnc_heal.sqf"]);
+        assert_eq!(std::fs::read(previous.join(utils::CHECKSUM_FILE_NAME)).unwrap(), previous_checksums);
+        assert_eq!(versions::retained_versions(&base_dir)[0].hash, "aa");
+        assert_eq!(ExtractionManifest::read(&base_dir).unwrap().hash, "bb");
+    }
+
+    #[test]
+    fn test_prune_compiled_scripts_with_source() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let mut filter = FileFilter::new("sqf,sqfc");
+        filter.prefer_source = true;
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        let output_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(output_dir.join("functions")).unwrap();
+        std::fs::write(output_dir.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(output_dir.join("functions/fnc_heal.sqfc"), b"compiled").unwrap();
+        std::fs::write(output_dir.join("functions/fnc_splint.sqfc"), b"compiled").unwrap();
+
+        // Even a filtered extraction may have written both
+        let pruned = processor.prune_output(&output_dir, ExtractionStrategy::Filtered).unwrap();
+        assert_eq!(pruned, 1);
+        assert!(output_dir.join("functions/fnc_heal.sqf").exists());
+        assert!(!output_dir.join("functions/fnc_heal.sqfc").exists());
+        assert!(output_dir.join("functions/fnc_splint.sqfc").exists());
+    }
+
+    #[test]
+    fn test_prune_after_permissive_fallback() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf,hpp");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        // Fake output of an unfiltered extraction
+        let output_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(output_dir.join("functions")).unwrap();
+        std::fs::create_dir_all(output_dir.join("data/textures")).unwrap();
+        std::fs::write(output_dir.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(output_dir.join("script_component.HPP"), b"#define").unwrap();
+        std::fs::write(output_dir.join("config.cpp"), b"class").unwrap();
+        std::fs::write(output_dir.join("data/textures/icon.paa"), b"paa").unwrap();
+
+        let pruned = processor.prune_output(&output_dir, ExtractionStrategy::Permissive).unwrap();
+
+        assert_eq!(pruned, 2);
+        assert!(output_dir.join("functions/fnc_heal.sqf").exists());
+        assert!(output_dir.join("script_component.HPP").exists());
+        assert!(!output_dir.join("config.cpp").exists());
+        assert!(!output_dir.join("data").exists());
+    }
+
+    #[test]
+    fn test_no_prune_after_filtered_extraction() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        std::fs::write(cache_dir.path().join("config.cpp"), b"class").unwrap();
+
+        let pruned = processor.prune_output(cache_dir.path(), ExtractionStrategy::Filtered).unwrap();
+        assert_eq!(pruned, 0);
+        assert!(cache_dir.path().join("config.cpp").exists());
+    }
+
+    #[test]
+    fn test_resume_skips_complete_output() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_resume(true);
+
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) }],
+            ..Default::default()
+        };
+        let output_dir = cache_dir.path().join("ace_medical/functions");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+
+        // The PBO doesn't exist, so any tool invocation would fail
+        let outcome = processor.process_pbo(&scan_result).unwrap();
+        assert_eq!(outcome, ProcessOutcome::Skipped(SkipReason::AlreadyExtracted));
+    }
+
+    #[test]
+    fn test_versions_kept_for_changed_pbos_only() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_retain_versions(2);
+
+        let pbo_path = input_dir.path().join("ace_medical.pbo");
+        std::fs::write(&pbo_path, b"PBO").unwrap();
+        let scan_result = PboScanResult { path: pbo_path.clone(), ..Default::default() };
+        let base_dir = cache_dir.path().join("ace_medical");
+        assert_eq!(processor.versions_to_keep(&scan_result, &base_dir), 0);
+
+        std::fs::create_dir_all(&base_dir).unwrap();
+        let manifest = |hash: String| {
+            ExtractionManifest::collect(&base_dir, &pbo_path, hash, None, "sqf").unwrap().write(&base_dir).unwrap();
+        };
+        manifest("stale".to_string());
+        assert_eq!(processor.versions_to_keep(&scan_result, &base_dir), 2);
+        // Extracted again unchanged, like after a filter change
+        manifest(utils::calculate_file_hash(&pbo_path).unwrap());
+        assert_eq!(processor.versions_to_keep(&scan_result, &base_dir), 0);
+    }
+
+    #[test]
+    fn test_sink_skips_unchanged_streamed_pbo() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let sink: Arc<dyn FileSink> = Arc::new(|_: &Path, _: &str, _: &[u8]| -> Result<()> { Ok(()) });
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_sink(Some(sink));
+
+        let pbo_path = input_dir.path().join("ace_medical.pbo");
+        std::fs::write(&pbo_path, b"PboPrefix").unwrap();
+        let scan_result = PboScanResult {
+            path: pbo_path.clone(),
+            expected_files: vec![PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) }],
+            ..Default::default()
+        };
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        let mut manifest = ExtractionManifest::collect(
+            &base_dir,
+            &pbo_path,
+            utils::calculate_file_hash(&pbo_path).unwrap(),
+            None,
+            "sqf",
+        ).unwrap();
+        manifest.write(&base_dir).unwrap();
+
+        // Extracted but not streamed, so the sink still has to see the files
+        assert!(!processor.is_streamed(&scan_result));
+
+        manifest.streamed = true;
+        manifest.write(&base_dir).unwrap();
+        let outcome = processor.process_pbo(&scan_result).unwrap();
+        assert_eq!(outcome, ProcessOutcome::Skipped(SkipReason::AlreadyExtracted));
+
+        std::fs::write(&pbo_path, b"PboPrefix changed").unwrap();
+        assert!(!processor.is_streamed(&scan_result));
+    }
+
+    /// Output of a previous run with one complete and one truncated file
+    fn overwrite_fixture(input_dir: &Path, cache_dir: &Path) -> PboScanResult {
+        let output_dir = cache_dir.join("ace_medical/functions");
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(output_dir.join("fnc_splint.sqf"), b"old").unwrap();
+
+        PboScanResult {
+            path: input_dir.join("ace_medical.pbo"),
+            expected_files: vec![
+                PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+                PboEntry { path: "functions/fnc_splint.sqf".to_string(), size: Some(6) },
+                PboEntry { path: "functions/fnc_bandage.sqf".to_string(), size: None },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hostile_paths_are_skipped() {
+        let input_dir = TempDir::new().unwrap();
+        let sandbox = TempDir::new().unwrap();
+        let cache_dir = sandbox.path().join("cache");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), &cache_dir, &filter, 1, 30);
+
+        let heal = || vec![PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: None }];
+        let hostile = [
+            (Some("../evil"), heal(), Vec::new()),
+            (Some("C:\\evil"), heal(), Vec::new()),
+            (Some("..\\.."), heal(), Vec::new()),
+            (None, vec![PboEntry { path: "..\\..\\evil.sqf".to_string(), size: None }], Vec::new()),
+            (None, heal(), vec!["../../evil.paa".to_string()]),
+        ];
+        for (prefix, expected_files, unsafe_paths) in hostile {
+            let scan_result = PboScanResult {
+                path: input_dir.path().join("ace_medical.pbo"),
+                expected_files,
+                prefix: prefix.map(str::to_string),
+                unsafe_paths,
+                ..Default::default()
+            };
+            let outcome = processor.process_pbo(&scan_result).unwrap();
+            assert_eq!(outcome, ProcessOutcome::Skipped(SkipReason::InvalidFormat), "{:?}", scan_result);
+            if prefix.is_some() {
+                assert!(processor.prepare_output_dirs(&scan_result).is_err());
+            }
+        }
+
+        // Nothing was created next to or inside the cache directory
+        let sandbox_entries: Vec<_> = std::fs::read_dir(sandbox.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(sandbox_entries, vec![std::ffi::OsString::from("cache")]);
+        assert!(!cache_dir.join("ace_medical").exists());
+        assert!(!cache_dir.join(STAGING_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn test_output_conflict_fails_without_extracting() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let pbo_path = input_dir.path().join("foo.pbo");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_output_conflicts(HashMap::from([(pbo_path.clone(), "Output directory of foo.pbo collides with: foo.ebo".to_string())]));
+
+        let scan_result = PboScanResult {
+            path: pbo_path,
+            expected_files: vec![PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+        let error = processor.process_pbo(&scan_result).unwrap_err();
+        assert_eq!(error.to_string(), "Output directory of foo.pbo collides with: foo.ebo");
+        assert!(!cache_dir.path().join(STAGING_DIR_NAME).exists());
+    }
+
+    #[test]
+    fn test_overwrite_policy_pending_entries() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_result = overwrite_fixture(input_dir.path(), cache_dir.path());
+        let pending = |overwrite, resume| {
+            PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+                .with_overwrite(overwrite)
+                .with_resume(resume)
+                .pending_entries(&scan_result)
+                .unwrap()
+                .map(|pending| pending.into_iter().map(|entry| entry.path).collect::<Vec<_>>())
+        };
+
+        assert_eq!(pending(OverwritePolicy::Overwrite, false), None);
+        assert_eq!(pending(OverwritePolicy::SkipExisting, false), Some(vec!["functions/fnc_bandage.sqf".to_string()]));
+        assert_eq!(pending(OverwritePolicy::SkipExisting, true), Some(vec!["functions/fnc_bandage.sqf".to_string()]));
+        assert_eq!(pending(OverwritePolicy::Overwrite, true), Some(vec![
+            "functions/fnc_splint.sqf".to_string(),
+            "functions/fnc_bandage.sqf".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn test_estimate_size_counts_pending_entries() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_result = PboScanResult {
+            pbo_size: 8,
+            ..overwrite_fixture(input_dir.path(), cache_dir.path())
+        };
+        let processor = || PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        // Everything is rewritten, but only the missing file takes new space
+        assert_eq!(processor().estimate_size(&scan_result), (10, 8));
+        assert_eq!(processor().with_resume(true).estimate_size(&scan_result), (8, 8));
+        assert_eq!(
+            processor().with_overwrite(OverwritePolicy::Error).estimate_size(&scan_result),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn test_output_quota_stops_further_pbos() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_max_output_bytes(Some(10));
+        let scan_result = |name: &str, size| PboScanResult {
+            path: input_dir.path().join(name),
+            expected_files: vec![PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(size) }],
+            ..Default::default()
+        };
+
+        // The PBO doesn't exist, so the failed extraction releases its reservation
+        assert!(processor.process_pbo(&scan_result("ace_medical.pbo", 6)).is_err());
+        assert_eq!(processor.output_bytes.load(Ordering::SeqCst), 0);
+
+        assert_eq!(processor.reserve_output_bytes(&scan_result("ace_common.pbo", 6), None), Some(6));
+        assert_eq!(processor.reserve_output_bytes(&scan_result("ace_interaction.pbo", 6), None), None);
+        // Nothing is started once the quota was hit, even if it would fit
+        assert_eq!(processor.reserve_output_bytes(&scan_result("ace_main.pbo", 1), None), None);
+
+        let report = processor.process_all(&[scan_result("ace_main.pbo", 1)]).unwrap();
+        assert!(report.quota_exceeded);
+        assert_eq!(report.not_processed, vec![PathBuf::from("ace_main.pbo")]);
+        assert_eq!(report.skipped, 1);
+    }
+
+    #[test]
+    fn test_overwrite_error_lists_conflicts() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let scan_result = overwrite_fixture(input_dir.path(), cache_dir.path());
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30)
+            .with_overwrite(OverwritePolicy::Error);
+
+        // The PBO doesn't exist, so the error can only come from the conflict check
+        let error = processor.process_pbo(&scan_result).unwrap_err().to_string();
+        assert!(error.starts_with("2 files already exist"), "{error}");
+        assert!(error.contains("functions/fnc_heal.sqf, functions/fnc_splint.sqf"), "{error}");
+
+        // Resuming would only replace the file with the wrong size
+        let processor = processor.with_resume(true);
+        let error = processor.pending_entries(&scan_result).unwrap_err().to_string();
+        assert!(error.starts_with("1 files already exist"), "{error}");
+        assert!(error.ends_with("functions/fnc_splint.sqf"), "{error}");
+    }
+
+    #[test]
+    fn test_resume_narrows_tool_filter_to_missing_files() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+
+        let output_dir = cache_dir.path().join("out");
+        std::fs::create_dir_all(output_dir.join("functions")).unwrap();
+        std::fs::write(output_dir.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+
+        let expected = vec![
+            PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+            PboEntry { path: "functions/fnc_bandage.sqf".to_string(), size: Some(8) },
+            PboEntry { path: "functions/fnc_splint.sqf".to_string(), size: None },
+            PboEntry { path: "functions/fnc_what_.sqf".to_string(), size: None },
+        ];
+        let mut scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: expected.clone(),
+            ..Default::default()
+        };
+        scan_result.renamed.insert("functions/fnc_what?.sqf".to_string(), "functions/fnc_what_.sqf".to_string());
+        scan_result.renamed.insert("Functions/fnc_Bandage.sqf".to_string(), "functions/fnc_bandage.sqf".to_string());
+        let missing = missing_entries(&output_dir, &expected);
+        let options = processor.create_extract_options(&scan_result, Some(&missing));
+
+        // The tool is given the names in the PBO, not the lowercased or sanitized ones
+        assert_eq!(
+            options.file_filter.as_deref(),
+            Some("Functions\\fnc_Bandage.sqf,functions\\fnc_splint.sqf,functions\\fnc_what?.sqf")
+        );
+        assert_eq!(processor.create_extract_options(&scan_result, None).file_filter.as_deref(), Some("sqf"));
+        // Nothing may wait for a key press
+        assert!(options.no_pause);
+    }
+
+    #[test]
+    fn test_carry_over_previous_output() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![
+                PboEntry { path: "fnc_heal.sqf".to_string(), size: None },
+                PboEntry { path: "fnc_splint.sqf".to_string(), size: None },
+            ],
+            ..Default::default()
+        };
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(base_dir.join("old")).unwrap();
+        std::fs::write(base_dir.join("fnc_heal.sqf"), b"old heal").unwrap();
+        std::fs::write(base_dir.join("fnc_splint.sqf"), b"splint").unwrap();
+        std::fs::write(base_dir.join("old/fnc_old.sqf"), b"old").unwrap();
+
+        let carry_over = |processor: PboProcessor| {
+            let staging = processor.staging_dir(&scan_result.path);
+            std::fs::create_dir_all(&staging).unwrap();
+            std::fs::write(staging.join("fnc_heal.sqf"), b"new heal").unwrap();
+            let (stale, _) = processor.carry_over(&scan_result.expected_files, &base_dir, &base_dir, &staging).unwrap();
+            (staging, stale)
+        };
+
+        // Dry run keeps stale files, fresh files win over old ones
+        let (staging, stale) = carry_over(
+            PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).with_sync_outputs(true, true)
+        );
+        assert_eq!(stale, vec!["old/fnc_old.sqf".to_string()]);
+        assert!(staging.join("old/fnc_old.sqf").exists());
+        assert_eq!(std::fs::read(staging.join("fnc_heal.sqf")).unwrap(), b"new heal");
+        assert_eq!(std::fs::read(staging.join("fnc_splint.sqf")).unwrap(), b"splint");
+
+        // Syncing leaves stale files behind, the previous output is untouched
+        let (staging, stale) = carry_over(
+            PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30).with_sync_outputs(true, false)
+        );
+        assert_eq!(stale.len(), 1);
+        assert!(!staging.join("old").exists());
+        assert!(staging.join("fnc_splint.sqf").exists());
+        assert!(base_dir.join("old/fnc_old.sqf").exists());
+    }
+
+    /// Uppercases files in place
+    #[derive(Debug)]
+    struct Uppercase;
+
+    impl FileTransformer for Uppercase {
+        fn transform(&self, _pbo: &Path, _entry: &str, path: &Path) -> Result<Option<PathBuf>> {
+            let data = std::fs::read(path)?;
+            std::fs::write(path, data.to_ascii_uppercase())?;
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_transformer_leaves_carried_over_links_alone() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let filter = FileFilter::new("sqf");
+        let processor = PboProcessor::new(input_dir.path(), cache_dir.path(), &filter, 1, 30);
+        let scan_result = PboScanResult {
+            path: input_dir.path().join("ace_medical.pbo"),
+            expected_files: vec![PboEntry { path: "fnc_heal.sqf".to_string(), size: None }],
+            ..Default::default()
+        };
+
+        // The previous output, its file also linked from a stored object
+        let base_dir = cache_dir.path().join("ace_medical");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(base_dir.join("fnc_heal.sqf"), b"heal").unwrap();
+        let object = cache_dir.path().join("object");
+        std::fs::hard_link(base_dir.join("fnc_heal.sqf"), &object).unwrap();
+        let mut manifest = ExtractionManifest::collect(&base_dir, &scan_result.path, "aa".to_string(), None, "sqf").unwrap();
+        manifest.write(&base_dir).unwrap();
+
+        let transform = |manifest: &ExtractionManifest| {
+            let staging = TempDir::new().unwrap();
+            let (_, carried) = processor.carry_over(&scan_result.expected_files, &base_dir, &base_dir, staging.path()).unwrap();
+            let found = vec!["fnc_heal.sqf".to_string()];
+            let (failed, transformed) = processor.transform_staged(
+                &Uppercase,
+                &scan_result,
+                (staging.path(), staging.path()),
+                &found,
+                &carried,
+                Some(manifest),
+            );
+            assert!(failed.is_empty());
+            assert_eq!(transformed, found);
+            std::fs::read(staging.path().join("fnc_heal.sqf")).unwrap()
+        };
+
+        // Not transformed before: the staged copy changes, the previous output doesn't
+        assert_eq!(transform(&manifest), b"HEAL");
+        assert_eq!(std::fs::read(base_dir.join("fnc_heal.sqf")).unwrap(), b"heal");
+        assert_eq!(std::fs::read(&object).unwrap(), b"heal");
+
+        // Transformed by an earlier run: carried over as it is
+        manifest.transformed = vec!["fnc_heal.sqf".to_string()];
+        assert_eq!(transform(&manifest), b"heal");
+    }
+}
@@ -0,0 +1,350 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif_log_bridge::LogWrapper;
+use log::info;
+use serde::Deserialize;
+
+use super::log_file::{tee_installed, TeeLogger};
+
+static MULTI_PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Progress display shared by every extraction run in this process
+pub fn multi_progress() -> &'static MultiProgress {
+    MULTI_PROGRESS.get_or_init(MultiProgress::new)
+}
+
+/// Install `logger` as the global logger, routed through the progress display
+///
+/// Log lines are printed above the progress bars instead of tearing through them.
+pub fn init_logging(logger: env_logger::Logger) -> Result<()> {
+    let level = logger.filter();
+    LogWrapper::new(multi_progress().clone(), TeeLogger::new(logger)).try_init()?;
+    tee_installed();
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// How progress is shown while extracting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// Bars when stderr is a terminal, plain status lines otherwise
+    #[default]
+    Auto,
+    /// Interactive progress bars
+    Bars,
+    /// Periodic status lines through `log::info!`, for journals and CI logs
+    Plain,
+    /// No progress output
+    Silent,
+}
+
+impl OutputMode {
+    /// Replace `Auto` with the mode suited to the current stderr
+    pub fn resolve(self) -> Self {
+        match self {
+            OutputMode::Auto if std::io::stderr().is_terminal() => OutputMode::Bars,
+            OutputMode::Auto => OutputMode::Plain,
+            mode => mode,
+        }
+    }
+}
+
+/// When `OutputMode::Plain` logs a status line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusInterval {
+    /// Log this often while a phase is running, zero to log only by PBO count
+    pub every: Duration,
+    /// Additionally log after every this many PBOs in a phase
+    pub every_pbos: Option<u64>,
+}
+
+impl Default for StatusInterval {
+    fn default() -> Self {
+        Self {
+            every: Duration::from_secs(30),
+            every_pbos: None,
+        }
+    }
+}
+
+/// Receiver of progress updates for one phase of a run
+///
+/// Implementations are called from the rayon workers and must be thread-safe.
+pub trait ProgressReporter: Send + Sync {
+    /// Set the number of units expected in this phase
+    fn set_total(&self, total: u64);
+    /// Mark `delta` more units as done
+    fn inc(&self, delta: u64);
+    /// Record `delta` units that failed; they are counted by `inc` as well
+    fn inc_failed(&self, _delta: u64) {}
+    /// Describe the unit currently being worked on
+    fn set_message(&self, message: &str);
+    /// Mark the phase as complete
+    fn finish(&self);
+}
+
+/// Reporter that ignores every update
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopProgress;
+
+impl ProgressReporter for NoopProgress {
+    fn set_total(&self, _total: u64) {}
+    fn inc(&self, _delta: u64) {}
+    fn set_message(&self, _message: &str) {}
+    fn finish(&self) {}
+}
+
+impl ProgressReporter for ProgressBar {
+    fn set_total(&self, total: u64) {
+        self.set_length(total);
+    }
+
+    fn inc(&self, delta: u64) {
+        ProgressBar::inc(self, delta);
+    }
+
+    fn set_message(&self, message: &str) {
+        ProgressBar::set_message(self, message.to_string());
+    }
+
+    fn finish(&self) {
+        ProgressBar::finish(self);
+    }
+}
+
+/// Counters shared by the plain status reporters of both phases
+#[derive(Debug)]
+struct PlainState {
+    interval: StatusInterval,
+    scanned: AtomicU64,
+    scan_total: AtomicU64,
+    processed: AtomicU64,
+    process_total: AtomicU64,
+    failed: AtomicU64,
+    /// A phase has its total set and isn't finished yet
+    running: AtomicBool,
+    last_line: Mutex<Instant>,
+    /// Dropped with the state, which stops the timer thread
+    _timer: Option<mpsc::Sender<()>>,
+}
+
+impl PlainState {
+    fn line(&self) -> String {
+        format!(
+            "scanned {}/{}, extracted {}/{}, failed {}",
+            self.scanned.load(Ordering::Relaxed),
+            self.scan_total.load(Ordering::Relaxed),
+            self.processed.load(Ordering::Relaxed),
+            self.process_total.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+        )
+    }
+
+    fn log_line(&self) {
+        *self.last_line.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        info!("{}", self.line());
+    }
+
+    /// Log a status line if `done` PBOs complete the PBO interval
+    fn tick_count(&self, done: u64) {
+        if self.interval.every_pbos.is_some_and(|n| n > 0 && done.is_multiple_of(n)) {
+            self.log_line();
+        }
+    }
+
+    /// Log a status line if a phase is running and the time interval has elapsed
+    ///
+    /// Called by the timer thread, so lines keep coming while a slow PBO holds
+    /// every worker.
+    fn tick_time(&self) {
+        let due = self.last_line.lock().unwrap_or_else(|e| e.into_inner()).elapsed() >= self.interval.every;
+        if due && self.running.load(Ordering::Relaxed) {
+            self.log_line();
+        }
+    }
+
+    /// Call `tick_time` every `interval.every` until `state` is dropped
+    fn start_timer(state: Weak<PlainState>, interval: Duration, stop: mpsc::Receiver<()>) {
+        std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+                match state.upgrade() {
+                    Some(state) => state.tick_time(),
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+/// Reporter logging plain status lines for one phase
+#[derive(Debug, Clone)]
+pub struct PlainStatus {
+    state: Arc<PlainState>,
+    extraction: bool,
+}
+
+impl PlainStatus {
+    /// Listing and extraction reporters sharing one set of counters
+    fn pair(interval: StatusInterval) -> (Self, Self) {
+        let timer = (!interval.every.is_zero()).then(mpsc::channel);
+        let state = Arc::new(PlainState {
+            interval,
+            scanned: AtomicU64::new(0),
+            scan_total: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            process_total: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+            last_line: Mutex::new(Instant::now()),
+            _timer: timer.as_ref().map(|(sender, _)| sender.clone()),
+        });
+        if let Some((_, stop)) = timer {
+            PlainState::start_timer(Arc::downgrade(&state), interval.every, stop);
+        }
+        (
+            Self { state: state.clone(), extraction: false },
+            Self { state, extraction: true },
+        )
+    }
+
+    fn counters(&self) -> (&AtomicU64, &AtomicU64) {
+        if self.extraction {
+            (&self.state.processed, &self.state.process_total)
+        } else {
+            (&self.state.scanned, &self.state.scan_total)
+        }
+    }
+}
+
+impl ProgressReporter for PlainStatus {
+    fn set_total(&self, total: u64) {
+        self.counters().1.store(total, Ordering::Relaxed);
+        self.state.running.store(true, Ordering::Relaxed);
+    }
+
+    fn inc(&self, delta: u64) {
+        let done = self.counters().0.fetch_add(delta, Ordering::Relaxed) + delta;
+        self.state.tick_count(done);
+    }
+
+    fn inc_failed(&self, delta: u64) {
+        self.state.failed.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    fn set_message(&self, _message: &str) {}
+
+    fn finish(&self) {
+        self.state.running.store(false, Ordering::Relaxed);
+        self.state.log_line();
+    }
+}
+
+/// Reporters for the listing and extraction phases of a run
+#[derive(Clone)]
+pub struct ExtractionProgress {
+    pub listing: Arc<dyn ProgressReporter>,
+    pub extraction: Arc<dyn ProgressReporter>,
+}
+
+impl ExtractionProgress {
+    /// Terminal progress bars added to the shared `multi_progress` display
+    pub fn indicatif() -> Self {
+        Self {
+            listing: Arc::new(Self::bar("Listing")),
+            extraction: Arc::new(Self::bar("Extracting")),
+        }
+    }
+
+    /// Status lines logged through `log::info!` at the given interval
+    pub fn plain(interval: StatusInterval) -> Self {
+        let (listing, extraction) = PlainStatus::pair(interval);
+        Self {
+            listing: Arc::new(listing),
+            extraction: Arc::new(extraction),
+        }
+    }
+
+    /// Reporters matching an output mode, resolving `Auto` against stderr
+    pub fn for_mode(mode: OutputMode, interval: StatusInterval) -> Self {
+        match mode.resolve() {
+            OutputMode::Bars => Self::indicatif(),
+            OutputMode::Plain => Self::plain(interval),
+            OutputMode::Auto | OutputMode::Silent => Self::noop(),
+        }
+    }
+
+    /// Reporters that discard every update
+    pub fn noop() -> Self {
+        Self {
+            listing: Arc::new(NoopProgress),
+            extraction: Arc::new(NoopProgress),
+        }
+    }
+
+    fn bar(prefix: &'static str) -> ProgressBar {
+        let style = ProgressStyle::with_template("{prefix:>10} [{bar:40}] {pos}/{len} ({elapsed}) {wide_msg}")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ");
+        multi_progress()
+            .add(ProgressBar::new(0))
+            .with_style(style)
+            .with_prefix(prefix)
+    }
+}
+
+impl Default for ExtractionProgress {
+    fn default() -> Self {
+        Self::noop()
+    }
+}
+
+impl std::fmt::Debug for ExtractionProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtractionProgress").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_status_line() {
+        let (listing, extraction) = PlainStatus::pair(StatusInterval::default());
+        listing.set_total(3);
+        listing.inc(2);
+        listing.inc_failed(1);
+        extraction.set_total(2);
+        extraction.inc(1);
+        assert_eq!(listing.state.line(), "scanned 2/3, extracted 1/2, failed 1");
+    }
+
+    #[test]
+    fn test_plain_status_logs_without_progress() {
+        let interval = StatusInterval { every: Duration::from_millis(10), every_pbos: None };
+        let (_listing, extraction) = PlainStatus::pair(interval);
+        let started = *extraction.state.last_line.lock().unwrap();
+        extraction.set_total(1);
+
+        // The timer logs while the only PBO is still being extracted
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while *extraction.state.last_line.lock().unwrap() == started {
+            assert!(Instant::now() < deadline, "no status line was logged");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_output_mode_resolve() {
+        assert_eq!(OutputMode::Bars.resolve(), OutputMode::Bars);
+        assert_eq!(OutputMode::Plain.resolve(), OutputMode::Plain);
+        assert_eq!(OutputMode::Silent.resolve(), OutputMode::Silent);
+        assert_ne!(OutputMode::Auto.resolve(), OutputMode::Auto);
+    }
+}
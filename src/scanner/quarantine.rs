@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+/// Directory in the cache directory quarantined PBOs are copied to
+pub const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+/// Default largest PBO copied into the quarantine directory: 512 MiB
+pub const DEFAULT_MAX_QUARANTINE_COPY_SIZE: u64 = 512 * 1024 * 1024;
+
+/// When PBOs that keep failing stop being processed
+///
+/// A PBO failing `after_failures` consecutive runs is quarantined: later runs skip
+/// it without listing it, until its hash changes. Quarantine is kept in the
+/// extraction index, which is written whenever a policy is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuarantinePolicy {
+    pub after_failures: u32,
+    /// Copy quarantined PBOs and their failure log into `<cache>/quarantine`
+    pub copy_pbos: bool,
+    /// Larger PBOs are quarantined without a copy
+    pub max_copy_size: u64,
+}
+
+impl Default for QuarantinePolicy {
+    fn default() -> Self {
+        Self {
+            after_failures: 3,
+            copy_pbos: true,
+            max_copy_size: DEFAULT_MAX_QUARANTINE_COPY_SIZE,
+        }
+    }
+}
+
+impl QuarantinePolicy {
+    /// Quarantine after `after_failures` consecutive failed runs, with default copying
+    pub fn after(after_failures: u32) -> Self {
+        Self {
+            after_failures,
+            ..Default::default()
+        }
+    }
+
+    /// Copy a quarantined PBO and its failure log at `failure_log`, if enabled
+    ///
+    /// The copy goes to `<cache_dir>/quarantine/<rel_path>`, the log next to it with
+    /// `.log` appended. Returns the copy relative to `cache_dir`, or `None` when
+    /// copying is off or the PBO is over the size limit.
+    pub fn copy(&self, pbo: &Path, rel_path: &Path, failure_log: &Path, cache_dir: &Path) -> Result<Option<PathBuf>> {
+        if !self.copy_pbos {
+            return Ok(None);
+        }
+        let size = std::fs::metadata(pbo)
+            .with_context(|| format!("Failed to read {}", pbo.display()))?
+            .len();
+        if size > self.max_copy_size {
+            debug!("Not copying {} into quarantine: {} bytes is over the limit", pbo.display(), size);
+            return Ok(None);
+        }
+
+        let copy = Path::new(QUARANTINE_DIR_NAME).join(rel_path);
+        let target = cache_dir.join(&copy);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(pbo, &target)
+            .with_context(|| format!("Failed to copy {} into quarantine", pbo.display()))?;
+        if failure_log.is_file() {
+            std::fs::copy(failure_log, log_path(&target))?;
+        }
+        Ok(Some(copy))
+    }
+}
+
+/// Remove a quarantine copy made by `QuarantinePolicy::copy` and its log
+pub fn remove_copy(cache_dir: &Path, copy: &Path) -> Result<()> {
+    let target = cache_dir.join(copy);
+    for path in [log_path(&target), target] {
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn log_path(copy: &Path) -> PathBuf {
+    let mut path = copy.as_os_str().to_owned();
+    path.push(".log");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_copy() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let pbo = input_dir.path().join("broken.pbo");
+        std::fs::write(&pbo, vec![0u8; 64]).unwrap();
+        let failure_log = cache_dir.path().join("failures/@mod/addons/broken.log");
+        std::fs::create_dir_all(failure_log.parent().unwrap()).unwrap();
+        std::fs::write(&failure_log, "Error: Invalid PBO header\n").unwrap();
+        let rel_path = Path::new("@mod/addons/broken.pbo");
+
+        let policy = QuarantinePolicy::after(2);
+        let copy = policy.copy(&pbo, rel_path, &failure_log, cache_dir.path()).unwrap().unwrap();
+        assert_eq!(copy, Path::new("quarantine/@mod/addons/broken.pbo"));
+        let target = cache_dir.path().join(&copy);
+        assert_eq!(std::fs::read(&target).unwrap().len(), 64);
+        assert!(cache_dir.path().join("quarantine/@mod/addons/broken.pbo.log").is_file());
+        assert!(pbo.is_file());
+
+        remove_copy(cache_dir.path(), &copy).unwrap();
+        assert!(!target.exists());
+        assert!(!cache_dir.path().join("quarantine/@mod/addons/broken.pbo.log").exists());
+
+        let capped = QuarantinePolicy { max_copy_size: 63, ..policy };
+        assert_eq!(capped.copy(&pbo, rel_path, &failure_log, cache_dir.path()).unwrap(), None);
+        let skipped = QuarantinePolicy { copy_pbos: false, ..policy };
+        assert_eq!(skipped.copy(&pbo, rel_path, &failure_log, cache_dir.path()).unwrap(), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::types::{ExtractionReport, PboStatus, SkipReason};
+
+/// Row of the per-PBO CSV written by `ExtractionReport::write_csv`
+///
+/// Columns follow the field order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboCsvRow {
+    /// PBO relative to the input directory
+    pub path: PathBuf,
+    #[serde(rename = "mod")]
+    pub mod_name: Option<String>,
+    pub hash: Option<String>,
+    /// `extracted`, `skipped`, `failed`, `scan_failed`, `quarantined` or `gave_up`
+    pub status: String,
+    pub skip_reason: Option<SkipReason>,
+    pub expected_files: Option<usize>,
+    pub extracted_files: Option<usize>,
+    pub duration_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl PboCsvRow {
+    fn new(path: &Path, status: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+            mod_name: None,
+            hash: None,
+            status: status.to_string(),
+            skip_reason: None,
+            expected_files: None,
+            extracted_files: None,
+            duration_ms: None,
+            error: None,
+        }
+    }
+}
+
+/// Row of the per-mod CSV written by `ExtractionReport::write_mods_csv`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModCsvRow {
+    #[serde(rename = "mod")]
+    pub mod_name: String,
+    /// Display name from the mod's metadata
+    pub name: String,
+    pub version: Option<String>,
+    pub published_id: Option<u64>,
+    pub pbos: usize,
+    pub extracted: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub scan_failed: usize,
+    pub extracted_files: usize,
+}
+
+impl ExtractionReport {
+    /// Rows of the per-PBO CSV
+    ///
+    /// Processed PBOs come first in the order of the report, followed by the ones
+    /// that failed to list, were quarantined, given up on or deferred before listing.
+    pub fn csv_rows(&self) -> Vec<PboCsvRow> {
+        let mut rows: Vec<_> = self.outcomes.iter()
+            .map(|outcome| {
+                let (status, skip_reason, error) = match &outcome.status {
+                    PboStatus::Extracted { .. } => ("extracted", None, None),
+                    PboStatus::Skipped { reason } => ("skipped", Some(*reason), None),
+                    PboStatus::Failed { error } => ("failed", None, Some(error.clone())),
+                };
+                PboCsvRow {
+                    mod_name: outcome.mod_name.clone(),
+                    hash: outcome.hash.clone(),
+                    skip_reason,
+                    expected_files: Some(outcome.expected_files),
+                    extracted_files: Some(outcome.extracted_files),
+                    duration_ms: Some(outcome.duration_ms),
+                    error,
+                    ..PboCsvRow::new(&outcome.path, status)
+                }
+            })
+            .collect();
+
+        for failure in &self.scan_failures {
+            rows.push(PboCsvRow {
+                error: Some(failure.message()),
+                ..PboCsvRow::new(&failure.path, "scan_failed")
+            });
+        }
+        rows.extend(self.quarantined.iter().map(|path| PboCsvRow::new(path, "quarantined")));
+        rows.extend(self.gave_up.iter().map(|path| PboCsvRow::new(path, "gave_up")));
+        // Deferred after listing are among the outcomes already
+        let processed: HashSet<&Path> = self.outcomes.iter().map(|outcome| outcome.path.as_path()).collect();
+        for path in self.deferred.iter().filter(|path| !processed.contains(path.as_path())) {
+            rows.push(PboCsvRow {
+                skip_reason: Some(SkipReason::Deferred),
+                ..PboCsvRow::new(path, "skipped")
+            });
+        }
+        rows
+    }
+
+    /// Rows of the per-mod CSV, sorted by mod
+    pub fn mod_csv_rows(&self) -> Vec<ModCsvRow> {
+        self.mods.iter()
+            .map(|(mod_name, summary)| ModCsvRow {
+                mod_name: mod_name.clone(),
+                name: summary.metadata.name.clone(),
+                version: summary.metadata.version.clone(),
+                published_id: summary.metadata.published_id,
+                pbos: summary.pbos,
+                extracted: summary.extracted,
+                skipped: summary.skipped,
+                failed: summary.failed,
+                scan_failed: summary.scan_failed,
+                extracted_files: summary.extracted_files,
+            })
+            .collect()
+    }
+
+    /// Write one row per PBO to a CSV file at `path`, replacing it atomically
+    pub fn write_csv(&self, path: &Path) -> Result<()> {
+        write_rows(path, self.csv_rows())
+    }
+
+    /// Write one row per mod to a CSV file at `path`, replacing it atomically
+    pub fn write_mods_csv(&self, path: &Path) -> Result<()> {
+        write_rows(path, self.mod_csv_rows())
+    }
+}
+
+fn write_rows<T: Serialize>(path: &Path, rows: Vec<T>) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    let csv = writer.into_inner().context("Failed to write CSV rows")?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    crate::utils::write_replacing(path, csv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::types::{ExtractionStrategy, ModMetadata, ModSummary, PboFailure, PboOutcome};
+    use tempfile::TempDir;
+
+    fn report() -> ExtractionReport {
+        let outcome = |path: &str, status| PboOutcome {
+            path: PathBuf::from(path),
+            status,
+            attempts: Vec::new(),
+            mod_name: Some("@ace".to_string()),
+            hash: Some("a1".to_string()),
+            expected_files: 3,
+            extracted_files: 3,
+            size: 0,
+            listing_ms: 0,
+            duration_ms: 250,
+        };
+        let mut report = ExtractionReport {
+            outcomes: vec![
+                outcome("@ace/addons/ace_medical.pbo", PboStatus::Extracted { strategy: ExtractionStrategy::Filtered }),
+                outcome("@ace/addons/ace_common.pbo", PboStatus::Skipped { reason: SkipReason::AlreadyExtracted }),
+                outcome("@ace/addons/ace, broken.pbo", PboStatus::Failed { error: "Invalid \"PBO\" header".to_string() }),
+            ],
+            scan_failures: vec![PboFailure::new(PathBuf::from("misc/unlisted.pbo"), &anyhow::anyhow!("Listing timed out"))],
+            quarantined: vec![PathBuf::from("misc/quarantined.pbo")],
+            deferred: vec![PathBuf::from("misc/fresh.pbo")],
+            ..Default::default()
+        };
+        report.mods.insert("@ace".to_string(), ModSummary {
+            pbos: 3,
+            extracted: 1,
+            skipped: 1,
+            failed: 1,
+            extracted_files: 3,
+            metadata: ModMetadata {
+                name: "Advanced Combat Environment".to_string(),
+                version: Some("3.18.0".to_string()),
+                published_id: Some(463939057),
+            },
+            ..Default::default()
+        });
+        report
+    }
+
+    #[test]
+    fn test_pbo_csv_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("pbos.csv");
+        report().write_csv(&path).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert!(csv.starts_with(
+            "path,mod,hash,status,skip_reason,expected_files,extracted_files,duration_ms,error\n"
+        ));
+        assert!(csv.contains("\"@ace/addons/ace, broken.pbo\""));
+
+        let rows: Vec<PboCsvRow> = csv::Reader::from_path(&path).unwrap()
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows, report().csv_rows());
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[1].skip_reason, Some(SkipReason::AlreadyExtracted));
+        assert_eq!(rows[2].path, Path::new("@ace/addons/ace, broken.pbo"));
+        assert_eq!(rows[2].error.as_deref(), Some("Invalid \"PBO\" header"));
+        assert_eq!((rows[3].status.as_str(), rows[3].expected_files), ("scan_failed", None));
+        assert_eq!(rows[4].status, "quarantined");
+        assert_eq!(rows[5].skip_reason, Some(SkipReason::Deferred));
+    }
+
+    #[test]
+    fn test_mod_csv_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("reports/mods.csv");
+        report().write_mods_csv(&path).unwrap();
+        // Written again over the first one, without leaving the partial file behind
+        report().write_mods_csv(&path).unwrap();
+        assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        let rows: Vec<ModCsvRow> = csv::Reader::from_path(&path).unwrap()
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name, "Advanced Combat Environment");
+        assert_eq!(rows[0].published_id, Some(463939057));
+        assert_eq!((rows[0].extracted, rows[0].failed, rows[0].scan_failed), (1, 1, 0));
+    }
+}
@@ -0,0 +1,293 @@
+use std::path::Path;
+use std::time::Duration;
+use anyhow::Result;
+use log::{debug, warn};
+
+/// Retry settings for PBO tool invocations that time out
+///
+/// Only errors that look like timeouts are retried; format errors fail immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Additional attempts after the first one
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub backoff_base: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            backoff_base: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that runs every operation exactly once
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            backoff_base: Duration::ZERO,
+        }
+    }
+
+    /// Delay before retry number `retry` (starting at 1)
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.backoff_base.saturating_mul(factor)
+    }
+
+    /// Run `op`, retrying timeouts with exponential backoff
+    ///
+    /// `what` and `path` only describe the operation in log messages and errors.
+    pub fn run<T, F>(&self, what: &str, path: &Path, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let max_attempts = self.max_retries + 1;
+        let mut attempt = 1;
+
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < max_attempts && is_timeout_error(&e) => {
+                    let delay = self.delay(attempt);
+                    warn!(
+                        "{} of {} timed out on attempt {}/{}, retrying in {:?}: {}",
+                        what, path.display(), attempt, max_attempts, delay, e
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                },
+                Err(e) if attempt > 1 => {
+                    return Err(e.context(format!("{} of {} failed after {} attempts", what, path.display(), attempt)));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A run of the PBO tool that didn't finish within its timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Operation timed out after {secs} seconds")]
+pub struct ToolTimeout {
+    /// Timeout of the run in seconds
+    pub secs: u32,
+}
+
+/// Whether an error from the PBO tools is a `ToolTimeout`, or caused by one
+pub fn is_timeout_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<ToolTimeout>())
+}
+
+/// Retry settings for PBOs another process holds open without sharing, like a
+/// launcher or antivirus scanner on Windows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockRetry {
+    /// Additional attempts after the first one
+    pub max_retries: u32,
+    /// Delay before each retry
+    pub delay: Duration,
+}
+
+impl Default for LockRetry {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl LockRetry {
+    /// Settings that give up on the first lock error
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Run `op`, retrying lock errors after a fixed delay
+    ///
+    /// The last error is returned unchanged, so callers can still classify it.
+    pub fn run<T, F>(&self, what: &str, path: &Path, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let max_attempts = self.max_retries + 1;
+        let mut attempt = 1;
+
+        loop {
+            match op() {
+                Err(e) if attempt < max_attempts && is_lock_error(&e) => {
+                    debug!(
+                        "{} of {} failed on a locked file, attempt {}/{}, retrying in {:?}: {}",
+                        what, path.display(), attempt, max_attempts, self.delay, e
+                    );
+                    std::thread::sleep(self.delay);
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error looks like another process holding the file open
+///
+/// Matches Windows sharing and lock violations, both as I/O errors from opening the
+/// file ourselves and in the messages the PBO tools print.
+pub fn is_lock_error(error: &anyhow::Error) -> bool {
+    if error.chain().any(|cause| cause.downcast_ref::<std::io::Error>().is_some_and(is_lock_io_error)) {
+        return true;
+    }
+    let message = format!("{:#}", error).to_lowercase();
+    [
+        "being used by another process",
+        "locked a portion of the file",
+        "sharing violation",
+        "lock violation",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Whether an I/O error is a Windows sharing or lock violation
+///
+/// The codes mean something else elsewhere, like a broken pipe on Unix.
+fn is_lock_io_error(error: &std::io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    cfg!(windows) && matches!(error.raw_os_error(), Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION))
+}
+
+/// Check that a PBO can be opened and read, failing with the I/O error otherwise
+pub fn check_readable(path: &Path) -> Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    std::io::Read::read(&mut file, &mut [0u8; 1])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            backoff_base: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_retries_timeouts_until_success() {
+        let calls = Cell::new(0);
+        let result = policy(2).run("Extraction", Path::new("map.pbo"), || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err(ToolTimeout { secs: 30 }.into())
+            } else {
+                Ok(calls.get())
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_format_errors_are_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<()> = policy(3).run("Extraction", Path::new("map.pbo"), || {
+            calls.set(calls.get() + 1);
+            Err(anyhow::anyhow!("Invalid PBO header"))
+        });
+        assert_eq!(calls.get(), 1);
+        assert_eq!(result.unwrap_err().to_string(), "Invalid PBO header");
+    }
+
+    #[test]
+    fn test_final_error_counts_attempts() {
+        let result: Result<()> = policy(1).run("Listing", Path::new("map.pbo"), || {
+            Err(anyhow::Error::new(ToolTimeout { secs: 10 }).context("Failed to list PBO contents"))
+        });
+        let error = result.unwrap_err();
+        assert_eq!(error.to_string(), "Listing of map.pbo failed after 2 attempts");
+        // The error itself is kept, so it is still a timeout
+        assert!(is_timeout_error(&error));
+        assert_eq!(
+            format!("{:#}", error),
+            "Listing of map.pbo failed after 2 attempts: Failed to list PBO contents: Operation timed out after 10 seconds"
+        );
+    }
+
+    #[test]
+    fn test_timeouts_are_told_by_type() {
+        assert!(is_timeout_error(&ToolTimeout { secs: 30 }.into()));
+        // Whatever a PBO's entries or the tool's output say
+        assert!(!is_timeout_error(&anyhow::anyhow!("Failed to extract data\\timeout_ca.paa")));
+    }
+
+    #[test]
+    fn test_exponential_delay() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            backoff_base: Duration::from_millis(100),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_lock_error_classification() {
+        for message in [
+            "The process cannot access the file because it is being used by another process. (os error 32)",
+            "Extraction failed: The process cannot access the file because another process has locked a portion of the file.",
+            "All extraction attempts failed (filtered): ERROR: Sharing violation opening addons\\ace_medical.pbo",
+            "Lock violation",
+        ] {
+            assert!(is_lock_error(&anyhow::anyhow!("{}", message)), "{}", message);
+        }
+        for message in [
+            "Broken pipe (os error 32)",
+            "Invalid PBO header",
+            "Operation timed out after 30 seconds",
+            "Access is denied. (os error 5)",
+        ] {
+            assert!(!is_lock_error(&anyhow::anyhow!("{}", message)), "{}", message);
+        }
+
+        let sharing_violation = anyhow::Error::new(std::io::Error::from_raw_os_error(32))
+            .context("Failed to hash map.pbo");
+        assert_eq!(is_lock_error(&sharing_violation), cfg!(windows));
+    }
+
+    #[test]
+    fn test_lock_retry_returns_last_lock_error() {
+        let calls = Cell::new(0);
+        let retry = LockRetry {
+            max_retries: 2,
+            delay: Duration::ZERO,
+        };
+        let result: Result<()> = retry.run("Extraction", Path::new("map.pbo"), || {
+            calls.set(calls.get() + 1);
+            Err(anyhow::anyhow!("Sharing violation"))
+        });
+        assert_eq!(calls.get(), 3);
+        assert!(is_lock_error(&result.unwrap_err()));
+
+        calls.set(0);
+        let result: Result<()> = retry.run("Extraction", Path::new("map.pbo"), || {
+            calls.set(calls.get() + 1);
+            match calls.get() {
+                1 => Err(anyhow::anyhow!("being used by another process")),
+                _ => Err(anyhow::anyhow!("Invalid PBO header")),
+            }
+        });
+        assert_eq!(calls.get(), 2);
+        assert_eq!(result.unwrap_err().to_string(), "Invalid PBO header");
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::types::{ExtractionReport, ExtractionStrategy, TimeoutPolicy};
+
+/// Default file name of the run report, in the cache directory
+pub const RUN_REPORT_FILE_NAME: &str = "last-run.json";
+
+/// Version of the run report layout, raised on incompatible changes
+pub const RUN_REPORT_VERSION: u32 = 1;
+
+/// Options a run was started with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunConfig {
+    pub input_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    /// Comma-separated extensions extracted
+    pub extensions: String,
+    pub include_paths: Vec<String>,
+    pub threads: usize,
+    pub list_timeout_secs: u32,
+    pub extract_timeout: TimeoutPolicy,
+    pub fallback_chain: Vec<ExtractionStrategy>,
+}
+
+/// Report of an extraction run as written to `last-run.json`
+///
+/// Also written for runs stopped by cancellation or the failure policy, whose
+/// report has `interrupted` or `aborted` set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunReport {
+    /// `RUN_REPORT_VERSION` of the writer
+    pub version: u32,
+    pub config: RunConfig,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    /// Seconds since the Unix epoch
+    pub finished_at: u64,
+    pub report: ExtractionReport,
+}
+
+impl RunReport {
+    pub fn new(config: RunConfig, started_at: u64, finished_at: u64, report: ExtractionReport) -> Self {
+        Self {
+            version: RUN_REPORT_VERSION,
+            config,
+            started_at,
+            finished_at,
+            report,
+        }
+    }
+
+    /// Read a run report written by an extraction run
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read run report: {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Malformed run report: {}", path.display()))
+    }
+
+    /// Write the report to `path`, replacing it atomically
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write run report: {}", partial.display()))?;
+        std::fs::rename(&partial, path)
+            .with_context(|| format!("Failed to move run report into place: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::types::{PboFailure, PboOutcome, PboStatus, SkipReason};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join(RUN_REPORT_FILE_NAME);
+        let report = ExtractionReport {
+            discovered: 2,
+            extracted: 1,
+            skipped: 1,
+            failures: vec![PboFailure::new(PathBuf::from("broken.pbo"), &anyhow::anyhow!("Invalid PBO header"))],
+            outcomes: vec![
+                PboOutcome {
+                    path: PathBuf::from("ace_medical.pbo"),
+                    status: PboStatus::Extracted { strategy: ExtractionStrategy::Filtered },
+                    attempts: Vec::new(),
+                    mod_name: Some("@ace".to_string()),
+                    hash: Some("a1".to_string()),
+                    expected_files: 12,
+                    extracted_files: 12,
+                    size: 0,
+                    listing_ms: 0,
+                    duration_ms: 1200,
+                },
+                PboOutcome {
+                    path: PathBuf::from("ace_common.pbo"),
+                    status: PboStatus::Skipped { reason: SkipReason::NoMatchingFiles },
+                    attempts: Vec::new(),
+                    mod_name: None,
+                    hash: None,
+                    expected_files: 0,
+                    extracted_files: 0,
+                    size: 0,
+                    listing_ms: 0,
+                    duration_ms: 0,
+                },
+            ],
+            interrupted: true,
+            ..Default::default()
+        };
+        let config = RunConfig {
+            input_dir: PathBuf::from("mods"),
+            cache_dir: PathBuf::from("cache"),
+            extensions: "sqf,hpp".to_string(),
+            include_paths: Vec::new(),
+            threads: 4,
+            list_timeout_secs: 30,
+            extract_timeout: TimeoutPolicy::fixed(30),
+            fallback_chain: ExtractionStrategy::default_chain(),
+        };
+        let run = RunReport::new(config, 1_700_000_000, 1_700_000_042, report);
+        run.write(&path).unwrap();
+        assert!(!path.with_extension("json.partial").exists());
+        assert_eq!(RunReport::load(&path).unwrap(), run);
+
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["version"], RUN_REPORT_VERSION);
+        assert_eq!(json["report"]["outcomes"][0]["status"], "extracted");
+        assert_eq!(json["report"]["outcomes"][0]["strategy"], "filtered");
+        assert_eq!(json["report"]["outcomes"][1]["reason"], "no_matching_files");
+    }
+}
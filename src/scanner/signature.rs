@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use num_bigint::BigUint;
+use sha1::{Digest, Sha1};
+
+use super::types::SignatureStatus;
+use super::utils::VERS_MAGIC;
+
+/// PUBLICKEYBLOB with CALG_RSA_SIGN, as written before every key
+const KEY_BLOB_HEADER: [u8; 8] = [0x06, 0x02, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00];
+
+/// DER prefix of a SHA-1 DigestInfo, as used by PKCS #1 v1.5 signatures
+const SHA1_DIGEST_INFO: [u8; 15] = [
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+
+/// Extensions left out of the file hash of version 2 signatures
+const V2_SKIPPED_EXTENSIONS: [&str; 13] = [
+    "fxy", "jpg", "lip", "ogg", "p3d", "paa", "pac", "png", "rtm", "rvmat", "tga", "wrp", "wss",
+];
+
+/// Extensions making up the file hash of version 3 signatures
+const V3_HASHED_EXTENSIONS: [&str; 11] = [
+    "bikb", "cfg", "ext", "fsm", "h", "hpp", "inc", "sqf", "sqfc", "sqm", "sqs",
+];
+
+/// RSA public key of a signing authority, as stored in a `.bikey` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BiPublicKey {
+    pub authority: String,
+    /// Key length in bits
+    pub bits: u32,
+    pub exponent: u32,
+    modulus: BigUint,
+}
+
+impl BiPublicKey {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut reader = ByteReader::new(&data);
+        Self::parse(&mut reader).with_context(|| format!("Malformed key: {}", path.display()))
+    }
+
+    fn parse(reader: &mut ByteReader) -> Result<Self> {
+        let authority = reader.cstring()?;
+        let length = reader.u32()? as usize;
+        if reader.take(8)? != KEY_BLOB_HEADER || reader.take(4)? != b"RSA1" {
+            anyhow::bail!("Not an RSA public key blob");
+        }
+        let bits = reader.u32()?;
+        let exponent = reader.u32()?;
+        if length != 20 + bits as usize / 8 {
+            anyhow::bail!("Key length {} doesn't match {} bit modulus", length, bits);
+        }
+        let modulus = BigUint::from_bytes_le(reader.take(bits as usize / 8)?);
+        Ok(Self { authority, bits, exponent, modulus })
+    }
+
+    /// Decrypt a signature value with this key, giving the padded hash it signs
+    fn open(&self, signature: &BigUint) -> BigUint {
+        signature.modpow(&BigUint::from(self.exponent), &self.modulus)
+    }
+
+    /// SHA-1 hash padded to the key length the way BI signatures are
+    fn pad(&self, hash: &[u8]) -> BigUint {
+        let size = self.bits as usize / 8;
+        let mut padded = vec![0x00, 0x01];
+        padded.resize(size.saturating_sub(SHA1_DIGEST_INFO.len() + 21), 0xff);
+        padded.push(0x00);
+        padded.extend_from_slice(&SHA1_DIGEST_INFO);
+        padded.extend_from_slice(hash);
+        BigUint::from_bytes_be(&padded)
+    }
+}
+
+/// Signature of a PBO by one authority, as stored in a `.bisign` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BiSignature {
+    /// Key the PBO was signed with
+    pub key: BiPublicKey,
+    /// Signature format, 2 or 3
+    pub version: u32,
+    signatures: [BigUint; 3],
+}
+
+impl BiSignature {
+    pub fn read(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut reader = ByteReader::new(&data);
+        Self::parse(&mut reader).with_context(|| format!("Malformed signature: {}", path.display()))
+    }
+
+    fn parse(reader: &mut ByteReader) -> Result<Self> {
+        let key = BiPublicKey::parse(reader)?;
+        let first = BigUint::from_bytes_le(reader.blob()?);
+        let version = reader.u32()?;
+        let second = BigUint::from_bytes_le(reader.blob()?);
+        let third = BigUint::from_bytes_le(reader.blob()?);
+        Ok(Self { key, version, signatures: [first, second, third] })
+    }
+
+    /// Check this signature against the hashes of a PBO, using the authority's `key`
+    fn verify(&self, key: &BiPublicKey, digest: &PboDigest) -> SignatureStatus {
+        let authority = &self.key.authority;
+        if key.modulus != self.key.modulus {
+            return SignatureStatus::Invalid(format!("{} key doesn't match the one {} was signed with", authority, authority));
+        }
+        let file_hash = match self.version {
+            2 => &digest.file_hashes[0],
+            3 => &digest.file_hashes[1],
+            version => return SignatureStatus::Invalid(format!("Unsupported signature version {}", version)),
+        };
+        if !digest.checksum_matches {
+            return SignatureStatus::Invalid("PBO checksum doesn't match its contents".to_string());
+        }
+
+        let hashes = [
+            ("checksum", digest.checksum.to_vec()),
+            ("file names", digest.hash_with_names(&digest.checksum)),
+            ("file contents", digest.hash_with_names(file_hash)),
+        ];
+        for ((part, hash), signature) in hashes.iter().zip(&self.signatures) {
+            if key.open(signature) != key.pad(hash) {
+                return SignatureStatus::Invalid(format!("Signature of the {} doesn't match", part));
+            }
+        }
+        SignatureStatus::Valid(authority.clone())
+    }
+}
+
+/// Hashes of a PBO covered by its signatures
+struct PboDigest {
+    /// SHA-1 stored at the end of the PBO
+    checksum: [u8; 20],
+    /// Whether the stored SHA-1 is that of the rest of the file
+    checksum_matches: bool,
+    /// Hash of the lowercased names of the non-empty entries, sorted
+    name_hash: [u8; 20],
+    /// Hash of the entry data covered by version 2 and 3 signatures
+    file_hashes: [[u8; 20]; 2],
+    prefix: Option<String>,
+}
+
+impl PboDigest {
+    /// Read a PBO once, hashing everything its signatures need
+    fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut reader = HashingReader { inner: BufReader::new(file), hasher: Sha1::new() };
+
+        let mut entries = Vec::new();
+        let mut prefix = None;
+        loop {
+            let name = reader.cstring()?;
+            let mime = reader.u32()?;
+            let mut fields = [0; 4];
+            for field in &mut fields {
+                *field = reader.u32()?;
+            }
+            let data_size = fields[3];
+            if name.is_empty() && mime == VERS_MAGIC {
+                loop {
+                    let key = reader.cstring()?;
+                    if key.is_empty() {
+                        break;
+                    }
+                    let value = reader.cstring()?;
+                    if key.eq_ignore_ascii_case("prefix") {
+                        prefix = Some(value);
+                    }
+                }
+                continue;
+            }
+            if name.is_empty() {
+                break;
+            }
+            entries.push((name, data_size as u64));
+        }
+
+        let mut file_hashers = [Sha1::new(), Sha1::new()];
+        let mut hashed = [false; 2];
+        let mut buffer = vec![0; 64 * 1024];
+        for (name, size) in &entries {
+            let extension = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+            let include = [
+                !V2_SKIPPED_EXTENSIONS.contains(&extension.as_str()),
+                V3_HASHED_EXTENSIONS.contains(&extension.as_str()),
+            ];
+            let mut remaining = *size;
+            while remaining > 0 {
+                let chunk = remaining.min(buffer.len() as u64) as usize;
+                reader.read_exact(&mut buffer[..chunk])
+                    .with_context(|| format!("{} is truncated in {}", name, path.display()))?;
+                for (hasher, include) in file_hashers.iter_mut().zip(include) {
+                    if include {
+                        hasher.update(&buffer[..chunk]);
+                    }
+                }
+                remaining -= chunk as u64;
+            }
+            for (hashed, include) in hashed.iter_mut().zip(include) {
+                *hashed |= include;
+            }
+        }
+        for ((hasher, hashed), nothing) in file_hashers.iter_mut().zip(hashed).zip([b"nothing", b"gnihton"]) {
+            if !hashed {
+                hasher.update(nothing);
+            }
+        }
+
+        let expected: [u8; 20] = reader.hasher.clone().finalize().into();
+        let mut trailer = [0; 21];
+        reader.inner.read_exact(&mut trailer)
+            .with_context(|| format!("{} has no checksum", path.display()))?;
+        let checksum: [u8; 20] = trailer[1..].try_into()?;
+
+        let mut names: Vec<_> = entries.iter()
+            .filter(|(_, size)| *size > 0)
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        names.sort();
+        let mut name_hasher = Sha1::new();
+        for name in &names {
+            name_hasher.update(name.as_bytes());
+        }
+
+        let [v2, v3] = file_hashers;
+        Ok(Self {
+            checksum,
+            checksum_matches: trailer[0] == 0 && checksum == expected,
+            name_hash: name_hasher.finalize().into(),
+            file_hashes: [v2.finalize().into(), v3.finalize().into()],
+            prefix,
+        })
+    }
+
+    /// Hash of `hash`, the name hash and the prefix with a trailing backslash
+    fn hash_with_names(&self, hash: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha1::new();
+        hasher.update(hash);
+        hasher.update(self.name_hash);
+        if let Some(prefix) = self.prefix.as_deref().filter(|prefix| !prefix.is_empty()) {
+            hasher.update(prefix.as_bytes());
+            if !prefix.ends_with('\\') {
+                hasher.update(b"\\");
+            }
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// Checks the `.bisign` files next to PBOs against the keys of their mods
+///
+/// Keys are read once per key directory and shared between threads.
+#[derive(Debug, Default)]
+pub struct SignatureVerifier {
+    key_dir: Option<PathBuf>,
+    keys: Mutex<HashMap<PathBuf, Arc<Vec<BiPublicKey>>>>,
+}
+
+impl SignatureVerifier {
+    /// Verifier using the keys in `key_dir` for every PBO, or each mod's `keys`
+    /// directory when `None`
+    pub fn new(key_dir: Option<PathBuf>) -> Self {
+        Self {
+            key_dir,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Signature status of the PBO at `pbo_path`
+    ///
+    /// `mod_dir` is the folder of the PBO's mod; without one, the `keys` directory
+    /// next to the PBO's `addons` directory is used. The PBO is valid when any of its
+    /// signatures verifies; otherwise the first failure is reported.
+    pub fn verify(&self, pbo_path: &Path, mod_dir: Option<&Path>) -> SignatureStatus {
+        let signatures = match bisign_files(pbo_path) {
+            Ok(signatures) if signatures.is_empty() => return SignatureStatus::Missing,
+            Ok(signatures) => signatures,
+            Err(e) => return SignatureStatus::Invalid(format!("{:#}", e)),
+        };
+        let digest = match PboDigest::read(pbo_path) {
+            Ok(digest) => digest,
+            Err(e) => return SignatureStatus::Invalid(format!("{:#}", e)),
+        };
+        let key_dir = self.key_dir(pbo_path, mod_dir);
+        let keys = self.keys(&key_dir);
+
+        let mut failure = None;
+        for path in &signatures {
+            let status = match BiSignature::read(path) {
+                Ok(signature) => match keys.iter().find(|key| key.authority.eq_ignore_ascii_case(&signature.key.authority)) {
+                    Some(key) => signature.verify(key, &digest),
+                    None => SignatureStatus::Invalid(format!(
+                        "No key for {} in {}",
+                        signature.key.authority,
+                        key_dir.display()
+                    )),
+                },
+                Err(e) => SignatureStatus::Invalid(format!("{:#}", e)),
+            };
+            debug!("Signature {}: {:?}", path.display(), status);
+            match status {
+                SignatureStatus::Valid(_) => return status,
+                _ => {
+                    failure.get_or_insert(status);
+                },
+            }
+        }
+        failure.unwrap_or(SignatureStatus::Missing)
+    }
+
+    fn key_dir(&self, pbo_path: &Path, mod_dir: Option<&Path>) -> PathBuf {
+        if let Some(key_dir) = &self.key_dir {
+            return key_dir.clone();
+        }
+        let mod_dir = mod_dir
+            .map(Path::to_owned)
+            .or_else(|| pbo_path.parent()?.parent().map(Path::to_owned))
+            .unwrap_or_default();
+        // Mods ship both `keys` and `Keys`
+        std::fs::read_dir(&mod_dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().eq_ignore_ascii_case("keys") && entry.path().is_dir())
+            .map(|entry| entry.path())
+            .unwrap_or_else(|| mod_dir.join("keys"))
+    }
+
+    fn keys(&self, key_dir: &Path) -> Arc<Vec<BiPublicKey>> {
+        let mut cache = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        cache.entry(key_dir.to_owned())
+            .or_insert_with(|| Arc::new(read_keys(key_dir)))
+            .clone()
+    }
+}
+
+/// Keys in the `.bikey` files of a directory; unreadable keys are skipped
+fn read_keys(key_dir: &Path) -> Vec<BiPublicKey> {
+    let mut keys = Vec::new();
+    for entry in std::fs::read_dir(key_dir).into_iter().flatten().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bikey")) {
+            continue;
+        }
+        match BiPublicKey::read(&path) {
+            Ok(key) => keys.push(key),
+            Err(e) => warn!("Skipping key: {:#}", e),
+        }
+    }
+    debug!("Read {} keys from {}", keys.len(), key_dir.display());
+    keys
+}
+
+/// Signature files of a PBO, like `ace_medical.pbo.ace_3.18.2.bisign`, sorted by name
+pub fn bisign_files(pbo_path: &Path) -> Result<Vec<PathBuf>> {
+    let pbo_name = pbo_path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+    let dir = pbo_path.parent().unwrap_or(Path::new("."));
+    let mut signatures = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+        let is_signature = name.strip_prefix(&pbo_name)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|rest| rest.len() > ".bisign".len() && rest.ends_with(".bisign"));
+        if is_signature && entry.path().is_file() {
+            signatures.push(entry.path());
+        }
+    }
+    signatures.sort();
+    Ok(signatures)
+}
+
+/// Cursor over an in-memory key or signature file
+struct ByteReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            anyhow::bail!("Unexpected end of file");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Bytes prefixed by their length
+    fn blob(&mut self) -> Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn cstring(&mut self) -> Result<String> {
+        let end = self.data.iter().position(|&b| b == 0).context("Unterminated string")?;
+        let value = String::from_utf8_lossy(&self.data[..end]).into_owned();
+        self.data = &self.data[end + 1..];
+        Ok(value)
+    }
+}
+
+/// Reader hashing everything read through it, for the PBO checksum
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha1,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: Read> HashingReader<R> {
+    fn u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        self.read_exact(&mut bytes).context("Truncated PBO header")?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn cstring(&mut self) -> Result<String> {
+        let mut bytes = Vec::new();
+        let mut byte = [0];
+        loop {
+            self.read_exact(&mut byte).context("Truncated PBO header")?;
+            if byte[0] == 0 {
+                break;
+            }
+            bytes.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const AUTHORITY: &str = "extraction_test";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    /// Mod folder with the signed fixture PBO in `addons` and its key in `keys`
+    fn signed_mod() -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let addons = temp_dir.path().join("@ace/addons");
+        let keys = temp_dir.path().join("@ace/keys");
+        std::fs::create_dir_all(&addons).unwrap();
+        std::fs::create_dir_all(&keys).unwrap();
+        std::fs::copy(fixture("ace_medical.pbo"), addons.join("ace_medical.pbo")).unwrap();
+        let bisign = format!("ace_medical.pbo.{AUTHORITY}.bisign");
+        std::fs::copy(fixture("signatures").join(&bisign), addons.join(&bisign)).unwrap();
+        let bikey = format!("{AUTHORITY}.bikey");
+        std::fs::copy(fixture("signatures").join(&bikey), keys.join(&bikey)).unwrap();
+        let pbo = addons.join("ace_medical.pbo");
+        (temp_dir, pbo)
+    }
+
+    #[test]
+    fn test_read_key_and_signature() {
+        let key = BiPublicKey::read(&fixture("signatures/extraction_test.bikey")).unwrap();
+        assert_eq!(key.authority, AUTHORITY);
+        assert_eq!((key.bits, key.exponent), (1024, 65537));
+
+        let signature = BiSignature::read(&fixture("signatures/ace_medical.pbo.extraction_test.bisign")).unwrap();
+        assert_eq!(signature.key, key);
+        assert_eq!(signature.version, 3);
+
+        assert!(BiPublicKey::read(&fixture("ace_medical.pbo")).is_err());
+    }
+
+    #[test]
+    fn test_verify_signed_pbo() {
+        let (temp_dir, pbo) = signed_mod();
+        let mod_dir = temp_dir.path().join("@ace");
+        let verifier = SignatureVerifier::new(None);
+        assert_eq!(verifier.verify(&pbo, Some(&mod_dir)), SignatureStatus::Valid(AUTHORITY.to_string()));
+        // Falls back to the keys next to the addons directory
+        assert_eq!(verifier.verify(&pbo, None), SignatureStatus::Valid(AUTHORITY.to_string()));
+
+        let unsigned = pbo.with_file_name("ace_common.pbo");
+        std::fs::copy(&pbo, &unsigned).unwrap();
+        assert_eq!(verifier.verify(&unsigned, Some(&mod_dir)), SignatureStatus::Missing);
+    }
+
+    #[test]
+    fn test_verify_rejects_wrong_key() {
+        let (temp_dir, pbo) = signed_mod();
+        let key_dir = temp_dir.path().join("other_keys");
+        std::fs::create_dir_all(&key_dir).unwrap();
+        let verifier = SignatureVerifier::new(Some(key_dir.clone()));
+        let SignatureStatus::Invalid(reason) = verifier.verify(&pbo, None) else {
+            panic!("signature verified without its key");
+        };
+        assert!(reason.starts_with("No key for extraction_test"), "{}", reason);
+
+        // Another key published under the same authority name
+        let mut other = std::fs::read(fixture("signatures/other.bikey")).unwrap();
+        other.splice(0..5, AUTHORITY.bytes());
+        std::fs::write(key_dir.join("impostor.bikey"), other).unwrap();
+        let verifier = SignatureVerifier::new(Some(key_dir));
+        let SignatureStatus::Invalid(reason) = verifier.verify(&pbo, None) else {
+            panic!("signature verified with the wrong key");
+        };
+        assert!(reason.contains("doesn't match the one"), "{}", reason);
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let (temp_dir, pbo) = signed_mod();
+        let mod_dir = temp_dir.path().join("@ace");
+        let data = std::fs::read(&pbo).unwrap();
+
+        // Changed script contents with the checksum left as it was
+        let offset = data.windows(6).rposition(|window| window == b"params").unwrap();
+        let mut tampered = data.clone();
+        tampered[offset] = b'P';
+        std::fs::write(&pbo, &tampered).unwrap();
+        let verifier = SignatureVerifier::new(None);
+        assert_eq!(
+            verifier.verify(&pbo, Some(&mod_dir)),
+            SignatureStatus::Invalid("PBO checksum doesn't match its contents".to_string())
+        );
+
+        // Changed script contents with the checksum updated to match
+        let body = &tampered[..tampered.len() - 20];
+        let mut resealed = body.to_vec();
+        resealed.extend_from_slice(&Sha1::digest(&body[..body.len() - 1]));
+        std::fs::write(&pbo, &resealed).unwrap();
+        assert_eq!(
+            verifier.verify(&pbo, Some(&mod_dir)),
+            SignatureStatus::Invalid("Signature of the checksum doesn't match".to_string())
+        );
+    }
+
+    #[test]
+    fn test_bisign_files() {
+        let (_temp_dir, pbo) = signed_mod();
+        let dir = pbo.parent().unwrap();
+        std::fs::write(dir.join("ACE_MEDICAL.PBO.other.BISIGN"), b"").unwrap();
+        std::fs::write(dir.join("ace_medical_ai.pbo.other.bisign"), b"").unwrap();
+        std::fs::write(dir.join("ace_medical.pbo.bisign"), b"").unwrap();
+        let names: Vec<_> = bisign_files(&pbo).unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["ACE_MEDICAL.PBO.other.BISIGN", "ace_medical.pbo.extraction_test.bisign"]);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::{debug, trace};
+
+use super::manifest::MANIFEST_FILE_NAME;
+use crate::utils::{self, CHECKSUM_FILE_NAME};
+
+/// Directory below the cache holding the content-addressed objects
+pub const OBJECTS_DIR_NAME: &str = "objects";
+
+/// Content-addressed file store shared by all extracted PBOs of a cache directory
+///
+/// Objects live at `objects/<first 2 hash chars>/<sha256>`. Extracted files are
+/// hard links to their object, or copies where the file system can't link.
+///
+/// Writing into a linked file would change every output sharing its object, so
+/// nothing writes extracted files in place: sidecars and converted files replace
+/// theirs with `utils::write_replacing`, and transformers get a copy of their own
+/// from `utils::unshare_file` first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectStore {
+    root: PathBuf,
+}
+
+/// Files handled by `ObjectStore::store_tree`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Files whose content was new and moved into the store
+    pub stored: usize,
+    /// Files replaced by a link to an existing object
+    pub reused: usize,
+}
+
+impl ObjectStore {
+    /// Store below `cache_dir`; nothing is created until files are stored
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            root: utils::long_path(&cache_dir.join(OBJECTS_DIR_NAME)),
+        }
+    }
+
+    /// Directory holding the objects, `objects` below the cache directory, as an
+    /// extended-length path on Windows
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Location of the object for a SHA-256 hex digest
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+
+    /// Move every file below `dir` into the store and link it back in place
+    ///
+    /// Files whose content is already stored are replaced by a link to the existing
+    /// object. Sidecar files at the top of `dir` are left alone, since they change
+    /// with every extraction.
+    pub fn store_tree(&self, dir: &Path) -> Result<StoreStats> {
+        let mut stats = StoreStats::default();
+
+        for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            let is_sidecar = entry.depth() == 1
+                && (entry.file_name() == MANIFEST_FILE_NAME || entry.file_name() == CHECKSUM_FILE_NAME);
+            if !entry.file_type().is_file() || is_sidecar {
+                continue;
+            }
+
+            let path = entry.path();
+            let object = self.object_path(&utils::sha256_file(path)?);
+            if object.is_file() {
+                trace!("Linking {} to existing object {}", path.display(), object.display());
+                std::fs::remove_file(path)?;
+                stats.reused += 1;
+            } else {
+                std::fs::create_dir_all(object.parent().unwrap_or(&self.root))?;
+                match std::fs::rename(path, &object) {
+                    Ok(()) => stats.stored += 1,
+                    // Another PBO may have stored the same content in the meantime
+                    Err(_) if object.is_file() => {
+                        std::fs::remove_file(path)?;
+                        stats.reused += 1;
+                    },
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to move {} into the object store", path.display()));
+                    },
+                }
+            }
+            utils::link_or_copy(&object, path)
+                .with_context(|| format!("Failed to link {} from the object store", path.display()))?;
+        }
+
+        debug!(
+            "Stored {} new and reused {} existing objects for {}",
+            stats.stored,
+            stats.reused,
+            dir.display()
+        );
+        Ok(stats)
+    }
+
+    /// Remove objects no longer referenced by any extracted file below `cache_dir`
+    ///
+    /// Returns the number of objects removed.
+    pub fn gc(&self, cache_dir: &Path) -> Result<usize> {
+        if !self.root.is_dir() {
+            return Ok(0);
+        }
+
+        let referenced = self.referenced_objects(cache_dir)?;
+        let mut removed = 0;
+        for entry in walkdir::WalkDir::new(&self.root).min_depth(2).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() && !referenced.contains(entry.path()) {
+                trace!("Removing unreferenced object {}", entry.path().display());
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        utils::remove_empty_dirs(&self.root)?;
+
+        debug!("Removed {} unreferenced objects from {}", removed, self.root.display());
+        Ok(removed)
+    }
+
+    /// Objects with a link outside the store; the store's own link is the only one
+    /// for unreferenced objects
+    #[cfg(unix)]
+    fn referenced_objects(&self, _cache_dir: &Path) -> Result<HashSet<PathBuf>> {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut referenced = HashSet::new();
+        for entry in walkdir::WalkDir::new(&self.root).min_depth(2).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() && entry.metadata()?.nlink() > 1 {
+                referenced.insert(entry.path().to_path_buf());
+            }
+        }
+        Ok(referenced)
+    }
+
+    /// Objects whose content matches an extracted file; link counts aren't available
+    /// on stable Rust here, and copies don't have links anyway
+    #[cfg(not(unix))]
+    fn referenced_objects(&self, cache_dir: &Path) -> Result<HashSet<PathBuf>> {
+        let mut referenced = HashSet::new();
+        let walker = walkdir::WalkDir::new(utils::long_path(cache_dir))
+            .into_iter()
+            .filter_entry(|e| e.depth() != 1 || e.file_name() != OBJECTS_DIR_NAME);
+        for entry in walker.filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                referenced.insert(self.object_path(&utils::sha256_file(entry.path())?));
+            }
+        }
+        Ok(referenced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_utils::write_file;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_store_tree_deduplicates() {
+        let cache_dir = TempDir::new().unwrap();
+        let store = ObjectStore::new(cache_dir.path());
+        let first = cache_dir.path().join("ace_medical");
+        let second = cache_dir.path().join("ace_medical_v2");
+        write_file(&first.join("functions/fnc_heal.sqf"), b"heal");
+        write_file(&first.join("functions/script_component.hpp"), b"#include");
+        write_file(&first.join(MANIFEST_FILE_NAME), b"{}");
+        write_file(&second.join("functions/fnc_heal.sqf"), b"heal");
+        write_file(&second.join("functions/fnc_splint.sqf"), b"splint");
+
+        assert_eq!(store.store_tree(&first).unwrap(), StoreStats { stored: 2, reused: 0 });
+        assert_eq!(store.store_tree(&second).unwrap(), StoreStats { stored: 1, reused: 1 });
+
+        let heal = store.object_path(&utils::sha256_file(&first.join("functions/fnc_heal.sqf")).unwrap());
+        assert!(heal.starts_with(store.root()));
+        assert_eq!(std::fs::read(&heal).unwrap(), b"heal");
+        assert_eq!(std::fs::read(second.join("functions/fnc_heal.sqf")).unwrap(), b"heal");
+        // Sidecars stay out of the store
+        assert!(first.join(MANIFEST_FILE_NAME).is_file());
+        assert_eq!(walkdir::WalkDir::new(store.root()).min_depth(2).into_iter().count(), 3);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(std::fs::metadata(&heal).unwrap().nlink(), 3);
+        }
+    }
+
+    #[test]
+    fn test_replaced_files_leave_objects_alone() {
+        let cache_dir = TempDir::new().unwrap();
+        let store = ObjectStore::new(cache_dir.path());
+        let first = cache_dir.path().join("ace_medical");
+        let second = cache_dir.path().join("ace_common");
+        write_file(&first.join("stringtable.json"), b"{}");
+        write_file(&second.join("stringtable.json"), b"{}");
+        store.store_tree(&first).unwrap();
+        store.store_tree(&second).unwrap();
+        let object = store.object_path(&utils::sha256_file(&first.join("stringtable.json")).unwrap());
+
+        utils::write_replacing(&first.join("stringtable.json"), b"{\"keys\": 1}").unwrap();
+        assert_eq!(std::fs::read(first.join("stringtable.json")).unwrap(), b"{\"keys\": 1}");
+        assert_eq!(std::fs::read(second.join("stringtable.json")).unwrap(), b"{}");
+        assert_eq!(std::fs::read(&object).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_objects() {
+        let cache_dir = TempDir::new().unwrap();
+        let store = ObjectStore::new(cache_dir.path());
+        let base_dir = cache_dir.path().join("ace_medical");
+        write_file(&base_dir.join("functions/fnc_heal.sqf"), b"heal");
+        write_file(&base_dir.join("functions/fnc_splint.sqf"), b"splint");
+        store.store_tree(&base_dir).unwrap();
+
+        std::fs::remove_file(base_dir.join("functions/fnc_splint.sqf")).unwrap();
+        assert_eq!(store.gc(cache_dir.path()).unwrap(), 1);
+        assert_eq!(store.gc(cache_dir.path()).unwrap(), 0);
+
+        std::fs::remove_dir_all(&base_dir).unwrap();
+        assert_eq!(store.gc(cache_dir.path()).unwrap(), 1);
+        assert_eq!(std::fs::read_dir(store.root()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_gc_without_store() {
+        let cache_dir = TempDir::new().unwrap();
+        assert_eq!(ObjectStore::new(cache_dir.path()).gc(cache_dir.path()).unwrap(), 0);
+    }
+}
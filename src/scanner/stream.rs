@@ -0,0 +1,78 @@
+use std::path::Path;
+use anyhow::{Context, Result};
+use log::trace;
+
+use crate::utils;
+
+/// Receiver of extracted files that are not kept on disk
+///
+/// Implemented for closures taking the same arguments as `accept`.
+pub trait FileSink: Send + Sync {
+    /// Accept the contents of entry `entry` of the PBO at `pbo` (relative to the
+    /// input directory)
+    ///
+    /// Called from several threads at once for different PBOs. Returning an error
+    /// aborts the extraction of that PBO.
+    fn accept(&self, pbo: &Path, entry: &str, data: &[u8]) -> Result<()>;
+}
+
+impl<F> FileSink for F
+where
+    F: Fn(&Path, &str, &[u8]) -> Result<()> + Send + Sync,
+{
+    fn accept(&self, pbo: &Path, entry: &str, data: &[u8]) -> Result<()> {
+        self(pbo, entry, data)
+    }
+}
+
+/// Hand the given entries below `output_dir` to `sink` in order, stopping at the
+/// first error
+pub fn stream_files(sink: &dyn FileSink, pbo: &Path, output_dir: &Path, entries: &[String]) -> Result<()> {
+    for entry in entries {
+        let path = output_dir.join(utils::native_entry_path(entry)?);
+        let data = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        sink.accept(pbo, entry, &data)
+            .with_context(|| format!("Sink failed on {} from {}", entry, pbo.display()))?;
+        trace!("Streamed {} from {}", entry, pbo.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stream_files_stops_at_sink_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        for name in ["fnc_heal.sqf", "fnc_treat.sqf", "fnc_wake.sqf"] {
+            std::fs::write(root.join("functions").join(name), name).unwrap();
+        }
+        let entries: Vec<_> = ["fnc_heal.sqf", "fnc_treat.sqf", "fnc_wake.sqf"]
+            .iter()
+            .map(|name| format!("functions/{name}"))
+            .collect();
+
+        let received = Mutex::new(Vec::new());
+        let sink = |pbo: &Path, entry: &str, data: &[u8]| -> Result<()> {
+            if entry.ends_with("fnc_treat.sqf") {
+                anyhow::bail!("index full");
+            }
+            received.lock().unwrap().push((pbo.to_owned(), entry.to_string(), data.to_vec()));
+            Ok(())
+        };
+
+        let error = stream_files(&sink, Path::new("ace_medical.pbo"), root, &entries).unwrap_err();
+        assert_eq!(error.to_string(), "Sink failed on functions/fnc_treat.sqf from ace_medical.pbo");
+        assert_eq!(format!("{:#}", error).rsplit(": ").next(), Some("index full"));
+        assert_eq!(received.into_inner().unwrap(), vec![(
+            Path::new("ace_medical.pbo").to_owned(),
+            "functions/fnc_heal.sqf".to_string(),
+            b"fnc_heal.sqf".to_vec(),
+        )]);
+    }
+}
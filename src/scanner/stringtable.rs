@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+
+/// File name of the stringtables converted to JSON
+pub const STRINGTABLE_FILE_NAME: &str = "stringtable.xml";
+
+/// Localized strings of a `stringtable.xml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stringtable {
+    /// Name of the `Project` root element, if any
+    pub project: Option<String>,
+    /// Package names in document order
+    pub packages: Vec<String>,
+    /// Keys in document order
+    pub keys: Vec<StringtableKey>,
+    /// Every language with at least one translation, sorted
+    pub languages: Vec<String>,
+}
+
+/// Single `Key` element of a stringtable
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StringtableKey {
+    pub id: String,
+    /// Enclosing package, if any
+    pub package: Option<String>,
+    /// Enclosing containers, outermost first and joined with `/`
+    pub container: Option<String>,
+    /// Text per language element name, like `English` or `Original`
+    pub translations: BTreeMap<String, String>,
+}
+
+impl Stringtable {
+    /// Parse stringtable XML
+    ///
+    /// `Key` elements may sit directly in the project, in a package, or in any
+    /// number of nested containers. Errors include the line they occurred on.
+    pub fn parse(xml: &str) -> Result<Self> {
+        // Text is kept as is, since spaces around a translation may be meant
+        let mut reader = Reader::from_str(xml);
+
+        let mut stringtable = Stringtable::default();
+        let mut package: Option<String> = None;
+        let mut containers: Vec<String> = Vec::new();
+        let mut key: Option<StringtableKey> = None;
+        let mut language: Option<(String, String)> = None;
+        let mut languages = BTreeSet::new();
+
+        loop {
+            let event = reader.read_event()
+                .map_err(|e| anyhow::anyhow!("line {}: {}", line_at(xml, reader.error_position()), e))?;
+            let line = || line_at(xml, reader.buffer_position());
+            match event {
+                Event::Start(element) => {
+                    let name = element_name(&element);
+                    if key.is_some() {
+                        if language.is_some() {
+                            anyhow::bail!("line {}: unexpected element {} inside a translation", line(), name);
+                        }
+                        language = Some((name, String::new()));
+                        continue;
+                    }
+                    match name.as_str() {
+                        "Project" => stringtable.project = attribute(&element, "name")?,
+                        "Package" => {
+                            let name = attribute(&element, "name")?.unwrap_or_default();
+                            stringtable.packages.push(name.clone());
+                            package = Some(name);
+                        },
+                        "Container" => containers.push(attribute(&element, "name")?.unwrap_or_default()),
+                        "Key" => {
+                            let id = attribute(&element, "ID")?
+                                .with_context(|| format!("line {}: Key without an ID", line()))?;
+                            key = Some(StringtableKey {
+                                id,
+                                package: package.clone(),
+                                container: (!containers.is_empty()).then(|| containers.join("/")),
+                                translations: BTreeMap::new(),
+                            });
+                        },
+                        _ => debug!("Ignoring stringtable element {} on line {}", name, line()),
+                    }
+                },
+                Event::Empty(element) => {
+                    let name = element_name(&element);
+                    if let Some(key) = &mut key {
+                        languages.insert(name.clone());
+                        key.translations.insert(name, String::new());
+                    }
+                },
+                Event::Text(text) => {
+                    if let Some((_, value)) = &mut language {
+                        value.push_str(&text.unescape()
+                            .map_err(|e| anyhow::anyhow!("line {}: {}", line(), e))?);
+                    }
+                },
+                Event::CData(text) => {
+                    if let Some((_, value)) = &mut language {
+                        value.push_str(&String::from_utf8_lossy(&text));
+                    }
+                },
+                Event::End(element) => {
+                    let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+                    if let Some((language_name, value)) = language.take() {
+                        if let Some(key) = &mut key {
+                            languages.insert(language_name.clone());
+                            key.translations.insert(language_name, value);
+                        }
+                        continue;
+                    }
+                    match name.as_str() {
+                        "Key" => stringtable.keys.extend(key.take()),
+                        "Container" => {
+                            containers.pop();
+                        },
+                        "Package" => package = None,
+                        _ => {},
+                    }
+                },
+                Event::Eof => break,
+                _ => {},
+            }
+        }
+
+        if key.is_some() || !containers.is_empty() {
+            anyhow::bail!("line {}: unexpected end of document", line_at(xml, xml.len() as u64));
+        }
+        stringtable.languages = languages.into_iter().collect();
+        Ok(stringtable)
+    }
+
+    /// Languages of the stringtable `key` has no translation for
+    pub fn missing_languages(&self, key: &StringtableKey) -> Vec<&str> {
+        self.languages.iter()
+            .filter(|language| !key.translations.contains_key(*language))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+fn element_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.name().as_ref()).into_owned()
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>> {
+    Ok(match element.try_get_attribute(name)? {
+        Some(attribute) => Some(attribute.unescape_value()?.into_owned()),
+        None => None,
+    })
+}
+
+/// One-based line of a byte offset into `text`
+fn line_at(text: &str, offset: u64) -> usize {
+    let offset = (offset as usize).min(text.len());
+    text.as_bytes()[..offset].iter().filter(|&&byte| byte == b'\n').count() + 1
+}
+
+/// Stringtables found by `convert_stringtables`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StringtableConversion {
+    /// Parsed stringtables, by path relative to the directory
+    pub stringtables: Vec<(String, Stringtable)>,
+    /// Stringtables that failed to parse, as `path: error`
+    pub errors: Vec<String>,
+}
+
+/// Write a `stringtable.json` next to every `stringtable.xml` below `dir`
+///
+/// Existing `stringtable.json` files are never overwritten: the processor only
+/// leaves those the PBO ships, and removes ones converted for an earlier output.
+/// Their stringtables are still parsed and returned. Malformed stringtables are
+/// logged and recorded, not treated as errors.
+pub fn convert_stringtables(dir: &Path) -> Result<StringtableConversion> {
+    let mut conversion = StringtableConversion::default();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() || !entry.file_name().eq_ignore_ascii_case(STRINGTABLE_FILE_NAME) {
+            continue;
+        }
+        let rel_path = entry.path().strip_prefix(dir)?.to_string_lossy().replace('\\', "/");
+        let data = std::fs::read(entry.path())?;
+        // Stringtables saved by some editors start with a byte order mark
+        let xml = String::from_utf8_lossy(data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data)).into_owned();
+
+        match Stringtable::parse(&xml) {
+            Ok(stringtable) => {
+                let json_path = entry.path().with_extension("json");
+                if json_path.exists() {
+                    debug!("Not writing {}: already exists", json_path.display());
+                } else {
+                    crate::utils::write_replacing(&json_path, serde_json::to_string_pretty(&stringtable)?)
+                        .with_context(|| format!("Failed to write {}", json_path.display()))?;
+                    debug!("Converted {} with {} keys", rel_path, stringtable.keys.len());
+                }
+                conversion.stringtables.push((rel_path, stringtable));
+            },
+            Err(e) => {
+                warn!("Malformed stringtable {}: {}", entry.path().display(), e);
+                conversion.errors.push(format!("{}: {}", rel_path, e));
+            },
+        }
+    }
+    Ok(conversion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const NESTED: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<Project name="ACE">
+    <Package name="Medical">
+        <Container name="Actions">
+            <Key ID="STR_ACE_Medical_Heal">
+                <Original>Heal</Original>
+                <English>Heal</English>
+                <German>Heilen</German>
+            </Key>
+            <Container name="Bandages">
+                <Key ID="STR_ACE_Medical_Bandage">
+                    <English>Bandage &amp; wrap</English>
+                    <French><![CDATA[Bander <vite>]]></French>
+                </Key>
+            </Container>
+        </Container>
+        <Key ID="STR_ACE_Medical_Category">
+            <English>Medical</English>
+            <Polish/>
+        </Key>
+    </Package>
+    <Package name="Common">
+        <Key ID="STR_ACE_Common_Yes">
+            <English>Yes</English>
+        </Key>
+    </Package>
+</Project>
+"#;
+
+    #[test]
+    fn test_parse_nested_containers() {
+        let stringtable = Stringtable::parse(NESTED).unwrap();
+        assert_eq!(stringtable.project.as_deref(), Some("ACE"));
+        assert_eq!(stringtable.packages, vec!["Medical".to_string(), "Common".to_string()]);
+        assert_eq!(stringtable.languages, vec!["English", "French", "German", "Original", "Polish"]);
+
+        let ids: Vec<_> = stringtable.keys.iter().map(|key| key.id.as_str()).collect();
+        assert_eq!(ids, vec![
+            "STR_ACE_Medical_Heal",
+            "STR_ACE_Medical_Bandage",
+            "STR_ACE_Medical_Category",
+            "STR_ACE_Common_Yes",
+        ]);
+
+        let bandage = &stringtable.keys[1];
+        assert_eq!(bandage.package.as_deref(), Some("Medical"));
+        assert_eq!(bandage.container.as_deref(), Some("Actions/Bandages"));
+        assert_eq!(bandage.translations["English"], "Bandage & wrap");
+        assert_eq!(bandage.translations["French"], "Bander <vite>");
+        assert_eq!(stringtable.keys[2].container, None);
+        assert_eq!(stringtable.keys[2].translations["Polish"], "");
+    }
+
+    #[test]
+    fn test_missing_languages() {
+        let stringtable = Stringtable::parse(NESTED).unwrap();
+        assert_eq!(stringtable.missing_languages(&stringtable.keys[0]), vec!["French", "Polish"]);
+        assert_eq!(
+            stringtable.missing_languages(&stringtable.keys[3]),
+            vec!["French", "German", "Original", "Polish"]
+        );
+    }
+
+    #[test]
+    fn test_parse_keys_without_package() {
+        let xml = r#"<Project name="Mission"><Key ID="STR_Briefing"><English>Briefing</English></Key></Project>"#;
+        let stringtable = Stringtable::parse(xml).unwrap();
+        assert!(stringtable.packages.is_empty());
+        assert_eq!(stringtable.keys[0].package, None);
+        assert_eq!(stringtable.keys[0].translations["English"], "Briefing");
+    }
+
+    #[test]
+    fn test_parse_keeps_whitespace() {
+        let xml = "<Project><Key ID=\"STR_Gap\"><English> Heal </English><German>\n\tHeilen\n</German></Key></Project>";
+        let stringtable = Stringtable::parse(xml).unwrap();
+        assert_eq!(stringtable.keys[0].translations["English"], " Heal ");
+        assert_eq!(stringtable.keys[0].translations["German"], "\n\tHeilen\n");
+    }
+
+    #[test]
+    fn test_parse_reports_line_of_malformed_xml() {
+        let xml = "<Project>\n<Package name=\"Medical\">\n<Key ID=\"STR_A\">\n<English>Heal</German>\n</Key>\n</Package>\n</Project>\n";
+        let error = Stringtable::parse(xml).unwrap_err().to_string();
+        assert!(error.starts_with("line 4:"), "{error}");
+
+        let error = Stringtable::parse("<Project>\n<Key>\n</Key>\n</Project>").unwrap_err().to_string();
+        assert_eq!(error, "line 2: Key without an ID");
+    }
+
+    #[test]
+    fn test_convert_stringtables() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("addons/medical")).unwrap();
+        std::fs::create_dir_all(root.join("addons/broken")).unwrap();
+        let mut with_bom = b"\xef\xbb\xbf".to_vec();
+        with_bom.extend_from_slice(NESTED.as_bytes());
+        std::fs::write(root.join("addons/medical/stringtable.xml"), with_bom).unwrap();
+        std::fs::write(root.join("addons/broken/Stringtable.xml"), "<Project>\n<Key ID=\"STR_A\">").unwrap();
+
+        let conversion = convert_stringtables(root).unwrap();
+        assert_eq!(conversion.stringtables.len(), 1);
+        assert_eq!(conversion.stringtables[0].0, "addons/medical/stringtable.xml");
+        assert_eq!(conversion.errors.len(), 1);
+        assert!(conversion.errors[0].starts_with("addons/broken/Stringtable.xml: line 2"), "{:?}", conversion.errors);
+
+        let json = std::fs::read_to_string(root.join("addons/medical/stringtable.json")).unwrap();
+        assert_eq!(serde_json::from_str::<Stringtable>(&json).unwrap(), conversion.stringtables[0].1);
+        assert!(!root.join("addons/broken/Stringtable.json").exists());
+
+        // A stringtable.json of the PBO's own is left alone
+        std::fs::write(root.join("addons/medical/stringtable.json"), b"{}").unwrap();
+        let conversion = convert_stringtables(root).unwrap();
+        assert_eq!(conversion.stringtables.len(), 1);
+        assert_eq!(std::fs::read(root.join("addons/medical/stringtable.json")).unwrap(), b"{}");
+    }
+}
@@ -0,0 +1,7 @@
+use std::path::Path;
+
+/// Write `content` to `path` in a test, creating its parent directories
+pub(crate) fn write_file(path: &Path, content: impl AsRef<[u8]>) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
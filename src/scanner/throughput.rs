@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::types::{PboEntry, Throughput};
+use crate::utils::normalize_entry_path;
+
+/// Throughput counted by the workers of a run as PBOs are extracted
+#[derive(Debug, Default)]
+pub struct ThroughputCounters {
+    bytes_written: AtomicU64,
+    files_written: AtomicU64,
+    extraction_us: AtomicU64,
+    verification_us: AtomicU64,
+    extraction_runs: AtomicU64,
+    tool_wait_us: AtomicU64,
+}
+
+impl ThroughputCounters {
+    pub fn add_extraction(&self, elapsed: Duration) {
+        self.extraction_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_verification(&self, elapsed: Duration) {
+        self.verification_us.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Count a run of the tool extracting a PBO
+    pub fn add_extraction_run(&self) {
+        self.extraction_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add time an extraction waited for a free tool slot
+    pub fn add_tool_wait(&self, waited: Duration) {
+        self.tool_wait_us.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Count the output of an extracted PBO
+    pub fn add_written(&self, files: usize, bytes: u64) {
+        self.files_written.fetch_add(files as u64, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Totals so far; listing is timed and counted by the coordinator, waits for the
+    /// tool included, and the wall
+    /// clock timed by `PboProcessor::process_all`, so those are left at 0
+    pub fn snapshot(&self) -> Throughput {
+        Throughput {
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            files_written: self.files_written.load(Ordering::Relaxed) as usize,
+            listing_ms: 0,
+            extraction_ms: self.extraction_us.load(Ordering::Relaxed) / 1000,
+            extraction_wall_ms: 0,
+            verification_ms: self.verification_us.load(Ordering::Relaxed) / 1000,
+            listing_runs: 0,
+            extraction_runs: self.extraction_runs.load(Ordering::Relaxed) as usize,
+            tool_wait_ms: self.tool_wait_us.load(Ordering::Relaxed) / 1000,
+        }
+    }
+}
+
+/// Bytes of the `found` entries in `output_dir`, as returned by verification
+///
+/// Sizes come from the listing; only entries it had no size for are looked up on disk.
+pub fn written_bytes(output_dir: &Path, expected: &[PboEntry], found: &[String]) -> u64 {
+    let sizes: HashMap<_, _> = expected.iter()
+        .map(|entry| (normalize_entry_path(&entry.path), entry.size))
+        .collect();
+    found.iter()
+        .map(|path| match sizes.get(path).copied().flatten() {
+            Some(size) => size,
+            None => std::fs::metadata(output_dir.join(path)).map_or(0, |meta| meta.len()),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_counters_add_up() {
+        let output_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(output_dir.path().join("data")).unwrap();
+        std::fs::write(output_dir.path().join("data/unsized.paa"), vec![0u8; 100]).unwrap();
+        let pbos: Vec<Vec<PboEntry>> = (0..8)
+            .map(|pbo| {
+                let mut entries: Vec<_> = (0..pbo + 1)
+                    .map(|file| PboEntry { path: format!("config{}.cpp", file), size: Some(10) })
+                    .collect();
+                entries.push(PboEntry { path: "data\\unsized.paa".to_string(), size: None });
+                entries
+            })
+            .collect();
+
+        let counters = ThroughputCounters::default();
+        pbos.par_iter().for_each(|expected| {
+            counters.add_extraction(Duration::from_millis(3));
+            counters.add_verification(Duration::from_millis(1));
+            let found: Vec<_> = expected.iter().map(|entry| normalize_entry_path(&entry.path)).collect();
+            counters.add_written(found.len(), written_bytes(output_dir.path(), expected, &found));
+        });
+
+        // Sizes from the listing, and from disk for the file it had none for
+        let throughput = counters.snapshot();
+        assert_eq!(throughput.files_written, 44);
+        assert_eq!(throughput.bytes_written, 36 * 10 + 8 * 100);
+        assert_eq!((throughput.extraction_ms, throughput.verification_ms), (24, 8));
+    }
+}
@@ -0,0 +1,120 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use log::{trace, warn};
+use rayon::prelude::*;
+
+use crate::utils;
+
+/// User hook run on every extracted file before it is moved into the cache
+pub trait FileTransformer: std::fmt::Debug + Send + Sync {
+    /// Transform the file at `path`, extracted from entry `entry` of the PBO at
+    /// `pbo` (relative to the input directory)
+    ///
+    /// The file may be rewritten in place: it shares no hard link with the cache,
+    /// like with earlier outputs or stored objects. Returning `Some(replacement)`
+    /// moves the file at `replacement` over `path` instead, e.g. a converted copy
+    /// written next to it. `path` is inside the staging directory, so only files of
+    /// this PBO can be seen next to it.
+    fn transform(&self, pbo: &Path, entry: &str, path: &Path) -> Result<Option<PathBuf>>;
+}
+
+/// Run `transformer` on the given entries below `output_dir` in parallel
+///
+/// Errors and panics are caught per file, logged and returned as the entry paths
+/// that failed. Their files are kept as the transformer left them, which may be
+/// rewritten in part.
+pub(crate) fn transform_files(
+    transformer: &dyn FileTransformer,
+    pbo: &Path,
+    output_dir: &Path,
+    entries: &[String],
+) -> Vec<String> {
+    let mut failed: Vec<String> = entries
+        .par_iter()
+        .filter_map(|entry| {
+            let result = catch_unwind(AssertUnwindSafe(|| transform_file(transformer, pbo, output_dir, entry)))
+                .unwrap_or_else(|panic| {
+                    let message = panic.downcast_ref::<&str>().map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    Err(anyhow::anyhow!("Transformer panicked: {}", message))
+                });
+            match result {
+                Ok(()) => None,
+                Err(e) => {
+                    warn!("Failed to transform {} from {}: {:#}", entry, pbo.display(), e);
+                    Some(entry.clone())
+                },
+            }
+        })
+        .collect();
+    failed.sort();
+    failed
+}
+
+fn transform_file(transformer: &dyn FileTransformer, pbo: &Path, output_dir: &Path, entry: &str) -> Result<()> {
+    let path = output_dir.join(utils::native_entry_path(entry)?);
+    // Files carried over from the previous output are links to it
+    utils::unshare_file(&path)?;
+    if let Some(replacement) = transformer.transform(pbo, entry, &path)? {
+        if replacement != path {
+            std::fs::rename(&replacement, &path)
+                .with_context(|| format!("Failed to move {} over {}", replacement.display(), path.display()))?;
+        }
+    }
+    trace!("Transformed {} from {}", entry, pbo.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Strips UTF-8 byte order marks, writing through a replacement file for `.hpp`
+    #[derive(Debug)]
+    struct StripBom;
+
+    impl FileTransformer for StripBom {
+        fn transform(&self, _pbo: &Path, entry: &str, path: &Path) -> Result<Option<PathBuf>> {
+            if entry.ends_with("fnc_panic.sqf") {
+                panic!("boom");
+            }
+            if entry.ends_with("fnc_error.sqf") {
+                anyhow::bail!("unsupported encoding");
+            }
+            let data = std::fs::read(path)?;
+            let stripped = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&data);
+            if entry.ends_with(".hpp") {
+                let replacement = path.with_extension("hpp.new");
+                std::fs::write(&replacement, stripped)?;
+                return Ok(Some(replacement));
+            }
+            std::fs::write(path, stripped)?;
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_transform_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        for name in ["fnc_heal.sqf", "fnc_panic.sqf", "fnc_error.sqf", "script_component.hpp"] {
+            std::fs::write(root.join("functions").join(name), b"\xef\xbb\xbf#include").unwrap();
+        }
+        let entries: Vec<_> = ["fnc_heal.sqf", "fnc_panic.sqf", "fnc_error.sqf", "script_component.hpp"]
+            .iter()
+            .map(|name| format!("functions/{name}"))
+            .collect();
+
+        let failed = transform_files(&StripBom, Path::new("ace_medical.pbo"), root, &entries);
+
+        assert_eq!(failed, vec!["functions/fnc_error.sqf".to_string(), "functions/fnc_panic.sqf".to_string()]);
+        assert_eq!(std::fs::read(root.join("functions/fnc_heal.sqf")).unwrap(), b"#include");
+        assert_eq!(std::fs::read(root.join("functions/script_component.hpp")).unwrap(), b"#include");
+        assert!(!root.join("functions/script_component.hpp.new").exists());
+        assert_eq!(std::fs::read(root.join("functions/fnc_error.sqf")).unwrap(), b"\xef\xbb\xbf#include");
+    }
+}
@@ -1,5 +1,12 @@
 #[allow(dead_code)]
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use super::archive::ArchiveStats;
+use super::stringtable::{Stringtable, StringtableConversion};
+
+pub use crate::utils::NameSanitization;
 
 #[derive(Debug)]
 pub struct PboHashResult {
@@ -7,8 +14,1232 @@ pub struct PboHashResult {
     pub hash: String,
 }
 
-#[derive(Debug)]
+/// Entry inside a PBO
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboEntry {
+    /// Path inside the PBO, using forward slashes
+    pub path: String,
+    /// Size in bytes, where the listing provides it
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct PboScanResult {
     pub path: PathBuf,
-    pub expected_files: Vec<String>,
+    /// Entries matching the active filter
+    pub expected_files: Vec<PboEntry>,
+    /// Prefix declared in the PBO header, if any
+    pub prefix: Option<String>,
+    /// Size of the PBO file on disk
+    pub pbo_size: u64,
+    /// Prefix or entry paths from the listing, matching or not, that can't be
+    /// extracted safely
+    pub unsafe_paths: Vec<String>,
+    /// Expected entries renamed by lowercasing or name sanitization, path in the PBO
+    /// to the path written
+    pub renamed: BTreeMap<String, String>,
+    /// Expected entries with a Windows device name in their path, as listed; either
+    /// renamed or left out depending on `ReservedNameHandling`
+    pub reserved_names: Vec<String>,
+    /// Mod folder the PBO belongs to, like `@ACE`
+    pub mod_name: Option<String>,
+    /// Result of checking the PBO's `.bisign` files, when verifying signatures
+    pub signature: Option<SignatureStatus>,
+    /// Why the PBO is skipped without extracting it, when its header already shows
+    /// it can't be one; such PBOs are never listed by the tool
+    pub skip_reason: Option<SkipReason>,
+    /// Time the listing took, retries included
+    pub listing_duration: std::time::Duration,
+    /// Whether the PBO wasn't listed by the tool, since every entry is extracted; its
+    /// expected files are then the ones the extraction writes
+    pub listed_after_extraction: bool,
+}
+
+/// Outcome of checking a PBO's header natively, before invoking the tool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderCheck {
+    /// The header looks like a PBO's; only the tool can tell for sure
+    Plausible,
+    /// The file is empty
+    Empty,
+    /// The file can't be a complete PBO, for the given reason
+    Invalid(String),
+}
+
+/// Entry names and properties declared in a PBO header, read without the tool
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PboHeader {
+    /// Entry names as stored, usually with backslashes
+    pub entries: Vec<String>,
+    /// Header properties like `prefix`, with lowercase keys
+    pub properties: BTreeMap<String, String>,
+}
+
+/// Outcome of checking the `.bisign` files of a PBO against the keys of its mod
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// No `.bisign` file next to the PBO
+    Missing,
+    /// Verified against the key of the given authority
+    Valid(String),
+    /// No signature verified, for the given reason
+    Invalid(String),
+}
+
+impl SignatureStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, Self::Valid(_))
+    }
+}
+
+impl PboScanResult {
+    /// Paths of the expected entries
+    pub fn expected_file_names(&self) -> Vec<&str> {
+        self.expected_files.iter().map(|entry| entry.path.as_str()).collect()
+    }
+}
+
+/// Full listing of a single PBO
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PboContents {
+    /// Prefix declared in the PBO header, if any
+    pub prefix: Option<String>,
+    /// Every entry path inside the PBO, using forward slashes
+    pub entries: Vec<String>,
+    /// Entry sizes in bytes keyed by entry path, where the listing provides them
+    pub entry_sizes: HashMap<String, u64>,
+    /// Header properties reported by the listing, with lowercase keys
+    pub properties: BTreeMap<String, String>,
+}
+
+/// Single file extracted by `extract_file`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractedFile {
+    /// Path of the entry inside the PBO, using forward slashes
+    pub entry: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Contents, when no destination was given
+    pub data: Option<Vec<u8>>,
+    /// File the entry was written to, when a destination was given
+    pub path: Option<PathBuf>,
+}
+
+/// Options controlling how the input directory is walked for PBO files
+#[derive(Debug, Clone)]
+pub struct WalkOptions {
+    /// Follow symbolic links, with loop detection
+    pub follow_symlinks: bool,
+    /// Maximum walk depth, where 1 means only PBOs directly inside the input directory
+    pub max_depth: Option<usize>,
+    /// Directory names or relative globs that are never descended into
+    pub ignore_dirs: Vec<String>,
+    /// Archive file extensions treated as PBOs, matched case-insensitively
+    pub archive_extensions: Vec<String>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: false,
+            max_depth: None,
+            ignore_dirs: Vec::new(),
+            archive_extensions: vec!["pbo".to_string()],
+        }
+    }
+}
+
+impl WalkOptions {
+    /// Check whether a file has one of the configured archive extensions
+    pub fn is_archive(&self, path: &Path) -> bool {
+        path.extension()
+            .map(|ext| {
+                let ext = ext.to_string_lossy();
+                self.archive_extensions.iter()
+                    .any(|archive_ext| archive_ext.trim_start_matches('.').eq_ignore_ascii_case(&ext))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Directories found in most mod folders that rarely contain PBOs worth extracting
+    pub fn default_ignore_dirs() -> Vec<String> {
+        ["keys", "optionals", ".git"].iter().map(|d| d.to_string()).collect()
+    }
+}
+
+/// How the mod a PBO belongs to is found from its path below the input directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModDetection {
+    /// Nearest enclosing directory whose name starts with `@`, like `@ACE` for
+    /// `@ACE/addons/ace_medical.pbo`, or is all digits, like the Workshop item
+    /// folder `463939057` for `!Workshop/463939057/addons/ace_medical.pbo`
+    #[default]
+    AtFolder,
+    /// First directory below the input directory, whatever its name
+    FirstComponent,
+    /// PBOs are not grouped by mod
+    Disabled,
+}
+
+impl ModDetection {
+    /// Mod of the PBO at `rel_path`, relative to the input directory
+    ///
+    /// PBOs directly inside the input directory belong to no mod.
+    pub fn mod_name(&self, rel_path: &Path) -> Option<String> {
+        let root = self.mod_root(rel_path)?;
+        Some(root.file_name()?.to_string_lossy().into_owned())
+    }
+
+    /// Folder of the mod of the PBO at `rel_path`, both relative to the input directory
+    pub fn mod_root(&self, rel_path: &Path) -> Option<PathBuf> {
+        let mut root = PathBuf::new();
+        let mut found = None;
+        for component in rel_path.parent()?.components() {
+            let std::path::Component::Normal(name) = component else {
+                continue;
+            };
+            root.push(name);
+            match self {
+                Self::AtFolder if is_mod_folder_name(&name.to_string_lossy()) => found = Some(root.clone()),
+                Self::FirstComponent => return Some(root),
+                _ => {},
+            }
+        }
+        found
+    }
+}
+
+/// Whether a directory name marks a mod folder for `ModDetection::AtFolder`
+fn is_mod_folder_name(name: &str) -> bool {
+    name.starts_with('@') || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Metadata of a mod from the `mod.cpp` and `meta.cpp` in its folder
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModMetadata {
+    /// Display name from mod.cpp, else meta.cpp, else the folder name
+    pub name: String,
+    /// Version from mod.cpp, when declared
+    pub version: Option<String>,
+    /// Steam Workshop item ID from meta.cpp
+    pub published_id: Option<u64>,
+}
+
+/// Outcome of the top-level PBOs of one mod
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModSummary {
+    /// PBOs found in the mod, including those that could not be listed
+    pub pbos: usize,
+    pub extracted: usize,
+    pub skipped: usize,
+    /// PBOs whose extraction failed
+    pub failed: usize,
+    /// PBOs whose contents could not be listed
+    pub scan_failed: usize,
+    /// Files found in the outputs of the extracted PBOs
+    pub extracted_files: usize,
+    pub metadata: ModMetadata,
+}
+
+/// Per-PBO timeout for tool invocations, scaled by the PBO's size on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeoutPolicy {
+    /// Timeout in seconds for any PBO
+    pub base_secs: u32,
+    /// Extra seconds for every 100 MB of PBO size
+    pub secs_per_100mb: u32,
+    /// Upper bound for the scaled timeout in seconds
+    pub max_secs: u32,
+}
+
+impl TimeoutPolicy {
+    const HUNDRED_MB: u64 = 100 * 1024 * 1024;
+
+    /// The same timeout for every PBO regardless of size
+    pub fn fixed(secs: u32) -> Self {
+        Self {
+            base_secs: secs,
+            secs_per_100mb: 0,
+            max_secs: secs,
+        }
+    }
+
+    /// Timeout in seconds for a PBO of `size` bytes
+    pub fn for_size(&self, size: u64) -> u32 {
+        let extra = size.saturating_mul(self.secs_per_100mb as u64) / Self::HUNDRED_MB;
+        let timeout = (self.base_secs as u64).saturating_add(extra);
+        timeout.min(self.max_secs.max(self.base_secs) as u64) as u32
+    }
+
+    /// Whether a PBO of `size` bytes gets more than the base timeout
+    pub fn is_extended(&self, size: u64) -> bool {
+        self.for_size(size) > self.base_secs
+    }
+}
+
+/// How missing files found by post-extraction verification are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerificationMode {
+    /// Log missing files and keep the extraction as successful
+    #[default]
+    Warn,
+    /// Fail the extraction when any expected file is missing
+    Strict,
+}
+
+/// How entries whose path contains a Windows device name like `aux.sqf` are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservedNameHandling {
+    /// Leave the entries out of the extraction
+    Skip,
+    /// Extract them with an underscore in front of each reserved component
+    #[default]
+    Rename,
+}
+
+/// Order PBOs are handed to the extraction workers in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingOrder {
+    /// Largest PBOs on disk first, so a big one can't start last and keep the run
+    /// going on a single worker while the others sit idle
+    #[default]
+    LargestFirst,
+    /// In the order PBOs were found, so they start the same way every run
+    PathOrder,
+}
+
+/// What happens when two PBOs would extract into the same output directory
+///
+/// This covers `foo.pbo` next to `foo.ebo`, and `foo.pbo` next to a directory `foo/`
+/// containing other PBOs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputCollisionHandling {
+    /// Extract each colliding PBO into `<file name>.d`, like `foo.pbo.d`
+    #[default]
+    Disambiguate,
+    /// Fail the colliding PBOs
+    Fail,
+}
+
+/// How expected files already present in a PBO's output directory are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverwritePolicy {
+    /// Extract every expected file, replacing existing ones
+    #[default]
+    Overwrite,
+    /// Only extract expected files not yet present, keeping existing ones as they are
+    SkipExisting,
+    /// Fail the PBO when any expected file would replace an existing one
+    Error,
+}
+
+/// Expected entries of a PBO split by their state in the output directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExistingEntries {
+    /// Present with the listed size, or without a listed size to compare
+    pub unchanged: Vec<PboEntry>,
+    /// Present with a size different from the listing
+    pub changed: Vec<PboEntry>,
+    /// Not present
+    pub missing: Vec<PboEntry>,
+}
+
+impl ExistingEntries {
+    /// Entries with a file of the same path on disk, regardless of size
+    pub fn present(&self) -> impl Iterator<Item = &PboEntry> {
+        self.unchanged.iter().chain(&self.changed)
+    }
+}
+
+/// Comparison of a PBO's output directory against its expected files
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationResult {
+    /// Expected files present on disk
+    pub found: Vec<String>,
+    /// Expected files not present on disk
+    pub missing: Vec<String>,
+    /// Files on disk that were not expected
+    pub unexpected: Vec<String>,
+}
+
+impl VerificationResult {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Reason a PBO was skipped instead of extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// No entries in the PBO matched the active filter
+    NoMatchingFiles,
+    /// Every expected file is already on disk, when resuming or skipping existing files
+    AlreadyExtracted,
+    /// The PBO's prefix or entry paths would be written outside its output directory
+    InvalidFormat,
+    /// The output quota was reached before the PBO was extracted
+    QuotaExceeded,
+    /// The PBO changed size since it was listed, so it is still being written
+    Deferred,
+    /// Another process kept the PBO open without sharing it, even after retrying
+    Locked,
+    /// The PBO file is empty
+    Empty,
+    /// The run was cancelled before the PBO was started
+    Interrupted,
+    /// The failure policy stopped the run before the PBO was started
+    Aborted,
+}
+
+/// Way of invoking the extraction tool, tried in order as a fallback chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractionStrategy {
+    /// Extract with the extension filter passed to the tool
+    Filtered,
+    /// Extract every file without a tool filter; unmatched files are pruned afterwards
+    Permissive,
+    /// Plain extraction without any options
+    Direct,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Self::NoMatchingFiles => "no matching files",
+            Self::AlreadyExtracted => "unchanged",
+            Self::InvalidFormat => "invalid",
+            Self::QuotaExceeded => "over quota",
+            Self::Deferred => "still being written",
+            Self::Locked => "locked",
+            Self::Empty => "empty",
+            Self::Interrupted => "interrupted",
+            Self::Aborted => "aborted",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl ExtractionStrategy {
+    /// Filtered, then permissive, then direct extraction
+    pub fn default_chain() -> Vec<Self> {
+        vec![Self::Filtered, Self::Permissive, Self::Direct]
+    }
+}
+
+impl std::fmt::Display for ExtractionStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Filtered => "filtered",
+            Self::Permissive => "permissive",
+            Self::Direct => "direct",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One strategy of the fallback chain tried on a PBO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StrategyAttempt {
+    pub strategy: ExtractionStrategy,
+    /// Full error chain of a failed attempt, `None` for the one that succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of processing a single PBO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProcessOutcome {
+    /// Matching files were extracted using `strategy`; `pruned` files outside
+    /// the filter were deleted afterwards
+    Extracted {
+        strategy: ExtractionStrategy,
+        /// Every strategy tried, in order, ending with `strategy`
+        attempts: Vec<StrategyAttempt>,
+        pruned: usize,
+        verification: Box<VerificationResult>,
+        /// Files from earlier extractions no longer in the listing, relative to the
+        /// base directory; deleted unless output sync runs as a dry run
+        stale: Vec<String>,
+        /// Rapified files a config.cpp was written for, and those that failed to convert
+        converted: usize,
+        unconverted: Vec<String>,
+        /// Files the transformer failed on, kept as it left them
+        transform_failed: Vec<String>,
+        /// Stringtables converted to JSON, and stringtables that failed to parse
+        stringtables: Box<StringtableConversion>,
+        /// Textures a PNG was written for, and those skipped as `path: reason`
+        converted_textures: usize,
+        unconverted_textures: Vec<String>,
+        /// Sizes of the archive written instead of an output directory
+        archive: Option<ArchiveStats>,
+        /// The previous output was extracted with a different extension filter
+        filter_changed: bool,
+    },
+    /// Extraction was not attempted
+    Skipped(SkipReason),
+}
+
+/// Result of processing one PBO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PboStatus {
+    Extracted { strategy: ExtractionStrategy },
+    Skipped { reason: SkipReason },
+    Failed { error: String },
+}
+
+/// How processing one PBO went, as listed in the report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboOutcome {
+    /// PBO relative to the input directory
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub status: PboStatus,
+    /// Extraction strategies tried, in order, with the errors of those that failed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<StrategyAttempt>,
+    /// Mod the PBO belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mod_name: Option<String>,
+    /// PBO hash as stored in manifests, unless the PBO couldn't be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Entries matching the filter in the listing
+    #[serde(default)]
+    pub expected_files: usize,
+    /// Files found in the output after extracting
+    #[serde(default)]
+    pub extracted_files: usize,
+    /// Size of the PBO file in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// Time spent listing the PBO, in milliseconds
+    #[serde(default)]
+    pub listing_ms: u64,
+    /// Time spent extracting the PBO, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl PboOutcome {
+    /// Time spent listing and extracting the PBO, in milliseconds
+    pub fn total_ms(&self) -> u64 {
+        self.listing_ms + self.duration_ms
+    }
+}
+
+/// PBO whose listing or extraction failed, with the error's source chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PboFailure {
+    /// PBO relative to the input directory
+    pub path: PathBuf,
+    pub error: String,
+    /// Messages of the errors that caused `error`, outermost first
+    pub causes: Vec<String>,
+}
+
+impl PboFailure {
+    pub fn new(path: PathBuf, error: &anyhow::Error) -> Self {
+        Self {
+            path,
+            error: error.to_string(),
+            causes: error.chain().skip(1).map(|cause| cause.to_string()).collect(),
+        }
+    }
+
+    /// Error message followed by the messages of its causes
+    pub fn message(&self) -> String {
+        std::iter::once(&self.error).chain(&self.causes).cloned().collect::<Vec<_>>().join(": ")
+    }
+}
+
+impl std::fmt::Display for PboFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message())
+    }
+}
+
+/// Virtual file path, prefix included, shipped by more than one PBO
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathConflict {
+    /// Path with forward slashes, as spelled by the first providing PBO
+    pub path: String,
+    /// Providing PBOs, relative to the input directory
+    pub pbos: Vec<PathBuf>,
+}
+
+/// Decisions taken while running, as opposed to the state kept in the index
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunStats {
+    /// PBOs skipped because their output was already complete
+    pub unchanged: usize,
+    /// PBOs processed again after failing in an earlier run, as recorded in the index
+    pub previously_failed: usize,
+    /// PBOs extracted again because their previous output used another extension filter
+    pub filter_changed: usize,
+}
+
+/// Output written by a run and where its time went
+///
+/// Times are summed over the worker threads, so with several threads they add up
+/// to more than the run took.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Throughput {
+    /// Bytes of the expected files found after extracting, sized from the listing
+    pub bytes_written: u64,
+    /// Expected files found after extracting, counted per PBO in `PboOutcome::extracted_files`
+    pub files_written: usize,
+    /// Time spent listing PBOs, failed listings and retries included
+    pub listing_ms: u64,
+    /// Time the PBO tool spent extracting, fallbacks included
+    pub extraction_ms: u64,
+    /// Wall-clock time of the extraction phase, from the first PBO started to the
+    /// last one finished; unlike the other times, not summed over the workers
+    pub extraction_wall_ms: u64,
+    /// Time spent checking the output against the listing
+    pub verification_ms: u64,
+    /// Times the PBO tool ran to list PBOs, retries and fallback listings included;
+    /// one listing gives both the entries and the prefix
+    pub listing_runs: usize,
+    /// Times the PBO tool ran to extract PBOs, retries and fallbacks included
+    pub extraction_runs: usize,
+    /// Time listings and extractions waited for a free slot under the limit on tool
+    /// processes running at once; part of `listing_ms` and `extraction_ms`
+    pub tool_wait_ms: u64,
+}
+
+impl Throughput {
+    pub fn add(&mut self, other: &Throughput) {
+        self.bytes_written += other.bytes_written;
+        self.files_written += other.files_written;
+        self.listing_ms += other.listing_ms;
+        self.extraction_ms += other.extraction_ms;
+        self.extraction_wall_ms += other.extraction_wall_ms;
+        self.verification_ms += other.verification_ms;
+        self.listing_runs += other.listing_runs;
+        self.extraction_runs += other.extraction_runs;
+        self.tool_wait_ms += other.tool_wait_ms;
+    }
+}
+
+/// State of the extraction index after the run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexStats {
+    /// PBOs with indexed files
+    pub sources: usize,
+    pub files: usize,
+    /// PBOs whose last listing or extraction failed
+    pub failed: usize,
+    pub quarantined: usize,
+}
+
+/// Summary of an extraction run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtractionReport {
+    /// PBO files found by the directory walk, including filtered ones
+    pub discovered: usize,
+    /// PBO files excluded by the include/exclude patterns
+    pub filtered_out: usize,
+    /// Directories not descended into because of the depth limit
+    pub depth_limited_dirs: usize,
+    /// PBOs whose contents could not be listed
+    pub scan_failed: usize,
+    /// PBOs whose contents could not be listed, with their errors
+    pub scan_failures: Vec<PboFailure>,
+    /// PBOs extracted successfully
+    pub extracted: usize,
+    /// PBOs whose existing output was recorded as their extraction instead of
+    /// extracting them, when adopting, relative to the input directory
+    pub adopted: Vec<PathBuf>,
+    /// PBOs skipped without extraction
+    pub skipped: usize,
+    /// PBOs whose extraction failed
+    pub failed: usize,
+    /// PBOs whose extraction failed, with their errors. When the failure policy
+    /// aborted the run, these include the failures that did
+    pub failures: Vec<PboFailure>,
+    /// Every PBO that reached the extraction phase, in the order of the listing
+    pub outcomes: Vec<PboOutcome>,
+    /// Whether the failure policy stopped the run; PBOs not started are listed in
+    /// `not_processed`
+    pub aborted: bool,
+    /// PBOs given more than the base extraction timeout, relative to the input directory,
+    /// with the timeout used
+    pub extended_timeouts: Vec<(PathBuf, u32)>,
+    /// PBOs extracted only by a fallback strategy, relative to the input directory
+    pub fallback_extractions: Vec<(PathBuf, ExtractionStrategy)>,
+    /// Extracted files deleted because they didn't pass the filter
+    pub pruned_files: usize,
+    /// Expected files missing after extraction, per PBO relative to the input directory
+    pub missing_files: Vec<(PathBuf, Vec<String>)>,
+    /// Stale files found by output sync, per PBO relative to the input directory
+    pub stale_files: Vec<(PathBuf, Vec<String>)>,
+    /// Leftover staging and partial output directories removed before the run
+    pub cleaned_dirs: usize,
+    /// Listed entries dropped by case normalization because they differ only by case
+    /// from another entry, per PBO relative to the input directory
+    pub case_collisions: Vec<(PathBuf, Vec<String>)>,
+    /// Entries with Windows device names in their path, per PBO relative to the input directory
+    pub reserved_names: Vec<(PathBuf, Vec<String>)>,
+    /// PBOs whose default output directory collided, with the PBOs they collided with,
+    /// relative to the input directory
+    pub output_collisions: Vec<(PathBuf, Vec<PathBuf>)>,
+    /// Prefixes declared by more than one PBO, compared case-insensitively, with the
+    /// declaring PBOs relative to the input directory
+    pub prefix_collisions: Vec<(String, Vec<PathBuf>)>,
+    /// Normalized prefix of every listed PBO declaring one, with the declaring PBOs
+    /// relative to the input directory
+    pub prefixes: BTreeMap<String, Vec<PathBuf>>,
+    /// Outcome per mod of the top-level PBOs, by mod name; PBOs without a mod are
+    /// left out
+    pub mods: BTreeMap<String, ModSummary>,
+    /// Signature status of every listed PBO relative to the input directory, when
+    /// verifying signatures
+    pub signatures: Vec<(PathBuf, SignatureStatus)>,
+    /// Virtual paths provided by more than one PBO, when conflict detection is enabled
+    pub conflicts: Vec<PathConflict>,
+    /// Estimated bytes written by the extractions, from the listed entry sizes
+    pub estimated_size: u64,
+    /// Part of `estimated_size` not replacing existing output files, checked against
+    /// the free space of the cache directory
+    pub required_space: u64,
+    /// Whether the output quota stopped the run before every PBO was extracted
+    pub quota_exceeded: bool,
+    /// PBOs left unextracted because of the output quota, an interruption or the
+    /// failure policy, relative to the input directory
+    pub not_processed: Vec<PathBuf>,
+    /// Whether the run was cancelled, by a `CancelToken` or Ctrl-C, before every PBO
+    /// was listed and extracted
+    pub interrupted: bool,
+    /// PBOs left for a later run because they are still being written, relative to
+    /// the input directory: modified within `min_age`, or changed size after listing.
+    /// The latter also count as skipped
+    pub deferred: Vec<PathBuf>,
+    /// Quarantined PBOs skipped without listing them, relative to the input directory
+    pub quarantined: Vec<PathBuf>,
+    /// PBOs quarantined at the end of this run, relative to the input directory
+    pub newly_quarantined: Vec<PathBuf>,
+    /// PBOs skipped without listing them because they failed `max_attempts` runs in
+    /// a row and haven't changed since, relative to the input directory
+    pub gave_up: Vec<PathBuf>,
+    /// PBOs another process kept locked through every retry, relative to the input
+    /// directory. They count as skipped and are extracted again by the next run
+    pub locked: Vec<PathBuf>,
+    /// PBOs found inside extracted PBOs, when extracting recursively
+    pub nested_discovered: usize,
+    /// Nested PBOs extracted successfully
+    pub nested_extracted: usize,
+    /// Nested PBOs skipped without extraction
+    pub nested_skipped: usize,
+    /// Nested PBOs whose listing or extraction failed
+    pub nested_failed: usize,
+    /// Nested PBOs beyond the nesting depth limit, as `outer.pbo!inner.pbo` paths
+    pub nesting_limited: Vec<PathBuf>,
+    /// Rapified configs converted to config.cpp, when derapifying
+    pub converted_configs: usize,
+    /// Rapified configs that failed to convert, per PBO relative to the input directory
+    pub unconverted_configs: Vec<(PathBuf, Vec<String>)>,
+    /// Files the transformer failed on, per PBO relative to the input directory
+    pub transform_failures: Vec<(PathBuf, Vec<String>)>,
+    /// Parsed stringtables by path inside the PBO, per PBO relative to the input
+    /// directory, when converting stringtables
+    pub stringtables: Vec<(PathBuf, Vec<(String, Stringtable)>)>,
+    /// Malformed stringtables as `path: error`, per PBO relative to the input directory
+    pub stringtable_errors: Vec<(PathBuf, Vec<String>)>,
+    /// PAA textures converted to PNG, when converting textures
+    pub converted_textures: usize,
+    /// Textures left unconverted as `path: reason`, per PBO relative to the input directory
+    pub unconverted_textures: Vec<(PathBuf, Vec<String>)>,
+    /// Total size of the files written into tar outputs
+    pub archived_bytes: u64,
+    /// Total size of the tar outputs on disk, after compression
+    pub archive_bytes: u64,
+    /// Files handed to the sink, when streaming
+    pub streamed_files: usize,
+    /// Extracted files replaced by a hard link to an identical file, when dedupe is enabled
+    pub deduplicated_files: usize,
+    /// Disk space freed by dedupe, in bytes
+    pub dedupe_bytes_saved: u64,
+    /// Files in the index written after the run, when building an index
+    pub indexed_files: usize,
+    /// Wall-clock time of the run, in milliseconds
+    pub elapsed_ms: u64,
+    /// Whether the run was in background mode: on fewer threads, with the workers at
+    /// lowered priority and pausing after each PBO, so slower than it could be
+    pub background: bool,
+    /// Whether the tool ran at lowered priority in background mode too. Only on Linux
+    /// does lowering the workers reach the tool; elsewhere it takes lowering the whole
+    /// process, as the command line tool does, see `lower_process_priority`
+    pub tool_priority_lowered: bool,
+    /// Counted from the decisions taken during this run
+    pub run_stats: RunStats,
+    /// Counted from the extraction index, when the run updated it
+    pub index_stats: Option<IndexStats>,
+    /// PBOs that took longest to list and extract, as many as configured
+    pub slowest: Vec<PboOutcome>,
+    /// Output and time spent per phase, nested PBOs included
+    pub throughput: Throughput,
+}
+
+impl ExtractionReport {
+    /// The `n` PBOs that took longest to list and extract, slowest first
+    pub fn slowest(&self, n: usize) -> Vec<&PboOutcome> {
+        let mut outcomes: Vec<_> = self.outcomes.iter().collect();
+        outcomes.sort_by(|a, b| b.total_ms().cmp(&a.total_ms()).then_with(|| a.path.cmp(&b.path)));
+        outcomes.truncate(n);
+        outcomes
+    }
+
+    /// PBOs processed per minute of the run, 0 before it has taken any time
+    pub fn pbos_per_minute(&self) -> f64 {
+        match self.elapsed_ms {
+            0 => 0.0,
+            elapsed_ms => self.outcomes.len() as f64 * 60_000.0 / elapsed_ms as f64,
+        }
+    }
+}
+
+/// Problems found in the output of an indexed PBO by `ScanCoordinator::verify_cache`,
+/// with paths relative to its base directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheDiscrepancy {
+    /// PBO relative to the input directory
+    pub path: PathBuf,
+    /// Listed or recorded files that are gone
+    pub missing: Vec<String>,
+    /// Files whose size differs from the listing or the manifest
+    pub changed: Vec<String>,
+    /// Files whose content no longer matches the checksums written with them
+    pub checksum_mismatches: Vec<String>,
+    /// Why the output couldn't be checked completely, like an unreadable manifest
+    pub error: Option<String>,
+}
+
+impl CacheDiscrepancy {
+    /// Whether the output passed every check
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.changed.is_empty() && self.checksum_mismatches.is_empty() && self.error.is_none()
+    }
+
+    /// What is wrong, like `2 missing, 1 changed size`
+    pub fn problems(&self) -> String {
+        let mut problems = Vec::new();
+        for (count, label) in [
+            (self.missing.len(), "missing"),
+            (self.changed.len(), "changed size"),
+            (self.checksum_mismatches.len(), "checksum mismatches"),
+        ] {
+            if count > 0 {
+                problems.push(format!("{} {}", count, label));
+            }
+        }
+        if let Some(error) = &self.error {
+            problems.push(error.clone());
+        }
+        problems.join(", ")
+    }
+}
+
+impl std::fmt::Display for CacheDiscrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.problems())
+    }
+}
+
+/// Result of `ScanCoordinator::verify_cache`, with PBOs relative to the input directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheVerification {
+    /// Indexed PBOs whose output was checked
+    pub checked: usize,
+    /// Checked PBOs whose output is broken, now recorded as failed in the index
+    pub broken: Vec<CacheDiscrepancy>,
+    /// Checked PBOs that could not be listed, whose output was only checked against
+    /// its manifest
+    pub scan_failures: Vec<PboFailure>,
+    /// Indexed PBOs no longer in the input directory, left for the next run to drop
+    pub gone: Vec<PathBuf>,
+    /// Run re-extracting the broken PBOs, when asked to fix them
+    pub repair: Option<Box<ExtractionReport>>,
+}
+
+impl std::fmt::Display for CacheVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Verified {} PBOs: {} broken, {} not listed, {} no longer in the input directory",
+            self.checked,
+            self.broken.len(),
+            self.scan_failures.len(),
+            self.gone.len()
+        )?;
+        for discrepancy in &self.broken {
+            write!(f, "\n  {}", discrepancy)?;
+        }
+        if let Some(repair) = &self.repair {
+            write!(f, "\nRepaired {} of {} broken PBOs", repair.extracted, self.broken.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of `ScanCoordinator::adopt_existing`, with PBOs relative to the input directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdoptionReport {
+    /// PBO files found by the directory walk, including filtered ones
+    pub discovered: usize,
+    /// PBOs whose output was complete, now recorded as extracted
+    pub adopted: Vec<PathBuf>,
+    /// PBOs with missing or differently sized output files, left for an extraction run
+    pub pending: Vec<PathBuf>,
+    /// PBOs without matching entries, or rejected while listing
+    pub skipped: Vec<PathBuf>,
+    /// PBOs whose contents could not be listed
+    pub scan_failures: Vec<PboFailure>,
+    /// Files in the index written after adopting
+    pub indexed_files: usize,
+}
+
+impl std::fmt::Display for AdoptionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Adopted {} of {} PBOs, {} pending extraction, {} skipped, {} failed listing",
+            self.adopted.len(),
+            self.discovered,
+            self.pending.len(),
+            self.skipped.len(),
+            self.scan_failures.len()
+        )
+    }
+}
+
+/// Result of `ScanCoordinator::touch_hashes`, with PBOs relative to the input directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TouchReport {
+    /// PBO files found by the directory walk, including filtered ones
+    pub discovered: usize,
+    /// PBOs with complete output whose recorded hash was replaced by the current one
+    pub refreshed: Vec<PathBuf>,
+    /// PBOs with complete output already recorded with their current hash
+    pub current: Vec<PathBuf>,
+    /// PBOs without a complete output of an earlier extraction, left for an extraction run
+    pub pending: Vec<PathBuf>,
+    /// PBOs without matching entries, or rejected while listing
+    pub skipped: Vec<PathBuf>,
+    /// PBOs whose contents could not be listed
+    pub scan_failures: Vec<PboFailure>,
+    /// Files in the index written after refreshing; 0 when nothing was refreshed
+    pub indexed_files: usize,
+}
+
+impl std::fmt::Display for TouchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refreshed the hash of {} of {} PBOs, {} already current, {} pending extraction, {} skipped, {} failed listing",
+            self.refreshed.len(),
+            self.discovered,
+            self.current.len(),
+            self.pending.len(),
+            self.skipped.len(),
+            self.scan_failures.len()
+        )
+    }
+}
+
+/// Compact multi-line summary for people reading the log
+///
+/// The wording may change between versions; tools should read the JSON run report.
+impl std::fmt::Display for ExtractionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elapsed = std::time::Duration::from_millis(self.elapsed_ms);
+        let processed = self.outcomes.len();
+        let status = match (self.interrupted, self.aborted) {
+            (true, _) => " (interrupted)",
+            (_, true) => " (stopped by the failure policy)",
+            _ => "",
+        };
+        writeln!(
+            f,
+            "Processed {} of {} PBOs in {:.1?}{}{}",
+            processed,
+            self.discovered,
+            elapsed,
+            match (self.background, self.tool_priority_lowered) {
+                (false, _) => "",
+                (true, true) => " in background mode",
+                (true, false) => " in background mode, the tool at normal priority",
+            },
+            status
+        )?;
+
+        let extracted_files: usize = self.outcomes.iter().map(|outcome| outcome.extracted_files).sum();
+        writeln!(f, "  Extracted: {} ({} files)", self.extracted, extracted_files)?;
+        if !self.adopted.is_empty() {
+            writeln!(f, "  Adopted: {}", self.adopted.len())?;
+        }
+        let mut skips: BTreeMap<String, usize> = BTreeMap::new();
+        for outcome in &self.outcomes {
+            if let PboStatus::Skipped { reason } = outcome.status {
+                *skips.entry(reason.to_string()).or_default() += 1;
+            }
+        }
+        if !skips.is_empty() {
+            let reasons: Vec<_> = skips.iter().map(|(reason, count)| format!("{} {}", count, reason)).collect();
+            writeln!(f, "  Skipped: {} ({})", self.skipped, reasons.join(", "))?;
+        }
+        if self.failed > 0 || self.scan_failed > 0 {
+            writeln!(f, "  Failed: {} extracting, {} listing", self.failed, self.scan_failed)?;
+        }
+        let left_out = [
+            (self.filtered_out, "filtered out"),
+            (self.deferred.len(), "deferred"),
+            (self.quarantined.len(), "quarantined"),
+            (self.gave_up.len(), "given up"),
+            (self.not_processed.len(), "not processed"),
+        ];
+        let left_out: Vec<_> = left_out.iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{} {}", count, label))
+            .collect();
+        if !left_out.is_empty() {
+            writeln!(f, "  Left out: {}", left_out.join(", "))?;
+        }
+        if !self.newly_quarantined.is_empty() {
+            writeln!(f, "  Newly quarantined: {}", self.newly_quarantined.len())?;
+        }
+
+        let throughput = &self.throughput;
+        writeln!(
+            f,
+            "  Written: {} in {} files",
+            indicatif::HumanBytes(throughput.bytes_written),
+            throughput.files_written
+        )?;
+        write!(
+            f,
+            "  Time: {:.1?} listing, {:.1?} extracting",
+            std::time::Duration::from_millis(throughput.listing_ms),
+            std::time::Duration::from_millis(throughput.extraction_ms)
+        )?;
+        if throughput.extraction_wall_ms > 0 {
+            write!(f, " ({:.1?} wall clock)", std::time::Duration::from_millis(throughput.extraction_wall_ms))?;
+        }
+        write!(f, ", {:.1?} verifying", std::time::Duration::from_millis(throughput.verification_ms))?;
+        let tool_runs = throughput.listing_runs + throughput.extraction_runs;
+        if tool_runs > 0 {
+            write!(f, "\n  Tool runs: {} listing, {} extracting", throughput.listing_runs, throughput.extraction_runs)?;
+            if processed > 0 {
+                write!(f, " ({:.1} per PBO)", tool_runs as f64 / processed as f64)?;
+            }
+            if throughput.tool_wait_ms > 0 {
+                write!(f, ", {:.1?} waiting for a free slot", std::time::Duration::from_millis(throughput.tool_wait_ms))?;
+            }
+        }
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            write!(
+                f,
+                "\n  Throughput: {:.1} PBOs/min, {:.1} files/s, {}/s",
+                self.pbos_per_minute(),
+                throughput.files_written as f64 / secs,
+                indicatif::HumanBytes((throughput.bytes_written as f64 / secs) as u64)
+            )?;
+        }
+        if !self.slowest.is_empty() {
+            write!(f, "\n  Slowest:")?;
+        }
+        for outcome in &self.slowest {
+            let how = match &outcome.status {
+                PboStatus::Extracted { strategy } => format!("{} extraction", strategy),
+                PboStatus::Skipped { reason } => format!("skipped, {}", reason),
+                PboStatus::Failed { .. } => "failed".to_string(),
+            };
+            write!(
+                f,
+                "\n    {:>8.1?}  {} ({}, {}; listing {:.1?})",
+                std::time::Duration::from_millis(outcome.total_ms()),
+                outcome.path.display(),
+                indicatif::HumanBytes(outcome.size),
+                how,
+                std::time::Duration::from_millis(outcome.listing_ms)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mod_detection() {
+        let path = Path::new("@ACE/optionals/@ace_compat_rhs/addons/compat.pbo");
+        assert_eq!(ModDetection::AtFolder.mod_name(path).as_deref(), Some("@ace_compat_rhs"));
+        assert_eq!(ModDetection::FirstComponent.mod_name(path).as_deref(), Some("@ACE"));
+        assert_eq!(ModDetection::Disabled.mod_name(path), None);
+
+        let path = Path::new("workshop/463939057/addons/ace_medical.pbo");
+        assert_eq!(ModDetection::AtFolder.mod_name(path).as_deref(), Some("463939057"));
+        assert_eq!(ModDetection::FirstComponent.mod_name(path).as_deref(), Some("workshop"));
+        assert_eq!(ModDetection::AtFolder.mod_name(Path::new("workshop/v2/addons/ace_medical.pbo")), None);
+
+        assert_eq!(
+            ModDetection::AtFolder.mod_root(Path::new("@ACE/optionals/@ace_compat_rhs/addons/compat.pbo")),
+            Some(PathBuf::from("@ACE/optionals/@ace_compat_rhs"))
+        );
+        assert_eq!(ModDetection::AtFolder.mod_name(Path::new("ace_medical.pbo")), None);
+        assert_eq!(ModDetection::FirstComponent.mod_name(Path::new("ace_medical.pbo")), None);
+    }
+
+    #[test]
+    fn test_report_summary() {
+        let outcome = |path: &str, status, extracted_files| PboOutcome {
+            path: PathBuf::from(path),
+            status,
+            attempts: Vec::new(),
+            mod_name: None,
+            hash: None,
+            expected_files: extracted_files,
+            extracted_files,
+            size: 0,
+            listing_ms: 0,
+            duration_ms: 100,
+        };
+        let report = ExtractionReport {
+            discovered: 5,
+            extracted: 2,
+            skipped: 1,
+            failed: 0,
+            scan_failed: 1,
+            outcomes: vec![
+                outcome("a.pbo", PboStatus::Extracted { strategy: ExtractionStrategy::Filtered }, 30),
+                outcome("b.pbo", PboStatus::Extracted { strategy: ExtractionStrategy::Direct }, 10),
+                outcome("c.pbo", PboStatus::Skipped { reason: SkipReason::AlreadyExtracted }, 0),
+            ],
+            deferred: vec![PathBuf::from("d.pbo")],
+            elapsed_ms: 2000,
+            throughput: Throughput {
+                bytes_written: 4 * 1024 * 1024,
+                files_written: 40,
+                listing_ms: 500,
+                extraction_ms: 1500,
+                extraction_wall_ms: 800,
+                verification_ms: 20,
+                listing_runs: 4,
+                extraction_runs: 3,
+                tool_wait_ms: 1200,
+            },
+            ..Default::default()
+        };
+        assert_eq!(report.to_string(), [
+            "Processed 3 of 5 PBOs in 2.0s",
+            "  Extracted: 2 (40 files)",
+            "  Skipped: 1 (1 unchanged)",
+            "  Failed: 0 extracting, 1 listing",
+            "  Left out: 1 deferred",
+            "  Written: 4.00 MiB in 40 files",
+            "  Time: 500.0ms listing, 1.5s extracting (800.0ms wall clock), 20.0ms verifying",
+            "  Tool runs: 4 listing, 3 extracting (2.3 per PBO), 1.2s waiting for a free slot",
+            "  Throughput: 90.0 PBOs/min, 20.0 files/s, 2.00 MiB/s",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_slowest() {
+        let outcome = |path: &str, status, listing_ms, duration_ms| PboOutcome {
+            path: PathBuf::from(path),
+            status,
+            attempts: Vec::new(),
+            mod_name: None,
+            hash: None,
+            expected_files: 0,
+            extracted_files: 0,
+            size: 2048,
+            listing_ms,
+            duration_ms,
+        };
+        let mut report = ExtractionReport {
+            outcomes: vec![
+                outcome("a.pbo", PboStatus::Extracted { strategy: ExtractionStrategy::Filtered }, 100, 200),
+                outcome("b.pbo", PboStatus::Extracted { strategy: ExtractionStrategy::Direct }, 50, 1200),
+                outcome("c.pbo", PboStatus::Skipped { reason: SkipReason::AlreadyExtracted }, 300, 0),
+                outcome("d.pbo", PboStatus::Failed { error: "Invalid PBO header".to_string() }, 0, 300),
+            ],
+            ..Default::default()
+        };
+        let paths = |n| report.slowest(n).iter().map(|outcome| outcome.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(2), [PathBuf::from("b.pbo"), PathBuf::from("a.pbo")]);
+        // Ties keep path order
+        assert_eq!(paths(10), ["b.pbo", "a.pbo", "c.pbo", "d.pbo"].map(PathBuf::from));
+        assert!(paths(0).is_empty());
+
+        report.slowest = report.slowest(2).into_iter().cloned().collect();
+        assert!(report.to_string().ends_with(&[
+            "  Slowest:",
+            "        1.2s  b.pbo (2.00 KiB, direct extraction; listing 50.0ms)",
+            "     300.0ms  a.pbo (2.00 KiB, filtered extraction; listing 100.0ms)",
+        ].join("\n")));
+    }
+
+    #[test]
+    fn test_timeout_policy_scales_and_clamps() {
+        let policy = TimeoutPolicy {
+            base_secs: 30,
+            secs_per_100mb: 20,
+            max_secs: 120,
+        };
+        let mb = 1024 * 1024;
+        assert_eq!(policy.for_size(0), 30);
+        assert_eq!(policy.for_size(50 * mb), 40);
+        assert_eq!(policy.for_size(300 * mb), 90);
+        assert_eq!(policy.for_size(2000 * mb), 120);
+        assert!(!policy.is_extended(mb));
+        assert!(policy.is_extended(300 * mb));
+    }
+
+    #[test]
+    fn test_fixed_timeout_policy() {
+        let policy = TimeoutPolicy::fixed(30);
+        assert_eq!(policy.for_size(u64::MAX), 30);
+        assert!(!policy.is_extended(u64::MAX));
+    }
+
+    #[test]
+    fn test_is_archive_case_insensitive() {
+        let options = WalkOptions::default();
+        assert!(options.is_archive(Path::new("FOO.PBO")));
+        assert!(options.is_archive(Path::new("foo.Pbo")));
+        assert!(options.is_archive(Path::new("addons/foo.pbo")));
+        assert!(!options.is_archive(Path::new("foo.ebo")));
+        assert!(!options.is_archive(Path::new("pbo")));
+    }
+
+    #[test]
+    fn test_is_archive_configured_extensions() {
+        let options = WalkOptions {
+            archive_extensions: vec!["pbo".to_string(), ".ebo".to_string()],
+            ..Default::default()
+        };
+        assert!(options.is_archive(Path::new("foo.ebo")));
+        assert!(options.is_archive(Path::new("FOO.EBO")));
+        assert!(!options.is_archive(Path::new("foo.ifa")));
+    }
 }
\ No newline at end of file
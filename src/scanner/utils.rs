@@ -1,53 +1,1805 @@
 #[allow(dead_code)]
-use std::path::Path;
-use anyhow::Result;
-use log::{debug, trace};
-use pbo_tools::core::api::{PboApi, PboApiOps};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{debug, trace, warn};
+use pbo_tools::core::api::{ListResult, PboApi, PboApiOps, PboError};
+use pbo_tools::core::config::PboConfig;
 use pbo_tools::extract::ExtractOptions;
 
-use super::types::PboScanResult;
+use super::backend::{check_tool_starts, HiddenTool};
+use super::filter::FileFilter;
+use super::limit::{ConcurrencyLimit, Permit};
+use super::manifest::MANIFEST_FILE_NAME;
+use super::retry::ToolTimeout;
+use super::types::{
+    ExistingEntries,
+    HeaderCheck,
+    ModMetadata,
+    NameSanitization,
+    PathConflict,
+    PboContents,
+    PboEntry,
+    PboHeader,
+    PboScanResult,
+    ReservedNameHandling,
+    SkipReason,
+    VerificationResult,
+};
+use crate::error::ExtractionError;
+use crate::utils::{
+    ensure_within,
+    escape_reserved_names,
+    native_entry_path,
+    normalize_entry_path,
+    sanitize_entry_path,
+    CHECKSUM_FILE_NAME,
+};
 
-/// Scan a PBO file for contents matching the specified extensions
-pub fn scan_pbo_contents(
+thread_local! {
+    /// Backend APIs built on this thread, by timeout in seconds
+    static PBO_APIS: RefCell<HashMap<u32, Rc<PboApi>>> = RefCell::new(HashMap::new());
+    /// Listings this thread ran the tool for
+    static LISTING_RUNS: Cell<usize> = const { Cell::new(0) };
+    /// Microseconds this thread waited for `TOOL_LIMIT`
+    static TOOL_WAIT_US: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Whether the tool runs without console windows, set by `set_hide_console`; by
+/// default when the process has no console of its own
+static HIDE_CONSOLE: LazyLock<AtomicBool> = LazyLock::new(|| AtomicBool::new(!crate::utils::console_attached()));
+
+/// Set once `check_backend` found the tool, so it isn't checked again
+static BACKEND_FOUND: AtomicBool = AtomicBool::new(false);
+
+/// Limit on tool processes running at once, for every run in the process
+///
+/// Set by `set_tool_concurrency`; until then, `default_tool_concurrency` of one
+/// thread per core.
+static TOOL_LIMIT: LazyLock<ConcurrencyLimit> = LazyLock::new(|| {
+    ConcurrencyLimit::new(default_tool_concurrency(std::thread::available_parallelism().map_or(1, |n| n.get())))
+});
+
+/// Tool processes run at once by default with `threads` workers: one per thread,
+/// up to two per core
+pub fn default_tool_concurrency(threads: usize) -> usize {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    threads.clamp(1, 2 * cores)
+}
+
+/// Run the tool at most `limit` times at once, across the process
+///
+/// Keeps high thread counts from starting hundreds of short-lived processes a
+/// second, which can hit process limits and set off antivirus scans on Windows.
+/// Runs over the limit queue until one finishes.
+pub fn set_tool_concurrency(limit: usize) {
+    TOOL_LIMIT.set_limit(limit);
+}
+
+/// Whether tool runs from now on, across the process, keep from opening console
+/// windows
+///
+/// On Windows, a console program started by a process without a console opens a
+/// console window of its own, flashing up for every PBO. Hidden runs start the tool
+/// directly with `CREATE_NO_WINDOW` rather than through the PBO tools. On by default
+/// in processes without a console, such as GUI applications; elsewhere than on
+/// Windows no windows open either way.
+pub fn set_hide_console(hide_console: bool) {
+    HIDE_CONSOLE.store(hide_console, Ordering::Relaxed);
+}
+
+/// Time the current thread waited for a free tool slot so far
+///
+/// Like `listing_runs`, the difference before and after a step is its own wait.
+pub(crate) fn tool_wait() -> Duration {
+    Duration::from_micros(TOOL_WAIT_US.with(Cell::get))
+}
+
+/// Run the tool through the backend API with `timeout` in seconds, once a slot
+/// under the tool limit is free
+///
+/// Every run of the tool goes through here, so none can bypass the limit or open a
+/// console window while they're hidden. The slot is held while `run` runs.
+pub(crate) fn with_tool<T>(timeout: u32, run: impl FnOnce(&dyn PboApiOps) -> T) -> T {
+    let _slot = tool_slot();
+    if cfg!(windows) && HIDE_CONSOLE.load(Ordering::Relaxed) {
+        run(&HiddenTool::new(timeout))
+    } else {
+        run(&*pbo_api(timeout))
+    }
+}
+
+/// Prepare the current thread to run the tool and wait for a free slot under the
+/// tool limit, held until the permit is dropped
+///
+/// For running the tool directly rather than through the backend API, like
+/// `health_check` does; everything else goes through `with_tool`.
+pub(crate) fn tool_slot() -> Permit<'static> {
+    let (permit, waited) = TOOL_LIMIT.acquire_timed();
+    TOOL_WAIT_US.with(|wait| wait.set(wait.get() + waited.as_micros() as u64));
+    permit
+}
+
+/// Times the current thread ran the tool to list a PBO so far
+///
+/// The difference before and after a listing counts the runs it took, whatever
+/// other threads list meanwhile.
+pub(crate) fn listing_runs() -> usize {
+    LISTING_RUNS.with(Cell::get)
+}
+
+fn count_listing_run() {
+    LISTING_RUNS.with(|runs| runs.set(runs.get() + 1));
+}
+
+/// Error of a tool run through `with_tool` with `timeout` in seconds
+///
+/// The PBO tools only tell a timeout by their message, so it becomes a typed
+/// `ToolTimeout` here, for everything after to tell it by type.
+pub(crate) fn tool_error(error: PboError, timeout: u32) -> anyhow::Error {
+    if error.to_string().to_lowercase().contains("timed out") {
+        ToolTimeout { secs: timeout }.into()
+    } else {
+        anyhow::Error::new(error)
+    }
+}
+
+/// Backend API running the tool with `timeout` in seconds
+///
+/// The tool takes its timeout when the API is built, so each thread builds one API
+/// per timeout it lists or extracts with and reuses it for every later PBO, rather
+/// than building one for each call. Only used by `with_tool`.
+fn pbo_api(timeout: u32) -> Rc<PboApi> {
+    PBO_APIS.with(|apis| {
+        apis.borrow_mut()
+            .entry(timeout)
+            .or_insert_with(|| {
+                trace!("Building backend API with a {} second timeout", timeout);
+                Rc::new(PboApi::builder().with_config(PboConfig::default()).with_timeout(timeout).build())
+            })
+            .clone()
+    })
+}
+
+/// Check that the tool can be started, before any PBO waits for it in vain
+///
+/// Starts the tool from PATH: only an error starting it fails the check, see
+/// `check_tool_starts`. Once the tool was found, later checks in the process pass
+/// right away.
+pub fn check_backend() -> Result<(), ExtractionError> {
+    if BACKEND_FOUND.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    check_tool_starts()?;
+    trace!("Found the PBO tools");
+    BACKEND_FOUND.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// List every entry of a single PBO together with its prefix
+///
+/// A full listing is requested since brief listings omit entry sizes and header
+/// properties; if it fails, the default listing used by the processor is tried.
+pub fn list_pbo_contents(path: &Path, timeout: u32) -> Result<PboContents> {
+    list_pbo_contents_with(path, timeout, |list| with_tool(timeout, list))
+}
+
+/// `list_pbo_contents` running each listing through `tool`, which hands the
+/// listing the API to run it with
+fn list_pbo_contents_with(
     path: &Path,
-    extensions: &str,
     timeout: u32,
-) -> Result<PboScanResult> {
-    debug!("Scanning PBO contents: {}", path.display());
-    debug!("Looking for extensions: {}", extensions);
-
-    let api = PboApi::builder()
-        .with_timeout(timeout)
-        .build();
+    tool: impl Fn(&dyn Fn(&dyn PboApiOps) -> Result<ListResult, PboError>) -> Result<ListResult, PboError>,
+) -> Result<PboContents> {
+    debug!("Listing PBO contents: {}", path.display());
 
+    // One full listing gives the entries with their sizes and the prefix together
     let options = ExtractOptions {
         no_pause: true,
         warnings_as_errors: false,
-        brief_listing: true,
+        brief_listing: false,
         ..Default::default()
     };
 
-    let result = api.list_with_options(path, options)?;
-    let mut matching_files = Vec::new();
-
-    debug!("Files in PBO:");
-    for file in result.get_file_list() {
-        trace!("  {}", file);
-        let path = Path::new(&file);
-        // Check if file matches extension filter
-        if path.extension()
-            .map(|ext| extensions.contains(&ext.to_string_lossy().to_string()))
-            .unwrap_or(false)
-        {
-            trace!("    -> Matches extension filter");
-            matching_files.push(file.to_string());
+    count_listing_run();
+    let result = match tool(&|api| api.list_with_options(path, options.clone())) {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("Listing with options failed for {}, retrying default listing: {}", path.display(), e);
+            count_listing_run();
+            tool(&|api| api.list_contents(path))
+                .map_err(|e| tool_error(e, timeout).context("Failed to list PBO contents"))?
         }
+    };
+
+    let files = result.get_file_list();
+    let entry_sizes = parse_entry_sizes(&result.stdout, &files)
+        .into_iter()
+        .map(|(name, size)| (normalize_entry_path(&name), size))
+        .collect();
+
+    Ok(PboContents {
+        prefix: result.get_prefix().filter(|prefix| !prefix.is_empty()),
+        entries: files.iter().map(|file| normalize_entry_path(file)).collect(),
+        entry_sizes,
+        properties: parse_listing_properties(&result.stdout, &files),
+    })
+}
+
+/// Scan a PBO file for contents matching the specified filter
+pub fn scan_pbo_contents(
+    path: &Path,
+    filter: &FileFilter,
+    timeout: u32,
+) -> Result<PboScanResult> {
+    scan_pbo_with(path, filter, || list_pbo_contents(path, timeout))
+}
+
+/// `scan_pbo_contents` taking the listing from `list`, such as a cached one
+pub(crate) fn scan_pbo_with(
+    path: &Path,
+    filter: &FileFilter,
+    list: impl FnOnce() -> Result<PboContents>,
+) -> Result<PboScanResult> {
+    debug!("Scanning PBO contents: {}", path.display());
+    debug!("Looking for extensions: {}", filter.extensions);
+    if filter.has_path_filters() {
+        debug!("Restricting to paths: {:?}", filter.include_paths);
+    }
+    if filter.has_size_limits() {
+        debug!("Restricting to sizes: {:?}..={:?}", filter.min_file_size, filter.max_file_size);
     }
 
+    let pbo_size = std::fs::metadata(path)?.len();
+    // Broken files would otherwise wait for the tool's timeout before failing
+    let skip_reason = header_skip_reason(path, check_pbo_header(path));
+    if skip_reason.is_some() {
+        return Ok(PboScanResult {
+            path: path.to_owned(),
+            pbo_size,
+            skip_reason,
+            ..Default::default()
+        });
+    }
+
+    let contents = list()?;
+    let matching_files = filter_entries(&contents.entries, &contents.entry_sizes, filter);
+
     debug!("Found {} matching files", matching_files.len());
 
+    // Fallback strategies extract every entry, so all of them have to be safe
+    let unsafe_paths: Vec<_> = contents.prefix.iter()
+        .chain(&contents.entries)
+        .filter(|entry| native_entry_path(entry).is_err())
+        .cloned()
+        .collect();
+    if !unsafe_paths.is_empty() {
+        debug!("Unsafe paths in {}: {:?}", path.display(), unsafe_paths);
+    }
+
     Ok(PboScanResult {
         path: path.to_owned(),
         expected_files: matching_files,
+        prefix: contents.prefix,
+        pbo_size,
+        unsafe_paths,
+        renamed: BTreeMap::new(),
+        reserved_names: Vec::new(),
+        mod_name: None,
+        signature: None,
+        skip_reason: None,
+        listing_duration: std::time::Duration::ZERO,
+        listed_after_extraction: false,
     })
-}
\ No newline at end of file
+}
+
+/// Scan a PBO from its header alone, for filters matching every entry
+///
+/// The tool isn't run: the prefix is read from the header and the expected files
+/// are left to the extraction. Returns `None` when the header names entries that
+/// have to be handled before extracting them, being unsafe, reserved, differing
+/// only by case or renamed by `sanitization`; those PBOs are listed by the tool.
+pub(crate) fn scan_pbo_header(path: &Path, sanitization: NameSanitization) -> Result<Option<PboScanResult>> {
+    let pbo_size = std::fs::metadata(path)?.len();
+    let (check, header) = match read_pbo_header(path) {
+        Ok((check, header)) => (Ok(check), Some(header)),
+        Err(e) => (Err(e), None),
+    };
+    let skip_reason = header_skip_reason(path, check);
+    if skip_reason.is_some() {
+        return Ok(Some(PboScanResult {
+            path: path.to_owned(),
+            pbo_size,
+            skip_reason,
+            ..Default::default()
+        }));
+    }
+    let Some(header) = header else {
+        return Ok(None);
+    };
+
+    let prefix = header.properties.get("prefix").filter(|prefix| !prefix.is_empty()).cloned();
+    let mut seen = HashSet::new();
+    let unlisted = prefix.iter().chain(&header.entries).find(|name| {
+        let normalized = normalize_entry_path(name);
+        native_entry_path(name).is_err()
+            || escape_reserved_names(name) != normalized
+            || !sanitize_entry_path(name, sanitization).is_ok_and(|sanitized| sanitized == normalized)
+    });
+    let unlisted = unlisted.or_else(|| header.entries.iter().find(|name| !seen.insert(normalize_entry_path(name).to_lowercase())));
+    if let Some(name) = unlisted {
+        debug!("Listing {}, its entry {} needs handling before extraction", path.display(), name);
+        return Ok(None);
+    }
+
+    Ok(Some(PboScanResult {
+        path: path.to_owned(),
+        prefix,
+        pbo_size,
+        listed_after_extraction: true,
+        ..Default::default()
+    }))
+}
+
+/// Why a PBO is skipped without listing it, given the result of checking its header
+fn header_skip_reason(path: &Path, check: Result<HeaderCheck>) -> Option<SkipReason> {
+    match check {
+        Ok(HeaderCheck::Plausible) => None,
+        Ok(HeaderCheck::Empty) => Some(SkipReason::Empty),
+        Ok(HeaderCheck::Invalid(reason)) => {
+            warn!("Not a valid PBO, skipping {}: {}", path.display(), reason);
+            Some(SkipReason::InvalidFormat)
+        },
+        Err(e) => {
+            debug!("Header check failed for {}, listing it anyway: {}", path.display(), e);
+            None
+        },
+    }
+}
+
+/// Magic of the header entry holding the PBO's header extensions, "sreV"
+pub(crate) const VERS_MAGIC: u32 = 0x5665_7273;
+/// Longest entry name accepted by the header check
+const MAX_HEADER_STRING: usize = 1024;
+
+/// Check that a file could be a PBO without invoking the tool
+///
+/// Walks the header entries and compares the data sizes they declare with the file
+/// size. Only files that can't be complete PBOs are reported as invalid: empty
+/// files, headers running past the end of the file or containing binary names, and
+/// data cut short. The trailing checksum is optional, so files merely missing it
+/// pass, as does anything the tool may still make sense of.
+pub fn check_pbo_header(path: &Path) -> Result<HeaderCheck> {
+    read_pbo_header(path).map(|(check, _)| check)
+}
+
+/// Check a PBO's header like `check_pbo_header`, also returning what it declares
+///
+/// The header is only complete for plausible PBOs.
+pub fn read_pbo_header(path: &Path) -> Result<(HeaderCheck, PboHeader)> {
+    let mut header = PboHeader::default();
+    let file_size = std::fs::metadata(path)?.len();
+    if file_size == 0 {
+        return Ok((HeaderCheck::Empty, header));
+    }
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+    let mut header_size = 0u64;
+    let mut data_size = 0u64;
+    loop {
+        let name = match read_header_string(&mut reader, &mut header_size, MAX_HEADER_STRING)? {
+            HeaderString::Text(name) => name,
+            HeaderString::End => {
+                let reason = format!("header runs past the end of the file after {} bytes", header_size);
+                return Ok((HeaderCheck::Invalid(reason), header));
+            },
+            HeaderString::Malformed => {
+                let reason = format!("malformed header entry name before byte {}", header_size);
+                return Ok((HeaderCheck::Invalid(reason), header));
+            },
+        };
+        let mut fields = [0u8; 20];
+        if !read_exact_or_eof(&mut reader, &mut fields)? {
+            let reason = format!("header runs past the end of the file after {} bytes", header_size);
+            return Ok((HeaderCheck::Invalid(reason), header));
+        }
+        header_size += fields.len() as u64;
+        let field = |index: usize| u32::from_le_bytes(fields[index * 4..index * 4 + 4].try_into().unwrap());
+
+        if name.is_empty() && field(0) == VERS_MAGIC {
+            // Property names and values up to an empty name; their contents are free-form,
+            // and read up to their terminator however long
+            loop {
+                let key = read_header_string(&mut reader, &mut header_size, usize::MAX)?;
+                if matches!(key, HeaderString::Text(ref key) if key.is_empty()) {
+                    break;
+                }
+                let value = read_header_string(&mut reader, &mut header_size, usize::MAX)?;
+                match (key, value) {
+                    (HeaderString::End, _) | (_, HeaderString::End) => {
+                        let reason = "header properties run past the end of the file".to_string();
+                        return Ok((HeaderCheck::Invalid(reason), header));
+                    },
+                    (HeaderString::Text(key), HeaderString::Text(value)) => {
+                        let key = String::from_utf8_lossy(&key).to_lowercase();
+                        header.properties.insert(key, String::from_utf8_lossy(&value).into_owned());
+                    },
+                    _ => {},
+                }
+            }
+        } else if name.is_empty() {
+            break;
+        } else {
+            header.entries.push(String::from_utf8_lossy(&name).into_owned());
+            data_size += u64::from(field(4));
+        }
+    }
+
+    let required = header_size + data_size;
+    if required > file_size {
+        let reason = format!("truncated: header and data take {} bytes, the file has {}", required, file_size);
+        return Ok((HeaderCheck::Invalid(reason), header));
+    }
+    Ok((HeaderCheck::Plausible, header))
+}
+
+/// NUL-terminated string in a PBO header
+enum HeaderString {
+    Text(Vec<u8>),
+    /// The file ended before the terminator
+    End,
+    /// Control characters, or no terminator within the length limit
+    Malformed,
+}
+
+fn read_header_string(reader: &mut impl BufRead, offset: &mut u64, max_len: usize) -> Result<HeaderString> {
+    let mut text = Vec::new();
+    let read = reader.take((max_len as u64).saturating_add(1)).read_until(0, &mut text)?;
+    *offset += read as u64;
+    let terminated = text.last() == Some(&0);
+    if terminated {
+        text.pop();
+    }
+    if text.len() > max_len || text.iter().any(|byte| *byte < 0x20) {
+        Ok(HeaderString::Malformed)
+    } else if !terminated {
+        Ok(HeaderString::End)
+    } else {
+        Ok(HeaderString::Text(text))
+    }
+}
+
+/// Fill `buf`, returning false when the file ends first
+fn read_exact_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Lowercase the prefix and expected entry paths of a scan result
+///
+/// Lowercased entries are recorded in `renamed`, so the tool is still given their
+/// names in the PBO. Entries that only differ by case from an earlier entry in the
+/// listing are dropped, since they would extract to the same file. Returns their
+/// original paths.
+pub fn lowercase_scan_result(scan_result: &mut PboScanResult) -> Vec<String> {
+    scan_result.prefix = scan_result.prefix.as_ref().map(|prefix| prefix.to_lowercase());
+
+    let mut seen = HashSet::new();
+    let mut collisions = Vec::new();
+    let mut entries = Vec::with_capacity(scan_result.expected_files.len());
+    for entry in std::mem::take(&mut scan_result.expected_files) {
+        let path = entry.path.to_lowercase();
+        if seen.insert(normalize_entry_path(&path)) {
+            if path != entry.path {
+                scan_result.renamed.insert(entry.path.clone(), path.clone());
+            }
+            entries.push(PboEntry { path, ..entry });
+        } else {
+            collisions.push(entry.path);
+        }
+    }
+    scan_result.expected_files = entries;
+
+    collisions
+}
+
+/// Make the prefix and expected entry paths of a scan result writable on Windows
+///
+/// Renamed entries are recorded in `renamed`, under their name in the PBO when they
+/// were lowercased before. With `NameSanitization::Error`, paths that would need
+/// renaming are added to `unsafe_paths` instead. Unsafe listing paths that
+/// sanitizing turns into safe ones, like drive letters, are no longer unsafe.
+pub fn sanitize_scan_result(scan_result: &mut PboScanResult, mode: NameSanitization) {
+    let mut invalid = Vec::new();
+
+    if let Some(prefix) = &scan_result.prefix {
+        match sanitize_entry_path(prefix, mode) {
+            Ok(sanitized) => scan_result.prefix = Some(sanitized),
+            Err(_) => invalid.push(prefix.clone()),
+        }
+    }
+    for entry in &mut scan_result.expected_files {
+        match sanitize_entry_path(&entry.path, mode) {
+            Ok(sanitized) if sanitized != entry.path => {
+                let lowercased = std::mem::replace(&mut entry.path, sanitized.clone());
+                // Entries may already have been renamed by lowercasing
+                let original = scan_result.renamed.iter()
+                    .find(|(_, renamed)| **renamed == lowercased)
+                    .map(|(original, _)| original.clone())
+                    .unwrap_or(lowercased);
+                scan_result.renamed.insert(original, sanitized);
+            },
+            Ok(_) => {},
+            Err(_) => invalid.push(entry.path.clone()),
+        }
+    }
+
+    scan_result.unsafe_paths.retain(|path| {
+        sanitize_entry_path(path, mode).and_then(|sanitized| native_entry_path(&sanitized)).is_err()
+    });
+    for path in invalid {
+        if !scan_result.unsafe_paths.contains(&path) {
+            scan_result.unsafe_paths.push(path);
+        }
+    }
+}
+
+/// Deal with expected entries whose path contains a Windows device name
+///
+/// Affected entries are recorded in `reserved_names` under their original path.
+/// Renamed entries are added to `renamed` like sanitized ones; skipped entries are
+/// removed from the expected files.
+pub fn handle_reserved_names(scan_result: &mut PboScanResult, handling: ReservedNameHandling) {
+    let mut expected_files = Vec::with_capacity(scan_result.expected_files.len());
+    for mut entry in scan_result.expected_files.drain(..) {
+        let escaped = escape_reserved_names(&entry.path);
+        if escaped == normalize_entry_path(&entry.path) {
+            expected_files.push(entry);
+            continue;
+        }
+
+        // Entries may already have been renamed by lowercasing or sanitization
+        let original = scan_result.renamed.iter()
+            .find(|(_, sanitized)| **sanitized == entry.path)
+            .map(|(original, _)| original.clone())
+            .unwrap_or_else(|| entry.path.clone());
+        scan_result.reserved_names.push(original.clone());
+
+        match handling {
+            ReservedNameHandling::Skip => {
+                scan_result.renamed.remove(&original);
+            },
+            ReservedNameHandling::Rename => {
+                scan_result.renamed.insert(original, escaped.clone());
+                entry.path = escaped;
+                expected_files.push(entry);
+            },
+        }
+    }
+    scan_result.expected_files = expected_files;
+}
+
+/// File name of the prefix map written into the cache directory
+pub const PREFIX_MAP_FILE_NAME: &str = "prefixes.json";
+
+/// Prefix as Arma resolves it: lowercase, forward slashes, no surrounding separators
+fn normalize_prefix(prefix: &str) -> String {
+    normalize_entry_path(prefix).trim_end_matches('/').to_lowercase()
+}
+
+/// Normalized prefix of every PBO declaring one, with the PBOs declaring it
+pub fn prefix_map(scan_results: &[PboScanResult]) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut map: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for scan_result in scan_results {
+        let prefix = normalize_prefix(scan_result.prefix.as_deref().unwrap_or_default());
+        if !prefix.is_empty() {
+            map.entry(prefix).or_default().push(scan_result.path.clone());
+        }
+    }
+    map
+}
+
+/// Write a prefix map to `path` as a JSON object of prefix to PBO paths
+pub fn write_prefix_map(path: &Path, map: &BTreeMap<String, Vec<PathBuf>>) -> Result<()> {
+    let json = serde_json::to_string_pretty(map)?;
+    std::fs::write(path, json)
+        .with_context(|| format!("Failed to write prefix map: {}", path.display()))
+}
+
+/// Prefix map written to `path`, empty if there is none
+pub fn read_prefix_map(path: &Path) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let json = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read prefix map: {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse prefix map: {}", path.display()))
+}
+
+/// Add the PBOs of `previous` for which `keep` holds to `map`
+pub fn merge_prefix_map(
+    map: &mut BTreeMap<String, Vec<PathBuf>>,
+    previous: BTreeMap<String, Vec<PathBuf>>,
+    keep: impl Fn(&Path) -> bool,
+) {
+    for (prefix, paths) in previous {
+        for path in paths.into_iter().filter(|path| keep(path)) {
+            let paths = map.entry(prefix.clone()).or_default();
+            if !paths.contains(&path) {
+                paths.push(path);
+                paths.sort();
+            }
+        }
+    }
+}
+
+/// Top-level assignments of a mod.cpp or meta.cpp, by lowercase key
+///
+/// Comments, a leading BOM, `[]` after array keys and `""` escapes inside strings
+/// are handled; values may be double-quoted, single-quoted or bare. Array values are
+/// kept as written. Fails on unterminated strings and comments.
+pub fn parse_mod_cpp(text: &str) -> Result<BTreeMap<String, String>> {
+    let text = text.trim_start_matches('\u{feff}');
+    let mut statements = Vec::new();
+    let mut statement = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                statement.push(c);
+                loop {
+                    let Some(next) = chars.next() else {
+                        anyhow::bail!("Unterminated string: {}", statement.trim());
+                    };
+                    statement.push(next);
+                    if next == c {
+                        // Doubled quotes are an escaped quote
+                        if chars.peek() != Some(&c) {
+                            break;
+                        }
+                        statement.push(chars.next().unwrap_or(c));
+                    }
+                }
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&next| next != '\n').is_some() {}
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    let Some(next) = chars.next() else {
+                        anyhow::bail!("Unterminated comment");
+                    };
+                    if previous == '*' && next == '/' {
+                        break;
+                    }
+                    previous = next;
+                }
+            },
+            ';' => statements.push(std::mem::take(&mut statement)),
+            _ => statement.push(c),
+        }
+    }
+    statements.push(statement);
+
+    let mut values = BTreeMap::new();
+    for statement in &statements {
+        let Some((key, value)) = statement.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().trim_end_matches("[]").trim().to_lowercase();
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(quote) => {
+                let doubled = format!("{quote}{quote}");
+                value[1..value.len() - 1].replace(&doubled, &quote.to_string())
+            },
+            _ => value.to_string(),
+        };
+        if !key.is_empty() {
+            values.insert(key, value);
+        }
+    }
+    Ok(values)
+}
+
+/// Read the metadata of the mod in `mod_dir` from its mod.cpp and meta.cpp
+///
+/// Missing or unparseable files are skipped, falling back to the folder name.
+pub fn read_mod_metadata(mod_dir: &Path) -> ModMetadata {
+    let read = |name: &str| -> BTreeMap<String, String> {
+        let path = mod_dir.join(name);
+        if !path.is_file() {
+            return BTreeMap::new();
+        }
+        let parsed = std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| parse_mod_cpp(&String::from_utf8_lossy(&data)));
+        parsed.unwrap_or_else(|e| {
+            warn!("Ignoring {}: {}", path.display(), e);
+            BTreeMap::new()
+        })
+    };
+    let mut mod_cpp = read("mod.cpp");
+    let mut meta_cpp = read("meta.cpp");
+
+    let declared = |metadata: &mut BTreeMap<String, String>| metadata.remove("name").filter(|name| !name.trim().is_empty());
+    let name = declared(&mut mod_cpp)
+        .or_else(|| declared(&mut meta_cpp))
+        .unwrap_or_else(|| mod_dir.file_name().unwrap_or_default().to_string_lossy().into_owned());
+    ModMetadata {
+        name,
+        version: mod_cpp.remove("version").filter(|version| !version.is_empty()),
+        published_id: meta_cpp.get("publishedid").and_then(|id| id.parse().ok()).filter(|&id| id != 0),
+    }
+}
+
+/// Prefixes declared by more than one PBO, with the PBOs declaring them
+///
+/// Prefixes are compared case-insensitively and regardless of separators, like Arma
+/// does. Each prefix is reported as declared by the first of its PBOs.
+pub fn prefix_collisions(scan_results: &[PboScanResult]) -> Vec<(String, Vec<PathBuf>)> {
+    let mut by_prefix: BTreeMap<String, (String, Vec<PathBuf>)> = BTreeMap::new();
+    for scan_result in scan_results {
+        let Some(prefix) = scan_result.prefix.as_deref() else {
+            continue;
+        };
+        let key = normalize_prefix(prefix);
+        if key.is_empty() {
+            continue;
+        }
+        by_prefix.entry(key)
+            .or_insert_with(|| (prefix.to_string(), Vec::new()))
+            .1
+            .push(scan_result.path.clone());
+    }
+
+    by_prefix.into_values()
+        .filter(|(_, paths)| paths.len() > 1)
+        .collect()
+}
+
+/// Virtual paths, prefix and entry path joined, provided by more than one PBO
+///
+/// Only the expected entries of each scan result are indexed. Paths are compared
+/// case-insensitively and regardless of separators.
+pub fn path_conflicts(scan_results: &[PboScanResult]) -> Vec<PathConflict> {
+    let mut index: BTreeMap<String, PathConflict> = BTreeMap::new();
+    for scan_result in scan_results {
+        let prefix = normalize_entry_path(scan_result.prefix.as_deref().unwrap_or_default());
+        let prefix = prefix.trim_end_matches('/');
+        for entry in &scan_result.expected_files {
+            let entry_path = normalize_entry_path(&entry.path);
+            let path = if prefix.is_empty() { entry_path } else { format!("{}/{}", prefix, entry_path) };
+            let conflict = index.entry(path.to_lowercase()).or_insert_with(|| PathConflict {
+                path,
+                pbos: Vec::new(),
+            });
+            if !conflict.pbos.contains(&scan_result.path) {
+                conflict.pbos.push(scan_result.path.clone());
+            }
+        }
+    }
+
+    index.into_values()
+        .filter(|conflict| conflict.pbos.len() > 1)
+        .collect()
+}
+
+/// Select the PBO entries that pass the filter
+///
+/// Entries without a known size always pass the size limits.
+pub fn filter_entries(
+    files: &[String],
+    sizes: &HashMap<String, u64>,
+    filter: &FileFilter,
+) -> Vec<PboEntry> {
+    // Compared case-insensitively, like the game resolves paths
+    let listed: HashSet<_> = if filter.prefer_source {
+        files.iter().map(|file| normalize_entry_path(file).to_lowercase()).collect()
+    } else {
+        HashSet::new()
+    };
+
+    debug!("Files in PBO:");
+    files.iter()
+        // Listings use backslashes, everything downstream compares forward slashes
+        .map(|file| PboEntry {
+            path: normalize_entry_path(file),
+            size: sizes.get(file.as_str()).copied(),
+        })
+        .filter(|entry| {
+            trace!("  {}", entry.path);
+            let matches = filter.matches(&entry.path) && filter.matches_size(entry.size);
+            if matches && filter.is_superseded(&entry.path, |source| listed.contains(&source.to_lowercase())) {
+                trace!("    -> Skipped in favour of its source");
+                return false;
+            }
+            if matches {
+                trace!("    -> Matches filter");
+            }
+            matches
+        })
+        .collect()
+}
+
+/// Compare the files below `output_dir` with the entries expected from the listing
+///
+/// Paths are compared with normalized separators, and case-insensitively on Windows.
+/// Sidecar files written next to the extracted files are ignored, and so are the
+/// outputs of nested PBOs among the expected entries.
+pub fn verify_output(output_dir: &Path, expected: &[PboEntry]) -> Result<VerificationResult> {
+    let nested = nested_output_roots(Path::new(""), expected);
+    let files = extracted_files(output_dir)?
+        .into_iter()
+        .filter(|rel_path| !in_nested_output(rel_path, &nested))
+        .collect();
+    Ok(verify_files(files, expected))
+}
+
+/// Compare extracted files, as paths relative to the output directory, with the
+/// entries expected from the listing
+pub fn verify_files(files: Vec<String>, expected: &[PboEntry]) -> VerificationResult {
+    let mut on_disk: HashMap<_, _> = files
+        .into_iter()
+        .map(|rel_path| (path_key(&rel_path), rel_path))
+        .collect();
+
+    let mut result = VerificationResult::default();
+    for entry in expected {
+        match on_disk.remove(&path_key(&entry.path)) {
+            Some(_) => result.found.push(normalize_entry_path(&entry.path)),
+            None => result.missing.push(normalize_entry_path(&entry.path)),
+        }
+    }
+    result.unexpected = on_disk.into_values().collect();
+    result.unexpected.sort();
+    result
+}
+
+/// Files below `base_dir` that are not among the expected entries extracted to `output_dir`
+///
+/// Returns normalized paths relative to `base_dir`, which catches leftovers of a
+/// previous prefix as well as entries removed from the PBO. The outputs of nested
+/// PBOs among the expected entries are never stale; they belong to those PBOs.
+pub fn stale_files(base_dir: &Path, output_dir: &Path, expected: &[PboEntry]) -> Result<Vec<String>> {
+    let prefix = output_dir.strip_prefix(base_dir)?;
+    let nested = nested_output_roots(prefix, expected);
+    let expected: HashSet<_> = expected.iter()
+        .map(|entry| path_key(&prefix.join(normalize_entry_path(&entry.path)).to_string_lossy()))
+        .collect();
+
+    let mut stale: Vec<_> = extracted_files(base_dir)?
+        .into_iter()
+        .filter(|rel_path| !expected.contains(&path_key(rel_path)) && !in_nested_output(rel_path, &nested))
+        .collect();
+    stale.sort();
+
+    Ok(stale)
+}
+
+/// Keys of the directories nested PBOs among `expected` extract into, below `prefix`
+///
+/// A nested PBO extracts into the directory named like it, next to it.
+fn nested_output_roots(prefix: &Path, expected: &[PboEntry]) -> Vec<String> {
+    expected.iter()
+        .map(|entry| normalize_entry_path(&entry.path))
+        .filter(|path| Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pbo")))
+        .map(|path| {
+            let root = prefix.join(Path::new(&path).with_extension(""));
+            format!("{}/", path_key(&root.to_string_lossy()))
+        })
+        .collect()
+}
+
+/// Whether `rel_path` lies in one of the directories from `nested_output_roots`
+fn in_nested_output(rel_path: &str, roots: &[String]) -> bool {
+    let key = path_key(rel_path);
+    roots.iter().any(|root| key.starts_with(root.as_str()))
+}
+
+/// Files below `dir` as entries with their sizes, like `extracted_files` finds them
+pub fn extracted_entries(dir: &Path) -> Result<Vec<PboEntry>> {
+    extracted_files(dir)?
+        .into_iter()
+        .map(|path| {
+            let size = std::fs::metadata(dir.join(native_entry_path(&path)?))?.len();
+            Ok(PboEntry { path, size: Some(size) })
+        })
+        .collect()
+}
+
+/// Normalized paths of the files below `dir`, without sidecar files at its top level
+///
+/// Fails if a symlink below `dir` resolves outside of it.
+fn extracted_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.path_is_symlink() {
+            ensure_within(dir, entry.path())?;
+        }
+        let is_sidecar = entry.depth() == 1
+            && (entry.file_name() == MANIFEST_FILE_NAME || entry.file_name() == CHECKSUM_FILE_NAME);
+        if !entry.file_type().is_file() || is_sidecar {
+            continue;
+        }
+        files.push(normalize_entry_path(&entry.path().strip_prefix(dir)?.to_string_lossy()));
+    }
+    Ok(files)
+}
+
+/// Key for comparing extracted paths, case-insensitive on Windows
+fn path_key(path: &str) -> String {
+    let path = normalize_entry_path(path);
+    if cfg!(windows) { path.to_lowercase() } else { path }
+}
+
+/// Split the expected entries by what is already on disk below `output_dir`
+///
+/// Entries are looked up by path; a file whose size differs from the listed size
+/// counts as changed. Anything at the path that isn't a file counts as missing.
+pub fn existing_entries(output_dir: &Path, expected: &[PboEntry]) -> ExistingEntries {
+    let mut existing = ExistingEntries::default();
+    for entry in expected {
+        let metadata = native_entry_path(&entry.path)
+            .and_then(|rel_path| Ok(std::fs::metadata(output_dir.join(rel_path))?));
+        match metadata {
+            Ok(meta) if meta.is_file() => {
+                if entry.size.is_some_and(|size| size != meta.len()) {
+                    existing.changed.push(entry.clone());
+                } else {
+                    existing.unchanged.push(entry.clone());
+                }
+            },
+            _ => existing.missing.push(entry.clone()),
+        }
+    }
+    existing
+}
+
+/// Estimated total size of the given entries once extracted
+///
+/// Entries without a listed size are covered by counting the whole PBO instead,
+/// which can't be smaller than its uncompressed contents.
+pub fn estimated_size(entries: &[PboEntry], pbo_size: u64) -> u64 {
+    let known: u64 = entries.iter().filter_map(|entry| entry.size).sum();
+    if entries.iter().any(|entry| entry.size.is_none()) {
+        known.max(pbo_size)
+    } else {
+        known
+    }
+}
+
+/// Expected entries not yet present below `output_dir`
+///
+/// An entry counts as present when a file exists at its path and, if the listing
+/// provided a size, the file has that size.
+pub fn missing_entries(output_dir: &Path, expected: &[PboEntry]) -> Vec<PboEntry> {
+    let unchanged: HashSet<_> = existing_entries(output_dir, expected).unchanged
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect();
+    expected.iter().filter(|entry| !unchanged.contains(&entry.path)).cloned().collect()
+}
+
+/// Extract per-entry sizes from a full (non-brief) PBO listing
+///
+/// Lines are expected to start with the entry name followed by its size. Names may
+/// contain spaces, so a line's name is the longest listed entry it starts with, and
+/// the size is the first number after it. Entries whose size cannot be determined
+/// are simply left out.
+pub fn parse_entry_sizes(listing: &str, files: &[String]) -> HashMap<String, u64> {
+    let known: HashSet<&str> = files.iter().map(String::as_str).collect();
+    let mut sizes = HashMap::new();
+
+    for line in listing.lines() {
+        let line = line.trim_start();
+        // The name ends where a column separator starts
+        let name = line.char_indices()
+            .filter(|(_, c)| c.is_whitespace())
+            .map(|(i, _)| &line[..i])
+            .rfind(|name| known.contains(name));
+        let Some(name) = name else {
+            continue;
+        };
+        if let Some(size) = line[name.len()..].split_whitespace().find_map(|token| token.parse::<u64>().ok()) {
+            sizes.insert(name.to_string(), size);
+        }
+    }
+
+    sizes
+}
+
+/// Extract `name=value` header properties from a PBO listing
+///
+/// Lines naming an entry are ignored so entries containing `=` are not mistaken for properties.
+pub fn parse_listing_properties(listing: &str, files: &[String]) -> BTreeMap<String, String> {
+    let known: HashSet<&str> = files.iter().map(String::as_str).collect();
+
+    listing.lines()
+        .map(str::trim)
+        .filter(|line| !known.contains(line))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+        .map(|(key, value)| (key.to_lowercase(), value.to_string()))
+        .collect()
+}
+
+/// Find the listed entry named by `query`
+///
+/// Paths are compared case-insensitively with either separator. A bare file name
+/// without a full path match is looked up by file name in every directory, and fails
+/// when more than one entry has it.
+pub fn find_entry(entries: &[String], query: &str) -> Result<String> {
+    let query = normalize_entry_path(query).trim_start_matches('/').to_lowercase();
+    let mut candidates: Vec<&String> = entries.iter()
+        .filter(|entry| normalize_entry_path(entry).to_lowercase() == query)
+        .collect();
+    if candidates.is_empty() && !query.contains('/') {
+        candidates = entries.iter()
+            .filter(|entry| {
+                let entry = normalize_entry_path(entry).to_lowercase();
+                entry.rsplit('/').next() == Some(query.as_str())
+            })
+            .collect();
+    }
+
+    match candidates.as_slice() {
+        [] => Err(anyhow::anyhow!("No entry named {} in the PBO", query)),
+        [entry] => Ok(normalize_entry_path(entry)),
+        _ => Err(anyhow::anyhow!(
+            "{} is ambiguous, candidates: {}",
+            query,
+            candidates.iter().map(|entry| normalize_entry_path(entry)).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(entries: &[PboEntry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.path.clone()).collect()
+    }
+
+    fn listing() -> Vec<String> {
+        vec![
+            "config.cpp".to_string(),
+            "functions\\fnc_setUnconscious.sqf".to_string(),
+            "functions\\medical\\fnc_heal.sqf".to_string(),
+            "functions\\medical\\fnc_heal.hpp".to_string(),
+            "ui\\tourniquet_arm_left.paa".to_string(),
+        ]
+    }
+
+    fn invalid_fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/invalid").join(name)
+    }
+
+    #[test]
+    fn test_check_pbo_header() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        assert_eq!(check_pbo_header(&fixtures.join("ace_medical.pbo")).unwrap(), HeaderCheck::Plausible);
+        assert_eq!(check_pbo_header(&invalid_fixture("empty.pbo")).unwrap(), HeaderCheck::Empty);
+
+        assert!(matches!(check_pbo_header(&invalid_fixture("photo.pbo")).unwrap(), HeaderCheck::Invalid(_)));
+        assert_eq!(
+            check_pbo_header(&invalid_fixture("truncated.pbo")).unwrap(),
+            HeaderCheck::Invalid("truncated: header and data take 205426 bytes, the file has 1753".to_string())
+        );
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let text = temp_dir.path().join("config.pbo");
+        std::fs::write(&text, "class CfgPatches\n{\n};\n").unwrap();
+        assert!(matches!(check_pbo_header(&text).unwrap(), HeaderCheck::Invalid(_)));
+        // Cut off in the middle of the header
+        let medical = std::fs::read(fixtures.join("ace_medical.pbo")).unwrap();
+        std::fs::write(&text, &medical[..300]).unwrap();
+        assert!(matches!(check_pbo_header(&text).unwrap(), HeaderCheck::Invalid(_)));
+        // Only missing the trailing checksum
+        std::fs::write(&text, &medical[..205426]).unwrap();
+        assert_eq!(check_pbo_header(&text).unwrap(), HeaderCheck::Plausible);
+    }
+
+    /// Bytes of an uncompressed PBO with the given prefix and entries, without a checksum
+    fn pbo_bytes(prefix: &str, entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut bytes = vec![0];
+        for field in [VERS_MAGIC, 0, 0, 0, 0] {
+            bytes.extend(field.to_le_bytes());
+        }
+        bytes.extend(format!("prefix\0{}\0\0", prefix).as_bytes());
+        for (name, data) in entries {
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            for field in [0, data.len() as u32, 0, 0, data.len() as u32] {
+                bytes.extend(field.to_le_bytes());
+            }
+        }
+        bytes.extend([0; 21]);
+        for (_, data) in entries {
+            bytes.extend(data.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_scan_pbo_header() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("ace_medical.pbo");
+        let entries = [("config.cpp", "class CfgPatches {};"), ("functions\\fnc_heal.sqf", "heal")];
+        std::fs::write(&path, pbo_bytes("z\\ace\\addons\\medical", &entries)).unwrap();
+
+        let (check, header) = read_pbo_header(&path).unwrap();
+        assert_eq!(check, HeaderCheck::Plausible);
+        assert_eq!(header.entries, vec!["config.cpp", "functions\\fnc_heal.sqf"]);
+        assert_eq!(header.properties.get("prefix").map(String::as_str), Some("z\\ace\\addons\\medical"));
+
+        let scan_result = scan_pbo_header(&path, NameSanitization::Replace).unwrap().unwrap();
+        assert!(scan_result.listed_after_extraction);
+        assert!(scan_result.expected_files.is_empty());
+        assert_eq!(scan_result.prefix.as_deref(), Some("z\\ace\\addons\\medical"));
+        assert_eq!(scan_result.pbo_size, std::fs::metadata(&path).unwrap().len());
+
+        // Entries to handle before extracting them leave the PBO to the tool
+        for name in ["aux.sqf", "..\\fnc_escape.sqf", "fnc_heal?.sqf", "CONFIG.cpp"] {
+            std::fs::write(&path, pbo_bytes("z\\ace", &[("config.cpp", "a"), (name, "b")])).unwrap();
+            assert!(scan_pbo_header(&path, NameSanitization::Replace).unwrap().is_none(), "{}", name);
+        }
+        let scan_result = scan_pbo_header(&invalid_fixture("empty.pbo"), NameSanitization::Replace).unwrap().unwrap();
+        assert_eq!(scan_result.skip_reason, Some(SkipReason::Empty));
+        assert!(!scan_result.listed_after_extraction);
+
+        // Header values may be longer than entry names
+        let long_prefix = "z\\ace\\".repeat(300);
+        std::fs::write(&path, pbo_bytes(&long_prefix, &entries)).unwrap();
+        let (check, header) = read_pbo_header(&path).unwrap();
+        assert_eq!(check, HeaderCheck::Plausible);
+        assert_eq!(header.entries, vec!["config.cpp", "functions\\fnc_heal.sqf"]);
+        assert_eq!(header.properties.get("prefix"), Some(&long_prefix));
+    }
+
+    #[test]
+    fn test_invalid_pbos_are_skipped_without_listing() {
+        // The tool would fail every listing in this test
+        let filter = FileFilter::new("sqf");
+        let scan_result = scan_pbo_contents(&invalid_fixture("empty.pbo"), &filter, 1).unwrap();
+        assert_eq!(scan_result.skip_reason, Some(SkipReason::Empty));
+        let scan_result = scan_pbo_contents(&invalid_fixture("photo.pbo"), &filter, 1).unwrap();
+        assert_eq!(scan_result.skip_reason, Some(SkipReason::InvalidFormat));
+        assert!(scan_result.expected_files.is_empty());
+    }
+
+    #[test]
+    fn test_find_entry() {
+        let mut entries = listing();
+        entries.push("dev\\config.cpp".to_string());
+        entries.push("functions\\medical\\fnc_setUnconscious.sqf".to_string());
+
+        assert_eq!(find_entry(&entries, "CONFIG.CPP").unwrap(), "config.cpp");
+        assert_eq!(find_entry(&entries, "\\Functions\\Medical\\fnc_heal.sqf").unwrap(), "functions/medical/fnc_heal.sqf");
+        assert_eq!(find_entry(&entries, "fnc_heal.hpp").unwrap(), "functions/medical/fnc_heal.hpp");
+        assert_eq!(find_entry(&entries, "functions/fnc_setunconscious.sqf").unwrap(), "functions/fnc_setUnconscious.sqf");
+
+        let error = find_entry(&entries, "fnc_setUnconscious.sqf").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "fnc_setunconscious.sqf is ambiguous, candidates: functions/fnc_setUnconscious.sqf, functions/medical/fnc_setUnconscious.sqf"
+        );
+        assert!(find_entry(&entries, "medical/fnc_treat.sqf").is_err());
+        assert!(find_entry(&entries, "fnc_heal.sqf/").is_err());
+    }
+
+    #[test]
+    fn test_filter_entries_nested_prefix() {
+        let filter = FileFilter::new("sqf")
+            .with_include_paths(vec!["functions/medical/".to_string()]);
+        let files = names(&filter_entries(&listing(), &HashMap::new(), &filter));
+        assert_eq!(files, vec!["functions/medical/fnc_heal.sqf".to_string()]);
+    }
+
+    #[test]
+    fn test_filter_entries_multiple_prefixes() {
+        let filter = FileFilter::new("")
+            .with_include_paths(vec!["functions".to_string(), "ui".to_string()]);
+        let files = names(&filter_entries(&listing(), &HashMap::new(), &filter));
+        assert_eq!(files.len(), 4);
+        assert!(!files.contains(&"config.cpp".to_string()));
+    }
+
+    #[test]
+    fn test_filter_entries_prefix_without_matches() {
+        let filter = FileFilter::new("sqf")
+            .with_include_paths(vec!["data/".to_string()]);
+        assert!(filter_entries(&listing(), &HashMap::new(), &filter).is_empty());
+    }
+
+    #[test]
+    fn test_filter_entries_size_limits() {
+        let files = listing();
+        let sizes: HashMap<String, u64> = vec![
+            (files[0].clone(), 99),
+            (files[1].clone(), 100),
+            (files[2].clone(), 500),
+            (files[3].clone(), 501),
+        ].into_iter().collect();
+
+        let mut filter = FileFilter::new("");
+        filter.min_file_size = Some(100);
+        filter.max_file_size = Some(500);
+
+        // Boundary values pass, entries without a known size pass
+        let matching = filter_entries(&files, &sizes, &filter);
+        assert_eq!(names(&matching), vec![
+            "functions/fnc_setUnconscious.sqf".to_string(),
+            "functions/medical/fnc_heal.sqf".to_string(),
+            "ui/tourniquet_arm_left.paa".to_string(),
+        ]);
+        assert_eq!(matching[0].size, Some(100));
+        assert_eq!(matching[2].size, None);
+    }
+
+    #[test]
+    fn test_filter_entries_prefer_source() {
+        let files: Vec<String> = vec![
+            // Compiled only
+            "functions\\fnc_compiledOnly.sqfc".to_string(),
+            // Source only
+            "functions\\fnc_sourceOnly.sqf".to_string(),
+            // Both, with differing case
+            "functions\\fnc_heal.sqf".to_string(),
+            "functions\\FNC_HEAL.sqfc".to_string(),
+        ];
+        let mut filter = FileFilter::new("sqf,sqfc");
+        assert_eq!(filter_entries(&files, &HashMap::new(), &filter).len(), 4);
+
+        filter.prefer_source = true;
+        assert_eq!(names(&filter_entries(&files, &HashMap::new(), &filter)), vec![
+            "functions/fnc_compiledOnly.sqfc".to_string(),
+            "functions/fnc_sourceOnly.sqf".to_string(),
+            "functions/fnc_heal.sqf".to_string(),
+        ]);
+
+        // The source counts even when the filter doesn't ask for it
+        let mut compiled_only = FileFilter::new("sqfc");
+        compiled_only.prefer_source = true;
+        assert_eq!(names(&filter_entries(&files, &HashMap::new(), &compiled_only)), vec![
+            "functions/fnc_compiledOnly.sqfc".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_entry_sizes() {
+        let files = listing();
+        let output = format!(
+            "Prefix=z\\ace\\addons\\medical\n{} 1234 2019-01-01\n{}  42\n{}\n",
+            files[0], files[1], files[2],
+        );
+        let sizes = parse_entry_sizes(&output, &files);
+        assert_eq!(sizes.get(&files[0]), Some(&1234));
+        assert_eq!(sizes.get(&files[1]), Some(&42));
+        assert_eq!(sizes.get(&files[2]), None);
+        assert_eq!(sizes.len(), 2);
+
+        // Names with spaces, even ending in a number, keep their whole size column
+        let files = vec![
+            "data\\my texture.paa".to_string(),
+            "data\\my".to_string(),
+            "sounds\\take 2.ogg".to_string(),
+            "sounds\\take 2.ogg 3".to_string(),
+        ];
+        let output = "data\\my texture.paa 2048 2019-01-01\ndata\\my 10\nsounds\\take 2.ogg  512\nsounds\\take 2.ogg 3 64\n";
+        let sizes = parse_entry_sizes(output, &files);
+        assert_eq!(sizes.get(&files[0]), Some(&2048));
+        assert_eq!(sizes.get(&files[1]), Some(&10));
+        assert_eq!(sizes.get(&files[2]), Some(&512));
+        assert_eq!(sizes.get(&files[3]), Some(&64));
+    }
+
+    #[test]
+    fn test_tool_timeouts_are_typed() {
+        let error = tool_error(PboError("Operation timed out after 30 seconds".to_string()), 30);
+        assert_eq!(error.downcast_ref::<ToolTimeout>(), Some(&ToolTimeout { secs: 30 }));
+        let error = tool_error(PboError("Failed to extract: invalid header".to_string()), 30);
+        assert!(error.downcast_ref::<ToolTimeout>().is_none());
+    }
+
+    #[test]
+    fn test_parse_listing_properties() {
+        let files = vec!["weird=name.sqf".to_string(), "config.cpp".to_string()];
+        let output = "Prefix=z\\ace\\addons\\medical\nproduct = ace\nweird=name.sqf\nconfig.cpp 12\nnot a property = x\n";
+        let properties = parse_listing_properties(output, &files);
+        assert_eq!(properties.get("prefix").map(String::as_str), Some("z\\ace\\addons\\medical"));
+        assert_eq!(properties.get("product").map(String::as_str), Some("ace"));
+        assert_eq!(properties.len(), 2);
+    }
+
+    #[test]
+    fn test_verify_output() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        std::fs::write(root.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("extra.sqf"), b"extra").unwrap();
+        std::fs::write(root.join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        let expected = vec![
+            PboEntry { path: "functions\\fnc_heal.sqf".to_string(), size: None },
+            PboEntry { path: "functions\\fnc_missing.sqf".to_string(), size: None },
+        ];
+        let result = verify_output(root, &expected).unwrap();
+
+        assert_eq!(result.found, vec!["functions/fnc_heal.sqf".to_string()]);
+        assert_eq!(result.missing, vec!["functions/fnc_missing.sqf".to_string()]);
+        assert_eq!(result.unexpected, vec!["extra.sqf".to_string()]);
+        assert!(!result.is_complete());
+    }
+
+    #[test]
+    fn test_missing_entries() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        std::fs::write(root.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("functions/fnc_partial.sqf"), b"par").unwrap();
+
+        let expected = vec![
+            PboEntry { path: "functions\\fnc_heal.sqf".to_string(), size: Some(4) },
+            PboEntry { path: "functions\\fnc_partial.sqf".to_string(), size: Some(7) },
+            PboEntry { path: "functions\\fnc_new.sqf".to_string(), size: None },
+        ];
+        let missing = names(&missing_entries(root, &expected));
+
+        assert_eq!(missing, vec![
+            "functions\\fnc_partial.sqf".to_string(),
+            "functions\\fnc_new.sqf".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_lowercase_scan_result_with_mixed_case_duplicates() {
+        let listing = "\
+Functions\\fnc_Heal.sqf 120
+functions\\fnc_heal.sqf 118
+FUNCTIONS/FNC_HEAL.SQF 121
+Functions\\fnc_Splint.sqf 80
+config.cpp 40
+Config.CPP 42
+";
+        let files: Vec<String> = listing.lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect();
+        let sizes = parse_entry_sizes(listing, &files);
+        let mut scan_result = PboScanResult {
+            expected_files: filter_entries(&files, &sizes, &FileFilter::new("sqf,cpp")),
+            prefix: Some("Z\\ACE\\addons\\Medical".to_string()),
+            ..Default::default()
+        };
+
+        let collisions = lowercase_scan_result(&mut scan_result);
+
+        assert_eq!(collisions, vec![
+            "functions/fnc_heal.sqf".to_string(),
+            "FUNCTIONS/FNC_HEAL.SQF".to_string(),
+            "Config.CPP".to_string(),
+        ]);
+        assert_eq!(scan_result.prefix.as_deref(), Some("z\\ace\\addons\\medical"));
+        assert_eq!(scan_result.expected_files, vec![
+            PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(120) },
+            PboEntry { path: "functions/fnc_splint.sqf".to_string(), size: Some(80) },
+            PboEntry { path: "config.cpp".to_string(), size: Some(40) },
+        ]);
+        // The tool is given the names in the PBO, also once sanitized
+        assert_eq!(scan_result.renamed.get("Functions/fnc_Splint.sqf").map(String::as_str), Some("functions/fnc_splint.sqf"));
+        assert!(!scan_result.renamed.contains_key("config.cpp"));
+        scan_result.expected_files.push(PboEntry { path: "data/what?.paa".to_string(), size: None });
+        scan_result.renamed.insert("Data/What?.paa".to_string(), "data/what?.paa".to_string());
+        sanitize_scan_result(&mut scan_result, NameSanitization::Replace);
+        assert_eq!(scan_result.renamed.get("Data/What?.paa").map(String::as_str), Some("data/what_.paa"));
+        assert!(!scan_result.renamed.contains_key("data/what?.paa"));
+    }
+
+    #[test]
+    fn test_sanitize_scan_result() {
+        let scan_result = || PboScanResult {
+            expected_files: vec![
+                PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+                PboEntry { path: "data/what?.paa".to_string(), size: Some(8) },
+                PboEntry { path: "data./notes.txt".to_string(), size: None },
+            ],
+            prefix: Some("C:\\mods\\ace".to_string()),
+            unsafe_paths: vec!["C:\\mods\\ace".to_string(), "../evil.sqf".to_string()],
+            ..Default::default()
+        };
+
+        let mut replaced = scan_result();
+        sanitize_scan_result(&mut replaced, NameSanitization::Replace);
+        assert_eq!(replaced.prefix.as_deref(), Some("C_/mods/ace"));
+        assert_eq!(names(&replaced.expected_files), vec![
+            "functions/fnc_heal.sqf".to_string(),
+            "data/what_.paa".to_string(),
+            "data_/notes.txt".to_string(),
+        ]);
+        assert_eq!(replaced.renamed.get("data/what?.paa").map(String::as_str), Some("data/what_.paa"));
+        assert_eq!(replaced.renamed.get("data./notes.txt").map(String::as_str), Some("data_/notes.txt"));
+        assert_eq!(replaced.renamed.len(), 2);
+        assert_eq!(replaced.unsafe_paths, vec!["../evil.sqf".to_string()]);
+
+        let mut encoded = scan_result();
+        sanitize_scan_result(&mut encoded, NameSanitization::PercentEncode);
+        assert_eq!(encoded.expected_files[1].path, "data/what%3F.paa");
+
+        let mut strict = scan_result();
+        sanitize_scan_result(&mut strict, NameSanitization::Error);
+        assert!(strict.renamed.is_empty());
+        assert_eq!(strict.unsafe_paths, vec![
+            "C:\\mods\\ace".to_string(),
+            "../evil.sqf".to_string(),
+            "data/what?.paa".to_string(),
+            "data./notes.txt".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_handle_reserved_names() {
+        let scan_result = || {
+            let mut scan_result = PboScanResult {
+                expected_files: vec![
+                    PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+                    PboEntry { path: "functions/AUX.sqf".to_string(), size: Some(3) },
+                    PboEntry { path: "con/what?.txt".to_string(), size: None },
+                    PboEntry { path: "data/console.paa".to_string(), size: None },
+                ],
+                ..Default::default()
+            };
+            sanitize_scan_result(&mut scan_result, NameSanitization::Replace);
+            scan_result
+        };
+
+        let mut renamed = scan_result();
+        handle_reserved_names(&mut renamed, ReservedNameHandling::Rename);
+        assert_eq!(renamed.reserved_names, vec!["functions/AUX.sqf".to_string(), "con/what?.txt".to_string()]);
+        assert_eq!(names(&renamed.expected_files), vec![
+            "functions/fnc_heal.sqf".to_string(),
+            "functions/_AUX.sqf".to_string(),
+            "_con/what_.txt".to_string(),
+            "data/console.paa".to_string(),
+        ]);
+        assert_eq!(renamed.renamed.get("functions/AUX.sqf").map(String::as_str), Some("functions/_AUX.sqf"));
+        assert_eq!(renamed.renamed.get("con/what?.txt").map(String::as_str), Some("_con/what_.txt"));
+        assert_eq!(renamed.renamed.len(), 2);
+
+        let mut skipped = scan_result();
+        handle_reserved_names(&mut skipped, ReservedNameHandling::Skip);
+        assert_eq!(skipped.reserved_names, renamed.reserved_names);
+        assert_eq!(names(&skipped.expected_files), vec![
+            "functions/fnc_heal.sqf".to_string(),
+            "data/console.paa".to_string(),
+        ]);
+        assert!(skipped.renamed.is_empty());
+    }
+
+    #[test]
+    fn test_prefix_collisions() {
+        let scan_result = |path: &str, prefix: Option<&str>| PboScanResult {
+            path: PathBuf::from(path),
+            prefix: prefix.map(str::to_string),
+            ..Default::default()
+        };
+        let scan_results = vec![
+            scan_result("@ace/addons/ace_medical.pbo", Some("z\\ace\\addons\\medical")),
+            scan_result("@copy/addons/medical.pbo", Some("Z/ACE/Addons/Medical/")),
+            scan_result("@ace/addons/ace_common.pbo", Some("z\\ace\\addons\\common")),
+            scan_result("@other/addons/a.pbo", None),
+            scan_result("@other/addons/b.pbo", None),
+            scan_result("@other/addons/c.pbo", Some("")),
+            scan_result("@other/addons/d.pbo", Some("")),
+        ];
+
+        assert_eq!(prefix_collisions(&scan_results), vec![(
+            "z\\ace\\addons\\medical".to_string(),
+            vec![PathBuf::from("@ace/addons/ace_medical.pbo"), PathBuf::from("@copy/addons/medical.pbo")],
+        )]);
+    }
+
+    fn mod_fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mods").join(name)
+    }
+
+    #[test]
+    fn test_parse_mod_cpp() {
+        let values = parse_mod_cpp(&std::fs::read_to_string(mod_fixture("@CBA_A3/mod.cpp")).unwrap()).unwrap();
+        assert_eq!(values["name"], "Community Base Addons v3.16.1");
+        assert_eq!(values["tooltip"], "Community Base Addons v3.16.1");
+        assert_eq!(values["overview"], "Bugtracker: https://github.com/CBATeam/CBA_A3/issues<br/>Said \"hello\" to the world");
+        assert_eq!(values["version"], "3.16.1");
+        assert_eq!(values["picture"], "logo_cba_ca.paa");
+        assert_eq!(values["hidename"], "0");
+        assert_eq!(values.len(), 6);
+
+        let values = parse_mod_cpp(&std::fs::read_to_string(mod_fixture("@ace/mod.cpp")).unwrap()).unwrap();
+        assert_eq!(values["dlccolor"], "{0.18, 0.52, 0.78, 1}");
+        assert_eq!(
+            values["overviewfootnote"],
+            "<br /><br /><t color='#80FFFFFF'>ACE3 is licensed under GNU General Public License (GPLv2).</t>"
+        );
+
+        assert!(parse_mod_cpp(&std::fs::read_to_string(mod_fixture("@broken/mod.cpp")).unwrap()).is_err());
+        assert!(parse_mod_cpp("name = \"ACE\"; /* unterminated").is_err());
+        let values = parse_mod_cpp("name = 'a;b'; // c;\nauthor=x").unwrap();
+        assert_eq!(values["name"], "a;b");
+        assert_eq!(values["author"], "x");
+    }
+
+    #[test]
+    fn test_read_mod_metadata() {
+        assert_eq!(read_mod_metadata(&mod_fixture("@ace")), ModMetadata {
+            name: "Advanced Combat Environment 3.18.2".to_string(),
+            version: None,
+            published_id: Some(463939057),
+        });
+        assert_eq!(read_mod_metadata(&mod_fixture("@CBA_A3")), ModMetadata {
+            name: "Community Base Addons v3.16.1".to_string(),
+            version: Some("3.16.1".to_string()),
+            published_id: None,
+        });
+        // Workshop folder with only a meta.cpp
+        assert_eq!(read_mod_metadata(&mod_fixture("463939057")).name, "ace");
+        assert_eq!(read_mod_metadata(&mod_fixture("@broken")).name, "@broken");
+        // An empty name in mod.cpp falls back to the one in meta.cpp
+        assert_eq!(read_mod_metadata(&mod_fixture("@unnamed")).name, "Unnamed Mod");
+        assert_eq!(read_mod_metadata(&mod_fixture("@missing")), ModMetadata {
+            name: "@missing".to_string(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_prefix_map() {
+        let scan_result = |path: &str, prefix: Option<&str>| PboScanResult {
+            path: PathBuf::from(path),
+            prefix: prefix.map(str::to_string),
+            ..Default::default()
+        };
+        let scan_results = vec![
+            scan_result("@ace/addons/ace_medical.pbo", Some("\\z\\ace\\addons\\medical")),
+            scan_result("@copy/addons/medical.pbo", Some("Z/ACE/Addons/Medical/")),
+            scan_result("@ace/addons/ace_common.pbo", Some("z\\ace\\addons\\common")),
+            scan_result("@other/addons/a.pbo", None),
+            scan_result("@other/addons/b.pbo", Some("")),
+        ];
+
+        let map = prefix_map(&scan_results);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["z/ace/addons/common", "z/ace/addons/medical"]);
+        assert_eq!(map["z/ace/addons/medical"], vec![
+            PathBuf::from("@ace/addons/ace_medical.pbo"),
+            PathBuf::from("@copy/addons/medical.pbo"),
+        ]);
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join(PREFIX_MAP_FILE_NAME);
+        write_prefix_map(&path, &map).unwrap();
+        let written: BTreeMap<String, Vec<PathBuf>> = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written, map);
+    }
+
+    #[test]
+    fn test_path_conflicts() {
+        let scan_result = |path: &str, prefix: Option<&str>, entries: &[&str]| PboScanResult {
+            path: PathBuf::from(path),
+            prefix: prefix.map(str::to_string),
+            expected_files: entries.iter()
+                .map(|entry| PboEntry { path: entry.to_string(), size: None })
+                .collect(),
+            ..Default::default()
+        };
+        let scan_results = vec![
+            scan_result("@cba/addons/cba_common.pbo", Some("x\\cba\\addons\\common"), &[
+                "script_macros.hpp",
+                "config.cpp",
+            ]),
+            scan_result("@fork/addons/common.pbo", Some("X\\CBA\\addons"), &[
+                "common\\Script_Macros.hpp",
+                "common\\script_macros.hpp",
+            ]),
+            scan_result("@loose/addons/loose.pbo", None, &["x/cba/addons/common/script_macros.hpp"]),
+            scan_result("@other/addons/other.pbo", Some("z\\other"), &["config.cpp"]),
+        ];
+
+        assert_eq!(path_conflicts(&scan_results), vec![PathConflict {
+            path: "x/cba/addons/common/script_macros.hpp".to_string(),
+            pbos: vec![
+                PathBuf::from("@cba/addons/cba_common.pbo"),
+                PathBuf::from("@fork/addons/common.pbo"),
+                PathBuf::from("@loose/addons/loose.pbo"),
+            ],
+        }]);
+    }
+
+    #[test]
+    fn test_mixed_separators_compare_equal() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions/medical")).unwrap();
+        std::fs::write(root.join("functions/medical/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("functions/fnc_splint.sqf"), b"splint").unwrap();
+
+        // Listing entries enter the scan result with forward slashes
+        let files = vec![
+            "functions\\medical\\fnc_heal.sqf".to_string(),
+            "\\functions/fnc_splint.sqf".to_string(),
+        ];
+        let sizes: HashMap<String, u64> = vec![(files[0].clone(), 4)].into_iter().collect();
+        let expected = filter_entries(&files, &sizes, &FileFilter::new("sqf"));
+        assert_eq!(expected, vec![
+            PboEntry { path: "functions/medical/fnc_heal.sqf".to_string(), size: Some(4) },
+            PboEntry { path: "functions/fnc_splint.sqf".to_string(), size: None },
+        ]);
+
+        // Entries that kept their backslashes still match the files on disk
+        let raw: Vec<_> = files.iter()
+            .map(|path| PboEntry { path: path.clone(), size: None })
+            .collect();
+        for entries in [&expected, &raw] {
+            let result = verify_output(root, entries).unwrap();
+            assert!(result.is_complete(), "{:?}", result);
+            assert!(result.unexpected.is_empty(), "{:?}", result);
+            assert_eq!(existing_entries(root, entries).unchanged.len(), 2);
+            assert!(stale_files(root, root, entries).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_existing_entries() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions/fnc_dir.sqf")).unwrap();
+        std::fs::write(root.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("functions/fnc_unsized.sqf"), b"x").unwrap();
+        std::fs::write(root.join("functions/fnc_partial.sqf"), b"par").unwrap();
+
+        let expected = vec![
+            PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(4) },
+            PboEntry { path: "functions\\fnc_unsized.sqf".to_string(), size: None },
+            PboEntry { path: "functions/fnc_partial.sqf".to_string(), size: Some(7) },
+            PboEntry { path: "functions/fnc_dir.sqf".to_string(), size: None },
+            PboEntry { path: "functions/fnc_new.sqf".to_string(), size: None },
+        ];
+        let existing = existing_entries(root, &expected);
+
+        assert_eq!(names(&existing.unchanged), vec![
+            "functions/fnc_heal.sqf".to_string(),
+            "functions\\fnc_unsized.sqf".to_string(),
+        ]);
+        assert_eq!(names(&existing.changed), vec!["functions/fnc_partial.sqf".to_string()]);
+        assert_eq!(names(&existing.missing), vec![
+            "functions/fnc_dir.sqf".to_string(),
+            "functions/fnc_new.sqf".to_string(),
+        ]);
+        assert_eq!(existing.present().count(), 3);
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        let sized = vec![
+            PboEntry { path: "functions/fnc_heal.sqf".to_string(), size: Some(400) },
+            PboEntry { path: "functions/fnc_splint.sqf".to_string(), size: Some(600) },
+        ];
+        assert_eq!(estimated_size(&sized, 10_000), 1000);
+        assert_eq!(estimated_size(&[], 10_000), 0);
+
+        let mut unsized_ = sized.clone();
+        unsized_.push(PboEntry { path: "data/body.paa".to_string(), size: None });
+        assert_eq!(estimated_size(&unsized_, 10_000), 10_000);
+        assert_eq!(estimated_size(&unsized_, 10), 1000);
+    }
+
+    #[test]
+    fn test_stale_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let base_dir = temp_dir.path();
+        let output_dir = base_dir.join("z/ace/medical");
+        std::fs::create_dir_all(output_dir.join("functions")).unwrap();
+        std::fs::create_dir_all(base_dir.join("old_prefix")).unwrap();
+        std::fs::write(output_dir.join("functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(output_dir.join("functions/fnc_old.sqf"), b"old").unwrap();
+        std::fs::write(base_dir.join("old_prefix/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(base_dir.join(MANIFEST_FILE_NAME), b"{}").unwrap();
+
+        let expected = vec![PboEntry { path: "functions\\fnc_heal.sqf".to_string(), size: None }];
+        let stale = stale_files(base_dir, &output_dir, &expected).unwrap();
+
+        assert_eq!(stale, vec![
+            "old_prefix/fnc_heal.sqf".to_string(),
+            "z/ace/medical/functions/fnc_old.sqf".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_nested_outputs_are_not_stale_or_unexpected() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let base_dir = temp_dir.path();
+        let output_dir = base_dir.join("z/ace/medical");
+        std::fs::create_dir_all(output_dir.join("optionals/compat/functions")).unwrap();
+        std::fs::write(output_dir.join("optionals/compat.pbo"), b"pbo").unwrap();
+        std::fs::write(output_dir.join("optionals/compat/functions/fnc_heal.sqf"), b"heal").unwrap();
+        std::fs::write(output_dir.join("optionals/compat_old.sqf"), b"old").unwrap();
+
+        let expected = vec![PboEntry { path: "optionals\\compat.pbo".to_string(), size: None }];
+        let stale = stale_files(base_dir, &output_dir, &expected).unwrap();
+        assert_eq!(stale, vec!["z/ace/medical/optionals/compat_old.sqf".to_string()]);
+
+        let result = verify_output(&output_dir, &expected).unwrap();
+        assert!(result.is_complete(), "{:?}", result);
+        assert_eq!(result.unexpected, vec!["optionals/compat_old.sqf".to_string()]);
+    }
+
+    #[test]
+    fn test_backend_api_built_once_per_worker() {
+        use rayon::prelude::*;
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        let used: Vec<(Option<usize>, usize)> = pool.install(|| (0..60)
+            .into_par_iter()
+            .map(|_| {
+                let api = pbo_api(30);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                (rayon::current_thread_index(), Rc::as_ptr(&api) as usize)
+            })
+            .collect());
+
+        let workers: HashSet<_> = used.iter().map(|(worker, _)| *worker).collect();
+        let built: HashSet<_> = used.iter().map(|(_, api)| *api).collect();
+        assert_eq!(built.len(), workers.len());
+        assert!(built.len() <= 3);
+        assert!(Rc::ptr_eq(&pbo_api(30), &pbo_api(30)));
+        assert!(!Rc::ptr_eq(&pbo_api(30), &pbo_api(60)));
+    }
+
+    #[test]
+    fn test_default_tool_concurrency() {
+        let cores = std::thread::available_parallelism().unwrap().get();
+        assert_eq!(default_tool_concurrency(1), 1);
+        assert_eq!(default_tool_concurrency(0), 1);
+        assert_eq!(default_tool_concurrency(2 * cores + 1), 2 * cores);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_listing_runs_tool_twice() {
+        use std::os::unix::fs::PermissionsExt;
+        let bin = tempfile::TempDir::new().unwrap();
+        let tool = bin.path().join(super::super::backend::EXTRACTPBO_FILE_NAME);
+        // Full listings always fail, default ones only for missing PBOs
+        std::fs::write(&tool, "\
+#!/bin/sh
+case \"$1 $3\" in
+  '-L '*|*missing*) echo 'cannot list' >&2; exit 1;;
+esac
+").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let hidden = HiddenTool::at(tool, 10);
+        let list = |path: &str| list_pbo_contents_with(Path::new(path), 10, |list| list(&hidden));
+
+        // The full listing failing falls back to the default listing, once
+        let before = listing_runs();
+        assert!(list("ace_medical.pbo").is_ok());
+        assert_eq!(listing_runs() - before, 2);
+        let before = listing_runs();
+        let error = list("missing.pbo").unwrap_err();
+        assert!(format!("{:#}", error).contains("cannot list"), "{:#}", error);
+        assert_eq!(listing_runs() - before, 2);
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::manifest::{ExtractionManifest, MANIFEST_FILE_NAME};
+use super::pbo_diff::{EntryChange, PboDiff};
+use super::types::PboContents;
+use crate::utils::{move_dir, native_entry_path, replace_dir, sha256_file};
+
+/// Earlier extraction of a PBO kept next to its output, in `<base>.prev<number>`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetainedVersion {
+    /// 1 for the output replaced last, counting up to older ones
+    pub number: usize,
+    /// Hash of the PBO this version was extracted from
+    pub hash: String,
+    pub extracted_at: u64,
+}
+
+/// Directory version `number` of the output in `base_dir` is kept in
+pub fn version_dir(base_dir: &Path, number: usize) -> PathBuf {
+    let mut name = base_dir.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".prev{}", number));
+    base_dir.with_file_name(name)
+}
+
+/// Output directory and version number of a directory named like `version_dir` names them
+pub(crate) fn parse_version_dir(dir: &Path) -> Option<(PathBuf, usize)> {
+    let name = dir.file_name()?.to_str()?;
+    let (base_name, number) = name.rsplit_once(".prev")?;
+    if base_name.is_empty() || number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((dir.with_file_name(base_name), number.parse().ok().filter(|number| *number > 0)?))
+}
+
+/// Versions kept of the output in `base_dir`, newest first
+///
+/// Only directories with a readable manifest count; numbers may have gaps where
+/// a rotation was interrupted.
+pub fn retained_versions(base_dir: &Path) -> Vec<RetainedVersion> {
+    let Some(parent) = base_dir.parent() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return Vec::new();
+    };
+    let mut versions: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let (base, number) = parse_version_dir(&entry.path())?;
+            if base != base_dir {
+                return None;
+            }
+            let manifest = ExtractionManifest::read(&entry.path()).ok()?;
+            Some(RetainedVersion { number, hash: manifest.hash, extracted_at: manifest.extracted_at })
+        })
+        .collect();
+    versions.sort_by_key(|version| version.number);
+    versions
+}
+
+/// Every output below `cache_dir` with retained versions, by output directory
+pub fn find_retained_versions(cache_dir: &Path) -> Result<BTreeMap<PathBuf, Vec<RetainedVersion>>> {
+    let mut outputs = BTreeMap::new();
+    let mut walker = walkdir::WalkDir::new(cache_dir).sort_by_file_name().into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = entry.with_context(|| format!("Failed to walk {}", cache_dir.display()))?;
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        if let Some((base_dir, _)) = parse_version_dir(entry.path()) {
+            outputs.entry(base_dir.clone()).or_insert_with(|| retained_versions(&base_dir));
+            walker.skip_current_dir();
+        } else if entry.path().join(MANIFEST_FILE_NAME).is_file() {
+            walker.skip_current_dir();
+        }
+    }
+    outputs.retain(|_, versions| !versions.is_empty());
+    Ok(outputs)
+}
+
+/// Remove the versions of the output in `base_dir` numbered above `keep`, returning
+/// the removed directories
+pub fn prune_versions(base_dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+    let Some(parent) = base_dir.parent().filter(|parent| parent.is_dir()) else {
+        return Ok(Vec::new());
+    };
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(parent)? {
+        let dir = entry?.path();
+        if parse_version_dir(&dir).is_some_and(|(base, number)| base == base_dir && number > keep) {
+            std::fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+            removed.push(dir);
+        }
+    }
+    removed.sort();
+    Ok(removed)
+}
+
+/// Put the directory `from` in place at `to` like `replace_dir`, keeping the
+/// existing output as version 1 and up to `keep` versions in all
+///
+/// Older versions move up a number and the oldest one makes room first. The
+/// existing output only ever moves, to version 1 and back if moving `from` in
+/// fails, so an interrupted replacement can't lose both the old and the new output.
+pub fn replace_retaining(from: &Path, to: &Path, keep: usize) -> Result<()> {
+    if keep == 0 || !to.exists() {
+        return replace_dir(from, to);
+    }
+
+    prune_versions(to, keep - 1)?;
+    for number in (1..keep).rev() {
+        let dir = version_dir(to, number);
+        if dir.exists() {
+            std::fs::rename(&dir, version_dir(to, number + 1))
+                .with_context(|| format!("Failed to rotate {}", dir.display()))?;
+        }
+    }
+    let previous = version_dir(to, 1);
+    std::fs::rename(to, &previous)
+        .with_context(|| format!("Failed to move aside old output: {}", to.display()))?;
+
+    if let Err(e) = move_dir(from, to) {
+        let _ = std::fs::remove_dir_all(to);
+        let _ = std::fs::rename(&previous, to);
+        return Err(e.context(format!("Failed to move {} into place", to.display())));
+    }
+    Ok(())
+}
+
+/// Differences from version `number` of the output in `base_dir` to the current output
+///
+/// Files are compared as the manifests of both list them, by path relative to the
+/// base directory. Unlike listings of two PBOs, both versions are on disk, so files
+/// of the same size are compared by content too.
+pub fn diff_version(base_dir: &Path, number: usize) -> Result<PboDiff> {
+    let old_dir = version_dir(base_dir, number);
+    let old = ExtractionManifest::read(&old_dir)
+        .with_context(|| format!("No version {} of {}", number, base_dir.display()))?;
+    let new = ExtractionManifest::read(base_dir)?;
+    let mut diff = PboDiff::from_contents(&manifest_contents(&old), &manifest_contents(&new));
+
+    let old_files: HashMap<String, _> = old.files.iter().map(|file| (file.path.to_lowercase(), file)).collect();
+    for file in &new.files {
+        let Some(old_file) = old_files.get(&file.path.to_lowercase()) else {
+            continue;
+        };
+        if old_file.size != file.size {
+            continue;
+        }
+        let old_hash = sha256_file(&old_dir.join(native_entry_path(&old_file.path)?))?;
+        if old_hash != sha256_file(&base_dir.join(native_entry_path(&file.path)?))? {
+            diff.changed.push(EntryChange { path: file.path.clone(), old_size: Some(file.size), new_size: Some(file.size) });
+        }
+    }
+    diff.changed.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(diff)
+}
+
+fn manifest_contents(manifest: &ExtractionManifest) -> PboContents {
+    PboContents {
+        prefix: manifest.prefix.clone(),
+        entries: manifest.files.iter().map(|file| file.path.clone()).collect(),
+        entry_sizes: manifest.files.iter().map(|file| (file.path.clone(), file.size)).collect(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::types::PboEntry;
+    use super::super::test_utils::write_file;
+    use tempfile::TempDir;
+
+    /// Write an output with the given files and its manifest, recording `hash`
+    fn output(base_dir: &Path, hash: &str, files: &[(&str, &str)]) {
+        std::fs::create_dir_all(base_dir).unwrap();
+        for (path, contents) in files {
+            write_file(&base_dir.join(path), contents);
+        }
+        ExtractionManifest::collect(base_dir, Path::new("ace_medical.pbo"), hash.to_string(), None, "")
+            .unwrap()
+            .write(base_dir)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rotation_keeps_newest_versions() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().join("ace_medical");
+        let staging = temp_dir.path().join(".staging");
+        output(&base_dir, "v1", &[("config.cpp", "1")]);
+        for hash in ["v2", "v3", "v4"] {
+            output(&staging, hash, &[("config.cpp", hash)]);
+            replace_retaining(&staging, &base_dir, 2).unwrap();
+        }
+
+        let hashes: Vec<_> = retained_versions(&base_dir).into_iter().map(|version| (version.number, version.hash)).collect();
+        assert_eq!(hashes, vec![(1, "v3".to_string()), (2, "v2".to_string())]);
+        assert_eq!(ExtractionManifest::read(&base_dir).unwrap().hash, "v4");
+        assert!(!staging.exists());
+
+        let found = find_retained_versions(temp_dir.path()).unwrap();
+        assert_eq!(found.keys().collect::<Vec<_>>(), vec![&base_dir]);
+        assert_eq!(prune_versions(&base_dir, 1).unwrap(), vec![version_dir(&base_dir, 2)]);
+        assert_eq!(retained_versions(&base_dir).len(), 1);
+        assert_eq!(version_dir(&base_dir, 1), temp_dir.path().join("ace_medical.prev1"));
+    }
+
+    #[test]
+    fn test_failed_move_restores_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().join("ace_medical");
+        output(&base_dir, "v1", &[("config.cpp", "1")]);
+
+        assert!(replace_retaining(&temp_dir.path().join("missing"), &base_dir, 1).is_err());
+        assert_eq!(ExtractionManifest::read(&base_dir).unwrap().hash, "v1");
+        assert!(retained_versions(&base_dir).is_empty());
+    }
+
+    #[test]
+    fn test_diff_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_dir = temp_dir.path().join("ace_medical");
+        let staging = temp_dir.path().join(".staging");
+        output(&base_dir, "v1", &[("config.cpp", "class A"), ("fnc_heal.sqf", "heal"), ("old.sqf", "x")]);
+        output(&staging, "v2", &[("config.cpp", "class B"), ("fnc_heal.sqf", "heal"), ("new.sqf", "y")]);
+        replace_retaining(&staging, &base_dir, 1).unwrap();
+
+        let diff = diff_version(&base_dir, 1).unwrap();
+        let paths = |entries: &[PboEntry]| entries.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&diff.added), vec!["new.sqf"]);
+        assert_eq!(paths(&diff.removed), vec!["old.sqf"]);
+        assert_eq!(diff.changed, vec![EntryChange { path: "config.cpp".to_string(), old_size: Some(7), new_size: Some(7) }]);
+        assert!(diff_version(&base_dir, 2).is_err());
+    }
+}
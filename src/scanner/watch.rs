@@ -0,0 +1,423 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use glob::Pattern;
+use log::{debug, info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::time::Instant;
+
+use crate::error::ExtractionError;
+use crate::scanner::cache_lock::CacheLock;
+use crate::scanner::cancel::SignalHandler;
+use crate::scanner::filter::{PathPatterns, PboFilter};
+use crate::scanner::index::{ExtractionIndex, INDEX_FILE_NAME};
+use crate::scanner::manifest::MANIFEST_FILE_NAME;
+use crate::scanner::types::WalkOptions;
+use crate::extraction::scan_coordinator;
+use crate::{extract_pbos, ExtractionConfig};
+
+/// How often a watch checks its cancel token while waiting for changes
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Options of `watch_pbos`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// Time without further changes before changed PBOs are extracted, so PBOs an
+    /// update is still writing are extracted once it's done
+    pub quiet_period: Duration,
+    /// Remove the output directories (or tar archives) and index entries of deleted
+    /// PBOs. Only directories holding an extraction manifest are removed
+    pub prune_deleted: bool,
+    /// Run over the whole input directory before watching, catching up with changes
+    /// made while nothing was watching
+    pub initial_run: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            quiet_period: Duration::from_secs(10),
+            prune_deleted: false,
+            initial_run: true,
+        }
+    }
+}
+
+/// PBOs changed since the last extraction, relative to the input directory
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PendingChanges {
+    changed: BTreeSet<PathBuf>,
+    deleted: BTreeSet<PathBuf>,
+    /// Events were lost, so the whole input directory is extracted
+    rescan: bool,
+}
+
+impl PendingChanges {
+    fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.deleted.is_empty() && !self.rescan
+    }
+
+    fn change(&mut self, rel_path: PathBuf) {
+        self.deleted.remove(&rel_path);
+        self.changed.insert(rel_path);
+    }
+
+    fn delete(&mut self, rel_path: PathBuf) {
+        self.changed.remove(&rel_path);
+        self.deleted.insert(rel_path);
+    }
+}
+
+/// Decides which file system events concern PBOs a run would process
+struct WatchFilter {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    walk_options: WalkOptions,
+    ignore_dirs: PathPatterns,
+    pbo_filter: PboFilter,
+}
+
+impl WatchFilter {
+    fn new(config: &ExtractionConfig<'_>, input_dir: PathBuf) -> Result<Self> {
+        Ok(Self {
+            input_dir,
+            output_dir: config.output_dir.canonicalize().unwrap_or_else(|_| config.output_dir.to_owned()),
+            walk_options: config.walk_options(),
+            ignore_dirs: PathPatterns::new(&config.ignore_dirs, "ignore directory")?,
+            pbo_filter: PboFilter::new(&config.pbo_include, &config.pbo_exclude)?,
+        })
+    }
+
+    /// `path` relative to the input directory, if it's a PBO a run would pick up
+    fn relative_pbo(&self, path: &Path) -> Option<PathBuf> {
+        if path.starts_with(&self.output_dir) || !self.walk_options.is_archive(path) {
+            return None;
+        }
+        let rel_path = path.strip_prefix(&self.input_dir).ok()?;
+        if self.walk_options.max_depth.is_some_and(|max_depth| rel_path.components().count() > max_depth) {
+            return None;
+        }
+        let ignored = !self.ignore_dirs.is_empty() && rel_path.ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| self.ignore_dirs.matches(dir));
+        (!ignored && self.pbo_filter.matches(rel_path)).then(|| rel_path.to_owned())
+    }
+
+    /// Add the PBOs `event` created, modified or removed to `pending`
+    ///
+    /// Whether a PBO changed or was deleted is decided by whether it exists now,
+    /// which also covers renames and events arriving late.
+    fn record(&self, event: notify::Event, pending: &mut PendingChanges) {
+        if event.need_rescan() {
+            pending.rescan = true;
+        }
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in event.paths {
+            if path.is_dir() {
+                // Directories moved or copied in bring PBOs no event was sent for
+                let pbos = walkdir::WalkDir::new(&path)
+                    .into_iter()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_file())
+                    .filter_map(|entry| self.relative_pbo(entry.path()));
+                for rel_path in pbos {
+                    pending.change(rel_path);
+                }
+            } else if let Some(rel_path) = self.relative_pbo(&path) {
+                match path.is_file() {
+                    true => pending.change(rel_path),
+                    false => pending.delete(rel_path),
+                }
+            }
+        }
+    }
+}
+
+/// Keep the cache in sync with the input directory, extracting PBOs as they are
+/// created or modified
+///
+/// Changes are collected until `options.quiet_period` passes without further ones,
+/// then the changed PBOs go through the usual pipeline of `extract_pbos`, restricted
+/// to them. Unchanged PBOs are skipped by hash as usual, and the extraction index,
+/// when kept, is updated for the changed PBOs only. Changes made while an
+/// extraction runs are queued for the next one, and PBOs deferred because of
+/// `min_age` are retried after another quiet period.
+///
+/// Runs until `config.cancel` is cancelled, returning once the extraction in
+/// progress, if any, stopped. Failing runs are logged and watching goes on; only
+/// the initial run's failures are returned.
+pub async fn watch_pbos(config: ExtractionConfig<'_>, options: WatchOptions) -> Result<()> {
+    if !config.input_dir.is_dir() {
+        return Err(ExtractionError::InputDirNotFound(config.input_dir.to_owned()).into());
+    }
+    let input_dir = config.input_dir.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", config.input_dir.display()))?;
+    // Before resolving it, so events for files extracted into it are told apart
+    if let Err(source) = std::fs::create_dir_all(config.output_dir) {
+        return Err(ExtractionError::OutputNotWritable { path: config.output_dir.to_owned(), source }.into());
+    }
+    let filter = WatchFilter::new(&config, input_dir.clone())?;
+
+    // Once for the whole watch, as the handler can't be installed again
+    let _signals = match config.handle_signals {
+        true => Some(SignalHandler::install(config.cancel.clone())?),
+        false => None,
+    };
+    let config = ExtractionConfig { handle_signals: false, ..config };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })
+    .context("Failed to set up watching for changed PBOs")?;
+    watcher.watch(&input_dir, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", input_dir.display()))?;
+    info!("Watching {} for changed PBOs", input_dir.display());
+
+    let mut pending = PendingChanges::default();
+    if options.initial_run {
+        match extract_changes(&config, &options, &filter, PendingChanges { rescan: true, ..Default::default() }).await? {
+            Some(deferred) => pending.changed.extend(deferred),
+            None => return Ok(()),
+        }
+    }
+
+    let mut poll = tokio::time::interval(CANCEL_POLL_INTERVAL);
+    let mut quiet_until = (!pending.is_empty()).then(|| Instant::now() + options.quiet_period);
+    while !config.cancel.is_cancelled() {
+        let quiet = async {
+            match quiet_until {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(Ok(event)) => {
+                    filter.record(event, &mut pending);
+                    if !pending.is_empty() {
+                        quiet_until = Some(Instant::now() + options.quiet_period);
+                    }
+                },
+                Some(Err(e)) => warn!("Error watching {}: {}", input_dir.display(), e),
+                None => break,
+            },
+            _ = quiet => {
+                let changes = std::mem::take(&mut pending);
+                quiet_until = None;
+                match extract_changes(&config, &options, &filter, changes).await {
+                    Ok(Some(deferred)) if deferred.is_empty() => {},
+                    Ok(Some(deferred)) => {
+                        debug!("Retrying {} deferred PBOs after the next quiet period", deferred.len());
+                        pending.changed.extend(deferred);
+                        quiet_until = Some(Instant::now() + options.quiet_period);
+                    },
+                    Ok(None) => break,
+                    Err(e) => warn!("Failed to extract changed PBOs: {:#}", e),
+                }
+            },
+            _ = poll.tick() => {},
+        }
+    }
+    info!("Stopped watching {}", input_dir.display());
+    Ok(())
+}
+
+/// Prune deleted PBOs and extract changed ones
+///
+/// Returns the PBOs deferred by the run, to be tried again later, or `None` when
+/// the run was cancelled.
+async fn extract_changes(
+    config: &ExtractionConfig<'_>,
+    options: &WatchOptions,
+    filter: &WatchFilter,
+    changes: PendingChanges,
+) -> Result<Option<Vec<PathBuf>>, ExtractionError> {
+    if options.prune_deleted && !changes.deleted.is_empty() {
+        if let Err(e) = prune_deleted(config, &changes.deleted) {
+            warn!("Failed to prune the output of deleted PBOs: {:#}", e);
+        }
+    }
+    let changed: Vec<_> = changes.changed.iter()
+        .filter(|rel_path| filter.input_dir.join(rel_path).is_file())
+        .collect();
+    if !changes.rescan && changed.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+
+    let run_config = match changes.rescan {
+        true => config.clone(),
+        false => {
+            info!("Extracting {} changed PBOs", changed.len());
+            // The watch filter already applied the configured patterns
+            let pbo_include = changed.iter()
+                .map(|rel_path| Pattern::escape(&rel_path.to_string_lossy().replace('\\', "/")))
+                .collect();
+            ExtractionConfig { pbo_include, ..config.clone() }
+        },
+    };
+    match extract_pbos(run_config).await {
+        Ok(report) => Ok(Some(report.deferred)),
+        Err(ExtractionError::PartialFailure(report)) => Ok(Some(report.deferred)),
+        Err(ExtractionError::Cancelled(_)) => Ok(None),
+        // Nothing to watch yet, or deleted again since the event
+        Err(ExtractionError::NoPbosFound { .. }) => Ok(Some(Vec::new())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove the cache output and index entries of PBOs deleted from the input directory
+fn prune_deleted(config: &ExtractionConfig<'_>, deleted: &BTreeSet<PathBuf>) -> Result<()> {
+    let _lock = CacheLock::exclusive(config.output_dir, config.cache_lock, &config.cancel)?;
+    let filter = config.file_filter();
+    let rel_paths: Vec<_> = deleted.iter().cloned().collect();
+    // Found where the run extracting them put them, next to colliding PBOs
+    let base_dirs = scan_coordinator(config, &filter, None)?.base_output_dirs(&rel_paths)?;
+    for (rel_path, base_dir) in rel_paths.iter().zip(base_dirs) {
+        if base_dir.join(MANIFEST_FILE_NAME).is_file() {
+            std::fs::remove_dir_all(&base_dir).with_context(|| format!("Failed to remove {}", base_dir.display()))?;
+            info!("Removed the output of deleted {}", rel_path.display());
+        }
+        if let Some(archive) = config.tar_output.map(|tar| tar.archive_path(&base_dir)).filter(|archive| archive.is_file()) {
+            std::fs::remove_file(&archive).with_context(|| format!("Failed to remove {}", archive.display()))?;
+            info!("Removed the archive of deleted {}", rel_path.display());
+        }
+    }
+
+    let index_path = config.output_dir.join(INDEX_FILE_NAME);
+    if index_path.is_file() {
+        let mut index = ExtractionIndex::load(&index_path)?;
+        index.retain_sources(|source| !deleted.contains(source));
+        index.write(&index_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::events::ExtractionEvent;
+    use crate::scanner::types::SkipReason;
+    use tempfile::TempDir;
+
+    fn event(kind: EventKind, paths: &[&Path]) -> notify::Event {
+        paths.iter().fold(notify::Event::new(kind), |event, path| event.add_path(path.to_path_buf()))
+    }
+
+    #[test]
+    fn test_record_changes_and_deletions() {
+        let input_dir = TempDir::new().unwrap();
+        let input = input_dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(input.join("@ace/addons")).unwrap();
+        std::fs::create_dir_all(input.join("@ace/keys")).unwrap();
+        std::fs::write(input.join("@ace/addons/ace_medical.pbo"), "pbo").unwrap();
+        std::fs::write(input.join("@ace/keys/ace.pbo"), "pbo").unwrap();
+        let cache = input.join("cache");
+        let config = ExtractionConfig {
+            ignore_dirs: WalkOptions::default_ignore_dirs(),
+            pbo_exclude: vec!["*_optional.pbo".to_string()],
+            ..ExtractionConfig::new(&input, &cache)
+        };
+        let filter = WatchFilter::new(&config, input.clone()).unwrap();
+        let mut pending = PendingChanges::default();
+
+        let create = EventKind::Create(notify::event::CreateKind::File);
+        filter.record(event(create, &[&input.join("@ace/addons/ace_medical.pbo")]), &mut pending);
+        filter.record(event(create, &[&input.join("@ace/keys/ace.pbo")]), &mut pending);
+        filter.record(event(create, &[&input.join("@ace/addons/ace_medical.bisign")]), &mut pending);
+        filter.record(event(create, &[&input.join("@ace/addons/ace_optional.pbo")]), &mut pending);
+        filter.record(event(create, &[&input.join("cache/ace_medical.pbo")]), &mut pending);
+        let read = EventKind::Access(notify::event::AccessKind::Any);
+        filter.record(event(read, &[&input.join("@ace/addons/ace_common.pbo")]), &mut pending);
+        assert_eq!(pending.changed, BTreeSet::from([PathBuf::from("@ace/addons/ace_medical.pbo")]));
+
+        // Renamed away: the old name no longer exists
+        let rename = EventKind::Modify(notify::event::ModifyKind::Name(notify::event::RenameMode::Both));
+        std::fs::rename(input.join("@ace/addons/ace_medical.pbo"), input.join("@ace/addons/ace_medical2.pbo")).unwrap();
+        filter.record(
+            event(rename, &[&input.join("@ace/addons/ace_medical.pbo"), &input.join("@ace/addons/ace_medical2.pbo")]),
+            &mut pending,
+        );
+        assert_eq!(pending.changed, BTreeSet::from([PathBuf::from("@ace/addons/ace_medical2.pbo")]));
+        assert_eq!(pending.deleted, BTreeSet::from([PathBuf::from("@ace/addons/ace_medical.pbo")]));
+
+        // A whole mod moved in
+        std::fs::create_dir_all(input.join("@cba/addons")).unwrap();
+        std::fs::write(input.join("@cba/addons/cba_main.pbo"), "pbo").unwrap();
+        filter.record(event(create, &[&input.join("@cba")]), &mut pending);
+        assert!(pending.changed.contains(Path::new("@cba/addons/cba_main.pbo")));
+        assert!(!pending.rescan);
+    }
+
+    #[test]
+    fn test_prune_deleted_outputs() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let cache = cache_dir.path();
+        // Its output moved aside for the directory next to it
+        std::fs::create_dir_all(input_dir.path().join("@ace/addons/ace_common")).unwrap();
+        std::fs::write(input_dir.path().join("@ace/addons/ace_common/extra.pbo"), "pbo").unwrap();
+        for dir in ["@ace/addons/ace_medical", "@ace/addons/ace_common.pbo.d", "@ace/addons/unmanaged"] {
+            std::fs::create_dir_all(cache.join(dir)).unwrap();
+            std::fs::write(cache.join(dir).join("config.cpp"), "class CfgPatches {};").unwrap();
+        }
+        std::fs::write(cache.join("@ace/addons/ace_medical").join(MANIFEST_FILE_NAME), "{}").unwrap();
+        std::fs::write(cache.join("@ace/addons/ace_common.pbo.d").join(MANIFEST_FILE_NAME), "{}").unwrap();
+        ExtractionIndex::new(cache).write(&cache.join(INDEX_FILE_NAME)).unwrap();
+
+        let config = ExtractionConfig::new(input_dir.path(), cache);
+        let deleted = ["ace_medical", "ace_common", "unmanaged"].iter()
+            .map(|name| PathBuf::from(format!("@ace/addons/{}.pbo", name)))
+            .collect();
+        prune_deleted(&config, &deleted).unwrap();
+        assert!(!cache.join("@ace/addons/ace_medical").exists());
+        assert!(!cache.join("@ace/addons/ace_common.pbo.d").exists());
+        // Not written by an extraction, so left alone
+        assert!(cache.join("@ace/addons/unmanaged/config.cpp").exists());
+        assert!(cache.join(INDEX_FILE_NAME).is_file());
+    }
+
+    #[tokio::test]
+    async fn test_watch_extracts_new_pbos_until_cancelled() {
+        let input_dir = TempDir::new().unwrap();
+        let cache_dir = TempDir::new().unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let config = ExtractionConfig {
+            events: Some(tx),
+            output_mode: crate::scanner::progress::OutputMode::Silent,
+            skip_backend_check: true,
+            ..ExtractionConfig::new(input_dir.path(), cache_dir.path())
+        };
+        let cancel = config.cancel.clone();
+        let options = WatchOptions { quiet_period: Duration::from_millis(100), ..Default::default() };
+
+        let changes = async {
+            // Not a PBO inside, so the run skips it as invalid without the tool
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            std::fs::write(input_dir.path().join("broken.pbo"), "not a PBO").unwrap();
+            let skipped = tokio::time::timeout(Duration::from_secs(10), async {
+                while let Some(event) = rx.recv().await {
+                    if let ExtractionEvent::PboSkipped { path, reason } = event {
+                        return Some((path, reason));
+                    }
+                }
+                None
+            })
+            .await;
+            cancel.cancel();
+            skipped
+        };
+        let (watched, skipped) = tokio::time::timeout(
+            Duration::from_secs(15),
+            async { tokio::join!(watch_pbos(config, options), changes) },
+        )
+        .await
+        .unwrap();
+        watched.unwrap();
+        assert_eq!(skipped.unwrap(), Some((PathBuf::from("broken.pbo"), SkipReason::InvalidFormat)));
+    }
+}
@@ -1,7 +1,2 @@
-use std::path::PathBuf;
-
-#[derive(Debug)]
-pub struct PboScanResult {
-    pub path: PathBuf,
-    pub expected_files: Vec<String>,
-}
\ No newline at end of file
+#[allow(unused_imports)]
+pub use crate::scanner::types::{PboEntry, PboScanResult};
\ No newline at end of file
@@ -1,10 +1,16 @@
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use sha2::{Sha256, Digest};
 use std::fs::{File, metadata};
 use std::io::Read;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Deserialize;
 use std::time::SystemTime;
 
+/// File name of the checksum list written into each extracted PBO's base directory
+pub const CHECKSUM_FILE_NAME: &str = "SHA256SUMS";
+
 /// Calculate a fast hash of a file based on metadata and partial content
 /// 
 /// This function creates a hash based on:
@@ -16,8 +22,30 @@ use std::time::SystemTime;
 /// reasonably accurate for detecting changes.
 pub fn calculate_file_hash(path: &Path) -> Result<String> {
     let meta = metadata(path)?;
-    let file_size = meta.len();
     
+    // Read first 4KB of file content
+    let mut file = File::open(path)?;
+    let mut buffer = [0; 4096];
+    let bytes_read = file.read(&mut buffer)?;
+    
+    fast_hash(&meta, &buffer[..bytes_read])
+}
+
+/// `calculate_file_hash` reading through `tokio::fs`, so the file I/O runs on the
+/// runtime's blocking threads and other tasks keep running meanwhile
+pub async fn calculate_file_hash_async(path: &Path) -> Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    let meta = tokio::fs::metadata(path).await?;
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = [0; 4096];
+    let bytes_read = file.read(&mut buffer).await?;
+
+    fast_hash(&meta, &buffer[..bytes_read])
+}
+
+/// Hash of a file's size, modification time and first bytes, `head`
+fn fast_hash(meta: &std::fs::Metadata, head: &[u8]) -> Result<String> {
     // Get modification time as seconds since UNIX epoch
     let modified = meta.modified()?
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -27,24 +55,84 @@ pub fn calculate_file_hash(path: &Path) -> Result<String> {
     let mut hasher = Sha256::new();
     
     // Add file metadata to hash
-    hasher.update(file_size.to_string().as_bytes());
+    hasher.update(meta.len().to_string().as_bytes());
     hasher.update(modified.to_string().as_bytes());
+    hasher.update(head);
     
-    // Read first 4KB of file content
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Calculate the SHA-256 of a file's full content, reading it in chunks
+pub fn sha256_file(path: &Path) -> Result<String> {
     let mut file = File::open(path)?;
-    let mut buffer = [0; 4096];
-    let bytes_read = file.read(&mut buffer)?;
-    
-    if bytes_read > 0 {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
         hasher.update(&buffer[..bytes_read]);
     }
-    
+
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// File whose content no longer matches its recorded checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    /// Path relative to the verified directory
+    pub path: String,
+    pub expected: String,
+    /// Current checksum, or `None` if the file is missing or unreadable
+    pub actual: Option<String>,
+}
+
+/// Re-check the files listed in a directory's `SHA256SUMS`
+///
+/// Returns the files that are missing or whose content changed.
+pub fn verify_checksums(dir: &Path) -> Result<Vec<ChecksumMismatch>> {
+    let path = dir.join(CHECKSUM_FILE_NAME);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read checksums: {}", path.display()))?;
+
+    let entries = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (expected, rel_path) = line.split_once(' ')
+                .with_context(|| format!("Malformed checksum line: {}", line))?;
+            // `sha256sum` marks binary mode with `*` in place of the second space
+            let rel_path = rel_path.strip_prefix([' ', '*']).unwrap_or(rel_path);
+            Ok((expected.to_lowercase(), rel_path.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut mismatches: Vec<_> = entries
+        .par_iter()
+        .filter_map(|(expected, rel_path)| {
+            let actual = sha256_file(&dir.join(rel_path)).ok();
+            (actual.as_ref() != Some(expected)).then(|| ChecksumMismatch {
+                path: rel_path.clone(),
+                expected: expected.clone(),
+                actual,
+            })
+        })
+        .collect();
+    mismatches.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(mismatches)
+}
+
+/// Whether a comma-separated extension list matches every file: empty, or `*`
+pub fn matches_every_extension(extensions: &str) -> bool {
+    matches!(extensions.trim(), "" | "*")
+}
+
 /// Check if a file extension matches any in a comma-separated list
 pub fn matches_extension(path: &Path, extensions: &str) -> bool {
-    if extensions.is_empty() {
+    if matches_every_extension(extensions) {
         return true;
     }
 
@@ -59,6 +147,591 @@ pub fn matches_extension(path: &Path, extensions: &str) -> bool {
     }
 }
 
+/// Normalize a PBO-internal path to forward slashes without leading separators
+pub fn normalize_entry_path(path: &str) -> String {
+    path.replace('\\', "/").trim_start_matches('/').to_string()
+}
+
+/// Relative path of a PBO-internal path with platform-native separators
+///
+/// Accepts either separator, so prefixes like `z\\ace\\addons\\medical` become nested
+/// directories on every platform instead of a single name containing backslashes.
+/// Leading separators are stripped; `..` components and drive letters are rejected,
+/// since joining them would leave the directory the path is joined to.
+pub fn native_entry_path(path: &str) -> Result<PathBuf> {
+    let mut native = PathBuf::new();
+    for component in normalize_entry_path(path).split('/') {
+        match component {
+            "" | "." => continue,
+            ".." => anyhow::bail!("Path leaves its parent directory: {}", path),
+            _ if component.contains(':') => anyhow::bail!("Path contains a drive or stream separator: {}", path),
+            _ => native.push(component),
+        }
+    }
+    Ok(native)
+}
+
+/// How entry names that can't be written on Windows are handled
+///
+/// Affected are the characters `<>:"|?*`, control characters, and trailing dots
+/// or spaces in any path component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameSanitization {
+    /// Skip PBOs containing such names as invalid
+    Error,
+    /// Replace each offending character with `_`
+    #[default]
+    Replace,
+    /// Replace each offending character with its `%XX` encoding
+    ///
+    /// `%` itself is encoded as `%25` in every name, so encoded names decode back
+    /// to the original.
+    PercentEncode,
+}
+
+/// Characters that can't appear in a Windows file name
+const FORBIDDEN_NAME_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Make every component of a PBO-internal path writable on Windows
+///
+/// The result uses forward slashes. `.` and `..` components are left for
+/// `native_entry_path` to deal with. With `NameSanitization::Error`, a path that
+/// would need changes is an error.
+pub fn sanitize_entry_path(path: &str, mode: NameSanitization) -> Result<String> {
+    let normalized = normalize_entry_path(path);
+    let sanitized = normalized
+        .split('/')
+        .map(|component| sanitize_component(component, mode))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if mode == NameSanitization::Error && sanitized != normalized {
+        anyhow::bail!("Entry name is not valid on Windows: {}", path);
+    }
+    Ok(sanitized)
+}
+
+fn sanitize_component(component: &str, mode: NameSanitization) -> String {
+    if component == "." || component == ".." {
+        return component.to_string();
+    }
+
+    // Windows silently drops trailing dots and spaces, so they are replaced as well
+    let kept = component.trim_end_matches(['.', ' ']).len();
+    let mut sanitized = String::with_capacity(component.len());
+    for (i, c) in component.char_indices() {
+        if FORBIDDEN_NAME_CHARS.contains(&c) || c.is_ascii_control() || i >= kept {
+            match mode {
+                NameSanitization::PercentEncode => sanitized.push_str(&format!("%{:02X}", c as u32)),
+                NameSanitization::Error | NameSanitization::Replace => sanitized.push('_'),
+            }
+        } else if c == '%' && mode == NameSanitization::PercentEncode {
+            sanitized.push_str("%25");
+        } else {
+            sanitized.push(c);
+        }
+    }
+    sanitized
+}
+
+/// Whether a path component is a Windows device name like `CON`, `aux.sqf` or `LPT1.txt`
+///
+/// Device names are matched case-insensitively and stay reserved with any extension.
+pub fn is_reserved_name(component: &str) -> bool {
+    let stem = component.split('.').next().unwrap_or_default().trim_end_matches(' ');
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            let (kind, digit) = upper.split_at(upper.len().min(3));
+            (kind == "COM" || kind == "LPT") && matches!(digit.as_bytes(), [b'1'..=b'9'])
+        }
+    }
+}
+
+/// Normalized PBO-internal path with an underscore in front of every reserved component
+pub fn escape_reserved_names(path: &str) -> String {
+    normalize_entry_path(path)
+        .split('/')
+        .map(|component| {
+            if is_reserved_name(component) {
+                format!("_{}", component)
+            } else {
+                component.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Extended-length form of a path on Windows, so deep prefix hierarchies aren't
+/// limited to `MAX_PATH`
+///
+/// The path is made absolute and normalized first, since the `\\?\` form
+/// disables any further processing by Windows. Other platforms get the path as is.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let raw = path.to_string_lossy().into_owned();
+    if raw.starts_with(r"\\?\") {
+        path
+    } else if let Some(unc) = raw.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", unc))
+    } else {
+        PathBuf::from(format!(r"\\?\{}", raw))
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Regular form of a path made by `long_path`, for external tools that don't
+/// understand extended-length paths
+#[cfg(windows)]
+pub fn tool_path(path: &Path) -> PathBuf {
+    let raw = path.to_string_lossy();
+    if let Some(unc) = raw.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(local) = raw.strip_prefix(r"\\?\") {
+        PathBuf::from(local)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(not(windows))]
+pub fn tool_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Check that `path` resolves to a location inside `base`
+///
+/// Both paths must exist; symlinks are resolved before comparing.
+pub fn ensure_within(base: &Path, path: &Path) -> Result<()> {
+    let base = base.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", base.display()))?;
+    let resolved = path.canonicalize()
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    if !resolved.starts_with(&base) {
+        anyhow::bail!("{} resolves outside of {}", path.display(), base.display());
+    }
+    Ok(())
+}
+
+/// Remove files under a directory rejected by `keep`
+///
+/// `keep` receives each file's normalized relative path and size in bytes.
+/// Directories left empty afterwards are removed as well, but `dir` itself is kept.
+/// Returns the number of files removed.
+pub fn remove_unmatched_files<F>(dir: &Path, keep: F) -> Result<usize>
+where
+    F: Fn(&str, u64) -> bool,
+{
+    let mut removed = 0;
+
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let rel_path = entry.path().strip_prefix(dir)?;
+        let rel_path = normalize_entry_path(&rel_path.to_string_lossy());
+        let size = entry.metadata()?.len();
+        if !keep(&rel_path, size) {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    remove_empty_dirs(dir)?;
+    Ok(removed)
+}
+
+/// Rename every file below `dir` to its lowercase relative path
+///
+/// Files that are already lowercase keep their place; of several other files that
+/// differ only by case, the first in sorted order wins. Losing files are left under
+/// their original name rather than overwriting the winner. Directories emptied by
+/// the renames are removed. Returns the normalized relative paths of losing files.
+pub fn lowercase_tree(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(dir)?.to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+
+    let mut claimed: HashSet<String> = files.iter()
+        .filter(|rel_path| rel_path.to_lowercase() == **rel_path)
+        .cloned()
+        .collect();
+    let mut collisions = Vec::new();
+
+    for rel_path in &files {
+        let lower = rel_path.to_lowercase();
+        if lower == *rel_path {
+            continue;
+        }
+        if !claimed.insert(lower.clone()) {
+            collisions.push(normalize_entry_path(rel_path));
+            continue;
+        }
+
+        let target = dir.join(&lower);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(dir.join(rel_path), &target)
+            .with_context(|| format!("Failed to rename {} to lowercase", rel_path))?;
+    }
+
+    remove_empty_dirs(dir)?;
+    Ok(collisions)
+}
+
+/// Put the directory `from` in place at `to`, replacing any existing directory there
+///
+/// The old directory is first renamed aside next to `from` and only deleted once the
+/// new one is in place; if moving `from` fails, the old directory is restored.
+pub fn replace_dir(from: &Path, to: &Path) -> Result<()> {
+    let backup = if to.exists() {
+        let mut name = from.file_name().unwrap_or_default().to_os_string();
+        name.push(".old");
+        let backup = from.with_file_name(name);
+        if backup.exists() {
+            std::fs::remove_dir_all(&backup)?;
+        }
+        std::fs::rename(to, &backup)
+            .with_context(|| format!("Failed to move aside old output: {}", to.display()))?;
+        Some(backup)
+    } else {
+        None
+    };
+
+    if let Err(e) = move_dir(from, to) {
+        if let Some(backup) = &backup {
+            let _ = std::fs::remove_dir_all(to);
+            let _ = std::fs::rename(backup, to);
+        }
+        return Err(e.context(format!("Failed to move {} into place", to.display())));
+    }
+
+    if let Some(backup) = backup {
+        std::fs::remove_dir_all(backup)?;
+    }
+    Ok(())
+}
+
+/// Move a directory, copying it recursively when it has to cross devices
+pub fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_dir_all(from, to)?;
+            std::fs::remove_dir_all(from)?;
+            Ok(())
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Recursively copy the contents of `from` into `to`
+pub fn copy_dir_all(from: &Path, to: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hard-link `from` to `to`, copying instead where linking isn't possible
+pub fn link_or_copy(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::hard_link(from, to).is_err() {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// Write `contents` to a file next to `path` and rename it over `path`
+///
+/// Unlike `std::fs::write`, this never writes into the existing file, so hard links
+/// to it, like files carried over from the previous output, keep their content.
+pub fn write_replacing(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut partial_name = path.file_name().unwrap_or_default().to_owned();
+    partial_name.push(".partial");
+    let partial = path.with_file_name(partial_name);
+    // Written through too if it were a link left behind by an interrupted write
+    if partial.exists() {
+        std::fs::remove_file(&partial)?;
+    }
+    std::fs::write(&partial, contents).with_context(|| format!("Failed to write {}", partial.display()))?;
+    std::fs::rename(&partial, path).with_context(|| format!("Failed to move {} into place", path.display()))
+}
+
+/// Give the file at `path` a copy of its content of its own, so writing it in
+/// place leaves other hard links to the old content alone
+///
+/// Files without other links are left as they are. Link counts aren't available on
+/// stable Rust off Unix, so every file is copied there.
+pub fn unshare_file(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if std::fs::metadata(path)?.nlink() <= 1 {
+            return Ok(());
+        }
+    }
+    let mut copy = path.as_os_str().to_owned();
+    copy.push(".unshared");
+    let copy = PathBuf::from(copy);
+    std::fs::copy(path, &copy).with_context(|| format!("Failed to copy {}", path.display()))?;
+    std::fs::rename(&copy, path).with_context(|| format!("Failed to move {} over {}", copy.display(), path.display()))?;
+    Ok(())
+}
+
+/// Free space in bytes available to this process on the file system holding `path`
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid, writable statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space of {}", path.display()));
+    }
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+/// Free space in bytes available to this process on the volume holding `path`
+#[cfg(windows)]
+pub fn available_space(path: &Path) -> Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: `wide` is NUL-terminated and the unused outputs may be null
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut())
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space of {}", path.display()));
+    }
+    Ok(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn available_space(path: &Path) -> Result<u64> {
+    Err(anyhow::anyhow!("Free space of {} can't be queried on this platform", path.display()))
+}
+
+/// Remove empty directories below `dir`, keeping `dir` itself
+///
+/// Returns the number of directories removed.
+pub fn remove_empty_dirs(dir: &Path) -> Result<usize> {
+    let mut removed = 0;
+
+    // Children are visited before their parents so nested empty trees collapse fully
+    for entry in walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_dir() && std::fs::read_dir(entry.path())?.next().is_none() {
+            std::fs::remove_dir(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Thread pool of `threads` workers, for the parallel work of one extraction
+///
+/// Work installed in it stays off the global pool, which a host embedding the
+/// library may be using for its own work.
+pub fn build_thread_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|index| format!("extraction-{}", index))
+        .build()
+        .context("Failed to start extraction threads")
+}
+
+/// `build_thread_pool` for a run in background mode, whose workers run at lowered
+/// priority, see `lower_thread_priority`
+///
+/// Only the pool's own threads are lowered, never the caller's, and they go away
+/// with the pool.
+pub fn build_background_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|index| format!("extraction-{}", index))
+        .start_handler(|_| {
+            if let Err(e) = lower_thread_priority() {
+                WARNED.call_once(|| log::warn!("Running at normal priority: {:#}", e));
+            }
+        })
+        .build()
+        .context("Failed to start extraction threads")
+}
+
+/// Workers of a run in background mode with `threads` asked for: at most half the cores
+pub fn background_threads(threads: usize) -> usize {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    threads.clamp(1, (cores / 2).max(1))
+}
+
+/// Nice value the tool runs with in background mode on Unix
+#[cfg(unix)]
+const BACKGROUND_NICE: libc::c_int = 10;
+
+/// Lower the priority of the calling thread and of the processes it starts afterwards
+///
+/// The nice value is per thread on Linux, so only this thread and the tools it runs
+/// are reniced. Already nicer threads are left alone, and without privileges the
+/// priority can't be raised again, so it's meant for threads of their own, like
+/// those of `build_background_pool`.
+#[cfg(target_os = "linux")]
+pub fn lower_thread_priority() -> Result<()> {
+    renice()
+}
+
+/// Raise the nice value of `PRIO_PROCESS` 0 to `BACKGROUND_NICE`, unless it's nicer
+/// already: the calling thread on Linux, the whole process elsewhere
+#[cfg(unix)]
+fn renice() -> Result<()> {
+    // SAFETY: both only take the calling thread's or process's id, 0
+    let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+    if current >= BACKGROUND_NICE {
+        return Ok(());
+    }
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, BACKGROUND_NICE) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to lower the priority");
+    }
+    Ok(())
+}
+
+/// Lower the priority of the calling thread
+///
+/// Processes started by the thread don't take on a thread priority, so the tool
+/// keeps running at normal priority; lowering it would take the priority class of
+/// the whole process, which belongs to the host application, see
+/// `lower_process_priority`.
+#[cfg(windows)]
+pub fn lower_thread_priority() -> Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL};
+
+    // SAFETY: the pseudo handle of the current thread is always valid
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to lower the priority");
+    }
+    Ok(())
+}
+
+/// Other Unixes only renice whole processes, which belong to the host application,
+/// see `lower_process_priority`
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn lower_thread_priority() -> Result<()> {
+    Err(anyhow::anyhow!("Thread priorities can't be lowered on this platform"))
+}
+
+/// Lower the priority of the whole process and of the processes it starts afterwards
+///
+/// For programs of their own, like the command line tool in background mode, since
+/// only on Linux does `lower_thread_priority` reach the tool. On Windows the process
+/// takes the below normal priority class, which the tool inherits; on Linux only the
+/// calling thread and the threads and processes it starts afterwards are reniced.
+#[cfg(windows)]
+pub fn lower_process_priority() -> Result<()> {
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetPriorityClass, BELOW_NORMAL_PRIORITY_CLASS};
+
+    // SAFETY: the pseudo handle of the current process is always valid
+    if unsafe { SetPriorityClass(GetCurrentProcess(), BELOW_NORMAL_PRIORITY_CLASS) } == 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to lower the priority");
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn lower_process_priority() -> Result<()> {
+    renice()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn lower_process_priority() -> Result<()> {
+    Err(anyhow::anyhow!("Process priorities can't be lowered on this platform"))
+}
+
+/// Whether processes the calling thread starts run at lowered priority, like the
+/// tool does in background mode when its priority could be lowered
+///
+/// On Windows, that takes a lowered priority class of the whole process.
+#[cfg(windows)]
+pub fn tool_priority_lowered() -> bool {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentProcess,
+        GetPriorityClass,
+        BELOW_NORMAL_PRIORITY_CLASS,
+        IDLE_PRIORITY_CLASS,
+    };
+
+    // SAFETY: the pseudo handle of the current process is always valid
+    let class = unsafe { GetPriorityClass(GetCurrentProcess()) };
+    class == BELOW_NORMAL_PRIORITY_CLASS || class == IDLE_PRIORITY_CLASS
+}
+
+#[cfg(unix)]
+pub fn tool_priority_lowered() -> bool {
+    // SAFETY: only takes the calling thread's or process's id, 0
+    unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) >= BACKGROUND_NICE }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn tool_priority_lowered() -> bool {
+    false
+}
+
+/// Whether the process has a console, which console processes it starts share
+///
+/// Only Windows opens a console window for processes started without one, so
+/// elsewhere this is always true.
+#[cfg(windows)]
+pub fn console_attached() -> bool {
+    use windows_sys::Win32::System::Console::GetConsoleWindow;
+
+    // SAFETY: takes no arguments
+    !unsafe { GetConsoleWindow() }.is_null()
+}
+
+#[cfg(not(windows))]
+pub fn console_attached() -> bool {
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,10 +768,39 @@ mod tests {
         assert_ne!(hash, different_hash);
     }
 
+    #[test]
+    fn test_async_hash_leaves_runtime_free() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("ace_medical.pbo");
+        std::fs::write(&file_path, vec![7u8; 64 * 1024]).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let (hash, ticks) = runtime.block_on(async {
+            let ticks = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let ticker = tokio::spawn({
+                let ticks = ticks.clone();
+                async move {
+                    loop {
+                        ticks.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        tokio::task::yield_now().await;
+                    }
+                }
+            });
+            let hash = calculate_file_hash_async(&file_path).await.unwrap();
+            ticker.abort();
+            (hash, ticks.load(std::sync::atomic::Ordering::SeqCst))
+        });
+
+        // The only thread of the runtime ran the other task while the file was read
+        assert!(ticks > 0);
+        assert_eq!(hash, calculate_file_hash(&file_path).unwrap());
+    }
+
     #[test]
     fn test_matches_extension_empty_list() {
         let path = Path::new("test.txt");
         assert!(matches_extension(path, ""));
+        assert!(matches_extension(path, " * "));
     }
 
     #[test]
@@ -135,4 +837,287 @@ mod tests {
         let path = Path::new("test.txt");
         assert!(matches_extension(path, " txt , cpp "));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_normalize_entry_path() {
+        assert_eq!(normalize_entry_path("functions\\fnc_test.sqf"), "functions/fnc_test.sqf");
+        assert_eq!(normalize_entry_path("\\ui\\icon.paa"), "ui/icon.paa");
+        assert_eq!(normalize_entry_path("config.cpp"), "config.cpp");
+    }
+
+    #[test]
+    fn test_native_entry_path() {
+        let expected: PathBuf = ["z", "ace", "addons", "medical"].iter().collect();
+        assert_eq!(native_entry_path("z\\ace\\addons\\medical").unwrap(), expected);
+        assert_eq!(native_entry_path("\\z/ace\\addons//medical").unwrap(), expected);
+        assert_eq!(native_entry_path("/z/./ace/addons/medical").unwrap(), expected);
+        assert_eq!(Path::new("/cache").join(native_entry_path("").unwrap()), Path::new("/cache"));
+    }
+
+    #[test]
+    fn test_native_entry_path_rejects_traversal() {
+        for hostile in ["../evil", "..\\..", "z\\..\\..\\evil", "C:\\evil", "c:/evil", "config.cpp:stream"] {
+            assert!(native_entry_path(hostile).is_err(), "{hostile}");
+        }
+        assert!(native_entry_path("functions/..fnc_heal.sqf").is_ok());
+    }
+
+    #[test]
+    fn test_sanitize_entry_path_forbidden_characters() {
+        for c in ['<', '>', ':', '"', '|', '?', '*'] {
+            let name = format!("functions/fnc{}heal.sqf", c);
+            assert_eq!(sanitize_entry_path(&name, NameSanitization::Replace).unwrap(), "functions/fnc_heal.sqf");
+            assert_eq!(
+                sanitize_entry_path(&name, NameSanitization::PercentEncode).unwrap(),
+                format!("functions/fnc%{:02X}heal.sqf", c as u32)
+            );
+            assert!(sanitize_entry_path(&name, NameSanitization::Error).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_sanitize_entry_path_control_characters() {
+        for c in ('\u{0}'..='\u{1f}').chain(['\u{7f}']) {
+            let name = format!("data/tex{}ture.paa", c);
+            assert_eq!(sanitize_entry_path(&name, NameSanitization::Replace).unwrap(), "data/tex_ture.paa");
+            assert_eq!(
+                sanitize_entry_path(&name, NameSanitization::PercentEncode).unwrap(),
+                format!("data/tex%{:02X}ture.paa", c as u32)
+            );
+            assert!(sanitize_entry_path(&name, NameSanitization::Error).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sanitize_entry_path_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_entry_path("data./readme. ", NameSanitization::Replace).unwrap(), "data_/readme__");
+        assert_eq!(sanitize_entry_path("data /readme..", NameSanitization::PercentEncode).unwrap(), "data%20/readme%2E%2E");
+        assert!(sanitize_entry_path("data./readme", NameSanitization::Error).is_err());
+        // Dots and spaces elsewhere in a name are fine
+        assert_eq!(sanitize_entry_path("my data/.hidden file.sqf", NameSanitization::Error).unwrap(), "my data/.hidden file.sqf");
+    }
+
+    #[test]
+    fn test_sanitize_entry_path_keeps_valid_paths() {
+        for mode in [NameSanitization::Error, NameSanitization::Replace, NameSanitization::PercentEncode] {
+            assert_eq!(sanitize_entry_path("functions\\fnc_heal.sqf", mode).unwrap(), "functions/fnc_heal.sqf");
+            assert_eq!(sanitize_entry_path("..\\evil.sqf", mode).unwrap(), "../evil.sqf");
+            assert_eq!(sanitize_entry_path("ünïcode/100.paa", mode).unwrap(), "ünïcode/100.paa");
+        }
+        assert_eq!(sanitize_entry_path("ünïcode/100%.paa", NameSanitization::Replace).unwrap(), "ünïcode/100%.paa");
+        assert_eq!(sanitize_entry_path("ünïcode/100%.paa", NameSanitization::Error).unwrap(), "ünïcode/100%.paa");
+        // Encoded names stay distinct from names that already looked encoded
+        assert_eq!(sanitize_entry_path("100%.paa", NameSanitization::PercentEncode).unwrap(), "100%25.paa");
+        assert_eq!(sanitize_entry_path("fnc%3Fheal.sqf", NameSanitization::PercentEncode).unwrap(), "fnc%253Fheal.sqf");
+        assert_eq!(sanitize_entry_path("fnc?heal.sqf", NameSanitization::PercentEncode).unwrap(), "fnc%3Fheal.sqf");
+        // Drive letters no longer form a drive once sanitized
+        assert_eq!(sanitize_entry_path("C:\\evil", NameSanitization::Replace).unwrap(), "C_/evil");
+        assert!(native_entry_path("C_/evil").is_ok());
+    }
+
+    #[test]
+    fn test_is_reserved_name() {
+        let mut reserved = vec!["CON", "PRN", "AUX", "NUL"].into_iter().map(str::to_string).collect::<Vec<_>>();
+        for n in 1..=9 {
+            reserved.push(format!("COM{}", n));
+            reserved.push(format!("LPT{}", n));
+        }
+        for name in &reserved {
+            assert!(is_reserved_name(name), "{name}");
+            assert!(is_reserved_name(&name.to_lowercase()), "{name}");
+            assert!(is_reserved_name(&format!("{}.sqf", name.to_lowercase())), "{name}");
+            assert!(is_reserved_name(&format!("{}.tar.gz", name)), "{name}");
+            assert!(is_reserved_name(&format!("{} .txt", name)), "{name}");
+        }
+        for name in ["console.sqf", "auxiliary", "nul_", "com", "COM0", "LPT10", "com1x.sqf", "xcon.sqf", "", ".aux"] {
+            assert!(!is_reserved_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_escape_reserved_names() {
+        assert_eq!(escape_reserved_names("functions\\aux.sqf"), "functions/_aux.sqf");
+        assert_eq!(escape_reserved_names("con/Nul/readme.txt"), "_con/_Nul/readme.txt");
+        assert_eq!(escape_reserved_names("data/lpt1"), "data/_lpt1");
+        assert_eq!(escape_reserved_names("functions/fnc_heal.sqf"), "functions/fnc_heal.sqf");
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_long_path_is_unchanged() {
+        let path = Path::new("/cache/z/ace/addons/medical");
+        assert_eq!(long_path(path), path);
+        assert_eq!(tool_path(&long_path(path)), path);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_long_path() {
+        assert_eq!(long_path(Path::new(r"C:\cache/z\..\ace")), Path::new(r"\\?\C:\cache\ace"));
+        assert_eq!(long_path(Path::new(r"\\server\share\cache")), Path::new(r"\\?\UNC\server\share\cache"));
+        assert_eq!(long_path(Path::new(r"\\?\C:\cache")), Path::new(r"\\?\C:\cache"));
+        assert_eq!(tool_path(Path::new(r"\\?\C:\cache")), Path::new(r"C:\cache"));
+        assert_eq!(tool_path(Path::new(r"\\?\UNC\server\share")), Path::new(r"\\server\share"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ensure_within() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path().join("base");
+        std::fs::create_dir_all(base.join("functions")).unwrap();
+        std::os::unix::fs::symlink(temp_dir.path(), base.join("escape")).unwrap();
+
+        assert!(ensure_within(&base, &base.join("functions")).is_ok());
+        assert!(ensure_within(&base, &base.join("functions/..")).is_ok());
+        assert!(ensure_within(&base, &base.join("..")).is_err());
+        assert!(ensure_within(&base, &base.join("escape")).is_err());
+    }
+
+    #[test]
+    fn test_remove_unmatched_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("functions/medical")).unwrap();
+        std::fs::create_dir_all(root.join("dev")).unwrap();
+        File::create(root.join("functions/medical/fnc_heal.sqf")).unwrap();
+        File::create(root.join("dev/debug.sqf")).unwrap();
+        File::create(root.join("config.cpp")).unwrap();
+
+        let removed = remove_unmatched_files(root, |path, _| path.starts_with("functions/")).unwrap();
+
+        assert_eq!(removed, 2);
+        assert!(root.join("functions/medical/fnc_heal.sqf").exists());
+        assert!(!root.join("config.cpp").exists());
+        assert!(!root.join("dev").exists());
+        assert!(root.exists());
+    }
+
+    #[test]
+    fn test_remove_unmatched_files_by_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("small.sqf"), b"ok").unwrap();
+        std::fs::write(root.join("large.paa"), vec![0u8; 1024]).unwrap();
+
+        let removed = remove_unmatched_files(root, |_, size| size <= 512).unwrap();
+
+        assert_eq!(removed, 1);
+        assert!(root.join("small.sqf").exists());
+        assert!(!root.join("large.paa").exists());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("test.txt");
+        std::fs::write(&file_path, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&file_path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    // Needs a case-sensitive file system to create files that differ only by case
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_lowercase_tree() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("Functions/Medical")).unwrap();
+        std::fs::create_dir_all(root.join("functions")).unwrap();
+        std::fs::write(root.join("Config.cpp"), b"config").unwrap();
+        std::fs::write(root.join("Functions/Medical/fnc_Heal.sqf"), b"heal").unwrap();
+        std::fs::write(root.join("Functions/fnc_splint.sqf"), b"upper").unwrap();
+        std::fs::write(root.join("functions/fnc_splint.sqf"), b"lower").unwrap();
+        std::fs::write(root.join("Functions/FNC_Bandage.sqf"), b"first").unwrap();
+        std::fs::write(root.join("Functions/fnc_bandage.SQF"), b"second").unwrap();
+
+        let collisions = lowercase_tree(root).unwrap();
+
+        assert_eq!(collisions, vec![
+            "Functions/fnc_bandage.SQF".to_string(),
+            "Functions/fnc_splint.sqf".to_string(),
+        ]);
+        assert_eq!(std::fs::read(root.join("config.cpp")).unwrap(), b"config");
+        assert_eq!(std::fs::read(root.join("functions/medical/fnc_heal.sqf")).unwrap(), b"heal");
+        assert_eq!(std::fs::read(root.join("functions/fnc_splint.sqf")).unwrap(), b"lower");
+        assert_eq!(std::fs::read(root.join("functions/fnc_bandage.sqf")).unwrap(), b"first");
+        assert!(!root.join("Functions/Medical").exists());
+    }
+
+    #[test]
+    fn test_replace_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let staging = temp_dir.path().join(".tmp/ace_medical-1");
+        let target = temp_dir.path().join("ace_medical");
+        std::fs::create_dir_all(staging.join("functions")).unwrap();
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(staging.join("functions/fnc_heal.sqf"), b"new").unwrap();
+        std::fs::write(target.join("fnc_old.sqf"), b"old").unwrap();
+
+        replace_dir(&staging, &target).unwrap();
+
+        assert_eq!(std::fs::read(target.join("functions/fnc_heal.sqf")).unwrap(), b"new");
+        assert!(!target.join("fnc_old.sqf").exists());
+        assert!(!staging.exists());
+        assert_eq!(std::fs::read_dir(temp_dir.path().join(".tmp")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_copy_dir_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = temp_dir.path().join("from");
+        let to = temp_dir.path().join("to");
+        std::fs::create_dir_all(from.join("a/b")).unwrap();
+        std::fs::write(from.join("a/b/c.sqf"), b"c").unwrap();
+
+        copy_dir_all(&from, &to).unwrap();
+        assert_eq!(std::fs::read(to.join("a/b/c.sqf")).unwrap(), b"c");
+    }
+
+    #[test]
+    fn test_write_replacing_leaves_links_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("SHA256SUMS");
+        let previous = temp_dir.path().join("previous");
+        std::fs::write(&previous, b"old").unwrap();
+        std::fs::hard_link(&previous, &path).unwrap();
+
+        write_replacing(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(std::fs::read(&previous).unwrap(), b"old");
+        assert!(!temp_dir.path().join("SHA256SUMS.partial").exists());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn test_available_space() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(available_space(temp_dir.path()).unwrap() > 0);
+        assert!(available_space(&temp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_background_threads() {
+        let cores = std::thread::available_parallelism().unwrap().get();
+        assert_eq!(background_threads(1), 1);
+        assert_eq!(background_threads(4 * cores), (cores / 2).max(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_background_pool_priority() {
+        let niceness = || unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let before = niceness();
+        let pool = build_background_pool(2).unwrap();
+        let lowered = pool.install(|| {
+            let output = std::process::Command::new("sh").args(["-c", "nice"]).output().unwrap();
+            (niceness(), String::from_utf8_lossy(&output.stdout).trim().parse::<i32>().unwrap())
+        });
+        assert_eq!(lowered, (before.max(BACKGROUND_NICE), before.max(BACKGROUND_NICE)));
+        // Called from outside the pool, which leaves the caller alone
+        assert_eq!(niceness(), before);
+        assert_eq!(build_thread_pool(1).unwrap().install(niceness), before);
+    }
+}
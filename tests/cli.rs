@@ -0,0 +1,265 @@
+#![cfg(feature = "cli")]
+
+use std::path::Path;
+use std::process::{Command, Output};
+use anyhow::Result;
+use extraction::{
+    replace_retaining,
+    version_dir,
+    CacheLock,
+    CacheLockPolicy,
+    CancelToken,
+    ExtractionIndex,
+    ExtractionManifest,
+    IndexedSource,
+    INDEX_FILE_NAME,
+};
+
+const TEST_PBO_DIR: &str = "tests/fixtures";
+const TEST_PBO: &str = "ace_medical.pbo";
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_extraction"))
+        .args(args)
+        .output()
+        .expect("failed to run the extraction binary")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn path_arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn test_missing_input_is_fatal() {
+    let output_dir = tempfile::tempdir().unwrap();
+    let output = run(&["extract", "--input", "tests/fixtures/missing", "--output", path_arg(output_dir.path()), "-q"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Input directory does not exist"));
+}
+
+#[test]
+fn test_verify_detects_changed_files() -> Result<()> {
+    let output_dir = tempfile::tempdir()?;
+    let base_dir = output_dir.path().join("ace_medical");
+    std::fs::create_dir_all(base_dir.join("functions"))?;
+    std::fs::write(base_dir.join("functions/fnc_heal.sqf"), "heal")?;
+    ExtractionManifest::collect(&base_dir, Path::new(TEST_PBO), "abc123".to_string(), None, "sqf")?
+        .write(&base_dir)?;
+
+    let output = run(&["verify", "--output", path_arg(output_dir.path())]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("Verified 1 extractions, 0 with problems"));
+
+    std::fs::write(base_dir.join("functions/fnc_heal.sqf"), "heal them all")?;
+    let output = run(&["verify", "--output", path_arg(output_dir.path())]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stdout(&output).contains("size changed functions/fnc_heal.sqf"));
+    Ok(())
+}
+
+#[test]
+fn test_stats_reads_index() -> Result<()> {
+    let output_dir = tempfile::tempdir()?;
+    ExtractionIndex::new(output_dir.path()).write(&output_dir.path().join(INDEX_FILE_NAME))?;
+
+    let output = run(&["stats", "--output", path_arg(output_dir.path())]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("  PBOs: 0 (0 failed, 0 quarantined)"));
+
+    let output = run(&["stats", "--output", path_arg(output_dir.path()), "--db", "tests/fixtures/missing.json"]);
+    assert_eq!(output.status.code(), Some(1));
+    Ok(())
+}
+
+#[test]
+fn test_extract_scan_stats_verify_medical() -> Result<()> {
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+    let (input, output) = (path_arg(input_dir.path()), path_arg(output_dir.path()));
+
+    let scanned = run(&["scan", "--input", input, "--ext", "sqf,hpp"]);
+    assert_eq!(scanned.status.code(), Some(0));
+    assert!(stdout(&scanned).contains(TEST_PBO));
+    assert!(stdout(&scanned).contains("Listed 1 of 1 PBOs"));
+
+    let extracted = run(&["extract", "--input", input, "--output", output, "--ext", "sqf,hpp", "--index", "-q"]);
+    assert_eq!(extracted.status.code(), Some(0), "{}", String::from_utf8_lossy(&extracted.stderr));
+
+    let stats = run(&["stats", "--output", output]);
+    assert_eq!(stats.status.code(), Some(0));
+    assert!(stdout(&stats).contains("  PBOs: 1 (0 failed, 0 quarantined)"));
+
+    let verified = run(&["verify", "--output", output]);
+    assert_eq!(verified.status.code(), Some(0));
+    assert!(stdout(&verified).contains("Verified 1 extractions, 0 with problems"));
+    Ok(())
+}
+
+#[test]
+fn test_config_file_and_overrides() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("extraction.toml");
+    let created = run(&["init-config", path_arg(&config)]);
+    assert_eq!(created.status.code(), Some(0));
+    assert_eq!(run(&["init-config", path_arg(&config)]).status.code(), Some(1));
+
+    // The template's input_dir is relative to the file, and doesn't exist
+    let output = run(&["extract", "--config", path_arg(&config), "-q"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(stderr.contains(path_arg(&dir.path().join("arma"))), "{}", stderr);
+
+    let output = run(&["extract", "--config", path_arg(&config), "--input", "tests/fixtures/missing", "-q"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("tests/fixtures/missing"));
+
+    std::fs::write(&config, "input_dir = \"arma\"\nthread = 4\n")?;
+    let output = run(&["scan", "--config", path_arg(&config)]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field `thread`"));
+    Ok(())
+}
+
+#[test]
+fn test_json_messages_end_with_summary() -> Result<()> {
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::write(input_dir.path().join("broken.pbo"), "not a PBO")?;
+    // The broken PBO is skipped without running the tool, so it needn't be installed
+    let config = output_dir.path().join("extraction.toml");
+    std::fs::write(&config, "skip_backend_check = true\n")?;
+
+    let output = run(&[
+        "extract",
+        "--config", path_arg(&config),
+        "--input", path_arg(input_dir.path()),
+        "--output", path_arg(output_dir.path()),
+        "--message-format", "json",
+        "-q",
+    ]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    let events: Vec<serde_json::Value> = stdout(&output).lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events[0]["event"], "pbo_discovered");
+    assert!(events.iter().any(|event| event["event"] == "pbo_skipped" && event["reason"] == "invalid_format"));
+    let summary = events.last().unwrap();
+    assert_eq!(summary["event"], "run_finished");
+    assert_eq!((summary["discovered"].as_u64(), summary["skipped"].as_u64()), (Some(1), Some(1)));
+    assert_eq!(summary["dropped_events"], 0);
+    Ok(())
+}
+
+#[test]
+fn test_db_diff() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let source = |hash: &str| IndexedSource { hash: hash.to_string(), extracted_at: 1, mod_name: None, versions: Vec::new(), listed_after_extraction: false };
+    let mut old = ExtractionIndex::new(dir.path());
+    old.sources.insert("@ace\\addons\\ace_medical.pbo".into(), source("ab12"));
+    old.sources.insert("@ace\\addons\\ace_common.pbo".into(), source("cd34"));
+    old.write(&dir.path().join("old.json"))?;
+    let mut new = ExtractionIndex::new(dir.path());
+    new.sources.insert("@ace/addons/ace_medical.pbo".into(), source("ab13"));
+    new.sources.insert("@ace/addons/ace_interaction.pbo".into(), source("ef56"));
+    new.write(&dir.path().join("new.json"))?;
+
+    let (old_path, new_path) = (dir.path().join("old.json"), dir.path().join("new.json"));
+    let output = run(&["db-diff", path_arg(&old_path), path_arg(&new_path)]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("1 added, 1 removed, 1 changed, 0 changed status"));
+    assert!(stdout(&output).contains("~ @ace/addons/ace_medical.pbo: ab12 -> ab13"));
+
+    let output = run(&["db-diff", path_arg(&old_path), path_arg(&new_path), "--json"]);
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(diff["added"][0]["path"], "@ace/addons/ace_interaction.pbo");
+    assert_eq!(diff["removed"][0]["path"], "@ace/addons/ace_common.pbo");
+    assert_eq!(diff["changed_hash"][0]["new_hash"], "ab13");
+    Ok(())
+}
+
+#[test]
+fn test_db_merge() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let source = |hash: &str, extracted_at| IndexedSource { hash: hash.to_string(), extracted_at, mod_name: None, versions: Vec::new(), listed_after_extraction: false };
+    let mut windows = ExtractionIndex::new(dir.path());
+    windows.sources.insert("@ace\\addons\\ace_medical.pbo".into(), source("ab12", 10));
+    windows.sources.insert("@ace\\addons\\ace_common.pbo".into(), source("cd34", 10));
+    windows.write(&dir.path().join("windows.json"))?;
+    let mut linux = ExtractionIndex::new(dir.path());
+    linux.sources.insert("@ace/addons/ace_medical.pbo".into(), source("ab13", 20));
+    linux.sources.insert("@ace/addons/ace_common.pbo".into(), source("CD34", 20));
+    linux.write(&dir.path().join("linux.json"))?;
+
+    let (windows_path, linux_path, merged_path) =
+        (dir.path().join("windows.json"), dir.path().join("linux.json"), dir.path().join("merged.json"));
+    let output = run(&["db-merge", path_arg(&windows_path), path_arg(&linux_path), "--strategy", "error-on-conflict", "-o", path_arg(&merged_path)]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!merged_path.exists());
+
+    let output = run(&["db-merge", path_arg(&windows_path), path_arg(&linux_path), "-o", path_arg(&merged_path)]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("0 added, 1 identical, 1 conflicting: 1 replaced, 0 kept"));
+    let merged = ExtractionIndex::load(&merged_path)?;
+    assert_eq!(merged.sources[Path::new("@ace/addons/ace_medical.pbo")].hash, "ab13");
+    assert_eq!(merged.sources.len(), 2);
+
+    // A run holding the cache keeps the merge from writing its index
+    let index_path = dir.path().join(INDEX_FILE_NAME);
+    let lock = CacheLock::shared(dir.path(), CacheLockPolicy::Fail, &CancelToken::new())?;
+    let output = run(&["db-merge", path_arg(&windows_path), path_arg(&linux_path), "-o", path_arg(&index_path)]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!index_path.exists());
+    drop(lock);
+    let output = run(&["db-merge", path_arg(&windows_path), path_arg(&linux_path), "-o", path_arg(&index_path)]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(index_path.is_file());
+    Ok(())
+}
+
+#[test]
+fn test_diff_mod_folders() -> Result<()> {
+    let old_dir = tempfile::tempdir()?;
+    let new_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), old_dir.path().join(TEST_PBO))?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), new_dir.path().join("ACE_Medical.pbo"))?;
+    std::fs::write(new_dir.path().join("ace_new.pbo"), "not a PBO")?;
+
+    let output = run(&["diff", path_arg(old_dir.path()), path_arg(new_dir.path())]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("1 PBOs added, 0 removed, 0 changed, 1 unchanged"));
+
+    let output = run(&["diff", path_arg(old_dir.path()), path_arg(new_dir.path()), "--json"]);
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(diff["added"][0], "ace_new.pbo");
+    Ok(())
+}
+
+#[test]
+fn test_versions_list_diff_prune() -> Result<()> {
+    let cache_dir = tempfile::tempdir()?;
+    let base_dir = cache_dir.path().join("@ace/addons/ace_medical");
+    let staging = cache_dir.path().join(".staging");
+    for (dir, hash, heal) in [(&base_dir, "v1", "heal"), (&staging, "v2", "heal them")] {
+        std::fs::create_dir_all(dir.join("functions"))?;
+        std::fs::write(dir.join("functions/fnc_heal.sqf"), heal)?;
+        ExtractionManifest::collect(dir, Path::new(TEST_PBO), hash.to_string(), None, "sqf")?.write(dir)?;
+    }
+    replace_retaining(&staging, &base_dir, 1)?;
+
+    let output = run(&["versions", "list", "--output", path_arg(cache_dir.path())]);
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("prev1: v1"));
+
+    let output = run(&["versions", "diff", "--output", path_arg(cache_dir.path()), "@ace/addons/ace_medical", "--json"]);
+    let diff: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(diff["changed"][0]["path"], "functions/fnc_heal.sqf");
+
+    let output = run(&["versions", "prune", "--output", path_arg(cache_dir.path())]);
+    assert!(stdout(&output).contains("Removed 1 versions"));
+    assert!(!version_dir(&base_dir, 1).exists());
+    Ok(())
+}
@@ -0,0 +1,4 @@
+protocol = 1;
+publishedid = 463939057;
+name = "ace";
+timestamp = 5249513734213498372;
@@ -0,0 +1,9 @@
+﻿// Community Base Addons
+/* Generated by the release script,
+   do not edit; */
+name = "Community Base Addons v3.16.1";
+tooltip = "Community Base Addons v3.16.1"; // shown on hover
+overview = "Bugtracker: https://github.com/CBATeam/CBA_A3/issues<br/>Said ""hello"" to the world";
+version = 3.16.1;
+picture = 'logo_cba_ca.paa';
+hideName = 0;
@@ -0,0 +1,15 @@
+name = "Advanced Combat Environment 3.18.2";
+picture = "title_ca.paa";
+actionName = "Website";
+action = "https://ace3.acemod.org/";
+description = "ACE3 - Version 3.18.2";
+logo = "logo_ace3_ca.paa";
+logoOver = "logo_ace3_ca.paa";
+tooltip = "ACE3";
+tooltipOwned = "ACE3 Owned";
+dlcColor[] = {0.18, 0.52, 0.78, 1};
+overview = "ACE3 is a joint effort by the teams behind ACE2, AGM and CSE to improve the realism and authenticity of Arma 3.";
+author = "ACE3 Team";
+overviewPicture = "logo_ace3_ca.paa";
+overviewText = "ACE3 is a joint effort by the teams behind ACE2, AGM and CSE to improve the realism and authenticity of Arma 3.";
+overviewFootnote = "<br /><br /><t color='#80FFFFFF'>ACE3 is licensed under GNU General Public License (GPLv2).</t>";
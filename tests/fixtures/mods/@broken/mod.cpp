@@ -0,0 +1,3 @@
+author = Someone;
+name = "Broken Mod;
+tooltip = Broken;
@@ -0,0 +1,3 @@
+protocol = 1;
+publishedid = 450814997;
+name = "Unnamed Mod";
@@ -116,6 +116,28 @@ fn test_list_pbo_contents() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_list_pbo_contents_helper() -> Result<()> {
+    setup_logging();
+    let pbo_path = Path::new(TEST_PBO_DIR).join(TEST_PBO);
+    info!("Testing list_pbo_contents helper with: {}", pbo_path.display());
+    
+    let contents = extraction::list_pbo_contents(&pbo_path, 30)?;
+    
+    // Entries use forward slashes regardless of the listing format
+    assert!(contents.entries.iter().all(|entry| !entry.contains('\\')));
+    for expected_file in EXPECTED_FILES {
+        assert!(
+            contents.entries.contains(&expected_file.to_string()),
+            "Expected file {} was not listed",
+            expected_file
+        );
+    }
+    assert_eq!(contents.entries.len(), EXPECTED_FILES.len());
+    
+    Ok(())
+}
+
 #[test]
 fn test_extract_with_options() -> Result<()> {
     setup_logging();
@@ -214,4 +236,355 @@ fn test_extract_with_extension_filter() -> Result<()> {
     assert_eq!(files.len(), 5);
     
     Ok(())
-} 
\ No newline at end of file
+} 
+#[test]
+fn test_extract_to_memory() -> Result<()> {
+    setup_logging();
+    let pbo_path = Path::new(TEST_PBO_DIR).join(TEST_PBO);
+    info!("Testing in-memory extraction from: {}", pbo_path.display());
+
+    let filter = extraction::FileFilter::new("cpp,hpp");
+    let files = extraction::extract_to_memory(&pbo_path, &filter, 30)?;
+
+    let mut paths: Vec<_> = files.keys().map(String::as_str).collect();
+    paths.sort();
+    assert_eq!(paths, vec![
+        "ACE_Settings.hpp",
+        "CfgEventHandlers.hpp",
+        "CfgVehicles.hpp",
+        "XEH_PREP.hpp",
+        "config.cpp",
+        "script_component.hpp",
+    ]);
+    assert_eq!(files["config.cpp"], std::fs::read(Path::new(TEST_PBO_DIR).join("medical/config.cpp"))?);
+
+    let error = extraction::extract_to_memory_with_limit(&pbo_path, &filter, 30, 16).unwrap_err();
+    assert!(error.to_string().contains("over the limit of 16 bytes"), "{error}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extract_to_memory_async() -> Result<()> {
+    setup_logging();
+    let pbo_path = Path::new(TEST_PBO_DIR).join(TEST_PBO);
+
+    let filter = extraction::FileFilter::new("sqf").with_include_paths(vec!["functions/".to_string()]);
+    let files = extraction::extract_to_memory_async(&pbo_path, &filter, 30, extraction::DEFAULT_MEMORY_LIMIT).await?;
+
+    assert_eq!(files.len(), 5);
+    assert!(files.keys().all(|path| path.starts_with("functions/fnc_") && path.ends_with(".sqf")));
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_streaming() -> Result<()> {
+    setup_logging();
+    let pbo_path = Path::new(TEST_PBO_DIR).join(TEST_PBO);
+
+    let mut received = Vec::new();
+    let streamed = extraction::extract_streaming(&pbo_path, &extraction::FileFilter::new("hpp"), 30, |path, data| {
+        received.push((path.to_string(), data.len()));
+        Ok(())
+    })?;
+    assert_eq!(streamed, 5);
+    assert!(received.iter().any(|(path, size)| path == "XEH_PREP.hpp" && *size > 0));
+
+    // Sink errors stop the extraction
+    let mut calls = 0;
+    let error = extraction::extract_streaming(&pbo_path, &extraction::FileFilter::new("hpp"), 30, |_, _| {
+        calls += 1;
+        anyhow::bail!("index full")
+    }).unwrap_err();
+    assert_eq!(error.to_string(), "index full");
+    assert_eq!(calls, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_extract_file() -> Result<()> {
+    setup_logging();
+    let pbo_path = Path::new(TEST_PBO_DIR).join(TEST_PBO);
+    let fixture = Path::new(TEST_PBO_DIR).join("medical");
+
+    let config = extraction::extract_file(&pbo_path, "config.cpp", None, 30)?;
+    assert_eq!(config.entry, "config.cpp");
+    assert_eq!(config.data.as_deref(), Some(std::fs::read(fixture.join("config.cpp"))?.as_slice()));
+    assert_eq!(config.path, None);
+
+    let output_dir = tempfile::tempdir()?;
+    let dest = output_dir.path().join("scripts/damage.sqf");
+    let function = extraction::extract_file(&pbo_path, "FUNCTIONS\\fnc_addDamageToUnit.sqf", Some(&dest), 30)?;
+    assert_eq!(function.entry, "functions/fnc_addDamageToUnit.sqf");
+    assert_eq!(function.path.as_deref(), Some(dest.as_path()));
+    assert_eq!(std::fs::read(&dest)?, std::fs::read(fixture.join("functions/fnc_addDamageToUnit.sqf"))?);
+    assert_eq!(function.size, std::fs::metadata(&dest)?.len());
+
+    assert!(extraction::extract_file(&pbo_path, "missing.sqf", None, 30).is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_second_run_counts_unchanged() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+
+    let config = || extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        resume: true,
+        build_index: true,
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+    let first = extraction::extract_pbos(config()).await?;
+    assert_eq!((first.extracted, first.run_stats.unchanged), (1, 0));
+    assert_eq!(first.throughput.listing_runs, 1);
+
+    let second = extraction::extract_pbos(config()).await?;
+    // The unchanged PBO is neither listed nor extracted again
+    assert_eq!((second.throughput.listing_runs, second.throughput.extraction_runs), (0, 0));
+    assert_eq!(second.run_stats.unchanged, second.outcomes.len());
+    assert_eq!(second.run_stats.unchanged, 1);
+    assert_eq!(second.run_stats.previously_failed, 0);
+    assert_eq!(second.index_stats.map(|stats| stats.sources), Some(1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_throughput_counts_written_files() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+
+    let report = extraction::extract_pbos(extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    })
+    .await?;
+    assert_eq!(report.extracted, 1);
+
+    // What the run counted is what ended up in the output
+    let written: Vec<u64> = walkdir::WalkDir::new(output_dir.path())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "sqf" || ext == "hpp"))
+        .map(|entry| entry.metadata().map(|meta| meta.len()))
+        .collect::<Result<_, _>>()?;
+    let expected = EXPECTED_FILES.iter().filter(|file| file.ends_with(".sqf") || file.ends_with(".hpp")).count();
+    assert_eq!(written.len(), expected);
+    assert_eq!(report.throughput.files_written, expected);
+    assert_eq!(report.throughput.files_written, report.outcomes[0].extracted_files);
+    assert_eq!(report.throughput.bytes_written, written.iter().sum::<u64>());
+    assert_eq!((report.throughput.listing_runs, report.throughput.extraction_runs), (1, 1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extract_everything_without_listing() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+
+    let report = extraction::extract_pbos(extraction::ExtractionConfig {
+        extensions: "*",
+        build_index: true,
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    })
+    .await?;
+    assert_eq!(report.extracted, 1);
+    // Only the extraction runs the tool, the expected files are what it wrote
+    assert_eq!(report.throughput.listing_runs, 0);
+    let outcome = &report.outcomes[0];
+    assert!(outcome.extracted_files >= EXPECTED_FILES.len());
+    assert_eq!(outcome.expected_files, outcome.extracted_files);
+
+    let manifest = extraction::ExtractionManifest::read(&output_dir.path().join("ace_medical"))?;
+    assert!(manifest.listed_after_extraction);
+    assert_eq!(manifest.prefix.as_deref(), Some("z/ace/addons/medical"));
+    let index = extraction::ExtractionIndex::load(&output_dir.path().join(extraction::INDEX_FILE_NAME))?;
+    assert!(index.sources[Path::new(TEST_PBO)].listed_after_extraction);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_adopt_existing_output() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+    let config = || extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+    extraction::extract_pbos(config()).await?;
+
+    // Output of another tool: the files without a manifest
+    let base_dir = output_dir.path().join("ace_medical");
+    std::fs::remove_file(base_dir.join(extraction::MANIFEST_FILE_NAME))?;
+    let report = extraction::adopt_existing(config())?;
+    assert_eq!(report.adopted, vec![Path::new(TEST_PBO).to_path_buf()]);
+    assert!(report.pending.is_empty());
+    assert!(base_dir.join(extraction::MANIFEST_FILE_NAME).is_file());
+    let index = extraction::ExtractionIndex::load(&output_dir.path().join(extraction::INDEX_FILE_NAME))?;
+    assert!(index.sources.contains_key(Path::new(TEST_PBO)));
+
+    // Runs adopting complete outputs leave them alone
+    std::fs::remove_file(base_dir.join(extraction::MANIFEST_FILE_NAME))?;
+    let report = extraction::extract_pbos(extraction::ExtractionConfig { adopt_existing: true, ..config() }).await?;
+    assert_eq!((report.adopted.len(), report.extracted), (1, 0));
+
+    let sqf = walkdir::WalkDir::new(&base_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.path().extension().is_some_and(|ext| ext == "sqf"))
+        .expect("no extracted sqf file");
+    std::fs::remove_file(sqf.path())?;
+    let report = extraction::adopt_existing(config())?;
+    assert!(report.adopted.is_empty());
+    assert_eq!(report.pending, vec![Path::new(TEST_PBO).to_path_buf()]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_verify_cache_repairs_broken_output() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+    let config = || extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        build_index: true,
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+    extraction::extract_pbos(config()).await?;
+    let verification = extraction::verify_cache(config(), false).await?;
+    assert_eq!((verification.checked, verification.broken.len()), (1, 0));
+
+    let sqf = walkdir::WalkDir::new(output_dir.path().join("ace_medical"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.path().extension().is_some_and(|ext| ext == "sqf"))
+        .expect("no extracted sqf file");
+    std::fs::remove_file(sqf.path())?;
+    let verification = extraction::verify_cache(config(), false).await?;
+    assert_eq!(verification.broken.len(), 1);
+    assert!(!verification.broken[0].missing.is_empty());
+    let index = extraction::ExtractionIndex::load(&output_dir.path().join(extraction::INDEX_FILE_NAME))?;
+    assert!(index.failed.contains_key(Path::new(TEST_PBO)));
+
+    // The next run extracts it again, even when resuming
+    let report = extraction::extract_pbos(extraction::ExtractionConfig { resume: true, ..config() }).await?;
+    assert_eq!(report.extracted, 1);
+    assert!(sqf.path().is_file());
+    let verification = extraction::verify_cache(config(), true).await?;
+    assert!(verification.broken.is_empty() && verification.repair.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_verify_fix_keeps_prefix_map() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join("copy.pbo"))?;
+    let config = || extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        build_index: true,
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+    extraction::extract_pbos(config()).await?;
+    let prefix_map_path = output_dir.path().join(extraction::PREFIX_MAP_FILE_NAME);
+    let prefixes = std::fs::read_to_string(&prefix_map_path)?;
+
+    let sqf = walkdir::WalkDir::new(output_dir.path().join("ace_medical"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| entry.path().extension().is_some_and(|ext| ext == "sqf"))
+        .expect("no extracted sqf file");
+    std::fs::remove_file(sqf.path())?;
+    // Only the broken PBO is extracted again, and copy.pbo keeps its prefix
+    let verification = extraction::verify_cache(config(), true).await?;
+    assert_eq!(verification.repair.map(|report| report.extracted), Some(1));
+    assert_eq!(std::fs::read_to_string(&prefix_map_path)?, prefixes);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_touch_refreshes_hash_of_redownloaded_pbo() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    let pbo = input_dir.path().join(TEST_PBO);
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), &pbo)?;
+    let config = || extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+    extraction::extract_pbos(config()).await?;
+
+    // Downloaded again: same contents, another modification time
+    let mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(86_400);
+    std::fs::File::options().write(true).open(&pbo)?.set_modified(mtime)?;
+    let report = extraction::touch_hashes(config())?;
+    assert_eq!(report.refreshed, vec![Path::new(TEST_PBO).to_path_buf()]);
+    let manifest = extraction::ExtractionManifest::read(&output_dir.path().join("ace_medical"))?;
+    assert_eq!(manifest.hash, extraction::utils::calculate_file_hash(&pbo)?);
+    let index = extraction::ExtractionIndex::load(&output_dir.path().join(extraction::INDEX_FILE_NAME))?;
+    assert_eq!(index.sources[Path::new(TEST_PBO)].hash, manifest.hash);
+
+    let report = extraction::touch_hashes(config())?;
+    assert_eq!((report.refreshed.len(), report.current.len()), (0, 1));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_runs_contend_for_cache_lock() -> Result<()> {
+    setup_logging();
+    let input_dir = tempfile::tempdir()?;
+    let output_dir = tempfile::tempdir()?;
+    std::fs::copy(Path::new(TEST_PBO_DIR).join(TEST_PBO), input_dir.path().join(TEST_PBO))?;
+    let config = |cache_lock| extraction::ExtractionConfig {
+        extensions: "sqf,hpp",
+        cache_lock,
+        output_mode: extraction::OutputMode::Silent,
+        ..extraction::ExtractionConfig::new(input_dir.path(), output_dir.path())
+    };
+
+    // Another process running on the cache
+    let cancel = extraction::CancelToken::new();
+    let lock = extraction::CacheLock::exclusive(output_dir.path(), extraction::CacheLockPolicy::Fail, &cancel)?;
+    let error = extraction::extract_pbos(config(extraction::CacheLockPolicy::Fail)).await.unwrap_err();
+    assert!(matches!(error, extraction::ExtractionError::CacheLocked(_)), "{:?}", error);
+    assert!(!output_dir.path().join("ace_medical").exists());
+
+    let holder = tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        drop(lock);
+    });
+    let report = extraction::extract_pbos(config(extraction::CacheLockPolicy::Wait)).await?;
+    assert_eq!(report.extracted, 1);
+    holder.await?;
+
+    Ok(())
+}